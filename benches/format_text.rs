@@ -0,0 +1,53 @@
+//! Times text formatting of a large result set: `cargo bench --bench format_text`.
+//!
+//! Without a benchmark harness dependency, each case runs a few times and reports its fastest
+//! run and the size of the text it built.
+
+use ast_grep_mcp::format::{format_matches_as_text, write_matches_as_text, MAX_TEXT_BYTES};
+use serde_json::{json, Value};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const MATCHES: usize = 50_000;
+const RUNS: usize = 5;
+
+fn sample_matches() -> Vec<Value> {
+    (0..MATCHES)
+        .map(|i| {
+            json!({
+                "file": format!("src/module_{}/file_{}.py", i / 100, i % 100),
+                "text": format!("def handler_{}(request, *args, **kwargs):\n    return process(request, {})", i, i),
+                "range": {"start": {"line": i % 500, "column": 0}, "end": {"line": i % 500 + 1, "column": 30}},
+            })
+        })
+        .collect()
+}
+
+fn fastest(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let bytes = black_box(run());
+            (start.elapsed(), bytes)
+        })
+        .min_by_key(|(elapsed, _)| *elapsed)
+        .unwrap_or_default()
+}
+
+fn report(name: &str, (elapsed, bytes): (Duration, usize)) {
+    let (ms, mb) = (elapsed.as_secs_f64() * 1e3, bytes as f64 / 1e6);
+    println!("format {} matches, {:<8} {:>8.1} ms  {:>6.1} MB of text", MATCHES, name, ms, mb);
+}
+
+fn main() {
+    let matches = sample_matches();
+    report("uncapped", fastest(|| format_matches_as_text(&matches).len()));
+    report(
+        "capped",
+        fastest(|| {
+            let mut text = String::new();
+            write_matches_as_text(&mut text, &matches, MAX_TEXT_BYTES);
+            text.len()
+        }),
+    );
+}
@@ -0,0 +1,97 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+/// A distinct text bound to the aggregated metavariable.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetavariableValue {
+    pub value: String,
+    /// Matches binding this value.
+    pub count: usize,
+    /// Distinct files those matches are in.
+    pub files: usize,
+}
+
+/// The distinct values of one metavariable across a set of matches.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MetavariableAggregate {
+    pub metavariable: String,
+    pub matches: usize,
+    /// Matches that did not bind the metavariable (e.g. it is in an `any` branch that did not match).
+    pub unbound: usize,
+    pub distinct: usize,
+    /// Most frequent first; ties in value order.
+    pub values: Vec<MetavariableValue>,
+}
+
+/// Normalizes `$NAME`, `$$$NAME` or `NAME` to the bare metavariable name.
+pub fn metavariable_name(name: &str) -> &str {
+    name.trim().trim_start_matches('$')
+}
+
+/// Text bound to `name` in a match: a single or transformed metavariable, or the nodes of a
+/// multi metavariable joined by `, ` (without separator nodes).
+fn bound_text(m: &Value, name: &str) -> Option<String> {
+    let vars = m.get("metaVariables")?;
+    if let Some(text) = vars.pointer(&format!("/single/{}/text", name)).and_then(Value::as_str) {
+        return Some(text.to_string());
+    }
+    if let Some(text) = vars.pointer(&format!("/transformed/{}", name)).and_then(Value::as_str) {
+        return Some(text.to_string());
+    }
+    let nodes = vars.pointer(&format!("/multi/{}", name))?.as_array()?;
+    let texts: Vec<&str> = nodes
+        .iter()
+        .filter_map(|node| node.get("text").and_then(Value::as_str))
+        .filter(|text| !matches!(*text, "," | ";"))
+        .collect();
+    Some(texts.join(", "))
+}
+
+/// Counts the distinct (trimmed) texts bound to metavariable `name` across `matches`.
+pub fn aggregate_metavariable(matches: &[Value], name: &str) -> MetavariableAggregate {
+    let name = metavariable_name(name);
+    let mut counts: HashMap<String, (usize, BTreeSet<&str>)> = HashMap::new();
+    let mut unbound = 0;
+    for m in matches {
+        let Some(text) = bound_text(m, name) else {
+            unbound += 1;
+            continue;
+        };
+        let file = m.get("file").and_then(Value::as_str).unwrap_or_default();
+        let entry = counts.entry(text.trim().to_string()).or_default();
+        entry.0 += 1;
+        entry.1.insert(file);
+    }
+
+    let mut values: Vec<MetavariableValue> = counts
+        .into_iter()
+        .map(|(value, (count, files))| MetavariableValue {
+            value,
+            count,
+            files: files.len(),
+        })
+        .collect();
+    values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    MetavariableAggregate {
+        metavariable: format!("${}", name),
+        matches: matches.len(),
+        unbound,
+        distinct: values.len(),
+        values,
+    }
+}
+
+/// One line per value (count, file count, value), with multi-line values shown on one line.
+pub fn format_aggregate_as_text(aggregate: &MetavariableAggregate) -> String {
+    aggregate
+        .values
+        .iter()
+        .map(|v| {
+            let value = v.value.split_whitespace().collect::<Vec<_>>().join(" ");
+            let files = if v.files == 1 { "1 file".to_string() } else { format!("{} files", v.files) };
+            format!("{:>6}  {:<9} {}", v.count, files, value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,135 @@
+use crate::approval::approved_edits;
+use crate::backup::Backup;
+use crate::config::Config;
+use crate::diff::{apply_edits, Edit};
+use crate::engine::run_ast_grep;
+use crate::filter::{exclude_glob_args, no_ignore_args};
+use crate::rewrite::{fix_fingerprint, planned_fixes, AppliedFix};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// What a rule's fix would change in a project.
+#[derive(Debug, Default)]
+pub struct RewritePlan {
+    pub files: Vec<PlannedFile>,
+    /// Matches left alone because they overlap an earlier fix.
+    pub overlapping: usize,
+    /// Files with matches that were not planned, with the reason.
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct PlannedFile {
+    /// The path relative to the project folder.
+    pub path: String,
+    pub absolute: PathBuf,
+    pub source: String,
+    pub edits: Vec<Edit>,
+    pub fixes: Vec<AppliedFix>,
+}
+
+impl RewritePlan {
+    /// Keeps only the `files` (relative paths) and the fixes with the given `fingerprints`, when
+    /// they are not empty; fingerprints that match no fix are listed as skipped, as the file has
+    /// likely changed since they were previewed.
+    pub fn select(&mut self, files: &[String], fingerprints: &[String]) {
+        if !files.is_empty() {
+            self.files.retain(|file| files.contains(&file.path));
+        }
+        if fingerprints.is_empty() {
+            return;
+        }
+        let planned: Vec<&str> =
+            self.files.iter().flat_map(|file| &file.fixes).filter_map(|fix| fix.fingerprint.as_deref()).collect();
+        for fingerprint in fingerprints.iter().filter(|fingerprint| !planned.contains(&fingerprint.as_str())) {
+            self.skipped.push(format!("fix {}: not found; its file may have changed since the preview", fingerprint));
+        }
+        for file in &mut self.files {
+            let keep: Vec<bool> = file
+                .fixes
+                .iter()
+                .map(|fix| fix.fingerprint.as_ref().is_some_and(|fingerprint| fingerprints.contains(fingerprint)))
+                .collect();
+            (file.edits, file.fixes) = approved_edits(&file.edits, std::mem::take(&mut file.fixes), &keep);
+        }
+        self.files.retain(|file| !file.edits.is_empty());
+    }
+}
+
+/// The edits the `fix` of the inline rule `yaml` makes to the files of `project_folder`, read
+/// after the scan. Files that cannot be read as UTF-8, or whose matches no longer fit their
+/// contents (changed since the scan), are skipped with the reason.
+pub async fn plan_rewrite(
+    yaml: &str,
+    project_folder: &Path,
+    include_vendored: bool,
+    no_ignore: bool,
+    config: &Config,
+) -> anyhow::Result<RewritePlan> {
+    let mut args = vec!["--inline-rules".to_string(), yaml.to_string()];
+    args.extend(exclude_glob_args(include_vendored));
+    args.extend(no_ignore_args(no_ignore));
+    args.push("--json".to_string());
+    args.push(project_folder.display().to_string());
+    let result = run_ast_grep("scan", &args, None, config).await?;
+
+    let mut by_file: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    let matches: Vec<Value> = serde_json::from_str(result.stdout.trim()).unwrap_or_default();
+    for m in matches {
+        if m.get("replacement").is_some() {
+            let file = m.get("file").and_then(Value::as_str).unwrap_or_default().to_string();
+            by_file.entry(file).or_default().push(m);
+        }
+    }
+    let mut plan = RewritePlan::default();
+    for (file, matches) in by_file {
+        let absolute = project_folder.join(&file);
+        let path = absolute.strip_prefix(project_folder).unwrap_or(&absolute).display().to_string();
+        let source = match std::fs::read(&absolute).map(String::from_utf8) {
+            Ok(Ok(source)) => source,
+            Ok(Err(_)) => {
+                plan.skipped.push(format!("{}: not UTF-8", path));
+                continue;
+            }
+            Err(e) => {
+                plan.skipped.push(format!("{}: {}", path, e));
+                continue;
+            }
+        };
+        let fits = |m: &Value| {
+            let offset = |edge: &str| m.pointer(&format!("/range/byteOffset/{}", edge)).and_then(Value::as_u64);
+            let text = m.get("text").and_then(Value::as_str);
+            match (offset("start"), offset("end")) {
+                (Some(start), Some(end)) => source.get(start as usize..end as usize) == text,
+                _ => false,
+            }
+        };
+        if !matches.iter().all(fits) {
+            plan.skipped.push(format!("{}: changed since the scan", path));
+            continue;
+        }
+        let (edits, mut fixes, overlapping) = planned_fixes(&source, &matches);
+        for (edit, fix) in edits.iter().zip(&mut fixes) {
+            fix.fingerprint = Some(fix_fingerprint(&path, edit, &fix.original));
+        }
+        plan.overlapping += overlapping;
+        if !edits.is_empty() {
+            plan.files.push(PlannedFile { path, absolute, source, edits, fixes });
+        }
+    }
+    Ok(plan)
+}
+
+/// Writes the planned edits of `file` atomically, once its original is saved in `backup`. The
+/// file is read again first, as it may have changed while other files were rewritten or approved;
+/// the error is the reason it was not written.
+pub fn write_planned_file(backup: &mut Backup, backup_dir: &Path, file: &PlannedFile) -> Result<(), String> {
+    match std::fs::read(&file.absolute) {
+        Ok(current) if current == file.source.as_bytes() => {}
+        Ok(_) => return Err("changed since the scan".to_string()),
+        Err(e) => return Err(e.to_string()),
+    }
+    let rewritten = apply_edits(&file.source, &file.edits);
+    backup.replace(backup_dir, &file.absolute, file.source.as_bytes(), rewritten.as_bytes()).map_err(|e| e.to_string())
+}
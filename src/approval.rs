@@ -0,0 +1,118 @@
+use crate::diff::Edit;
+use crate::rewrite::AppliedFix;
+use rmcp::model::{
+    BooleanSchema, CreateElicitationRequestParams, CreateElicitationResult, ElicitationAction, ElicitationSchema,
+    PrimitiveSchema,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Values of apply_rewrite's `approve`.
+pub const APPROVAL_SCOPES: &[&str] = &["file", "match"];
+
+/// Longest diff or fix shown in one approval request; longer ones are cut, so the client is not
+/// sent a whole generated file to review.
+pub const MAX_APPROVAL_TEXT: usize = 8000;
+
+/// What an interactive apply_rewrite asks the client to approve, through MCP elicitation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalScope {
+    /// Each file, shown as its diff.
+    File,
+    /// Each fix, shown as the code it replaces and its replacement.
+    Match,
+}
+
+impl ApprovalScope {
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "" | "file" => Some(Self::File),
+            "match" => Some(Self::Match),
+            _ => None,
+        }
+    }
+}
+
+/// The client's answer to one approval request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    Approved,
+    Rejected,
+    /// The user cancelled: nothing more is written.
+    Cancelled,
+}
+
+/// An elicitation request with `message` and a single `apply` checkbox, checked by default.
+pub fn approval_request(message: String) -> CreateElicitationRequestParams {
+    let apply = BooleanSchema::new()
+        .title("Apply")
+        .description("Write this change; uncheck or decline to leave it out")
+        .with_default(true);
+    let properties = BTreeMap::from([("apply".to_string(), PrimitiveSchema::Boolean(apply))]);
+    CreateElicitationRequestParams::FormElicitationParams {
+        meta: None,
+        message,
+        requested_schema: ElicitationSchema::new(properties).with_required(vec!["apply".to_string()]),
+    }
+}
+
+/// Reads the answer to an [`approval_request`]: accepted with `apply` checked (or left out) is an
+/// approval, accepted unchecked or declined a rejection.
+pub fn approval_of(result: &CreateElicitationResult) -> Approval {
+    match result.action {
+        ElicitationAction::Accept => {
+            let apply = result.content.as_ref().and_then(|content| content.get("apply")).and_then(Value::as_bool);
+            if apply.unwrap_or(true) {
+                Approval::Approved
+            } else {
+                Approval::Rejected
+            }
+        }
+        ElicitationAction::Decline => Approval::Rejected,
+        ElicitationAction::Cancel => Approval::Cancelled,
+    }
+}
+
+/// The message asking to approve the rewrite of the file `path`, with its unified `diff`.
+pub fn file_approval_message(path: &str, fixes: usize, diff: &str) -> String {
+    let plural = if fixes == 1 { "" } else { "es" };
+    format!("Apply {} fix{} to {}?\n\n{}", fixes, plural, path, truncated(diff))
+}
+
+/// The message asking to approve fix `number` of the `count` fixes to the file `path`.
+pub fn match_approval_message(path: &str, number: usize, count: usize, fix: &AppliedFix) -> String {
+    format!(
+        "Apply fix {} of {} to {} (line {})?\n\n--- original\n{}\n+++ replacement\n{}",
+        number,
+        count,
+        path,
+        fix.line + 1,
+        truncated(&fix.original),
+        truncated(&fix.replacement)
+    )
+}
+
+fn truncated(text: &str) -> String {
+    if text.len() <= MAX_APPROVAL_TEXT {
+        return text.to_string();
+    }
+    let mut end = MAX_APPROVAL_TEXT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n... ({} more bytes)", &text[..end], text.len() - end)
+}
+
+/// The `edits` of a file and their `fixes` (one per edit, as `planned_fixes` returns them) that
+/// were approved; `approved` has an entry per edit.
+pub fn approved_edits(edits: &[Edit], fixes: Vec<AppliedFix>, approved: &[bool]) -> (Vec<Edit>, Vec<AppliedFix>) {
+    let mut kept_edits = Vec::new();
+    let mut kept_fixes = Vec::new();
+    for ((edit, fix), &approved) in edits.iter().zip(fixes).zip(approved) {
+        if approved {
+            kept_edits.push(edit.clone());
+            kept_fixes.push(fix);
+        }
+    }
+    (kept_edits, kept_fixes)
+}
@@ -0,0 +1,324 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey, VerificationAlgorithm};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Scope write-capable tools require when `--oauth-write-scope` is not given.
+pub const DEFAULT_WRITE_SCOPE: &str = "ast-grep:write";
+
+/// Clock skew tolerated when checking `exp` and `nbf`.
+const CLOCK_LEEWAY_SECS: u64 = 60;
+
+/// Shortest time between two JWKS downloads, so tokens with unknown key ids cannot make the
+/// server hammer the issuer.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest a request for the issuer's metadata or JWKS may take, so an issuer that does not
+/// answer cannot hold up authentication.
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the HTTP transport authenticates requests; with nothing configured every request is
+/// allowed, as a server bound to localhost has always done.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthConfig {
+    /// Static bearer tokens, each granting access to every tool.
+    pub tokens: Vec<String>,
+    pub oauth: Option<OAuthConfig>,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || self.oauth.is_some()
+    }
+}
+
+/// Validation of JWT access tokens issued by an OAuth 2.0 / OpenID Connect authorization server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthConfig {
+    /// Expected `iss` claim; also where the JWKS is discovered from.
+    pub issuer: String,
+    /// Expected `aud` claim, when tokens must be meant for this server.
+    pub audience: Option<String>,
+    /// Where to get the signing keys; default: `jwks_uri` of the issuer's discovery document.
+    pub jwks_uri: Option<String>,
+    /// Scope needed for [`WRITE_TOOLS`].
+    pub write_scope: String,
+}
+
+/// Who made a request, attached to the request once it is authenticated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    /// `sub` claim of a JWT.
+    pub subject: Option<String>,
+    /// Who the per-client quotas count the request against: `sub:<subject>` (or
+    /// `client:<client_id>` without a subject) for a JWT, `token:<n>` for the nth static token.
+    /// `None` when the token does not tell clients apart, so each session counts on its own.
+    pub client: Option<String>,
+    /// Granted scopes; `None` for static tokens, which may call every tool.
+    pub scopes: Option<Vec<String>>,
+}
+
+impl Grant {
+    /// Whether the grant allows calling tools that change files.
+    pub fn may_write(&self, write_scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|scope| scope == write_scope),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Missing bearer token")]
+    Missing,
+
+    #[error("Invalid token: {0}")]
+    Invalid(String),
+
+    #[error("Cannot get the issuer's signing keys: {0}")]
+    Jwks(String),
+}
+
+fn invalid(reason: impl Into<String>) -> AuthError {
+    AuthError::Invalid(reason.into())
+}
+
+/// A JSON Web Key Set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// A public key of a [`Jwks`]; RSA keys use `n` and `e`, EC keys `crv`, `x` and `y`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub crv: Option<String>,
+    #[serde(default)]
+    pub n: Option<String>,
+    #[serde(default)]
+    pub e: Option<String>,
+    #[serde(default)]
+    pub x: Option<String>,
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>, AuthError> {
+    URL_SAFE_NO_PAD.decode(segment).map_err(|_| invalid("not base64url"))
+}
+
+fn key_part(part: &Option<String>) -> Result<Vec<u8>, AuthError> {
+    decode_segment(part.as_deref().ok_or_else(|| invalid("incomplete signing key"))?)
+}
+
+/// Checks the signature of `message` with `key` for JWS algorithm `alg`.
+fn verify_signature(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> Result<(), AuthError> {
+    let rsa = |params: &'static signature::RsaParameters| -> Result<(), AuthError> {
+        if key.kty != "RSA" {
+            return Err(invalid("key type does not match the algorithm"));
+        }
+        let components = RsaPublicKeyComponents { n: key_part(&key.n)?, e: key_part(&key.e)? };
+        components.verify(params, message, signature).map_err(|_| invalid("bad signature"))
+    };
+    let ec = |curve: &str, algorithm: &'static dyn VerificationAlgorithm| -> Result<(), AuthError> {
+        if key.kty != "EC" || key.crv.as_deref() != Some(curve) {
+            return Err(invalid("key type does not match the algorithm"));
+        }
+        let mut point = vec![0x04];
+        point.extend(key_part(&key.x)?);
+        point.extend(key_part(&key.y)?);
+        UnparsedPublicKey::new(algorithm, point)
+            .verify(message, signature)
+            .map_err(|_| invalid("bad signature"))
+    };
+    match alg {
+        "RS256" => rsa(&signature::RSA_PKCS1_2048_8192_SHA256),
+        "RS384" => rsa(&signature::RSA_PKCS1_2048_8192_SHA384),
+        "RS512" => rsa(&signature::RSA_PKCS1_2048_8192_SHA512),
+        "PS256" => rsa(&signature::RSA_PSS_2048_8192_SHA256),
+        "PS384" => rsa(&signature::RSA_PSS_2048_8192_SHA384),
+        "PS512" => rsa(&signature::RSA_PSS_2048_8192_SHA512),
+        "ES256" => ec("P-256", &signature::ECDSA_P256_SHA256_FIXED),
+        "ES384" => ec("P-384", &signature::ECDSA_P384_SHA384_FIXED),
+        other => Err(invalid(format!("unsupported algorithm '{}'", other))),
+    }
+}
+
+/// Key id of a JWT, to tell whether the JWKS has to be downloaded again.
+pub fn jwt_key_id(token: &str) -> Option<String> {
+    let header = decode_segment(token.split('.').next()?).ok()?;
+    let header: Value = serde_json::from_slice(&header).ok()?;
+    header.get("kid")?.as_str().map(str::to_string)
+}
+
+/// Verifies a JWT access token: its signature against `jwks`, and its issuer, audience and
+/// validity period (with a minute of leeway) at `now` (seconds since the epoch).
+pub fn verify_jwt(token: &str, jwks: &Jwks, oauth: &OAuthConfig, now: u64) -> Result<Grant, AuthError> {
+    let segments: Vec<&str> = token.split('.').collect();
+    let [header, payload, signature] = segments[..] else {
+        return Err(invalid("not a JWT"));
+    };
+    let header: Value = serde_json::from_slice(&decode_segment(header)?).map_err(|_| invalid("bad header"))?;
+    let alg = header.get("alg").and_then(Value::as_str).unwrap_or_default();
+    let kid = header.get("kid").and_then(Value::as_str);
+    let key = jwks
+        .keys
+        .iter()
+        .find(|key| kid.is_none() || key.kid.as_deref() == kid)
+        .ok_or_else(|| invalid("unknown signing key"))?;
+    let message = &token.as_bytes()[..header_and_payload_len(token)];
+    verify_signature(alg, key, message, &decode_segment(signature)?)?;
+
+    let claims: Value = serde_json::from_slice(&decode_segment(payload)?).map_err(|_| invalid("bad claims"))?;
+    if claims.get("iss").and_then(Value::as_str) != Some(oauth.issuer.as_str()) {
+        return Err(invalid("wrong issuer"));
+    }
+    if let Some(audience) = &oauth.audience {
+        let matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience.as_str())),
+            _ => false,
+        };
+        if !matches {
+            return Err(invalid("wrong audience"));
+        }
+    }
+    let exp = claims.get("exp").and_then(Value::as_u64).ok_or_else(|| invalid("no expiry"))?;
+    if exp + CLOCK_LEEWAY_SECS <= now {
+        return Err(invalid("expired"));
+    }
+    if claims.get("nbf").and_then(Value::as_u64).is_some_and(|nbf| nbf > now + CLOCK_LEEWAY_SECS) {
+        return Err(invalid("not yet valid"));
+    }
+
+    // `scope` is a space-separated string (RFC 9068); some issuers use an `scp` array
+    let scopes = match (claims.get("scope"), claims.get("scp")) {
+        (Some(Value::String(scope)), _) => scope.split_whitespace().map(str::to_string).collect(),
+        (_, Some(Value::Array(scp))) => scp.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+    let subject = claims.get("sub").and_then(Value::as_str).map(str::to_string);
+    let client = match (&subject, claims.get("client_id").and_then(Value::as_str)) {
+        (Some(subject), _) => Some(format!("sub:{}", subject)),
+        (None, Some(client_id)) => Some(format!("client:{}", client_id)),
+        (None, None) => None,
+    };
+    Ok(Grant { subject, client, scopes: Some(scopes) })
+}
+
+fn header_and_payload_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or(token.len())
+}
+
+/// Compares in time independent of where the inputs differ, so tokens cannot be guessed byte
+/// by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn fetch_json(agent: &ureq::Agent, url: &str) -> Result<Value, AuthError> {
+    let mut response = agent.get(url).call().map_err(|e| AuthError::Jwks(e.to_string()))?;
+    let body = response.body_mut().read_to_string().map_err(|e| AuthError::Jwks(e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| AuthError::Jwks(e.to_string()))
+}
+
+/// Authenticates bearer tokens of the HTTP transport, downloading and caching the issuer's
+/// signing keys as needed.
+pub struct Authenticator {
+    config: AuthConfig,
+    jwks: Mutex<JwksCache>,
+    /// Held while the JWKS is downloaded.
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+/// The issuer's signing keys as last downloaded.
+#[derive(Default)]
+struct JwksCache {
+    jwks: Jwks,
+    /// When the last download started, successful or not.
+    fetched: Option<Instant>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Self {
+        Self { config, jwks: Mutex::default(), refreshing: tokio::sync::Mutex::new(()) }
+    }
+
+    fn jwks(&self) -> MutexGuard<'_, JwksCache> {
+        self.jwks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
+    }
+
+    /// Checks the `Authorization` header of a request.
+    pub async fn authenticate(&self, authorization: Option<&str>) -> Result<Grant, AuthError> {
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer ").or_else(|| value.strip_prefix("bearer ")))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or(AuthError::Missing)?;
+        // Every token is compared, so the time taken does not tell which one matched
+        let known = self.config.tokens.iter().enumerate().fold(None, |found, (index, known)| {
+            found.or(constant_time_eq(known.as_bytes(), token.as_bytes()).then_some(index))
+        });
+        if let Some(index) = known {
+            return Ok(Grant { subject: None, client: Some(format!("token:{}", index)), scopes: None });
+        }
+        let Some(oauth) = &self.config.oauth else {
+            return Err(invalid("unknown token"));
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let kid = jwt_key_id(token);
+        let needs_refresh = || {
+            let cached = self.jwks();
+            let known_key = cached.jwks.keys.iter().any(|key| kid.is_none() || key.kid == kid);
+            !known_key && cached.fetched.is_none_or(|fetched| fetched.elapsed() >= JWKS_REFRESH_INTERVAL)
+        };
+        if needs_refresh() {
+            // One download at a time, while the cache stays free for tokens of known keys; a
+            // request that waited for another's download finds the key there
+            let _refreshing = self.refreshing.lock().await;
+            if needs_refresh() {
+                self.jwks().fetched = Some(Instant::now());
+                let oauth = oauth.clone();
+                let jwks = tokio::task::spawn_blocking(move || download_jwks(&oauth))
+                    .await
+                    .map_err(|e| AuthError::Jwks(e.to_string()))??;
+                self.jwks().jwks = jwks;
+            }
+        }
+        let jwks = self.jwks().jwks.clone();
+        verify_jwt(token, &jwks, oauth, now)
+    }
+}
+
+/// Downloads the JWKS, discovering its location from the issuer's OpenID Connect or OAuth
+/// authorization server metadata unless it is configured.
+fn download_jwks(oauth: &OAuthConfig) -> Result<Jwks, AuthError> {
+    let agent = ureq::Agent::config_builder().timeout_global(Some(JWKS_FETCH_TIMEOUT)).build().new_agent();
+    let jwks_uri = match &oauth.jwks_uri {
+        Some(uri) => uri.clone(),
+        None => {
+            let issuer = oauth.issuer.trim_end_matches('/');
+            let metadata = fetch_json(&agent, &format!("{}/.well-known/openid-configuration", issuer))
+                .or_else(|_| fetch_json(&agent, &format!("{}/.well-known/oauth-authorization-server", issuer)))?;
+            metadata
+                .get("jwks_uri")
+                .and_then(Value::as_str)
+                .ok_or_else(|| AuthError::Jwks("the issuer metadata has no jwks_uri".to_string()))?
+                .to_string()
+        }
+    };
+    serde_json::from_value(fetch_json(&agent, &jwks_uri)?).map_err(|e| AuthError::Jwks(e.to_string()))
+}
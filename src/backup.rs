@@ -0,0 +1,159 @@
+use crate::fingerprint::Fnv1a;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where `apply_rewrite` keeps backups when `--backup-dir` is not given.
+pub fn default_backup_dir(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    Some(crate::config::data_dir(env)?.join("backups"))
+}
+
+/// The files one `apply_rewrite` call (or other tool call that writes files) changed, saved as
+/// `manifest.json` next to their backups in `<backup dir>/<id>/`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    pub id: String,
+    pub project_folder: PathBuf,
+    /// When the files were rewritten, UTC.
+    pub created_at: String,
+    /// The rule that was applied; empty for changes not made by a rule.
+    pub yaml: String,
+    #[serde(default)]
+    pub files: Vec<BackedUpFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackedUpFile {
+    /// The rewritten file.
+    pub path: PathBuf,
+    /// File name of its original contents in the backup's directory.
+    pub backup: String,
+    /// Hash of the contents it was rewritten to, to tell whether it changed since.
+    #[serde(default)]
+    pub written: String,
+}
+
+/// What restoring a backup did.
+#[derive(Debug, Default, Serialize)]
+pub struct Restored {
+    pub restored: Vec<PathBuf>,
+    /// Files that were not restored, with the reason.
+    pub skipped: Vec<String>,
+}
+
+/// Directory of the backup `id`; `None` for ids that are not a plain name, so an id cannot point
+/// outside `dir`.
+pub fn backup_path(dir: &Path, id: &str) -> Option<PathBuf> {
+    let plain = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    plain.then(|| dir.join(id))
+}
+
+impl Backup {
+    /// An empty backup with a new id, for changes to files of `project_folder` made now.
+    pub fn new(project_folder: &Path, yaml: &str) -> Self {
+        Backup {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            project_folder: project_folder.to_path_buf(),
+            created_at: crate::manifest::utc_timestamp(std::time::SystemTime::now()),
+            yaml: yaml.to_string(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Saves the original `contents` of `path` before it is rewritten to `rewritten`; the copy is
+    /// named after the file with a `.bak` extension and numbered, so files of the same name do not
+    /// collide.
+    pub fn save_original(&mut self, dir: &Path, path: &Path, contents: &[u8], rewritten: &[u8]) -> io::Result<()> {
+        let folder = backup_path(dir, &self.id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid backup id"))?;
+        std::fs::create_dir_all(&folder)?;
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let backup = format!("{:04}-{}.bak", self.files.len() + 1, name);
+        std::fs::write(folder.join(&backup), contents)?;
+        self.files.push(BackedUpFile { path: path.to_path_buf(), backup, written: content_hash(rewritten) });
+        Ok(())
+    }
+
+    /// Saves the original `contents` of `path` and then replaces them with `rewritten`, atomically;
+    /// the saved original is dropped again if the file cannot be written.
+    pub fn replace(&mut self, dir: &Path, path: &Path, contents: &[u8], rewritten: &[u8]) -> io::Result<()> {
+        self.save_original(dir, path, contents, rewritten)?;
+        write_atomically(path, rewritten).inspect_err(|_| {
+            self.files.pop();
+        })
+    }
+
+    /// Writes `manifest.json`, replacing an older one only once the new one is complete.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let folder = backup_path(dir, &self.id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid backup id"))?;
+        std::fs::create_dir_all(&folder)?;
+        write_atomically(&folder.join("manifest.json"), &serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Puts the original contents of the backup's files back. Files changed since they were
+/// rewritten are skipped unless `force` is set, so later edits are not lost.
+pub fn restore_backup(dir: &Path, backup: &Backup, force: bool) -> Restored {
+    let mut restored = Restored::default();
+    let Some(folder) = backup_path(dir, &backup.id) else {
+        restored.skipped.push("invalid backup id".to_string());
+        return restored;
+    };
+    for file in &backup.files {
+        let changed = match std::fs::read(&file.path) {
+            Ok(current) => content_hash(&current) != file.written,
+            // A deleted file is recreated
+            Err(_) => false,
+        };
+        if changed && !force {
+            restored.skipped.push(format!("{}: changed since it was rewritten", file.path.display()));
+            continue;
+        }
+        let original = std::fs::read(folder.join(&file.backup));
+        match original.and_then(|original| write_atomically(&file.path, &original)) {
+            Ok(()) => restored.restored.push(file.path.clone()),
+            Err(e) => restored.skipped.push(format!("{}: {}", file.path.display(), e)),
+        }
+    }
+    restored
+}
+
+/// Deletes the backup `id` and its files, if there is one.
+pub fn remove_backup(dir: &Path, id: &str) {
+    if let Some(folder) = backup_path(dir, id) {
+        let _ = std::fs::remove_dir_all(folder);
+    }
+}
+
+pub fn load_backup(dir: &Path, id: &str) -> io::Result<Backup> {
+    let folder =
+        backup_path(dir, id).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid backup id"))?;
+    Ok(serde_json::from_slice(&std::fs::read(folder.join("manifest.json"))?)?)
+}
+
+/// Short hash of file contents.
+pub fn content_hash(contents: &[u8]) -> String {
+    let mut hash = Fnv1a::new();
+    hash.write(contents);
+    format!("{:016x}", hash.finish())
+}
+
+/// Replaces the contents of `path` so that readers see either the old or the new contents, never
+/// a partly written file: the new contents go into a temporary file in the same directory, which
+/// is then renamed over `path`. An existing file's permissions are kept.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let partial = path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), uuid::Uuid::new_v4().simple()));
+    let written = (|| {
+        std::fs::write(&partial, contents)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&partial, metadata.permissions())?;
+        }
+        std::fs::rename(&partial, path)
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    written
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// File extensions of the languages ast-grep supports out of the box, keyed by the names and
+/// aliases accepted in a rule's `language` field.
+pub fn language_extensions(language: &str) -> Option<&'static [&'static str]> {
+    let extensions: &[&str] = match language.to_lowercase().as_str() {
+        "bash" | "sh" => &["sh", "bash"],
+        "c" => &["c", "h"],
+        "cpp" | "c++" => &["cpp", "cc", "cxx", "hpp", "hh", "hxx", "h"],
+        "csharp" | "cs" | "c#" => &["cs"],
+        "css" => &["css"],
+        "elixir" | "ex" => &["ex", "exs"],
+        "go" | "golang" => &["go"],
+        "haskell" | "hs" => &["hs"],
+        "html" => &["html", "htm"],
+        "java" => &["java"],
+        "javascript" | "js" => &["js", "mjs", "cjs", "jsx"],
+        "json" => &["json"],
+        "jsx" => &["jsx"],
+        "kotlin" | "kt" => &["kt", "kts"],
+        "lua" => &["lua"],
+        "nix" => &["nix"],
+        "php" => &["php"],
+        "python" | "py" => &["py", "pyi"],
+        "ruby" | "rb" => &["rb"],
+        "rust" | "rs" => &["rs"],
+        "scala" => &["scala", "sc"],
+        "solidity" | "sol" => &["sol"],
+        "swift" => &["swift"],
+        "tsx" => &["tsx"],
+        "typescript" | "ts" => &["ts", "mts", "cts"],
+        "yaml" | "yml" => &["yaml", "yml"],
+        _ => return None,
+    };
+    Some(extensions)
+}
+
+/// Canonical names of the languages ast-grep supports out of the box.
+const LANGUAGES: &[&str] = &[
+    "bash", "c", "cpp", "csharp", "css", "elixir", "go", "haskell", "html", "java", "javascript", "json", "kotlin",
+    "lua", "nix", "php", "python", "ruby", "rust", "scala", "solidity", "swift", "tsx", "typescript", "yaml",
+];
+
+/// The built-in language a file belongs to, judging by its extension.
+pub fn language_for_path(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    LANGUAGES
+        .iter()
+        .copied()
+        .find(|language| language_extensions(language).is_some_and(|exts| exts.contains(&extension.as_str())))
+}
+
+/// Parses the first document of an inline rule; `--inline-rules` accepts several separated by `---`.
+pub fn first_rule_document(yaml: &str) -> Result<serde_yaml::Value, serde_yaml::Error> {
+    Ok(serde_yaml::Deserializer::from_str(yaml)
+        .next()
+        .map(serde_yaml::Value::deserialize)
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// Reads the `language` field of an inline rule (the first document when there are several).
+pub fn rule_language(yaml: &str) -> Result<String, serde_yaml::Error> {
+    let rule = first_rule_document(yaml)?;
+    Ok(rule.get("language").and_then(|v| v.as_str()).unwrap_or("").to_string())
+}
+
+/// Picks the files to time one by one: those with the rule's extensions (all files when the
+/// language is unknown), largest first, since big files are where slow rules hurt most.
+pub fn profile_candidates(files: Vec<PathBuf>, language: &str, limit: usize) -> (Vec<PathBuf>, usize) {
+    let mut sized: Vec<(u64, PathBuf)> = files
+        .into_iter()
+        .filter(|path| is_language_file(path, language))
+        .map(|path| (std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0), path))
+        .collect();
+    let total = sized.len();
+    sized.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    (sized.into_iter().take(limit).map(|(_, path)| path).collect(), total)
+}
+
+/// Whether `path` has one of `language`'s extensions; always true for unknown languages,
+/// which may be custom ones registered in sgconfig.yaml.
+pub fn is_language_file(path: &Path, language: &str) -> bool {
+    let Some(extensions) = language_extensions(language) else {
+        return true;
+    };
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| extensions.contains(&ext.as_str()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileTiming {
+    pub file: String,
+    /// Scan time minus the startup baseline.
+    pub ms: f64,
+    pub matches: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkReport {
+    /// Wall time of one scan over the whole project.
+    pub wall_ms: f64,
+    pub total_matches: usize,
+    pub files_with_matches: usize,
+    /// Time ast-grep takes to start and scan empty input; subtracted from per-file timings.
+    pub startup_ms: f64,
+    pub candidate_files: usize,
+    pub profiled_files: usize,
+    pub slowest_files: Vec<FileTiming>,
+}
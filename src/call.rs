@@ -0,0 +1,115 @@
+use crate::config::{CallArgs, Config};
+use crate::server::AstGrepServer;
+use crate::toolset::{ast_grep_version, unavailable_reason};
+use anyhow::Result;
+use rmcp::model::{CallToolRequestParams, CallToolResult, JsonObject, RawContent};
+use rmcp::ServiceExt;
+use serde_json::Value;
+
+/// Runs a single tool call against an in-process server and returns the result.
+///
+/// The server and client are connected through an in-memory pipe, so the call goes
+/// through the same MCP request handling as a real client session.
+pub async fn call_tool(config: Config, args: &CallArgs) -> Result<CallToolResult> {
+    let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+
+    let read_only = config.read_only;
+    let server = AstGrepServer::new(config);
+    tokio::spawn(async move {
+        if let Ok(service) = server.serve(server_io).await {
+            let _ = service.waiting().await;
+        }
+    });
+
+    let client = ().serve(client_io).await.map_err(|e| anyhow::anyhow!("Error connecting to server: {}", e))?;
+
+    let tools = client.list_all_tools().await?;
+    let Some(tool) = tools.iter().find(|t| t.name == args.tool) else {
+        let installed = ast_grep_version().await.is_some();
+        if let Some(reason) = unavailable_reason(&args.tool, read_only, installed) {
+            anyhow::bail!("Tool '{}' is not available: {}. Run the `doctor` tool for details", args.tool, reason);
+        }
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_ref()).collect();
+        anyhow::bail!("Unknown tool '{}'. Available tools: {}", args.tool, names.join(", "));
+    };
+
+    let arguments = parse_tool_args(&args.args, &tool.input_schema)?;
+    let result = client
+        .call_tool(CallToolRequestParams {
+            meta: None,
+            name: tool.name.clone(),
+            arguments: Some(arguments),
+            task: None,
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e));
+
+    let _ = client.cancel().await;
+    result
+}
+
+/// Converts `--name value` pairs into a JSON argument object for a tool.
+///
+/// Dashes in names are mapped to underscores. Values are coerced using the property
+/// type declared in the tool's input schema, so `--max-results 5` becomes a number while
+/// `--pattern 5` stays a string. A flag with no value is treated as `true`.
+pub fn parse_tool_args(args: &[String], input_schema: &JsonObject) -> Result<JsonObject> {
+    let properties = input_schema.get("properties").and_then(|v| v.as_object());
+    let mut arguments = JsonObject::new();
+    let mut iter = args.iter().peekable();
+
+    while let Some(arg) = iter.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            anyhow::bail!("Unexpected argument '{}'. Tool arguments must be passed as --name value", arg);
+        };
+
+        let (name, raw_value) = match name.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => {
+                let value = match iter.peek() {
+                    Some(next) if !next.starts_with("--") => iter.next().cloned(),
+                    _ => None,
+                };
+                (name, value)
+            }
+        };
+        let name = name.replace('-', "_");
+
+        let property_type = properties
+            .and_then(|p| p.get(&name))
+            .and_then(schema_type);
+
+        let value = match raw_value {
+            None => Value::Bool(true),
+            Some(raw) if property_type == Some("string") => Value::String(raw),
+            Some(raw) => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+        };
+
+        arguments.insert(name, value);
+    }
+
+    Ok(arguments)
+}
+
+/// Returns the primary JSON type of a schema property, skipping `"null"` in type unions.
+fn schema_type(property: &Value) -> Option<&str> {
+    match property.get("type")? {
+        Value::String(t) => Some(t.as_str()),
+        Value::Array(types) => types.iter().filter_map(|t| t.as_str()).find(|t| *t != "null"),
+        _ => None,
+    }
+}
+
+/// Renders a tool result for terminal output, joining text content blocks.
+pub fn render_result(result: &CallToolResult) -> String {
+    result
+        .content
+        .iter()
+        .filter_map(|c| match &c.raw {
+            RawContent::Text(text) => Some(text.text.clone()),
+            RawContent::ResourceLink(link) => Some(link.uri.clone()),
+            other => serde_json::to_string_pretty(other).ok(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,186 @@
+use crate::language::language_alias;
+use crate::ranges::{match_position, Position};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const DEF_RULE_ID: &str = "callgraph-def";
+pub const CALL_RULE_ID: &str = "callgraph-call";
+
+/// Name of the caller for calls outside any function, e.g. at module level.
+pub const TOP_LEVEL: &str = "<top-level>";
+
+/// Values of `call_graph`'s `format`.
+pub const CALL_GRAPH_FORMATS: &[&str] = &["json", "dot"];
+
+/// Node kinds of function definitions and calls, each with the field holding the function's
+/// name or the called expression.
+struct CallGraphKinds {
+    definitions: &'static [(&'static str, &'static str)],
+    calls: &'static [(&'static str, &'static str)],
+}
+
+fn call_graph_kinds(language: &str) -> Option<CallGraphKinds> {
+    let kinds = match language_alias(language).as_str() {
+        "python" => CallGraphKinds {
+            definitions: &[("function_definition", "name")],
+            calls: &[("call", "function")],
+        },
+        "javascript" | "typescript" | "tsx" => CallGraphKinds {
+            definitions: &[
+                ("function_declaration", "name"),
+                ("generator_function_declaration", "name"),
+                ("method_definition", "name"),
+            ],
+            calls: &[("call_expression", "function")],
+        },
+        "rust" => CallGraphKinds {
+            definitions: &[("function_item", "name")],
+            calls: &[("call_expression", "function")],
+        },
+        "go" => CallGraphKinds {
+            definitions: &[("function_declaration", "name"), ("method_declaration", "name")],
+            calls: &[("call_expression", "function")],
+        },
+        "java" => CallGraphKinds {
+            definitions: &[("method_declaration", "name"), ("constructor_declaration", "name")],
+            calls: &[("method_invocation", "name")],
+        },
+        "csharp" => CallGraphKinds {
+            definitions: &[("method_declaration", "name"), ("local_function_statement", "name")],
+            calls: &[("invocation_expression", "function")],
+        },
+        "ruby" => CallGraphKinds {
+            definitions: &[("method", "name"), ("singleton_method", "name")],
+            calls: &[("call", "method")],
+        },
+        "php" => CallGraphKinds {
+            definitions: &[("function_definition", "name"), ("method_declaration", "name")],
+            calls: &[("function_call_expression", "function"), ("member_call_expression", "name")],
+        },
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// Inline rules matching the function definitions (capturing `$NAME`) and calls (capturing
+/// `$CALLEE`) of `language`.
+pub fn call_graph_rules(language: &str) -> Option<String> {
+    let kinds = call_graph_kinds(language)?;
+    let rule = |id: &str, kinds: &[(&str, &str)], var: &str| {
+        let any: Vec<String> = kinds
+            .iter()
+            .map(|(kind, field)| {
+                format!("    - kind: {}\n      has:\n        field: {}\n        pattern: ${}", kind, field, var)
+            })
+            .collect();
+        format!("id: {}\nlanguage: {}\nrule:\n  any:\n{}\n", id, language, any.join("\n"))
+    };
+    Some([rule(DEF_RULE_ID, kinds.definitions, "NAME"), rule(CALL_RULE_ID, kinds.calls, "CALLEE")].join("---\n"))
+}
+
+/// The function name a call expression refers to: the last segment of `obj.method`,
+/// `Type::function` or `ptr->method`, without generic arguments.
+pub fn callee_name(callee: &str) -> String {
+    let mut depth = 0usize;
+    let mut stripped = String::new();
+    for (i, c) in callee.char_indices() {
+        match c {
+            '<' => depth += 1,
+            // `->` is a member access, not the end of generic arguments
+            '>' if depth > 0 && !callee[..i].ends_with('-') => depth -= 1,
+            '(' | '[' if depth == 0 => break,
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    let last = stripped
+        .rsplit(|c: char| c == '.' || c == ':' || c == '>' || c == '-' || c.is_whitespace())
+        .find(|segment| !segment.is_empty())
+        .unwrap_or_default();
+    last.trim_matches(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$' || c == '?' || c == '!'))
+        .to_string()
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CallGraph {
+    /// Function name to where it is defined, as `file:line` (1-based).
+    pub functions: BTreeMap<String, Vec<String>>,
+    /// Caller to callees.
+    pub edges: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl CallGraph {
+    pub fn edge_count(&self) -> usize {
+        self.edges.values().map(BTreeSet::len).sum()
+    }
+
+    /// The graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut lines = vec!["digraph calls {".to_string()];
+        for (caller, callees) in &self.edges {
+            for callee in callees {
+                lines.push(format!("  {:?} -> {:?};", caller, callee));
+            }
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+}
+
+/// Builds caller-to-callee edges from the matches of [`call_graph_rules`].
+///
+/// Each call is attributed to the innermost function definition containing it in the same
+/// file. Functions are identified by name alone, so same-named functions are merged; calls
+/// to functions not defined in the project are dropped unless `include_external` is set.
+pub fn build_call_graph(matches: &[Value], include_external: bool) -> CallGraph {
+    type Range = (Position, Position);
+    let range_of = |m: &Value| -> Range { (match_position(m, "start"), match_position(m, "end")) };
+    let captured = |m: &Value, var: &str| {
+        m.pointer(&format!("/metaVariables/single/{}/text", var))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+
+    let mut graph = CallGraph::default();
+    let mut definitions: BTreeMap<String, Vec<(Range, String)>> = BTreeMap::new();
+    let mut calls = Vec::new();
+    for m in matches {
+        let file = m.get("file").and_then(Value::as_str).unwrap_or_default().to_string();
+        match m.get("ruleId").and_then(Value::as_str) {
+            Some(DEF_RULE_ID) => {
+                let Some(name) = captured(m, "NAME") else {
+                    continue;
+                };
+                let range = range_of(m);
+                graph
+                    .functions
+                    .entry(name.clone())
+                    .or_default()
+                    .push(format!("{}:{}", file, range.0 .0 + 1));
+                definitions.entry(file).or_default().push((range, name));
+            }
+            Some(CALL_RULE_ID) => {
+                if let Some(callee) = captured(m, "CALLEE") {
+                    calls.push((file, range_of(m).0, callee_name(&callee)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (file, start, callee) in calls {
+        if callee.is_empty() || (!include_external && !graph.functions.contains_key(&callee)) {
+            continue;
+        }
+        let caller = definitions
+            .get(&file)
+            .into_iter()
+            .flatten()
+            .filter(|(range, _)| range.0 <= start && start < range.1)
+            .max_by_key(|(range, _)| range.0)
+            .map_or(TOP_LEVEL.to_string(), |(_, name)| name.clone());
+        graph.edges.entry(caller).or_default().insert(callee);
+    }
+    graph
+}
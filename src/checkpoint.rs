@@ -0,0 +1,117 @@
+use crate::fingerprint::Fnv1a;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Progress of a background scan saved to disk, so the scan can be resumed after the server
+/// restarts or the scan fails part-way.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub job_id: String,
+    /// The `submit_scan` arguments, to restart the scan with.
+    pub request: Value,
+    /// Query arguments the completed parts were scanned with.
+    pub args: Vec<String>,
+    /// [`plan_hash`] of the scan parts the completed parts belong to; their numbers mean nothing
+    /// for another plan.
+    #[serde(default)]
+    pub plan: String,
+    /// Completed scan parts as (folder index, shard number; `None` for unsharded folders).
+    #[serde(default)]
+    pub completed: Vec<(usize, Option<usize>)>,
+    /// Matches found by the completed parts.
+    #[serde(default)]
+    pub matches: Vec<Value>,
+}
+
+/// Completed parts as appended to a checkpoint file, one per line after the header.
+#[derive(Serialize, Deserialize)]
+struct CompletedParts {
+    parts: Vec<(usize, Option<usize>)>,
+    matches: Vec<Value>,
+}
+
+/// Hash of the paths each part of a scan covers, in order: a scan resumed over a project whose
+/// files were added or removed since is planned into different shards, and gets a different hash.
+pub fn plan_hash<'a>(parts: impl IntoIterator<Item = &'a [String]>) -> String {
+    let mut hash = Fnv1a::new();
+    for paths in parts {
+        for path in paths {
+            hash.write(path.as_bytes());
+        }
+        hash.write(b"");
+    }
+    format!("{:016x}", hash.finish())
+}
+
+/// Where checkpoints go when `--checkpoint-dir` is not given.
+pub fn default_checkpoint_dir(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    Some(crate::config::data_dir(env)?.join("checkpoints"))
+}
+
+/// Path of the checkpoint of `job_id`; `None` for ids that are not a plain name, so an id
+/// cannot point outside `dir`.
+pub fn checkpoint_path(dir: &Path, job_id: &str) -> Option<PathBuf> {
+    let plain = !job_id.is_empty() && job_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    plain.then(|| dir.join(format!("{}.json", job_id)))
+}
+
+/// Writes the checkpoint, replacing an older one only once the new one is complete.
+///
+/// The file holds the checkpoint without its parts on the first line, then lines of completed
+/// parts, so [`append_part`] can add a part without rewriting the matches of the others.
+pub fn save_checkpoint(dir: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    let path = checkpoint_path(dir, &checkpoint.job_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid job id"))?;
+    std::fs::create_dir_all(dir)?;
+    let header = Checkpoint {
+        completed: Vec::new(),
+        matches: Vec::new(),
+        ..checkpoint.clone()
+    };
+    let mut contents = serde_json::to_vec(&header)?;
+    contents.push(b'\n');
+    if !checkpoint.completed.is_empty() {
+        let parts = CompletedParts { parts: checkpoint.completed.clone(), matches: checkpoint.matches.clone() };
+        serde_json::to_writer(&mut contents, &parts)?;
+        contents.push(b'\n');
+    }
+    let partial = path.with_extension("json.tmp");
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, &path)
+}
+
+/// Adds the completed `part` of job `job_id` and its `matches` to its saved checkpoint.
+pub fn append_part(dir: &Path, job_id: &str, part: (usize, Option<usize>), matches: &[Value]) -> io::Result<()> {
+    let path =
+        checkpoint_path(dir, job_id).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid job id"))?;
+    let mut line = serde_json::to_vec(&CompletedParts { parts: vec![part], matches: matches.to_vec() })?;
+    line.push(b'\n');
+    std::fs::OpenOptions::new().append(true).open(path)?.write_all(&line)
+}
+
+/// Reads a checkpoint saved by [`save_checkpoint`] and [`append_part`]. A last part cut short
+/// (the server stopped while appending it) is left out, so that part is scanned again.
+pub fn load_checkpoint(dir: &Path, job_id: &str) -> io::Result<Checkpoint> {
+    let path = checkpoint_path(dir, job_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid job id"))?;
+    let mut lines = io::BufReader::new(std::fs::File::open(path)?).lines();
+    let header = lines.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty checkpoint"))??;
+    let mut checkpoint: Checkpoint = serde_json::from_str(&header)?;
+    for line in lines {
+        let Ok(parts) = serde_json::from_str::<CompletedParts>(&line?) else {
+            break;
+        };
+        checkpoint.completed.extend(parts.parts);
+        checkpoint.matches.extend(parts.matches);
+    }
+    Ok(checkpoint)
+}
+
+/// Deletes the checkpoint of `job_id`, if there is one.
+pub fn remove_checkpoint(dir: &Path, job_id: &str) {
+    if let Some(path) = checkpoint_path(dir, job_id) {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::apply::{plan_rewrite, write_planned_file};
+use crate::backup::Backup;
+use crate::config::{Cli, Config};
+use crate::dump::{DumpFormat, DUMP_FORMATS};
+use crate::encoding::fix_match_encodings;
+use crate::engine::run_ast_grep;
+use crate::filter::{drop_generated_files, exclude_glob_args, no_ignore_args, SkippedFile};
+use crate::fingerprint::add_fingerprints;
+use crate::inline::with_language;
+#[cfg(feature = "parsers")]
+use crate::parsers::dump_tree;
+use crate::ranges::{normalize_match_ranges, normalize_source_ranges, sort_by_position};
+use crate::rewrite::apply_fixes;
+use crate::roots::resolve_within_roots;
+use crate::schema::{validate_rule_yaml, SchemaViolation};
+use crate::toolset::one_of;
+use clap::Parser;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Why an [`AstGrepClient`] call failed.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("The rule does not follow the ast-grep rule schema: {}", list_violations(.0))]
+    InvalidRule(Vec<SchemaViolation>),
+
+    #[error(transparent)]
+    AstGrep(#[from] anyhow::Error),
+}
+
+fn list_violations(violations: &[SchemaViolation]) -> String {
+    violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+/// A zero-based line and character column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a match is, as positions and byte offsets into its file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MatchRange {
+    pub start: Position,
+    pub end: Position,
+    pub byte_start: usize,
+    pub byte_end: usize,
+}
+
+/// One match of a pattern or rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Match {
+    /// Path of the file as ast-grep reports it (`STDIN` for code passed directly).
+    pub file: String,
+    pub text: String,
+    pub range: MatchRange,
+    /// Text bound to each single metavariable, by name without `$`.
+    pub metavariables: BTreeMap<String, String>,
+    /// Texts bound to each multi metavariable (`$$$NAME`).
+    pub multi_metavariables: BTreeMap<String, Vec<String>>,
+    /// The rule that matched, for rule searches.
+    pub rule_id: Option<String>,
+    pub severity: Option<String>,
+    pub message: Option<String>,
+    /// The rewritten text, for rewrites and rules with a `fix`.
+    pub replacement: Option<String>,
+    /// Stable fingerprint of rule id, file and text; see [`crate::fingerprint::fingerprint`].
+    pub fingerprint: Option<String>,
+}
+
+impl Match {
+    /// Reads a match from ast-grep's `--json` output, after its ranges were normalized.
+    pub fn from_json(m: &Value) -> Option<Match> {
+        let number = |pointer: &str| m.pointer(pointer).and_then(Value::as_u64).map(|n| n as usize);
+        let string = |key: &str| m.get(key).and_then(Value::as_str).map(str::to_string);
+        let position = |end: &str| {
+            Some(Position {
+                line: number(&format!("/range/{}/line", end))?,
+                column: number(&format!("/range/{}/column", end))?,
+            })
+        };
+        let text_of = |v: &Value| v.get("text").and_then(Value::as_str).unwrap_or_default().to_string();
+
+        let single = m.pointer("/metaVariables/single").and_then(Value::as_object);
+        let multi = m.pointer("/metaVariables/multi").and_then(Value::as_object);
+        Some(Match {
+            file: string("file")?,
+            text: string("text").unwrap_or_default(),
+            range: MatchRange {
+                start: position("start")?,
+                end: position("end")?,
+                byte_start: number("/range/byteOffset/start").unwrap_or_default(),
+                byte_end: number("/range/byteOffset/end").unwrap_or_default(),
+            },
+            metavariables: single
+                .into_iter()
+                .flatten()
+                .map(|(name, value)| (name.clone(), text_of(value)))
+                .collect(),
+            multi_metavariables: multi
+                .into_iter()
+                .flatten()
+                .map(|(name, values)| {
+                    let texts = values.as_array().into_iter().flatten().map(text_of).collect();
+                    (name.clone(), texts)
+                })
+                .collect(),
+            rule_id: string("ruleId"),
+            severity: string("severity"),
+            message: string("message"),
+            replacement: string("replacement"),
+            fingerprint: string("fingerprint"),
+        })
+    }
+}
+
+/// The matches of a search.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Matches {
+    /// Matches by file and position, at most `max_results` of them.
+    pub matches: Vec<Match>,
+    /// How many matches there were before `max_results` was applied.
+    pub total: usize,
+    /// Minified or binary-looking files whose matches were dropped.
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// The result of [`AstGrepClient::rewrite`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Rewritten {
+    /// The matches, each with its `replacement`.
+    pub matches: Matches,
+    /// Files written, relative to the project folder; empty unless the rewrite was applied.
+    pub files: Vec<String>,
+    /// The backup of the files written, which `undo_rewrite` or [`crate::backup::restore_backup`]
+    /// puts back.
+    pub backup_id: Option<String>,
+    /// Files with matches that were not written, with the reason.
+    pub skipped: Vec<String>,
+}
+
+/// Options shared by the search requests, with the same meaning as the `find_code` parameters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    /// Most matches to return; `None` returns all of them.
+    pub max_results: Option<usize>,
+    /// Also search dependency and build directories (node_modules, target, ...).
+    pub include_vendored: bool,
+    /// Also search files excluded by .gitignore, .ignore or .sgignore.
+    pub no_ignore: bool,
+    /// Keep matches in minified or binary-looking files.
+    pub include_minified: bool,
+}
+
+/// A search for a pattern in a project folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FindCodeRequest {
+    /// Absolute path of the project folder.
+    pub project_folder: PathBuf,
+    pub pattern: String,
+    /// The language of the pattern; `None` lets ast-grep infer it from file extensions.
+    pub language: Option<String>,
+    pub options: SearchOptions,
+}
+
+impl FindCodeRequest {
+    pub fn new(project_folder: impl Into<PathBuf>, pattern: impl Into<String>) -> Self {
+        Self {
+            project_folder: project_folder.into(),
+            pattern: pattern.into(),
+            language: None,
+            options: SearchOptions::default(),
+        }
+    }
+}
+
+/// A search for a YAML rule in a project folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FindRuleRequest {
+    /// Absolute path of the project folder.
+    pub project_folder: PathBuf,
+    pub yaml: String,
+    pub options: SearchOptions,
+}
+
+impl FindRuleRequest {
+    pub fn new(project_folder: impl Into<PathBuf>, yaml: impl Into<String>) -> Self {
+        Self { project_folder: project_folder.into(), yaml: yaml.into(), options: SearchOptions::default() }
+    }
+}
+
+/// A rewrite of every match of a pattern in a project folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewriteRequest {
+    /// Absolute path of the project folder.
+    pub project_folder: PathBuf,
+    pub pattern: String,
+    /// The replacement, which may use the pattern's metavariables.
+    pub rewrite: String,
+    pub language: String,
+    /// Write the rewritten files; otherwise the matches only carry their `replacement`.
+    pub apply: bool,
+    pub options: SearchOptions,
+}
+
+/// The search and rewrite functionality of the server, for Rust applications that embed it
+/// instead of speaking MCP. Calls run the ast-grep CLI with the same filtering and
+/// post-processing as the MCP tools.
+#[derive(Debug, Clone)]
+pub struct AstGrepClient {
+    config: Config,
+}
+
+impl Default for AstGrepClient {
+    /// A client with the server's default configuration, ignoring the environment.
+    fn default() -> Self {
+        let cli = Cli::parse_from(["ast-grep-mcp-server"]);
+        Self::new(Config::from_args_with_env(&cli, |_| None).expect("the default configuration is valid"))
+    }
+}
+
+impl AstGrepClient {
+    /// A client using `config`; its sgconfig path and timeout apply, and for rewrites that are
+    /// applied its allowed roots and backup directory.
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Finds the matches of a pattern, like the `find_code` tool.
+    pub async fn find_code(&self, request: FindCodeRequest) -> Result<Matches, ClientError> {
+        let mut args = vec!["--pattern".to_string(), request.pattern];
+        if let Some(language) = request.language {
+            args.extend(["--lang".to_string(), language]);
+        }
+        self.search("run", args, &request.project_folder, &request.options).await
+    }
+
+    /// Finds the matches of a YAML rule, like the `find_code_by_rule` tool.
+    pub async fn find_code_by_rule(&self, request: FindRuleRequest) -> Result<Matches, ClientError> {
+        validate_rule_yaml(&request.yaml).map_err(ClientError::InvalidRule)?;
+        let args = vec!["--inline-rules".to_string(), request.yaml];
+        self.search("scan", args, &request.project_folder, &request.options).await
+    }
+
+    /// Matches a YAML rule against `code`, like the `test_match_code_rule` tool, except that no
+    /// matches is not an error.
+    pub async fn test_rule(&self, yaml: &str, code: &str) -> Result<Vec<Match>, ClientError> {
+        validate_rule_yaml(yaml).map_err(ClientError::InvalidRule)?;
+        let args = ["--inline-rules".to_string(), yaml.to_string(), "--json".to_string(), "--stdin".to_string()];
+        let result = run_ast_grep("scan", &args, Some(code), &self.config).await?;
+        let mut matches = parse_json(&result.stdout);
+        normalize_source_ranges(&mut matches, code);
+        Ok(matches.iter().filter_map(Match::from_json).collect())
+    }
+
+    /// `code` rewritten by the `fix` of a YAML rule, like the `rewrite_code` tool; rule documents
+    /// without a language get `language`.
+    pub async fn rewrite_code(&self, yaml: &str, language: &str, code: &str) -> Result<String, ClientError> {
+        let yaml = with_language(yaml, language).map_err(ClientError::InvalidRequest)?;
+        validate_rule_yaml(&yaml).map_err(ClientError::InvalidRule)?;
+        let args = ["--inline-rules".to_string(), yaml, "--json".to_string(), "--stdin".to_string()];
+        let result = run_ast_grep("scan", &args, Some(code), &self.config).await?;
+        let (fixed, _, _) = apply_fixes(code, &parse_json(&result.stdout));
+        Ok(fixed)
+    }
+
+    /// Rewrites every match of a pattern. The returned matches carry their `replacement`; with
+    /// `apply`, the files are rewritten as well, as the `apply_rewrite` tool rewrites them: the
+    /// project folder must be inside the configured allowed roots, and each file is replaced
+    /// atomically once its original is saved in the backup directory.
+    pub async fn rewrite(&self, request: RewriteRequest) -> Result<Rewritten, ClientError> {
+        let args = vec![
+            "--pattern".to_string(),
+            request.pattern.clone(),
+            "--rewrite".to_string(),
+            request.rewrite.clone(),
+            "--lang".to_string(),
+            request.language.clone(),
+        ];
+        let matches = self.search("run", args, &request.project_folder, &request.options).await?;
+        let mut rewritten = Rewritten { matches, ..Rewritten::default() };
+        if !request.apply || rewritten.matches.matches.is_empty() {
+            return Ok(rewritten);
+        }
+
+        let Some(backup_dir) = &self.config.backup_dir else {
+            return Err(ClientError::InvalidRequest("No backup directory is configured".to_string()));
+        };
+        let project_folder = resolve_within_roots(&request.project_folder, &self.config.allowed_roots)
+            .map_err(|e| ClientError::InvalidRequest(e.to_string()))?;
+        // JSON is YAML, and spares quoting the pattern and its replacement
+        let yaml = serde_json::json!({
+            "id": "rewrite",
+            "language": request.language,
+            "rule": { "pattern": request.pattern },
+            "fix": request.rewrite,
+        })
+        .to_string();
+        let SearchOptions { include_vendored, no_ignore, .. } = request.options;
+        let plan = plan_rewrite(&yaml, &project_folder, include_vendored, no_ignore, &self.config).await?;
+
+        let mut backup = Backup::new(&project_folder, &yaml);
+        rewritten.skipped = plan.skipped;
+        for file in plan.files {
+            match write_planned_file(&mut backup, backup_dir, &file) {
+                Ok(()) => rewritten.files.push(file.path),
+                Err(reason) => rewritten.skipped.push(format!("{}: {}", file.path, reason)),
+            }
+        }
+        if !backup.files.is_empty() {
+            backup.save(backup_dir).map_err(anyhow::Error::from)?;
+            rewritten.backup_id = Some(backup.id);
+        }
+        Ok(rewritten)
+    }
+
+    /// ast-grep's syntax tree dump of `code`, like the `dump_syntax_tree` tool; `format` is
+    /// `pattern`, `cst` or `ast`.
+    pub async fn dump_syntax_tree(&self, code: &str, language: &str, format: &str) -> Result<String, ClientError> {
+        let Some(dump_format) = DumpFormat::parse(format) else {
+            return Err(ClientError::InvalidRequest(format!(
+                "Invalid format: {}. Must be {}.",
+                format,
+                one_of(DUMP_FORMATS)
+            )));
+        };
+        #[cfg(feature = "parsers")]
+        if let Some(dump) = dump_tree(code, language, dump_format) {
+            return Ok(dump.trim().to_string());
+        }
+        let args = [
+            "--pattern".to_string(),
+            code.to_string(),
+            "--lang".to_string(),
+            language.to_string(),
+            format!("--debug-query={}", dump_format.as_str()),
+        ];
+        let result = run_ast_grep("run", &args, None, &self.config).await?;
+        Ok(result.stderr.trim().to_string())
+    }
+
+    async fn search(
+        &self,
+        command: &str,
+        mut args: Vec<String>,
+        project_folder: &Path,
+        options: &SearchOptions,
+    ) -> Result<Matches, ClientError> {
+        if !project_folder.is_absolute() {
+            return Err(ClientError::InvalidRequest(format!(
+                "project_folder must be an absolute path, got {}",
+                project_folder.display()
+            )));
+        }
+        args.extend(exclude_glob_args(options.include_vendored));
+        args.extend(no_ignore_args(options.no_ignore));
+        args.push("--json".to_string());
+        args.push(project_folder.to_string_lossy().into_owned());
+        let result = run_ast_grep(command, &args, None, &self.config).await?;
+
+        let mut matches = parse_json(&result.stdout);
+        let mut skipped = Vec::new();
+        if !options.include_minified {
+            (matches, skipped) = drop_generated_files(matches, project_folder);
+        }
+        normalize_match_ranges(&mut matches, project_folder);
+        sort_by_position(&mut matches);
+        fix_match_encodings(&mut matches, project_folder);
+        add_fingerprints(&mut matches);
+
+        let total = matches.len();
+        if let Some(max_results) = options.max_results {
+            matches.truncate(max_results);
+        }
+        Ok(Matches { matches: matches.iter().filter_map(Match::from_json).collect(), total, skipped })
+    }
+}
+
+fn parse_json(stdout: &str) -> Vec<Value> {
+    serde_json::from_str(stdout.trim()).unwrap_or_default()
+}
@@ -1,7 +1,13 @@
+use crate::config::Config;
 use anyhow::Result;
-use std::process::Stdio;
-use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CommandError {
@@ -11,6 +17,12 @@ pub enum CommandError {
     #[error("Command '{name}' not found. Please ensure {name} is installed and in PATH.")]
     NotFound { name: String, source: std::io::Error },
 
+    #[error("Command {cmd:?} timed out after {secs}s")]
+    Timeout { cmd: Vec<String>, secs: u64 },
+
+    #[error("Refusing to run '{program}' with a command string: commands are never run through a shell")]
+    ShellInvocation { program: String },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -20,77 +32,228 @@ pub struct CommandResult {
     pub stderr: String,
 }
 
-pub async fn run_command(args: &[String], input_text: Option<&str>) -> Result<CommandResult, CommandError> {
-    // Windows handling: if command is "ast-grep", use shell=True equivalent
-    // But here we are passed "args" where args[0] is likely "ast-grep".
+/// Result of [`run_command_limited`].
+pub struct LimitedResult {
+    pub result: CommandResult,
+    /// Whether the command was killed after `max_lines` lines, so its output is incomplete.
+    pub stopped: bool,
+}
 
-    let mut cmd_args = args.to_vec();
-    if cmd_args.is_empty() {
-        return Err(CommandError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty command args")));
+/// Decides which lines of a command's output count towards the limit of [`run_command_counted`].
+pub type LineFilter<'a> = dyn Fn(&[u8]) -> bool + Sync + 'a;
+
+/// Pids of the children of running commands.
+fn children() -> &'static Mutex<HashSet<u32>> {
+    static CHILDREN: OnceLock<Mutex<HashSet<u32>>> = OnceLock::new();
+    CHILDREN.get_or_init(Default::default)
+}
+
+/// Pids of the children of commands that are still running.
+pub fn running_children() -> Vec<u32> {
+    children().lock().unwrap_or_else(|e| e.into_inner()).iter().copied().collect()
+}
+
+/// Kills the process groups of all running commands, for shutdown paths that exit the process
+/// without dropping the futures running them.
+pub fn kill_all_children() {
+    for pid in children().lock().unwrap_or_else(|e| e.into_inner()).drain() {
+        kill_process_group(pid);
     }
-    let program = cmd_args.remove(0);
+}
 
-    let mut command = if cfg!(target_os = "windows") && program == "ast-grep" {
-        let mut cmd = Command::new("cmd");
-        cmd.arg("/C");
-        cmd.arg(&program);
-        cmd.args(&cmd_args);
-        cmd
-    } else {
-        let mut cmd = Command::new(&program);
-        cmd.args(&cmd_args);
-        cmd
-    };
+/// Registers the child in [`running_children`] and kills its whole process group when dropped,
+/// unless disarmed.
+///
+/// The child is spawned as the leader of its own process group, so this also reaches any
+/// helper processes it started. Dropping the guard without disarming it happens when the
+/// command times out or the calling future is dropped (e.g. the request was cancelled, the
+/// client disconnected or the task panicked); the child itself is then reaped by tokio, as it
+/// is spawned with `kill_on_drop`.
+struct ProcessGroupGuard {
+    pid: Option<u32>,
+    armed: bool,
+}
 
-    command.stdin(Stdio::piped());
-    command.stdout(Stdio::piped());
-    command.stderr(Stdio::piped());
+impl ProcessGroupGuard {
+    fn new(pid: Option<u32>) -> Self {
+        if let Some(pid) = pid {
+            children().lock().unwrap_or_else(|e| e.into_inner()).insert(pid);
+        }
+        Self { pid, armed: true }
+    }
 
-    // Spawn the child process
-    let mut child = command.spawn().map_err(|e| {
-        if e.kind() == std::io::ErrorKind::NotFound {
-            CommandError::NotFound { name: program.clone(), source: e }
-        } else {
-            CommandError::Io(e)
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        let Some(pid) = self.pid else {
+            return;
+        };
+        children().lock().unwrap_or_else(|e| e.into_inner()).remove(&pid);
+        if self.armed {
+            tracing::debug!(pid, "killing process group");
+            kill_process_group(pid);
         }
-    })?;
+    }
+}
+
+#[cfg(unix)]
+fn kill_process_group(pid: u32) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid targets the group.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
 
-    // Write input to stdin if provided
-    if let Some(input) = input_text {
-        if let Some(mut stdin) = child.stdin.take() {
-            if let Err(e) = stdin.write_all(input.as_bytes()).await {
-                 // Ignore broken pipe errors as the process might have closed stdin
-                 if e.kind() != std::io::ErrorKind::BrokenPipe {
-                     return Err(CommandError::Io(e));
-                 }
+#[cfg(windows)]
+fn kill_process_group(pid: u32) {
+    // taskkill /T terminates the whole tree, including the cmd.exe running an `ast-grep.cmd` shim.
+    let _ = std::process::Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
+
+/// Whether `program` is a shell and `args` make it run a command string (`sh -c`, `cmd /C`,
+/// `powershell -Command`), which would interpret patterns and rules passed in it.
+pub fn is_shell_invocation(program: &str, args: &[String]) -> bool {
+    let name = Path::new(program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let command_flags: &[&str] = match name.as_str() {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" | "fish" => &["-c"],
+        "cmd" => &["/c", "/k", "/r"],
+        "powershell" | "pwsh" => &["-c", "-command", "-encodedcommand", "-ec"],
+        _ => return false,
+    };
+    // Only options before the script or command count; later ones belong to the script
+    args.iter()
+        .map(|arg| arg.to_lowercase())
+        .take_while(|arg| arg.starts_with('-') || (name == "cmd" && arg.starts_with('/')))
+        .any(|arg| {
+            command_flags.contains(&arg.as_str())
+                // Combined short flags such as `sh -ec`
+                || (command_flags == ["-c"] && !arg.starts_with("--") && arg.contains('c'))
+        })
+}
+
+/// Finds `program` on PATH with the extensions ast-grep is installed with on Windows
+/// (`ast-grep.exe`, or the `ast-grep.cmd` shim npm creates). The standard library escapes the
+/// arguments of batch files for cmd.exe, so they are still not interpreted.
+#[cfg(windows)]
+fn resolve_program(program: &str) -> std::path::PathBuf {
+    let path = Path::new(program);
+    if path.extension().is_some() || path.components().count() > 1 {
+        return path.to_path_buf();
+    }
+    for dir in std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()) {
+        for extension in ["exe", "cmd", "bat"] {
+            let candidate = dir.join(format!("{}.{}", program, extension));
+            if candidate.is_file() {
+                return candidate;
             }
         }
     }
+    path.to_path_buf()
+}
+
+#[cfg(not(windows))]
+fn resolve_program(program: &str) -> std::path::PathBuf {
+    program.into()
+}
+
+/// Runs `args[0]` with the rest of `args` as its arguments. Arguments are passed to the program
+/// as they are, never through a shell, so patterns and rules cannot run commands.
+pub async fn run_command(
+    args: &[String],
+    input_text: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<CommandResult, CommandError> {
+    Ok(run_command_limited(args, input_text, timeout, None).await?.result)
+}
+
+/// Like [`run_command`], for output that is not text (e.g. git blobs): returns stdout as bytes,
+/// failing unless the command exits successfully.
+pub async fn run_command_bytes(
+    args: &[String],
+    input_text: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>, CommandError> {
+    let (status, stdout, stderr, group) = execute(args, input_text, timeout, None).await?;
+    group.disarm();
+    match status {
+        Some(status) if status.success() => Ok(stdout),
+        status => {
+            let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+            Err(CommandError::Failed {
+                cmd: args.to_vec(),
+                code: status.and_then(|status| status.code()).unwrap_or(1),
+                stderr: if stderr.is_empty() { "(no error output)".to_string() } else { stderr },
+            })
+        }
+    }
+}
+
+/// Like [`run_command`], but with `max_lines`, kills the command's process group once it has
+/// printed that many lines of output, for streaming output of which only the start is needed.
+pub async fn run_command_limited(
+    args: &[String],
+    input_text: Option<&str>,
+    timeout: Option<Duration>,
+    max_lines: Option<usize>,
+) -> Result<LimitedResult, CommandError> {
+    run_command_counted(args, input_text, timeout, max_lines, &|_| true).await
+}
 
-    // Wait for output
-    let output = child.wait_with_output().await.map_err(CommandError::Io)?;
+/// Like [`run_command_limited`], but only the lines of output `counts` accepts count towards
+/// `max_lines`, e.g. matches that are filtered out afterwards do not.
+pub async fn run_command_counted(
+    args: &[String],
+    input_text: Option<&str>,
+    timeout: Option<Duration>,
+    max_lines: Option<usize>,
+    counts: &LineFilter<'_>,
+) -> Result<LimitedResult, CommandError> {
+    let limit = max_lines.map(|max_lines| (max_lines, counts));
+    let (status, stdout, stderr, group) = execute(args, input_text, timeout, limit).await?;
+    let stdout = String::from_utf8_lossy(&stdout).to_string();
+    let stderr = String::from_utf8_lossy(&stderr).to_string();
+    let Some(status) = status else {
+        // Dropping `group` kills the process tree that was stopped early
+        drop(group);
+        return Ok(LimitedResult { result: CommandResult { stdout, stderr }, stopped: true });
+    };
+    group.disarm();
+    let finished = |stdout, stderr| Ok(LimitedResult { result: CommandResult { stdout, stderr }, stopped: false });
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    let exit_code = output.status.code().unwrap_or(1); // Default to 1 if no code (signal)
+    let exit_code = status.code().unwrap_or(1); // Default to 1 if no code (signal)
 
-    if output.status.success() {
-        return Ok(CommandResult { stdout, stderr });
+    if status.success() {
+        return finished(stdout, stderr);
     }
 
     // Handle exit code 1 logic
     if exit_code == 1 {
         let stdout_stripped = stdout.trim();
-        // Valid "no matches" cases: empty JSON array or valid JSON with matches (starts with [)
-        // or empty string
-        if stdout_stripped.is_empty() || stdout_stripped == "[]" || stdout_stripped.starts_with('[') {
-             return Ok(CommandResult { stdout, stderr });
+        // Valid "no matches" cases: empty JSON array or valid JSON with matches (starts with [,
+        // or { for --json=stream) or empty string
+        if stdout_stripped.is_empty()
+            || stdout_stripped == "[]"
+            || stdout_stripped.starts_with('[')
+            || stdout_stripped.starts_with('{')
+        {
+             return finished(stdout, stderr);
         }
 
         // If --json flag is not present, empty stdout is also valid "no matches"
         // Check if --json is in args. Note: args here includes program name at index 0.
         if !args.contains(&"--json".to_string()) && stdout_stripped.is_empty() {
-            return Ok(CommandResult { stdout, stderr });
+            return finished(stdout, stderr);
         }
     }
 
@@ -101,20 +264,151 @@ pub async fn run_command(args: &[String], input_text: Option<&str>) -> Result<Co
     })
 }
 
+/// Runs a command as [`run_command_counted`] describes, returning its exit status (`None` when
+/// stopped after `limit`'s counted lines), raw output and the guard of its process group.
+async fn execute(
+    args: &[String],
+    input_text: Option<&str>,
+    timeout: Option<Duration>,
+    limit: Option<(usize, &LineFilter<'_>)>,
+) -> Result<(Option<ExitStatus>, Vec<u8>, Vec<u8>, ProcessGroupGuard), CommandError> {
+    let mut cmd_args = args.to_vec();
+    if cmd_args.is_empty() {
+        return Err(CommandError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty command args")));
+    }
+    let program = cmd_args.remove(0);
+    if is_shell_invocation(&program, &cmd_args) {
+        return Err(CommandError::ShellInvocation { program });
+    }
+    tracing::debug!(command = ?args, "running command");
+
+    let mut command = Command::new(resolve_program(&program));
+    command.args(&cmd_args);
+
+    command.stdin(Stdio::piped());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+
+    // Spawn the child process
+    let mut child = command.spawn().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            CommandError::NotFound { name: program.clone(), source: e }
+        } else {
+            CommandError::Io(e)
+        }
+    })?;
+    tracing::info!(program = %program, pid = ?child.id(), "subprocess started");
+    let _subprocess = crate::stats::global().subprocess_started();
+    let group = ProcessGroupGuard::new(child.id());
+
+    let run = async move {
+        // Write input to stdin if provided
+        if let Some(input) = input_text {
+            if let Some(mut stdin) = child.stdin.take() {
+                if let Err(e) = stdin.write_all(input.as_bytes()).await {
+                     // Ignore broken pipe errors as the process might have closed stdin
+                     if e.kind() != std::io::ErrorKind::BrokenPipe {
+                         return Err(CommandError::Io(e));
+                     }
+                }
+            }
+        }
+
+        match limit {
+            Some((max_lines, counts)) => read_lines(child, max_lines, counts).await.map_err(CommandError::Io),
+            None => {
+                let output = child.wait_with_output().await.map_err(CommandError::Io)?;
+                Ok((Some(output.status), output.stdout, output.stderr))
+            }
+        }
+    };
+
+    let (status, stdout, stderr) = match timeout {
+        Some(limit) => match tokio::time::timeout(limit, run).await {
+            Ok(output) => output?,
+            // Dropping `group` on return kills the timed-out process tree
+            Err(_) => return Err(CommandError::Timeout { cmd: args.to_vec(), secs: limit.as_secs() }),
+        },
+        None => run.await?,
+    };
+    Ok((status, stdout, stderr, group))
+}
+
+/// Reads the output of `child` until it exits, or until it has printed `max_lines` non-blank
+/// lines of stdout that `counts` accepts. The exit status is `None` when it was stopped there, in
+/// which case stderr is not read.
+async fn read_lines(
+    mut child: Child,
+    max_lines: usize,
+    counts: &LineFilter<'_>,
+) -> std::io::Result<(Option<ExitStatus>, Vec<u8>, Vec<u8>)> {
+    drop(child.stdin.take());
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        let _ = stderr.read_to_end(&mut buffer).await;
+        buffer
+    });
+
+    let mut output = Vec::new();
+    let mut lines = 0;
+    loop {
+        let start = output.len();
+        if stdout.read_until(b'\n', &mut output).await? == 0 {
+            break;
+        }
+        let line = &output[start..];
+        if line.iter().any(|b| !b.is_ascii_whitespace()) && counts(line) {
+            lines += 1;
+        }
+        if lines >= max_lines {
+            stderr.abort();
+            return Ok((None, output, Vec::new()));
+        }
+    }
+    let status = child.wait().await?;
+    Ok((Some(status), output, stderr.await.unwrap_or_default()))
+}
+
 pub async fn run_ast_grep(
     command: &str,
     args: &[String],
     input_text: Option<&str>,
-    config_path: Option<&std::path::PathBuf>,
+    config: &Config,
 ) -> Result<CommandResult> {
     let mut final_args = vec!["ast-grep".to_string(), command.to_string()];
 
-    if let Some(path) = config_path {
+    if let Some(path) = &config.config_path {
         final_args.push("--config".to_string());
         final_args.push(path.to_string_lossy().to_string());
     }
 
     final_args.extend_from_slice(args);
 
-    Ok(run_command(&final_args, input_text).await?)
+    Ok(run_command(&final_args, input_text, config.timeout).await?)
+}
+
+/// Like [`run_ast_grep`] with `--json`, but stops ast-grep once it has reported `max_matches`
+/// matches that `counts` accepts. The matches are printed as they are found (`--json=stream`, one
+/// per line), so stdout holds one JSON object per line instead of an array.
+pub async fn run_ast_grep_limited(
+    command: &str,
+    args: &[String],
+    config: &Config,
+    max_matches: usize,
+    counts: &(dyn Fn(&Value) -> bool + Sync),
+) -> Result<LimitedResult> {
+    let mut final_args = vec!["ast-grep".to_string(), command.to_string()];
+    if let Some(path) = &config.config_path {
+        final_args.push("--config".to_string());
+        final_args.push(path.to_string_lossy().to_string());
+    }
+    final_args.extend(args.iter().map(|arg| if arg == "--json" { "--json=stream".to_string() } else { arg.clone() }));
+
+    let counts = |line: &[u8]| serde_json::from_slice::<Value>(line).ok().is_none_or(|m| counts(&m));
+    Ok(run_command_counted(&final_args, None, config.timeout, Some(max_matches), &counts).await?)
 }
@@ -1,51 +1,331 @@
+use crate::auth::{AuthConfig, OAuthConfig, DEFAULT_WRITE_SCOPE};
+use crate::profiles::{load_filter_profiles, FilterProfile};
+use crate::session::SessionQuotas;
 use anyhow::Result;
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
 use std::env;
-use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_PORT: u16 = 3101;
+const DEFAULT_MAX_CONCURRENCY: usize = 4;
+const DEFAULT_SHARD_THRESHOLD: usize = 50_000;
 
 #[derive(Parser, Debug)]
 #[command(name = "ast-grep-mcp-server")]
 #[command(author, version, about, long_about = None)]
 #[command(after_help = "environment variables:
-  AST_GREP_CONFIG    Path to sgconfig.yaml file (overridden by --config flag)
+  AST_GREP_CONFIG          Path to sgconfig.yaml file (overridden by --config flag)
+  AST_GREP_MCP_TRANSPORT   Transport type, stdio, sse or ws (overridden by --transport flag)
+  AST_GREP_MCP_PORT        Port for the sse and ws transports (overridden by --port flag)
+  AST_GREP_MCP_LISTEN      Address the sse and ws transports listen on (overridden by --listen flag)
+  AST_GREP_MCP_ALLOWED_ROOTS
+                           Directories read_file_range may read, separated like PATH
+                           (overridden by --allowed-root flags)
+  AST_GREP_MCP_GRAMMAR_DIR Directory install_grammar builds parsers into
+                           (overridden by --grammar-dir flag)
+  AST_GREP_MCP_CHECKPOINT_DIR
+                           Directory background scans save checkpoints into
+                           (overridden by --checkpoint-dir flag)
+  AST_GREP_MCP_BACKUP_DIR  Directory apply_rewrite keeps backups of rewritten files in
+                           (overridden by --backup-dir flag)
+  AST_GREP_MCP_FILTER_PROFILES
+                           YAML file of named filter profiles
+                           (overridden by --filter-profiles flag)
 
 For more information, see: https://github.com/ast-grep/ast-grep-mcp")]
 pub struct Cli {
     /// Path to sgconfig.yaml file for customizing ast-grep behavior (language mappings, rule directories, etc.)
-    #[arg(long, value_name = "PATH")]
+    #[arg(long, value_name = "PATH", global = true)]
     pub config: Option<PathBuf>,
 
     /// Transport type for MCP server (default: stdio)
-    #[arg(long, default_value_t = TransportType::Stdio, value_enum)]
-    pub transport: TransportType,
+    #[arg(long, value_enum)]
+    pub transport: Option<TransportType>,
 
-    /// Port for SSE transport (default: 3101)
-    #[arg(long, default_value_t = 3101)]
-    pub port: u16,
+    /// Port for the sse and ws transports (default: 3101)
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Address the sse and ws transports listen on, e.g. 0.0.0.0 for all interfaces (default: 127.0.0.1)
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<IpAddr>,
+
+    /// PEM certificate chain (leaf first) to serve the sse and ws transports over HTTPS and wss with
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --tls-cert
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Kill ast-grep subprocesses that run longer than this many seconds (default: no limit)
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+
+    /// Maximum number of ast-grep scans a single tool call runs at once (default: 4)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_concurrency: Option<u64>,
+
+    /// Split scans of project folders with more files than this into shards (default: 50000, 0 disables)
+    #[arg(long, value_name = "FILES")]
+    pub shard_threshold: Option<usize>,
+
+    /// Directory that read_file_range may read from; repeat for several roots
+    /// (default: the roots provided by the MCP client)
+    #[arg(long = "allowed-root", value_name = "PATH")]
+    pub allowed_roots: Vec<PathBuf>,
+
+    /// Leave out tools that write files (export_matches, export_session, init_sgconfig, install_grammar,
+    /// license_header, register_custom_language, suppress_finding)
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Leave out deprecated tools and parameters, e.g. to check that prompts no longer use them
+    #[arg(long)]
+    pub hide_deprecated: bool,
+
+    /// Origin (e.g. https://app.example.com) whose browser pages may call the sse and ws transports;
+    /// repeat for several, `*` allows any (default: none)
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    pub cors_origins: Vec<String>,
+
+    /// Host name (e.g. mcp.example.com) clients may reach the sse and ws transports by, besides
+    /// loopback names and the --listen address; repeat for several
+    #[arg(long = "allowed-host", value_name = "NAME")]
+    pub allowed_hosts: Vec<String>,
+
+    /// Bearer token clients of the sse and ws transports must send; repeat for several
+    /// (default: $AST_GREP_MCP_AUTH_TOKEN, or no authentication)
+    #[arg(long = "auth-token", value_name = "TOKEN")]
+    pub auth_tokens: Vec<String>,
+
+    /// Accept JWT access tokens from this OAuth 2.0 / OpenID Connect issuer on the sse and ws transports
+    #[arg(long, value_name = "URL")]
+    pub oauth_issuer: Option<String>,
+
+    /// Audience (aud claim) JWT access tokens must be issued for (default: any)
+    #[arg(long, value_name = "AUDIENCE", requires = "oauth_issuer")]
+    pub oauth_audience: Option<String>,
+
+    /// Where to get the issuer's signing keys (default: the jwks_uri of its discovery document)
+    #[arg(long, value_name = "URL", requires = "oauth_issuer")]
+    pub oauth_jwks_uri: Option<String>,
+
+    /// Scope JWT access tokens need for tools that write files (default: ast-grep:write)
+    #[arg(long, value_name = "SCOPE", requires = "oauth_issuer")]
+    pub oauth_write_scope: Option<String>,
+
+    /// Directory install_grammar clones and builds tree-sitter grammars into
+    /// (default: $XDG_DATA_HOME/ast-grep-mcp/grammars)
+    #[arg(long, value_name = "PATH")]
+    pub grammar_dir: Option<PathBuf>,
+
+    /// Directory background scans (submit_scan) save checkpoints into, so they can be resumed
+    /// after a restart (default: $XDG_DATA_HOME/ast-grep-mcp/checkpoints)
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint_dir: Option<PathBuf>,
+
+    /// Directory apply_rewrite keeps the original contents of rewritten files in
+    /// (default: $XDG_DATA_HOME/ast-grep-mcp/backups)
+    #[arg(long, value_name = "PATH")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// YAML file of named filter profiles (globs, excludes, languages) that find tools select
+    /// with filter_profile
+    #[arg(long, value_name = "PATH")]
+    pub filter_profiles: Option<PathBuf>,
+
+    /// Rescan the folders of watch_rule watches every this many seconds, in addition to
+    /// re-evaluating changed files (default: never)
+    #[arg(long, value_name = "SECS")]
+    pub rescan_interval: Option<u64>,
+
+    /// Most scans one client may run at once, background scans included (default: no limit)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_client_scans: Option<u64>,
+
+    /// Most bytes of matches remembered for get_match_details per client (default: no limit
+    /// beyond 10000 matches)
+    #[arg(long, value_name = "BYTES")]
+    pub max_client_cache_bytes: Option<usize>,
+
+    /// Most watch_rule watches one client may have (default: 32)
+    #[arg(long, value_name = "N")]
+    pub max_client_watches: Option<usize>,
+
+    /// Log level for messages written to stderr (overrides RUST_LOG)
+    #[arg(long, value_enum, global = true)]
+    pub log_level: Option<LogLevel>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run a single tool and print its result to stdout, without an MCP client
+    Call(CallArgs),
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(after_help = "Tool arguments are passed as --name value pairs; dashes in names map to underscores.
+
+Example:
+  ast-grep-mcp-server call find_code --pattern 'def $NAME' --project-folder /abs/path --max-results 5")]
+pub struct CallArgs {
+    /// Name of the tool to call (e.g. find_code, find_code_by_rule, dump_syntax_tree)
+    pub tool: String,
+
+    /// Tool arguments as --name value pairs
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum TransportType {
     Stdio,
     Sse,
+    /// MCP over WebSocket, for browser-based clients.
+    Ws,
+}
+
+/// The certificate chain and private key the HTTP and WebSocket transports serve TLS with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub config_path: Option<PathBuf>,
     pub transport: TransportType,
-    #[allow(dead_code)]
     pub port: u16,
+    /// Address the HTTP and WebSocket transports listen on.
+    pub listen: IpAddr,
+    /// Serve TLS with these files; `None` for plain HTTP.
+    pub tls: Option<TlsFiles>,
+    pub timeout: Option<Duration>,
+    /// Directories file-reading tools are confined to; empty means "ask the client".
+    pub allowed_roots: Vec<PathBuf>,
+    /// Whether tools that write files are left out.
+    pub read_only: bool,
+    /// Whether deprecated tools and parameters are left out.
+    pub hide_deprecated: bool,
+    pub max_concurrency: usize,
+    /// File count above which a project folder is scanned in shards; `None` disables sharding.
+    pub shard_threshold: Option<usize>,
+    /// Where install_grammar puts grammars; `None` when no data directory could be determined.
+    pub grammar_dir: Option<PathBuf>,
+    /// Where background scans save checkpoints; `None` when no data directory could be determined.
+    pub checkpoint_dir: Option<PathBuf>,
+    /// Where apply_rewrite keeps backups; `None` when no data directory could be determined.
+    pub backup_dir: Option<PathBuf>,
+    /// How often watched folders are rescanned as a whole; `None` disables scheduled rescans.
+    pub rescan_interval: Option<Duration>,
+    /// Filter profiles by name, selected by find tools with `filter_profile`.
+    pub filter_profiles: BTreeMap<String, FilterProfile>,
+    /// What one client may use at once.
+    pub quotas: SessionQuotas,
+    /// Origins allowed to make cross-origin requests to the HTTP and WebSocket transports; `*` allows any.
+    pub cors_origins: Vec<String>,
+    /// Host names, besides loopback names and the listen address, requests to the HTTP and
+    /// WebSocket transports may be addressed to.
+    pub allowed_hosts: Vec<String>,
+    /// How clients of the HTTP and WebSocket transports authenticate.
+    pub auth: AuthConfig,
+    /// Extra tools registered by the embedding application; never set from the command line.
+    #[cfg(feature = "plugins")]
+    pub plugins: crate::plugin::Plugins,
+}
+
+/// Checks that `origin` is `*` or a bare `scheme://host[:port]` origin, as browsers send it.
+fn validate_cors_origin(origin: &str) -> Result<()> {
+    if origin == "*" {
+        return Ok(());
+    }
+    let invalid = || anyhow::anyhow!("Invalid --cors-origin '{}'. Must be like https://host[:port], or *", origin);
+    let url = url::Url::parse(origin).map_err(|_| invalid())?;
+    let bare = matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some()
+        && url.path() == "/"
+        && !origin.ends_with('/')
+        && url.query().is_none()
+        && url.username().is_empty();
+    if !bare {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Checks that `url` is an absolute http(s) URL, as OAuth issuers and JWKS locations are.
+fn validate_oauth_url(flag: &str, url: &str) -> Result<()> {
+    match url::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host_str().is_some() => Ok(()),
+        _ => anyhow::bail!("Invalid {} '{}'. Must be an http(s) URL", flag, url),
+    }
+}
+
+/// This server's data directory: `$XDG_DATA_HOME` or `~/.local/share` (`%LOCALAPPDATA%` on
+/// Windows), under `ast-grep-mcp`.
+pub fn data_dir(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let var = |name: &str| env(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let data_dir = if cfg!(windows) {
+        var("LOCALAPPDATA")
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local").join("share")))
+    }?;
+    Some(data_dir.join("ast-grep-mcp"))
 }
 
 impl Config {
     pub fn from_cli() -> Result<Self> {
-        let cli = Cli::parse();
-        let mut config_path = cli.config;
+        Self::from_args(&Cli::parse())
+    }
+
+    pub fn from_args(cli: &Cli) -> Result<Self> {
+        Self::from_args_with_env(cli, |name| env::var(name).ok())
+    }
+
+    /// Builds the config from parsed CLI args, using `env` to look up environment variables.
+    /// CLI flags always take precedence over environment variables.
+    pub fn from_args_with_env(cli: &Cli, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config_path = cli.config.clone();
 
         // If config path not provided via CLI, check env var
         if config_path.is_none() {
-            if let Ok(env_config) = env::var("AST_GREP_CONFIG") {
+            if let Some(env_config) = env("AST_GREP_CONFIG") {
                 if !env_config.is_empty() {
                     let path = PathBuf::from(env_config);
                     if !path.exists() {
@@ -60,10 +340,113 @@ impl Config {
             }
         }
 
+        let transport = match (cli.transport, env("AST_GREP_MCP_TRANSPORT")) {
+            (Some(transport), _) => transport,
+            (None, Some(value)) if !value.is_empty() => TransportType::from_str(&value, true).map_err(|_| {
+                anyhow::anyhow!("Invalid AST_GREP_MCP_TRANSPORT '{}'. Must be 'stdio', 'sse' or 'ws'", value)
+            })?,
+            _ => TransportType::Stdio,
+        };
+
+        let port = match (cli.port, env("AST_GREP_MCP_PORT")) {
+            (Some(port), _) => port,
+            (None, Some(value)) if !value.is_empty() => value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid AST_GREP_MCP_PORT '{}'. Must be a port number", value))?,
+            _ => DEFAULT_PORT,
+        };
+
+        let listen = match (cli.listen, env("AST_GREP_MCP_LISTEN")) {
+            (Some(listen), _) => listen,
+            (None, Some(value)) if !value.is_empty() => value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid AST_GREP_MCP_LISTEN '{}'. Must be an IP address", value))?,
+            _ => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+
+        let allowed_roots = match env("AST_GREP_MCP_ALLOWED_ROOTS") {
+            Some(value) if cli.allowed_roots.is_empty() => {
+                env::split_paths(&value).filter(|p| !p.as_os_str().is_empty()).collect()
+            }
+            _ => cli.allowed_roots.clone(),
+        };
+
+        let grammar_dir = match (&cli.grammar_dir, env("AST_GREP_MCP_GRAMMAR_DIR")) {
+            (Some(dir), _) => Some(dir.clone()),
+            (None, Some(value)) if !value.is_empty() => Some(PathBuf::from(value)),
+            _ => crate::grammar::default_grammar_dir(&env),
+        };
+        let checkpoint_dir = match (&cli.checkpoint_dir, env("AST_GREP_MCP_CHECKPOINT_DIR")) {
+            (Some(dir), _) => Some(dir.clone()),
+            (None, Some(value)) if !value.is_empty() => Some(PathBuf::from(value)),
+            _ => crate::checkpoint::default_checkpoint_dir(&env),
+        };
+        let backup_dir = match (&cli.backup_dir, env("AST_GREP_MCP_BACKUP_DIR")) {
+            (Some(dir), _) => Some(dir.clone()),
+            (None, Some(value)) if !value.is_empty() => Some(PathBuf::from(value)),
+            _ => crate::backup::default_backup_dir(&env),
+        };
+
+        let filter_profiles = match (&cli.filter_profiles, env("AST_GREP_MCP_FILTER_PROFILES")) {
+            (Some(path), _) => load_filter_profiles(path)?,
+            (None, Some(value)) if !value.is_empty() => load_filter_profiles(Path::new(&value))?,
+            _ => BTreeMap::new(),
+        };
+
+        for origin in &cli.cors_origins {
+            validate_cors_origin(origin)?;
+        }
+
+        let mut tokens = cli.auth_tokens.clone();
+        if tokens.is_empty() {
+            tokens.extend(env("AST_GREP_MCP_AUTH_TOKEN").filter(|token| !token.is_empty()));
+        }
+        let oauth = match &cli.oauth_issuer {
+            Some(issuer) => {
+                validate_oauth_url("--oauth-issuer", issuer)?;
+                if let Some(jwks_uri) = &cli.oauth_jwks_uri {
+                    validate_oauth_url("--oauth-jwks-uri", jwks_uri)?;
+                }
+                Some(OAuthConfig {
+                    issuer: issuer.clone(),
+                    audience: cli.oauth_audience.clone(),
+                    jwks_uri: cli.oauth_jwks_uri.clone(),
+                    write_scope: cli.oauth_write_scope.clone().unwrap_or_else(|| DEFAULT_WRITE_SCOPE.to_string()),
+                })
+            }
+            None => None,
+        };
+
         Ok(Self {
             config_path,
-            transport: cli.transport,
-            port: cli.port,
+            transport,
+            port,
+            listen,
+            tls: match (&cli.tls_cert, &cli.tls_key) {
+                (Some(cert), Some(key)) => Some(TlsFiles { cert: cert.clone(), key: key.clone() }),
+                _ => None,
+            },
+            timeout: cli.timeout.filter(|secs| *secs > 0).map(Duration::from_secs),
+            allowed_roots,
+            read_only: cli.read_only,
+            hide_deprecated: cli.hide_deprecated,
+            max_concurrency: cli.max_concurrency.map_or(DEFAULT_MAX_CONCURRENCY, |n| n as usize),
+            shard_threshold: Some(cli.shard_threshold.unwrap_or(DEFAULT_SHARD_THRESHOLD)).filter(|n| *n > 0),
+            grammar_dir,
+            checkpoint_dir,
+            backup_dir,
+            rescan_interval: cli.rescan_interval.filter(|secs| *secs > 0).map(Duration::from_secs),
+            filter_profiles,
+            quotas: SessionQuotas {
+                max_scans: cli.max_client_scans.map(|n| n as usize),
+                max_cache_bytes: cli.max_client_cache_bytes,
+                max_watches: cli.max_client_watches.unwrap_or(crate::watch::MAX_WATCHES),
+            },
+            cors_origins: cli.cors_origins.clone(),
+            allowed_hosts: cli.allowed_hosts.clone(),
+            auth: AuthConfig { tokens, oauth },
+            #[cfg(feature = "plugins")]
+            plugins: Default::default(),
         })
     }
 }
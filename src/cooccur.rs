@@ -0,0 +1,182 @@
+use crate::metrics::metric_kinds;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+pub const A_RULE_ID: &str = "cooccur-a";
+pub const B_RULE_ID: &str = "cooccur-b";
+pub const FUNCTION_RULE_ID: &str = "cooccur-function";
+
+/// Values of `find_cooccurrence`'s `mode` and `scope`.
+pub const COOCCURRENCE_MODES: &[&str] = &["a_without_b", "both"];
+pub const COOCCURRENCE_SCOPES: &[&str] = &["function", "file"];
+
+/// Which scopes a co-occurrence search reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CooccurrenceMode {
+    /// Scopes where pattern A matches and pattern B does not.
+    AWithoutB,
+    /// Scopes where both patterns match.
+    Both,
+}
+
+impl CooccurrenceMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "" | "a_without_b" => Some(Self::AWithoutB),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+}
+
+/// Inline rules matching pattern A, pattern B and, for function scope, the function kinds of
+/// `language` (those counted by code_metrics); `None` when function scope is asked for a language
+/// without known function kinds.
+pub fn cooccurrence_rules(language: &str, pattern_a: &str, pattern_b: &str, functions: bool) -> Option<String> {
+    let document = |id: &str, rule: Value| {
+        let rule = json!({ "id": id, "language": language, "rule": rule });
+        serde_yaml::to_string(&rule).expect("a rule of strings serializes")
+    };
+    let mut documents = vec![
+        document(A_RULE_ID, json!({ "pattern": pattern_a })),
+        document(B_RULE_ID, json!({ "pattern": pattern_b })),
+    ];
+    if functions {
+        let kinds = metric_kinds(language)?.functions;
+        let any: Vec<Value> = kinds.iter().map(|kind| json!({ "kind": kind })).collect();
+        documents.push(document(FUNCTION_RULE_ID, json!({ "any": any })));
+    }
+    Some(documents.join("---\n"))
+}
+
+/// The function a co-occurrence was found in.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScopeFunction {
+    /// 1-based first and last line.
+    pub lines: String,
+    /// The function's first line of text, usually its signature.
+    pub signature: String,
+}
+
+/// A file or function where the patterns co-occur as asked.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cooccurrence {
+    pub file: String,
+    /// The outermost function around the matches, for function scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<ScopeFunction>,
+    /// 1-based lines where pattern A matches.
+    pub a_lines: Vec<u64>,
+    /// 1-based lines where pattern B matches; empty in `a_without_b` mode.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub b_lines: Vec<u64>,
+}
+
+/// Co-occurrences found in the matches of [`cooccurrence_rules`], by file and position.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CooccurrenceReport {
+    pub results: Vec<Cooccurrence>,
+    /// Matches of pattern A outside any function, which function scope cannot place.
+    pub a_outside_functions: usize,
+}
+
+/// The matches of one file or function.
+#[derive(Default)]
+struct Scope<'a> {
+    function: Option<&'a Value>,
+    a_lines: Vec<u64>,
+    b_lines: Vec<u64>,
+}
+
+fn rule_id(m: &Value) -> &str {
+    m.get("ruleId").and_then(Value::as_str).unwrap_or_default()
+}
+
+fn byte_range(m: &Value) -> Option<(u64, u64)> {
+    let start = m.pointer("/range/byteOffset/start")?.as_u64()?;
+    let end = m.pointer("/range/byteOffset/end")?.as_u64()?;
+    Some((start, end))
+}
+
+fn line_of(m: &Value) -> u64 {
+    m.pointer("/range/start/line").and_then(Value::as_u64).unwrap_or(0) + 1
+}
+
+/// Groups the matches of pattern A and B by scope and keeps the scopes `mode` asks for. With
+/// function scope, each match belongs to the outermost function containing it, so that code in
+/// callbacks and closures counts as part of the function that defines them (a `db.save` in a
+/// callback inside a `transaction` block is in a transaction).
+pub fn find_cooccurrences(matches: &[Value], mode: CooccurrenceMode, function_scope: bool) -> CooccurrenceReport {
+    let mut files: BTreeMap<&str, Vec<&Value>> = BTreeMap::new();
+    for m in matches {
+        if let Some(file) = m.get("file").and_then(Value::as_str) {
+            files.entry(file).or_default().push(m);
+        }
+    }
+
+    let mut report = CooccurrenceReport::default();
+    for (file, matches) in files {
+        let functions: Vec<(&Value, (u64, u64))> = matches
+            .iter()
+            .filter(|m| rule_id(m) == FUNCTION_RULE_ID)
+            .filter_map(|m| Some((*m, byte_range(m)?)))
+            .collect();
+        // Scopes by their start and end offset; the whole file is (0, u64::MAX)
+        let mut scopes: BTreeMap<(u64, u64), Scope> = BTreeMap::new();
+        for m in &matches {
+            let is_a = rule_id(m) == A_RULE_ID;
+            if !is_a && rule_id(m) != B_RULE_ID {
+                continue;
+            }
+            let scope = if function_scope {
+                let Some((start, end)) = byte_range(m) else {
+                    continue;
+                };
+                let outermost = functions
+                    .iter()
+                    .filter(|(_, (f_start, f_end))| *f_start <= start && end <= *f_end)
+                    .max_by_key(|(_, (f_start, f_end))| f_end - f_start);
+                match outermost {
+                    Some((function, range)) => (*range, Some(*function)),
+                    None => {
+                        report.a_outside_functions += usize::from(is_a);
+                        continue;
+                    }
+                }
+            } else {
+                ((0, u64::MAX), None)
+            };
+            let entry = scopes.entry(scope.0).or_insert_with(|| Scope { function: scope.1, ..Default::default() });
+            if is_a {
+                entry.a_lines.push(line_of(m));
+            } else {
+                entry.b_lines.push(line_of(m));
+            }
+        }
+
+        for Scope { function, mut a_lines, mut b_lines } in scopes.into_values() {
+            let wanted = match mode {
+                CooccurrenceMode::AWithoutB => !a_lines.is_empty() && b_lines.is_empty(),
+                CooccurrenceMode::Both => !a_lines.is_empty() && !b_lines.is_empty(),
+            };
+            if !wanted {
+                continue;
+            }
+            a_lines.sort_unstable();
+            a_lines.dedup();
+            b_lines.sort_unstable();
+            b_lines.dedup();
+            let function = function.map(|function| {
+                let end = function.pointer("/range/end/line").and_then(Value::as_u64).unwrap_or(0) + 1;
+                let text = function.get("text").and_then(Value::as_str).unwrap_or_default();
+                ScopeFunction {
+                    lines: format!("{}-{}", line_of(function), end),
+                    signature: text.lines().next().unwrap_or_default().trim().to_string(),
+                }
+            });
+            report.results.push(Cooccurrence { file: file.to_string(), function, a_lines, b_lines });
+        }
+    }
+    report
+}
@@ -0,0 +1,129 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// `source` of every diagnostic, shown by editors next to the message.
+pub const DIAGNOSTIC_SOURCE: &str = "ast-grep";
+
+/// LSP `DiagnosticSeverity` of an ast-grep rule severity. Matches without one (pattern
+/// searches) are informational.
+pub fn lsp_severity(severity: Option<&str>) -> u8 {
+    match severity {
+        Some("error") => 1,
+        Some("warning") => 2,
+        Some("hint") => 4,
+        _ => 3,
+    }
+}
+
+/// Converts a character column on `line` to UTF-16 code units, the LSP default position
+/// encoding. Without the line text the column is returned as is.
+fn utf16_column(line: Option<&str>, column: u64) -> u64 {
+    match line {
+        Some(line) => line.chars().take(column as usize).map(|c| c.len_utf16() as u64).sum(),
+        None => column,
+    }
+}
+
+/// LSP range of an ast-grep `range`. `lines` are the full source lines starting at line
+/// `first_line` (ast-grep includes those of the match), used to convert columns to UTF-16.
+fn lsp_range(range: &Value, lines: Option<&str>, first_line: u64) -> Option<Value> {
+    let start_line = range.pointer("/start/line")?.as_u64()?;
+    let start_column = range.pointer("/start/column")?.as_u64()?;
+    let end_line = range.pointer("/end/line").and_then(Value::as_u64).unwrap_or(start_line);
+    let end_column = range.pointer("/end/column").and_then(Value::as_u64).unwrap_or(start_column);
+    let line_text = |line: u64| {
+        let index = line.checked_sub(first_line)?;
+        lines.and_then(|lines| lines.lines().nth(index as usize))
+    };
+    Some(json!({
+        "start": { "line": start_line, "character": utf16_column(line_text(start_line), start_column) },
+        "end": { "line": end_line, "character": utf16_column(line_text(end_line), end_column) },
+    }))
+}
+
+fn file_uri(file: &str, project_folder: &Path) -> Option<String> {
+    url::Url::from_file_path(project_folder.join(file)).ok().map(|uri| uri.to_string())
+}
+
+/// The LSP `Diagnostic` of one match. Secondary labels of the rule become its
+/// `relatedInformation`; the fix, note and fingerprint, when present, go in `data`.
+pub fn match_to_diagnostic(m: &Value, uri: &str) -> Option<Value> {
+    let lines = m.get("lines").and_then(Value::as_str);
+    let first_line = m.pointer("/range/start/line").and_then(Value::as_u64).unwrap_or(0);
+    let range = lsp_range(m.get("range")?, lines, first_line)?;
+    let rule_id = m.get("ruleId").and_then(Value::as_str);
+    let message = m
+        .get("message")
+        .and_then(Value::as_str)
+        .filter(|message| !message.is_empty())
+        .map(str::to_string)
+        .or_else(|| rule_id.map(|id| format!("Matched rule {}", id)))
+        .unwrap_or_else(|| "Matched pattern".to_string());
+
+    let mut diagnostic = Map::new();
+    diagnostic.insert("range".to_string(), range);
+    diagnostic.insert("severity".to_string(), lsp_severity(m.get("severity").and_then(Value::as_str)).into());
+    if let Some(rule_id) = rule_id {
+        diagnostic.insert("code".to_string(), rule_id.into());
+    }
+    diagnostic.insert("source".to_string(), DIAGNOSTIC_SOURCE.into());
+    diagnostic.insert("message".to_string(), message.into());
+
+    let related: Vec<Value> = m
+        .get("labels")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|label| label.get("style").and_then(Value::as_str) != Some("primary"))
+        .filter_map(|label| {
+            let range = lsp_range(label.get("range")?, lines, first_line)?;
+            let message = label
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .or_else(|| label.get("text").and_then(Value::as_str).map(|text| format!("Related: {}", text)))?;
+            Some(json!({ "location": { "uri": uri, "range": range }, "message": message }))
+        })
+        .collect();
+    if !related.is_empty() {
+        diagnostic.insert("relatedInformation".to_string(), Value::Array(related));
+    }
+
+    let mut data = Map::new();
+    for key in ["replacement", "note", "fingerprint"] {
+        if let Some(value) = m.get(key).filter(|v| !v.is_null()) {
+            data.insert(key.to_string(), value.clone());
+        }
+    }
+    if !data.is_empty() {
+        diagnostic.insert("data".to_string(), Value::Object(data));
+    }
+    Some(Value::Object(diagnostic))
+}
+
+/// Groups matches by file into LSP `PublishDiagnosticsParams` (`{ uri, diagnostics }`), so an
+/// editor-embedded client can hand each entry to its diagnostics UI unchanged. Files are in path
+/// order and keep the order of their matches; matches without a file or range are left out.
+pub fn matches_to_diagnostics(matches: &[Value], project_folder: &Path) -> Vec<Value> {
+    let mut files: BTreeMap<&str, (String, Vec<Value>)> = BTreeMap::new();
+    for m in matches {
+        let Some(file) = m.get("file").and_then(Value::as_str) else {
+            continue;
+        };
+        if !files.contains_key(file) {
+            let Some(uri) = file_uri(file, project_folder) else {
+                continue;
+            };
+            files.insert(file, (uri, Vec::new()));
+        }
+        let (uri, diagnostics) = files.get_mut(file).expect("inserted above");
+        if let Some(diagnostic) = match_to_diagnostic(m, uri) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    files
+        .into_values()
+        .map(|(uri, diagnostics)| json!({ "uri": uri, "diagnostics": diagnostics }))
+        .collect()
+}
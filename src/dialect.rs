@@ -0,0 +1,114 @@
+use serde::Deserialize;
+
+/// Values of the find tools' `dialect`.
+pub const DIALECTS: &[&str] = &["auto", "plain", "jsx"];
+
+/// Which TypeScript grammar a search uses. ast-grep parses `.ts`, `.mts` and `.cts` files with
+/// the `typescript` grammar and `.tsx` files with the `tsx` one, and a search for either language
+/// only visits that language's files, so `typescript` silently skips `.tsx` files. JavaScript
+/// has a single grammar that parses JSX, in `.js` and `.jsx` files alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Each file with the grammar of its extension: `typescript` and `tsx` both search all
+    /// TypeScript files.
+    Auto,
+    /// Only the files of the `typescript` grammar.
+    Plain,
+    /// Only the files of the `tsx` grammar.
+    Jsx,
+}
+
+impl Dialect {
+    pub fn parse(dialect: &str) -> Option<Self> {
+        match dialect {
+            "" | "auto" => Some(Self::Auto),
+            "plain" => Some(Self::Plain),
+            "jsx" => Some(Self::Jsx),
+            _ => None,
+        }
+    }
+
+    /// The TypeScript grammars to search with, the one asked for first.
+    fn languages(&self, language: &str) -> Vec<&'static str> {
+        match (self, language) {
+            (Self::Auto, "tsx") => vec!["tsx", "typescript"],
+            (Self::Auto, _) => vec!["typescript", "tsx"],
+            (Self::Plain, _) => vec!["typescript"],
+            (Self::Jsx, _) => vec!["tsx"],
+        }
+    }
+}
+
+/// `typescript` or `tsx` for the names and aliases of the TypeScript grammars, `None` otherwise.
+fn typescript_grammar(language: &str) -> Option<&'static str> {
+    match language.to_lowercase().as_str() {
+        "typescript" | "ts" => Some("typescript"),
+        "tsx" => Some("tsx"),
+        _ => None,
+    }
+}
+
+/// The query arguments (`--lang <language>` with a pattern, or `--inline-rules <yaml>`) to scan
+/// with so that every TypeScript file is parsed with the grammar `dialect` calls for, one scan per
+/// grammar; `query_args` alone when the query is not for TypeScript. Rule documents for other
+/// languages are only kept in the first scan, so their matches are not reported twice.
+///
+/// Rules that already have documents for both grammars (as `find_code` makes when it detects both
+/// kinds of file) search both without another scan; with `plain` or `jsx` the documents for the
+/// other grammar are left out rather than converted.
+pub fn dialect_queries(query_args: &[String], dialect: Dialect) -> Result<Vec<Vec<String>>, String> {
+    let value_of = |flag: &str| query_args.iter().position(|arg| arg == flag).map(|i| i + 1);
+    if let Some(i) = value_of("--lang").filter(|&i| i < query_args.len()) {
+        let Some(language) = typescript_grammar(&query_args[i]) else {
+            return Ok(vec![query_args.to_vec()]);
+        };
+        let queries = dialect.languages(language).into_iter().map(|language| {
+            let mut args = query_args.to_vec();
+            args[i] = language.to_string();
+            args
+        });
+        return Ok(queries.collect());
+    }
+    let Some(i) = value_of("--inline-rules").filter(|&i| i < query_args.len()) else {
+        return Ok(vec![query_args.to_vec()]);
+    };
+
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&query_args[i]) {
+        documents.push(serde_yaml::Value::deserialize(document).map_err(|e| format!("Invalid rule YAML: {}", e))?);
+    }
+    let grammar = |document: &serde_yaml::Value| {
+        document.get("language").and_then(serde_yaml::Value::as_str).and_then(typescript_grammar)
+    };
+    let grammars: Vec<&str> = documents.iter().filter_map(grammar).collect();
+    let Some(first) = grammars.first() else {
+        return Ok(vec![query_args.to_vec()]);
+    };
+    let both = grammars.iter().any(|other| other != first);
+    if both && dialect == Dialect::Auto {
+        return Ok(vec![query_args.to_vec()]);
+    }
+
+    let mut queries = Vec::new();
+    for (scan, language) in dialect.languages(first).into_iter().enumerate() {
+        let mut yaml = Vec::new();
+        for document in &documents {
+            let mut document = document.clone();
+            match (grammar(&document), document.as_mapping_mut()) {
+                (Some(other), _) if both && other != language => continue,
+                (Some(_), Some(root)) => {
+                    root.insert("language".into(), language.into());
+                }
+                _ if scan > 0 => continue,
+                _ => {}
+            }
+            yaml.push(serde_yaml::to_string(&document).map_err(|e| e.to_string())?);
+        }
+        if !yaml.is_empty() {
+            let mut args = query_args.to_vec();
+            args[i] = yaml.join("---\n");
+            queries.push(args);
+        }
+    }
+    Ok(queries)
+}
@@ -0,0 +1,144 @@
+use std::fmt::Write as _;
+
+/// Lines of context around each change, as `diff -u` uses.
+pub const CONTEXT_LINES: usize = 3;
+
+/// A replacement of the bytes `start..end` of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
+/// `text` with `edits` (sorted, not overlapping) applied.
+pub fn apply_edits(text: &str, edits: &[Edit]) -> String {
+    let mut edited = String::with_capacity(text.len());
+    let mut copied = 0;
+    for edit in edits {
+        edited.push_str(&text[copied..edit.start]);
+        edited.push_str(&edit.replacement);
+        copied = edit.end;
+    }
+    edited.push_str(&text[copied..]);
+    edited
+}
+
+/// A run of whole lines of the old file, `first..last` (0-based, exclusive), and the lines that
+/// replace them.
+struct Block {
+    first: usize,
+    last: usize,
+    lines: Vec<String>,
+}
+
+/// The unified diff (`diff -u` with `a/` and `b/` prefixes) of applying `edits` to `old`, the
+/// contents of `path`. Edits must be sorted and must not overlap; they are spread to whole lines,
+/// so no line-matching algorithm is needed. Empty when the edits change nothing.
+pub fn unified_diff(path: &str, old: &str, edits: &[Edit], context: usize) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let mut line_starts = Vec::with_capacity(old_lines.len() + 1);
+    let mut offset = 0;
+    for line in &old_lines {
+        line_starts.push(offset);
+        offset += line.len();
+    }
+    line_starts.push(offset);
+    // Index of the line holding byte `at`; the end of the file belongs to the last line
+    let line_of = |at: usize| line_starts.partition_point(|&start| start <= at).saturating_sub(1);
+
+    let mut blocks: Vec<(usize, usize, Vec<&Edit>)> = Vec::new();
+    for edit in edits {
+        let first = line_of(edit.start).min(old_lines.len().saturating_sub(1));
+        let last_byte = edit.end.saturating_sub(1).max(edit.start);
+        let last = (line_of(last_byte) + 1).max(first + 1).min(old_lines.len());
+        match blocks.last_mut() {
+            Some(block) if first < block.1 => {
+                block.1 = block.1.max(last);
+                block.2.push(edit);
+            }
+            _ => blocks.push((first, last, vec![edit])),
+        }
+    }
+    let blocks: Vec<Block> = blocks
+        .into_iter()
+        .filter_map(|(first, last, edits)| {
+            let (from, to) = (line_starts[first], line_starts[last]);
+            let shifted: Vec<Edit> = edits
+                .into_iter()
+                .map(|edit| Edit { start: edit.start - from, end: edit.end - from, ..edit.clone() })
+                .collect();
+            let text = apply_edits(&old[from..to], &shifted);
+            let lines = text.split_inclusive('\n').map(String::from).collect();
+            (text != old[from..to]).then_some(Block { first, last, lines })
+        })
+        .collect();
+    if blocks.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut delta: isize = 0;
+    let mut index = 0;
+    while index < blocks.len() {
+        // Blocks whose contexts touch go in the same hunk
+        let mut end = index + 1;
+        while end < blocks.len() && blocks[end].first - blocks[end - 1].last <= 2 * context {
+            end += 1;
+        }
+        let hunk = &blocks[index..end];
+        let old_start = hunk[0].first.saturating_sub(context);
+        let old_end = (hunk[hunk.len() - 1].last + context).min(old_lines.len());
+        let added: isize = hunk.iter().map(|b| b.lines.len() as isize - (b.last - b.first) as isize).sum();
+        let new_start = (old_start as isize + delta) as usize;
+        let new_len = ((old_end - old_start) as isize + added) as usize;
+        let _ = writeln!(
+            diff,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_end - old_start),
+            hunk_range(new_start, new_len)
+        );
+
+        let mut line = old_start;
+        for block in hunk {
+            for context_line in &old_lines[line..block.first] {
+                push_line(&mut diff, ' ', context_line);
+            }
+            for removed in &old_lines[block.first..block.last] {
+                push_line(&mut diff, '-', removed);
+            }
+            for added in &block.lines {
+                push_line(&mut diff, '+', added);
+            }
+            line = block.last;
+        }
+        for context_line in &old_lines[line..old_end] {
+            push_line(&mut diff, ' ', context_line);
+        }
+        delta += added;
+        index = end;
+    }
+    diff
+}
+
+/// Hunks of a diff made by [`unified_diff`].
+pub fn count_hunks(diff: &str) -> usize {
+    diff.lines().filter(|line| line.starts_with("@@ ")).count()
+}
+
+/// `start,length` of a hunk header, 1-based; an empty range names the line before it.
+fn hunk_range(start: usize, length: usize) -> String {
+    match length {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, length),
+    }
+}
+
+fn push_line(diff: &mut String, marker: char, line: &str) {
+    diff.push(marker);
+    diff.push_str(line);
+    if !line.ends_with('\n') {
+        diff.push_str("\n\\ No newline at end of file\n");
+    }
+}
@@ -0,0 +1,41 @@
+/// Values of `dump_syntax_tree`'s `format`.
+pub const DUMP_FORMATS: &[&str] = &["pattern", "ast", "cst"];
+
+/// What `dump_syntax_tree` prints, as ast-grep's `--debug-query` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// The node a pattern matches with, its metavariables shown as such.
+    Pattern,
+    /// Named nodes only.
+    Ast,
+    /// All nodes, punctuation and keywords included.
+    Cst,
+}
+
+impl DumpFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "pattern" => Some(Self::Pattern),
+            "ast" => Some(Self::Ast),
+            "cst" => Some(Self::Cst),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pattern => "pattern",
+            Self::Ast => "ast",
+            Self::Cst => "cst",
+        }
+    }
+
+    /// The line ast-grep starts the dump with.
+    pub fn header(&self) -> &'static str {
+        match self {
+            Self::Pattern => "Debug Pattern:",
+            Self::Ast => "Debug AST:",
+            Self::Cst => "Debug CST:",
+        }
+    }
+}
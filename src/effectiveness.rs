@@ -0,0 +1,113 @@
+use crate::shard::list_files;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// A rule of a rules directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleSource {
+    pub id: String,
+    /// The rule file, relative to the rules directory.
+    pub file: String,
+    /// The YAML document of the rule.
+    pub yaml: String,
+}
+
+/// The YAML rule files under `rules_dir` (hidden entries skipped), in path order.
+pub fn rule_files(rules_dir: &Path) -> Vec<PathBuf> {
+    list_files(rules_dir, true)
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yml" || ext == "yaml"))
+        .collect()
+}
+
+/// The rules of `yaml`, one per document with an `id`; `file` is the name to report them under.
+pub fn parse_rule_sources(yaml: &str, file: &str) -> Result<Vec<RuleSource>, serde_yaml::Error> {
+    let mut rules = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(yaml) {
+        let rule = serde_yaml::Value::deserialize(document)?;
+        if let Some(id) = rule.get("id").and_then(|id| id.as_str()) {
+            rules.push(RuleSource { id: id.to_string(), file: file.to_string(), yaml: serde_yaml::to_string(&rule)? });
+        }
+    }
+    Ok(rules)
+}
+
+/// When each rule last matched in `project_folder` according to a database written by
+/// `export_matches`: the `created_at` of the latest scan with a match of the rule.
+pub fn last_matched(conn: &Connection, project_folder: &str) -> rusqlite::Result<HashMap<String, String>> {
+    let mut statement = conn.prepare(
+        "SELECT matches.rule_id, MAX(scans.created_at) FROM matches JOIN scans ON scans.id = matches.scan_id
+         WHERE scans.project_folder = ?1 AND matches.rule_id IS NOT NULL GROUP BY matches.rule_id",
+    )?;
+    let rows = statement.query_map(params![project_folder], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// How one rule does on a project.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleEffectiveness {
+    pub id: String,
+    pub file: String,
+    pub matches: usize,
+    /// Files with at least one match.
+    pub files: usize,
+    /// When the rule last matched according to the history database, for rules without matches now.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_matched: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectivenessReport {
+    pub rules_total: usize,
+    pub rules_matching: usize,
+    /// Rules without matches now or in the history database: candidates for removal.
+    pub never_matched: Vec<String>,
+    /// Every rule, fewest matches first.
+    pub rules: Vec<RuleEffectiveness>,
+}
+
+/// Counts the matches and files of each of `rules` in `matches` (by `ruleId`), with the last
+/// match of rules without matches now taken from `history`.
+pub fn effectiveness_report(
+    rules: &[RuleSource],
+    matches: &[Value],
+    history: &HashMap<String, String>,
+) -> EffectivenessReport {
+    let mut found: BTreeMap<&str, (usize, BTreeSet<&str>)> = BTreeMap::new();
+    for m in matches {
+        let Some(rule_id) = m.get("ruleId").and_then(Value::as_str) else {
+            continue;
+        };
+        let entry = found.entry(rule_id).or_default();
+        entry.0 += 1;
+        entry.1.extend(m.get("file").and_then(Value::as_str));
+    }
+
+    let mut results: Vec<RuleEffectiveness> = rules
+        .iter()
+        .map(|rule| {
+            let (matches, files) = found.get(rule.id.as_str()).map_or((0, 0), |(count, files)| (*count, files.len()));
+            RuleEffectiveness {
+                id: rule.id.clone(),
+                file: rule.file.clone(),
+                matches,
+                files,
+                last_matched: (matches == 0).then(|| history.get(&rule.id).cloned()).flatten(),
+            }
+        })
+        .collect();
+    results.sort_by(|a, b| a.matches.cmp(&b.matches).then_with(|| a.id.cmp(&b.id)));
+    EffectivenessReport {
+        rules_total: results.len(),
+        rules_matching: results.iter().filter(|rule| rule.matches > 0).count(),
+        never_matched: results
+            .iter()
+            .filter(|rule| rule.matches == 0 && rule.last_matched.is_none())
+            .map(|rule| rule.id.clone())
+            .collect(),
+        rules: results,
+    }
+}
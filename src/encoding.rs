@@ -0,0 +1,185 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Values of the find tools' `text_source`.
+pub const TEXT_SOURCES: &[&str] = &["cli", "file"];
+
+/// Text encodings recognized when reading source files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl SourceEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceEncoding::Utf8 => "utf-8",
+            SourceEncoding::Utf16Le => "utf-16le",
+            SourceEncoding::Utf16Be => "utf-16be",
+            SourceEncoding::Latin1 => "latin-1",
+        }
+    }
+}
+
+/// Decodes file contents, detecting UTF-8 (with or without BOM), UTF-16 with a BOM, and
+/// falling back to Latin-1 for anything else that is not valid UTF-8.
+///
+/// Returns `None` only for malformed UTF-16, since every byte sequence is valid Latin-1.
+pub fn decode(bytes: &[u8]) -> Option<(String, SourceEncoding)> {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        if let Ok(text) = std::str::from_utf8(rest) {
+            return Some((text.to_string(), SourceEncoding::Utf8));
+        }
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return decode_utf16(rest, u16::from_le_bytes).map(|t| (t, SourceEncoding::Utf16Le));
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return decode_utf16(rest, u16::from_be_bytes).map(|t| (t, SourceEncoding::Utf16Be));
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Some((text.to_string(), SourceEncoding::Utf8));
+    }
+    Some((bytes.iter().map(|&b| b as char).collect(), SourceEncoding::Latin1))
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+/// Reads and decodes a source file with [`decode`].
+pub fn read_source(path: &Path) -> std::io::Result<(String, SourceEncoding)> {
+    let bytes = std::fs::read(path)?;
+    decode(&bytes).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("could not decode {}", path.display()))
+    })
+}
+
+/// Replaces mojibake in match text for files that are not UTF-8.
+///
+/// ast-grep decodes files lossily, so matches in Latin-1 or UTF-16 files contain U+FFFD
+/// replacement characters. For those matches the file is re-read, transcoded, and the match
+/// `text` (and `lines`, when present) is re-sliced from the decoded content using the match
+/// range; the match gets an `encoding` field naming the source encoding. Returns the files
+/// that could not be read or decoded.
+pub fn fix_match_encodings(matches: &mut [Value], project_folder: &Path) -> Vec<String> {
+    let mut decoded: HashMap<String, Option<(Vec<String>, SourceEncoding)>> = HashMap::new();
+    let mut undecodable = Vec::new();
+
+    for m in matches.iter_mut() {
+        let has_mojibake = m.get("text").and_then(|v| v.as_str()).is_some_and(|t| t.contains('\u{FFFD}'));
+        if !has_mojibake {
+            continue;
+        }
+        let Some(file) = m.get("file").and_then(|v| v.as_str()).map(str::to_string) else {
+            continue;
+        };
+
+        let entry = decoded.entry(file.clone()).or_insert_with(|| {
+            let result = read_source(&project_folder.join(&file))
+                .ok()
+                .map(|(text, encoding)| (text.lines().map(str::to_string).collect(), encoding));
+            if result.is_none() {
+                undecodable.push(file.clone());
+            }
+            result
+        });
+
+        let Some((lines, encoding)) = entry else {
+            continue;
+        };
+        // Valid UTF-8 means the replacement characters are really in the source
+        if *encoding == SourceEncoding::Utf8 {
+            continue;
+        }
+
+        let position = |edge: &str| {
+            let line = m.pointer(&format!("/range/{}/line", edge)).and_then(|v| v.as_u64())? as usize;
+            let column = m.pointer(&format!("/range/{}/column", edge)).and_then(|v| v.as_u64())? as usize;
+            Some((line, column))
+        };
+        let (Some((start_line, start_col)), Some((end_line, end_col))) = (position("start"), position("end")) else {
+            continue;
+        };
+        if end_line >= lines.len() || start_line > end_line {
+            continue;
+        }
+
+        let region = &lines[start_line..=end_line];
+        let mut text = String::new();
+        for (i, line) in region.iter().enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            let from = if i == 0 { start_col.min(chars.len()) } else { 0 };
+            let to = if i == region.len() - 1 { end_col.min(chars.len()) } else { chars.len() };
+            if i > 0 {
+                text.push('\n');
+            }
+            text.extend(&chars[from..to.max(from)]);
+        }
+
+        if let Some(obj) = m.as_object_mut() {
+            obj.insert("text".to_string(), Value::String(text));
+            if obj.contains_key("lines") {
+                obj.insert("lines".to_string(), Value::String(region.join("\n")));
+            }
+            obj.insert("encoding".to_string(), Value::String(encoding.as_str().to_string()));
+        }
+    }
+
+    undecodable
+}
+
+/// Replaces the text of `matches` with the text read from their files (relative to
+/// `project_folder`), from the start of the first line when only indentation precedes the match,
+/// so displays and rewrites keep the exact original whitespace.
+///
+/// Each match gets `textSource`: `file`, or `cli` when its file no longer holds the scanned text
+/// at the match range (it changed since the scan) and ast-grep's text is kept. Files that are
+/// unreadable or not UTF-8 keep ast-grep's text without a `textSource`. Returns the changed files.
+pub fn reread_match_text(matches: &mut [Value], project_folder: &Path) -> Vec<String> {
+    let mut sources: HashMap<String, Option<String>> = HashMap::new();
+    let mut changed = Vec::new();
+
+    for m in matches.iter_mut() {
+        let Some(file) = m.get("file").and_then(|v| v.as_str()).map(str::to_string) else {
+            continue;
+        };
+        let offset = |edge: &str| m.pointer(&format!("/range/byteOffset/{}", edge)).and_then(|v| v.as_u64());
+        let (Some(start), Some(end)) = (offset("start"), offset("end")) else {
+            continue;
+        };
+        let (start, end) = (start as usize, end as usize);
+        let source = sources.entry(file.clone()).or_insert_with(|| {
+            std::fs::read(project_folder.join(&file)).ok().and_then(|bytes| String::from_utf8(bytes).ok())
+        });
+        let Some(source) = source else {
+            continue;
+        };
+
+        let unchanged = source.get(start..end) == Some(m.get("text").and_then(|v| v.as_str()).unwrap_or(""));
+        let Some(obj) = m.as_object_mut() else {
+            continue;
+        };
+        if !unchanged {
+            if !changed.contains(&file) {
+                changed.push(file);
+            }
+            obj.insert("textSource".to_string(), Value::from("cli"));
+            continue;
+        }
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let from = if source[line_start..start].trim().is_empty() { line_start } else { start };
+        obj.insert("text".to_string(), Value::String(source[from..end].to_string()));
+        obj.insert("textSource".to_string(), Value::from("file"));
+    }
+
+    changed
+}
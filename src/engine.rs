@@ -0,0 +1,28 @@
+use crate::command::{self, CommandResult, LimitedResult};
+use crate::config::Config;
+use anyhow::Result;
+use serde_json::Value;
+
+/// Runs `ast-grep <command> <args>`; output is as the CLI prints it. The tools run their queries
+/// through this and [`run_ast_grep_limited`] only, so that an in-process engine (the ast-grep
+/// library crates, say) could take over here, producing the same JSON, without changing them.
+pub async fn run_ast_grep(
+    command: &str,
+    args: &[String],
+    input_text: Option<&str>,
+    config: &Config,
+) -> Result<CommandResult> {
+    command::run_ast_grep(command, args, input_text, config).await
+}
+
+/// Like [`run_ast_grep`], stopping once `max_matches` matches were reported; see
+/// [`command::run_ast_grep_limited`].
+pub async fn run_ast_grep_limited(
+    command: &str,
+    args: &[String],
+    config: &Config,
+    max_matches: usize,
+    counts: &(dyn Fn(&Value) -> bool + Sync),
+) -> Result<LimitedResult> {
+    command::run_ast_grep_limited(command, args, config, max_matches, counts).await
+}
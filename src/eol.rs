@@ -0,0 +1,151 @@
+use crate::ranges::position_of;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Values of the find tools' `line_endings`.
+pub const LINE_ENDINGS: &[&str] = &["keep", "lf"];
+
+/// The line endings of a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    /// Lone carriage returns, as in classic Mac OS files.
+    Cr,
+    /// More than one of the above.
+    Mixed,
+    /// No line breaks at all.
+    None,
+}
+
+impl LineEnding {
+    pub fn detect(source: &str) -> Self {
+        let crlf = source.matches("\r\n").count();
+        let cr = source.matches('\r').count() - crlf;
+        let lf = source.matches('\n').count() - crlf;
+        match (lf > 0, crlf > 0, cr > 0) {
+            (false, false, false) => LineEnding::None,
+            (true, false, false) => LineEnding::Lf,
+            (false, true, false) => LineEnding::Crlf,
+            (false, false, true) => LineEnding::Cr,
+            _ => LineEnding::Mixed,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+            LineEnding::Cr => "cr",
+            LineEnding::Mixed => "mixed",
+            LineEnding::None => "none",
+        }
+    }
+}
+
+/// A source file as an editor that takes `\r\n`, `\r` and `\n` alike as one line break (as LSP
+/// does) sees it, with `\n` for each.
+struct LfSource {
+    source: String,
+    /// Byte offsets of the `\r`s of `\r\n`, which the LF source drops.
+    dropped: Vec<usize>,
+}
+
+impl LfSource {
+    fn new(original: &str) -> Self {
+        let dropped = original.match_indices("\r\n").map(|(i, _)| i).collect();
+        Self { source: normalize_text(original), dropped }
+    }
+
+    /// The LF source's offset of a byte offset in the original.
+    fn offset(&self, original: usize) -> usize {
+        original - self.dropped.partition_point(|&i| i < original)
+    }
+}
+
+/// `text` with every `\r\n` and lone `\r` turned into `\n`.
+pub fn normalize_text(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Gives every match an `eol` field naming its file's line endings and reports matches in files
+/// with `\r\n` or `\r` endings as if those were `\n`: text without carriage returns, byte offsets
+/// into the LF version of the file, and lines counted the way editors count them, a lone `\r`
+/// included (ast-grep counts only `\n`). Metavariable captures get the same treatment.
+///
+/// Files that cannot be read, or are not UTF-8, are left as ast-grep reported them.
+pub fn normalize_line_endings(matches: &mut [Value], project_folder: &Path) {
+    let mut sources: HashMap<String, Option<(LineEnding, Option<LfSource>)>> = HashMap::new();
+
+    for m in matches.iter_mut() {
+        let Some(file) = m.get("file").and_then(Value::as_str).map(str::to_string) else {
+            continue;
+        };
+        let source = sources.entry(file.clone()).or_insert_with(|| {
+            let source = String::from_utf8(std::fs::read(project_folder.join(&file)).ok()?).ok()?;
+            let eol = LineEnding::detect(&source);
+            let carriage_returns = matches!(eol, LineEnding::Crlf | LineEnding::Cr | LineEnding::Mixed);
+            Some((eol, carriage_returns.then(|| LfSource::new(&source))))
+        });
+        let Some((eol, lf)) = source else {
+            continue;
+        };
+        if let Some(lf) = lf {
+            normalize_capture(m, lf);
+            for key in ["lines", "replacement"] {
+                normalize_string(m, key);
+            }
+            if let Some(offsets) = m.get_mut("replacementOffsets") {
+                normalize_offsets(offsets, lf);
+            }
+            if let Some(variables) = m.get_mut("metaVariables") {
+                let single = variables.get_mut("single").and_then(Value::as_object_mut);
+                for capture in single.into_iter().flat_map(|single| single.values_mut()) {
+                    normalize_capture(capture, lf);
+                }
+                let multi = variables.get_mut("multi").and_then(Value::as_object_mut);
+                for captures in multi.into_iter().flat_map(|multi| multi.values_mut()) {
+                    for capture in captures.as_array_mut().into_iter().flatten() {
+                        normalize_capture(capture, lf);
+                    }
+                }
+            }
+        }
+        if let Some(obj) = m.as_object_mut() {
+            obj.insert("eol".to_string(), Value::from(eol.as_str()));
+        }
+    }
+}
+
+/// Normalizes the `text` and `range` of a match or metavariable capture.
+fn normalize_capture(capture: &mut Value, lf: &LfSource) {
+    normalize_string(capture, "text");
+    let Some(range) = capture.get_mut("range") else {
+        return;
+    };
+    let Some(offsets) = range.get_mut("byteOffset") else {
+        return;
+    };
+    normalize_offsets(offsets, lf);
+    for edge in ["start", "end"] {
+        let offset = range.pointer(&format!("/byteOffset/{}", edge)).and_then(Value::as_u64);
+        if let Some((line, column)) = offset.and_then(|offset| position_of(&lf.source, offset as usize)) {
+            range[edge] = json!({ "line": line, "column": column });
+        }
+    }
+}
+
+fn normalize_offsets(offsets: &mut Value, lf: &LfSource) {
+    for edge in ["start", "end"] {
+        if let Some(offset) = offsets.get(edge).and_then(Value::as_u64) {
+            offsets[edge] = Value::from(lf.offset(offset as usize));
+        }
+    }
+}
+
+fn normalize_string(value: &mut Value, key: &str) {
+    if let Some(text) = value.get(key).and_then(Value::as_str) {
+        value[key] = Value::String(normalize_text(text));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::benchmark::first_rule_document;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// What a find call would do, worked out without scanning the project.
+#[derive(Debug, Default, Serialize)]
+pub struct QueryPlan {
+    /// Language the query targets; empty when ast-grep would infer it per file.
+    pub language: String,
+    /// Extensions of files the language covers; `None` when unknown or inferred.
+    pub extensions: Option<Vec<String>>,
+    /// Files that would be considered, estimated without consulting ignore files.
+    pub candidate_files: usize,
+    /// Candidate files per extension.
+    pub files_by_extension: BTreeMap<String, usize>,
+    /// `--globs` exclusions applied by default.
+    pub excluded_globs: Vec<String>,
+    /// Ignore files in the project folder that will be respected.
+    pub ignore_files: Vec<String>,
+    pub no_ignore: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<PatternCheck>,
+    pub notes: Vec<String>,
+}
+
+/// How ast-grep parsed the query's pattern.
+#[derive(Debug, Default, Serialize)]
+pub struct PatternCheck {
+    pub pattern: String,
+    /// Node kind at the root of the parsed pattern.
+    pub root_kind: Option<String>,
+    pub has_errors: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches_expected: Option<bool>,
+    /// ast-grep's `--debug-query=pattern` output.
+    pub dump: String,
+}
+
+impl PatternCheck {
+    /// Builds the check from a `--debug-query=pattern` dump.
+    pub fn from_dump(pattern: &str, dump: &str, expected_kind: Option<&str>) -> Self {
+        let root_kind = dump_root_kind(dump);
+        Self {
+            pattern: pattern.to_string(),
+            has_errors: dump_has_errors(dump),
+            expected_kind: expected_kind.map(str::to_string),
+            matches_expected: expected_kind.map(|expected| root_kind.as_deref() == Some(expected)),
+            root_kind,
+            dump: dump.trim().to_string(),
+        }
+    }
+}
+
+/// Kind of the first node in a syntax tree dump, skipping the `Debug ...:` header.
+///
+/// Dump lines look like `call_expression (0,0)-(0,14)` or `identifier: console`.
+pub fn dump_root_kind(dump: &str) -> Option<String> {
+    let line = dump
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("Debug "))?;
+    let kind = line.split_whitespace().next()?.trim_end_matches(':');
+    Some(kind.to_string())
+}
+
+/// Whether the dump contains ERROR or MISSING nodes, i.e. the pattern is not valid code.
+pub fn dump_has_errors(dump: &str) -> bool {
+    dump.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .any(|token| token == "ERROR" || token == "MISSING")
+}
+
+/// Reads the `rule.pattern` of an inline rule when it is a plain string.
+pub fn rule_pattern(yaml: &str) -> Option<String> {
+    let rule = first_rule_document(yaml).ok()?;
+    rule.get("rule")?.get("pattern")?.as_str().map(str::to_string)
+}
@@ -0,0 +1,121 @@
+use rusqlite::{params, Connection};
+use serde_json::Value;
+
+/// Tables written by [`export_matches`]. Every export adds a `scans` row, so one database can
+/// hold several exports; lines and columns are zero-based as in ast-grep's JSON output.
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    project_folder TEXT NOT NULL,
+    query TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE TABLE IF NOT EXISTS matches (
+    id INTEGER PRIMARY KEY,
+    scan_id INTEGER NOT NULL REFERENCES scans(id),
+    file TEXT NOT NULL,
+    rule_id TEXT,
+    severity TEXT,
+    message TEXT,
+    start_line INTEGER NOT NULL,
+    start_column INTEGER NOT NULL,
+    end_line INTEGER NOT NULL,
+    end_column INTEGER NOT NULL,
+    start_byte INTEGER,
+    end_byte INTEGER,
+    text TEXT NOT NULL,
+    fingerprint TEXT
+);
+CREATE TABLE IF NOT EXISTS metavariables (
+    match_id INTEGER NOT NULL REFERENCES matches(id),
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    text TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS matches_file ON matches(file);
+CREATE INDEX IF NOT EXISTS matches_fingerprint ON matches(fingerprint);
+CREATE INDEX IF NOT EXISTS metavariables_name ON metavariables(name, text);
+";
+
+/// Metavariables of a match as (name, kind, text); `kind` is `single`, `multi` or `transformed`.
+/// A multi metavariable is one row with its nodes' texts joined by `, `, separators left out.
+fn metavariable_rows(m: &Value) -> Vec<(String, &'static str, String)> {
+    let mut rows = Vec::new();
+    let Some(vars) = m.get("metaVariables") else {
+        return rows;
+    };
+    if let Some(single) = vars.get("single").and_then(Value::as_object) {
+        for (name, node) in single {
+            let text = node.get("text").and_then(Value::as_str).unwrap_or_default();
+            rows.push((name.clone(), "single", text.to_string()));
+        }
+    }
+    if let Some(multi) = vars.get("multi").and_then(Value::as_object) {
+        for (name, nodes) in multi {
+            let texts: Vec<&str> = nodes
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|node| node.get("text").and_then(Value::as_str))
+                .filter(|text| !matches!(*text, "," | ";"))
+                .collect();
+            rows.push((name.clone(), "multi", texts.join(", ")));
+        }
+    }
+    if let Some(transformed) = vars.get("transformed").and_then(Value::as_object) {
+        for (name, text) in transformed {
+            rows.push((name.clone(), "transformed", text.as_str().unwrap_or_default().to_string()));
+        }
+    }
+    rows
+}
+
+/// Writes `matches` into the database behind `conn` in one transaction, creating the tables
+/// when needed, and returns the id of the new `scans` row.
+pub fn export_matches(
+    conn: &mut Connection,
+    project_folder: &str,
+    query: &str,
+    matches: &[Value],
+) -> rusqlite::Result<i64> {
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO scans (project_folder, query) VALUES (?1, ?2)",
+        params![project_folder, query],
+    )?;
+    let scan_id = tx.last_insert_rowid();
+    {
+        let mut insert_match = tx.prepare(
+            "INSERT INTO matches (scan_id, file, rule_id, severity, message, start_line, start_column, end_line,
+                end_column, start_byte, end_byte, text, fingerprint)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        )?;
+        let mut insert_var = tx.prepare("INSERT INTO metavariables (match_id, name, kind, text) VALUES (?1, ?2, ?3, ?4)")?;
+        for m in matches {
+            let str_field = |pointer: &str| m.pointer(pointer).and_then(Value::as_str);
+            let int_field = |pointer: &str| m.pointer(pointer).and_then(Value::as_i64);
+            insert_match.execute(params![
+                scan_id,
+                str_field("/file").unwrap_or_default(),
+                str_field("/ruleId"),
+                str_field("/severity"),
+                str_field("/message"),
+                int_field("/range/start/line").unwrap_or(0),
+                int_field("/range/start/column").unwrap_or(0),
+                int_field("/range/end/line").unwrap_or(0),
+                int_field("/range/end/column").unwrap_or(0),
+                int_field("/range/byteOffset/start"),
+                int_field("/range/byteOffset/end"),
+                str_field("/text").unwrap_or_default(),
+                str_field("/fingerprint"),
+            ])?;
+            let match_id = tx.last_insert_rowid();
+            for (name, kind, text) in metavariable_rows(m) {
+                insert_var.execute(params![match_id, name, kind, text])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(scan_id)
+}
@@ -0,0 +1,354 @@
+use crate::benchmark::language_for_path;
+use crate::profiles::FilterProfile;
+use crate::shard::walker;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Dependency and build directories skipped by the find tools unless `include_vendored` is set.
+pub const DEFAULT_EXCLUDES: &[&str] = &["node_modules", "target", ".venv", "dist", "build", ".git"];
+
+/// Returns the `--globs` arguments that exclude [`DEFAULT_EXCLUDES`], or nothing when
+/// vendored code was explicitly requested.
+///
+/// Globs follow .gitignore rules, so a bare directory name matches at any depth.
+pub fn exclude_glob_args(include_vendored: bool) -> Vec<String> {
+    if include_vendored {
+        return Vec::new();
+    }
+
+    DEFAULT_EXCLUDES
+        .iter()
+        .flat_map(|dir| ["--globs".to_string(), format!("!{}", dir)])
+        .collect()
+}
+
+/// Ignore files that silently hide files from ast-grep and are easy to overlook.
+/// `.gitignore` is respected too, but it is expected enough not to be worth a note.
+pub const NOTABLE_IGNORE_FILES: &[&str] = &[".sgignore", ".ignore"];
+
+/// Returns the `--no-ignore` arguments that make ast-grep search files hidden by ignore files
+/// (.gitignore, .ignore, .sgignore, git excludes and parent directories).
+pub fn no_ignore_args(no_ignore: bool) -> Vec<String> {
+    if !no_ignore {
+        return Vec::new();
+    }
+
+    ["dot", "exclude", "parent", "vcs"]
+        .iter()
+        .flat_map(|kind| ["--no-ignore".to_string(), kind.to_string()])
+        .collect()
+}
+
+/// Lists the notable ignore files present at the root of `project_folder`.
+pub fn detect_ignore_files(project_folder: &Path) -> Vec<&'static str> {
+    NOTABLE_IGNORE_FILES
+        .iter()
+        .copied()
+        .filter(|name| project_folder.join(name).is_file())
+        .collect()
+}
+
+/// Builds a note telling the caller that ignore files may have excluded files from a search.
+pub fn ignore_note(project_folder: &Path, no_ignore: bool) -> Option<String> {
+    if no_ignore {
+        return None;
+    }
+
+    let found = detect_ignore_files(project_folder);
+    if found.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "{} in the project folder may exclude files from this search; pass no_ignore=true to search ignored files.",
+        found.join(", ")
+    ))
+}
+
+/// File name suffixes of bundler/minifier output.
+pub const MINIFIED_SUFFIXES: &[&str] = &[".min.js", ".min.mjs", ".min.cjs", ".min.css"];
+
+/// How much of a file is sampled when deciding whether it looks minified or binary.
+const SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Why a file was left out of the results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    MinifiedSuffix,
+    LongLines,
+    Binary,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            SkipReason::MinifiedSuffix => "minified file name",
+            SkipReason::LongLines => "very long lines",
+            SkipReason::Binary => "binary-looking content",
+        };
+        f.write_str(reason)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedFile {
+    pub file: String,
+    pub reason: SkipReason,
+}
+
+/// Decides whether a file looks like minified or binary output whose matches are useless.
+///
+/// Only the first 64 KiB are inspected: NUL bytes or near-random byte entropy mean binary,
+/// and an average line over 300 bytes or any line over 10,000 bytes means minified. Text with a
+/// UTF-16 byte order mark is judged by its characters, as its NUL bytes do not mean binary.
+pub fn classify_generated(path: &Path) -> Option<SkipReason> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if MINIFIED_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        return Some(SkipReason::MinifiedSuffix);
+    }
+
+    let mut sample = Vec::with_capacity(SAMPLE_BYTES);
+    let file = std::fs::File::open(path).ok()?;
+    file.take(SAMPLE_BYTES as u64).read_to_end(&mut sample).ok()?;
+    if sample.is_empty() {
+        return None;
+    }
+    let sample = utf16_sample(&sample).unwrap_or(sample);
+
+    if sample.contains(&0) || byte_entropy(&sample) > 7.0 {
+        return Some(SkipReason::Binary);
+    }
+
+    let lines = sample.split(|&b| b == b'\n').collect::<Vec<_>>();
+    let longest = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+    if longest > 10_000 || sample.len() / lines.len() > 300 {
+        return Some(SkipReason::LongLines);
+    }
+
+    None
+}
+
+/// `sample` transcoded to UTF-8 when it starts with a UTF-16 byte order mark. A sample may end
+/// in the middle of a character, which becomes a replacement character.
+fn utf16_sample(sample: &[u8]) -> Option<Vec<u8>> {
+    let to_unit: fn([u8; 2]) -> u16 = match sample.get(..2)? {
+        [0xFF, 0xFE] => u16::from_le_bytes,
+        [0xFE, 0xFF] => u16::from_be_bytes,
+        _ => return None,
+    };
+    let units = sample[2..].chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]]));
+    let text: String = char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+    Some(text.into_bytes())
+}
+
+/// Shannon entropy of `bytes` in bits per byte (0.0 to 8.0).
+fn byte_entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Removes matches located in minified or binary-looking files.
+///
+/// Relative match paths are resolved against `project_folder`. Returns the remaining matches
+/// and one entry per skipped file.
+pub fn drop_generated_files(matches: Vec<Value>, project_folder: &Path) -> (Vec<Value>, Vec<SkippedFile>) {
+    let mut verdicts: HashMap<String, Option<SkipReason>> = HashMap::new();
+    let mut skipped = Vec::new();
+
+    let kept = matches
+        .into_iter()
+        .filter(|m| {
+            let file = m.get("file").and_then(|v| v.as_str()).unwrap_or("");
+            let verdict = *verdicts.entry(file.to_string()).or_insert_with(|| {
+                let reason = classify_generated(&project_folder.join(file));
+                if let Some(reason) = reason {
+                    skipped.push(SkippedFile { file: file.to_string(), reason });
+                }
+                reason
+            });
+            verdict.is_none()
+        })
+        .collect();
+
+    (kept, skipped)
+}
+
+/// The matches [`drop_generated_files`] and a filter profile keep, decided one match at a time, so
+/// that a scan stopping after some number of matches counts only those that are shown.
+pub struct KeptMatches {
+    profile: Option<FilterProfile>,
+    drop_generated: bool,
+    /// Whether each file seen so far is kept, as classifying it reads it.
+    verdicts: Mutex<HashMap<PathBuf, bool>>,
+}
+
+impl KeptMatches {
+    pub fn new(profile: Option<FilterProfile>, drop_generated: bool) -> Self {
+        KeptMatches { profile, drop_generated, verdicts: Mutex::default() }
+    }
+
+    /// Whether the match `m` of a scan of `folder` (against which relative paths are resolved) is
+    /// kept.
+    pub fn keeps(&self, m: &Value, folder: &Path) -> bool {
+        let Some(file) = m.get("file").and_then(Value::as_str) else {
+            return true;
+        };
+        if self.profile.as_ref().is_some_and(|profile| !profile.keeps(Path::new(file))) {
+            return false;
+        }
+        if !self.drop_generated {
+            return true;
+        }
+        let path = folder.join(file);
+        let mut verdicts = self.verdicts.lock().unwrap_or_else(|e| e.into_inner());
+        *verdicts.entry(path).or_insert_with_key(|path| classify_generated(path).is_none())
+    }
+}
+
+/// Why an entry under a project folder is not searched by ast-grep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotSearchedReason {
+    /// Hidden files and directories, which ast-grep never walks into.
+    Hidden,
+    /// Dependency and build directories excluded by default ([`DEFAULT_EXCLUDES`]).
+    Vendored,
+    /// Entries excluded by ignore files (.gitignore, .ignore, .sgignore, git excludes).
+    Ignored,
+    /// Files no built-in or custom language claims by extension.
+    UnsupportedLanguage,
+}
+
+impl std::fmt::Display for NotSearchedReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            NotSearchedReason::Hidden => "hidden",
+            NotSearchedReason::Vendored => "vendored",
+            NotSearchedReason::Ignored => "ignored",
+            NotSearchedReason::UnsupportedLanguage => "unsupported language",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// How many example paths are kept per [`NotSearchedReason`].
+pub const NOT_SEARCHED_EXAMPLES: usize = 5;
+
+/// Entries of one [`NotSearchedReason`]; a directory counts once, its contents are not walked.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NotSearchedGroup {
+    pub count: usize,
+    /// The first few entries found, directories ending with `/`.
+    pub examples: Vec<String>,
+}
+
+/// Entries of project folders that a scan does not search, by reason.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct NotSearched(pub BTreeMap<NotSearchedReason, NotSearchedGroup>);
+
+impl NotSearched {
+    pub fn add(&mut self, reason: NotSearchedReason, entry: String) {
+        let group = self.0.entry(reason).or_default();
+        group.count += 1;
+        if group.examples.len() < NOT_SEARCHED_EXAMPLES {
+            group.examples.push(entry);
+        }
+    }
+
+    /// Adds the entries of `other`, with examples under `prefix` (e.g. another project folder).
+    pub fn merge(&mut self, other: NotSearched, prefix: &Path) {
+        for (reason, group) in other.0 {
+            let merged = self.0.entry(reason).or_default();
+            merged.count += group.count;
+            let room = NOT_SEARCHED_EXAMPLES.saturating_sub(merged.examples.len());
+            let examples = group.examples.into_iter().take(room);
+            merged.examples.extend(examples.map(|example| format!("{}/{}", prefix.display(), example)));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// One line such as `Not searched: 2 hidden (.git/, .env), 1 vendored (node_modules/)`.
+    pub fn to_text(&self) -> String {
+        let groups: Vec<String> = self
+            .0
+            .iter()
+            .map(|(reason, group)| {
+                let more = if group.count > group.examples.len() { ", ..." } else { "" };
+                format!("{} {} ({}{})", group.count, reason, group.examples.join(", "), more)
+            })
+            .collect();
+        format!("Not searched: {}", groups.join(", "))
+    }
+}
+
+/// Walks `root` like ast-grep does (see [`walker`]) and collects what it leaves out: hidden
+/// entries, vendored directories (unless `include_vendored`), entries of ignore files (unless
+/// `no_ignore`) and files without a known language (`extra_extensions` are those of custom
+/// languages). Symbolic links are not followed, and files are not opened.
+pub fn not_searched(root: &Path, include_vendored: bool, no_ignore: bool, extra_extensions: &[String]) -> NotSearched {
+    let mut walk = walker(root, include_vendored);
+    if no_ignore {
+        walk.ignore(false).git_ignore(false).git_exclude(false).git_global(false).parents(false);
+    }
+    let walked: Vec<ignore::DirEntry> = walk.build().filter_map(Result::ok).collect();
+    let visited: HashSet<&Path> = walked.iter().map(ignore::DirEntry::path).collect();
+    let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+
+    let mut summary = NotSearched::default();
+    for entry in &walked {
+        let path = entry.path();
+        if entry.file_type().is_some_and(|kind| kind.is_file()) {
+            let custom = path.extension().is_some_and(|ext| extra_extensions.iter().any(|e| ext == e.as_str()));
+            if language_for_path(path).is_none() && !custom {
+                summary.add(NotSearchedReason::UnsupportedLanguage, relative(path));
+            }
+            continue;
+        }
+        if !entry.file_type().is_some_and(|kind| kind.is_dir()) {
+            continue;
+        }
+        // The entries of a visited directory that the walk did not visit were left out
+        let Ok(children) = std::fs::read_dir(path) else {
+            continue;
+        };
+        let mut left_out: Vec<(PathBuf, bool)> = children
+            .filter_map(|child| child.ok())
+            .filter(|child| !visited.contains(child.path().as_path()))
+            .map(|child| (child.path(), child.file_type().is_ok_and(|kind| kind.is_dir())))
+            .collect();
+        left_out.sort();
+        for (child, is_dir) in left_out {
+            let name = child.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let reason = if name.starts_with('.') {
+                NotSearchedReason::Hidden
+            } else if is_dir && DEFAULT_EXCLUDES.contains(&name.as_str()) {
+                NotSearchedReason::Vendored
+            } else {
+                NotSearchedReason::Ignored
+            };
+            let entry = if is_dir { format!("{}/", relative(&child)) } else { relative(&child) };
+            summary.add(reason, entry);
+        }
+    }
+    summary
+}
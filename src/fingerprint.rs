@@ -0,0 +1,58 @@
+use serde_json::Value;
+
+/// 64-bit FNV-1a; unlike `DefaultHasher` its output is the same across builds and runs.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        // Separate fields so ("ab", "c") and ("a", "bc") hash differently
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Stable fingerprint of a match: rule id, file and whitespace-normalized text.
+///
+/// Positions are deliberately left out so the fingerprint survives edits elsewhere in the
+/// file; baselines and suppression lists can key on it across scans.
+pub fn fingerprint(m: &Value) -> String {
+    let mut hash = Fnv1a::new();
+    hash.write(m.get("ruleId").and_then(|v| v.as_str()).unwrap_or("").as_bytes());
+    hash.write(m.get("file").and_then(|v| v.as_str()).unwrap_or("").as_bytes());
+    let text = m.get("text").and_then(|v| v.as_str()).unwrap_or("");
+    hash.write(normalize_text(text).as_bytes());
+    format!("{:016x}", hash.finish())
+}
+
+/// Adds a `fingerprint` field to every match.
+pub fn add_fingerprints(matches: &mut [Value]) {
+    for m in matches.iter_mut() {
+        let fingerprint = fingerprint(m);
+        if let Some(obj) = m.as_object_mut() {
+            obj.insert("fingerprint".to_string(), Value::String(fingerprint));
+        }
+    }
+}
+
+/// Collapses whitespace runs so reindenting or rewrapping a match keeps its fingerprint.
+fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
@@ -1,43 +1,246 @@
+use rmcp::model::RawResource;
+use serde::Serialize;
 use serde_json::Value;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// Most bytes of text output one response carries; matches past it are left out with a note,
+/// so a search with tens of thousands of matches does not build a second copy of them as text.
+pub const MAX_TEXT_BYTES: usize = 4 * 1024 * 1024;
+
+/// Values of the find tools' `output_format`.
+pub const OUTPUT_FORMATS: &[&str] = &["text", "json", "headers", "diagnostics"];
 
 pub fn format_matches_as_text(matches: &[Value]) -> String {
-    if matches.is_empty() {
-        return String::new();
+    let mut output = String::new();
+    write_matches_as_text(&mut output, matches, usize::MAX);
+    output
+}
+
+/// Appends the text blocks of `matches` to `output` one at a time, stopping before the block that
+/// would take `output` past `max_bytes` (the first block is always written). Returns how many
+/// matches were written.
+pub fn write_matches_as_text(output: &mut String, matches: &[Value], max_bytes: usize) -> usize {
+    for (written, m) in matches.iter().enumerate() {
+        let block_start = output.len();
+        if written > 0 {
+            output.push_str("\n\n");
+        }
+        let match_text = m.get("text").and_then(|v| v.as_str()).unwrap_or("").trim_end();
+        output.push_str(&location_label(m));
+
+        // Collapsed duplicates list every occurrence; the first one is the header itself
+        let locations = duplicate_locations(m);
+        if locations.len() > 1 {
+            let _ = write!(output, " ({} occurrences)", locations.len());
+        }
+        output.push('\n');
+        output.push_str(match_text);
+        for (i, other) in locations.iter().skip(1).enumerate() {
+            output.push_str(if i == 0 { "\nAlso at: " } else { ", " });
+            output.push_str(&location_label(other));
+        }
+
+        if written > 0 && output.len() > max_bytes {
+            output.truncate(block_start);
+            return written;
+        }
     }
+    matches.len()
+}
 
-    let mut output_blocks = Vec::new();
+/// Groups matches with identical (trimmed) text into one entry per text.
+///
+/// Each entry is the first occurrence, plus a `locations` list of `{file, range}` for every
+/// occurrence and a `count` when the text occurs more than once. Order follows the first
+/// occurrences.
+pub fn collapse_duplicates(matches: Vec<Value>) -> Vec<Value> {
+    let mut entries: Vec<Value> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
 
     for m in matches {
-        let file_path = m.get("file").and_then(|v| v.as_str()).unwrap_or("");
+        let key = m.get("text").and_then(|v| v.as_str()).unwrap_or("").trim().to_string();
+        match index.get(&key) {
+            Some(&i) => {
+                let location = location_of(&m);
+                let entry = &mut entries[i];
+                let first = location_of(entry);
+                if let Some(obj) = entry.as_object_mut() {
+                    let locations = obj.entry("locations").or_insert_with(|| Value::Array(vec![first]));
+                    if let Some(locations) = locations.as_array_mut() {
+                        locations.push(location);
+                    }
+                    let count = obj["locations"].as_array().map_or(1, |l| l.len());
+                    obj.insert("count".to_string(), Value::from(count));
+                }
+            }
+            None => {
+                index.insert(key, entries.len());
+                entries.push(m);
+            }
+        }
+    }
 
-        // lines are 0-indexed in JSON, convert to 1-indexed
-        let start_line = m.pointer("/range/start/line")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(0) + 1;
+    entries
+}
 
-        let end_line = m.pointer("/range/end/line")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(0) + 1;
+/// A file with more matches than `max_matches_per_file`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CappedFile {
+    pub file: String,
+    /// All matches in the file.
+    pub matches: usize,
+    /// The matches kept.
+    pub shown: usize,
+}
 
-        let match_text = m.get("text").and_then(|v| v.as_str()).unwrap_or("").trim_end();
+/// Keeps the first `max` matches of each file, so that one file full of matches (generated code,
+/// a vendored bundle) does not use up `max_results` and hide the matches elsewhere. Returns the
+/// matches kept, in their order, and the files that had more, with their true match counts.
+pub fn cap_matches_per_file(matches: Vec<Value>, max: usize) -> (Vec<Value>, Vec<CappedFile>) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut capped: Vec<CappedFile> = Vec::new();
+    let mut kept = Vec::new();
+    for m in matches {
+        let file = m.get("file").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let count = counts.entry(file.clone()).or_default();
+        *count += 1;
+        if *count <= max {
+            kept.push(m);
+        } else if *count == max + 1 {
+            capped.push(CappedFile { file, matches: *count, shown: max });
+        } else if let Some(entry) = capped.iter_mut().rev().find(|entry| entry.file == file) {
+            entry.matches = *count;
+        }
+    }
+    (kept, capped)
+}
 
-        let header = if start_line == end_line {
-            format!("{}:{}", file_path, start_line)
-        } else {
-            format!("{}:{}-{}", file_path, start_line, end_line)
-        };
+fn location_of(m: &Value) -> Value {
+    serde_json::json!({
+        "file": m.get("file").cloned().unwrap_or(Value::Null),
+        "range": m.get("range").cloned().unwrap_or(Value::Null),
+    })
+}
+
+fn duplicate_locations(m: &Value) -> &[Value] {
+    m.get("locations").and_then(|v| v.as_array()).map_or(&[], Vec::as_slice)
+}
 
-        output_blocks.push(format!("{}\n{}", header, match_text));
+/// `file:start` or `file:start-end` with 1-indexed lines.
+fn location_label(m: &Value) -> String {
+    let file_path = m.get("file").and_then(|v| v.as_str()).unwrap_or("");
+    // lines are 0-indexed in JSON, convert to 1-indexed
+    let start_line = m.pointer("/range/start/line").and_then(|v| v.as_u64()).unwrap_or(0) + 1;
+    let end_line = m.pointer("/range/end/line").and_then(|v| v.as_u64()).unwrap_or(0) + 1;
+    if start_line == end_line {
+        format!("{}:{}", file_path, start_line)
+    } else {
+        format!("{}:{}-{}", file_path, start_line, end_line)
     }
+}
+
+/// Longest one-line preview shown per match in headers output, in characters.
+const PREVIEW_CHARS: usize = 80;
+
+/// Formats one compact line per match: `id file:start-end [rule] preview`, with `(xN)` after
+/// the location of collapsed duplicates.
+///
+/// The preview is the first line of the match text, cut at 80 characters; the rule id is only
+/// present for rule scans.
+pub fn format_match_headers(matches: &[Value]) -> String {
+    matches
+        .iter()
+        .map(|m| {
+            let id = m.get("id").and_then(|v| v.as_str()).unwrap_or("");
+            let mut line = format!("{} {}", id, location_label(m));
+            if let Some(count) = m.get("count").and_then(|v| v.as_u64()) {
+                line.push_str(&format!(" (x{})", count));
+            }
+            if let Some(rule_id) = m.get("ruleId").and_then(|v| v.as_str()) {
+                line.push_str(&format!(" [{}]", rule_id));
+            }
 
-    output_blocks.join("\n\n")
+            let first_line = m
+                .get("text")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .lines()
+                .next()
+                .unwrap_or("")
+                .trim();
+            let mut preview: String = first_line.chars().take(PREVIEW_CHARS).collect();
+            if first_line.chars().count() > PREVIEW_CHARS {
+                preview.push_str("...");
+            }
+            format!("{} {}", line, preview)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Builds a resource link (`file:///abs/path#L10-L25`) pointing at the region of a match.
+///
+/// Relative match paths are resolved against `project_folder`; lines in the fragment are
+/// 1-indexed like the text output headers.
+pub fn match_resource_link(m: &Value, project_folder: &Path) -> Option<RawResource> {
+    let file_path = m.get("file").and_then(|v| v.as_str())?;
+    let start_line = m.pointer("/range/start/line").and_then(|v| v.as_u64())? + 1;
+    let end_line = m.pointer("/range/end/line").and_then(|v| v.as_u64()).unwrap_or(start_line - 1) + 1;
+
+    let mut uri = url::Url::from_file_path(project_folder.join(file_path)).ok()?;
+    let (fragment, name) = if start_line == end_line {
+        (format!("L{}", start_line), format!("{}:{}", file_path, start_line))
+    } else {
+        (format!("L{}-L{}", start_line, end_line), format!("{}:{}-{}", file_path, start_line, end_line))
+    };
+    uri.set_fragment(Some(&fragment));
+
+    Some(RawResource {
+        uri: uri.to_string(),
+        name,
+        title: None,
+        description: None,
+        mime_type: None,
+        size: None,
+        icons: None,
+        meta: None,
+    })
 }
 
-#[allow(dead_code)]
+/// Built-in languages plus the `customLanguages` of the sgconfig at `config_path`, sorted.
+///
+/// The list is cached per config path and reused while the file's modification time and size
+/// stay the same, so callers may ask for it on every request without re-reading the config.
 pub fn get_supported_languages(config_path: Option<&Path>) -> Vec<String> {
+    type Cache = HashMap<PathBuf, (SystemTime, u64, Vec<String>)>;
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+    let Some(path) = config_path else {
+        return read_supported_languages(None);
+    };
+    let Some((modified, len)) = fs::metadata(path).ok().and_then(|m| Some((m.modified().ok()?, m.len()))) else {
+        return read_supported_languages(config_path);
+    };
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some((cached_modified, cached_len, languages)) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(path) {
+        if *cached_modified == modified && *cached_len == len {
+            return languages.clone();
+        }
+    }
+    let languages = read_supported_languages(config_path);
+    cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(path.to_path_buf(), (modified, len, languages.clone()));
+    languages
+}
+
+fn read_supported_languages(config_path: Option<&Path>) -> Vec<String> {
     let mut languages = vec![
         "bash", "c", "cpp", "csharp", "css", "elixir", "go", "haskell", "html", "java",
         "javascript", "json", "jsx", "kotlin", "lua", "nix", "php", "python", "ruby", "rust",
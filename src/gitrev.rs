@@ -0,0 +1,76 @@
+use crate::benchmark::{is_language_file, language_for_path};
+use crate::filter::DEFAULT_EXCLUDES;
+use std::path::{Component, Path};
+
+/// Most files `find_code_at_rev` reads out of a revision; narrow `paths` for more.
+pub const MAX_REV_FILES: usize = 20_000;
+
+/// A file of a git tree, as `git ls-tree -r -z --full-name` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeFile {
+    /// The blob's object id.
+    pub object: String,
+    /// The path from the repository root.
+    pub path: String,
+}
+
+/// The regular files of `git ls-tree -r -z` output; symlinks and submodules are left out, and so
+/// are paths with `..`, root or prefix components, which would lead out of the directory the
+/// files are written to.
+pub fn parse_ls_tree(stdout: &[u8]) -> Vec<TreeFile> {
+    stdout
+        .split(|&b| b == 0)
+        .filter_map(|entry| {
+            let entry = std::str::from_utf8(entry).ok()?;
+            let (info, path) = entry.split_once('\t')?;
+            match info.split(' ').collect::<Vec<_>>()[..] {
+                [mode, "blob", object] if mode != "120000" && is_plain_relative(path) => {
+                    Some(TreeFile { object: object.to_string(), path: path.to_string() })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn is_plain_relative(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Whether a file of a revision is worth searching: one of `language` (any language ast-grep knows
+/// when empty), outside the directories skipped by default.
+pub fn is_searchable(path: &str, language: &str) -> bool {
+    let path = Path::new(path);
+    let vendored = path
+        .parent()
+        .is_some_and(|dir| dir.iter().any(|name| DEFAULT_EXCLUDES.iter().any(|excluded| name == *excluded)));
+    let known = match language {
+        "" => language_for_path(path).is_some(),
+        language => is_language_file(path, language),
+    };
+    known && !vendored
+}
+
+/// The contents of the objects of `git cat-file --batch` output, in the order they were asked for;
+/// `None` for objects git reported missing.
+pub fn parse_cat_file_batch(mut stdout: &[u8]) -> Vec<Option<Vec<u8>>> {
+    let mut objects = Vec::new();
+    while let Some(newline) = stdout.iter().position(|&b| b == b'\n') {
+        let header = String::from_utf8_lossy(&stdout[..newline]);
+        stdout = &stdout[newline + 1..];
+        let size = match header.split(' ').collect::<Vec<_>>()[..] {
+            [_, _, size] => size.parse::<usize>().ok(),
+            _ => None,
+        };
+        match size {
+            Some(size) if size <= stdout.len() => {
+                objects.push(Some(stdout[..size].to_vec()));
+                // The contents are followed by a newline
+                stdout = stdout.get(size + 1..).unwrap_or_default();
+            }
+            Some(_) => break,
+            None => objects.push(None),
+        }
+    }
+    objects
+}
@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+/// A tree-sitter grammar `install_grammar` knows how to fetch without further arguments.
+#[derive(Debug, Clone, Copy)]
+pub struct KnownGrammar {
+    pub name: &'static str,
+    pub repository: &'static str,
+    /// Directory inside the repository holding `src/parser.c`, for repositories with several grammars.
+    pub subdirectory: Option<&'static str>,
+    pub extensions: &'static [&'static str],
+    /// Snippet that must parse without errors once the grammar is registered.
+    pub sample: &'static str,
+}
+
+/// Grammars for languages ast-grep does not bundle.
+pub const KNOWN_GRAMMARS: &[KnownGrammar] = &[
+    KnownGrammar {
+        name: "dart",
+        repository: "https://github.com/UserNobody14/tree-sitter-dart",
+        subdirectory: None,
+        extensions: &["dart"],
+        sample: "void main() {}",
+    },
+    KnownGrammar {
+        name: "elm",
+        repository: "https://github.com/elm-tooling/tree-sitter-elm",
+        subdirectory: None,
+        extensions: &["elm"],
+        sample: "x = 1",
+    },
+    KnownGrammar {
+        name: "julia",
+        repository: "https://github.com/tree-sitter/tree-sitter-julia",
+        subdirectory: None,
+        extensions: &["jl"],
+        sample: "x = 1",
+    },
+    KnownGrammar {
+        name: "ocaml",
+        repository: "https://github.com/tree-sitter/tree-sitter-ocaml",
+        subdirectory: Some("grammars/ocaml"),
+        extensions: &["ml"],
+        sample: "let x = 1",
+    },
+    KnownGrammar {
+        name: "r",
+        repository: "https://github.com/r-lib/tree-sitter-r",
+        subdirectory: None,
+        extensions: &["r", "R"],
+        sample: "x <- 1",
+    },
+    KnownGrammar {
+        name: "toml",
+        repository: "https://github.com/tree-sitter-grammars/tree-sitter-toml",
+        subdirectory: None,
+        extensions: &["toml"],
+        sample: "a = 1",
+    },
+    KnownGrammar {
+        name: "zig",
+        repository: "https://github.com/tree-sitter-grammars/tree-sitter-zig",
+        subdirectory: None,
+        extensions: &["zig"],
+        sample: "const x = 1;",
+    },
+];
+
+/// Looks up a known grammar by name, ignoring case.
+pub fn known_grammar(name: &str) -> Option<&'static KnownGrammar> {
+    KNOWN_GRAMMARS.iter().find(|g| g.name.eq_ignore_ascii_case(name))
+}
+
+/// Where installed grammars go when `--grammar-dir` is not given: `$XDG_DATA_HOME` or
+/// `~/.local/share` (`%LOCALAPPDATA%` on Windows), under `ast-grep-mcp/grammars`.
+pub fn default_grammar_dir(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    Some(crate::config::data_dir(env)?.join("grammars"))
+}
+
+/// File name of the compiled parser library for `name` on this platform.
+pub fn library_file_name(name: &str) -> String {
+    let extension = if cfg!(target_os = "macos") {
+        "dylib"
+    } else if cfg!(windows) {
+        "dll"
+    } else {
+        "so"
+    };
+    format!("{}.{}", name, extension)
+}
+
+/// Command line that clones `repository` into `checkout`. Repositories starting with `-` are
+/// refused, as git would take them for an option such as `--upload-pack`.
+pub fn clone_command(repository: &str, checkout: &Path) -> Result<Vec<String>, String> {
+    if repository.starts_with('-') {
+        return Err(format!("Invalid repository '{}': must be a URL or path, not start with '-'", repository));
+    }
+    let args = ["git", "clone", "--depth", "1", "--", repository];
+    Ok(args.iter().map(|arg| arg.to_string()).chain([checkout.display().to_string()]).collect())
+}
+
+/// The directory of the clone `checkout` with the grammar: `subdirectory` of it, which must stay
+/// inside the clone once `..` and links are resolved, or the clone itself.
+pub fn grammar_source_dir(checkout: &Path, subdirectory: Option<&str>) -> Result<PathBuf, String> {
+    let Some(subdirectory) = subdirectory else {
+        return Ok(checkout.to_path_buf());
+    };
+    let invalid = || format!("Invalid subdirectory '{}': must be a directory inside the clone", subdirectory);
+    let checkout = checkout.canonicalize().map_err(|_| invalid())?;
+    let dir = checkout.join(subdirectory).canonicalize().map_err(|_| invalid())?;
+    if !dir.starts_with(&checkout) || !dir.is_dir() {
+        return Err(invalid());
+    }
+    Ok(dir)
+}
+
+/// Command line that compiles the grammar in `grammar_dir` into `output`.
+///
+/// Grammars generate `src/parser.c` and may add an external scanner in `src/scanner.c` or,
+/// in older grammars, `src/scanner.cc`; a C++ scanner switches the compiler to `cxx`.
+pub fn build_command(grammar_dir: &Path, output: &Path, cc: &str, cxx: &str) -> Vec<String> {
+    let src = grammar_dir.join("src");
+    let mut sources = vec![src.join("parser.c")];
+    let mut compiler = cc;
+    if src.join("scanner.c").is_file() {
+        sources.push(src.join("scanner.c"));
+    } else if src.join("scanner.cc").is_file() {
+        sources.push(src.join("scanner.cc"));
+        compiler = cxx;
+    }
+
+    let mut command = vec![compiler.to_string()];
+    if cfg!(target_os = "macos") {
+        command.push("-dynamiclib".to_string());
+    } else {
+        command.push("-shared".to_string());
+        command.push("-fPIC".to_string());
+    }
+    command.extend(["-O2".to_string(), "-I".to_string(), src.display().to_string()]);
+    command.extend(sources.iter().map(|p| p.display().to_string()));
+    command.extend(["-o".to_string(), output.display().to_string()]);
+    command
+}
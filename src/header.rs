@@ -0,0 +1,135 @@
+use crate::language::language_alias;
+
+/// How a language writes comments, for formatting and recognizing header comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommentStyle {
+    pub line: Option<&'static str>,
+    pub block: Option<(&'static str, &'static str)>,
+}
+
+/// Comment style of `language`, keyed like a rule's `language` field.
+pub fn comment_style(language: &str) -> Option<CommentStyle> {
+    let c_like = CommentStyle { line: Some("//"), block: Some(("/*", "*/")) };
+    let hash = CommentStyle { line: Some("#"), block: None };
+    let style = match language_alias(language).as_str() {
+        "c" | "cpp" | "csharp" | "go" | "java" | "javascript" | "kotlin" | "php" | "rust" | "scala" | "solidity"
+        | "swift" | "tsx" | "typescript" => c_like,
+        "python" | "ruby" | "bash" | "yaml" | "nix" | "elixir" => hash,
+        "lua" | "haskell" => CommentStyle { line: Some("--"), block: None },
+        "css" => CommentStyle { line: None, block: Some(("/*", "*/")) },
+        "html" => CommentStyle { line: None, block: Some(("<!--", "-->")) },
+        _ => return None,
+    };
+    Some(style)
+}
+
+/// `header` (plain text, one line per line) as a comment in `style`, ending with a newline.
+pub fn format_header(header: &str, style: CommentStyle) -> String {
+    let lines: Vec<&str> = header.trim_end().lines().collect();
+    match (style.line, style.block) {
+        (Some(prefix), _) => lines
+            .iter()
+            .map(|line| match line.trim_end() {
+                "" => format!("{}\n", prefix),
+                line => format!("{} {}\n", prefix, line),
+            })
+            .collect(),
+        (None, Some((open, close))) => {
+            let body: Vec<String> = lines.iter().map(|line| format!("  {}", line).trim_end().to_string()).collect();
+            format!("{}\n{}\n{}\n", open, body.join("\n"), close)
+        }
+        (None, None) => String::new(),
+    }
+}
+
+/// Byte length of the lines that must stay at the top of a file: a byte order mark, a shebang,
+/// an encoding pragma (which Python only honours on the first two lines), an XML declaration
+/// or a PHP opening tag.
+pub fn prologue_len(source: &str) -> usize {
+    let mut len = if source.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+    for index in 0..2 {
+        let rest = &source[len..];
+        let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let line = rest[..line_end].trim();
+        let keep = (index == 0 && (line.starts_with("#!") || line.starts_with("<?xml") || line.starts_with("<?php")))
+            || is_encoding_pragma(line);
+        if !keep {
+            break;
+        }
+        len += line_end;
+    }
+    len
+}
+
+fn is_encoding_pragma(line: &str) -> bool {
+    line.starts_with('#') && (line.contains("coding:") || line.contains("coding="))
+}
+
+/// Text of the comments at the top of `source` (after the prologue), with comment markers
+/// stripped and whitespace collapsed.
+fn leading_comment_text(source: &str, style: CommentStyle) -> String {
+    let mut words = Vec::new();
+    let mut in_block: Option<&str> = None;
+    for line in source[prologue_len(source)..].lines() {
+        let mut line = line.trim();
+        if let Some(close) = in_block {
+            if let Some(end) = line.find(close) {
+                line = &line[..end];
+                in_block = None;
+            }
+            words.extend(line.trim_start_matches('*').split_whitespace());
+            continue;
+        }
+        if line.is_empty() {
+            if words.is_empty() {
+                continue;
+            }
+            break;
+        }
+        if let Some(prefix) = style.line.filter(|prefix| line.starts_with(prefix)) {
+            // Doc-comment variants such as `///`, `//!` or `##` extend the marker
+            let marker = prefix.chars().next().unwrap_or_default();
+            words.extend(line.trim_start_matches(marker).trim_start_matches('!').split_whitespace());
+            continue;
+        }
+        if let Some((open, close)) = style.block.filter(|(open, _)| line.starts_with(open)) {
+            let text = &line[open.len()..];
+            match text.find(close) {
+                Some(end) => words.extend(text[..end].split_whitespace()),
+                None => {
+                    words.extend(text.split_whitespace());
+                    in_block = Some(close);
+                }
+            }
+            continue;
+        }
+        break;
+    }
+    words.join(" ")
+}
+
+/// Whether the comments at the top of `source` contain `header`, ignoring comment markers
+/// and whitespace differences.
+pub fn has_header(source: &str, header: &str, style: CommentStyle) -> bool {
+    let wanted: Vec<&str> = header.split_whitespace().collect();
+    wanted.is_empty() || leading_comment_text(source, style).contains(&wanted.join(" "))
+}
+
+/// `source` with the formatted header inserted after its prologue and separated from the
+/// code by a blank line. CRLF line endings are kept.
+pub fn insert_header(source: &str, header: &str, style: CommentStyle) -> String {
+    let split = prologue_len(source);
+    let (prologue, rest) = source.split_at(split);
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+
+    let mut result = prologue.to_string();
+    if !prologue.is_empty() && !prologue.ends_with('\n') {
+        result.push_str(newline);
+    }
+    result.push_str(&format_header(header, style).replace('\n', newline));
+    if !rest.is_empty() && !rest.starts_with(newline) {
+        result.push_str(newline);
+    }
+    result.push_str(rest);
+    result
+}
@@ -0,0 +1,254 @@
+use crate::auth::{AuthError, Authenticator};
+use crate::config::Config;
+use crate::server::AstGrepServer;
+use crate::session::SessionRegistry;
+use crate::tls::{server_config, TlsListener};
+use anyhow::Result;
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use rmcp::transport::{StreamableHttpServerConfig, StreamableHttpService};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Path of the MCP endpoint.
+pub const MCP_PATH: &str = "/mcp";
+
+/// Path of the OAuth protected resource metadata (RFC 9728), served when OAuth is configured.
+pub const RESOURCE_METADATA_PATH: &str = "/.well-known/oauth-protected-resource";
+
+/// How long a session may go without requests before it is closed, for clients that disappear
+/// without ending their session.
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Serves MCP over Streamable HTTP (responses streamed as Server-Sent Events) on
+/// `http://<listen>:<port>/mcp` (`https://` with TLS), with a separate server, and so separate
+/// state, per session.
+pub async fn serve(config: Config) -> Result<()> {
+    let sessions = Arc::new(SessionRegistry::new(config.quotas.clone()));
+    let mut session_manager = LocalSessionManager::default();
+    session_manager.session_config.keep_alive = Some(SESSION_IDLE_TIMEOUT);
+
+    let endpoint = Endpoint {
+        authenticator: Arc::new(Authenticator::new(config.auth.clone())),
+        path: MCP_PATH,
+        secure: config.tls.is_some(),
+    };
+    let tls = config.tls.as_ref().map(|files| server_config(&files.cert, &files.key)).transpose()?;
+    let listener = tokio::net::TcpListener::bind((config.listen, config.port)).await?;
+    let address = listener.local_addr()?;
+    let per_session = config.clone();
+    let service = StreamableHttpService::new(
+        move || Ok(AstGrepServer::with_sessions(per_session.clone(), sessions.clone())),
+        Arc::new(session_manager),
+        StreamableHttpServerConfig::default(),
+    );
+    let router = protect(axum::Router::new().nest_service(MCP_PATH, service), endpoint, &config)?;
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    eprintln!("Server listening on {}://{}{}", scheme, address, MCP_PATH);
+    serve_router(listener, router, tls).await
+}
+
+/// Serves `router` on `listener`, over TLS with `tls`.
+pub(crate) async fn serve_router(
+    listener: tokio::net::TcpListener,
+    router: axum::Router,
+    tls: Option<Arc<rustls::ServerConfig>>,
+) -> Result<()> {
+    match tls {
+        Some(tls) => axum::serve(TlsListener::new(listener, tls), router).await?,
+        None => {
+            if listener.local_addr().is_ok_and(|address| !address.ip().is_loopback()) {
+                tracing::warn!("serving plain HTTP beyond localhost; use --tls-cert and --tls-key for HTTPS");
+            }
+            axum::serve(listener, router).await?
+        }
+    }
+    Ok(())
+}
+
+/// An MCP endpoint of the server, as its authentication sees it.
+#[derive(Clone)]
+pub(crate) struct Endpoint {
+    pub authenticator: Arc<Authenticator>,
+    /// Path of the endpoint, the resource of the protected resource metadata.
+    pub path: &'static str,
+    /// Whether it is served over TLS.
+    pub secure: bool,
+}
+
+/// `router` behind the configured authentication, with the protected resource metadata describing
+/// `endpoint` when OAuth is configured, CORS for the configured origins, and outermost the check of
+/// the `Origin` and `Host` headers (see [`check_origin`]).
+pub(crate) fn protect(mut router: axum::Router, endpoint: Endpoint, config: &Config) -> Result<axum::Router> {
+    let auth = endpoint.authenticator.config();
+    if auth.is_enabled() {
+        router = router.layer(axum::middleware::from_fn_with_state(endpoint.clone(), authenticate));
+    }
+    if auth.oauth.is_some() {
+        router = router.route(RESOURCE_METADATA_PATH, axum::routing::get(resource_metadata).with_state(endpoint));
+    }
+    if let Some(cors) = cors_layer(&config.cors_origins)? {
+        router = router.layer(cors);
+    }
+    let origins = Arc::new(RequestOrigins {
+        cors_origins: config.cors_origins.clone(),
+        listen: config.listen,
+        allowed_hosts: config.allowed_hosts.clone(),
+    });
+    Ok(router.layer(axum::middleware::from_fn_with_state(origins, check_origin)))
+}
+
+/// What [`check_origin`] lets through.
+struct RequestOrigins {
+    cors_origins: Vec<String>,
+    listen: IpAddr,
+    allowed_hosts: Vec<String>,
+}
+
+/// Whether a request from a page of `origin` may be served. Browsers do not apply CORS to
+/// WebSockets, nor to every HTTP request (a form POST is sent before CORS has a say), so without
+/// this check any page could reach a local server: only clients that send no `Origin` (not
+/// browsers) and `allowed` origins (`*` for any) get through. An origin matching the `Host`
+/// header is not enough, since DNS rebinding gives an attacker's page exactly that.
+pub fn origin_allowed(origin: Option<&str>, allowed: &[String]) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    allowed.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+}
+
+/// Whether `host`, a request's `Host` header, names this server: `localhost`, a loopback address,
+/// the `listen` address (any address when listening on all interfaces) or one of the `allowed`
+/// host names, with any port. Other names are refused, so a page of an attacker's domain that
+/// resolves to this machine (DNS rebinding) cannot reach the server.
+pub fn host_allowed(host: Option<&str>, listen: IpAddr, allowed: &[String]) -> bool {
+    let Some(host) = host else {
+        return false;
+    };
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    match name.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback() || ip == listen || listen.is_unspecified(),
+        Err(_) => name.eq_ignore_ascii_case("localhost") || allowed.iter().any(|host| host.eq_ignore_ascii_case(name)),
+    }
+}
+
+/// Refuses, with a 403, requests from browser pages of origins that are not allowed and requests
+/// addressed to host names other than this server's (see [`origin_allowed`] and [`host_allowed`]).
+async fn check_origin(State(origins): State<Arc<RequestOrigins>>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let origin = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok());
+    // HTTP/2 requests carry the host in the URI instead
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| request.uri().authority().map(|authority| authority.as_str()));
+    if !origin_allowed(origin, &origins.cors_origins) {
+        tracing::warn!(origin = origin.unwrap_or_default(), "rejected a request from a disallowed origin");
+        return (StatusCode::FORBIDDEN, "Origin not allowed").into_response();
+    }
+    if !host_allowed(host, origins.listen, &origins.allowed_hosts) {
+        tracing::warn!(host = host.unwrap_or_default(), "rejected a request to an unknown host name");
+        return (StatusCode::FORBIDDEN, "Host not allowed").into_response();
+    }
+    next.run(request).await
+}
+
+/// CORS handling for browser-based clients from `origins` (`*` for any); `None` when no origin
+/// is allowed, so browsers only let same-origin pages call the server.
+pub fn cors_layer(origins: &[String]) -> Result<Option<CorsLayer>> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin))
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    let mcp_headers = [
+        HeaderName::from_static("mcp-session-id"),
+        HeaderName::from_static("mcp-protocol-version"),
+        HeaderName::from_static("last-event-id"),
+    ];
+    let mut allow_headers = vec![header::CONTENT_TYPE, header::ACCEPT, header::AUTHORIZATION];
+    allow_headers.extend(mcp_headers.iter().cloned());
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
+            .allow_headers(allow_headers)
+            .expose_headers([HeaderName::from_static("mcp-session-id")]),
+    ))
+}
+
+/// Base URL clients reach this server at, from the `Host` header of a request.
+fn base_url(headers: &HeaderMap, secure: bool) -> String {
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok()).unwrap_or("127.0.0.1");
+    format!("{}://{}", if secure { "https" } else { "http" }, host)
+}
+
+/// Lets only requests with a valid bearer token through, attaching their [`Grant`] so tool calls
+/// can check its scopes; others get a 401 pointing OAuth clients to the resource metadata.
+///
+/// [`Grant`]: crate::auth::Grant
+async fn authenticate(State(endpoint): State<Endpoint>, mut request: Request, next: Next) -> Response {
+    let authenticator = &endpoint.authenticator;
+    // CORS preflight requests carry no credentials
+    if request.method() == Method::OPTIONS {
+        return next.run(request).await;
+    }
+    let authorization = request.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    match authenticator.authenticate(authorization).await {
+        Ok(grant) => {
+            request.extensions_mut().insert(grant);
+            next.run(request).await
+        }
+        Err(e) => {
+            let status = match e {
+                AuthError::Jwks(_) => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::UNAUTHORIZED,
+            };
+            tracing::warn!(error = %e, "rejected unauthenticated request");
+            let mut challenge = "Bearer".to_string();
+            if authenticator.config().oauth.is_some() {
+                let metadata = format!("{}{}", base_url(request.headers(), endpoint.secure), RESOURCE_METADATA_PATH);
+                challenge.push_str(&format!(" resource_metadata=\"{}\"", metadata));
+            }
+            if let AuthError::Invalid(_) = e {
+                challenge.push_str(", error=\"invalid_token\"");
+            }
+            let mut response = (status, e.to_string()).into_response();
+            if let Ok(value) = HeaderValue::from_str(&challenge) {
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, value);
+            }
+            response
+        }
+    }
+}
+
+/// The OAuth protected resource metadata, telling clients which authorization server issues
+/// tokens for this server.
+async fn resource_metadata(State(endpoint): State<Endpoint>, headers: HeaderMap) -> Response {
+    let Some(oauth) = &endpoint.authenticator.config().oauth else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let metadata = serde_json::json!({
+        "resource": format!("{}{}", base_url(&headers, endpoint.secure), endpoint.path),
+        "authorization_servers": [oauth.issuer],
+        "bearer_methods_supported": ["header"],
+        "scopes_supported": [oauth.write_scope],
+    });
+    ([(header::CONTENT_TYPE, "application/json")], metadata.to_string()).into_response()
+}
@@ -0,0 +1,130 @@
+use crate::benchmark::language_for_path;
+use crate::shard::list_files;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Directories a starter sgconfig.yaml uses when the project has none of a kind, as `ast-grep new`
+/// lays them out.
+pub const DEFAULT_RULE_DIR: &str = "rules";
+pub const DEFAULT_TEST_DIR: &str = "rule-tests";
+pub const DEFAULT_UTIL_DIR: &str = "utils";
+
+/// What `init_sgconfig` found in a project, with directories relative to its root.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProjectSurvey {
+    /// Source files per built-in language, most files first.
+    pub languages: Vec<LanguageFiles>,
+    /// Directories holding rule files (`id`, `language` and `rule`), outermost only.
+    pub rule_dirs: Vec<String>,
+    /// Directories holding rule test files (`id` and `valid` or `invalid`).
+    pub test_dirs: Vec<String>,
+    /// Rule directories whose name says they hold utility rules.
+    pub util_dirs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LanguageFiles {
+    pub language: String,
+    pub files: usize,
+}
+
+/// Which kind of ast-grep file a YAML document is.
+fn yaml_kind(path: &Path) -> Option<&'static str> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let document = serde_yaml::Deserializer::from_str(&text).next()?;
+    let value = Value::deserialize(document).ok()?;
+    value.get("id")?;
+    if value.get("rule").is_some() && value.get("language").is_some() {
+        Some("rule")
+    } else if value.get("valid").is_some() || value.get("invalid").is_some() {
+        Some("test")
+    } else {
+        None
+    }
+}
+
+/// `dirs` without those inside another of them, since ast-grep searches rule directories recursively.
+fn outermost(dirs: BTreeSet<PathBuf>) -> Vec<String> {
+    let mut kept: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if !kept.iter().any(|outer| dir.starts_with(outer)) {
+            kept.push(dir);
+        }
+    }
+    kept.iter().map(|dir| dir.to_string_lossy().replace('\\', "/")).collect()
+}
+
+/// Surveys the files under `root` (hidden entries, dependency and build directories skipped).
+pub fn survey_project(root: &Path) -> ProjectSurvey {
+    let mut languages: BTreeMap<&str, usize> = BTreeMap::new();
+    let (mut rule_dirs, mut test_dirs, mut util_dirs) = (BTreeSet::new(), BTreeSet::new(), BTreeSet::new());
+    for file in list_files(root, false) {
+        let relative_dir = file.parent().and_then(|dir| dir.strip_prefix(root).ok()).map(Path::to_path_buf);
+        let is_yaml = file.extension().is_some_and(|ext| ext == "yml" || ext == "yaml");
+        match (is_yaml.then(|| yaml_kind(&file)).flatten(), relative_dir) {
+            (Some(kind), Some(dir)) if !dir.as_os_str().is_empty() => {
+                let name = dir.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+                match kind {
+                    "test" => test_dirs.insert(dir),
+                    _ if name.contains("util") => util_dirs.insert(dir),
+                    _ => rule_dirs.insert(dir),
+                };
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(language) = language_for_path(&file) {
+            *languages.entry(language).or_default() += 1;
+        }
+    }
+
+    let mut languages: Vec<LanguageFiles> = languages
+        .into_iter()
+        .map(|(language, files)| LanguageFiles { language: language.to_string(), files })
+        .collect();
+    languages.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.language.cmp(&b.language)));
+    // Utility rules inside a rule directory are found through it already
+    let rule_dirs = outermost(rule_dirs);
+    let util_dirs = outermost(util_dirs)
+        .into_iter()
+        .filter(|dir| !rule_dirs.iter().any(|rules| Path::new(dir).starts_with(rules)))
+        .collect();
+    ProjectSurvey { languages, rule_dirs, test_dirs: outermost(test_dirs), util_dirs }
+}
+
+/// A starter sgconfig.yaml for a project surveyed as `survey`: its rule, test and util directories,
+/// or the defaults where it has none. Returns the config text and the directories it names.
+pub fn starter_config(survey: &ProjectSurvey) -> (String, Vec<String>) {
+    let or_default = |dirs: &[String], default: &str| {
+        if dirs.is_empty() {
+            vec![default.to_string()]
+        } else {
+            dirs.to_vec()
+        }
+    };
+    let rule_dirs = or_default(&survey.rule_dirs, DEFAULT_RULE_DIR);
+    let test_dirs = or_default(&survey.test_dirs, DEFAULT_TEST_DIR);
+    let util_dirs = or_default(&survey.util_dirs, DEFAULT_UTIL_DIR);
+
+    let mut config = Mapping::new();
+    config.insert("ruleDirs".into(), rule_dirs.iter().map(|dir| Value::from(dir.as_str())).collect());
+    let tests: Vec<Value> = test_dirs
+        .iter()
+        .map(|dir| Value::Mapping(Mapping::from_iter([("testDir".into(), Value::from(dir.as_str()))])))
+        .collect();
+    config.insert("testConfigs".into(), Value::Sequence(tests));
+    config.insert("utilDirs".into(), util_dirs.iter().map(|dir| Value::from(dir.as_str())).collect());
+
+    let mut text =
+        String::from("# Starter ast-grep project config, see https://ast-grep.github.io/reference/sgconfig.html\n");
+    if !survey.languages.is_empty() {
+        let languages: Vec<String> =
+            survey.languages.iter().map(|l| format!("{} {}", l.language, l.files)).collect();
+        text.push_str(&format!("# Source files per language: {}\n", languages.join(", ")));
+    }
+    text.push_str(&serde_yaml::to_string(&Value::Mapping(config)).unwrap_or_default());
+    let dirs = rule_dirs.into_iter().chain(test_dirs).chain(util_dirs).collect();
+    (text, dirs)
+}
@@ -0,0 +1,86 @@
+use crate::benchmark::language_extensions;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// `yaml` with `utils` added to the local `utils` of each of its documents, so that `matches:`
+/// can refer to them in an inline rule. A utility is a rule object, or a YAML string of one.
+/// Without utils, `yaml` is returned unchanged; a utility also defined by a document is an error.
+pub fn with_utils(yaml: &str, utils: &BTreeMap<String, Value>) -> Result<String, String> {
+    if utils.is_empty() {
+        return Ok(yaml.to_string());
+    }
+    let mut rules = BTreeMap::new();
+    for (name, rule) in utils {
+        let rule: serde_yaml::Value = match rule {
+            Value::String(text) => serde_yaml::from_str(text).map_err(|e| format!("Invalid utils.{}: {}", name, e))?,
+            rule => serde_yaml::to_value(rule).map_err(|e| e.to_string())?,
+        };
+        if !rule.is_mapping() {
+            return Err(format!(
+                "Invalid utils.{}: a utility must be a rule object, e.g. {{kind: call_expression}}",
+                name
+            ));
+        }
+        rules.insert(name.clone(), rule);
+    }
+
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(yaml) {
+        let mut document =
+            serde_yaml::Value::deserialize(document).map_err(|e| format!("Invalid rule YAML: {}", e))?;
+        let Some(root) = document.as_mapping_mut() else {
+            return Err("Invalid rule YAML: each document must be a mapping".to_string());
+        };
+        let local = root.entry("utils".into()).or_insert_with(|| serde_yaml::Mapping::new().into());
+        if local.is_null() {
+            *local = serde_yaml::Mapping::new().into();
+        }
+        let Some(local) = local.as_mapping_mut() else {
+            return Err("Invalid rule YAML: utils must be a mapping".to_string());
+        };
+        for (name, rule) in &rules {
+            if local.contains_key(name.as_str()) {
+                return Err(format!("Utility '{}' is defined both in the yaml and in utils", name));
+            }
+            local.insert(name.as_str().into(), rule.clone());
+        }
+        documents.push(serde_yaml::to_string(&document).map_err(|e| e.to_string())?);
+    }
+    Ok(documents.join("---\n"))
+}
+
+/// `yaml` with `language` set on each of its documents that has none. A document with another
+/// language (aliases such as `ts` for `typescript` count as the same) is an error.
+pub fn with_language(yaml: &str, language: &str) -> Result<String, String> {
+    let same = |other: &str| {
+        other.eq_ignore_ascii_case(language)
+            || language_extensions(other).is_some_and(|extensions| language_extensions(language) == Some(extensions))
+    };
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(yaml) {
+        let mut document =
+            serde_yaml::Value::deserialize(document).map_err(|e| format!("Invalid rule YAML: {}", e))?;
+        let Some(root) = document.as_mapping_mut() else {
+            return Err("Invalid rule YAML: each document must be a mapping".to_string());
+        };
+        match root.get("language").and_then(serde_yaml::Value::as_str) {
+            Some(other) if !same(other) => {
+                return Err(format!("The rule is for {} but language is {}", other, language));
+            }
+            Some(_) => {}
+            None => {
+                root.insert("language".into(), language.into());
+            }
+        }
+        documents.push(serde_yaml::to_string(&document).map_err(|e| e.to_string())?);
+    }
+    Ok(documents.join("---\n"))
+}
+
+/// Whether some document of `yaml` has a `fix`.
+pub fn has_fix(yaml: &str) -> bool {
+    serde_yaml::Deserializer::from_str(yaml)
+        .filter_map(|document| serde_yaml::Value::deserialize(document).ok())
+        .any(|document| document.get("fix").is_some_and(|fix| !fix.is_null()))
+}
@@ -0,0 +1,58 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Flags that make ast-grep print its `--inspect` summary to stderr.
+pub fn inspect_args(profile: bool) -> Vec<String> {
+    if !profile {
+        return Vec::new();
+    }
+    vec!["--inspect".to_string(), "summary".to_string()]
+}
+
+/// Adds the numeric counters from ast-grep's `--inspect summary` lines in `stderr` to `counters`.
+///
+/// A line such as `sg: summary|file: scannedFileCount=12,skippedFileCount=3` yields
+/// `file.scannedFileCount` and `file.skippedFileCount`. Counters from several invocations
+/// (folders or shards) are summed.
+pub fn add_inspect_counters(stderr: &str, counters: &mut BTreeMap<String, u64>) {
+    for line in stderr.lines() {
+        let Some(summary) = line.trim().strip_prefix("sg: summary|") else {
+            continue;
+        };
+        let Some((topic, fields)) = summary.split_once(':') else {
+            continue;
+        };
+        for field in fields.split(',') {
+            let Some((name, value)) = field.trim().split_once('=') else {
+                continue;
+            };
+            if let Ok(value) = value.trim().parse::<u64>() {
+                *counters.entry(format!("{}.{}", topic.trim(), name.trim())).or_default() += value;
+            }
+        }
+    }
+}
+
+/// Where the time of a profiled find call went.
+///
+/// ast-grep does not report its own phases, so the split is between running ast-grep (file
+/// discovery, parsing and matching together) and this server's post-processing, next to the
+/// counters ast-grep does report.
+#[derive(Debug, Default, Serialize)]
+pub struct ScanProfile {
+    pub scan_ms: f64,
+    pub postprocess_ms: f64,
+    pub invocations: usize,
+    pub counters: BTreeMap<String, u64>,
+}
+
+impl ScanProfile {
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("  ast-grep: {:.1} ms in {} invocation(s)", self.scan_ms, self.invocations),
+            format!("  post-processing: {:.1} ms", self.postprocess_ms),
+        ];
+        lines.extend(self.counters.iter().map(|(name, value)| format!("  {}: {}", name, value)));
+        format!("Profile:\n{}", lines.join("\n"))
+    }
+}
@@ -0,0 +1,62 @@
+use crate::suggest::DumpNode;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Largest source passed to ast-grep for a dump; the code travels as a command-line argument,
+/// which Linux caps at 128 KiB.
+pub const MAX_DUMP_SOURCE_BYTES: usize = 100_000;
+
+/// How often a node kind occurs in a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct KindCount {
+    pub kind: String,
+    pub count: usize,
+    /// Ranges of the first occurrences as `line:column-line:column`, 1-based.
+    pub examples: Vec<String>,
+}
+
+/// Counts the node kinds of a parsed tree, most frequent first (ties by kind), keeping up to
+/// `examples` ranges per kind in source order. Punctuation tokens are not counted.
+pub fn kind_frequencies(root: &DumpNode, examples: usize) -> Vec<KindCount> {
+    let mut counts: HashMap<&str, KindCount> = HashMap::new();
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        pending.extend(node.children.iter().rev());
+        if !node.kind.chars().any(char::is_alphanumeric) {
+            continue;
+        }
+        let entry = counts.entry(node.kind.as_str()).or_insert_with(|| KindCount {
+            kind: node.kind.clone(),
+            count: 0,
+            examples: Vec::new(),
+        });
+        entry.count += 1;
+        if entry.examples.len() < examples {
+            entry.examples.push(format!(
+                "{}:{}-{}:{}",
+                node.start.0 + 1,
+                node.start.1 + 1,
+                node.end.0 + 1,
+                node.end.1 + 1
+            ));
+        }
+    }
+    let mut kinds: Vec<KindCount> = counts.into_values().collect();
+    kinds.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.kind.cmp(&b.kind)));
+    kinds
+}
+
+/// The leading part of `source` that fits in a dump, cut at a line boundary.
+pub fn dump_prefix(source: &str) -> &str {
+    if source.len() <= MAX_DUMP_SOURCE_BYTES {
+        return source;
+    }
+    let mut end = MAX_DUMP_SOURCE_BYTES;
+    while !source.is_char_boundary(end) {
+        end -= 1;
+    }
+    match source[..end].rfind('\n') {
+        Some(newline) => &source[..newline + 1],
+        None => &source[..end],
+    }
+}
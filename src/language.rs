@@ -0,0 +1,25 @@
+/// The name `language` goes by in a rule's `language` field: the aliases ast-grep accepts (`py`,
+/// `ts`, `golang`, ...) become the language's name, and other names are lowercased. `tsx` is a
+/// language of its own, while JSX is part of `javascript`.
+pub fn language_alias(language: &str) -> String {
+    let language = language.to_lowercase();
+    let name = match language.as_str() {
+        "sh" => "bash",
+        "c++" | "cc" | "cxx" => "cpp",
+        "cs" | "c#" => "csharp",
+        "ex" => "elixir",
+        "golang" => "go",
+        "hs" => "haskell",
+        "htm" => "html",
+        "js" | "jsx" => "javascript",
+        "kt" => "kotlin",
+        "py" => "python",
+        "rb" => "ruby",
+        "rs" => "rust",
+        "sol" => "solidity",
+        "ts" => "typescript",
+        "yml" => "yaml",
+        _ => return language,
+    };
+    name.to_string()
+}
@@ -1,4 +1,72 @@
+pub mod aggregate;
+pub mod apply;
+pub mod approval;
+pub mod auth;
+pub mod backup;
+pub mod benchmark;
+pub mod call;
+pub mod callgraph;
+pub mod checkpoint;
+pub mod client;
 pub mod command;
 pub mod config;
+pub mod cooccur;
+pub mod diagnostics;
+pub mod dialect;
+pub mod diff;
+pub mod dump;
+pub mod effectiveness;
+pub mod encoding;
+pub mod engine;
+pub mod eol;
+pub mod explain;
+pub mod export;
+pub mod filter;
+pub mod fingerprint;
 pub mod format;
+pub mod gitrev;
+pub mod grammar;
+pub mod header;
+pub mod http;
+pub mod init;
+pub mod inline;
+pub mod inspect;
+pub mod kinds;
+pub mod language;
+pub mod lint;
+pub mod logging;
+pub mod manifest;
+pub mod metrics;
+pub mod missing;
+pub mod multi;
+pub mod outline;
+#[cfg(feature = "parsers")]
+pub mod parsers;
+pub mod playground;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod profiles;
+pub mod ranges;
+pub mod refine;
+pub mod relax;
+pub mod resources;
+pub mod rewrite;
+pub mod roots;
+pub mod rulesession;
+pub mod schema;
 pub mod server;
+pub mod session;
+pub mod sgconfig;
+pub mod shard;
+pub mod snapshot;
+pub mod stats;
+pub mod store;
+pub mod suggest;
+pub mod suppress;
+pub mod tempfiles;
+pub mod tls;
+pub mod todo;
+pub mod toolset;
+pub mod trend;
+pub mod watch;
+pub mod ws;
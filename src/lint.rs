@@ -0,0 +1,404 @@
+use crate::benchmark::first_rule_document;
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::BTreeSet;
+
+/// Top-level keys of an ast-grep rule file.
+const RULE_CONFIG_KEYS: &[&str] = &[
+    "id", "language", "rule", "constraints", "utils", "transform", "fix", "rewriters", "message", "note",
+    "severity", "labels", "files", "ignores", "url", "metadata",
+];
+
+const REQUIRED_KEYS: &[&str] = &["id", "language", "rule"];
+
+const RULE_KEYS: &[&str] = &[
+    "pattern", "kind", "regex", "nthChild", "range", "inside", "has", "precedes", "follows", "all", "any", "not",
+    "matches",
+];
+
+const RELATIONAL_KEYS: &[&str] = &["inside", "has", "precedes", "follows"];
+
+/// Keys a relational rule accepts on top of [`RULE_KEYS`].
+const RELATIONAL_ONLY_KEYS: &[&str] = &["stopBy", "field"];
+
+const PATTERN_OBJECT_KEYS: &[&str] = &["context", "selector", "strictness"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem found in a rule, located in the YAML source.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    /// 1-based line of the offending key.
+    pub line: usize,
+    /// 1-based column of the offending key.
+    pub column: usize,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub suggestion: String,
+}
+
+/// A pattern string in the rule, to be parsed by ast-grep.
+#[derive(Debug, Clone)]
+pub struct RulePattern {
+    pub pattern: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Positions of the block-style mapping keys of the first YAML document.
+///
+/// This is a line-based scan rather than a YAML parser: keys inside flow collections
+/// (`{...}`, `[...]`) are not recorded, and lookups fall back to the enclosing key.
+struct KeyPositions {
+    keys: Vec<(Vec<Segment>, usize, usize)>,
+}
+
+impl KeyPositions {
+    fn scan(yaml: &str) -> Self {
+        let mut keys = Vec::new();
+        let mut stack: Vec<(usize, Segment)> = Vec::new();
+        let mut block_scalar_indent: Option<usize> = None;
+        let mut seen_content = false;
+
+        for (number, raw) in yaml.lines().enumerate() {
+            let trimmed = raw.trim_start();
+            let mut column = raw.len() - trimmed.len();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(indent) = block_scalar_indent {
+                if column > indent {
+                    continue;
+                }
+                block_scalar_indent = None;
+            }
+            if trimmed.starts_with('#') {
+                continue;
+            }
+            if trimmed.starts_with("---") {
+                if seen_content {
+                    break;
+                }
+                continue;
+            }
+            seen_content = true;
+
+            let mut content = trimmed;
+            while content == "-" || content.starts_with("- ") {
+                while stack.last().is_some_and(|(indent, _)| *indent > column) {
+                    stack.pop();
+                }
+                let index = match stack.last() {
+                    Some((indent, Segment::Index(i))) if *indent == column => {
+                        let next = i + 1;
+                        stack.pop();
+                        next
+                    }
+                    _ => 0,
+                };
+                stack.push((column, Segment::Index(index)));
+                let rest = content[1..].trim_start();
+                column += content.len() - rest.len();
+                content = rest;
+            }
+
+            let Some(key) = mapping_key(content) else {
+                continue;
+            };
+            while stack.last().is_some_and(|(indent, _)| *indent >= column) {
+                stack.pop();
+            }
+            let mut path: Vec<Segment> = stack.iter().map(|(_, segment)| segment.clone()).collect();
+            path.push(Segment::Key(key.to_string()));
+            keys.push((path, number + 1, column + 1));
+            stack.push((column, Segment::Key(key.to_string())));
+
+            let value = content[key.len()..].trim_start()[1..].trim_start();
+            if value.starts_with('|') || value.starts_with('>') {
+                block_scalar_indent = Some(column);
+            }
+        }
+        Self { keys }
+    }
+
+    /// Position of `path`, or of its closest recorded ancestor.
+    fn locate(&self, path: &[Segment]) -> (usize, usize) {
+        for len in (1..=path.len()).rev() {
+            if let Some((_, line, column)) = self.keys.iter().find(|(p, _, _)| p.as_slice() == &path[..len]) {
+                return (*line, *column);
+            }
+        }
+        (1, 1)
+    }
+}
+
+/// The key of a `key: value` line, for plain keys.
+fn mapping_key(content: &str) -> Option<&str> {
+    let end = content.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '$'))?;
+    let rest = content[end..].trim_start();
+    let is_key = end > 0 && rest.starts_with(':') && rest[1..].chars().next().is_none_or(char::is_whitespace);
+    is_key.then(|| &content[..end])
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The key of `known` closest to a misspelled `key`, if one is within two edits of it.
+pub fn closest_key<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (edit_distance(&key.to_lowercase(), &k.to_lowercase()), *k))
+        .min()
+        .filter(|(distance, _)| *distance <= 2)
+        .map(|(_, k)| k)
+}
+
+fn unknown_key_suggestion(key: &str, known: &[&str]) -> String {
+    match closest_key(key, known) {
+        Some(k) => format!("Did you mean `{}`?", k),
+        None => format!("Remove it; valid keys here are: {}.", known.join(", ")),
+    }
+}
+
+struct Linter<'a> {
+    positions: &'a KeyPositions,
+    diagnostics: Vec<Diagnostic>,
+    patterns: Vec<RulePattern>,
+    referenced_utils: BTreeSet<String>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, path: &[Segment], severity: Severity, code: &'static str, message: String, suggestion: String) {
+        let (line, column) = self.positions.locate(path);
+        self.diagnostics.push(Diagnostic { line, column, severity, code, message, suggestion });
+    }
+
+    fn check_keys(&mut self, map: &serde_yaml::Mapping, path: &[Segment], known: &[&str], what: &str) {
+        for key in map.keys().filter_map(Value::as_str) {
+            if !known.contains(&key) {
+                let key_path = child(path, Segment::Key(key.to_string()));
+                let suggestion = unknown_key_suggestion(key, known);
+                self.report(&key_path, Severity::Error, "unknown-key", format!("Unknown {} key `{}`", what, key), suggestion);
+            }
+        }
+    }
+
+    fn lint_rule(&mut self, rule: &Value, path: &[Segment], relational: bool) {
+        let Some(map) = rule.as_mapping() else {
+            return;
+        };
+        let known: Vec<&str> = if relational {
+            RULE_KEYS.iter().chain(RELATIONAL_ONLY_KEYS).copied().collect()
+        } else {
+            RULE_KEYS.to_vec()
+        };
+        self.check_keys(map, path, &known, "rule");
+
+        for (key, value) in map {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            let key_path = child(path, Segment::Key(key.to_string()));
+            match key {
+                "pattern" => self.lint_pattern(value, &key_path),
+                "matches" => {
+                    if let Some(name) = value.as_str() {
+                        self.referenced_utils.insert(name.to_string());
+                    }
+                }
+                "all" | "any" => {
+                    for (i, item) in value.as_sequence().into_iter().flatten().enumerate() {
+                        self.lint_rule(item, &child(&key_path, Segment::Index(i)), false);
+                    }
+                }
+                "not" => self.lint_rule(value, &key_path, false),
+                _ if RELATIONAL_KEYS.contains(&key) => {
+                    if value.is_mapping() && value.get("stopBy").is_none() {
+                        let reach = match key {
+                            "inside" => "the direct parent",
+                            "has" => "the direct children",
+                            _ => "the adjacent sibling",
+                        };
+                        self.report(
+                            &key_path,
+                            Severity::Warning,
+                            "relational-without-stop-by",
+                            format!("`{}` without `stopBy` only checks {}", key, reach),
+                            "Add `stopBy: end` to search all the way, unless the immediate neighbour is intended."
+                                .to_string(),
+                        );
+                    }
+                    if let Some(stop_by) = value.get("stopBy").filter(|v| v.is_mapping()) {
+                        self.lint_rule(stop_by, &child(&key_path, Segment::Key("stopBy".to_string())), false);
+                    }
+                    self.lint_rule(value, &key_path, true);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn lint_pattern(&mut self, pattern: &Value, path: &[Segment]) {
+        if let Some(map) = pattern.as_mapping() {
+            self.check_keys(map, path, PATTERN_OBJECT_KEYS, "pattern");
+            if let Some(context) = pattern.get("context") {
+                self.lint_pattern(context, &child(path, Segment::Key("context".to_string())));
+            }
+        } else if let Some(text) = pattern.as_str() {
+            let (line, column) = self.positions.locate(path);
+            self.patterns.push(RulePattern { pattern: text.to_string(), line, column });
+        }
+    }
+}
+
+fn child(path: &[Segment], segment: Segment) -> Vec<Segment> {
+    let mut path = path.to_vec();
+    path.push(segment);
+    path
+}
+
+fn key(name: &str) -> Vec<Segment> {
+    vec![Segment::Key(name.to_string())]
+}
+
+/// Checks an inline rule (the first document when there are several) for common mistakes that
+/// ast-grep accepts silently or reports without a location.
+///
+/// Returns the diagnostics, sorted by position, and the pattern strings found, which only
+/// ast-grep can tell apart from broken code.
+pub fn lint_rule(yaml: &str) -> (Vec<Diagnostic>, Vec<RulePattern>) {
+    let rule = match first_rule_document(yaml) {
+        Ok(rule) => rule,
+        Err(e) => {
+            let (line, column) = e.location().map_or((1, 1), |l| (l.line(), l.column()));
+            let diagnostic = Diagnostic {
+                line,
+                column,
+                severity: Severity::Error,
+                code: "yaml-syntax",
+                message: e.to_string(),
+                suggestion: "Fix the YAML syntax; check indentation and quote patterns containing `:` or `#`.".to_string(),
+            };
+            return (vec![diagnostic], Vec::new());
+        }
+    };
+
+    let positions = KeyPositions::scan(yaml);
+    let mut linter = Linter {
+        positions: &positions,
+        diagnostics: Vec::new(),
+        patterns: Vec::new(),
+        referenced_utils: BTreeSet::new(),
+    };
+    let Some(root) = rule.as_mapping() else {
+        linter.report(
+            &[],
+            Severity::Error,
+            "not-a-mapping",
+            "The rule must be a YAML mapping".to_string(),
+            format!("Start from the required keys: {}.", REQUIRED_KEYS.join(", ")),
+        );
+        return (linter.diagnostics, Vec::new());
+    };
+
+    linter.check_keys(root, &[], RULE_CONFIG_KEYS, "top-level");
+    for required in REQUIRED_KEYS {
+        if rule.get(required).is_none() {
+            linter.report(
+                &[],
+                Severity::Error,
+                "missing-key",
+                format!("Missing required key `{}`", required),
+                format!("Add a top-level `{}:` entry.", required),
+            );
+        }
+    }
+
+    if let Some(value) = rule.get("rule") {
+        linter.lint_rule(value, &key("rule"), false);
+    }
+    for section in ["utils", "constraints"] {
+        for (name, value) in rule.get(section).and_then(Value::as_mapping).into_iter().flatten() {
+            let Some(name) = name.as_str() else {
+                continue;
+            };
+            linter.lint_rule(value, &child(&key(section), Segment::Key(name.to_string())), false);
+        }
+    }
+    for (i, rewriter) in rule.get("rewriters").and_then(Value::as_sequence).into_iter().flatten().enumerate() {
+        if let Some(value) = rewriter.get("rule") {
+            let path = [key("rewriters"), vec![Segment::Index(i), Segment::Key("rule".to_string())]].concat();
+            linter.lint_rule(value, &path, false);
+        }
+    }
+
+    for name in rule.get("utils").and_then(Value::as_mapping).into_iter().flatten().filter_map(|(k, _)| k.as_str()) {
+        if !linter.referenced_utils.contains(name) {
+            linter.report(
+                &child(&key("utils"), Segment::Key(name.to_string())),
+                Severity::Warning,
+                "unused-util",
+                format!("Utility rule `{}` is never referenced", name),
+                format!("Use it with `matches: {}`, or remove it.", name),
+            );
+        }
+    }
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    (diagnostics, linter.patterns)
+}
+
+/// Diagnostic for a pattern that ast-grep parsed into ERROR or MISSING nodes.
+pub fn pattern_error(pattern: &RulePattern) -> Diagnostic {
+    Diagnostic {
+        line: pattern.line,
+        column: pattern.column,
+        severity: Severity::Error,
+        code: "pattern-parse-error",
+        message: format!("Pattern `{}` does not parse as valid code", pattern.pattern),
+        suggestion: "Make the pattern a complete snippet, or use `pattern: { context: ..., selector: ... }` \
+            to parse a fragment inside surrounding code."
+            .to_string(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LintReport {
+    /// False when any diagnostic is an error.
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LintReport {
+    pub fn new(mut diagnostics: Vec<Diagnostic>) -> Self {
+        diagnostics.sort_by_key(|d| (d.line, d.column));
+        Self {
+            valid: diagnostics.iter().all(|d| d.severity != Severity::Error),
+            diagnostics,
+        }
+    }
+}
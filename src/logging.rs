@@ -0,0 +1,161 @@
+use rmcp::model::{LoggingLevel, LoggingMessageNotificationParam};
+use rmcp::{Peer, RoleServer};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Span field naming the session the events inside the span belong to.
+pub const SESSION_FIELD: &str = "session_id";
+
+/// Target prefix of this crate's events, the only ones forwarded to clients.
+pub const LOG_TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// Level a client gets messages at until it sends `logging/setLevel`.
+pub const DEFAULT_CLIENT_LEVEL: LoggingLevel = LoggingLevel::Info;
+
+/// Messages queued per client; when a client reads them slower than they are produced, the
+/// excess is dropped rather than buffered without bound.
+const CLIENT_LOG_BUFFER: usize = 256;
+
+struct ClientSink {
+    level: LoggingLevel,
+    sender: mpsc::Sender<LoggingMessageNotificationParam>,
+}
+
+fn sinks() -> MutexGuard<'static, HashMap<String, ClientSink>> {
+    static SINKS: OnceLock<Mutex<HashMap<String, ClientSink>>> = OnceLock::new();
+    SINKS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Severity of `level`, for comparing levels.
+fn severity(level: LoggingLevel) -> u8 {
+    level as u8
+}
+
+/// The MCP level of a tracing level.
+pub fn logging_level(level: &Level) -> LoggingLevel {
+    match *level {
+        Level::ERROR => LoggingLevel::Error,
+        Level::WARN => LoggingLevel::Warning,
+        Level::INFO => LoggingLevel::Info,
+        _ => LoggingLevel::Debug,
+    }
+}
+
+/// Starts forwarding the log events of session `session_id` to its client `peer`, until
+/// [`unregister_client`] is called.
+pub fn register_client(session_id: &str, peer: Peer<RoleServer>) {
+    let (sender, mut receiver) = mpsc::channel(CLIENT_LOG_BUFFER);
+    sinks().insert(session_id.to_string(), ClientSink { level: DEFAULT_CLIENT_LEVEL, sender });
+    tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if peer.notify_logging_message(message).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Sets the lowest level session `session_id` gets messages at, as asked by `logging/setLevel`.
+pub fn set_client_level(session_id: &str, level: LoggingLevel) {
+    if let Some(sink) = sinks().get_mut(session_id) {
+        sink.level = level;
+    }
+}
+
+/// Stops forwarding log events to the client of session `session_id`.
+pub fn unregister_client(session_id: &str) {
+    sinks().remove(session_id);
+}
+
+/// Collects the fields of an event or span as JSON.
+#[derive(Default)]
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::String(value.to_string()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::Bool(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), Value::String(format!("{:?}", value)));
+    }
+}
+
+/// Fields of a span, kept in its extensions for the events inside it.
+struct SpanFields(Map<String, Value>);
+
+/// A tracing layer that forwards this crate's events to the MCP client of the session they
+/// happen in, as `notifications/message`, so problems show up in the client and not only in the
+/// server's stderr.
+///
+/// An event belongs to the session named by the `session_id` field of its innermost span that
+/// has one; events outside any session (e.g. while starting up) only go to stderr. The fields
+/// of the event's spans (such as `request_id` and `tool`) are sent along with its own.
+pub struct ClientLogLayer;
+
+impl<S> Layer<S> for ClientLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = JsonFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.0));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !metadata.target().starts_with(LOG_TARGET) {
+            return;
+        }
+        let mut data = Map::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    data.extend(fields.clone());
+                }
+            }
+        }
+        let Some(Value::String(session_id)) = data.remove(SESSION_FIELD) else {
+            return;
+        };
+
+        let level = logging_level(metadata.level());
+        let sinks = sinks();
+        let Some(sink) = sinks.get(&session_id).filter(|sink| severity(level) >= severity(sink.level)) else {
+            return;
+        };
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        data.extend(fields.0);
+        // A full queue means the client is not keeping up; drop the message instead of blocking
+        let _ = sink.sender.try_send(LoggingMessageNotificationParam {
+            level,
+            logger: Some(metadata.target().to_string()),
+            data: Value::Object(data),
+        });
+    }
+}
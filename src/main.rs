@@ -1,19 +1,58 @@
-use ast_grep_mcp::config::{Config, TransportType};
+use ast_grep_mcp::call::{call_tool, render_result};
+use ast_grep_mcp::config::{Cli, Command, Config, TransportType};
+use ast_grep_mcp::logging::{ClientLogLayer, LOG_TARGET};
 use ast_grep_mcp::server::AstGrepServer;
+use clap::{CommandFactory, Parser};
 use rmcp::transport::stdio;
 use rmcp::ServiceExt;
 use anyhow::Result;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // 1. Setup tracing (log to stderr only, never stdout — stdout is for MCP protocol)
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .with_writer(std::io::stderr)
-        .with_ansi(false)
+    // 1. Parse CLI args
+    let cli = Cli::parse();
+    if let Some(Command::Completions { shell }) = cli.command {
+        clap_complete::generate(shell, &mut Cli::command(), "ast-grep-mcp-server", &mut std::io::stdout());
+        return Ok(());
+    }
+
+    // 2. Setup tracing (log to stderr only, never stdout — stdout is for MCP protocol)
+    // --log-level wins over RUST_LOG; with neither set, warnings and errors are still shown.
+    let env_filter = match cli.log_level {
+        Some(level) => tracing_subscriber::EnvFilter::new(level.as_str()),
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+    };
+    // Clients get this crate's events down to debug, whatever stderr shows, at the level they ask for.
+    let client_filter = tracing_subscriber::filter::Targets::new()
+        .with_target(LOG_TARGET, tracing::Level::DEBUG);
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_ansi(false)
+                .with_filter(env_filter),
+        )
+        .with(ClientLogLayer.with_filter(client_filter))
         .init();
 
-    // 2. Setup signal handlers
+    let config = Config::from_args(&cli)?;
+
+    // 3. One-shot subcommands run without signal handling or a transport
+    if let Some(Command::Call(args)) = cli.command {
+        let result = call_tool(config, &args).await?;
+        let output = render_result(&result);
+        if result.is_error == Some(true) {
+            anyhow::bail!("{}", output);
+        }
+        println!("{}", output);
+        return Ok(());
+    }
+
+    // 4. Setup signal handlers
     #[cfg(unix)]
     {
         use tokio::signal::unix::{signal, SignalKind};
@@ -32,6 +71,9 @@ async fn main() -> Result<()> {
             let mut sigterm = signal(SignalKind::terminate()).unwrap();
             sigterm.recv().await;
             eprintln!("Received SIGTERM - shutting down gracefully");
+            // exit() skips destructors, so stop running ast-grep processes and remove temp files here
+            ast_grep_mcp::command::kill_all_children();
+            ast_grep_mcp::tempfiles::remove_all_session_dirs();
             std::process::exit(0);
         });
     }
@@ -48,24 +90,17 @@ async fn main() -> Result<()> {
         });
     }
 
-    // 3. Parse CLI args and build Config
-    let config = Config::from_cli()?;
-
-    // 4. Create server instance
-    let server = AstGrepServer::new(config.clone());
-
-    // 5. Start the server based on transport type
+    // 5. Start the server based on transport type; the HTTP and WebSocket transports create a server
+    //    per session
     match config.transport {
         TransportType::Stdio => {
+            let server = AstGrepServer::new(config.clone());
             let service = server.serve(stdio()).await.map_err(|e| anyhow::anyhow!("Error starting server: {}", e))?;
             eprintln!("Server started on stdio");
             service.waiting().await.map_err(|e| anyhow::anyhow!("Error waiting for service: {}", e))?;
         }
-        TransportType::Sse => {
-            // SSE transport requires an HTTP server (e.g., axum) which is not currently in dependencies.
-            // Documenting this limitation as per prompt instructions.
-            anyhow::bail!("SSE transport is not yet implemented in this Rust port. Please use --transport stdio (default).");
-        }
+        TransportType::Sse => ast_grep_mcp::http::serve(config).await?,
+        TransportType::Ws => ast_grep_mcp::ws::serve(config).await?,
     }
 
     Ok(())
@@ -0,0 +1,96 @@
+use crate::fingerprint::Fnv1a;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Table of the manifests of exported scans, one row per `scans` row. Kept apart from `scans` so
+/// databases written before manifests existed gain it with `CREATE TABLE IF NOT EXISTS`.
+pub const MANIFEST_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS manifests (
+    scan_id INTEGER PRIMARY KEY REFERENCES scans(id),
+    git_commit TEXT,
+    git_dirty INTEGER,
+    files_scanned INTEGER,
+    query_hash TEXT NOT NULL,
+    ast_grep_version TEXT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL
+);
+";
+
+/// What code state and query a set of findings refers to, so consumers of an export can tell
+/// whether it still describes the code in front of them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanManifest {
+    /// `HEAD` of the git repository around the project folder, when it is in one.
+    pub git_commit: Option<String>,
+    /// Whether the project folder had uncommitted changes (untracked files left out).
+    pub git_dirty: Option<bool>,
+    /// Files ast-grep searched, from its `--inspect summary`.
+    pub files_scanned: Option<u64>,
+    /// Hash of the pattern or rule and its language, to group findings of the same query.
+    pub query_hash: String,
+    pub ast_grep_version: Option<String>,
+    /// UTC times the scan started and finished, RFC 3339.
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// Short hash of a query (a pattern or YAML rule) and its language.
+pub fn query_hash(query: &str, language: &str) -> String {
+    let mut hash = Fnv1a::new();
+    hash.write(query.as_bytes());
+    hash.write(language.as_bytes());
+    format!("{:016x}", hash.finish())
+}
+
+/// Whether `git status --porcelain` output lists any change.
+pub fn is_dirty(porcelain: &str) -> bool {
+    porcelain.lines().any(|line| !line.trim().is_empty())
+}
+
+/// `time` as an RFC 3339 UTC timestamp with seconds, e.g. `2024-05-01T12:00:00Z`.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since the epoch, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Stores `manifest` for the scan `scan_id` of an export, creating the table when needed.
+pub fn record_manifest(conn: &Connection, scan_id: i64, manifest: &ScanManifest) -> rusqlite::Result<()> {
+    conn.execute_batch(MANIFEST_SCHEMA)?;
+    conn.execute(
+        "INSERT INTO manifests (scan_id, git_commit, git_dirty, files_scanned, query_hash, ast_grep_version,
+            started_at, finished_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            scan_id,
+            manifest.git_commit,
+            manifest.git_dirty,
+            manifest.files_scanned.map(|files| files as i64),
+            manifest.query_hash,
+            manifest.ast_grep_version,
+            manifest.started_at,
+            manifest.finished_at,
+        ],
+    )?;
+    Ok(())
+}
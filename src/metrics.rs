@@ -0,0 +1,261 @@
+use crate::language::language_alias;
+use crate::ranges::{match_position, Position};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub const FUNCTION_RULE_ID: &str = "metrics-function";
+pub const CLASS_RULE_ID: &str = "metrics-class";
+pub const NESTING_RULE_ID: &str = "metrics-nesting";
+
+/// Node kinds counted as functions, classes (or the language's closest equivalent) and
+/// nesting control-flow constructs.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricKinds {
+    pub functions: &'static [&'static str],
+    pub classes: &'static [&'static str],
+    pub nesting: &'static [&'static str],
+}
+
+const JS_KINDS: MetricKinds = MetricKinds {
+    functions: &[
+        "function_declaration",
+        "function_expression",
+        "arrow_function",
+        "method_definition",
+        "generator_function_declaration",
+    ],
+    classes: &["class_declaration", "class"],
+    nesting: &[
+        "if_statement",
+        "for_statement",
+        "for_in_statement",
+        "while_statement",
+        "do_statement",
+        "try_statement",
+        "switch_statement",
+    ],
+};
+
+/// Metric node kinds for `language`, keyed like a rule's `language` field.
+pub fn metric_kinds(language: &str) -> Option<MetricKinds> {
+    let kinds = match language_alias(language).as_str() {
+        "python" => MetricKinds {
+            functions: &["function_definition"],
+            classes: &["class_definition"],
+            nesting: &[
+                "if_statement",
+                "for_statement",
+                "while_statement",
+                "try_statement",
+                "with_statement",
+                "match_statement",
+            ],
+        },
+        "javascript" | "typescript" | "tsx" => JS_KINDS,
+        "rust" => MetricKinds {
+            functions: &["function_item", "closure_expression"],
+            classes: &["struct_item", "enum_item", "trait_item"],
+            nesting: &["if_expression", "for_expression", "while_expression", "loop_expression", "match_expression"],
+        },
+        "go" => MetricKinds {
+            functions: &["function_declaration", "method_declaration", "func_literal"],
+            classes: &["type_declaration"],
+            nesting: &[
+                "if_statement",
+                "for_statement",
+                "expression_switch_statement",
+                "type_switch_statement",
+                "select_statement",
+            ],
+        },
+        "java" => MetricKinds {
+            functions: &["method_declaration", "constructor_declaration", "lambda_expression"],
+            classes: &["class_declaration", "interface_declaration", "enum_declaration", "record_declaration"],
+            nesting: &[
+                "if_statement",
+                "for_statement",
+                "enhanced_for_statement",
+                "while_statement",
+                "do_statement",
+                "try_statement",
+                "switch_expression",
+            ],
+        },
+        "c" => MetricKinds {
+            functions: &["function_definition"],
+            classes: &["struct_specifier"],
+            nesting: &["if_statement", "for_statement", "while_statement", "do_statement", "switch_statement"],
+        },
+        "cpp" => MetricKinds {
+            functions: &["function_definition", "lambda_expression"],
+            classes: &["class_specifier", "struct_specifier"],
+            nesting: &[
+                "if_statement",
+                "for_statement",
+                "for_range_loop",
+                "while_statement",
+                "do_statement",
+                "switch_statement",
+                "try_statement",
+            ],
+        },
+        "csharp" => MetricKinds {
+            functions: &[
+                "method_declaration",
+                "constructor_declaration",
+                "local_function_statement",
+                "lambda_expression",
+            ],
+            classes: &[
+                "class_declaration",
+                "interface_declaration",
+                "struct_declaration",
+                "record_declaration",
+                "enum_declaration",
+            ],
+            nesting: &[
+                "if_statement",
+                "for_statement",
+                "foreach_statement",
+                "while_statement",
+                "do_statement",
+                "try_statement",
+                "switch_statement",
+            ],
+        },
+        "ruby" => MetricKinds {
+            functions: &["method", "singleton_method"],
+            classes: &["class", "module"],
+            nesting: &["if", "unless", "while", "until", "for", "case", "begin"],
+        },
+        "kotlin" => MetricKinds {
+            functions: &["function_declaration", "lambda_literal"],
+            classes: &["class_declaration", "object_declaration"],
+            nesting: &[
+                "if_expression",
+                "for_statement",
+                "while_statement",
+                "do_while_statement",
+                "when_expression",
+                "try_expression",
+            ],
+        },
+        "php" => MetricKinds {
+            functions: &["function_definition", "method_declaration", "anonymous_function"],
+            classes: &["class_declaration", "interface_declaration", "trait_declaration"],
+            nesting: &[
+                "if_statement",
+                "for_statement",
+                "foreach_statement",
+                "while_statement",
+                "do_statement",
+                "switch_statement",
+                "try_statement",
+            ],
+        },
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// Inline rules (one document per metric) matching the metric node kinds of `language`.
+pub fn metrics_rules(language: &str) -> Option<String> {
+    let kinds = metric_kinds(language)?;
+    let rule = |id: &str, kinds: &[&str]| {
+        let any: Vec<String> = kinds.iter().map(|kind| format!("    - kind: {}", kind)).collect();
+        format!("id: {}\nlanguage: {}\nrule:\n  any:\n{}\n", id, language, any.join("\n"))
+    };
+    Some(
+        [
+            rule(FUNCTION_RULE_ID, kinds.functions),
+            rule(CLASS_RULE_ID, kinds.classes),
+            rule(NESTING_RULE_ID, kinds.nesting),
+        ]
+        .join("---\n"),
+    )
+}
+
+/// Structural metrics of one file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FileMetrics {
+    pub file: String,
+    pub functions: usize,
+    pub classes: usize,
+    /// Deepest nesting of control-flow constructs (if, loops, try, switch/match).
+    pub max_nesting_depth: usize,
+    pub avg_function_lines: f64,
+    pub longest_function_lines: usize,
+}
+
+/// Computes per-file metrics from the matches of [`metrics_rules`], most complex first: by
+/// nesting depth, then longest function, then file name.
+pub fn file_metrics(matches: &[Value]) -> Vec<FileMetrics> {
+    #[derive(Default)]
+    struct Collected {
+        function_lines: Vec<usize>,
+        classes: usize,
+        nesting: Vec<(Position, Position)>,
+    }
+
+    let mut files: BTreeMap<String, Collected> = BTreeMap::new();
+    for m in matches {
+        let Some(file) = m.get("file").and_then(Value::as_str) else {
+            continue;
+        };
+        let collected = files.entry(file.to_string()).or_default();
+        match m.get("ruleId").and_then(Value::as_str) {
+            Some(FUNCTION_RULE_ID) => {
+                collected.function_lines.push(match_position(m, "end").0 - match_position(m, "start").0 + 1);
+            }
+            Some(CLASS_RULE_ID) => collected.classes += 1,
+            Some(NESTING_RULE_ID) => {
+                collected.nesting.push((match_position(m, "start"), match_position(m, "end")));
+            }
+            _ => {}
+        }
+    }
+
+    let mut metrics: Vec<FileMetrics> = files
+        .into_iter()
+        .map(|(file, collected)| {
+            let functions = collected.function_lines.len();
+            let total_lines: usize = collected.function_lines.iter().sum();
+            FileMetrics {
+                file,
+                functions,
+                classes: collected.classes,
+                max_nesting_depth: max_depth(collected.nesting),
+                avg_function_lines: if functions == 0 {
+                    0.0
+                } else {
+                    (total_lines as f64 / functions as f64 * 10.0).round() / 10.0
+                },
+                longest_function_lines: collected.function_lines.into_iter().max().unwrap_or(0),
+            }
+        })
+        .collect();
+    metrics.sort_by(|a, b| {
+        b.max_nesting_depth
+            .cmp(&a.max_nesting_depth)
+            .then_with(|| b.longest_function_lines.cmp(&a.longest_function_lines))
+            .then_with(|| a.file.cmp(&b.file))
+    });
+    metrics
+}
+
+/// Deepest chain of ranges contained in one another.
+fn max_depth(mut ranges: Vec<(Position, Position)>) -> usize {
+    // Outer ranges first when two start together
+    ranges.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(&a.1)));
+    let mut open: Vec<Position> = Vec::new();
+    let mut deepest = 0;
+    for (start, end) in ranges {
+        while open.last().is_some_and(|outer_end| *outer_end <= start) {
+            open.pop();
+        }
+        open.push(end);
+        deepest = deepest.max(open.len());
+    }
+    deepest
+}
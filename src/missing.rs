@@ -0,0 +1,64 @@
+use crate::benchmark::is_language_file;
+use crate::filter::classify_generated;
+use crate::shard::list_files;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Files of `language` under `project_folder` that a `find_missing` search checks, in path order:
+/// the files the walk visits (hidden entries, ignored files and, unless `include_vendored`,
+/// dependency and build directories are skipped) with one of the language's extensions, except
+/// minified or binary-looking ones.
+pub fn candidate_files(project_folder: &Path, language: &str, include_vendored: bool) -> Vec<PathBuf> {
+    list_files(project_folder, include_vendored)
+        .into_iter()
+        .filter(|path| is_language_file(path, language) && classify_generated(path).is_none())
+        .collect()
+}
+
+/// The result of a `find_missing` search.
+#[derive(Debug, Serialize)]
+pub struct MissingReport {
+    pub language: String,
+    pub files_checked: usize,
+    pub files_with_matches: usize,
+    /// Files without a match, relative to the project folder, in path order.
+    pub missing: Vec<String>,
+    /// How many files are missing the match when `missing` was cut to `max_results`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_missing: Option<usize>,
+}
+
+/// Splits `candidates` into files with and without one of `matches`; relative match paths are
+/// resolved against `project_folder`.
+pub fn missing_report(
+    language: &str,
+    candidates: &[PathBuf],
+    matches: &[Value],
+    project_folder: &Path,
+    max_results: usize,
+) -> MissingReport {
+    let matched: HashSet<PathBuf> = matches
+        .iter()
+        .filter_map(|m| m.get("file").and_then(Value::as_str))
+        .map(|file| project_folder.join(file))
+        .collect();
+    let mut missing: Vec<String> = candidates
+        .iter()
+        .filter(|path| !matched.contains(*path))
+        .map(|path| path.strip_prefix(project_folder).unwrap_or(path).to_string_lossy().into_owned())
+        .collect();
+    let total = missing.len();
+    let truncated = max_results > 0 && total > max_results;
+    if truncated {
+        missing.truncate(max_results);
+    }
+    MissingReport {
+        language: language.to_string(),
+        files_checked: candidates.len(),
+        files_with_matches: candidates.len() - total,
+        missing,
+        total_missing: truncated.then_some(total),
+    }
+}
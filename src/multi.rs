@@ -0,0 +1,157 @@
+use crate::benchmark::language_for_path;
+use crate::shard::walker;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the rule ids of a multi-language `find_code` search; the language follows it.
+pub const MULTI_RULE_PREFIX: &str = "find-code-";
+
+/// Rule id of a single-language `find_code` search with constraints.
+pub const CONSTRAINED_RULE_ID: &str = "find-code";
+
+/// Most languages a `find_code` call without a language searches, those with the most files.
+pub const MAX_DETECTED_LANGUAGES: usize = 4;
+
+/// Files per built-in language.
+pub type LanguageCounts = BTreeMap<&'static str, usize>;
+
+/// Files of a built-in language [`folder_languages`] counts; past that many, more files rarely
+/// change which languages have the most, so the rest of the folder is not walked.
+pub const LANGUAGE_SAMPLE_FILES: usize = 10_000;
+
+/// Data formats, left out of detection: a code pattern rarely means them, and a project's
+/// config files would push its source languages out of the detected set.
+const DATA_LANGUAGES: &[&str] = &["json", "yaml"];
+
+/// The kinds of constraint `find_code` takes; anything else needs a full rule.
+const CONSTRAINT_KEYS: &[&str] = &["regex", "kind", "pattern"];
+
+/// Whether `pattern` uses the metavariable `name` (as `$NAME` or `$$$NAME`).
+fn uses_metavariable(pattern: &str, name: &str) -> bool {
+    let needle = format!("${}", name);
+    pattern.match_indices(&needle).any(|(start, _)| {
+        let next = pattern[start + needle.len()..].chars().next();
+        !next.is_some_and(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// The `constraints` section of a rule for `find_code`'s constraints: metavariable names, with or
+/// without `$`, to a constraint object with regex, kind and/or pattern, or a string as shorthand
+/// for a regex. Every constrained metavariable must be used by one of `patterns`.
+pub fn rule_constraints(
+    constraints: &BTreeMap<String, Value>,
+    patterns: &[&str],
+) -> Result<Map<String, Value>, String> {
+    let mut section = Map::new();
+    for (name, constraint) in constraints {
+        let name = name.trim_start_matches('$');
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+            return Err(format!("Invalid constraint name '{}': it must be a metavariable such as $NAME.", name));
+        }
+        if !patterns.iter().any(|pattern| uses_metavariable(pattern, name)) {
+            return Err(format!("Constraint on ${} but the pattern has no ${}.", name, name));
+        }
+        let constraint = match constraint {
+            Value::String(regex) => json!({ "regex": regex }),
+            Value::Object(object) if !object.is_empty() => {
+                if let Some(key) = object.keys().find(|key| !CONSTRAINT_KEYS.contains(&key.as_str())) {
+                    return Err(format!(
+                        "Unsupported constraint '{}' on ${}: use regex, kind or pattern, or find_code_by_rule.",
+                        key, name
+                    ));
+                }
+                constraint.clone()
+            }
+            _ => {
+                return Err(format!("Invalid constraint on ${}: give {{regex, kind, pattern}} or a regex string.", name))
+            }
+        };
+        section.insert(name.to_string(), constraint);
+    }
+    Ok(section)
+}
+
+/// The inline rule for a `find_code` search of `pattern` in `language` with `constraints`.
+pub fn constrained_rule(
+    language: &str,
+    pattern: &str,
+    constraints: &BTreeMap<String, Value>,
+) -> Result<String, String> {
+    let rule = json!({
+        "id": CONSTRAINED_RULE_ID,
+        "language": language,
+        "rule": { "pattern": pattern },
+        "constraints": rule_constraints(constraints, &[pattern])?,
+    });
+    serde_yaml::to_string(&rule).map_err(|e| e.to_string())
+}
+
+/// Inline rules for a `find_code` search with one pattern per language, one document per
+/// language with the id `find-code-<language>`, so every match says which pattern found it.
+/// Each constraint applies to the documents whose pattern uses its metavariable.
+pub fn multi_language_rules(
+    patterns: &BTreeMap<String, String>,
+    constraints: &BTreeMap<String, Value>,
+) -> Result<String, String> {
+    let all: Vec<&str> = patterns.values().map(String::as_str).collect();
+    let section = rule_constraints(constraints, &all)?;
+    let mut documents = Vec::new();
+    for (language, pattern) in patterns {
+        if language.trim().is_empty() || pattern.trim().is_empty() {
+            return Err(format!(
+                "patterns needs a language and a pattern in every entry, got '{}': '{}'",
+                language, pattern
+            ));
+        }
+        let mut rule = json!({
+            "id": format!("{}{}", MULTI_RULE_PREFIX, language),
+            "language": language,
+            "rule": { "pattern": pattern },
+        });
+        let own: Map<String, Value> = section
+            .iter()
+            .filter(|(name, _)| uses_metavariable(pattern, name))
+            .map(|(name, constraint)| (name.clone(), constraint.clone()))
+            .collect();
+        if !own.is_empty() {
+            rule["constraints"] = Value::Object(own);
+        }
+        documents.push(serde_yaml::to_string(&rule).map_err(|e| e.to_string())?);
+    }
+    Ok(documents.join("---\n"))
+}
+
+/// Built-in languages of `files` with how many files each has, most files first, data formats
+/// left out.
+pub fn detect_languages(files: &[PathBuf]) -> Vec<(&'static str, usize)> {
+    ranked_languages(count_languages(files.iter().map(PathBuf::as_path), usize::MAX))
+}
+
+/// How many of the files under `folder` (as [`walker`] visits them) each built-in language has,
+/// data formats left out, counting at most [`LANGUAGE_SAMPLE_FILES`] files.
+pub fn folder_languages(folder: &Path, include_vendored: bool) -> LanguageCounts {
+    let files = walker(folder, include_vendored)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(ignore::DirEntry::into_path);
+    count_languages(files, LANGUAGE_SAMPLE_FILES)
+}
+
+/// Languages of `counts` (as [`folder_languages`] returns them, summed over folders), most files
+/// first.
+pub fn ranked_languages(counts: LanguageCounts) -> Vec<(&'static str, usize)> {
+    let mut languages: Vec<(&str, usize)> = counts.into_iter().collect();
+    languages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    languages
+}
+
+fn count_languages<P: AsRef<Path>>(files: impl IntoIterator<Item = P>, limit: usize) -> LanguageCounts {
+    let mut counts = LanguageCounts::new();
+    let languages = files.into_iter().filter_map(|file| language_for_path(file.as_ref()));
+    for language in languages.filter(|language| !DATA_LANGUAGES.contains(language)).take(limit) {
+        *counts.entry(language).or_default() += 1;
+    }
+    counts
+}
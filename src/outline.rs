@@ -0,0 +1,184 @@
+use crate::language::language_alias;
+use crate::ranges::{match_position, Position};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Prefix of the rule ids in [`outline_rules`]; the rest of the id is the item's label.
+pub const OUTLINE_RULE_PREFIX: &str = "outline-";
+
+/// Labels of items that contain methods.
+const CONTAINER_LABELS: &[&str] = &["class", "interface", "struct", "trait", "impl", "enum", "module"];
+
+const JS_ITEMS: &[(&str, &str, &str)] = &[
+    ("class_declaration", "class", "name"),
+    ("function_declaration", "function", "name"),
+    ("generator_function_declaration", "function", "name"),
+    ("method_definition", "function", "name"),
+];
+
+const TS_ITEMS: &[(&str, &str, &str)] = &[
+    ("class_declaration", "class", "name"),
+    ("abstract_class_declaration", "class", "name"),
+    ("interface_declaration", "interface", "name"),
+    ("enum_declaration", "enum", "name"),
+    ("type_alias_declaration", "type", "name"),
+    ("internal_module", "module", "name"),
+    ("function_declaration", "function", "name"),
+    ("generator_function_declaration", "function", "name"),
+    ("method_definition", "function", "name"),
+    ("method_signature", "function", "name"),
+];
+
+/// Outlined node kinds of `language` as (kind, label, field holding the name).
+fn outline_items(language: &str) -> Option<&'static [(&'static str, &'static str, &'static str)]> {
+    let items: &[(&str, &str, &str)] = match language_alias(language).as_str() {
+        "python" => &[("class_definition", "class", "name"), ("function_definition", "function", "name")],
+        "javascript" => JS_ITEMS,
+        "typescript" | "tsx" => TS_ITEMS,
+        "rust" => &[
+            ("mod_item", "module", "name"),
+            ("struct_item", "struct", "name"),
+            ("enum_item", "enum", "name"),
+            ("trait_item", "trait", "name"),
+            ("impl_item", "impl", "type"),
+            ("function_item", "function", "name"),
+            ("function_signature_item", "function", "name"),
+        ],
+        "go" => &[
+            ("type_spec", "type", "name"),
+            ("function_declaration", "function", "name"),
+            ("method_declaration", "function", "name"),
+        ],
+        "java" => &[
+            ("class_declaration", "class", "name"),
+            ("interface_declaration", "interface", "name"),
+            ("enum_declaration", "enum", "name"),
+            ("record_declaration", "class", "name"),
+            ("method_declaration", "function", "name"),
+            ("constructor_declaration", "function", "name"),
+        ],
+        "csharp" => &[
+            ("namespace_declaration", "module", "name"),
+            ("class_declaration", "class", "name"),
+            ("interface_declaration", "interface", "name"),
+            ("struct_declaration", "struct", "name"),
+            ("enum_declaration", "enum", "name"),
+            ("record_declaration", "class", "name"),
+            ("method_declaration", "function", "name"),
+            ("constructor_declaration", "function", "name"),
+        ],
+        "ruby" => &[
+            ("module", "module", "name"),
+            ("class", "class", "name"),
+            ("method", "function", "name"),
+            ("singleton_method", "function", "name"),
+        ],
+        "php" => &[
+            ("class_declaration", "class", "name"),
+            ("interface_declaration", "interface", "name"),
+            ("trait_declaration", "trait", "name"),
+            ("function_definition", "function", "name"),
+            ("method_declaration", "function", "name"),
+        ],
+        _ => return None,
+    };
+    Some(items)
+}
+
+/// Inline rules (one document per label) matching the outlined items of `language` and
+/// capturing their name as `$NAME`.
+pub fn outline_rules(language: &str) -> Option<String> {
+    let mut by_label: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (kind, label, field) in outline_items(language)? {
+        by_label.entry(label).or_default().push(format!(
+            "    - kind: {}\n      has:\n        field: {}\n        pattern: $NAME",
+            kind, field
+        ));
+    }
+    let documents: Vec<String> = by_label
+        .into_iter()
+        .map(|(label, any)| {
+            format!(
+                "id: {}{}\nlanguage: {}\nrule:\n  any:\n{}\n",
+                OUTLINE_RULE_PREFIX,
+                label,
+                language,
+                any.join("\n")
+            )
+        })
+        .collect();
+    Some(documents.join("---\n"))
+}
+
+/// An item of a file outline. Functions directly inside a class-like item are methods.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutlineItem {
+    pub kind: String,
+    pub name: String,
+    /// 1-based, inclusive line range.
+    pub start_line: usize,
+    pub end_line: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<OutlineItem>,
+}
+
+/// Nests the matches of [`outline_rules`] by range containment, in source order.
+pub fn build_outline(matches: &[Value]) -> Vec<OutlineItem> {
+    let mut flat: Vec<(Position, Position, OutlineItem)> = Vec::new();
+    for m in matches {
+        let Some(label) = m
+            .get("ruleId")
+            .and_then(Value::as_str)
+            .and_then(|id| id.strip_prefix(OUTLINE_RULE_PREFIX))
+        else {
+            continue;
+        };
+        let Some(name) = m.pointer("/metaVariables/single/NAME/text").and_then(Value::as_str) else {
+            continue;
+        };
+        let (start, end) = (match_position(m, "start"), match_position(m, "end"));
+        flat.push((
+            start,
+            end,
+            OutlineItem {
+                kind: label.to_string(),
+                name: name.to_string(),
+                start_line: start.0 + 1,
+                end_line: end.0 + 1,
+                children: Vec::new(),
+            },
+        ));
+    }
+    // Outer items first when two start together
+    flat.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(&a.1)));
+
+    let mut roots = Vec::new();
+    let mut open: Vec<(Position, OutlineItem)> = Vec::new();
+    for (start, end, mut item) in flat {
+        while open.last().is_some_and(|(outer_end, _)| *outer_end <= start) {
+            close_item(&mut open, &mut roots);
+        }
+        let in_container = open
+            .last()
+            .is_some_and(|(_, parent)| CONTAINER_LABELS.contains(&parent.kind.as_str()));
+        if item.kind == "function" && in_container {
+            item.kind = "method".to_string();
+        }
+        open.push((end, item));
+    }
+    while !open.is_empty() {
+        close_item(&mut open, &mut roots);
+    }
+    roots
+}
+
+fn close_item(open: &mut Vec<(Position, OutlineItem)>, roots: &mut Vec<OutlineItem>) {
+    let Some((_, item)) = open.pop() else {
+        return;
+    };
+    match open.last_mut() {
+        Some((_, parent)) => parent.children.push(item),
+        None => roots.push(item),
+    }
+}
@@ -0,0 +1,126 @@
+use crate::dump::DumpFormat;
+use crate::language::language_alias;
+use std::fmt::Write as _;
+use tree_sitter::{Language, Node, Parser, Point};
+
+/// The tree-sitter grammar built into the server for `language` (names and aliases as in a
+/// rule's `language` field), with the character ast-grep puts in place of `$` in patterns of
+/// languages where `$` cannot start an identifier; `None` for languages only the ast-grep CLI
+/// can parse.
+pub fn embedded_language(language: &str) -> Option<(Language, char)> {
+    let (grammar, expando) = match language_alias(language).as_str() {
+        "bash" => (tree_sitter_bash::LANGUAGE, '$'),
+        "c" => (tree_sitter_c::LANGUAGE, 'µ'),
+        "cpp" => (tree_sitter_cpp::LANGUAGE, 'µ'),
+        "csharp" => (tree_sitter_c_sharp::LANGUAGE, 'µ'),
+        "css" => (tree_sitter_css::LANGUAGE, '_'),
+        "elixir" => (tree_sitter_elixir::LANGUAGE, 'µ'),
+        "go" => (tree_sitter_go::LANGUAGE, 'µ'),
+        "html" => (tree_sitter_html::LANGUAGE, 'z'),
+        "java" => (tree_sitter_java::LANGUAGE, '$'),
+        "javascript" => (tree_sitter_javascript::LANGUAGE, '$'),
+        "json" => (tree_sitter_json::LANGUAGE, '$'),
+        "php" => (tree_sitter_php::LANGUAGE_PHP_ONLY, 'µ'),
+        "python" => (tree_sitter_python::LANGUAGE, 'µ'),
+        "ruby" => (tree_sitter_ruby::LANGUAGE, 'µ'),
+        "rust" => (tree_sitter_rust::LANGUAGE, 'µ'),
+        "swift" => (tree_sitter_swift::LANGUAGE, 'µ'),
+        "tsx" => (tree_sitter_typescript::LANGUAGE_TSX, '$'),
+        "typescript" => (tree_sitter_typescript::LANGUAGE_TYPESCRIPT, '$'),
+        "yaml" => (tree_sitter_yaml::LANGUAGE, '$'),
+        _ => return None,
+    };
+    Some((grammar.into(), expando))
+}
+
+/// Dumps the syntax tree of `code` as ast-grep's `--debug-query=<format>` does, without running
+/// it: one node per line, two spaces of indent per level, `field: kind (line,column)-(line,column)`
+/// with 0-based lines and character columns. `None` when `language` has no embedded grammar.
+pub fn dump_tree(code: &str, language: &str, format: DumpFormat) -> Option<String> {
+    let (grammar, expando) = embedded_language(language)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar).ok()?;
+    let source = match format {
+        DumpFormat::Pattern if expando != '$' => code.replace('$', &expando.to_string()),
+        _ => code.to_string(),
+    };
+    let tree = parser.parse(&source, None)?;
+
+    let mut root = tree.root_node();
+    if format == DumpFormat::Pattern {
+        // ast-grep matches with the innermost node of the chain of single-child wrappers
+        while root.child_count() == 1 {
+            match root.child(0) {
+                Some(child) => root = child,
+                None => break,
+            }
+        }
+    }
+    let mut dump = format!("{}\n", format.header());
+    let mut dumper = Dumper { source: &source, code, format, out: &mut dump };
+    dumper.node(root, None, 0);
+    Some(dump)
+}
+
+struct Dumper<'a> {
+    /// What was parsed: `code`, with the expando character in place of `$` in patterns.
+    source: &'a str,
+    code: &'a str,
+    format: DumpFormat,
+    out: &'a mut String,
+}
+
+impl Dumper<'_> {
+    fn node(&mut self, node: Node, field: Option<&str>, depth: usize) {
+        let _ = write!(self.out, "{}", "  ".repeat(depth));
+        if let Some(field) = field {
+            let _ = write!(self.out, "{}: ", field);
+        }
+        let start = self.position(node.start_byte(), node.start_position());
+        let end = self.position(node.end_byte(), node.end_position());
+        if node.is_missing() {
+            let _ = write!(self.out, "MISSING {}", node.kind());
+        } else if self.format == DumpFormat::Pattern && node.child_count() == 0 {
+            // The expando character replaced `$` one for one, so the code has the metavariable
+            // at the same character positions
+            let from = self.source[..node.start_byte()].chars().count();
+            let to = from + self.source[node.byte_range()].chars().count();
+            let text: String = self.code.chars().skip(from).take(to - from).collect();
+            let _ = write!(self.out, "{}", if is_metavariable(&text) { text.as_str() } else { node.kind() });
+        } else {
+            let _ = write!(self.out, "{}", node.kind());
+        }
+        let _ = writeln!(self.out, " ({},{})-({},{})", start.0, start.1, end.0, end.1);
+
+        let mut cursor = node.walk();
+        if !cursor.goto_first_child() {
+            return;
+        }
+        loop {
+            let child = cursor.node();
+            if self.format != DumpFormat::Ast || child.is_named() {
+                self.node(child, cursor.field_name(), depth + 1);
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+
+    /// Line and character column of the byte `offset` at `point`; only that line is scanned, as
+    /// tree-sitter's columns are in bytes.
+    fn position(&self, offset: usize, point: Point) -> (usize, usize) {
+        (point.row, self.source[offset - point.column..offset].chars().count())
+    }
+}
+
+/// Whether `text` is a metavariable such as `$A`, `$_`, `$$B` or `$$$ARGS`.
+fn is_metavariable(text: &str) -> bool {
+    let name = text.trim_start_matches('$');
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    match text.len() - name.len() {
+        1 | 2 => valid,
+        3 => valid || name.is_empty(),
+        _ => false,
+    }
+}
@@ -0,0 +1,225 @@
+use crate::ranges::byte_offset_of;
+use crate::suggest::DumpNode;
+use serde::Serialize;
+
+/// A node of the code or the pattern, as shown in a playground response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeSummary {
+    pub kind: String,
+    pub text: String,
+    /// `line:column-line:column`, 1-based.
+    pub range: String,
+    /// Zero-based start and end, to find the node again.
+    #[serde(skip)]
+    pub span: ((usize, usize), (usize, usize)),
+}
+
+/// Where a pattern stops matching the code node that resembles it most.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Divergence {
+    pub reason: String,
+    /// The pattern node that did not match; `None` when the code has more than the pattern.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern_node: Option<NodeSummary>,
+    /// The code node it was compared with; `None` when the code ran out of nodes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_node: Option<NodeSummary>,
+    /// The code node of the same kind as the pattern's root that the pattern matched furthest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate: Option<NodeSummary>,
+}
+
+/// Source text of `node`.
+pub fn node_text<'a>(source: &'a str, node: &DumpNode) -> &'a str {
+    let start = byte_offset_of(source, node.start).unwrap_or(source.len());
+    let end = byte_offset_of(source, node.end).unwrap_or(source.len()).max(start);
+    &source[start..end]
+}
+
+fn summary(source: &str, node: &DumpNode) -> NodeSummary {
+    NodeSummary {
+        kind: node.kind.clone(),
+        text: node_text(source, node).to_string(),
+        range: format!("{}:{}-{}:{}", node.start.0 + 1, node.start.1 + 1, node.end.0 + 1, node.end.1 + 1),
+        span: (node.start, node.end),
+    }
+}
+
+/// The node a pattern matches with: ast-grep skips the wrappers (program, expression
+/// statement, ...) that have a single child.
+pub fn pattern_root(node: &DumpNode) -> &DumpNode {
+    let mut node = node;
+    while let [only] = node.children.as_slice() {
+        node = only;
+    }
+    node
+}
+
+/// Whether `text` is a metavariable, and if so whether it stands for several nodes (`$$$`).
+fn metavariable(text: &str) -> Option<bool> {
+    let (multi, name) = match text.strip_prefix("$$$") {
+        Some(name) => (true, name),
+        None => (false, text.strip_prefix("$$").or_else(|| text.strip_prefix('$'))?),
+    };
+    let valid = name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    (valid && (multi || !name.is_empty())).then_some(multi)
+}
+
+/// Tokens such as `(` or `def`, whose kind is their text; code may have ones the pattern leaves out.
+fn is_token(source: &str, node: &DumpNode) -> bool {
+    node.children.is_empty() && node_text(source, node) == node.kind
+}
+
+/// Compares pattern and code trees like ast-grep's smart strictness, approximately: kinds and
+/// leaf texts must agree, metavariables match any node (`$$$` any run of nodes), and code tokens
+/// missing from the pattern are skipped.
+struct Comparison<'a> {
+    pattern_source: &'a str,
+    code_source: &'a str,
+    /// Pattern nodes matched before the first divergence.
+    matched: usize,
+}
+
+impl Comparison<'_> {
+    fn divergence(&self, reason: String, pattern: Option<&DumpNode>, code: Option<&DumpNode>) -> Box<Divergence> {
+        Box::new(Divergence {
+            reason,
+            pattern_node: pattern.map(|node| summary(self.pattern_source, node)),
+            code_node: code.map(|node| summary(self.code_source, node)),
+            candidate: None,
+        })
+    }
+
+    fn compare(&mut self, pattern: &DumpNode, code: &DumpNode) -> Result<(), Box<Divergence>> {
+        let pattern_text = node_text(self.pattern_source, pattern);
+        if metavariable(pattern_text.trim()).is_some() {
+            self.matched += 1;
+            return Ok(());
+        }
+        if pattern.kind != code.kind {
+            let reason = format!("expected a `{}` node, found `{}`", pattern.kind, code.kind);
+            return Err(self.divergence(reason, Some(pattern), Some(code)));
+        }
+        if pattern.children.is_empty() {
+            let code_text = node_text(self.code_source, code);
+            if pattern_text != code_text {
+                let reason = format!("expected `{}`, found `{}`", pattern_text, code_text);
+                return Err(self.divergence(reason, Some(pattern), Some(code)));
+            }
+            self.matched += 1;
+            return Ok(());
+        }
+        self.matched += 1;
+        self.compare_children(&pattern.children, &code.children, code)
+    }
+
+    fn compare_children(
+        &mut self,
+        patterns: &[DumpNode],
+        codes: &[DumpNode],
+        parent: &DumpNode,
+    ) -> Result<(), Box<Divergence>> {
+        let mut next = 0;
+        for (index, pattern) in patterns.iter().enumerate() {
+            if metavariable(node_text(self.pattern_source, pattern).trim()) == Some(true) {
+                // `$$$` takes code nodes up to the one the next pattern node starts matching at
+                let following = patterns.get(index + 1).map(|p| p.kind.as_str());
+                while next < codes.len() && Some(codes[next].kind.as_str()) != following {
+                    next += 1;
+                }
+                self.matched += 1;
+                continue;
+            }
+            while next < codes.len() && codes[next].kind != pattern.kind && is_token(self.code_source, &codes[next]) {
+                next += 1;
+            }
+            let Some(code) = codes.get(next) else {
+                let reason = format!(
+                    "expected a `{}` node, but the `{}` has no more children",
+                    pattern.kind, parent.kind
+                );
+                return Err(self.divergence(reason, Some(pattern), Some(parent)));
+            };
+            self.compare(pattern, code)?;
+            next += 1;
+        }
+        if let Some(extra) = codes[next..].iter().find(|code| !is_token(self.code_source, code)) {
+            let reason = format!("the code has a `{}` node the pattern does not have", extra.kind);
+            return Err(self.divergence(reason, None, Some(extra)));
+        }
+        Ok(())
+    }
+}
+
+fn preorder<'a>(node: &'a DumpNode, nodes: &mut Vec<&'a DumpNode>) {
+    nodes.push(node);
+    for child in &node.children {
+        preorder(child, nodes);
+    }
+}
+
+/// Why `pattern` does not match anything in `code`, at the code node of the pattern's root kind
+/// it gets furthest into. `None` when some node matches as far as this comparison can tell
+/// (ast-grep may still disagree, e.g. because of repeated metavariables).
+pub fn first_divergence(pattern_source: &str, pattern: &DumpNode, code_source: &str, code: &DumpNode) -> Option<Divergence> {
+    let root = pattern_root(pattern);
+    let mut nodes = Vec::new();
+    preorder(code, &mut nodes);
+    let candidates: Vec<&DumpNode> = nodes.into_iter().filter(|node| node.kind == root.kind).collect();
+    if candidates.is_empty() {
+        let reason = format!("the code has no `{}` node, the kind of the pattern's root", root.kind);
+        return Some(Divergence {
+            reason,
+            pattern_node: Some(summary(pattern_source, root)),
+            code_node: None,
+            candidate: None,
+        });
+    }
+
+    let mut best: Option<(usize, Box<Divergence>)> = None;
+    for candidate in candidates {
+        let mut comparison = Comparison { pattern_source, code_source, matched: 0 };
+        match comparison.compare(root, candidate) {
+            Ok(()) => return None,
+            Err(mut divergence) => {
+                if best.as_ref().is_none_or(|(matched, _)| comparison.matched > *matched) {
+                    divergence.candidate = Some(summary(code_source, candidate));
+                    best = Some((comparison.matched, divergence));
+                }
+            }
+        }
+    }
+    best.map(|(_, divergence)| *divergence)
+}
+
+/// The smallest node of `root` that contains the range from `start` to `end`.
+pub fn enclosing_node(root: &DumpNode, start: (usize, usize), end: (usize, usize)) -> &DumpNode {
+    let mut node = root;
+    while let Some(child) = node.children.iter().find(|child| child.start <= start && end <= child.end) {
+        node = child;
+    }
+    node
+}
+
+/// Renders `node` and its descendants like ast-grep's `--debug-query=cst` output.
+pub fn render_dump(node: &DumpNode) -> String {
+    fn render(node: &DumpNode, depth: usize, out: &mut String) {
+        let field = node.field.as_ref().map_or(String::new(), |field| format!("{}: ", field));
+        out.push_str(&format!(
+            "{}{}{} ({},{})-({},{})\n",
+            "  ".repeat(depth),
+            field,
+            node.kind,
+            node.start.0,
+            node.start.1,
+            node.end.0,
+            node.end.1
+        ));
+        for child in &node.children {
+            render(child, depth + 1, out);
+        }
+    }
+    let mut out = String::new();
+    render(node, 0, &mut out);
+    out
+}
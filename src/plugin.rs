@@ -0,0 +1,62 @@
+use crate::config::Config;
+use crate::server::AstGrepServer;
+use rmcp::handler::server::router::tool::ToolRoute;
+use std::fmt;
+use std::sync::Arc;
+
+/// Extra MCP tools from outside this crate, such as an organisation's internal rule catalog.
+///
+/// Routes are added to every server built with a [`Config`] whose `plugins` include the plugin,
+/// after the built-in tools. A route named like a built-in tool is ignored. Routes that write
+/// files are gated like the built-in tools that do: left out with `--read-only`, and needing the
+/// OAuth write scope.
+pub trait ToolPlugin: Send + Sync + 'static {
+    /// Name shown by the `doctor` tool and in warnings.
+    fn name(&self) -> &str;
+
+    /// The tools to add for a server running with `config`.
+    fn routes(&self, config: &Config) -> Vec<PluginRoute>;
+}
+
+/// A tool of a [`ToolPlugin`].
+pub struct PluginRoute {
+    pub route: ToolRoute<AstGrepServer>,
+    /// Whether the tool changes files on disk, like the built-in [`WRITE_TOOLS`](crate::toolset::WRITE_TOOLS).
+    pub writes: bool,
+}
+
+impl PluginRoute {
+    /// A tool that only reads.
+    pub fn new(route: ToolRoute<AstGrepServer>) -> Self {
+        PluginRoute { route, writes: false }
+    }
+
+    /// A tool that changes files on disk.
+    pub fn writing(route: ToolRoute<AstGrepServer>) -> Self {
+        PluginRoute { route, writes: true }
+    }
+}
+
+/// The plugins of a [`Config`], in registration order.
+#[derive(Clone, Default)]
+pub struct Plugins(Vec<Arc<dyn ToolPlugin>>);
+
+impl Plugins {
+    pub fn add(&mut self, plugin: impl ToolPlugin) {
+        self.0.push(Arc::new(plugin));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn ToolPlugin> {
+        self.0.iter().map(|plugin| plugin.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter().map(ToolPlugin::name)).finish()
+    }
+}
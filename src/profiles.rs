@@ -0,0 +1,70 @@
+use crate::benchmark::is_language_file;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A named set of file filters from the `--filter-profiles` file, selected per call with
+/// `filter_profile` so that agents need not repeat complex filters.
+///
+/// ```yaml
+/// tests-only:
+///   globs: ["**/tests/**", "**/test_*.py"]
+///   excludes: ["**/fixtures/**"]
+/// frontend:
+///   languages: [typescript, tsx, css]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterProfile {
+    /// Only files matching one of these globs are searched (.gitignore syntax, as `--globs`).
+    #[serde(default)]
+    pub globs: Vec<String>,
+    /// Files matching these globs are skipped.
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    /// Only matches in files of these languages are kept.
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+impl FilterProfile {
+    /// The `--globs` arguments for the profile's globs and excludes.
+    pub fn glob_args(&self) -> Vec<String> {
+        let includes = self.globs.iter().cloned();
+        let excludes = self.excludes.iter().map(|glob| format!("!{}", glob.trim_start_matches('!')));
+        includes.chain(excludes).flat_map(|glob| ["--globs".to_string(), glob]).collect()
+    }
+
+    /// Whether a match in `file` is kept by the profile's languages.
+    pub fn keeps(&self, file: &Path) -> bool {
+        self.languages.is_empty() || self.languages.iter().any(|language| is_language_file(file, language))
+    }
+
+    /// Drops the matches the profile's languages do not keep and returns how many were dropped.
+    pub fn retain_matches(&self, matches: &mut Vec<Value>) -> usize {
+        let before = matches.len();
+        matches.retain(|m| m.get("file").and_then(Value::as_str).is_none_or(|file| self.keeps(Path::new(file))));
+        before - matches.len()
+    }
+}
+
+/// Parses a filter profiles file: a YAML map from profile names to [`FilterProfile`]s.
+pub fn parse_filter_profiles(yaml: &str) -> Result<BTreeMap<String, FilterProfile>> {
+    if yaml.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let profiles: BTreeMap<String, FilterProfile> = serde_yaml::from_str(yaml)?;
+    if let Some(name) = profiles.keys().find(|name| name.trim().is_empty()) {
+        anyhow::bail!("Invalid filter profile name '{}'", name);
+    }
+    Ok(profiles)
+}
+
+/// Reads and parses the filter profiles file at `path`.
+pub fn load_filter_profiles(path: &Path) -> Result<BTreeMap<String, FilterProfile>> {
+    let yaml = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read filter profiles file '{}'", path.display()))?;
+    parse_filter_profiles(&yaml).with_context(|| format!("Invalid filter profiles file '{}'", path.display()))
+}
@@ -0,0 +1,139 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A zero-based line and character column.
+pub type Position = (usize, usize);
+
+/// The position of the `start` or `end` of a match's range, zero for missing fields.
+pub fn match_position(m: &Value, edge: &str) -> Position {
+    let field = |field: &str| m.pointer(&format!("/range/{}/{}", edge, field)).and_then(Value::as_u64).unwrap_or(0);
+    (field("line") as usize, field("column") as usize)
+}
+
+/// Makes sure every match carries `range.byteOffset.{start,end}` and a complete
+/// `range.start`/`range.end` position.
+///
+/// ast-grep versions differ in which of these they emit. Missing pieces are derived from the
+/// pieces that are present, reading the matched file (relative to `project_folder`) only when
+/// the match text alone is not enough. Columns count characters, as in ast-grep's output.
+pub fn normalize_match_ranges(matches: &mut [Value], project_folder: &Path) {
+    let mut sources: HashMap<String, Option<String>> = HashMap::new();
+
+    for m in matches.iter_mut() {
+        let file = m.get("file").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        normalize_range(m, || {
+            sources
+                .entry(file.clone())
+                .or_insert_with(|| {
+                    std::fs::read(project_folder.join(&file))
+                        .ok()
+                        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                })
+                .clone()
+        });
+    }
+}
+
+/// Sorts matches by file, then start line and column.
+///
+/// ast-grep walks files in parallel, so its output order varies between runs; sorting before
+/// `max_results` is applied makes truncated results repeatable.
+pub fn sort_by_position(matches: &mut [Value]) {
+    matches.sort_by_cached_key(|m| {
+        let file = m.get("file").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let line = m.pointer("/range/start/line").and_then(|v| v.as_u64()).unwrap_or(0);
+        let column = m.pointer("/range/start/column").and_then(|v| v.as_u64()).unwrap_or(0);
+        (file, line, column)
+    });
+}
+
+/// Like [`normalize_match_ranges`] for matches against an in-memory `source`, e.g. stdin.
+pub fn normalize_source_ranges(matches: &mut [Value], source: &str) {
+    for m in matches.iter_mut() {
+        normalize_range(m, || Some(source.to_string()));
+    }
+}
+
+fn normalize_range(m: &mut Value, mut load_source: impl FnMut() -> Option<String>) {
+    let Some(text) = m.get("text").and_then(|v| v.as_str()).map(str::to_string) else {
+        return;
+    };
+    let Some(obj) = m.as_object_mut() else {
+        return;
+    };
+    let range = obj.entry("range").or_insert_with(|| json!({}));
+    let Some(range) = range.as_object_mut() else {
+        return;
+    };
+
+    let mut start = position(range, "start");
+    let mut start_byte = field(range, "byteOffset", "start");
+
+    // Recover whichever start coordinate is missing from the other one
+    if start.is_none() || start_byte.is_none() {
+        if let Some(source) = load_source() {
+            match (start, start_byte) {
+                (Some(pos), None) => start_byte = byte_offset_of(&source, pos),
+                (None, Some(offset)) => start = position_of(&source, offset),
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(start) = start {
+        range.insert("start".to_string(), position_value(start));
+        if position(range, "end").is_none() {
+            range.insert("end".to_string(), position_value(end_position(start, &text)));
+        }
+    }
+    if let Some(start_byte) = start_byte {
+        let end_byte = field(range, "byteOffset", "end").unwrap_or(start_byte + text.len());
+        range.insert("byteOffset".to_string(), json!({ "start": start_byte, "end": end_byte }));
+    }
+}
+
+/// Reads `range[key][field]` as an unsigned integer.
+fn field(range: &Map<String, Value>, key: &str, field: &str) -> Option<usize> {
+    range.get(key)?.get(field)?.as_u64().map(|v| v as usize)
+}
+
+fn position(range: &Map<String, Value>, edge: &str) -> Option<Position> {
+    Some((field(range, edge, "line")?, field(range, edge, "column")?))
+}
+
+fn position_value((line, column): Position) -> Value {
+    json!({ "line": line, "column": column })
+}
+
+/// Position just past `text` when it starts at `start`.
+fn end_position((line, column): Position, text: &str) -> Position {
+    match text.rsplit_once('\n') {
+        Some((before, last)) => (line + before.matches('\n').count() + 1, last.chars().count()),
+        None => (line, column + text.chars().count()),
+    }
+}
+
+/// Byte offset of a line/character position in `source`.
+pub fn byte_offset_of(source: &str, (line, column): Position) -> Option<usize> {
+    let line_start = if line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let line_text = source[line_start..].split('\n').next().unwrap_or("");
+    let in_line = line_text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(line_text.len()))
+        .nth(column)?;
+    Some(line_start + in_line)
+}
+
+/// Line/character position of a byte offset in `source`.
+pub fn position_of(source: &str, offset: usize) -> Option<Position> {
+    let before = source.get(..offset)?;
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some((line, before[line_start..].chars().count()))
+}
@@ -0,0 +1,92 @@
+use crate::resources::RULE_CHEATSHEET;
+use serde::Serialize;
+
+/// Attempts `refine_pattern` makes when the caller does not say.
+pub const DEFAULT_REFINE_ATTEMPTS: u32 = 3;
+
+/// Most attempts `refine_pattern` makes; each one is a round trip through the client's model.
+pub const MAX_REFINE_ATTEMPTS: u32 = 5;
+
+/// Tokens the client's model may use for one reply; a pattern is rarely more than a few lines.
+pub const REFINE_MAX_TOKENS: u32 = 512;
+
+/// Longest syntax tree dump put in a prompt; the rest is cut, to keep prompts small.
+const MAX_DUMP_CHARS: usize = 4000;
+
+/// A pattern that was tried, and how it did.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RefineAttempt {
+    pub pattern: String,
+    /// Why the pattern is no good, `None` when it parses and matches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+    pub matches: usize,
+    /// ast-grep's `--debug-query=pattern` dump of the pattern, for the next prompt.
+    #[serde(skip)]
+    pub dump: String,
+}
+
+/// System prompt of the sampling requests: what a good reply looks like, and the pattern syntax.
+pub fn refine_system_prompt() -> String {
+    format!(
+        "You fix ast-grep search patterns. A pattern is code in the target language in which \
+         metavariables stand for syntax nodes; it must parse on its own as that language. Reply with \
+         the corrected pattern only, in a single code block, without explanation.\n\n{}",
+        RULE_CHEATSHEET
+    )
+}
+
+fn truncate_dump(dump: &str) -> String {
+    let dump = dump.trim();
+    match dump.char_indices().nth(MAX_DUMP_CHARS) {
+        Some((cut, _)) => format!("{}\n... (truncated)", &dump[..cut]),
+        None => dump.to_string(),
+    }
+}
+
+/// The request for a corrected pattern, after `attempts` (the original pattern first) all
+/// failed. `code` is the example the pattern should match, with its CST dump, if the caller
+/// gave one.
+pub fn refine_prompt(language: &str, attempts: &[RefineAttempt], code: Option<(&str, &str)>) -> String {
+    let mut prompt = format!("This ast-grep pattern for {} does not work.\n", language);
+    for (index, attempt) in attempts.iter().enumerate() {
+        let label = if index == 0 { "Pattern".to_string() } else { format!("Attempt {}", index) };
+        prompt.push_str(&format!(
+            "\n{}:\n```\n{}\n```\nProblem: {}\n",
+            label,
+            attempt.pattern,
+            attempt.problem.as_deref().unwrap_or("none")
+        ));
+    }
+    if let Some(last) = attempts.last().filter(|attempt| !attempt.dump.trim().is_empty()) {
+        prompt.push_str(&format!(
+            "\nHow ast-grep parses the last pattern:\n```\n{}\n```\n",
+            truncate_dump(&last.dump)
+        ));
+    }
+    if let Some((code, dump)) = code {
+        prompt.push_str(&format!(
+            "\nIt should match this code:\n```\n{}\n```\nIts concrete syntax tree:\n```\n{}\n```\n",
+            code.trim_end(),
+            truncate_dump(dump)
+        ));
+    }
+    prompt.push_str("\nReply with a corrected pattern that parses cleanly and matches what the original meant to find.");
+    prompt
+}
+
+/// The pattern in a model's reply: the first fenced code block if there is one, else the whole
+/// reply without surrounding backticks. `None` when the reply is empty.
+pub fn extract_pattern(reply: &str) -> Option<String> {
+    let reply = reply.trim();
+    let pattern = match reply.split_once("```") {
+        Some((_, rest)) => {
+            // Skip the info string (e.g. `python`) on the fence line
+            let body = rest.split_once('\n').map_or(rest, |(_, body)| body);
+            body.split("```").next().unwrap_or(body)
+        }
+        None => reply.trim_matches('`'),
+    };
+    let pattern = pattern.trim();
+    (!pattern.is_empty()).then(|| pattern.to_string())
+}
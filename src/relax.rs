@@ -0,0 +1,490 @@
+use crate::benchmark::{first_rule_document, is_language_file, language_for_path};
+use crate::filter::{NotSearched, NotSearchedReason, DEFAULT_EXCLUDES};
+use crate::multi::detect_languages;
+use crate::profiles::FilterProfile;
+use crate::watch::rule_languages;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::path::PathBuf;
+
+const RELATIONAL_KEYS: &[&str] = &["inside", "has", "precedes", "follows"];
+
+/// Pattern strictness levels to fall back to, from the least to the most lenient.
+pub const RELAXED_STRICTNESS: &[&str] = &["relaxed", "signature"];
+
+/// Values of the find tools' `fallback`; empty retries `stopBy: end` only.
+pub const FALLBACKS: &[&str] = &["none", "relaxed"];
+
+/// A variant of a query with one constraint loosened.
+#[derive(Debug, Clone)]
+pub struct Relaxation {
+    pub description: String,
+    /// Query arguments replacing the original `--pattern ...` or `--inline-rules ...` ones.
+    pub args: Vec<String>,
+}
+
+/// How a relaxed variant fared when the original query found nothing.
+#[derive(Debug, Clone, Serialize)]
+pub struct RelaxationAttempt {
+    pub relaxation: String,
+    pub matches: usize,
+}
+
+/// Relaxed variants of a find query, each loosening a single constraint so that the first one
+/// that matches points at the constraint that excluded everything.
+///
+/// Pattern queries (`--pattern ...`) get lower strictness levels. Rule queries
+/// (`--inline-rules ...`) get, in order: `stopBy: end` on relational rules that lack it, lower
+/// strictness for every pattern, and then each `inside` constraint dropped in turn. Only the
+/// first document of a multi-rule YAML is relaxed.
+pub fn relaxations(query_args: &[String]) -> Vec<Relaxation> {
+    match query_args {
+        [flag, yaml, ..] if flag == "--inline-rules" => rule_relaxations(yaml),
+        [flag, ..] if flag == "--pattern" => strictness_relaxations(query_args),
+        _ => Vec::new(),
+    }
+}
+
+/// Variants of a find query with every pattern matched at each of [`RELAXED_STRICTNESS`].
+pub fn strictness_relaxations(query_args: &[String]) -> Vec<Relaxation> {
+    match query_args {
+        [flag, yaml, ..] if flag == "--inline-rules" => {
+            let Ok(rule) = first_rule_document(yaml) else {
+                return Vec::new();
+            };
+            RELAXED_STRICTNESS
+                .iter()
+                .filter_map(|strictness| {
+                    let mut relaxed = rule.clone();
+                    let mut changed = false;
+                    for_each_rule(&mut relaxed, &mut |rule, _| changed |= set_strictness(rule, strictness));
+                    let yaml = serde_yaml::to_string(&relaxed).ok().filter(|_| changed)?;
+                    Some(Relaxation {
+                        description: strictness_description(strictness),
+                        args: vec!["--inline-rules".to_string(), yaml],
+                    })
+                })
+                .collect()
+        }
+        [flag, ..] if flag == "--pattern" => RELAXED_STRICTNESS
+            .iter()
+            .map(|strictness| Relaxation {
+                description: strictness_description(strictness),
+                args: [query_args, &["--strictness".to_string(), strictness.to_string()]].concat(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn strictness_description(strictness: &str) -> String {
+    format!("pattern strictness `{}`", strictness)
+}
+
+/// The variant of a rule query with `stopBy: end` on every relational rule that lacks a
+/// `stopBy`, or `None` when there is no such rule (or the query is a pattern). Only the first
+/// document of a multi-rule YAML is kept.
+pub fn stop_by_retry(query_args: &[String]) -> Option<Relaxation> {
+    match query_args {
+        [flag, yaml, ..] if flag == "--inline-rules" => stop_by_relaxation(&first_rule_document(yaml).ok()?),
+        _ => None,
+    }
+}
+
+fn stop_by_relaxation(rule: &Value) -> Option<Relaxation> {
+    let mut relaxed = rule.clone();
+    let mut changed = Vec::new();
+    for_each_rule(&mut relaxed, &mut |rule, path| add_stop_by(rule, path, &mut changed));
+    let yaml = serde_yaml::to_string(&relaxed).ok().filter(|_| !changed.is_empty())?;
+    Some(Relaxation {
+        description: format!("`stopBy: end` on {}", changed.join(", ")),
+        args: vec!["--inline-rules".to_string(), yaml],
+    })
+}
+
+fn rule_relaxations(yaml: &str) -> Vec<Relaxation> {
+    let Ok(rule) = first_rule_document(yaml) else {
+        return Vec::new();
+    };
+    let mut relaxations: Vec<Relaxation> = stop_by_relaxation(&rule).into_iter().collect();
+    relaxations.extend(strictness_relaxations(&["--inline-rules".to_string(), yaml.to_string()]));
+
+    let mut inside_paths = Vec::new();
+    let mut scratch = rule.clone();
+    for_each_rule(&mut scratch, &mut |rule, path| {
+        if rule.get("inside").is_some() {
+            inside_paths.push(path.to_string());
+        }
+    });
+    for path in inside_paths {
+        let mut relaxed = rule.clone();
+        let mut dropped = false;
+        for_each_rule(&mut relaxed, &mut |rule, current| {
+            if current == path {
+                dropped = drop_key(rule, "inside");
+            }
+        });
+        remove_empty_rules(&mut relaxed);
+        let still_has_rule = relaxed.get("rule").and_then(Value::as_mapping).is_some_and(|m| !m.is_empty());
+        if let Some(yaml) = serde_yaml::to_string(&relaxed).ok().filter(|_| dropped && still_has_rule) {
+            relaxations.push(Relaxation {
+                description: format!("without the `inside` at {}", path),
+                args: vec!["--inline-rules".to_string(), yaml],
+            });
+        }
+    }
+    relaxations
+}
+
+/// Calls `visit` on every rule object under `rule` and `utils`, with a dotted path such as
+/// `rule.all[1]` or `utils.name.has`.
+fn for_each_rule(config: &mut Value, visit: &mut dyn FnMut(&mut Value, &str)) {
+    if let Some(rule) = config.get_mut("rule") {
+        walk(rule, "rule", visit);
+    }
+    if let Some(utils) = config.get_mut("utils").and_then(Value::as_mapping_mut) {
+        for (name, rule) in utils.iter_mut() {
+            let path = format!("utils.{}", name.as_str().unwrap_or_default());
+            walk(rule, &path, visit);
+        }
+    }
+}
+
+fn walk(rule: &mut Value, path: &str, visit: &mut dyn FnMut(&mut Value, &str)) {
+    if !rule.is_mapping() {
+        return;
+    }
+    visit(rule, path);
+    let Some(map) = rule.as_mapping_mut() else {
+        return;
+    };
+    for (key, value) in map.iter_mut() {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        let child = format!("{}.{}", path, key);
+        match key {
+            "all" | "any" => {
+                for (i, item) in value.as_sequence_mut().into_iter().flatten().enumerate() {
+                    walk(item, &format!("{}[{}]", child, i), visit);
+                }
+            }
+            "not" | "stopBy" => walk(value, &child, visit),
+            _ if RELATIONAL_KEYS.contains(&key) => walk(value, &child, visit),
+            _ => {}
+        }
+    }
+}
+
+fn add_stop_by(rule: &mut Value, path: &str, changed: &mut Vec<String>) {
+    for key in RELATIONAL_KEYS {
+        if let Some(relational) = rule.get_mut(*key).and_then(Value::as_mapping_mut) {
+            if !relational.contains_key("stopBy") {
+                relational.insert("stopBy".into(), "end".into());
+                changed.push(format!("{}.{}", path, key));
+            }
+        }
+    }
+}
+
+fn set_strictness(rule: &mut Value, strictness: &str) -> bool {
+    let Some(pattern) = rule.get_mut("pattern") else {
+        return false;
+    };
+    if let Some(text) = pattern.as_str() {
+        let mut object = Mapping::new();
+        object.insert("context".into(), text.into());
+        *pattern = Value::Mapping(object);
+    }
+    match pattern.as_mapping_mut() {
+        Some(object) => {
+            object.insert("strictness".into(), strictness.into());
+            true
+        }
+        None => false,
+    }
+}
+
+fn drop_key(rule: &mut Value, key: &str) -> bool {
+    rule.as_mapping_mut().is_some_and(|map| map.remove(key).is_some())
+}
+
+/// Removes rule objects left empty by dropping a constraint, which ast-grep would reject.
+fn remove_empty_rules(config: &mut Value) {
+    fn prune(rule: &mut Value) {
+        let Some(map) = rule.as_mapping_mut() else {
+            return;
+        };
+        for (key, value) in map.iter_mut() {
+            match key.as_str() {
+                Some("all" | "any") => {
+                    if let Some(items) = value.as_sequence_mut() {
+                        items.iter_mut().for_each(prune);
+                        items.retain(|item| !item.as_mapping().is_some_and(Mapping::is_empty));
+                    }
+                }
+                Some("not") => prune(value),
+                _ => {}
+            }
+        }
+        map.retain(|key, value| match key.as_str() {
+            Some("all" | "any") => value.as_sequence().is_none_or(|items| !items.is_empty()),
+            Some("not") => !value.as_mapping().is_some_and(Mapping::is_empty),
+            _ => true,
+        });
+    }
+    if let Some(rule) = config.get_mut("rule") {
+        prune(rule);
+    }
+}
+
+/// Text section summarizing a zero-match diagnosis.
+pub fn diagnosis_text(attempts: &[RelaxationAttempt]) -> String {
+    let mut lines: Vec<String> = attempts
+        .iter()
+        .map(|attempt| match attempt.matches {
+            0 => format!("  {}: no matches", attempt.relaxation),
+            n => format!("  {}: {} matches", attempt.relaxation, n),
+        })
+        .collect();
+    match attempts.iter().find(|attempt| attempt.matches > 0) {
+        Some(attempt) => lines.push(format!(
+            "The query matches with {}, so the constraint it loosens is likely what excludes the code.",
+            attempt.relaxation
+        )),
+        None => lines.push(
+            "No relaxed variant matched either; check the language, the project folder and ignore settings."
+                .to_string(),
+        ),
+    }
+    format!("Zero-match diagnosis (relaxed variants, in order):\n{}", lines.join("\n"))
+}
+
+/// What find tools report instead of a bare "No matches found": the query as it ran, how many
+/// files it could match in, what narrowed the search, and what to try next.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NoMatchReport {
+    /// The ast-grep command line of the query, without filters and folders.
+    pub query: String,
+    /// Files of the query's languages in the searched folders (of any known language when the
+    /// query names none) that the walk visits and a filter profile keeps.
+    pub files_scanned: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Something to change about a query that found nothing.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    /// `relax_strictness`, `add_stop_by`, `drop_inside`, `diagnose`, `check_language`,
+    /// `remove_glob`, `include_vendored` or `no_ignore`.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// How a search that found nothing was scoped, besides its query.
+pub struct SearchScope<'a> {
+    /// Files of the searched folders, as the walk visits them.
+    pub files: &'a [PathBuf],
+    pub filter_profile: Option<(&'a str, &'a FilterProfile)>,
+    pub include_vendored: bool,
+    pub no_ignore: bool,
+    /// Whether relaxed strictness levels were already retried (`fallback: relaxed`).
+    pub retried_strictness: bool,
+    /// Whether a rule lacking `stopBy` was already retried with `stopBy: end`.
+    pub retried_stop_by: bool,
+    pub not_searched: &'a NotSearched,
+}
+
+/// Builds the report for a query (`ast-grep <command> <query_args>`) that found nothing in
+/// `scope`, with suggestions from the zero-match `diagnosis` when there was one.
+pub fn no_match_report(
+    command: &str,
+    query_args: &[String],
+    scope: &SearchScope,
+    diagnosis: &[RelaxationAttempt],
+) -> NoMatchReport {
+    let languages = query_languages(query_args);
+    let files_scanned = scope
+        .files
+        .iter()
+        .filter(|file| match languages.is_empty() {
+            true => language_for_path(file).is_some(),
+            false => languages.iter().any(|language| is_language_file(file, language)),
+        })
+        .filter(|file| scope.filter_profile.is_none_or(|(_, profile)| profile.keeps(file)))
+        .count();
+
+    let mut filters = Vec::new();
+    if let Some((name, profile)) = scope.filter_profile {
+        filters.push(format!("filter_profile '{}': {}", name, profile_summary(profile)));
+    }
+    if !scope.include_vendored {
+        filters.push(format!("vendored directories skipped: {}", DEFAULT_EXCLUDES.join(", ")));
+    }
+    if !scope.no_ignore {
+        filters.push("files excluded by .gitignore, .ignore and .sgignore skipped".to_string());
+    }
+
+    let mut suggestions = Vec::new();
+    match diagnosis.iter().find(|attempt| attempt.matches > 0) {
+        Some(attempt) => suggestions.push(Suggestion {
+            kind: relaxation_kind(&attempt.relaxation),
+            message: format!(
+                "The query finds {} matches with {}; loosen the constraint it relaxes.",
+                attempt.matches, attempt.relaxation
+            ),
+        }),
+        None if diagnosis.is_empty() => {
+            let relaxations = relaxations(query_args);
+            if !scope.retried_stop_by {
+                if let Some(retry) = stop_by_retry(query_args) {
+                    suggestions.push(Suggestion {
+                        kind: "add_stop_by",
+                        message: format!(
+                            "Relational rules without stopBy only look at adjacent nodes; try {}.",
+                            retry.description
+                        ),
+                    });
+                }
+            }
+            let strictness = relaxations.iter().any(|r| r.description.starts_with("pattern strictness"));
+            if strictness && !scope.retried_strictness {
+                suggestions.push(Suggestion {
+                    kind: "relax_strictness",
+                    message: "Patterns match at smart strictness; fallback: 'relaxed' retries with relaxed, then \
+                              signature, strictness."
+                        .to_string(),
+                });
+            }
+            if !relaxations.is_empty() {
+                let tried: Vec<&str> = relaxations.iter().map(|r| r.description.as_str()).collect();
+                suggestions.push(Suggestion {
+                    kind: "diagnose",
+                    message: format!(
+                        "diagnose: true tries each relaxed variant ({}) and reports which one finds matches.",
+                        tried.join("; ")
+                    ),
+                });
+            }
+        }
+        None => {}
+    }
+    if files_scanned == 0 {
+        let detected = detect_languages(scope.files);
+        let message = match (languages.is_empty(), detected.first()) {
+            (_, None) => "The searched folders hold no files ast-grep can parse; check project_folder.".to_string(),
+            (true, Some((language, count))) => {
+                format!("No files matched the filters; the folders mostly hold {} files ({}).", language, count)
+            }
+            (false, Some((language, count))) => format!(
+                "The folders hold no {} files but {} {} files; check the language.",
+                languages.join("/"),
+                count,
+                language
+            ),
+        };
+        suggestions.push(Suggestion { kind: "check_language", message });
+    }
+    if let Some((name, _)) = scope.filter_profile {
+        suggestions.push(Suggestion {
+            kind: "remove_glob",
+            message: format!("Retry without filter_profile '{}' to search every file.", name),
+        });
+    }
+    let not_searched = |reason| scope.not_searched.0.get(&reason).filter(|group| group.count > 0);
+    if let Some(group) = not_searched(NotSearchedReason::Vendored).filter(|_| !scope.include_vendored) {
+        suggestions.push(Suggestion {
+            kind: "include_vendored",
+            message: format!(
+                "{} vendored directories ({}) were skipped; include_vendored: true searches them.",
+                group.count,
+                group.examples.join(", ")
+            ),
+        });
+    }
+    if let Some(group) = not_searched(NotSearchedReason::Ignored).filter(|_| !scope.no_ignore) {
+        suggestions.push(Suggestion {
+            kind: "no_ignore",
+            message: format!(
+                "{} entries excluded by ignore files ({}) were skipped; no_ignore: true searches them.",
+                group.count,
+                group.examples.join(", ")
+            ),
+        });
+    }
+
+    NoMatchReport {
+        query: std::iter::once("ast-grep")
+            .chain(std::iter::once(command))
+            .chain(query_args.iter().map(String::as_str))
+            .map(shell_quote)
+            .collect::<Vec<_>>()
+            .join(" "),
+        files_scanned,
+        filters,
+        suggestions,
+    }
+}
+
+impl NoMatchReport {
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("Query: {}", self.query),
+            format!("Files of the query's language searched: {}", self.files_scanned),
+        ];
+        if !self.filters.is_empty() {
+            lines.push(format!("Filters: {}", self.filters.join("; ")));
+        }
+        if !self.suggestions.is_empty() {
+            lines.push("Suggestions:".to_string());
+            lines.extend(self.suggestions.iter().map(|s| format!("  - [{}] {}", s.kind, s.message)));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Languages a query is restricted to: its `--lang`, or those of its inline rules.
+fn query_languages(query_args: &[String]) -> Vec<String> {
+    match query_args {
+        [flag, yaml, ..] if flag == "--inline-rules" => rule_languages(yaml),
+        _ => query_args
+            .iter()
+            .skip_while(|arg| *arg != "--lang")
+            .nth(1)
+            .map(|language| vec![language.clone()])
+            .unwrap_or_default(),
+    }
+}
+
+fn relaxation_kind(description: &str) -> &'static str {
+    if description.starts_with("pattern strictness") {
+        "relax_strictness"
+    } else if description.starts_with("`stopBy") {
+        "add_stop_by"
+    } else {
+        "drop_inside"
+    }
+}
+
+fn profile_summary(profile: &FilterProfile) -> String {
+    let mut parts = Vec::new();
+    let lists = [("globs", &profile.globs), ("excludes", &profile.excludes), ("languages", &profile.languages)];
+    for (name, values) in lists {
+        if !values.is_empty() {
+            parts.push(format!("{} {}", name, values.join(", ")));
+        }
+    }
+    parts.join("; ")
+}
+
+/// `arg` as a POSIX shell word.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
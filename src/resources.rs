@@ -0,0 +1,65 @@
+use rmcp::model::{AnnotateAble, RawResource, Resource, ResourceContents};
+
+/// URI of ast-grep's rule JSON Schema.
+pub const RULE_SCHEMA_URI: &str = "astgrep://schema/rule";
+
+/// URI of the compact rule-syntax reference.
+pub const RULE_CHEATSHEET_URI: &str = "astgrep://docs/rule-cheatsheet";
+
+/// JSON Schema of ast-grep rule files, as accepted by `find_code_by_rule` and friends.
+pub const RULE_SCHEMA: &str = include_str!("resources/rule-schema.json");
+
+/// Markdown of the rule-syntax reference served at [`RULE_CHEATSHEET_URI`].
+pub const RULE_CHEATSHEET: &str = include_str!("resources/rule-cheatsheet.md");
+
+/// A document the server embeds, so agents can read it as an MCP resource while writing rules.
+struct Document {
+    uri: &'static str,
+    name: &'static str,
+    description: &'static str,
+    mime_type: &'static str,
+    text: &'static str,
+}
+
+const DOCUMENTS: &[Document] = &[
+    Document {
+        uri: RULE_SCHEMA_URI,
+        name: "ast-grep rule JSON Schema",
+        description: "JSON Schema of ast-grep YAML rules: every key, its type and allowed values.",
+        mime_type: "application/schema+json",
+        text: RULE_SCHEMA,
+    },
+    Document {
+        uri: RULE_CHEATSHEET_URI,
+        name: "ast-grep rule cheat sheet",
+        description: "Compact reference of rule syntax: metavariables, atomic, relational and composite keys, \
+                      constraints, transforms, fixes and common mistakes.",
+        mime_type: "text/markdown",
+        text: RULE_CHEATSHEET,
+    },
+];
+
+/// The embedded documents, for `resources/list`.
+pub fn list_resources() -> Vec<Resource> {
+    DOCUMENTS
+        .iter()
+        .map(|document| {
+            let mut resource = RawResource::new(document.uri, document.name);
+            resource.description = Some(document.description.to_string());
+            resource.mime_type = Some(document.mime_type.to_string());
+            resource.size = Some(document.text.len() as u32);
+            resource.no_annotation()
+        })
+        .collect()
+}
+
+/// Contents of the embedded document at `uri`, for `resources/read`.
+pub fn read_resource(uri: &str) -> Option<ResourceContents> {
+    let document = DOCUMENTS.iter().find(|document| document.uri == uri)?;
+    Some(ResourceContents::TextResourceContents {
+        uri: document.uri.to_string(),
+        mime_type: Some(document.mime_type.to_string()),
+        text: document.text.to_string(),
+        meta: None,
+    })
+}
@@ -0,0 +1,284 @@
+use crate::diff::{apply_edits, Edit};
+use crate::fingerprint::Fnv1a;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// Rule id of the rule `preview_match_rewrite` generates for a rewrite with transforms.
+pub const TRANSFORM_RULE_ID: &str = "preview-match-rewrite";
+
+/// The cases ast-grep's `convert` transform converts to.
+const CASES: &[&str] = &["lowerCase", "upperCase", "capitalize", "camelCase", "snakeCase", "kebabCase", "pascalCase"];
+
+/// The separators ast-grep's `convert` transform splits words at.
+const SEPARATORS: &[&str] = &["caseChange", "dash", "dot", "slash", "space", "underscore"];
+
+/// The ast-grep spelling of a case or separator name given in any common spelling, e.g.
+/// `snake_case` or `snake-case` for `snakeCase`.
+fn spelled(name: &str, known: &[&'static str]) -> Option<&'static str> {
+    let plain = |text: &str| text.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+    known.iter().copied().find(|known| plain(known) == plain(name))
+}
+
+/// The ast-grep transformation for one entry of a rewrite's `transform`: either an ast-grep
+/// transformation as is (`substring`, `replace` or `convert`), or the flat shorthand
+/// `{source, to_case, separated_by}`, `{source, replace, by}` or `{source, start_char, end_char}`.
+fn transformation(name: &str, spec: &Value) -> Result<Value, String> {
+    let invalid = |reason: String| format!("Invalid transform {}: {}", name, reason);
+    let Some(spec) = spec.as_object() else {
+        return Err(invalid("it must be an object".to_string()));
+    };
+    if let [(kind, _)] = spec.iter().collect::<Vec<_>>()[..] {
+        if ["substring", "replace", "convert"].contains(&kind.as_str()) {
+            return Ok(Value::Object(spec.clone()));
+        }
+    }
+    let source = match spec.get("source").and_then(Value::as_str) {
+        Some(source) if source.starts_with('$') => source,
+        _ => return Err(invalid("source must be a metavariable such as $NAME".to_string())),
+    };
+    let string = |key: &str| spec.get(key).and_then(Value::as_str);
+    let (kind, mut body, known): (&str, Map<String, Value>, &[&str]) = if let Some(case) = string("to_case") {
+        let case = spelled(case, CASES).ok_or_else(|| invalid(format!("to_case must be one of {}", CASES.join(", "))))?;
+        let mut body = Map::from_iter([("toCase".to_string(), json!(case))]);
+        if let Some(separators) = spec.get("separated_by") {
+            let separators = separators
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|s| s.as_str().and_then(|s| spelled(s, SEPARATORS)).map(Value::from))
+                .collect::<Option<Vec<_>>>()
+                .filter(|separators| !separators.is_empty())
+                .ok_or_else(|| invalid(format!("separated_by must list some of {}", SEPARATORS.join(", "))))?;
+            body.insert("separatedBy".to_string(), Value::Array(separators));
+        }
+        ("convert", body, &["to_case", "separated_by"])
+    } else if let Some(pattern) = string("replace") {
+        let by = string("by").ok_or_else(|| invalid("replace needs by, the replacement text".to_string()))?;
+        let body = Map::from_iter([("replace".to_string(), json!(pattern)), ("by".to_string(), json!(by))]);
+        ("replace", body, &["replace", "by"])
+    } else if spec.contains_key("start_char") || spec.contains_key("end_char") {
+        let mut body = Map::new();
+        for (key, field) in [("start_char", "startChar"), ("end_char", "endChar")] {
+            if let Some(index) = spec.get(key) {
+                let index = index.as_i64().ok_or_else(|| invalid(format!("{} must be an integer", key)))?;
+                body.insert(field.to_string(), json!(index));
+            }
+        }
+        ("substring", body, &["start_char", "end_char"])
+    } else {
+        return Err(invalid("give to_case, replace and by, or start_char/end_char".to_string()));
+    };
+    if let Some(key) = spec.keys().find(|key| *key != "source" && !known.contains(&key.as_str())) {
+        return Err(invalid(format!("unexpected '{}' for a {} transform", key, kind)));
+    }
+    body.insert("source".to_string(), json!(source));
+    Ok(json!({ kind: body }))
+}
+
+/// The `transform` section of a rule for a rewrite's transforms, keyed by the new metavariable
+/// names (without `$`), so that the rewrite template can use e.g. `$CAMEL` for a converted `$NAME`.
+pub fn transform_section(transforms: &BTreeMap<String, Value>) -> Result<Map<String, Value>, String> {
+    let mut section = Map::new();
+    for (name, spec) in transforms {
+        let name = name.trim_start_matches('$');
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+            return Err(format!("Invalid transform name '{}': it must be a metavariable name such as NEW_NAME.", name));
+        }
+        section.insert(name.to_string(), transformation(name, spec)?);
+    }
+    Ok(section)
+}
+
+/// The inline rule matching `pattern` in `language` with `transforms`, whose matches carry the
+/// transformed metavariables and their `fix` rewritten.
+pub fn transform_rule(
+    pattern: &str,
+    language: &str,
+    transforms: &BTreeMap<String, Value>,
+    fix: &str,
+) -> Result<String, String> {
+    let rule = json!({
+        "id": TRANSFORM_RULE_ID,
+        "language": language,
+        "rule": { "pattern": pattern },
+        "transform": transform_section(transforms)?,
+        "fix": fix,
+    });
+    serde_yaml::to_string(&rule).map_err(|e| e.to_string())
+}
+
+/// A rewrite template applied to one match, as `preview_match_rewrite` reports it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatchRewrite {
+    /// The text of the match.
+    pub original: String,
+    /// The text the match would be replaced with.
+    pub replacement: String,
+    /// The match's lines with the replacement spliced in, when the match can be located in them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines_after: Option<String>,
+    /// The text the match captured for each metavariable of the template.
+    pub metavariables: BTreeMap<String, String>,
+    /// Metavariables of the template the match did not capture; they are replaced with nothing.
+    pub unbound: Vec<String>,
+}
+
+/// The names of the metavariables `template` refers to (`$NAME`, `$$NAME` or `$$$NAME`), in order
+/// of first use. `$` without a name (as in `$$$`) is literal.
+fn referenced_names(template: &str) -> Vec<String> {
+    let bytes = template.as_bytes();
+    let mut found: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'$' {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i] == b'$' && i - start < 3 {
+            i += 1;
+        }
+        let name_start = i;
+        while i < bytes.len() && (bytes[i].is_ascii_uppercase() || bytes[i].is_ascii_digit() || bytes[i] == b'_') {
+            i += 1;
+        }
+        let name = &template[name_start..i];
+        if name.starts_with(|c: char| c.is_ascii_uppercase() || c == '_') && !found.iter().any(|known| known == name) {
+            found.push(name.to_string());
+        }
+    }
+    found
+}
+
+fn byte_offset(node: &Value, end: &str) -> Option<usize> {
+    node.pointer(&format!("/range/byteOffset/{}", end)).and_then(Value::as_u64).map(|offset| offset as usize)
+}
+
+/// The text match `m` captured for a metavariable: a single node's text, the source spanned by
+/// the nodes of a multi metavariable (separators included), or a transformed value.
+fn captured(m: &Value, name: &str) -> Option<String> {
+    let variables = m.get("metaVariables")?;
+    if let Some(node) = variables.pointer(&format!("/single/{}", name)) {
+        return Some(node.get("text").and_then(Value::as_str).unwrap_or("").to_string());
+    }
+    if let Some(nodes) = variables.pointer(&format!("/multi/{}", name)).and_then(Value::as_array) {
+        let (Some(first), Some(last)) = (nodes.first(), nodes.last()) else {
+            return Some(String::new());
+        };
+        let text = m.get("text").and_then(Value::as_str).unwrap_or("");
+        let span = match (byte_offset(m, "start"), byte_offset(first, "start"), byte_offset(last, "end")) {
+            (Some(base), Some(start), Some(end)) => text.get(start.checked_sub(base)?..end.checked_sub(base)?),
+            _ => None,
+        };
+        return Some(match span {
+            Some(span) => span.to_string(),
+            None => nodes.iter().filter_map(|n| n.get("text").and_then(Value::as_str)).collect::<Vec<_>>().join(" "),
+        });
+    }
+    variables.pointer(&format!("/transformed/{}", name))?.as_str().map(str::to_string)
+}
+
+/// `lines` with `text` at (0-based, character) `column` of its first line replaced by `replacement`.
+fn splice(lines: &str, column: usize, text: &str, replacement: &str) -> Option<String> {
+    let at = lines.char_indices().nth(column).map_or(lines.len(), |(at, _)| at);
+    lines[at..].starts_with(text).then(|| format!("{}{}{}", &lines[..at], replacement, &lines[at + text.len()..]))
+}
+
+/// The preview of the rewrite template `template` for the ast-grep JSON match `m`, found by
+/// `ast-grep run --rewrite` (or a scan with the template as the rule's `fix`), whose
+/// `replacement` is the rewritten text; `None` when `m` has no replacement.
+pub fn match_rewrite(m: &Value, template: &str) -> Option<MatchRewrite> {
+    let replacement = m.get("replacement").and_then(Value::as_str)?.to_string();
+    let original = m.get("text").and_then(Value::as_str).unwrap_or("").to_string();
+    let mut metavariables = BTreeMap::new();
+    let mut unbound = Vec::new();
+    for name in referenced_names(template) {
+        match captured(m, &name) {
+            Some(text) => {
+                metavariables.insert(name, text);
+            }
+            None => unbound.push(name),
+        }
+    }
+    let column = m.pointer("/range/start/column").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let lines_after =
+        m.get("lines").and_then(Value::as_str).and_then(|lines| splice(lines, column, &original, &replacement));
+    Some(MatchRewrite { original, replacement, lines_after, metavariables, unbound })
+}
+
+/// One fix of [`apply_fixes`].
+#[derive(Debug, Serialize)]
+pub struct AppliedFix {
+    #[serde(rename = "ruleId", skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
+    /// 0-based line of the match in the original code.
+    pub line: u64,
+    pub original: String,
+    pub replacement: String,
+    /// Identifies the fix of a planned rewrite (see [`fix_fingerprint`]), so that apply_rewrite can
+    /// be told to apply only some of the fixes preview_rewrite showed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+/// Fingerprint of the fix `edit` of the file `path` whose original text is `original`: unlike a
+/// match's fingerprint it includes the position, so equal fixes of the same file differ, and it
+/// no longer matches once the file changes there.
+pub fn fix_fingerprint(path: &str, edit: &Edit, original: &str) -> String {
+    let mut hash = Fnv1a::new();
+    hash.write(path.as_bytes());
+    hash.write(&(edit.start as u64).to_le_bytes());
+    hash.write(original.as_bytes());
+    hash.write(edit.replacement.as_bytes());
+    format!("{:016x}", hash.finish())
+}
+
+/// The fixes of `matches` (ast-grep JSON matches of a scan of `code` with a rule that has a
+/// `fix`) as edits of `code`, in the way `ast-grep scan --update-all` applies them: each match's
+/// `replacement` goes in place of its `replacementOffsets` (its own range when absent), and a
+/// match that overlaps one before it is left alone. Returns the edits in order, the fixes they
+/// make and how many overlapping matches were left alone; matches without a `replacement` are
+/// ignored.
+pub fn planned_fixes(code: &str, matches: &[Value]) -> (Vec<Edit>, Vec<AppliedFix>, usize) {
+    let mut candidates: Vec<(usize, usize, &Value, &str)> = matches
+        .iter()
+        .filter_map(|m| {
+            let replacement = m.get("replacement").and_then(Value::as_str)?;
+            let offset = |edge: &str| {
+                let at = m.pointer(&format!("/replacementOffsets/{}", edge));
+                at.or_else(|| m.pointer(&format!("/range/byteOffset/{}", edge)))?.as_u64().map(|at| at as usize)
+            };
+            let (start, end) = (offset("start")?, offset("end")?);
+            code.get(start..end)?;
+            Some((start, end, m, replacement))
+        })
+        .collect();
+    candidates.sort_by_key(|&(start, end, _, _)| (start, end));
+
+    let mut edits: Vec<Edit> = Vec::new();
+    let mut applied = Vec::new();
+    let mut overlapping = 0;
+    for (start, end, m, replacement) in candidates {
+        if edits.last().is_some_and(|last| start < last.end) {
+            overlapping += 1;
+            continue;
+        }
+        edits.push(Edit { start, end, replacement: replacement.to_string() });
+        applied.push(AppliedFix {
+            rule_id: m.get("ruleId").and_then(Value::as_str).map(str::to_string),
+            line: m.pointer("/range/start/line").and_then(Value::as_u64).unwrap_or(0),
+            original: code[start..end].to_string(),
+            replacement: replacement.to_string(),
+            fingerprint: None,
+        });
+    }
+    (edits, applied, overlapping)
+}
+
+/// `code` with the fixes of `matches` applied, as planned by [`planned_fixes`]. Returns the new
+/// code, the fixes applied and how many overlapping matches were left alone.
+pub fn apply_fixes(code: &str, matches: &[Value]) -> (String, Vec<AppliedFix>, usize) {
+    let (edits, applied, overlapping) = planned_fixes(code, matches);
+    (apply_edits(code, &edits), applied, overlapping)
+}
@@ -0,0 +1,52 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RootError {
+    #[error("Path '{0}' must be absolute")]
+    NotAbsolute(PathBuf),
+
+    #[error("No allowed roots: start the server with --allowed-root or use a client that provides MCP roots")]
+    NoRoots,
+
+    #[error("Path '{0}' is outside the allowed roots")]
+    OutsideRoots(PathBuf),
+
+    #[error("Cannot access '{path}': {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+}
+
+/// Converts the `file://` URIs of MCP client roots to paths; other schemes are ignored.
+pub fn roots_from_uris<'a>(uris: impl IntoIterator<Item = &'a str>) -> Vec<PathBuf> {
+    uris.into_iter()
+        .filter_map(|uri| url::Url::parse(uri).ok())
+        .filter(|uri| uri.scheme() == "file")
+        .filter_map(|uri| uri.to_file_path().ok())
+        .collect()
+}
+
+/// Resolves `path` and checks that it lies inside one of `roots`.
+///
+/// Both sides are canonicalized, so `..` segments and symlinks cannot be used to escape a
+/// root. Roots that do not exist are ignored.
+pub fn resolve_within_roots(path: &Path, roots: &[PathBuf]) -> Result<PathBuf, RootError> {
+    if !path.is_absolute() {
+        return Err(RootError::NotAbsolute(path.to_path_buf()));
+    }
+    if roots.is_empty() {
+        return Err(RootError::NoRoots);
+    }
+
+    let resolved = path.canonicalize().map_err(|source| RootError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let inside = roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| resolved.starts_with(root));
+    if !inside {
+        return Err(RootError::OutsideRoots(path.to_path_buf()));
+    }
+
+    Ok(resolved)
+}
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Most rule sessions one client keeps; creating another drops the least recently used.
+pub const MAX_RULE_SESSIONS: usize = 16;
+
+/// A rule being built: its YAML, the utility rules it may use, the sample code it is tested on,
+/// and the matches of its last test.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleSession {
+    pub yaml: String,
+    pub utils: BTreeMap<String, Value>,
+    pub code: String,
+    /// Matches of the last test, `None` until the rule is tested, and again after the sample
+    /// code changes, since earlier matches then point into other code.
+    pub last_matches: Option<Vec<Value>>,
+    pub tests: usize,
+    #[serde(skip, default = "Instant::now")]
+    used: Instant,
+}
+
+/// The rule sessions of one client, by id.
+#[derive(Default)]
+pub struct RuleSessions {
+    sessions: Mutex<HashMap<String, RuleSession>>,
+}
+
+impl RuleSessions {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, RuleSession>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Starts a session with `yaml`, `utils` and `code`, returning its id and the id of the
+    /// session dropped to make room, if any.
+    pub fn create(&self, yaml: String, utils: BTreeMap<String, Value>, code: String) -> (String, Option<String>) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let session = RuleSession { yaml, utils, code, last_matches: None, tests: 0, used: Instant::now() };
+        let dropped = self.restore(id.clone(), session);
+        (id, dropped)
+    }
+
+    /// Puts `session` back under `id`, e.g. from a session snapshot, replacing a session with
+    /// that id; returns the id of the session dropped to make room, if any.
+    pub fn restore(&self, id: String, mut session: RuleSession) -> Option<String> {
+        let mut sessions = self.lock();
+        let mut dropped = None;
+        if !sessions.contains_key(&id) && sessions.len() >= MAX_RULE_SESSIONS {
+            dropped = sessions.iter().min_by_key(|(_, session)| session.used).map(|(id, _)| id.clone());
+            if let Some(id) = &dropped {
+                sessions.remove(id);
+            }
+        }
+        session.used = Instant::now();
+        sessions.insert(id, session);
+        dropped
+    }
+
+    /// Copies of all sessions with their ids, least recently used first.
+    pub fn list(&self) -> Vec<(String, RuleSession)> {
+        let mut sessions: Vec<(String, RuleSession)> =
+            self.lock().iter().map(|(id, session)| (id.clone(), session.clone())).collect();
+        sessions.sort_by_key(|(_, session)| session.used);
+        sessions
+    }
+
+    /// A copy of session `id`.
+    pub fn get(&self, id: &str) -> Option<RuleSession> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(id)?;
+        session.used = Instant::now();
+        Some(session.clone())
+    }
+
+    /// Changes session `id` with `change`, returning the session as changed; `None` if there is
+    /// no such session. A failed change leaves the session as it was.
+    pub fn update<F>(&self, id: &str, change: F) -> Option<Result<RuleSession, String>>
+    where
+        F: FnOnce(&mut RuleSession) -> Result<(), String>,
+    {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(id)?;
+        let mut changed = session.clone();
+        if let Err(e) = change(&mut changed) {
+            return Some(Err(e));
+        }
+        changed.used = Instant::now();
+        *session = changed.clone();
+        Some(Ok(changed))
+    }
+
+    /// Stores the matches of a test of session `id` that ran on `code`, returning the matches of
+    /// the previous test on the same code. Matches of a rule or code that changed during the test
+    /// are not stored.
+    pub fn record(&self, id: &str, yaml: &str, code: &str, matches: &[Value]) -> Option<Vec<Value>> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(id)?;
+        session.tests += 1;
+        if session.yaml != yaml || session.code != code {
+            return None;
+        }
+        session.last_matches.replace(matches.to_vec())
+    }
+
+    /// Ends session `id`, returning it.
+    pub fn remove(&self, id: &str) -> Option<RuleSession> {
+        self.lock().remove(id)
+    }
+
+    /// Ends all sessions.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+}
+
+/// `yaml` with fields of its (first) rule document set, by dotted path such as `rule.pattern` or
+/// `constraints.NAME.regex`. Missing mappings on the way are created; a `null` value removes the
+/// field. Later documents are kept as they are.
+pub fn set_rule_fields(yaml: &str, fields: &BTreeMap<String, Value>) -> Result<String, String> {
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(yaml) {
+        documents.push(serde_yaml::Value::deserialize(document).map_err(|e| format!("Invalid rule YAML: {}", e))?);
+    }
+    let Some(first) = documents.first_mut() else {
+        return Err("The rule YAML is empty".to_string());
+    };
+    for (path, value) in fields {
+        let keys: Vec<&str> = path.split('.').collect();
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(format!("Invalid field path '{}': use dotted keys such as rule.pattern", path));
+        }
+        let (last, parents) = keys.split_last().unwrap_or((&"", &[]));
+        let mut node = &mut *first;
+        for key in parents {
+            if node.is_null() {
+                *node = serde_yaml::Mapping::new().into();
+            }
+            let Some(mapping) = node.as_mapping_mut() else {
+                return Err(format!("Cannot set '{}': '{}' is not inside a mapping", path, key));
+            };
+            node = mapping.entry((*key).into()).or_insert(serde_yaml::Value::Null);
+        }
+        if node.is_null() {
+            *node = serde_yaml::Mapping::new().into();
+        }
+        let Some(mapping) = node.as_mapping_mut() else {
+            return Err(format!("Cannot set '{}': its parent is not a mapping", path));
+        };
+        if value.is_null() {
+            mapping.remove(*last);
+        } else {
+            mapping.insert((*last).into(), serde_yaml::to_value(value).map_err(|e| e.to_string())?);
+        }
+    }
+    let documents: Result<Vec<String>, _> = documents.iter().map(serde_yaml::to_string).collect();
+    Ok(documents.map_err(|e| e.to_string())?.join("---\n"))
+}
+
+/// Key of a match in the sample code, its byte range and text.
+fn match_key(m: &Value) -> (u64, u64, &str) {
+    let offset = |end: &str| m.pointer(&format!("/range/byteOffset/{}", end)).and_then(Value::as_u64).unwrap_or(0);
+    (offset("start"), offset("end"), m.get("text").and_then(Value::as_str).unwrap_or_default())
+}
+
+/// Texts of the matches of `current` that `previous` did not have, and of those it had that
+/// `current` does not, for tests of different rules on the same code.
+pub fn match_changes(previous: &[Value], current: &[Value]) -> (Vec<String>, Vec<String>) {
+    let only_in = |these: &[Value], others: &[Value]| -> Vec<String> {
+        let others: Vec<_> = others.iter().map(match_key).collect();
+        these
+            .iter()
+            .map(match_key)
+            .filter(|key| !others.contains(key))
+            .map(|(_, _, text)| text.to_string())
+            .collect()
+    };
+    (only_in(current, previous), only_in(previous, current))
+}
@@ -0,0 +1,270 @@
+use crate::lint::closest_key;
+use crate::resources::RULE_SCHEMA;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Most violations reported for one rule; the rest are usually follow-on errors.
+pub const MAX_VIOLATIONS: usize = 10;
+
+/// A place where a rule does not follow the rule schema.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaViolation {
+    /// 1-based YAML document of the rule, when it has several.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<usize>,
+    /// Property path of the offending value, e.g. `rule.all[1]`; empty for the rule itself.
+    pub path: String,
+    pub message: String,
+    /// How to fix it, e.g. the key that was probably meant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(document) = self.document {
+            write!(f, "document {}: ", document)?;
+        }
+        if self.path.is_empty() {
+            write!(f, "{} at the top level", self.message)?;
+        } else {
+            write!(f, "{} at {}", self.message, self.path)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "; {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+fn violation(path: &str, message: String) -> SchemaViolation {
+    SchemaViolation { document: None, path: path.to_string(), message, hint: None }
+}
+
+fn rule_schema() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+    SCHEMA.get_or_init(|| serde_json::from_str(RULE_SCHEMA).expect("the embedded rule schema is valid JSON"))
+}
+
+/// Checks every document of a YAML rule against the embedded rule JSON Schema, so mistakes such
+/// as misspelled keys are reported with their property path before ast-grep runs.
+///
+/// Only the parts of JSON Schema the embedded schema uses are supported: `$ref` to its
+/// definitions, `type`, `enum`, `anyOf`, `properties`, `additionalProperties`, `required`,
+/// `minProperties`, `maxProperties`, `items` and `minimum`.
+pub fn validate_rule_yaml(yaml: &str) -> Result<(), Vec<SchemaViolation>> {
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(yaml) {
+        let value = serde_yaml::Value::deserialize(document)
+            .map_err(|e| vec![violation("", format!("invalid YAML ({})", e))])?;
+        if !value.is_null() {
+            documents.push(value);
+        }
+    }
+
+    let several = documents.len() > 1;
+    let mut violations = Vec::new();
+    for (index, document) in documents.into_iter().enumerate() {
+        let value = serde_json::to_value(document)
+            .map_err(|e| vec![violation("", format!("unsupported YAML ({})", e))])?;
+        let mut validator = Validator { root: rule_schema(), violations: Vec::new() };
+        validator.validate(rule_schema(), &value, "");
+        for mut violation in validator.violations {
+            violation.document = several.then_some(index + 1);
+            violations.push(violation);
+        }
+    }
+    if violations.is_empty() {
+        return Ok(());
+    }
+    violations.truncate(MAX_VIOLATIONS);
+    Err(violations)
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn kind_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn article(kind: &str) -> String {
+    match kind {
+        "array" | "integer" | "object" => format!("an {}", kind),
+        _ => format!("a {}", kind),
+    }
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    violations: Vec<SchemaViolation>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, path: &str, message: String) {
+        self.violations.push(violation(path, message));
+    }
+
+    /// Follows `$ref`s to the schema they point to.
+    fn resolve(&self, mut schema: &'a Value) -> &'a Value {
+        while let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let Some(name) = reference.strip_prefix("#/definitions/") else {
+                break;
+            };
+            schema = &self.root["definitions"][name];
+        }
+        schema
+    }
+
+    /// Kinds of values `schema` can accept, for choosing among `anyOf` branches.
+    fn accepted_kinds(&self, schema: &'a Value) -> Vec<&'static str> {
+        let schema = self.resolve(schema);
+        if let Some(kind) = schema.get("type").and_then(Value::as_str) {
+            return vec![match kind {
+                "object" => "object",
+                "array" => "array",
+                "string" => "string",
+                "integer" => "integer",
+                "boolean" => "boolean",
+                _ => "number",
+            }];
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let mut kinds: Vec<&'static str> = values.iter().map(kind_of).collect();
+            kinds.dedup();
+            return kinds;
+        }
+        let branches = schema.get("anyOf").and_then(Value::as_array).into_iter().flatten();
+        branches.flat_map(|branch| self.accepted_kinds(branch)).collect()
+    }
+
+    fn validate(&mut self, schema: &'a Value, value: &Value, path: &str) {
+        let schema = self.resolve(schema);
+
+        if let Some(branches) = schema.get("anyOf").and_then(Value::as_array) {
+            // Report the errors of the branch meant for this kind of value, not of every branch
+            let mut best: Option<Vec<SchemaViolation>> = None;
+            for branch in branches {
+                let kinds = self.accepted_kinds(branch);
+                let kind = kind_of(value);
+                let accepted = kinds.contains(&kind) || (kind == "integer" && kinds.contains(&"number"));
+                if !accepted {
+                    continue;
+                }
+                let mut validator = Validator { root: self.root, violations: Vec::new() };
+                validator.validate(branch, value, path);
+                if validator.violations.is_empty() {
+                    return;
+                }
+                if best.as_ref().is_none_or(|best| validator.violations.len() < best.len()) {
+                    best = Some(validator.violations);
+                }
+            }
+            match best {
+                Some(violations) => self.violations.extend(violations),
+                None => {
+                    let mut kinds: Vec<String> = branches
+                        .iter()
+                        .flat_map(|branch| self.accepted_kinds(branch))
+                        .map(article)
+                        .collect();
+                    kinds.dedup();
+                    self.report(path, format!("expected {}, found {}", kinds.join(" or "), article(kind_of(value))));
+                }
+            }
+            return;
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                let names: Vec<String> = allowed.iter().map(|v| v.as_str().map_or(v.to_string(), str::to_string)).collect();
+                let found = value.as_str().map_or(value.to_string(), |s| format!("'{}'", s));
+                self.report(path, format!("{} is not one of: {}", found, names.join(", ")));
+            }
+            return;
+        }
+
+        if let Some(kind) = schema.get("type").and_then(Value::as_str) {
+            let found = kind_of(value);
+            let matches = found == kind || (kind == "number" && found == "integer");
+            if !matches {
+                self.report(path, format!("expected {}, found {}", article(kind), article(found)));
+                return;
+            }
+        }
+
+        match value {
+            Value::Object(object) => self.validate_object(schema, object, path),
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        self.validate(item_schema, item, &format!("{}[{}]", path, index));
+                    }
+                }
+            }
+            Value::Number(number) => {
+                let minimum = schema.get("minimum").and_then(Value::as_f64);
+                if let (Some(minimum), Some(number)) = (minimum, number.as_f64()) {
+                    if number < minimum {
+                        self.report(path, format!("{} is less than {}", number, minimum));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn validate_object(&mut self, schema: &'a Value, object: &serde_json::Map<String, Value>, path: &str) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let known: Vec<&str> = properties.into_iter().flat_map(|p| p.keys().map(String::as_str)).collect();
+        for required in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+            if let Some(required) = required.as_str().filter(|key| !object.contains_key(*key)) {
+                self.report(path, format!("missing required key '{}'", required));
+            }
+        }
+        let count = object.len() as u64;
+        if schema.get("minProperties").and_then(Value::as_u64).is_some_and(|min| count < min) {
+            self.report(path, "expected at least one key".to_string());
+        }
+        if schema.get("maxProperties").and_then(Value::as_u64).is_some_and(|max| count > max) {
+            self.report(path, format!("expected only one of: {}", known.join(", ")));
+        }
+
+        for (key, value) in object {
+            let key_path = child_path(path, key);
+            if let Some(property) = properties.and_then(|p| p.get(key)) {
+                self.validate(property, value, &key_path);
+                continue;
+            }
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    let hint = match closest_key(key, &known) {
+                        Some(closest) => format!("did you mean '{}'?", closest),
+                        None => format!("valid keys here are: {}", known.join(", ")),
+                    };
+                    self.violations.push(SchemaViolation {
+                        hint: Some(hint),
+                        ..violation(path, format!("unknown key '{}'", key))
+                    });
+                }
+                Some(additional @ Value::Object(_)) => self.validate(additional, value, &key_path),
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,15 +1,120 @@
-use crate::command::run_ast_grep;
+use crate::aggregate::{aggregate_metavariable, format_aggregate_as_text, metavariable_name, MetavariableAggregate};
+use crate::apply::{plan_rewrite, write_planned_file, PlannedFile};
+use crate::approval::{
+    approval_of, approval_request, approved_edits, file_approval_message, match_approval_message, Approval,
+    ApprovalScope, APPROVAL_SCOPES,
+};
+use crate::auth::Grant;
+use crate::backup::{load_backup, remove_backup, restore_backup, Backup};
+use crate::benchmark::{
+    first_rule_document, is_language_file, language_extensions, language_for_path, profile_candidates, rule_language,
+    BenchmarkReport, FileTiming,
+};
+use crate::callgraph::{build_call_graph, call_graph_rules, CALL_GRAPH_FORMATS};
+use crate::checkpoint::{append_part, load_checkpoint, plan_hash, remove_checkpoint, save_checkpoint, Checkpoint};
+use crate::command::{run_command, run_command_bytes, LimitedResult};
 use crate::config::Config;
-use crate::format::format_matches_as_text;
+use crate::cooccur::{
+    cooccurrence_rules, find_cooccurrences, CooccurrenceMode, COOCCURRENCE_MODES, COOCCURRENCE_SCOPES,
+};
+use crate::diagnostics::matches_to_diagnostics;
+use crate::dialect::{dialect_queries, Dialect, DIALECTS};
+use crate::diff::{count_hunks, unified_diff, CONTEXT_LINES};
+use crate::dump::{DumpFormat, DUMP_FORMATS};
+use crate::effectiveness::{effectiveness_report, last_matched, parse_rule_sources, rule_files};
+use crate::encoding::{fix_match_encodings, read_source, reread_match_text, TEXT_SOURCES};
+use crate::engine::{run_ast_grep, run_ast_grep_limited};
+use crate::eol::{normalize_line_endings, LINE_ENDINGS};
+use crate::export::export_matches;
+use crate::explain::{dump_has_errors, rule_pattern, PatternCheck, QueryPlan};
+use crate::filter::{
+    detect_ignore_files, drop_generated_files, exclude_glob_args, ignore_note, no_ignore_args, not_searched,
+    KeptMatches, NotSearched, SkippedFile, DEFAULT_EXCLUDES,
+};
+use crate::fingerprint::add_fingerprints;
+use crate::format::{
+    cap_matches_per_file, collapse_duplicates, format_match_headers, get_supported_languages, match_resource_link,
+    write_matches_as_text, CappedFile, MAX_TEXT_BYTES, OUTPUT_FORMATS,
+};
+use crate::gitrev::{is_searchable, parse_cat_file_batch, parse_ls_tree, TreeFile, MAX_REV_FILES};
+use crate::grammar::{
+    build_command, clone_command, grammar_source_dir, known_grammar, library_file_name, KNOWN_GRAMMARS,
+};
+use crate::lint::{lint_rule, pattern_error, LintReport};
+use crate::logging;
+use crate::manifest::{is_dirty, query_hash, record_manifest, utc_timestamp, ScanManifest};
+use crate::kinds::{dump_prefix, kind_frequencies, MAX_DUMP_SOURCE_BYTES};
+use crate::header::{comment_style, has_header, insert_header};
+use crate::inline::{has_fix, with_language, with_utils};
+use crate::init::{starter_config, survey_project};
+use crate::inspect::{add_inspect_counters, inspect_args, ScanProfile};
+use crate::ranges::{normalize_match_ranges, normalize_source_ranges, sort_by_position};
+use crate::refine::{
+    extract_pattern, refine_prompt, refine_system_prompt, RefineAttempt, DEFAULT_REFINE_ATTEMPTS, MAX_REFINE_ATTEMPTS,
+    REFINE_MAX_TOKENS,
+};
+use crate::relax::{
+    diagnosis_text, no_match_report, relaxations, stop_by_retry, strictness_relaxations, NoMatchReport, Relaxation,
+    RelaxationAttempt, SearchScope, FALLBACKS,
+};
+use crate::metrics::{file_metrics, metrics_rules};
+use crate::missing::{candidate_files, missing_report};
+use crate::multi::{
+    constrained_rule, folder_languages, multi_language_rules, ranked_languages, LanguageCounts, MAX_DETECTED_LANGUAGES,
+};
+use crate::outline::{build_outline, outline_rules};
+#[cfg(feature = "parsers")]
+use crate::parsers::dump_tree;
+#[cfg(feature = "plugins")]
+use crate::plugin::PluginRoute;
+use crate::profiles::FilterProfile;
+use crate::playground::{enclosing_node, first_divergence, render_dump};
+use crate::resources::{self, RULE_SCHEMA_URI};
+use crate::rewrite::{apply_fixes, match_rewrite, transform_rule};
+use crate::roots::{resolve_within_roots, roots_from_uris};
+use crate::rulesession::{match_changes, set_rule_fields, RuleSessions, MAX_RULE_SESSIONS};
+use crate::schema::validate_rule_yaml;
+use crate::sgconfig::{add_custom_language, custom_language_extensions, CustomLanguage};
+use crate::session::{SessionGuard, SessionRegistry, SESSION_CHECK_INTERVAL};
+use crate::shard::{count_files, list_files, plan_shards, SHARD_TARGET_FILES};
+use crate::snapshot::{
+    load_snapshot, save_snapshot, RuleSessionSnapshot, SessionSnapshot, WatchSnapshot, SNAPSHOT_VERSION,
+};
+use crate::stats;
+use crate::store::MatchStore;
+use crate::todo::{extract_todos, todo_rules, DEFAULT_TAGS, TODO_LANGUAGES};
+use crate::suggest::{parse_dump, suggest_patterns};
+use crate::suppress::insert_suppression;
+use crate::tempfiles::SessionTempDir;
+use crate::trend::{
+    parse_commits, sample_commits, trend_summary, TrendPoint, Worktree, COMMIT_FORMAT, MAX_REVISIONS,
+};
+use crate::toolset::{
+    add_parameter_values, ast_grep_version, call_deprecations, deprecate, one_of, unavailable_reason, DEPRECATIONS,
+    READ_ONLY_REASON, WRITE_TOOLS,
+};
+use crate::watch::{
+    config_watch_paths, match_summary, plan_rescan, rule_languages, ChangeFilter, MatchChanges, RescanPlan, WatchInfo,
+    WatchRegistry, WatchedMatches, CONFIG_LOGGER, WATCH_DEBOUNCE, WATCH_LOGGER, WATCH_MAX_BATCH_DELAY,
+};
 use rmcp::{
-    handler::server::{router::tool::ToolRouter, wrapper::Parameters},
+    handler::server::{router::tool::ToolRouter, tool::ToolCallContext, wrapper::Parameters},
     model::*,
-    schemars, tool, tool_handler, tool_router,
+    schemars,
+    service::{ElicitationMode, NotificationContext, RequestContext},
+    tool, tool_router,
     ErrorData as McpError,
-    ServerHandler,
+    Peer, RoleServer, ServerHandler, ServiceError,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::Instrument;
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct DumpSyntaxTreeParams {
@@ -32,6 +137,113 @@ pub struct TestMatchCodeRuleParams {
     pub code: String,
     /// The ast-grep YAML rule to search. It must have id, language, rule fields.
     pub yaml: String,
+    /// Named utility rules the yaml can use with `matches: <name>`, e.g. {\"is-literal\": {\"any\": [{\"kind\": \"string\"},
+    /// {\"kind\": \"number\"}]}}; they are added to the local utils of every rule document
+    #[serde(default)]
+    pub utils: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RewriteCodeParams {
+    /// The code to rewrite
+    pub code: String,
+    /// The language of the code; set on the rule documents that have no language
+    pub language: String,
+    /// The ast-grep YAML rule whose `fix` rewrites the matches, e.g.
+    /// \"id: x\\nrule:\\n  pattern: var $A = $B\\nfix: let $A = $B\"
+    pub yaml: String,
+    /// Named utility rules the yaml can use with `matches: <name>`; they are added to the local utils of every
+    /// rule document
+    #[serde(default)]
+    pub utils: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PreviewRewriteParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The ast-grep YAML rule whose `fix` rewrites the matches. It must have id, language, rule and fix fields.
+    pub yaml: String,
+    /// Named utility rules the yaml can use with `matches: <name>`; they are added to the local utils of every
+    /// rule document
+    #[serde(default)]
+    pub utils: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ApplyRewriteParams {
+    /// The absolute path to the project folder. It must be absolute path and inside an allowed root.
+    pub project_folder: String,
+    /// The ast-grep YAML rule whose `fix` rewrites the matches. It must have id, language, rule and fix fields.
+    pub yaml: String,
+    /// Named utility rules the yaml can use with `matches: <name>`; they are added to the local utils of every
+    /// rule document
+    #[serde(default)]
+    pub utils: BTreeMap<String, Value>,
+    /// Ask the user, through MCP elicitation, to approve each change before it is written; rejected changes are
+    /// left out. Needs a client that supports elicitation
+    #[serde(default)]
+    pub interactive: bool,
+    /// With interactive, what each request approves: 'file' (the default), a file's diff, or 'match', a single fix
+    #[serde(default)]
+    pub approve: String,
+    /// Only rewrite these files, as paths relative to project_folder like preview_rewrite lists them
+    /// (default: every file)
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Only apply the fixes with these fingerprints from preview_rewrite (default: every fix)
+    #[serde(default)]
+    pub fingerprints: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UndoRewriteParams {
+    /// The backup id apply_rewrite returned; defaults to the session's most recent apply_rewrite that was not undone
+    #[serde(default)]
+    pub backup_id: String,
+    /// Also restore files that were changed after they were rewritten, losing those changes
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CreateRuleSessionParams {
+    /// The ast-grep YAML rule to start from. It must have id, language, rule fields.
+    pub yaml: String,
+    /// The sample code to test the rule against
+    pub code: String,
+    /// Named utility rules the yaml can use with `matches: <name>`; they are added to the local utils
+    /// of every rule document when the rule is tested and finalized
+    #[serde(default)]
+    pub utils: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UpdateRuleSessionParams {
+    /// The rule session id returned by create_rule_session
+    pub rule_session_id: String,
+    /// Fields of the rule to set, by dotted path, e.g. {"rule.pattern": "foo($A)", "constraints.A.regex": "^x"};
+    /// a null value removes the field
+    #[serde(default)]
+    pub set: BTreeMap<String, Value>,
+    /// A whole new rule YAML, applied before set
+    #[serde(default)]
+    pub yaml: Option<String>,
+    /// New sample code, replacing the current one
+    #[serde(default)]
+    pub code: Option<String>,
+    /// Code to add at the end of the sample code, on a new line
+    #[serde(default)]
+    pub append_code: Option<String>,
+    /// Utility rules to add or replace by name; a null value removes one
+    #[serde(default)]
+    pub utils: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RuleSessionParams {
+    /// The rule session id returned by create_rule_session
+    pub rule_session_id: String,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -39,315 +251,5493 @@ pub struct FindCodeParams {
     /// The absolute path to the project folder. It must be absolute path.
     pub project_folder: String,
     /// The ast-grep pattern to search for. Note, the pattern must have valid AST structure.
+    #[serde(default)]
     pub pattern: String,
-    /// The language of the code. Supported: bash, c, cpp, csharp, css, elixir, go, haskell, html, java, javascript, json, jsx, kotlin, lua, nix, php, python, ruby, rust, scala, solidity, swift, tsx, typescript, yaml. If not specified, will be auto-detected based on file extensions.
+    /// The language of the code. Supported: bash, c, cpp, csharp, css, elixir, go, haskell, html, java, javascript, json, jsx, kotlin, lua, nix, php, python, ruby, rust, scala, solidity, swift, tsx, typescript, yaml. If not specified, the pattern is searched in each language detected in the project (the 4 with the most files, json and yaml left out), as with patterns.
     #[serde(default)]
     pub language: String,
-    /// Maximum results to return
+    /// Instead of pattern and language, one pattern per language to search for in one call, e.g.
+    /// {\"javascript\": \"console.log($$$)\", \"python\": \"print($$$)\", \"go\": \"fmt.Println($$$)\"}.
+    /// Each match has the rule id find-code-<language> and the language it was found in
     #[serde(default)]
-    pub max_results: i32,
-    /// 'text' or 'json'
-    #[serde(default = "default_text")]
-    pub output_format: String,
+    pub patterns: BTreeMap<String, String>,
+    /// Limit what metavariables may match, e.g. {"$NAME": {"regex": "^Test"}}, or {"$NAME": "^Test"}
+    /// for short; each constraint may have regex, kind and pattern
+    #[serde(default)]
+    pub constraints: BTreeMap<String, Value>,
+    #[serde(flatten)]
+    pub options: FindOptions,
 }
 
 fn default_text() -> String {
     "text".to_string()
 }
 
+/// Result-shaping and filtering options shared by `find_code` and `find_code_by_rule`.
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct FindOptions {
+    /// Maximum results to return; the search stops once it has found this many
+    #[serde(default)]
+    pub max_results: i32,
+    /// 'text', 'json', 'headers' (one line per match with an id to pass to get_match_details) or
+    /// 'diagnostics' (LSP publishDiagnostics entries per file: uri, range, severity, code, message, relatedInformation)
+    #[serde(default = "default_text")]
+    pub output_format: String,
+    /// Also search dependency and build directories (node_modules, target, .venv, dist, build, .git), which are skipped by default
+    #[serde(default)]
+    pub include_vendored: bool,
+    /// Also search files excluded by ignore files (.gitignore, .ignore, .sgignore)
+    #[serde(default)]
+    pub no_ignore: bool,
+    /// Keep matches in minified or binary-looking files (e.g. *.min.js), which are skipped by default
+    #[serde(default)]
+    pub include_minified: bool,
+    /// Group matches with identical text into one entry listing every location
+    #[serde(default)]
+    pub collapse_duplicates: bool,
+    /// More absolute project folders to search along with project_folder; they are scanned concurrently
+    #[serde(default)]
+    pub additional_folders: Vec<String>,
+    /// Only scan these shard numbers of a sharded scan, to resume after a partial result
+    #[serde(default)]
+    pub shards: Vec<usize>,
+    /// Report where the time went (ast-grep vs post-processing) and ast-grep's file/rule counters
+    #[serde(default)]
+    pub profile: bool,
+    /// Report what the search left out of the project folders (hidden, vendored, ignored and
+    /// unsupported-language entries), which takes another walk of them; reported anyway when nothing
+    /// matches
+    #[serde(default)]
+    pub report_not_searched: bool,
+    /// When nothing matches, retry relaxed variants of the query (stopBy: end, lower strictness,
+    /// without each inside constraint) and report which one first finds matches
+    #[serde(default)]
+    pub diagnose: bool,
+    /// What to retry when nothing matches: by default, a rule whose inside/has/precedes/follows
+    /// rules lack stopBy is retried once with stopBy: end; 'relaxed' then also retries with relaxed,
+    /// then signature, pattern strictness; 'none' retries nothing. Such results are labelled as
+    /// relaxed matches
+    #[serde(default)]
+    pub fallback: String,
+    /// Instead of the matches, return the distinct values bound to this metavariable (e.g. '$MODULE')
+    /// with how many matches and files bind each; max_results limits the number of values
+    #[serde(default)]
+    pub aggregate: String,
+    /// Name of a filter profile from the server's --filter-profiles file (see doctor) whose globs,
+    /// excludes and languages restrict the files searched, e.g. 'tests-only'
+    #[serde(default)]
+    pub filter_profile: String,
+    /// Where match text comes from: 'cli' (default, ast-grep's text) or 'file', re-read from disk with
+    /// the original whitespace, including the first line's indentation; matches in files changed since
+    /// the scan keep ast-grep's text
+    #[serde(default)]
+    pub text_source: String,
+    /// 'keep' (default) reports matches as ast-grep does; 'lf' reports matches in files with CRLF or
+    /// CR line endings as if they were LF: text without carriage returns, and lines and byte offsets
+    /// as editors count them. Each match then names its file's line endings in `eol`
+    #[serde(default)]
+    pub line_endings: String,
+    /// Keep at most this many matches per file (0, the default, for no limit), so one file full of
+    /// matches does not use up max_results; capped files are reported with their true match count
+    #[serde(default)]
+    pub max_matches_per_file: usize,
+    /// TypeScript grammar for typescript/tsx queries: 'auto' (default) parses .ts, .mts and .cts files as typescript
+    /// and .tsx files as tsx whichever of the two was asked for, so neither kind of file is skipped; 'plain' only
+    /// searches the typescript files, 'jsx' only the tsx ones. JavaScript's grammar parses JSX in .js and .jsx files
+    /// alike, so this does not change JavaScript queries
+    #[serde(default)]
+    pub dialect: String,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct FindCodeByRuleParams {
     /// The absolute path to the project folder. It must be absolute path.
     pub project_folder: String,
     /// The ast-grep YAML rule to search. It must have id, language, rule fields.
     pub yaml: String,
-    /// Maximum results to return
+    /// Named utility rules the yaml can use with `matches: <name>`, e.g. {\"is-literal\": {\"any\": [{\"kind\": \"string\"},
+    /// {\"kind\": \"number\"}]}}; they are added to the local utils of every rule document
     #[serde(default)]
-    pub max_results: i32,
-    /// 'text' or 'json'
-    #[serde(default = "default_text")]
-    pub output_format: String,
+    pub utils: BTreeMap<String, Value>,
+    #[serde(flatten)]
+    pub options: FindOptions,
 }
 
-#[derive(Clone)]
-pub struct AstGrepServer {
-    config: Config,
-    tool_router: ToolRouter<Self>,
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FindCooccurrenceParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The language of both patterns
+    pub language: String,
+    /// The pattern to report scopes of, e.g. a database write
+    pub pattern_a: String,
+    /// The pattern to look for in the same scope, e.g. the start of a transaction
+    pub pattern_b: String,
+    /// 'a_without_b' (default): scopes where pattern_a matches but pattern_b does not; 'both': scopes where both match
+    #[serde(default)]
+    pub mode: String,
+    /// 'function' (default): the outermost function around each match; 'file': the whole file
+    #[serde(default)]
+    pub scope: String,
+    /// Most scopes to return (0 returns all of them)
+    #[serde(default)]
+    pub max_results: usize,
+    /// Also search dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
 }
 
-#[tool_router]
-impl AstGrepServer {
-    pub fn new(config: Config) -> Self {
-        Self {
-            config,
-            tool_router: Self::tool_router(),
-        }
-    }
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FindMissingParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The pattern files must contain to be left out of the result; give either pattern and language, or yaml
+    #[serde(default)]
+    pub pattern: String,
+    /// The language of the pattern and of the files to check
+    #[serde(default)]
+    pub language: String,
+    /// An ast-grep YAML rule files must match to be left out of the result, instead of a pattern; its language is used
+    #[serde(default)]
+    pub yaml: String,
+    /// Most files to list (0 lists all of them)
+    #[serde(default)]
+    pub max_results: usize,
+    /// Also check dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
 
-    #[tool(description = "
-Dump code's syntax structure or dump a query's pattern structure.
-This is useful to discover correct syntax kind and syntax tree structure. Call it when debugging a rule.
-The tool requires three arguments: code, language and format. The first two are self-explanatory.
-`format` is the output format of the syntax tree.
-use `format=cst` to inspect the code's concrete syntax tree structure, useful to debug target code.
-use `format=pattern` to inspect how ast-grep interprets a pattern, useful to debug pattern rule.
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReadFileRangeParams {
+    /// The absolute path of the file to read. It must be inside an allowed root.
+    pub path: String,
+    /// First line to return (1-indexed)
+    pub start_line: u64,
+    /// Last line to return (1-indexed, inclusive). Clamped to the end of the file.
+    pub end_line: u64,
+}
 
-Internally calls: ast-grep run --pattern <code> --lang <language> --debug-query=<format>
-")]
-    async fn dump_syntax_tree(
-        &self,
-        Parameters(params): Parameters<DumpSyntaxTreeParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let result = run_ast_grep(
-            "run",
-            &[
-                "--pattern".to_string(),
-                params.code,
-                "--lang".to_string(),
-                params.language,
-                format!("--debug-query={}", params.format),
-            ],
-            None,
-            self.config.config_path.as_ref(),
-        )
-        .await
-        .map_err(|e| McpError {
-            code: ErrorCode(0),
-            message: e.to_string().into(),
-            data: None,
-        })?;
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct BenchmarkRuleParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The ast-grep YAML rule to benchmark. It must have id, language, rule fields.
+    pub yaml: String,
+    /// How many of the slowest files to report
+    #[serde(default = "default_top_files")]
+    pub top_files: usize,
+    /// How many files (largest first) to time individually
+    #[serde(default = "default_profile_files")]
+    pub profile_files: usize,
+    /// Also scan dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
 
-        Ok(CallToolResult::success(vec![Content::text(
-            result.stderr.trim().to_string(),
-        )]))
-    }
+fn default_top_files() -> usize {
+    10
+}
 
-    #[tool(description = "
-Test a code against an ast-grep YAML rule.
-This is useful to test a rule before using it in a project.
+fn default_profile_files() -> usize {
+    100
+}
 
-Internally calls: ast-grep scan --inline-rules <yaml> --json --stdin
-")]
-    async fn test_match_code_rule(
-        &self,
-        Parameters(params): Parameters<TestMatchCodeRuleParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let result = run_ast_grep(
-            "scan",
-            &[
-                "--inline-rules".to_string(),
-                params.yaml,
-                "--json".to_string(),
-                "--stdin".to_string(),
-            ],
-            Some(&params.code),
-            self.config.config_path.as_ref(),
-        )
-        .await
-        .map_err(|e| McpError {
-            code: ErrorCode(0),
-            message: e.to_string().into(),
-            data: None,
-        })?;
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ExplainQueryParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The ast-grep pattern to explain. Give either pattern or yaml.
+    #[serde(default)]
+    pub pattern: String,
+    /// The ast-grep YAML rule to explain. Give either pattern or yaml.
+    #[serde(default)]
+    pub yaml: String,
+    /// The language of the pattern. Defaults to the rule's language; needed to check how the pattern parses.
+    #[serde(default)]
+    pub language: String,
+    /// Node kind the pattern should parse into, e.g. call_expression
+    #[serde(default)]
+    pub expected_kind: String,
+    /// Also consider dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+    /// Also consider files excluded by ignore files (.gitignore, .ignore, .sgignore)
+    #[serde(default)]
+    pub no_ignore: bool,
+}
 
-        let matches: Vec<Value> = serde_json::from_str(&result.stdout).unwrap_or_else(|_| vec![]);
-        if matches.is_empty() {
-             return Err(McpError {
-                 code: ErrorCode(-32603), // Internal error or similar
-                 message: "No matches found for the given code and rule. Try adding `stopBy: end` to your inside/has rule.".to_string().into(),
-                 data: None,
-             });
-        }
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SuggestPatternParams {
+    /// A concrete example of the code to search for
+    pub code: String,
+    /// The language of the code
+    pub language: String,
+}
 
-        let json_str = serde_json::to_string_pretty(&matches).unwrap_or_default();
-        Ok(CallToolResult::success(vec![Content::text(json_str)]))
-    }
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PatternPlaygroundParams {
+    /// The code to match the pattern against
+    pub code: String,
+    /// The ast-grep pattern to try
+    pub pattern: String,
+    /// The language of the code and the pattern
+    pub language: String,
+}
 
-    #[tool(description = "
-Find code in a project folder that matches the given ast-grep pattern.
-Pattern is good for simple and single-AST node result.
-For more complex usage, please use YAML by `find_code_by_rule`.
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RefinePatternParams {
+    /// The ast-grep pattern that fails to parse or matches nothing
+    pub pattern: String,
+    /// The language of the pattern
+    pub language: String,
+    /// Example code the pattern should match; the pattern is tested against it
+    #[serde(default)]
+    pub code: String,
+    /// Absolute project folder to test the pattern in, when no code is given
+    #[serde(default)]
+    pub project_folder: String,
+    /// Corrected patterns to ask for before giving up (default 3, at most 5)
+    #[serde(default)]
+    pub max_attempts: u32,
+}
 
-Internally calls: ast-grep run --pattern <pattern> [--json] <project_folder>
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct NodeKindStatsParams {
+    /// The absolute path of the file to analyze. It must be inside an allowed root.
+    pub path: String,
+    /// The language of the file (default: inferred from its extension)
+    #[serde(default)]
+    pub language: String,
+    /// How many of the most frequent node kinds to report
+    #[serde(default = "default_top_kinds")]
+    pub top: usize,
+    /// How many example ranges to list per node kind
+    #[serde(default = "default_kind_examples")]
+    pub examples: usize,
+}
 
-Output formats:
-- text (default): Compact text format with file:line-range headers and complete match text
-  Example:
-    Found 2 matches:
+fn default_top_kinds() -> usize {
+    20
+}
 
-    path/to/file.py:10-15
-    def example_function():
-        # function body
-        return result
+fn default_kind_examples() -> usize {
+    3
+}
 
-    path/to/file.py:20-22
-    def another_function():
-        pass
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CodeMetricsParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The language to measure: python, javascript, typescript, tsx, rust, go, java, c, cpp, csharp, ruby, kotlin, php
+    pub language: String,
+    /// How many files to report, most complex first
+    #[serde(default = "default_top_files")]
+    pub top_files: usize,
+    /// Also measure dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
 
-- json: Full match objects with metadata including ranges, meta-variables, etc.
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FindTodosParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// Only search files of this language (default: all languages with known comment syntax)
+    #[serde(default)]
+    pub language: String,
+    /// Tags to look for (default: TODO, FIXME, HACK, XXX)
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Maximum number of notes to return
+    #[serde(default)]
+    pub max_results: usize,
+    /// Also search dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
 
-The max_results parameter limits the number of complete matches returned (not individual lines).
-When limited, the header shows \"Found X matches (showing first Y of Z)\".
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct WatchRuleParams {
+    /// The absolute path to the project folder to watch. It must be absolute path.
+    pub project_folder: String,
+    /// The ast-grep YAML rule to evaluate on changed files. It must have id, language, rule fields.
+    pub yaml: String,
+    /// Also watch dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
 
-Example usage:
-  find_code(pattern=\"class $NAME\", max_results=20)  # Returns text format
-  find_code(pattern=\"class $NAME\", output_format=\"json\")  # Returns JSON with metadata
-")]
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UnwatchParams {
+    /// The watch id returned by watch_rule
+    pub watch_id: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SessionSnapshotParams {
+    /// Absolute path of the snapshot JSON file, inside an allowed root
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SubmitScanParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The ast-grep pattern to search for (pass either pattern or yaml)
+    #[serde(default)]
+    pub pattern: String,
+    /// The ast-grep YAML rule to search with (pass either pattern or yaml)
+    #[serde(default)]
+    pub yaml: String,
+    /// The language of the pattern
+    #[serde(default)]
+    pub language: String,
+    #[serde(flatten)]
+    pub options: FindOptions,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ScanJobParams {
+    /// The job id returned by submit_scan
+    pub job_id: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FetchPartialParams {
+    /// The job id returned by submit_scan
+    pub job_id: String,
+    /// Only return matches from this index on; pass the previous call's next_since
+    #[serde(default)]
+    pub since: usize,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ExportMatchesParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// Absolute path of the SQLite database to write; created if missing. Its folder must be inside an allowed root.
+    pub database: String,
+    /// The ast-grep pattern to search for (pass either pattern or yaml)
+    #[serde(default)]
+    pub pattern: String,
+    /// The ast-grep YAML rule to search with (pass either pattern or yaml)
+    #[serde(default)]
+    pub yaml: String,
+    /// The language of the pattern
+    #[serde(default)]
+    pub language: String,
+    /// Also search dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RuleEffectivenessParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// Absolute path of the directory of YAML rules to assess, e.g. a ruleDirs entry of sgconfig.yaml
+    pub rules_dir: String,
+    /// Absolute path of a SQLite database written by export_matches, to look up when rules without matches
+    /// last matched. It must be inside an allowed root.
+    #[serde(default)]
+    pub history_database: String,
+    /// Also search dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PatternTrendParams {
+    /// The absolute path to the project folder. It must be inside a git repository.
+    pub project_folder: String,
+    /// The ast-grep pattern to count, e.g. a call of a deprecated API
+    pub pattern: String,
+    /// The language of the pattern (default: auto-detected from file extensions)
+    #[serde(default)]
+    pub language: String,
+    /// Commits, tags or branches to scan, e.g. ["v1.0", "v2.0", "HEAD"] (default: sampled history of HEAD)
+    #[serde(default)]
+    pub revisions: Vec<String>,
+    /// Without revisions: how many commits of HEAD's first-parent history to scan
+    #[serde(default = "default_trend_count")]
+    pub count: usize,
+    /// Without revisions: commits between two scanned ones
+    #[serde(default = "default_trend_step")]
+    pub step: usize,
+}
+
+fn default_trend_count() -> usize {
+    5
+}
+
+fn default_trend_step() -> usize {
+    20
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FindCodeAtRevParams {
+    /// The absolute path to the project folder. It must be inside a git repository.
+    pub project_folder: String,
+    /// The ast-grep pattern to search for
+    pub pattern: String,
+    /// The language of the pattern (default: auto-detected from file extensions)
+    #[serde(default)]
+    pub language: String,
+    /// The commit, tag or branch to search, e.g. 'HEAD~10' or 'v1.0'
+    pub rev: String,
+    /// Files or directories to search, relative to project_folder (default: all of project_folder)
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Maximum matches to return (0 for all)
+    #[serde(default)]
+    pub max_results: usize,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct OutlineFileParams {
+    /// The absolute path of the file to outline. It must be inside an allowed root.
+    pub path: String,
+    /// The language of the file (default: inferred from its extension)
+    #[serde(default)]
+    pub language: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CallGraphParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The language to analyze: python, javascript, typescript, tsx, rust, go, java, csharp, ruby, php
+    pub language: String,
+    /// `json` (default): functions and an adjacency list; `dot`: a Graphviz digraph
+    #[serde(default)]
+    pub format: String,
+    /// Also keep calls to functions not defined in the project (library and built-in calls)
+    #[serde(default)]
+    pub include_external: bool,
+    /// Also analyze dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct LicenseHeaderParams {
+    /// The absolute path to the project folder. It must be absolute path.
+    pub project_folder: String,
+    /// The language whose source files to check
+    pub language: String,
+    /// The required header as plain text, without comment markers; it is commented in the language's style
+    pub header: String,
+    /// Insert the header into files that lack it (requires the folder to be inside an allowed root)
+    #[serde(default)]
+    pub insert: bool,
+    /// Also check dependency and build directories (node_modules, target, .venv, dist, build, .git)
+    #[serde(default)]
+    pub include_vendored: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SuppressFindingParams {
+    /// The absolute path of the file with the finding. It must be inside an allowed root.
+    pub path: String,
+    /// The finding's first line (1-indexed)
+    pub line: usize,
+    /// Id of the rule to suppress there
+    pub rule_id: String,
+    /// Why the finding is a false positive or acceptable; written as a comment above the suppression
+    pub justification: String,
+    /// Language of the file, for its comment style (default: from the file extension)
+    #[serde(default)]
+    pub language: String,
+    /// Only return the lines that would be inserted, without changing the file
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct InitSgconfigParams {
+    /// The absolute path to the project root, where sgconfig.yaml goes
+    pub project_folder: String,
+    /// Write sgconfig.yaml and create its missing directories (requires the folder to be inside an allowed
+    /// root); without it, only the proposed config is returned
+    #[serde(default)]
+    pub write: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct LintRuleParams {
+    /// The ast-grep YAML rule to check
+    pub yaml: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RegisterCustomLanguageParams {
+    /// Name of the language, to use as `language` in patterns and rules
+    pub name: String,
+    /// Path to the compiled tree-sitter parser (.so/.dylib/.dll), absolute or relative to sgconfig.yaml
+    pub library_path: String,
+    /// File extensions of the language, without the dot
+    pub extensions: Vec<String>,
+    /// Character that stands in for `$` in patterns, for languages where `$` is not valid in identifiers
+    #[serde(default)]
+    pub expando_char: Option<String>,
+    /// A short snippet of valid code in the language, parsed to validate the parser
+    pub sample: String,
+    /// Overwrite an existing custom language with the same name
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct InstallGrammarParams {
+    /// Name of the language, e.g. zig; known grammars need no other arguments
+    pub name: String,
+    /// Git URL of the tree-sitter grammar; required for languages without a known grammar
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// Directory inside the repository that contains src/parser.c, for repositories with several grammars
+    #[serde(default)]
+    pub subdirectory: Option<String>,
+    /// File extensions of the language, without the dot (default: those of the known grammar)
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Character that stands in for `$` in patterns, for languages where `$` is not valid in identifiers
+    #[serde(default)]
+    pub expando_char: Option<String>,
+    /// A short snippet of valid code in the language (default: the known grammar's sample)
+    #[serde(default)]
+    pub sample: Option<String>,
+    /// Fetch and rebuild the grammar, and overwrite an existing custom language with the same name
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetMatchDetailsParams {
+    /// Match ids from a find_code or find_code_by_rule call with output_format='headers'
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PreviewMatchRewriteParams {
+    /// The rewrite template, e.g. "logger.info($$$ARGS)"
+    pub rewrite: String,
+    /// Id of the match to rewrite, from a find_code or find_code_by_rule call with output_format='headers'
+    #[serde(default)]
+    pub match_id: String,
+    /// Otherwise, the absolute path of the file with the match. It must be inside an allowed root.
+    #[serde(default)]
+    pub path: String,
+    /// With path: the line the match starts on (1-indexed)
+    #[serde(default)]
+    pub line: u64,
+    /// With path: the column the match starts at (1-indexed), when several matches start on the line
+    #[serde(default)]
+    pub column: Option<u64>,
+    /// The pattern that binds the template's metavariables, also with match_id
+    #[serde(default)]
+    pub pattern: String,
+    /// With path: the language of the pattern (default: inferred from the file extension)
+    #[serde(default)]
+    pub language: String,
+    /// New metavariables for the template computed from captured ones, e.g.
+    /// {"CAMEL": {"source": "$NAME", "to_case": "camelCase"}}, {"source": "$NAME", "replace": "^get_", "by": ""}
+    /// or {"source": "$NAME", "start_char": 1, "end_char": -1}; ast-grep transform objects are also accepted.
+    #[serde(default)]
+    pub transform: BTreeMap<String, Value>,
+}
+
+#[derive(Clone)]
+pub struct AstGrepServer {
+    config: Config,
+    matches: Arc<MatchStore>,
+    jobs: Arc<JobManager>,
+    watches: Arc<WatchRegistry>,
+    rule_sessions: Arc<RuleSessions>,
+    /// Backup ids of the session's apply_rewrite calls, most recent last, for undo_rewrite.
+    rewrites: Arc<Mutex<Vec<String>>>,
+    /// Created on first use, removed when the session ends.
+    temp_dir: Arc<Mutex<Option<Arc<SessionTempDir>>>>,
+    /// Languages find_code detected in each folder (with include_vendored), so later calls without
+    /// a language do not walk it again.
+    languages: Arc<Mutex<HashMap<(PathBuf, bool), LanguageCounts>>>,
+    sessions: Arc<SessionRegistry>,
+    session_id: String,
+    /// Closes the session once the last clone of the server is gone.
+    _session: Arc<SessionGuard>,
+    tool_router: ToolRouter<Self>,
+    /// Tools of plugins that change files, including those left out in read-only mode.
+    plugin_write_tools: Arc<Vec<String>>,
+}
+
+#[tool_router]
+impl AstGrepServer {
+    pub fn new(config: Config) -> Self {
+        let sessions = Arc::new(SessionRegistry::new(config.quotas.clone()));
+        Self::with_sessions(config, sessions)
+    }
+
+    /// A server for one new session among the open sessions in `sessions`.
+    pub fn with_sessions(config: Config, sessions: Arc<SessionRegistry>) -> Self {
+        let session_id = sessions.open();
+        let (tool_router, plugin_write_tools) = Self::tool_router_for(&config);
+        Self {
+            _session: Arc::new(SessionGuard::new(sessions.clone(), &session_id)),
+            session_id,
+            matches: Arc::new(MatchStore::default()),
+            sessions,
+            jobs: Arc::new(JobManager::default().with_checkpoint_dir(config.checkpoint_dir.clone())),
+            watches: Arc::new(WatchRegistry::default()),
+            rule_sessions: Arc::default(),
+            rewrites: Arc::default(),
+            temp_dir: Arc::default(),
+            languages: Arc::default(),
+            tool_router,
+            plugin_write_tools: Arc::new(plugin_write_tools),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The tools `config` allows, with deprecations marked: in read-only mode, tools that write
+    /// files are left out, and with `hide_deprecated`, deprecated tools and parameters. Tools of
+    /// plugins come last and cannot replace built-in ones; those that write files are returned too.
+    fn tool_router_for(config: &Config) -> (ToolRouter<Self>, Vec<String>) {
+        let mut router = Self::tool_router();
+        #[cfg(feature = "plugins")]
+        let built_in: Vec<String> = router.map.keys().map(|name| name.to_string()).collect();
+        if config.read_only {
+            for tool in WRITE_TOOLS {
+                router.remove_route(tool);
+            }
+        }
+        let languages = get_supported_languages(config.config_path.as_deref());
+        for route in router.map.values_mut() {
+            add_parameter_values(&mut route.attr, &languages);
+        }
+        for deprecation in DEPRECATIONS {
+            if config.hide_deprecated && deprecation.parameter.is_none() {
+                router.remove_route(deprecation.tool);
+            } else if let Some(route) = router.map.get_mut(deprecation.tool) {
+                deprecate(&mut route.attr, deprecation, config.hide_deprecated);
+            }
+        }
+        #[cfg(feature = "plugins")]
+        let plugin_write_tools = Self::add_plugin_routes(&mut router, config, &built_in);
+        #[cfg(not(feature = "plugins"))]
+        let plugin_write_tools = Vec::new();
+        (router, plugin_write_tools)
+    }
+
+    /// Adds the tools of `config`'s plugins not named like a `built_in` tool, except in read-only
+    /// mode those that write files, and returns the names of those that do.
+    #[cfg(feature = "plugins")]
+    fn add_plugin_routes(router: &mut ToolRouter<Self>, config: &Config, built_in: &[String]) -> Vec<String> {
+        let mut write_tools = Vec::new();
+        for plugin in config.plugins.iter() {
+            for PluginRoute { route, writes } in plugin.routes(config) {
+                if built_in.iter().any(|name| name == route.name()) {
+                    tracing::warn!(
+                        "Ignoring tool {} of plugin {}: a built-in tool has that name",
+                        route.name(),
+                        plugin.name()
+                    );
+                    continue;
+                }
+                if writes {
+                    write_tools.push(route.name().to_string());
+                    if config.read_only {
+                        continue;
+                    }
+                }
+                router.add_route(route);
+            }
+        }
+        write_tools
+    }
+
+    /// Whether `tool` changes files: a built-in write tool or a plugin tool that says it writes.
+    fn writes(&self, tool: &str) -> bool {
+        WRITE_TOOLS.contains(&tool) || self.plugin_write_tools.iter().any(|name| name == tool)
+    }
+
+    /// Whether `grant` allows calling `tool`: with OAuth, tools that change files (plugin tools
+    /// that say they write included) need the write scope.
+    pub fn may_call(&self, grant: &Grant, tool: &str) -> bool {
+        match &self.config.auth.oauth {
+            Some(oauth) if self.writes(tool) => grant.may_write(&oauth.write_scope),
+            _ => true,
+        }
+    }
+
+    /// Tools to list to clients: all routed tools, except those that need ast-grep while it is not
+    /// installed, as they would fail.
+    pub async fn available_tools(&self) -> Vec<Tool> {
+        let installed = ast_grep_version().await.is_some();
+        self.tool_router
+            .list_all()
+            .into_iter()
+            .filter(|tool| unavailable_reason(&tool.name, self.config.read_only, installed).is_none())
+            .collect()
+    }
+
+    #[tool(description = "
+Dump code's syntax structure or dump a query's pattern structure.
+This is useful to discover correct syntax kind and syntax tree structure. Call it when debugging a rule.
+The tool requires three arguments: code, language and format. The first two are self-explanatory.
+`format` is the output format of the syntax tree.
+use `format=cst` to inspect the code's concrete syntax tree structure, useful to debug target code.
+use `format=pattern` to inspect how ast-grep interprets a pattern, useful to debug pattern rule.
+Unless built without the `parsers` feature, the server parses bash, c, cpp, csharp, css, elixir, go,
+html, java, javascript, json, php, python, ruby, rust, swift, tsx, typescript and yaml itself, so those
+work without the ast-grep CLI.
+
+Internally calls, for other languages: ast-grep run --pattern <code> --lang <language> --debug-query=<format>
+")]
+    async fn dump_syntax_tree(
+        &self,
+        Parameters(params): Parameters<DumpSyntaxTreeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(format) = DumpFormat::parse(&params.format) else {
+            return Err(invalid_params(format!(
+                "Unknown format '{}'. Available values: {}",
+                params.format,
+                DUMP_FORMATS.join(", ")
+            )));
+        };
+        #[cfg(feature = "parsers")]
+        if let Some(dump) = dump_tree(&params.code, &params.language, format) {
+            return Ok(CallToolResult::success(vec![Content::text(dump.trim().to_string())]));
+        }
+
+        let result = run_ast_grep(
+            "run",
+            &[
+                "--pattern".to_string(),
+                params.code,
+                "--lang".to_string(),
+                params.language,
+                format!("--debug-query={}", format.as_str()),
+            ],
+            None,
+            &self.config,
+        )
+        .await
+        .map_err(tool_error)?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            result.stderr.trim().to_string(),
+        )]))
+    }
+
+    #[tool(description = "
+Suggest search patterns from a concrete code example. The example is parsed and its literals,
+identifiers, and argument lists/bodies are replaced by metavariables, giving patterns at three
+levels of generalization (literals < identifiers < structure), each with the text every
+metavariable replaced and whether ast-grep parses the pattern cleanly. `suggested` is the
+identifiers level, a good starting point for find_code.
+
+Internally calls: ast-grep run --pattern <code> --lang <language> --debug-query=cst
+")]
+    async fn suggest_pattern(
+        &self,
+        Parameters(params): Parameters<SuggestPatternParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let dump_args = |code: &str, format: &str| {
+            vec![
+                "--pattern".to_string(),
+                code.to_string(),
+                "--lang".to_string(),
+                params.language.clone(),
+                format!("--debug-query={}", format),
+            ]
+        };
+        let result = run_ast_grep("run", &dump_args(&params.code, "cst"), None, &self.config)
+            .await
+            .map_err(tool_error)?;
+        let Some(root) = parse_dump(&result.stderr) else {
+            return Err(tool_error(format!(
+                "Could not read the syntax tree of the code:\n{}",
+                result.stderr.trim()
+            )));
+        };
+
+        let mut notes = Vec::new();
+        if dump_has_errors(&result.stderr) {
+            notes.push(format!(
+                "The code does not parse cleanly as {}; the suggestions may not match as expected.",
+                params.language
+            ));
+        }
+        let mut suggestions = suggest_patterns(&params.code, &root);
+        for suggestion in suggestions.iter_mut() {
+            if let Ok(check) = run_ast_grep("run", &dump_args(&suggestion.pattern, "pattern"), None, &self.config).await {
+                suggestion.valid = Some(!dump_has_errors(&check.stderr));
+            }
+        }
+        if suggestions.is_empty() {
+            notes.push("The code has no literals, identifiers or lists to generalize; use it as the pattern.".to_string());
+        }
+
+        let suggested = suggestions
+            .iter()
+            .find(|s| s.level == "identifiers")
+            .or(suggestions.last())
+            .map_or(params.code.trim().to_string(), |s| s.pattern.clone());
+        let response = serde_json::json!({
+            "suggested": suggested,
+            "suggestions": suggestions,
+            "notes": notes,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Try a pattern on a code snippet and see why it does or does not match, in one call instead of
+dump_syntax_tree twice and test_match_code_rule. Returns how ast-grep interprets the pattern
+(`pattern_structure`), the matches, and the code's concrete syntax tree (`code_cst`) for the relevant
+region: the first match, or else the code node the pattern gets furthest into. Without matches,
+`divergence` names the first pattern node that differs from the code there (expected vs found kind or
+text); the comparison approximates ast-grep's smart strictness.
+
+Internally calls: ast-grep run --pattern <pattern> --lang <language> --debug-query=pattern, the same
+with --debug-query=cst for the pattern and the code, and ast-grep run --pattern <pattern> --json --stdin
+")]
+    async fn pattern_playground(
+        &self,
+        Parameters(params): Parameters<PatternPlaygroundParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.code.len() > MAX_DUMP_SOURCE_BYTES {
+            return Err(invalid_params(format!(
+                "The code is too long ({} bytes); the playground takes at most {} bytes.",
+                params.code.len(),
+                MAX_DUMP_SOURCE_BYTES
+            )));
+        }
+        let query = |code: &str, extra: &str| {
+            vec![
+                "--pattern".to_string(),
+                code.to_string(),
+                "--lang".to_string(),
+                params.language.clone(),
+                extra.to_string(),
+            ]
+        };
+        let dump = |code: &str, format: &str| {
+            let args = query(code, &format!("--debug-query={}", format));
+            async move { run_ast_grep("run", &args, None, &self.config).await.map_err(tool_error) }
+        };
+
+        let structure = dump(&params.pattern, "pattern").await?.stderr;
+        let pattern_cst = dump(&params.pattern, "cst").await?.stderr;
+        let code_cst = dump(&params.code, "cst").await?.stderr;
+        let Some(code_root) = parse_dump(&code_cst) else {
+            return Err(tool_error(format!("Could not read the syntax tree of the code:\n{}", code_cst.trim())));
+        };
+        let mut args = query(&params.pattern, "--json");
+        args.push("--stdin".to_string());
+        let result = run_ast_grep("run", &args, Some(&params.code), &self.config)
+            .await
+            .map_err(tool_error)?;
+        let mut matches = parse_matches(&result.stdout);
+        normalize_source_ranges(&mut matches, &params.code);
+
+        let mut notes = Vec::new();
+        let pattern_parses = !dump_has_errors(&structure);
+        if !pattern_parses {
+            notes.push(format!(
+                "The pattern does not parse cleanly as {}; fix the ERROR/MISSING nodes in pattern_structure first.",
+                params.language
+            ));
+        }
+        if dump_has_errors(&code_cst) {
+            notes.push(format!("The code does not parse cleanly as {}.", params.language));
+        }
+
+        let position = |m: &Value, end: &str| {
+            let line = m.pointer(&format!("/range/{}/line", end)).and_then(Value::as_u64).unwrap_or(0);
+            let column = m.pointer(&format!("/range/{}/column", end)).and_then(Value::as_u64).unwrap_or(0);
+            (line as usize, column as usize)
+        };
+        let mut divergence = None;
+        let region = match matches.first() {
+            Some(first) => enclosing_node(&code_root, position(first, "start"), position(first, "end")),
+            None => {
+                divergence = parse_dump(&pattern_cst)
+                    .and_then(|pattern_root| first_divergence(&params.pattern, &pattern_root, &params.code, &code_root));
+                if divergence.is_none() {
+                    notes.push(
+                        "The pattern's nodes line up with the code, yet ast-grep finds no match; check repeated \
+                         metavariables, which must bind the same text."
+                            .to_string(),
+                    );
+                }
+                match divergence.as_ref().and_then(|d| d.candidate.as_ref()) {
+                    Some(candidate) => enclosing_node(&code_root, candidate.span.0, candidate.span.1),
+                    None => &code_root,
+                }
+            }
+        };
+
+        let response = serde_json::json!({
+            "pattern_structure": structure.trim(),
+            "pattern_parses": pattern_parses,
+            "matches": matches,
+            "code_cst": render_dump(region).trim_end(),
+            "divergence": divergence,
+            "notes": notes,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Fix a pattern that fails to parse or matches nothing, with the help of the client's model. The
+pattern is tested against code (or, without code, searched for in project_folder); if it has a
+problem, the client is asked through MCP sampling for a corrected pattern, given the problem and
+ast-grep's syntax tree dumps of the pattern and the code. Each suggestion is tested the same way, and
+the first one that parses cleanly and matches is returned as `refined`, together with every attempt.
+Needs a client that supports sampling; otherwise only the problem is reported.
+
+Internally calls: ast-grep run --pattern <pattern> --lang <language> --debug-query=pattern, then
+ast-grep run --pattern <pattern> --lang <language> --json (--stdin | <project_folder>)
+")]
+    async fn refine_pattern(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<RefinePatternParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.code.is_empty() && params.project_folder.is_empty() {
+            return Err(invalid_params("Pass code or project_folder to test the pattern against."));
+        }
+        let max_attempts = match params.max_attempts {
+            0 => DEFAULT_REFINE_ATTEMPTS,
+            n => n.min(MAX_REFINE_ATTEMPTS),
+        };
+
+        let mut attempts = vec![self.try_pattern(&params.pattern, &params).await?];
+        let mut notes = Vec::new();
+        let supports_sampling = context
+            .peer
+            .peer_info()
+            .is_some_and(|info| info.capabilities.sampling.is_some());
+        if attempts[0].problem.is_none() {
+            notes.push("The pattern parses cleanly and matches; there is nothing to refine.".to_string());
+        } else if !supports_sampling {
+            notes.push("The client does not support sampling, so no corrected pattern could be requested.".to_string());
+        } else {
+            let code_dump = if params.code.is_empty() {
+                None
+            } else {
+                let args = [
+                    "--pattern".to_string(),
+                    params.code.clone(),
+                    "--lang".to_string(),
+                    params.language.clone(),
+                    "--debug-query=cst".to_string(),
+                ];
+                run_ast_grep("run", &args, None, &self.config).await.ok().map(|result| result.stderr)
+            };
+            while attempts.len() <= max_attempts as usize {
+                let code = code_dump.as_deref().map(|dump| (params.code.as_str(), dump));
+                let request = CreateMessageRequestParams {
+                    meta: None,
+                    task: None,
+                    messages: vec![SamplingMessage::user_text(refine_prompt(&params.language, &attempts, code))],
+                    model_preferences: None,
+                    system_prompt: Some(refine_system_prompt()),
+                    include_context: None,
+                    temperature: Some(0.0),
+                    max_tokens: REFINE_MAX_TOKENS,
+                    stop_sequences: None,
+                    metadata: None,
+                    tools: None,
+                    tool_choice: None,
+                };
+                let reply = match context.peer.create_message(request).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "sampling request failed");
+                        notes.push(format!("The client did not answer the sampling request: {}", e));
+                        break;
+                    }
+                };
+                let text: String = reply
+                    .message
+                    .content
+                    .into_vec()
+                    .into_iter()
+                    .filter_map(|content| match content {
+                        SamplingMessageContent::Text(text) => Some(text.text),
+                        _ => None,
+                    })
+                    .collect();
+                let Some(pattern) = extract_pattern(&text) else {
+                    notes.push("The client's model replied without a pattern.".to_string());
+                    break;
+                };
+                if attempts.iter().any(|attempt| attempt.pattern == pattern) {
+                    notes.push(format!("The client's model suggested `{}` again.", pattern));
+                    break;
+                }
+                let attempt = self.try_pattern(&pattern, &params).await?;
+                let fixed = attempt.problem.is_none();
+                attempts.push(attempt);
+                if fixed {
+                    break;
+                }
+            }
+        }
+
+        let refined = attempts.iter().skip(1).find(|attempt| attempt.problem.is_none());
+        let response = serde_json::json!({
+            "pattern": params.pattern,
+            "problem": attempts[0].problem,
+            "refined": refined.map(|attempt| &attempt.pattern),
+            "matches": refined.unwrap_or(&attempts[0]).matches,
+            "attempts": &attempts[1..],
+            "notes": notes,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Test a code against an ast-grep YAML rule.
+This is useful to test a rule before using it in a project.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json --stdin
+")]
+    async fn test_match_code_rule(
+        &self,
+        Parameters(params): Parameters<TestMatchCodeRuleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let yaml = with_utils(&params.yaml, &params.utils).map_err(invalid_params)?;
+        check_rule_schema(&yaml)?;
+        let (matches, retry) = self.test_rule_on_code(yaml, &params.code).await?;
+        let note = retry.as_ref().map(|description| {
+            format!(
+                "Note: The rule matched nothing as written; these matches were found with {}. Add `stopBy: end` \
+                 there to get them directly.",
+                description
+            )
+        });
+        if matches.is_empty() {
+            let message = if retry.is_some() {
+                "No matches found for the given code and rule, even with `stopBy: end` on its relational rules."
+            } else {
+                "No matches found for the given code and rule."
+            };
+            return Err(McpError {
+                code: ErrorCode(-32603), // Internal error or similar
+                message: message.to_string().into(),
+                data: None,
+            });
+        }
+
+        let json_str = serde_json::to_string_pretty(&matches).unwrap_or_default();
+        let mut content = vec![Content::text(json_str)];
+        content.extend(note.map(Content::text));
+        Ok(CallToolResult::success(content))
+    }
+
+    #[tool(description = "
+Rewrite a code snippet with an ast-grep YAML rule that has a `fix`, without touching any file: the
+counterpart of test_match_code_rule for codemods. Returns the rewritten code and each fix applied
+(rule id, 0-based line, original and replacement text). Matches that overlap an earlier one are left
+alone, as ast-grep --update-all does; run the tool again to rewrite them too.
+
+Example usage:
+  rewrite_code(code=\"var a = 1\", language=\"javascript\",
+               yaml=\"id: x\\nrule:\\n  pattern: var $A = $B\\nfix: let $A = $B\")
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json --stdin, then applies each match's replacement
+")]
+    async fn rewrite_code(
+        &self,
+        Parameters(params): Parameters<RewriteCodeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let yaml = with_utils(&params.yaml, &params.utils).map_err(invalid_params)?;
+        let yaml = with_language(&yaml, &params.language).map_err(invalid_params)?;
+        if !has_fix(&yaml) {
+            return Err(invalid_params("The rule has no fix; add a `fix` with the replacement template."));
+        }
+        check_rule_schema(&yaml)?;
+        let args = ["--inline-rules".to_string(), yaml, "--json".to_string(), "--stdin".to_string()];
+        let result = run_ast_grep("scan", &args, Some(&params.code), &self.config).await.map_err(tool_error)?;
+        let mut matches = parse_matches(&result.stdout);
+        normalize_source_ranges(&mut matches, &params.code);
+
+        let (code, fixes, overlapping) = apply_fixes(&params.code, &matches);
+        let mut response = serde_json::json!({
+            "code": code,
+            "changed": code != params.code,
+            "fixes": fixes,
+        });
+        if overlapping > 0 {
+            response["note"] = Value::from(format!(
+                "{} matches overlapping an earlier fix were left alone; run rewrite_code on the result to rewrite \
+                 them.",
+                overlapping
+            ));
+        }
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Show what a rule's `fix` would change in a project, as a unified diff of every file that would change,
+without touching any file: the dry run to show before rewriting files. Matches that overlap an earlier
+one in the same file are left out, as ast-grep --update-all leaves them. The diff is followed by a JSON
+summary with the fixes per file, each with a fingerprint to pass to apply_rewrite to apply only it.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json <project_folder>
+")]
+    async fn preview_rewrite(
+        &self,
+        Parameters(params): Parameters<PreviewRewriteParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let yaml = with_utils(&params.yaml, &params.utils).map_err(invalid_params)?;
+        if !has_fix(&yaml) {
+            return Err(invalid_params("The rule has no fix; add a `fix` with the replacement template."));
+        }
+        check_rule_schema(&yaml)?;
+        let project_folder = Path::new(&params.project_folder);
+        let planned = plan_rewrite(&yaml, project_folder, false, false, &self.config).await.map_err(tool_error)?;
+
+        let mut diff = String::new();
+        let mut files = Vec::new();
+        for file in &planned.files {
+            diff.push_str(&unified_diff(&file.path, &file.source, &file.edits, CONTEXT_LINES));
+            files.push(serde_json::json!({ "file": file.path, "fixes": file.fixes }));
+        }
+        let summary = serde_json::json!({
+            "files": files,
+            "fixes": planned.files.iter().map(|file| file.fixes.len()).sum::<usize>(),
+            "overlapping": planned.overlapping,
+            "skipped": planned.skipped,
+        });
+        let text = if diff.is_empty() { "No files would change.".to_string() } else { diff };
+        let json_str = serde_json::to_string_pretty(&summary).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(text), Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Apply a rule's `fix` to the files of a project: the changes preview_rewrite shows, written to disk.
+Each file is replaced atomically (written to a temporary file next to it, then renamed over it), and
+its original contents are first copied to a `.bak` file in the server's backup directory
+(--backup-dir), under a backup id returned with the summary; undo_rewrite puts them back. Files
+changed since the scan, not UTF-8 or not writable are skipped and listed. Matches that overlap an
+earlier one in the same file are left alone, as ast-grep --update-all leaves them; apply again to
+rewrite them. Returns JSON with the files modified and the hunks and fixes applied to each.
+
+To apply part of a preview, pass files (paths relative to the project folder) and/or fingerprints
+(those of the fixes preview_rewrite listed); fingerprints of fixes no longer found are listed as
+skipped.
+
+With interactive=true the user approves each change through MCP elicitation before it is written:
+each file's diff, or with approve=\"match\" each fix. Rejected changes are left out and listed under
+`rejected`; cancelling stops the rewrite, keeping the files already written. Needs a client that
+supports elicitation.
+
+The project folder must be inside an allowed root: the directories given with --allowed-root, or
+else the roots provided by the MCP client.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json <project_folder>
+")]
+    async fn apply_rewrite(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<ApplyRewriteParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let yaml = with_utils(&params.yaml, &params.utils).map_err(invalid_params)?;
+        if !has_fix(&yaml) {
+            return Err(invalid_params("The rule has no fix; add a `fix` with the replacement template."));
+        }
+        check_rule_schema(&yaml)?;
+        let Some(scope) = ApprovalScope::parse(&params.approve) else {
+            return Err(invalid_params(format!("Invalid approve: must be {}", one_of(APPROVAL_SCOPES))));
+        };
+        if params.interactive && !peer.supported_elicitation_modes().contains(&ElicitationMode::Form) {
+            return Err(invalid_params(
+                "The client does not support elicitation, so changes cannot be approved interactively; \
+                 review them with preview_rewrite instead.",
+            ));
+        }
+        let Some(backup_dir) = self.config.backup_dir.clone() else {
+            return Err(tool_error("No backup directory could be determined; start the server with --backup-dir."));
+        };
+        let roots = self.allowed_roots(&peer).await;
+        let project_folder =
+            resolve_within_roots(Path::new(&params.project_folder), &roots).map_err(invalid_params)?;
+        let mut planned =
+            plan_rewrite(&yaml, &project_folder, false, false, &self.config).await.map_err(tool_error)?;
+        planned.select(&params.files, &params.fingerprints);
+
+        let mut backup = Backup::new(&project_folder, &params.yaml);
+        let mut skipped = planned.skipped;
+        let mut files = Vec::new();
+        let mut rejected = Vec::new();
+        let mut cancelled = false;
+        for mut file in planned.files {
+            if cancelled {
+                skipped.push(format!("{}: not applied, the approval was cancelled", file.path));
+                continue;
+            }
+            if params.interactive {
+                match self.approve_planned_file(&peer, scope, &mut file, &mut rejected).await {
+                    Ok(Approval::Cancelled) => {
+                        cancelled = true;
+                        skipped.push(format!("{}: not applied, the approval was cancelled", file.path));
+                        continue;
+                    }
+                    Ok(_) if file.edits.is_empty() => continue,
+                    Ok(_) => {}
+                    Err(e) => {
+                        cancelled = true;
+                        skipped.push(format!("{}: not applied, the approval request failed: {}", file.path, e));
+                        continue;
+                    }
+                }
+            }
+            if let Err(reason) = write_planned_file(&mut backup, &backup_dir, &file) {
+                skipped.push(format!("{}: {}", file.path, reason));
+                continue;
+            }
+            let hunks = count_hunks(&unified_diff(&file.path, &file.source, &file.edits, CONTEXT_LINES));
+            files.push(serde_json::json!({ "file": file.path, "hunks": hunks, "fixes": file.fixes.len() }));
+        }
+        if !backup.files.is_empty() {
+            self.record_backup(&backup_dir, &backup)?;
+            tracing::info!(
+                project_folder = %project_folder.display(),
+                backup_id = %backup.id,
+                files = backup.files.len(),
+                "Applied a rewrite"
+            );
+        }
+
+        let mut response = serde_json::json!({
+            "backup_id": (!backup.files.is_empty()).then_some(&backup.id),
+            "files_modified": files.len(),
+            "hunks": files.iter().filter_map(|file| file["hunks"].as_u64()).sum::<u64>(),
+            "fixes": files.iter().filter_map(|file| file["fixes"].as_u64()).sum::<u64>(),
+            "files": files,
+            "overlapping": planned.overlapping,
+            "skipped": skipped,
+        });
+        if params.interactive {
+            response["rejected"] = serde_json::json!(rejected);
+            response["cancelled"] = serde_json::json!(cancelled);
+        }
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Undo an apply_rewrite (or a license_header insert or suppress_finding): put back the original
+contents of the files it rewrote, from the backups it kept. Without backup_id, the session's most
+recent apply_rewrite that was not undone is undone, so calling it again walks further back. Files
+changed since they were rewritten are skipped and listed unless force=true, so later edits are not
+lost; the backup is deleted once every file is restored.
+Returns JSON with the files restored and skipped.
+
+The project folder of the rewrite must be inside an allowed root: the directories given with
+--allowed-root, or else the roots provided by the MCP client.
+")]
+    async fn undo_rewrite(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<UndoRewriteParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(backup_dir) = self.config.backup_dir.clone() else {
+            return Err(tool_error("No backup directory could be determined; start the server with --backup-dir."));
+        };
+        let id = if params.backup_id.is_empty() {
+            let rewrites = self.rewrites.lock().unwrap_or_else(|e| e.into_inner());
+            match rewrites.last() {
+                Some(id) => id.clone(),
+                None => return Err(invalid_params("No apply_rewrite of this session is left to undo; pass backup_id.")),
+            }
+        } else {
+            params.backup_id.clone()
+        };
+        let backup = load_backup(&backup_dir, &id)
+            .map_err(|e| invalid_params(format!("Cannot read backup '{}': {}", id, e)))?;
+        let roots = self.allowed_roots(&peer).await;
+        resolve_within_roots(&backup.project_folder, &roots).map_err(invalid_params)?;
+
+        let restored = restore_backup(&backup_dir, &backup, params.force);
+        let complete = restored.skipped.is_empty();
+        if complete {
+            remove_backup(&backup_dir, &id);
+            self.rewrites.lock().unwrap_or_else(|e| e.into_inner()).retain(|rewrite| *rewrite != id);
+        }
+        tracing::info!(
+            project_folder = %backup.project_folder.display(),
+            backup_id = %id,
+            restored = restored.restored.len(),
+            skipped = restored.skipped.len(),
+            "Undid a rewrite"
+        );
+
+        let response = serde_json::json!({
+            "backup_id": id,
+            "project_folder": backup.project_folder.display().to_string(),
+            "restored": restored.restored.iter().map(|path| path.display().to_string()).collect::<Vec<_>>(),
+            "skipped": restored.skipped,
+            "complete": complete,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Start a rule-building session: the server keeps the rule, its utility rules, the sample code and the
+matches of the last test, so refining the rule does not mean resending the whole YAML and code.
+Change it with update_rule_session, test it with test_rule_session, and get the final rule with
+finalize_rule_session. Sessions end with finalize_rule_session or when the client disconnects; a client
+keeps at most 16, creating more drops the least recently used.
+
+Example usage:
+  create_rule_session(yaml=\"id: x\\nlanguage: python\\nrule:\\n  pattern: print($A)\", code=\"print(1)\")
+")]
+    async fn create_rule_session(
+        &self,
+        Parameters(params): Parameters<CreateRuleSessionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        check_rule_schema(&with_utils(&params.yaml, &params.utils).map_err(invalid_params)?)?;
+        let (id, dropped) = self.rule_sessions.create(params.yaml, params.utils, params.code);
+        let mut response = serde_json::json!({ "rule_session_id": id });
+        if let Some(dropped) = dropped {
+            response["note"] = Value::from(format!(
+                "Dropped rule session {} (least recently used) to stay within {} sessions.",
+                dropped, MAX_RULE_SESSIONS
+            ));
+        }
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Change the rule or sample code of a rule session: set rule fields by dotted path (e.g.
+{\"rule.pattern\": \"foo($A)\", \"constraints.A.regex\": \"^x\"}, null removes a field), replace the whole
+yaml, replace the code or append to it, and add or remove utility rules. Returns the rule as changed;
+a change that breaks the rule schema is rejected and leaves the session as it was.
+")]
+    async fn update_rule_session(
+        &self,
+        Parameters(params): Parameters<UpdateRuleSessionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let updated = self.rule_sessions.update(&params.rule_session_id, |session| {
+            if let Some(yaml) = params.yaml {
+                session.yaml = yaml;
+            }
+            if !params.set.is_empty() {
+                session.yaml = set_rule_fields(&session.yaml, &params.set)?;
+            }
+            for (name, rule) in params.utils {
+                match rule {
+                    Value::Null => session.utils.remove(&name),
+                    rule => session.utils.insert(name, rule),
+                };
+            }
+            let code_changed = params.code.is_some() || params.append_code.is_some();
+            if let Some(code) = params.code {
+                session.code = code;
+            }
+            if let Some(more) = params.append_code {
+                if !session.code.is_empty() && !session.code.ends_with('\n') {
+                    session.code.push('\n');
+                }
+                session.code.push_str(&more);
+            }
+            if code_changed {
+                session.last_matches = None;
+            }
+            let yaml = with_utils(&session.yaml, &session.utils)?;
+            check_rule_schema(&yaml).map_err(|e| e.message.to_string())
+        });
+        let session = match updated {
+            Some(Ok(session)) => session,
+            Some(Err(e)) => return Err(invalid_params(e)),
+            None => return Err(unknown_rule_session(&params.rule_session_id)),
+        };
+        let response = serde_json::json!({
+            "rule_session_id": params.rule_session_id,
+            "yaml": session.yaml,
+            "utils": session.utils.keys().collect::<Vec<_>>(),
+            "code_lines": session.code.lines().count(),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Test the rule of a rule session against its sample code and keep the matches. The response has the
+matches and, when the previous test ran on the same code, how many matches it had and the texts of the
+matches that appeared and disappeared since. A rule that matches nothing is retried once with stopBy: end
+on its relational rules that lack stopBy, as in test_match_code_rule.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json --stdin
+")]
+    async fn test_rule_session(
+        &self,
+        Parameters(params): Parameters<RuleSessionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let id = &params.rule_session_id;
+        let session = self.rule_sessions.get(id).ok_or_else(|| unknown_rule_session(id))?;
+        let yaml = with_utils(&session.yaml, &session.utils).map_err(invalid_params)?;
+        let (matches, retry) = self.test_rule_on_code(yaml, &session.code).await?;
+        let previous = self.rule_sessions.record(id, &session.yaml, &session.code, &matches);
+        let mut response = serde_json::json!({
+            "rule_session_id": id,
+            "test": session.tests + 1,
+            "match_count": matches.len(),
+            "matches": matches,
+        });
+        if let Some(previous) = previous {
+            let (added, removed) = match_changes(&previous, &matches);
+            response["previous_match_count"] = Value::from(previous.len());
+            response["added"] = Value::from(added);
+            response["removed"] = Value::from(removed);
+        }
+        if let Some(description) = retry {
+            response["note"] = Value::from(format!(
+                "The rule matched nothing as written; these matches were found with {}. Add `stopBy: end` there.",
+                description
+            ));
+        }
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+End a rule session and return its rule, with its utility rules added to the rule's local utils, ready
+for find_code_by_rule or a rule file, along with how often it was tested and the match count of its
+last test on the current code.
+")]
+    async fn finalize_rule_session(
+        &self,
+        Parameters(params): Parameters<RuleSessionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(session) = self.rule_sessions.remove(&params.rule_session_id) else {
+            return Err(unknown_rule_session(&params.rule_session_id));
+        };
+        let yaml = with_utils(&session.yaml, &session.utils).map_err(invalid_params)?;
+        let response = serde_json::json!({
+            "yaml": yaml,
+            "tests": session.tests,
+            "last_match_count": session.last_matches.as_ref().map(Vec::len),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Find code in a project folder that matches the given ast-grep pattern.
+Pattern is good for simple and single-AST node result.
+For more complex usage, please use YAML by `find_code_by_rule`.
+
+Internally calls: ast-grep run --pattern <pattern> [--json] <project_folder>
+(with patterns: ast-grep scan --inline-rules <one rule per language> [--json] <project_folder>;
+with constraints: ast-grep scan --inline-rules <pattern rule with constraints> [--json] <project_folder>)
+
+Dependency and build directories (node_modules, target, .venv, dist, build, .git) are skipped
+unless include_vendored=true. Files excluded by ignore files (.gitignore, .ignore, .sgignore) are
+skipped unless no_ignore=true; the response notes when a .sgignore/.ignore file is in effect.
+Matches in minified or binary-looking files (e.g. *.min.js) are dropped and listed under
+\"Skipped files\" unless include_minified=true. With collapse_duplicates=true, matches with identical
+text are grouped into one entry that lists every location. Pass additional_folders to search several
+project folders at once; they are scanned concurrently and reported with absolute paths.
+Very large folders are scanned in shards; if a shard fails the results are partial and the response
+says which shards to pass as shards=[...] to rescan. With profile=true the response includes a timing
+breakdown (ast-grep vs post-processing) and ast-grep's --inspect file/rule counters.
+With diagnose=true, a search that finds nothing retries relaxed variants of the query (stopBy: end,
+lower pattern strictness, each inside constraint dropped) and reports which one first finds matches.
+A search that finds nothing reports the query as run, how many files of its language were searched,
+the filters applied and suggestions on what to change.
+With fallback='relaxed', a search that finds nothing is retried with relaxed, then signature, pattern
+strictness; such results are labelled \"relaxed matches\".
+With aggregate='$NAME', the response lists the distinct texts bound to that metavariable across all
+matches, with how many matches and files bind each, most frequent first, instead of the matches.
+
+Output formats:
+- text (default): Compact text format with file:line-range headers and complete match text
+  Example:
+    Found 2 matches:
+
+    path/to/file.py:10-15
+    def example_function():
+        # function body
+        return result
+
+    path/to/file.py:20-22
+    def another_function():
+        pass
+
+- json: Full match objects with metadata including ranges, meta-variables, etc.
+  Every match has range.byteOffset.{start,end} and range.end.{line,column} for precise edits,
+  and a fingerprint (rule id + file + normalized text) that stays stable when lines shift.
+- headers: One line per match (id, file:line-range, rule id, one-line preview). Fetch the full
+  text and meta-variables of the matches you need with get_match_details(ids=[...]).
+
+The max_results parameter limits the number of complete matches returned (not individual lines).
+ast-grep stops once it has found that many, so on large projects the matches returned can differ
+between calls; the header then shows \"Found X matches (stopped at max_results, there may be more)\".
+Matches are sorted by file, line and column, so without max_results every call returns the same output.
+max_matches_per_file keeps at most that many matches per file, so one file full of matches does not use
+up max_results; the files it cut are listed with their true match count.
+
+To search a polyglot project for the same thing in several languages at once, pass patterns, a map of
+language to pattern, instead of pattern and language. All patterns run in one ast-grep invocation and
+each match carries its language and the rule id find-code-<language>. Without language, pattern is
+searched the same way in each language detected in the project folders (the 4 with the most files; json
+and yaml are left out), and the response notes which languages were searched.
+
+Example usage:
+  find_code(pattern=\"class $NAME\", max_results=20)  # Returns text format
+  find_code(pattern=\"class $NAME\", output_format=\"json\")  # Returns JSON with metadata
+  find_code(patterns={\"javascript\": \"console.log($$$)\", \"python\": \"print($$$)\"})
+")]
     async fn find_code(
         &self,
-        Parameters(params): Parameters<FindCodeParams>,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<FindCodeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if !params.patterns.is_empty() {
+            if !params.pattern.is_empty() || !params.language.is_empty() {
+                return Err(invalid_params("Give either pattern (and language) or patterns, not both."));
+            }
+            let rules = multi_language_rules(&params.patterns, &params.constraints).map_err(invalid_params)?;
+            let args = vec!["--inline-rules".to_string(), rules];
+            return self
+                .run_find("scan", args, &params.project_folder, &params.options, Vec::new(), Progress::new(&context))
+                .await;
+        }
+        if params.pattern.is_empty() {
+            return Err(invalid_params("pattern is required unless patterns is given."));
+        }
+
+        // Without a language, ast-grep parses a bare pattern as the one language it infers, silently
+        // missing the other languages of a polyglot project, so each detected language gets its
+        // own copy of the pattern instead
+        let mut language = params.language;
+        let mut notes = Vec::new();
+        if language.is_empty() {
+            let mut folders = vec![PathBuf::from(&params.project_folder)];
+            folders.extend(params.options.additional_folders.iter().map(PathBuf::from));
+            let mut counts = BTreeMap::new();
+            for folder in folders {
+                for (language, files) in self.folder_languages(folder, params.options.include_vendored).await? {
+                    *counts.entry(language).or_default() += files;
+                }
+            }
+            let mut detected = ranked_languages(counts);
+            let left_out: Vec<&str> = detected.iter().skip(MAX_DETECTED_LANGUAGES).map(|(name, _)| *name).collect();
+            detected.truncate(MAX_DETECTED_LANGUAGES);
+            if detected.len() > 1 {
+                let searched: Vec<&str> = detected.iter().map(|(name, _)| *name).collect();
+                notes.push(format!(
+                    "No language given: searched {}, the languages detected in the project; each match's rule id \
+                     find-code-<language> says which one it was parsed as.",
+                    searched.join(", ")
+                ));
+            }
+            if !left_out.is_empty() {
+                notes.push(format!(
+                    "Not searched: {} (fewer files); pass language to search one of them.",
+                    left_out.join(", ")
+                ));
+            }
+            if detected.len() > 1 {
+                let patterns = detected.iter().map(|(name, _)| (name.to_string(), params.pattern.clone())).collect();
+                let rules = multi_language_rules(&patterns, &params.constraints).map_err(invalid_params)?;
+                let args = vec!["--inline-rules".to_string(), rules];
+                let progress = Progress::new(&context);
+                return self.run_find("scan", args, &params.project_folder, &params.options, notes, progress).await;
+            }
+            if let Some((name, _)) = detected.first() {
+                language = name.to_string();
+            }
+        }
+
+        if !params.constraints.is_empty() {
+            if language.is_empty() {
+                return Err(invalid_params("language is required with constraints."));
+            }
+            let rule = constrained_rule(&language, &params.pattern, &params.constraints).map_err(invalid_params)?;
+            let args = vec!["--inline-rules".to_string(), rule];
+            return self
+                .run_find("scan", args, &params.project_folder, &params.options, notes, Progress::new(&context))
+                .await;
+        }
+
+        let mut args = vec!["--pattern".to_string(), params.pattern];
+        if !language.is_empty() {
+            args.push("--lang".to_string());
+            args.push(language);
+        }
+
+        self.run_find("run", args, &params.project_folder, &params.options, notes, Progress::new(&context))
+            .await
+    }
+
+    #[tool(description = "
+Find code using ast-grep's YAML rule in a project folder.
+YAML rule is more powerful than simple pattern and can perform complex search like find AST inside/having another AST.
+It is a more advanced search tool than the simple `find_code`.
+
+Tip: When using relational rules (inside/has), add `stopBy: end` to ensure complete traversal.
+Read the resources astgrep://docs/rule-cheatsheet and astgrep://schema/rule for the rule syntax.
+
+Internally calls: ast-grep scan --inline-rules <yaml> [--json] <project_folder>
+
+Dependency and build directories (node_modules, target, .venv, dist, build, .git) are skipped
+unless include_vendored=true. Files excluded by ignore files (.gitignore, .ignore, .sgignore) are
+skipped unless no_ignore=true; the response notes when a .sgignore/.ignore file is in effect.
+Matches in minified or binary-looking files (e.g. *.min.js) are dropped and listed under
+\"Skipped files\" unless include_minified=true. With collapse_duplicates=true, matches with identical
+text are grouped into one entry that lists every location. Pass additional_folders to search several
+project folders at once; they are scanned concurrently and reported with absolute paths.
+Very large folders are scanned in shards; if a shard fails the results are partial and the response
+says which shards to pass as shards=[...] to rescan. With profile=true the response includes a timing
+breakdown (ast-grep vs post-processing) and ast-grep's --inspect file/rule counters.
+With diagnose=true, a search that finds nothing retries relaxed variants of the query (stopBy: end,
+lower pattern strictness, each inside constraint dropped) and reports which one first finds matches.
+A search that finds nothing reports the query as run, how many files of its language were searched,
+the filters applied and suggestions on what to change.
+A rule that finds nothing is retried once with stopBy: end on its relational rules that lack stopBy,
+unless fallback='none'. With fallback='relaxed', it is then retried with relaxed, then signature, pattern
+strictness; such results are labelled \"relaxed matches\".
+With aggregate='$NAME', the response lists the distinct texts bound to that metavariable across all
+matches, with how many matches and files bind each, most frequent first, instead of the matches.
+
+Output formats:
+- text (default): Compact text format with file:line-range headers and complete match text
+  Example:
+    Found 2 matches:
+
+    src/models.py:45-52
+    class UserModel:
+        def __init__(self):
+            self.id = None
+            self.name = None
+
+    src/views.py:12
+    class SimpleView: pass
+
+- json: Full match objects with metadata including ranges, meta-variables, etc.
+  Every match has range.byteOffset.{start,end} and range.end.{line,column} for precise edits,
+  and a fingerprint (rule id + file + normalized text) that stays stable when lines shift.
+- headers: One line per match (id, file:line-range, rule id, one-line preview). Fetch the full
+  text and meta-variables of the matches you need with get_match_details(ids=[...]).
+
+The max_results parameter limits the number of complete matches returned (not individual lines).
+ast-grep stops once it has found that many, so on large projects the matches returned can differ
+between calls; the header then shows \"Found X matches (stopped at max_results, there may be more)\".
+Matches are sorted by file, line and column, so without max_results every call returns the same output.
+max_matches_per_file keeps at most that many matches per file, so one file full of matches does not use
+up max_results; the files it cut are listed with their true match count.
+
+Example usage:
+  find_code_by_rule(yaml=\"id: x\\nlanguage: python\\nrule: {pattern: 'class $NAME'}\", max_results=20)
+  find_code_by_rule(yaml=\"...\", output_format=\"json\")  # For full metadata
+")]
+    async fn find_code_by_rule(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<FindCodeByRuleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let yaml = with_utils(&params.yaml, &params.utils).map_err(invalid_params)?;
+        check_rule_schema(&yaml)?;
+        let args = vec!["--inline-rules".to_string(), yaml];
+
+        self.run_find("scan", args, &params.project_folder, &params.options, Vec::new(), Progress::new(&context))
+            .await
+    }
+
+    #[tool(description = "
+Find functions (or files) where one pattern matches and another does not, or where both match, for
+audit questions such as \"database writes outside a transaction\" or \"handlers that parse input and
+also log it\". With scope='function' (default), each match belongs to the outermost function around it,
+so callbacks and closures count as part of the function defining them; matches of pattern_a outside any
+function are only counted (a_outside_functions). mode='a_without_b'
+(default) reports scopes where pattern_a matches and pattern_b does not; mode='both' those where both do.
+Returns JSON results by file and position, each with the file, the function's lines and signature, and
+the lines of the matches.
+
+Internally calls: ast-grep scan --inline-rules <pattern_a, pattern_b and function kind rules> --json <project_folder>
+")]
+    async fn find_cooccurrence(
+        &self,
+        Parameters(params): Parameters<FindCooccurrenceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(mode) = CooccurrenceMode::parse(&params.mode) else {
+            return Err(invalid_params(format!(
+                "Invalid mode: {}. Must be {}.",
+                params.mode,
+                one_of(COOCCURRENCE_MODES)
+            )));
+        };
+        let function_scope = match params.scope.as_str() {
+            "" | "function" => true,
+            "file" => false,
+            _ => {
+                return Err(invalid_params(format!(
+                    "Invalid scope: {}. Must be {}.",
+                    params.scope,
+                    one_of(COOCCURRENCE_SCOPES)
+                )))
+            }
+        };
+        let rules = cooccurrence_rules(&params.language, &params.pattern_a, &params.pattern_b, function_scope)
+            .ok_or_else(|| {
+                invalid_params(format!(
+                    "find_cooccurrence does not know the functions of language '{}'; use scope='file'.",
+                    params.language
+                ))
+            })?;
+        let mut args = vec!["--inline-rules".to_string(), rules];
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+
+        let mut report = find_cooccurrences(&parse_matches(&result.stdout), mode, function_scope);
+        let total = report.results.len();
+        if params.max_results > 0 {
+            report.results.truncate(params.max_results);
+        }
+        let mut response = serde_json::json!({
+            "mode": if mode == CooccurrenceMode::Both { "both" } else { "a_without_b" },
+            "scope": if function_scope { "function" } else { "file" },
+            "total": total,
+            "results": report.results,
+        });
+        if report.a_outside_functions > 0 {
+            response["a_outside_functions"] = report.a_outside_functions.into();
+        }
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+List the files of a language that contain no match for a pattern or rule, e.g. test files that never
+call an assertion helper, or request handlers without an auth check. Give either pattern and language,
+or a YAML rule (whose language is used).
+
+Files are found by walking project_folder: hidden entries, minified or binary-looking files and, unless
+include_vendored, dependency and build directories are skipped. Ignore files and a rule's files/ignores
+globs do not narrow the list, so point project_folder at the part of the project to check.
+Returns JSON with files_checked, files_with_matches and the missing files in path order (relative to
+project_folder), at most max_results of them with total_missing when cut.
+
+Internally calls: ast-grep run --pattern <pattern> --lang <language> --json <project_folder>
+(or ast-grep scan --inline-rules <yaml> --json <project_folder>)
+")]
+    async fn find_missing(
+        &self,
+        Parameters(params): Parameters<FindMissingParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let folder = PathBuf::from(&params.project_folder);
+        if !folder.is_absolute() || !folder.is_dir() {
+            return Err(invalid_params(format!(
+                "project_folder must be an absolute path to a directory: {}",
+                params.project_folder
+            )));
+        }
+        let (command, mut args, language) = match (params.pattern.is_empty(), params.yaml.is_empty()) {
+            (false, true) => {
+                if params.language.is_empty() {
+                    return Err(invalid_params("language is required with a pattern."));
+                }
+                let args = vec![
+                    "--pattern".to_string(),
+                    params.pattern.clone(),
+                    "--lang".to_string(),
+                    params.language.clone(),
+                ];
+                ("run", args, params.language.clone())
+            }
+            (true, false) => {
+                check_rule_schema(&params.yaml)?;
+                let language =
+                    rule_language(&params.yaml).map_err(|e| invalid_params(format!("Invalid rule YAML: {}", e)))?;
+                ("scan", vec!["--inline-rules".to_string(), params.yaml.clone()], language)
+            }
+            _ => return Err(invalid_params("Give either pattern (with language) or yaml.")),
+        };
+        if language_extensions(&language).is_none() {
+            return Err(invalid_params(format!(
+                "Unknown language: {}. find_missing knows the file extensions of ast-grep's built-in languages only.",
+                language
+            )));
+        }
+
+        // Every listed file must be searched, including those ignore files would hide from ast-grep
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.extend(no_ignore_args(true));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let result = run_ast_grep(command, &args, None, &self.config).await.map_err(tool_error)?;
+        let matches = parse_matches(&result.stdout);
+
+        let (root, walk_language, include_vendored) = (folder.clone(), language.clone(), params.include_vendored);
+        let candidates = tokio::task::spawn_blocking(move || candidate_files(&root, &walk_language, include_vendored))
+            .await
+            .map_err(tool_error)?;
+        let report = missing_report(&language, &candidates, &matches, &folder, params.max_results);
+        let json_str = serde_json::to_string_pretty(&report).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Benchmark an ast-grep YAML rule against a project before shipping it to CI.
+Reports the wall time of a full scan, total matches and files with matches, and the slowest files:
+the largest files in the rule's language (up to profile_files) are scanned one at a time and timed,
+minus ast-grep's startup time. Catastrophically slow relational rules show up as outliers.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json <project_folder>, then once per profiled file
+")]
+    async fn benchmark_rule(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<BenchmarkRuleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let language = rule_language(&params.yaml).map_err(|e| invalid_params(format!("Invalid rule YAML: {}", e)))?;
+        let progress = Progress::new(&context);
+
+        let mut args = vec!["--inline-rules".to_string(), params.yaml.clone()];
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.push("--json".to_string());
+        let scan = |target: &str| {
+            let mut args = args.clone();
+            args.push(target.to_string());
+            async move {
+                let started = Instant::now();
+                let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+                Ok::<_, McpError>((started.elapsed(), parse_matches(&result.stdout)))
+            }
+        };
+
+        let (wall, matches) = scan(&params.project_folder).await?;
+        let mut files: Vec<&str> = matches.iter().filter_map(|m| m.get("file").and_then(|v| v.as_str())).collect();
+        files.sort_unstable();
+        files.dedup();
+
+        // Startup cost of one invocation, so per-file timings measure the rule and not process spawn
+        let baseline_args = ["--inline-rules".to_string(), params.yaml.clone(), "--json".to_string(), "--stdin".to_string()];
+        let started = Instant::now();
+        let startup = match run_ast_grep("scan", &baseline_args, Some(""), &self.config).await {
+            Ok(_) => started.elapsed(),
+            Err(_) => Duration::ZERO,
+        };
+
+        let root = PathBuf::from(&params.project_folder);
+        let (include_vendored, profile_files) = (params.include_vendored, params.profile_files);
+        let (candidates, candidate_files) = tokio::task::spawn_blocking(move || {
+            profile_candidates(list_files(&root, include_vendored), &language, profile_files)
+        })
+        .await
+        .map_err(tool_error)?;
+
+        let mut timings = Vec::new();
+        for (done, path) in candidates.iter().enumerate() {
+            let (elapsed, file_matches) = scan(&path.to_string_lossy()).await?;
+            let file = path.strip_prefix(&params.project_folder).unwrap_or(path);
+            timings.push(FileTiming {
+                file: file.to_string_lossy().into_owned(),
+                ms: millis(elapsed.saturating_sub(startup)),
+                matches: file_matches.len(),
+            });
+            progress.report(done + 1, candidates.len(), format!("Timed {} of {} files", done + 1, candidates.len())).await;
+        }
+        timings.sort_by(|a, b| b.ms.total_cmp(&a.ms));
+        timings.truncate(params.top_files);
+
+        let report = BenchmarkReport {
+            wall_ms: millis(wall),
+            total_matches: matches.len(),
+            files_with_matches: files.len(),
+            startup_ms: millis(startup),
+            candidate_files,
+            profiled_files: candidates.len(),
+            slowest_files: timings,
+        };
+        let json_str = serde_json::to_string_pretty(&report).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Explain what a find_code pattern or find_code_by_rule rule will do, without scanning the project:
+the targeted language and file extensions, how many candidate files exist (per extension), which
+exclusion globs and ignore files apply, and how ast-grep parses the pattern (root node kind, syntax
+errors, and whether it matches expected_kind). Use it to check a query before running a large search.
+
+Internally calls: ast-grep run --pattern <pattern> --lang <language> --debug-query=pattern
+")]
+    async fn explain_query(
+        &self,
+        Parameters(params): Parameters<ExplainQueryParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.pattern.is_empty() == params.yaml.is_empty() {
+            return Err(invalid_params("Give exactly one of pattern or yaml."));
+        }
+
+        let mut plan = QueryPlan {
+            language: params.language.clone(),
+            no_ignore: params.no_ignore,
+            ..Default::default()
+        };
+        let mut pattern = Some(params.pattern.clone()).filter(|p| !p.is_empty());
+        if !params.yaml.is_empty() {
+            if plan.language.is_empty() {
+                plan.language = rule_language(&params.yaml)
+                    .map_err(|e| invalid_params(format!("Invalid rule YAML: {}", e)))?;
+            }
+            pattern = rule_pattern(&params.yaml);
+            if pattern.is_none() {
+                plan.notes.push("The rule has no top-level pattern, so there is no pattern to parse.".to_string());
+            }
+        }
+        plan.extensions = language_extensions(&plan.language).map(|exts| exts.iter().map(|e| e.to_string()).collect());
+        if plan.language.is_empty() {
+            plan.notes.push("No language given; ast-grep infers it from each file's extension.".to_string());
+        } else if plan.extensions.is_none() {
+            plan.notes.push(format!(
+                "'{}' is not a built-in language; if it is a custom language, its extensions come from sgconfig.yaml.",
+                plan.language
+            ));
+        }
+
+        let root = PathBuf::from(&params.project_folder);
+        let (language, include_vendored) = (plan.language.clone(), params.include_vendored);
+        let files = tokio::task::spawn_blocking(move || {
+            list_files(&root, include_vendored)
+                .into_iter()
+                .filter(|path| language.is_empty() || is_language_file(path, &language))
+                .collect::<Vec<PathBuf>>()
+        })
+        .await
+        .map_err(tool_error)?;
+        plan.candidate_files = files.len();
+        for path in &files {
+            let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            *plan.files_by_extension.entry(extension).or_default() += 1;
+        }
+
+        if !params.include_vendored {
+            plan.excluded_globs = DEFAULT_EXCLUDES.iter().map(|dir| format!("!{}", dir)).collect();
+        }
+        let project_folder = Path::new(&params.project_folder);
+        if project_folder.join(".gitignore").is_file() {
+            plan.ignore_files.push(".gitignore".to_string());
+        }
+        plan.ignore_files.extend(detect_ignore_files(project_folder).iter().map(|f| f.to_string()));
+        if !plan.ignore_files.is_empty() && !params.no_ignore {
+            plan.notes.push("Candidate counts do not apply ignore files; ast-grep will skip the files they exclude.".to_string());
+        }
+
+        if let Some(pattern) = pattern {
+            if plan.language.is_empty() {
+                plan.notes.push("Pass language to check how the pattern parses.".to_string());
+            } else {
+                let args = [
+                    "--pattern".to_string(),
+                    pattern.clone(),
+                    "--lang".to_string(),
+                    plan.language.clone(),
+                    "--debug-query=pattern".to_string(),
+                ];
+                match run_ast_grep("run", &args, None, &self.config).await {
+                    Ok(result) => {
+                        let expected = Some(params.expected_kind.as_str()).filter(|k| !k.is_empty());
+                        plan.pattern = Some(PatternCheck::from_dump(&pattern, &result.stderr, expected));
+                    }
+                    Err(e) => plan.notes.push(format!("Could not parse the pattern: {}", e)),
+                }
+            }
+        }
+
+        let json_str = serde_json::to_string_pretty(&plan).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Watch a project folder for changes to a YAML rule's matches, e.g. to be told when someone reintroduces
+a banned pattern. The rule is first evaluated on the whole folder as a baseline; afterwards changed,
+created and deleted files are re-evaluated together once a burst of changes (such as a branch switch)
+settles and, when matches appear or disappear, the server sends a notifications/message log
+notification (logger \"watch_rule\", level notice) whose data has watch_id, added and removed
+matches (file, line, text, fingerprint). Moved matches do not count as changes, and changes to files
+a scan skips (hidden, listed in ignore files, or vendored) are left out. When the server runs with
+--rescan-interval, the whole folder is also rescanned that often, notifying only about differences. The watch lasts until unwatch or the end of the session;
+list_watches shows active watches.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json <project_folder | changed files>
+")]
+    async fn watch_rule(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<WatchRuleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let watch_id = uuid::Uuid::new_v4().to_string();
+        let (baseline_count, _) = self
+            .start_watch(peer, &watch_id, &params.yaml, &params.project_folder, params.include_vendored, None)
+            .await?;
+
+        let response = serde_json::json!({
+            "watch_id": watch_id,
+            "baseline_matches": baseline_count,
+            "notifications": format!("notifications/message with logger '{}'", WATCH_LOGGER),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+List the active watch_rule watches of this session: watch id, rule id and YAML, project folder, the
+rule's current match count, and how many matches appeared and disappeared in how many notifications.
+")]
+    async fn list_watches(&self) -> Result<CallToolResult, McpError> {
+        let json_str = serde_json::to_string_pretty(&self.watches.list()).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Stop a watch started with watch_rule. Watches also stop by themselves when the session ends.
+")]
+    async fn unwatch(
+        &self,
+        Parameters(params): Parameters<UnwatchParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if !self.watches.remove(&params.watch_id) {
+            return Err(invalid_params(format!("Unknown watch id '{}'", params.watch_id)));
+        }
+        Ok(CallToolResult::success(vec![Content::text(format!("Stopped watch {}", params.watch_id))]))
+    }
+
+    #[tool(description = "
+Save the state of this session to a JSON file, so a long investigation survives a client restart
+(which ends a stdio server): the rule sessions (rule YAML, utils, sample code, last test matches) and
+the watch_rule watches with their current matches as baselines. Restore it with import_session. The
+file must be inside an allowed root: the directories given with --allowed-root, or else the roots
+provided by the MCP client; an existing file is replaced.
+")]
+    async fn export_session(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<SessionSnapshotParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let path = Path::new(&params.path);
+        let (Some(folder), Some(file_name)) = (path.parent(), path.file_name()) else {
+            return Err(invalid_params(format!("Invalid snapshot path '{}'", params.path)));
+        };
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(folder, &roots).map_err(invalid_params)?.join(file_name);
+
+        let snapshot = SessionSnapshot {
+            version: SNAPSHOT_VERSION,
+            exported_at: utc_timestamp(SystemTime::now()),
+            rule_sessions: self
+                .rule_sessions
+                .list()
+                .into_iter()
+                .map(|(rule_session_id, session)| RuleSessionSnapshot { rule_session_id, session })
+                .collect(),
+            watches: self
+                .watches
+                .snapshot()
+                .into_iter()
+                .map(|(info, matches)| WatchSnapshot {
+                    watch_id: info.watch_id,
+                    yaml: info.yaml,
+                    project_folder: info.project_folder,
+                    include_vendored: info.include_vendored,
+                    matches,
+                })
+                .collect(),
+        };
+        save_snapshot(&path, &snapshot).map_err(tool_error)?;
+
+        let response = serde_json::json!({
+            "path": path,
+            "rule_sessions": snapshot.rule_sessions.len(),
+            "watches": snapshot.watches.len(),
+            "baseline_matches": snapshot.watches.iter().map(|watch| watch.matches.len()).sum::<usize>(),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Restore a session saved with export_session. Rule sessions come back under their old ids, replacing
+sessions with the same id. Each watch is restarted under its old id with a full scan of its folder,
+and the response lists the matches that appeared (added) and disappeared (removed) since the
+snapshot, as in watch_rule notifications; a watch whose folder is gone is reported with an error.
+The file must be inside an allowed root.
+
+Internally calls: ast-grep scan --inline-rules <yaml> --json <project_folder> for each watch
+")]
+    async fn import_session(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<SessionSnapshotParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(Path::new(&params.path), &roots).map_err(invalid_params)?;
+        let snapshot = load_snapshot(&path)
+            .map_err(|e| invalid_params(format!("Cannot read session snapshot '{}': {}", path.display(), e)))?;
+
+        let mut rule_sessions = Vec::new();
+        let mut dropped = Vec::new();
+        for saved in snapshot.rule_sessions {
+            dropped.extend(self.rule_sessions.restore(saved.rule_session_id.clone(), saved.session));
+            rule_sessions.push(saved.rule_session_id);
+        }
+
+        let mut watches = Vec::new();
+        for saved in snapshot.watches {
+            self.watches.remove(&saved.watch_id);
+            let started = self
+                .start_watch(
+                    peer.clone(),
+                    &saved.watch_id,
+                    &saved.yaml,
+                    &saved.project_folder,
+                    saved.include_vendored,
+                    Some(saved.matches),
+                )
+                .await;
+            watches.push(match started {
+                Ok((matches, changes)) => serde_json::json!({
+                    "watch_id": saved.watch_id,
+                    "project_folder": saved.project_folder,
+                    "matches": matches,
+                    "added": changes.added.iter().map(match_summary).collect::<Vec<_>>(),
+                    "removed": changes.removed.iter().map(match_summary).collect::<Vec<_>>(),
+                }),
+                Err(e) => serde_json::json!({
+                    "watch_id": saved.watch_id,
+                    "project_folder": saved.project_folder,
+                    "error": e.message,
+                }),
+            });
+        }
+
+        let mut response = serde_json::json!({
+            "exported_at": snapshot.exported_at,
+            "rule_sessions": rule_sessions,
+            "watches": watches,
+        });
+        if !dropped.is_empty() {
+            response["dropped_rule_sessions"] = serde_json::json!(dropped);
+        }
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Start a find_code/find_code_by_rule search in the background and return a job id immediately, for
+scans of very large repositories that would outlast a client's tool call timeout. Takes the same
+arguments as find_code (pattern) or find_code_by_rule (yaml), including all find options.
+Poll scan_status(job_id), get early hits with fetch_partial(job_id) and the final output with
+fetch_scan_results(job_id). Finished results are kept for 30 minutes or until fetched.
+")]
+    async fn submit_scan(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<SubmitScanParams>,
+    ) -> Result<CallToolResult, McpError> {
+        validate_output_format(&params.options.output_format)?;
+        self.filter_profile(&params.options.filter_profile)?;
+        scan_query(&params)?;
+        let request = serde_json::to_value(&params).map_err(tool_error)?;
+        let job_id = self.jobs.submit(&scan_query_text(&request), request);
+        self.start_scan_job(&context, &job_id, params)?;
+
+        let response = serde_json::json!({
+            "job_id": job_id,
+            "state": "running",
+            "next": "Poll scan_status(job_id) or fetch_partial(job_id), then call fetch_scan_results(job_id).",
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Resume a background scan that failed or was interrupted (e.g. by a server restart) from its
+checkpoint: parts of the project that were already scanned are skipped and their matches kept, so
+a long scan does not start over. Checkpoints are saved per finished part (shard, or folder with
+additional_folders) in the server's checkpoint directory (--checkpoint-dir), so a folder scanned
+in one part restarts from the beginning. If files were added to or removed from the project since,
+its parts are planned differently and the resumed scan fails instead of skipping the wrong files;
+submit a new scan then. Continue with scan_status/fetch_scan_results as usual.
+")]
+    async fn resume_scan(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<ScanJobParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let checkpoint = self.jobs.checkpoint(&params.job_id).map_err(invalid_params)?;
+        let request: SubmitScanParams = serde_json::from_value(checkpoint.request.clone())
+            .map_err(|e| tool_error(format!("Invalid checkpoint for job '{}': {}", params.job_id, e)))?;
+        let (parts_done, matches) = (checkpoint.completed.len(), checkpoint.matches.len());
+        if !self.jobs.resume(&scan_query_text(&checkpoint.request), checkpoint) {
+            return Err(invalid_params(format!("Job '{}' is still running", params.job_id)));
+        }
+        self.start_scan_job(&context, &params.job_id, request)?;
+
+        let response = serde_json::json!({
+            "job_id": params.job_id,
+            "state": "running",
+            "parts_already_scanned": parts_done,
+            "matches_so_far": matches,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Report the state of a background scan started with submit_scan: running, done, failed, or
+interrupted (left a checkpoint in an earlier server process), with the elapsed time, parts scanned,
+whether resume_scan can continue it, and for finished jobs how long the results are kept.
+")]
+    async fn scan_status(
+        &self,
+        Parameters(params): Parameters<ScanJobParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(status) = self.jobs.status(&params.job_id) else {
+            return Err(invalid_params(format!("Unknown or expired job id '{}'", params.job_id)));
+        };
+        let json_str = serde_json::to_string_pretty(&status).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Get the matches a background scan started with submit_scan has found so far, to start working on
+early hits before the scan finishes. Large folders are scanned in parts (shards, and one part per
+folder with additional_folders); matches become available as each part finishes, so a folder
+scanned in one part only has results when it is done. Matches are raw ast-grep JSON, not yet
+filtered, sorted or de-duplicated; pass next_since as since to get only newer matches.
+")]
+    async fn fetch_partial(
+        &self,
+        Parameters(params): Parameters<FetchPartialParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(partial) = self.jobs.partial(&params.job_id, params.since) else {
+            return Err(invalid_params(format!("Unknown or expired job id '{}'", params.job_id)));
+        };
+        let json_str = serde_json::to_string_pretty(&partial).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Get the output of a finished background scan started with submit_scan, formatted as the find tools
+format it (output_format, max_results and the other find options apply). The results are removed
+once fetched. While the scan is still running this returns its status instead.
+")]
+    async fn fetch_scan_results(
+        &self,
+        Parameters(params): Parameters<ScanJobParams>,
+    ) -> Result<CallToolResult, McpError> {
+        match self.jobs.take(&params.job_id) {
+            None => Err(invalid_params(format!("Unknown or expired job id '{}'", params.job_id))),
+            Some(JobOutcome::Running(status)) => {
+                let json_str = serde_json::to_string_pretty(&status).unwrap_or_default();
+                Ok(CallToolResult::success(vec![Content::text(json_str)]))
+            }
+            Some(JobOutcome::Finished(Ok(result))) => Ok(result),
+            Some(JobOutcome::Finished(Err(e))) => Err(tool_error(format!("Scan {} failed: {}", params.job_id, e))),
+        }
+    }
+
+    #[tool(description = "
+Search a project and write every match into a SQLite database instead of returning it, so large
+result sets can be explored with ad-hoc SQL. Each call adds a row to `scans` (id, project_folder,
+query, created_at) and its matches to `matches` (scan_id, file, rule_id, severity, message,
+start_line/start_column/end_line/end_column (zero-based), start_byte/end_byte, text, fingerprint);
+metavariables go to `metavariables` (match_id, name, kind single|multi|transformed, text).
+A manifest of the code state the matches refer to goes to `manifests` (scan_id, git_commit,
+git_dirty, files_scanned, query_hash, ast_grep_version, started_at, finished_at).
+The database's folder must be inside an allowed root (--allowed-root, or the roots provided by the
+MCP client). Returns the scan id, match count and manifest.
+
+Internally calls: ast-grep run --pattern <pattern> --inspect summary --json <project_folder>
+              or: ast-grep scan --inline-rules <yaml> --inspect summary --json <project_folder>
+              and git rev-parse HEAD, git status --porcelain in <project_folder>
+")]
+    async fn export_matches(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<ExportMatchesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let (command, mut args, query) = match (params.pattern.is_empty(), params.yaml.is_empty()) {
+            (false, true) => {
+                let mut args = vec!["--pattern".to_string(), params.pattern.clone()];
+                if !params.language.is_empty() {
+                    args.push("--lang".to_string());
+                    args.push(params.language.clone());
+                }
+                ("run", args, params.pattern.clone())
+            }
+            (true, false) => ("scan", vec!["--inline-rules".to_string(), params.yaml.clone()], params.yaml.clone()),
+            _ => return Err(invalid_params("Pass exactly one of pattern or yaml")),
+        };
+
+        let database = Path::new(&params.database);
+        let (Some(folder), Some(file_name)) = (database.parent(), database.file_name()) else {
+            return Err(invalid_params(format!("Invalid database path '{}'", params.database)));
+        };
+        let roots = self.allowed_roots(&peer).await;
+        let database = resolve_within_roots(folder, &roots).map_err(invalid_params)?.join(file_name);
+
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.extend(inspect_args(true));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let started_at = utc_timestamp(SystemTime::now());
+        let (result, (git_commit, git_dirty), version) = tokio::join!(
+            run_ast_grep(command, &args, None, &self.config),
+            git_state(&params.project_folder),
+            ast_grep_version()
+        );
+        let result = result.map_err(tool_error)?;
+        let mut counters = BTreeMap::new();
+        add_inspect_counters(&result.stderr, &mut counters);
+        let manifest = ScanManifest {
+            git_commit,
+            git_dirty,
+            files_scanned: counters.get("file.scannedFileCount").copied(),
+            query_hash: query_hash(&query, &params.language),
+            ast_grep_version: version,
+            started_at,
+            finished_at: utc_timestamp(SystemTime::now()),
+        };
+
+        let project_folder = Path::new(&params.project_folder);
+        let mut matches = parse_matches(&result.stdout);
+        normalize_match_ranges(&mut matches, project_folder);
+        sort_by_position(&mut matches);
+        fix_match_encodings(&mut matches, project_folder);
+        add_fingerprints(&mut matches);
+
+        let count = matches.len();
+        let path = database.clone();
+        let project = params.project_folder.clone();
+        let recorded = manifest.clone();
+        let scan_id = tokio::task::spawn_blocking(move || {
+            let mut conn = rusqlite::Connection::open(&path)?;
+            let scan_id = export_matches(&mut conn, &project, &query, &matches)?;
+            record_manifest(&conn, scan_id, &recorded)?;
+            Ok::<_, rusqlite::Error>(scan_id)
+        })
+        .await
+        .map_err(tool_error)?
+        .map_err(tool_error)?;
+
+        let response = serde_json::json!({
+            "database": database,
+            "scan_id": scan_id,
+            "matches": count,
+            "manifest": manifest,
+            "example_query": format!("SELECT file, COUNT(*) FROM matches WHERE scan_id = {} GROUP BY file ORDER BY 2 DESC", scan_id),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Assess the rules of a rules directory on a project, to prune dead lint rules: per rule, the matches
+and files with matches, and the rules that never fire. All rules are run in one scan. With
+history_database (a database export_matches wrote scans of the same project_folder into), rules
+without matches now get the time they last matched (UTC), and only rules that never matched there
+either are listed as never_matched. Rules using utils from utilDirs cannot be run this way.
+
+Internally calls: ast-grep scan --inline-rules <rules of rules_dir> --json <project_folder>
+")]
+    async fn rule_effectiveness(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<RuleEffectivenessParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let rules_dir = PathBuf::from(&params.rules_dir);
+        if !rules_dir.is_absolute() || !rules_dir.is_dir() {
+            return Err(invalid_params(format!(
+                "rules_dir must be an absolute path to a directory: {}",
+                params.rules_dir
+            )));
+        }
+        let mut rules = Vec::new();
+        for path in rule_files(&rules_dir) {
+            let relative = path.strip_prefix(&rules_dir).unwrap_or(&path).to_string_lossy().into_owned();
+            let yaml = std::fs::read_to_string(&path).map_err(tool_error)?;
+            let sources = parse_rule_sources(&yaml, &relative)
+                .map_err(|e| invalid_params(format!("Invalid rule file {}: {}", relative, e)))?;
+            rules.extend(sources);
+        }
+        if rules.is_empty() {
+            return Err(invalid_params(format!("No rules with an id found in {}", params.rules_dir)));
+        }
+        let history = if params.history_database.is_empty() {
+            HashMap::new()
+        } else {
+            let roots = self.allowed_roots(&peer).await;
+            let database =
+                resolve_within_roots(Path::new(&params.history_database), &roots).map_err(invalid_params)?;
+            let project = params.project_folder.clone();
+            tokio::task::spawn_blocking(move || {
+                let flags = rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY;
+                last_matched(&rusqlite::Connection::open_with_flags(&database, flags)?, &project)
+            })
+            .await
+            .map_err(tool_error)?
+            .map_err(|e| tool_error(format!("Cannot read history_database: {}", e)))?
+        };
+
+        let documents: Vec<&str> = rules.iter().map(|rule| rule.yaml.as_str()).collect();
+        let mut args = vec!["--inline-rules".to_string(), documents.join("---\n")];
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+        let report = effectiveness_report(&rules, &parse_matches(&result.stdout), &history);
+        let json_str = serde_json::to_string_pretty(&report).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Count the matches of a pattern at several git revisions, oldest first, to answer questions such as
+\"is usage of this deprecated API shrinking?\". Give revisions (commits, tags, branches), or let the
+tool scan count commits of HEAD's first-parent history, step commits apart (default: 5 commits,
+20 apart). At most 20 revisions are scanned per call.
+
+Each revision is checked out into a temporary git worktree that is removed afterwards; the working
+tree of the project is not touched. The result lists, per revision, its commit, date, subject,
+matches and files with matches, and a summary of the change from the oldest to the newest.
+
+Internally calls: git worktree add --detach <temp dir> <revision>, then
+ast-grep run --pattern <pattern> [--lang <language>] --json <worktree>
+")]
+    async fn pattern_trend(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(params): Parameters<PatternTrendParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let git = |args: &[&str]| {
+            let mut command = vec!["git".to_string(), "-C".to_string(), params.project_folder.clone()];
+            command.extend(args.iter().map(|arg| arg.to_string()));
+            command
+        };
+        let toplevel = run_command(&git(&["rev-parse", "--show-toplevel"]), None, Some(GIT_TIMEOUT))
+            .await
+            .map_err(|_| invalid_params(format!("{} is not inside a git repository.", params.project_folder)))?;
+        let toplevel = PathBuf::from(toplevel.stdout.trim());
+        let folder = std::fs::canonicalize(&params.project_folder).map_err(tool_error)?;
+        let subdirectory = folder.strip_prefix(&toplevel).map(Path::to_path_buf).unwrap_or_default();
+
+        let commits = if params.revisions.is_empty() {
+            if params.count == 0 {
+                return Err(invalid_params("count must be at least 1."));
+            }
+            let count = params.count.min(MAX_REVISIONS);
+            let limit = format!("-n{}", (count - 1) * params.step.max(1) + 1);
+            let log = git(&["log", "--first-parent", COMMIT_FORMAT, &limit, "HEAD"]);
+            let log = run_command(&log, None, Some(GIT_TIMEOUT)).await.map_err(tool_error)?;
+            let sampled = sample_commits(parse_commits(&log.stdout), count, params.step);
+            sampled.into_iter().map(|commit| (commit.commit.clone(), commit)).collect()
+        } else {
+            if params.revisions.len() > MAX_REVISIONS {
+                return Err(invalid_params(format!("At most {} revisions can be scanned per call.", MAX_REVISIONS)));
+            }
+            let mut commits = Vec::new();
+            for revision in &params.revisions {
+                let spec = format!("{}^{{commit}}", revision);
+                let log = git(&["log", "-1", COMMIT_FORMAT, &spec, "--"]);
+                let unknown = || invalid_params(format!("Unknown revision '{}'", revision));
+                let log = run_command(&log, None, Some(GIT_TIMEOUT)).await.map_err(|_| unknown())?;
+                commits.push((revision.clone(), parse_commits(&log.stdout).pop().ok_or_else(unknown)?));
+            }
+            commits
+        };
+
+        let temp_dir = self.temp_dir().map_err(tool_error)?;
+        let progress = Progress::new(&context);
+        let total = commits.len();
+        let mut points = Vec::new();
+        for (done, (revision, commit)) in commits.into_iter().enumerate() {
+            progress.report(done, total, format!("Scanning {}", revision)).await;
+            let path = temp_dir.path().join(format!("trend-{}", commit.commit));
+            let worktree = Worktree::add(&params.project_folder, path, &commit.commit, self.config.timeout)
+                .await
+                .map_err(tool_error)?;
+            let mut args = vec!["--pattern".to_string(), params.pattern.clone()];
+            if !params.language.is_empty() {
+                args.extend(["--lang".to_string(), params.language.clone()]);
+            }
+            args.extend(exclude_glob_args(false));
+            args.extend(["--json".to_string(), worktree.path.join(&subdirectory).display().to_string()]);
+            let result = run_ast_grep("run", &args, None, &self.config).await;
+            worktree.remove(Some(GIT_TIMEOUT)).await;
+            let result = result.map_err(tool_error)?;
+            points.push(TrendPoint::new(revision, commit, &parse_matches(&result.stdout)));
+        }
+
+        let response = serde_json::json!({
+            "pattern": params.pattern,
+            "summary": trend_summary(&points),
+            "revisions": points,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Search the files of a git revision (commit, tag, branch) for a pattern without checking it out, e.g. to
+verify whether an anti-pattern existed before a change. The files under paths (default: all of
+project_folder) at rev are read from git's object store into a temporary directory that is removed
+afterwards; the working tree and index are not touched. Dependency and build directories are skipped,
+and at most 20000 files are read per call. Match paths are relative to the repository root. Returns
+JSON with the resolved commit, the number of files searched and the matches.
+
+Internally calls: git ls-tree -r <rev> -- <paths>, git cat-file --batch, then
+ast-grep run --pattern <pattern> [--lang <language>] --json <temp dir>
+")]
+    async fn find_code_at_rev(
+        &self,
+        Parameters(params): Parameters<FindCodeAtRevParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let git = |args: &[&str]| {
+            let mut command = vec!["git".to_string(), "-C".to_string(), params.project_folder.clone()];
+            command.extend(args.iter().map(|arg| arg.to_string()));
+            command
+        };
+        let spec = format!("{}^{{commit}}", params.rev);
+        // `--quiet` makes an unknown revision exit 1 without output, which `run_command` takes for no matches
+        let commit = run_command_bytes(&git(&["rev-parse", "--verify", "--quiet", &spec]), None, Some(GIT_TIMEOUT))
+            .await
+            .map_err(|_| {
+                invalid_params(format!(
+                    "Unknown revision '{}', or {} is not inside a git repository.",
+                    params.rev, params.project_folder
+                ))
+            })?;
+        let commit = String::from_utf8_lossy(&commit).trim().to_string();
+
+        let mut ls_tree = vec!["ls-tree", "-r", "-z", "--full-name", &commit, "--"];
+        if params.paths.is_empty() {
+            ls_tree.push(".");
+        }
+        ls_tree.extend(params.paths.iter().map(String::as_str));
+        let listing = run_command_bytes(&git(&ls_tree), None, Some(GIT_TIMEOUT)).await.map_err(tool_error)?;
+        let files: Vec<TreeFile> =
+            parse_ls_tree(&listing).into_iter().filter(|file| is_searchable(&file.path, &params.language)).collect();
+        if files.len() > MAX_REV_FILES {
+            return Err(invalid_params(format!(
+                "{} has {} files to search under the given paths; narrow paths to at most {}.",
+                params.rev,
+                files.len(),
+                MAX_REV_FILES
+            )));
+        }
+
+        let objects: String = files.iter().map(|file| format!("{}\n", file.object)).collect();
+        let batch = run_command_bytes(&git(&["cat-file", "--batch"]), Some(&objects), self.config.timeout)
+            .await
+            .map_err(tool_error)?;
+        let temp_dir = self.temp_dir().map_err(tool_error)?;
+        let root = temp_dir.path().join(format!("rev-{}-{}", commit, uuid::Uuid::new_v4().simple()));
+        let files_searched = files.len();
+        let written = {
+            let root = root.clone();
+            tokio::task::spawn_blocking(move || {
+                for (file, contents) in files.iter().zip(parse_cat_file_batch(&batch)) {
+                    let path = root.join(&file.path);
+                    if let (Some(contents), Some(dir)) = (contents, path.parent()) {
+                        std::fs::create_dir_all(dir)?;
+                        std::fs::write(&path, contents)?;
+                    }
+                }
+                std::fs::create_dir_all(&root)
+            })
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|written| written)
+        };
+
+        let result = match written {
+            Ok(()) => {
+                let mut args = vec!["--pattern".to_string(), params.pattern.clone()];
+                if !params.language.is_empty() {
+                    args.extend(["--lang".to_string(), params.language.clone()]);
+                }
+                args.extend(no_ignore_args(true));
+                args.extend(["--json".to_string(), root.display().to_string()]);
+                run_ast_grep("run", &args, None, &self.config).await.map_err(tool_error)
+            }
+            Err(e) => Err(tool_error(e)),
+        };
+        let removed = {
+            let root = root.clone();
+            tokio::task::spawn_blocking(move || std::fs::remove_dir_all(root)).await.map_err(std::io::Error::other)
+        };
+        if let Err(e) = removed.and_then(|removed| removed) {
+            tracing::warn!(dir = %root.display(), error = %e, "cannot remove find_code_at_rev files");
+        }
+        let mut matches = parse_matches(&result?.stdout);
+
+        for m in &mut matches {
+            if let Some(file) = m.get("file").and_then(Value::as_str) {
+                let relative = Path::new(file).strip_prefix(&root).map(|path| path.display().to_string());
+                if let Ok(relative) = relative {
+                    m["file"] = Value::from(relative);
+                }
+            }
+        }
+        sort_by_position(&mut matches);
+        let total = matches.len();
+        if params.max_results > 0 {
+            matches.truncate(params.max_results);
+        }
+        let response = serde_json::json!({
+            "rev": params.rev,
+            "commit": commit,
+            "files_searched": files_searched,
+            "total": total,
+            "matches": matches,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Outline a source file: its classes, interfaces, structs, traits/impls, modules, functions and methods
+with 1-based line ranges, nested as in the source (methods inside their class, nested functions
+inside their function). Use it to get a map of a file before reading parts of it with read_file_range.
+Supported languages: python, javascript, typescript, tsx, rust, go, java, csharp, ruby, php.
+
+Internally calls: ast-grep scan --inline-rules <definition kind rules> --json <path>
+")]
+    async fn outline_file(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<OutlineFileParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(Path::new(&params.path), &roots).map_err(invalid_params)?;
+        let language = match params.language.as_str() {
+            "" => language_for_path(&path).map(str::to_string).ok_or_else(|| {
+                invalid_params(format!("Cannot infer the language of {}; pass language.", params.path))
+            })?,
+            language => language.to_string(),
+        };
+        let Some(rules) = outline_rules(&language) else {
+            return Err(invalid_params(format!("outline_file does not support language '{}'.", language)));
+        };
+        let args = [
+            "--inline-rules".to_string(),
+            rules,
+            "--json".to_string(),
+            path.to_string_lossy().into_owned(),
+        ];
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+
+        let response = serde_json::json!({
+            "file": params.path,
+            "language": language,
+            "items": build_outline(&parse_matches(&result.stdout)),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Extract an approximate call graph of a project in one language: which functions call which, as an
+adjacency list from caller to callees (or a Graphviz DOT digraph with format=dot). Useful to see what
+a refactoring may affect. Calls are attributed to the innermost enclosing function definition
+(`<top-level>` outside any function) and matched to definitions by name only, so same-named
+functions and methods are merged and dynamic dispatch is not resolved. Calls to functions not
+defined in the project are dropped unless include_external=true.
+
+Internally calls: ast-grep scan --inline-rules <function definition/call rules> --json <project_folder>
+")]
+    async fn call_graph(
+        &self,
+        Parameters(params): Parameters<CallGraphParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(rules) = call_graph_rules(&params.language) else {
+            return Err(invalid_params(format!(
+                "call_graph does not support language '{}'.",
+                params.language
+            )));
+        };
+        if !params.format.is_empty() && !CALL_GRAPH_FORMATS.contains(&params.format.as_str()) {
+            return Err(invalid_params(format!(
+                "Invalid format '{}': expected {}",
+                params.format,
+                one_of(CALL_GRAPH_FORMATS)
+            )));
+        }
+        let mut args = vec!["--inline-rules".to_string(), rules];
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+
+        let graph = build_call_graph(&parse_matches(&result.stdout), params.include_external);
+        if params.format == "dot" {
+            return Ok(CallToolResult::success(vec![Content::text(graph.to_dot())]));
+        }
+        let response = serde_json::json!({
+            "language": params.language,
+            "function_count": graph.functions.len(),
+            "edge_count": graph.edge_count(),
+            "functions": graph.functions,
+            "edges": graph.edges,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Check that every source file of a language starts with a required header comment (e.g. a license),
+and optionally insert it where missing. `header` is plain text; it is written as a comment in the
+language's style. Files count as having the header when the comments at the top contain its text,
+ignoring comment markers and whitespace. Insertion goes after any shebang, encoding pragma, XML
+declaration or <?php tag, so those keep working. The walk skips ignored files, vendored directories
+(unless include_vendored=true) and symbolic links. With insert=true the project folder must be inside
+an allowed root (--allowed-root, or the roots provided by the MCP client); files are written
+atomically and their originals backed up, so undo_rewrite can put them back (see backup_id).
+")]
+    async fn license_header(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<LicenseHeaderParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(style) = comment_style(&params.language) else {
+            return Err(invalid_params(format!(
+                "license_header does not support language '{}'.",
+                params.language
+            )));
+        };
+        if params.header.trim().is_empty() {
+            return Err(invalid_params("header must not be empty"));
+        }
+        let root = if params.insert {
+            let roots = self.allowed_roots(&peer).await;
+            resolve_within_roots(Path::new(&params.project_folder), &roots).map_err(invalid_params)?
+        } else {
+            PathBuf::from(&params.project_folder)
+        };
+        let backup_dir = match (&self.config.backup_dir, params.insert) {
+            (Some(dir), true) => Some(dir.clone()),
+            (None, true) => {
+                return Err(tool_error("No backup directory could be determined; start the server with --backup-dir."))
+            }
+            (_, false) => None,
+        };
+        let mut backup = Backup::new(&root, "");
+
+        let walk_root = root.clone();
+        let include_vendored = params.include_vendored;
+        let files = tokio::task::spawn_blocking(move || list_files(&walk_root, include_vendored))
+            .await
+            .map_err(tool_error)?;
+        let mut checked = 0;
+        let mut missing = Vec::new();
+        let mut inserted = Vec::new();
+        let mut skipped = Vec::new();
+        for file in files.iter().filter(|f| is_language_file(f, &params.language)) {
+            let relative = file.strip_prefix(&root).unwrap_or(file).to_string_lossy().into_owned();
+            let Ok(source) = std::fs::read_to_string(file) else {
+                skipped.push(serde_json::json!({"file": relative, "reason": "not valid UTF-8"}));
+                continue;
+            };
+            checked += 1;
+            if has_header(&source, &params.header, style) {
+                continue;
+            }
+            if let Some(backup_dir) = &backup_dir {
+                let rewritten = insert_header(&source, &params.header, style);
+                match backup.replace(backup_dir, file, source.as_bytes(), rewritten.as_bytes()) {
+                    Ok(()) => inserted.push(relative.clone()),
+                    Err(e) => skipped.push(serde_json::json!({"file": relative, "reason": e.to_string()})),
+                }
+            }
+            missing.push(relative);
+        }
+        if let (Some(backup_dir), false) = (&backup_dir, backup.files.is_empty()) {
+            self.record_backup(backup_dir, &backup)?;
+        }
+
+        let response = serde_json::json!({
+            "checked": checked,
+            "with_header": checked - missing.len(),
+            "missing": missing,
+            "inserted": inserted,
+            "skipped": skipped,
+            "backup_id": (!backup.files.is_empty()).then_some(&backup.id),
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Suppress one finding of a rule: insert an `ast-grep-ignore: <rule_id>` comment on the line above it,
+with the justification as a comment above that, so later scans skip it and reviewers can see why.
+An `ast-grep-ignore` comment already above the finding gets the rule id added. Comments are indented
+like the finding and written in the language's comment style. Returns the inserted lines and the
+finding's new line number; with dry_run=true the file is left unchanged. The file is written
+atomically and its original backed up, so undo_rewrite can put it back (see backup_id).
+
+The file must be inside an allowed root: the directories given with --allowed-root, or else the roots
+provided by the MCP client.
+")]
+    async fn suppress_finding(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<SuppressFindingParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(Path::new(&params.path), &roots).map_err(invalid_params)?;
+        let language = match params.language.as_str() {
+            "" => language_for_path(&path).unwrap_or_default(),
+            language => language,
+        };
+        let Some(style) = comment_style(language) else {
+            return Err(invalid_params(format!(
+                "suppress_finding does not know the comment style of '{}'; pass a supported language.",
+                params.path
+            )));
+        };
+        let source = std::fs::read_to_string(&path).map_err(tool_error)?;
+        let suppression = insert_suppression(&source, params.line, &params.rule_id, &params.justification, style)
+            .map_err(invalid_params)?;
+        let mut backup_id = None;
+        if !params.dry_run {
+            let Some(backup_dir) = &self.config.backup_dir else {
+                return Err(tool_error("No backup directory could be determined; start the server with --backup-dir."));
+            };
+            let mut backup = Backup::new(path.parent().unwrap_or(&path), "");
+            backup
+                .replace(backup_dir, &path, source.as_bytes(), suppression.source.as_bytes())
+                .map_err(tool_error)?;
+            self.record_backup(backup_dir, &backup)?;
+            tracing::info!(
+                file = %path.display(),
+                line = params.line,
+                rule_id = %params.rule_id,
+                justification = %params.justification,
+                backup_id = %backup.id,
+                "Suppressed a finding"
+            );
+            backup_id = Some(backup.id);
+        }
+
+        let response = serde_json::json!({
+            "file": path.display().to_string(),
+            "rule_id": params.rule_id,
+            "inserted": suppression.lines,
+            "line": suppression.line,
+            "written": !params.dry_run,
+            "backup_id": backup_id,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Propose a starter sgconfig.yaml for a project without one: the project's files are surveyed for the
+languages present and for existing rule, rule test and utility rule directories (YAML files with
+id/language/rule, or id and valid/invalid), which become ruleDirs, testConfigs and utilDirs; kinds
+the project has none of get the `ast-grep new` defaults (rules, rule-tests, utils). By default only
+the survey and the proposed config are returned, to review first; call again with write=true to
+write sgconfig.yaml at the project root and create the missing directories. An existing
+sgconfig.yaml is never overwritten. With write=true the project folder must be inside an allowed
+root (--allowed-root, or the roots provided by the MCP client).
+")]
+    async fn init_sgconfig(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<InitSgconfigParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let root = if params.write {
+            let roots = self.allowed_roots(&peer).await;
+            resolve_within_roots(Path::new(&params.project_folder), &roots).map_err(invalid_params)?
+        } else {
+            PathBuf::from(&params.project_folder)
+        };
+        if !root.is_absolute() || !root.is_dir() {
+            return Err(invalid_params(format!(
+                "project_folder must be an absolute path to a directory: {}",
+                params.project_folder
+            )));
+        }
+        let existing = ["sgconfig.yaml", "sgconfig.yml"].iter().map(|name| root.join(name)).find(|p| p.exists());
+        if let Some(existing) = existing {
+            return Err(invalid_params(format!("{} already exists; edit it instead.", existing.display())));
+        }
+
+        let walk_root = root.clone();
+        let survey = tokio::task::spawn_blocking(move || survey_project(&walk_root)).await.map_err(tool_error)?;
+        let (config, dirs) = starter_config(&survey);
+        let path = root.join("sgconfig.yaml");
+        let mut created = Vec::new();
+        if params.write {
+            // create_new, so a config written since the check above is never overwritten.
+            let mut file = match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                    return Err(invalid_params(format!("{} already exists; edit it instead.", path.display())));
+                }
+                file => file.map_err(tool_error)?,
+            };
+            std::io::Write::write_all(&mut file, config.as_bytes()).map_err(tool_error)?;
+            for dir in &dirs {
+                if !root.join(dir).exists() {
+                    std::fs::create_dir_all(root.join(dir)).map_err(tool_error)?;
+                    created.push(dir.clone());
+                }
+            }
+        }
+
+        let response = serde_json::json!({
+            "path": path.display().to_string(),
+            "languages": survey.languages,
+            "found": {
+                "rule_dirs": survey.rule_dirs,
+                "test_dirs": survey.test_dirs,
+                "util_dirs": survey.util_dirs,
+            },
+            "config": config,
+            "written": params.write,
+            "created_dirs": created,
+            "next": if params.write {
+                "Add rules to the rule directories and run them with find_code_by_rule or lint them with lint_rule."
+            } else {
+                "Review the config, then call init_sgconfig again with write=true to write it."
+            },
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Find TODO/FIXME/HACK/XXX notes in comments. Only comment nodes are searched, so tags inside string
+literals or identifiers are not reported. Each note has its file, line, tag, owner (from TODO(owner)
+or an @mention) and date (YYYY-MM-DD) when present, and the note text.
+
+Internally calls: ast-grep scan --inline-rules <comment rules per language> --json <project_folder>
+")]
+    async fn find_todos(
+        &self,
+        Parameters(params): Parameters<FindTodosParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let tags: Vec<String> = if params.tags.is_empty() {
+            DEFAULT_TAGS.iter().map(|t| t.to_string()).collect()
+        } else {
+            params.tags.clone()
+        };
+        if let Some(bad) = tags.iter().find(|t| t.is_empty() || !t.chars().all(|c| c.is_alphanumeric() || c == '_')) {
+            return Err(invalid_params(format!("Invalid tag '{}': tags may only contain letters, digits and '_'", bad)));
+        }
+        let languages: Vec<&str> = match params.language.as_str() {
+            "" => TODO_LANGUAGES.to_vec(),
+            language => vec![language],
+        };
+
+        let mut args = vec!["--inline-rules".to_string(), todo_rules(&languages, &tags)];
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+
+        let mut items = extract_todos(&parse_matches(&result.stdout), &tags);
+        let mut by_tag = std::collections::BTreeMap::new();
+        for item in &items {
+            *by_tag.entry(item.tag.clone()).or_insert(0usize) += 1;
+        }
+        let total = items.len();
+        if params.max_results > 0 {
+            items.truncate(params.max_results);
+        }
+        let response = serde_json::json!({
+            "total": total,
+            "by_tag": by_tag,
+            "items": items,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Compute structural metrics per file for one language: function count, class count (structs/traits/
+modules where the language has no classes), maximum nesting depth of control flow (if, loops, try,
+switch/match) and average/longest function length in lines. Files are listed most complex first
+(deepest nesting, then longest function), giving a quick map of where the complex code lives.
+In grammars that nest `else if` inside the preceding `if`, long else-if chains count as nesting.
+
+Internally calls: ast-grep scan --inline-rules <function/class/nesting kind rules> --json <project_folder>
+")]
+    async fn code_metrics(
+        &self,
+        Parameters(params): Parameters<CodeMetricsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(rules) = metrics_rules(&params.language) else {
+            return Err(invalid_params(format!(
+                "code_metrics does not support language '{}'.",
+                params.language
+            )));
+        };
+        let mut args = vec!["--inline-rules".to_string(), rules];
+        args.extend(exclude_glob_args(params.include_vendored));
+        args.push("--json".to_string());
+        args.push(params.project_folder.clone());
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+
+        let mut files = file_metrics(&parse_matches(&result.stdout));
+        let totals = serde_json::json!({
+            "files": files.len(),
+            "functions": files.iter().map(|f| f.functions).sum::<usize>(),
+            "classes": files.iter().map(|f| f.classes).sum::<usize>(),
+            "max_nesting_depth": files.iter().map(|f| f.max_nesting_depth).max().unwrap_or(0),
+        });
+        files.truncate(params.top_files);
+        let response = serde_json::json!({
+            "language": params.language,
+            "totals": totals,
+            "files": files,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Check a YAML rule for common mistakes before using it: relational rules (inside/has/precedes/follows)
+without `stopBy: end`, patterns that do not parse as valid code, unknown keys, missing required keys
+and unused `utils`. Each diagnostic has the 1-based line/column in the YAML and a suggested fix.
+
+Internally calls: ast-grep run --pattern <pattern> --lang <language> --debug-query=pattern (per pattern)
+")]
+    async fn lint_rule(
+        &self,
+        Parameters(params): Parameters<LintRuleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let (mut diagnostics, patterns) = lint_rule(&params.yaml);
+        let language = rule_language(&params.yaml).unwrap_or_default();
+        if !language.is_empty() {
+            for pattern in &patterns {
+                let args = [
+                    "--pattern".to_string(),
+                    pattern.pattern.clone(),
+                    "--lang".to_string(),
+                    language.clone(),
+                    "--debug-query=pattern".to_string(),
+                ];
+                let result = run_ast_grep("run", &args, None, &self.config).await.map_err(tool_error)?;
+                if dump_has_errors(&result.stderr) {
+                    diagnostics.push(pattern_error(pattern));
+                }
+            }
+        }
+
+        let report = LintReport::new(diagnostics);
+        let json_str = serde_json::to_string_pretty(&report).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Register a custom tree-sitter language in the active sgconfig.yaml (customLanguages), so the other
+tools can use it immediately without a restart.
+The parser library is validated first by parsing `sample` with it; the config file is only updated
+when the sample parses without errors. Requires the server to run with --config (or AST_GREP_CONFIG).
+Note: the config file is re-serialized, so comments in it are not preserved.
+
+Internally calls: ast-grep run --pattern <sample> --lang <name> --debug-query=cst
+")]
+    async fn register_custom_language(
+        &self,
+        Parameters(params): Parameters<RegisterCustomLanguageParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let language = CustomLanguage {
+            expando_char: parse_expando_char(params.expando_char.as_deref())?,
+            library_path: params.library_path.clone(),
+            extensions: params.extensions.clone(),
+        };
+        let text = self
+            .register_language(&params.name, &language, &params.sample, params.replace)
+            .await?;
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "
+Fetch a tree-sitter grammar, compile it into a parser library and register it as a custom language
+in the active sgconfig.yaml, so e.g. `name: zig` is all it takes to search Zig code.
+Known grammars: dart, elm, julia, ocaml, r, toml, zig; other languages need `repository`, `extensions`
+and `sample`. Requires git and a C compiler (CC/CXX, default cc/c++), and --config (or AST_GREP_CONFIG).
+Grammars are cloned and built in the server's grammar directory (--grammar-dir) and reused
+unless replace=true.
+")]
+    async fn install_grammar(
+        &self,
+        Parameters(params): Parameters<InstallGrammarParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.name.is_empty() || !params.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(invalid_params("name may only contain letters, digits, '_' and '-'"));
+        }
+        let known = known_grammar(&params.name);
+        let Some(repository) = params.repository.clone().or(known.map(|g| g.repository.to_string())) else {
+            let names: Vec<&str> = KNOWN_GRAMMARS.iter().map(|g| g.name).collect();
+            return Err(invalid_params(format!(
+                "No known grammar for '{}'; pass repository (known: {})",
+                params.name,
+                names.join(", ")
+            )));
+        };
+        let extensions = if params.extensions.is_empty() {
+            known.map(|g| g.extensions.iter().map(|e| e.to_string()).collect()).unwrap_or_default()
+        } else {
+            params.extensions.clone()
+        };
+        let Some(sample) = params.sample.clone().or(known.map(|g| g.sample.to_string())) else {
+            return Err(invalid_params("sample is required for grammars that are not known"));
+        };
+        let subdirectory = params.subdirectory.clone().or(known.and_then(|g| g.subdirectory.map(str::to_string)));
+        let expando_char = parse_expando_char(params.expando_char.as_deref())?;
+        let Some(grammar_dir) = self.config.grammar_dir.clone() else {
+            return Err(invalid_params(
+                "No grammar directory could be determined; start the server with --grammar-dir <path>.",
+            ));
+        };
+
+        let checkout = grammar_dir.join("sources").join(&params.name);
+        let clone = clone_command(&repository, &checkout).map_err(invalid_params)?;
+        if checkout.exists() && params.replace {
+            std::fs::remove_dir_all(&checkout).map_err(tool_error)?;
+        }
+        if !checkout.exists() {
+            std::fs::create_dir_all(grammar_dir.join("sources")).map_err(tool_error)?;
+            run_command(&clone, None, self.config.timeout).await.map_err(tool_error)?;
+        }
+
+        let source_dir = grammar_source_dir(&checkout, subdirectory.as_deref()).map_err(invalid_params)?;
+        if !source_dir.join("src").join("parser.c").is_file() {
+            return Err(tool_error(format!(
+                "{} has no generated src/parser.c; pass subdirectory if the grammar is not at the repository root",
+                source_dir.display()
+            )));
+        }
+        let library = grammar_dir.join(library_file_name(&params.name));
+        let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let cxx = std::env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+        let build = build_command(&source_dir, &library, &cc, &cxx);
+        run_command(&build, None, self.config.timeout).await.map_err(tool_error)?;
+
+        let language = CustomLanguage {
+            library_path: library.display().to_string(),
+            extensions,
+            expando_char,
+        };
+        let registered = self.register_language(&params.name, &language, &sample, params.replace).await?;
+        let text = format!("Built {} from {}.\n{}", library.display(), repository, registered);
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "
+Return the full match objects (text, ranges, meta-variables) for match ids listed by
+find_code or find_code_by_rule with output_format='headers'.
+Ids stay valid for the most recent 10000 matches; unknown or expired ids are reported.
+")]
+    async fn get_match_details(
+        &self,
+        Parameters(params): Parameters<GetMatchDetailsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut found = Vec::new();
+        let mut unknown = Vec::new();
+        for id in &params.ids {
+            match self.matches.get(id) {
+                Some(m) => found.push(m),
+                None => unknown.push(id.as_str()),
+            }
+        }
+        if found.is_empty() {
+            return Err(invalid_params(format!(
+                "Unknown or expired match ids: {}. Run the search again with output_format='headers'.",
+                unknown.join(", ")
+            )));
+        }
+
+        let mut content = vec![Content::text(serde_json::to_string_pretty(&found).unwrap_or_default())];
+        if !unknown.is_empty() {
+            content.push(Content::text(format!("Note: unknown or expired match ids: {}", unknown.join(", "))));
+        }
+        Ok(CallToolResult::success(content))
+    }
+
+    #[tool(description = "
+Show the exact text a rewrite template would replace a match with, after metavariable
+substitution, without touching any file. Use it to check tricky `$$$ARGS` splices before
+applying a rewrite to many matches. The replacement is ast-grep's own, from rewriting the match.
+
+Give the match by match_id (from find_code or find_code_by_rule with output_format='headers'),
+or by path, line (and column); either way with the pattern that binds the metavariables. The
+result has the original and replacement text, the match's lines after the rewrite, what the match
+captured for each metavariable of the template, and `unbound` metavariables it did not capture
+(replaced with nothing).
+
+For renames, transform derives new metavariables from captured ones without writing a YAML
+`transform` section: case conversion (to_case camelCase, snakeCase, kebabCase, pascalCase,
+lowerCase, upperCase, capitalize, optionally separated_by), regex replace (replace, by) and
+substring (start_char, end_char), e.g. transform={\"CAMEL\": {\"source\": \"$NAME\", \"to_case\": \"camelCase\"}}
+with rewrite='$CAMEL($$$ARGS)'.
+
+The file must be inside an allowed root: the directories given with --allowed-root, or else the
+roots provided by the MCP client.
+
+Internally calls: ast-grep run --pattern <pattern> [--lang <language>] --rewrite <rewrite> --json <file>
+(with transform: ast-grep scan --inline-rules <pattern rule with transform and fix> --json <file>)
+")]
+    async fn preview_match_rewrite(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<PreviewMatchRewriteParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let stored = match params.match_id.as_str() {
+            "" => None,
+            id => Some(self.matches.get(id).ok_or_else(|| {
+                invalid_params(format!(
+                    "Unknown or expired match id: {}. Run the search again with output_format='headers'.",
+                    id
+                ))
+            })?),
+        };
+        let (file, line, column) = match &stored {
+            Some(m) => {
+                let start = |field: &str| m.pointer(&format!("/range/start/{}", field)).and_then(Value::as_u64);
+                let file = m.get("file").and_then(Value::as_str).unwrap_or_default().to_string();
+                (file, start("line").map_or(0, |line| line + 1), start("column").map(|column| column + 1))
+            }
+            None if params.path.is_empty() => {
+                return Err(invalid_params("Give match_id, or path and line, with the pattern."));
+            }
+            None => (params.path.clone(), params.line, params.column),
+        };
+        if line == 0 || params.pattern.is_empty() {
+            return Err(invalid_params(
+                "The pattern of the match is required to bind its metavariables, and with path the line (1-indexed).",
+            ));
+        }
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(Path::new(&file), &roots).map_err(invalid_params)?;
+        let (command, mut args) = if params.transform.is_empty() {
+            let mut args = vec!["--pattern".to_string(), params.pattern.clone()];
+            if !params.language.is_empty() {
+                args.extend(["--lang".to_string(), params.language.clone()]);
+            }
+            args.extend(["--rewrite".to_string(), params.rewrite.clone()]);
+            ("run", args)
+        } else {
+            let language = match params.language.as_str() {
+                "" => language_for_path(&path)
+                    .ok_or_else(|| invalid_params(format!("Cannot infer the language of {}; pass language.", file)))?,
+                language => language,
+            };
+            let rule = transform_rule(&params.pattern, language, &params.transform, &params.rewrite)
+                .map_err(invalid_params)?;
+            ("scan", vec!["--inline-rules".to_string(), rule])
+        };
+        args.extend(["--json".to_string(), path.display().to_string()]);
+        let result = run_ast_grep(command, &args, None, &self.config).await.map_err(tool_error)?;
+        let starts_at = |m: &Value, field: &str, wanted: u64| {
+            m.pointer(&format!("/range/start/{}", field)).and_then(Value::as_u64).map(|n| n + 1) == Some(wanted)
+        };
+        let m = parse_matches(&result.stdout)
+            .into_iter()
+            .find(|m| starts_at(m, "line", line) && column.is_none_or(|column| starts_at(m, "column", column)))
+            .ok_or_else(|| {
+                invalid_params(format!(
+                    "No match of the pattern starts at {}:{}{}.",
+                    file,
+                    line,
+                    column.map(|column| format!(":{}", column)).unwrap_or_default()
+                ))
+            })?;
+        let preview = match_rewrite(&m, &params.rewrite)
+            .ok_or_else(|| tool_error("ast-grep did not report the replacement of the match."))?;
+        let response = serde_json::json!({
+            "file": m.get("file"),
+            "range": m.get("range"),
+            "preview": preview,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Read a range of lines from a file, e.g. to see more context around a match.
+Lines are 1-indexed and inclusive; the output starts with a path:start-end header like find_code's text output.
+
+The file must be inside an allowed root: the directories given with --allowed-root, or else the roots
+provided by the MCP client.
+")]
+    async fn read_file_range(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<ReadFileRangeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.start_line == 0 || params.end_line < params.start_line {
+            return Err(invalid_params(format!(
+                "Invalid line range {}-{}: lines are 1-indexed and start_line must not exceed end_line.",
+                params.start_line, params.end_line
+            )));
+        }
+
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(Path::new(&params.path), &roots).map_err(invalid_params)?;
+        let (source, _) = read_source(&path).map_err(tool_error)?;
+
+        let lines: Vec<&str> = source.lines().collect();
+        let start = params.start_line as usize;
+        if start > lines.len() {
+            return Err(invalid_params(format!(
+                "start_line {} is past the end of the file ({} lines).",
+                start,
+                lines.len()
+            )));
+        }
+        let end = (params.end_line as usize).min(lines.len());
+
+        let text = format!("{}:{}-{}\n{}", params.path, start, end, lines[start - 1..end].join("\n"));
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "
+Parse a file and report its most frequent syntax node kinds, with counts and example ranges
+(line:column-line:column, 1-based). Use it to discover the right `kind:` for a rule in an unfamiliar
+language's grammar. Only named nodes are counted; files over 100 KB are analyzed up to that size.
+
+The file must be inside an allowed root: the directories given with --allowed-root, or else the roots
+provided by the MCP client.
+
+Internally calls: ast-grep run --pattern <file contents> --lang <language> --debug-query=ast
+")]
+    async fn node_kind_stats(
+        &self,
+        peer: Peer<RoleServer>,
+        Parameters(params): Parameters<NodeKindStatsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let roots = self.allowed_roots(&peer).await;
+        let path = resolve_within_roots(Path::new(&params.path), &roots).map_err(invalid_params)?;
+        let language = match params.language.as_str() {
+            "" => language_for_path(&path).map(str::to_string).ok_or_else(|| {
+                invalid_params(format!("Cannot infer the language of {}; pass language.", params.path))
+            })?,
+            language => language.to_string(),
+        };
+        let (source, _) = read_source(&path).map_err(tool_error)?;
+
+        let code = dump_prefix(&source);
+        let mut notes = Vec::new();
+        if code.len() < source.len() {
+            notes.push(format!(
+                "Only the first {} of {} lines were analyzed.",
+                code.lines().count(),
+                source.lines().count()
+            ));
+        }
+        let args = [
+            "--pattern".to_string(),
+            code.to_string(),
+            "--lang".to_string(),
+            language.clone(),
+            "--debug-query=ast".to_string(),
+        ];
+        let result = run_ast_grep("run", &args, None, &self.config).await.map_err(tool_error)?;
+        let Some(root) = parse_dump(&result.stderr) else {
+            return Err(tool_error(format!("Could not read the syntax tree:\n{}", result.stderr.trim())));
+        };
+        if dump_has_errors(&result.stderr) {
+            notes.push(format!("The file has syntax errors when parsed as {}.", language));
+        }
+
+        let mut kinds = kind_frequencies(&root, params.examples);
+        let distinct_kinds = kinds.len();
+        let total_nodes: usize = kinds.iter().map(|k| k.count).sum();
+        kinds.truncate(params.top);
+        let response = serde_json::json!({
+            "file": params.path,
+            "language": language,
+            "total_nodes": total_nodes,
+            "distinct_kinds": distinct_kinds,
+            "kinds": kinds,
+            "notes": notes,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Report runtime statistics for this server process: uptime, calls and errors per tool,
+subprocess count and average duration, currently running subprocesses, and cache hit rate.
+Useful to introspect server health from within a session.
+")]
+    async fn get_server_stats(&self) -> Result<CallToolResult, McpError> {
+        let json_str = serde_json::to_string_pretty(&stats::global().snapshot()).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+
+    #[tool(description = "
+Check the server's setup: whether ast-grep is installed and which version, the active sgconfig file,
+read-only mode, the grammar, checkpoint and backup directories, and which tools are not offered and why.
+Call it when a tool you expect is missing or every search fails.
+
+Internally calls: ast-grep --version
+")]
+    async fn doctor(&self) -> Result<CallToolResult, McpError> {
+        let version = ast_grep_version().await;
+        let mut tools: Vec<String> = self.tool_router.list_all().into_iter().map(|tool| tool.name.to_string()).collect();
+        if self.config.read_only {
+            tools.extend(WRITE_TOOLS.iter().map(|tool| tool.to_string()));
+            tools.extend(self.plugin_write_tools.iter().cloned());
+        }
+        tools.sort();
+        let unavailable: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| {
+                let reason = match self.config.read_only && self.writes(tool) {
+                    true => READ_ONLY_REASON,
+                    false => unavailable_reason(tool, self.config.read_only, version.is_some())?,
+                };
+                Some(serde_json::json!({ "tool": tool, "reason": reason }))
+            })
+            .collect();
+        let mut notes = Vec::new();
+        if version.is_none() {
+            notes.push(
+                "Install the ast-grep CLI (e.g. `brew install ast-grep` or `cargo install ast-grep --locked`) and make \
+                 sure it is on the server's PATH; the missing tools are offered again within a minute."
+                    .to_string(),
+            );
+        }
+        let config = self.config.config_path.as_ref().map(|path| {
+            serde_json::json!({ "path": path.display().to_string(), "exists": path.exists() })
+        });
+        if config.is_none() {
+            notes.push("No sgconfig.yaml is active, so custom languages cannot be registered.".to_string());
+        }
+        #[cfg(feature = "plugins")]
+        let plugins: Vec<&str> = self.config.plugins.iter().map(|plugin| plugin.name()).collect();
+        #[cfg(not(feature = "plugins"))]
+        let plugins: Vec<&str> = Vec::new();
+
+        let response = serde_json::json!({
+            "ast_grep": { "installed": version.is_some(), "version": version },
+            "config": config,
+            "read_only": self.config.read_only,
+            "grammar_dir": self.config.grammar_dir.as_ref().map(|dir| dir.display().to_string()),
+            "checkpoint_dir": self.config.checkpoint_dir.as_ref().map(|dir| dir.display().to_string()),
+            "backup_dir": self.config.backup_dir.as_ref().map(|dir| dir.display().to_string()),
+            "unavailable_tools": unavailable,
+            "plugins": plugins,
+            "filter_profiles": self.config.filter_profiles,
+            "notes": notes,
+        });
+        let json_str = serde_json::to_string_pretty(&response).unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(json_str)]))
+    }
+}
+
+impl AstGrepServer {
+    /// What ast-grep leaves out of `folders` with `options`, for the scan report.
+    async fn not_searched(&self, folders: &[String], options: &FindOptions) -> NotSearched {
+        let extensions = self
+            .config
+            .config_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|config| custom_language_extensions(&config))
+            .unwrap_or_default();
+        let mut summary = NotSearched::default();
+        for (index, folder) in folders.iter().enumerate() {
+            let root = PathBuf::from(folder);
+            let (include_vendored, no_ignore) = (options.include_vendored, options.no_ignore);
+            let extensions = extensions.clone();
+            let walk = move || not_searched(&root, include_vendored, no_ignore, &extensions);
+            let Ok(folder_summary) = tokio::task::spawn_blocking(walk).await else {
+                continue;
+            };
+            if index == 0 {
+                summary = folder_summary;
+            } else {
+                summary.merge(folder_summary, Path::new(folder));
+            }
+        }
+        summary
+    }
+
+    /// The languages of `folder` (see `folder_languages`), walked once per session.
+    async fn folder_languages(
+        &self,
+        folder: PathBuf,
+        include_vendored: bool,
+    ) -> Result<LanguageCounts, McpError> {
+        let key = (folder, include_vendored);
+        if let Some(counts) = self.languages.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+            return Ok(counts.clone());
+        }
+        let walked = key.0.clone();
+        let counts = tokio::task::spawn_blocking(move || folder_languages(&walked, include_vendored))
+            .await
+            .map_err(tool_error)?;
+        self.languages.lock().unwrap_or_else(|e| e.into_inner()).insert(key, counts.clone());
+        Ok(counts)
+    }
+
+    /// The filter profile named `name`, or `None` for an empty name.
+    fn filter_profile(&self, name: &str) -> Result<Option<&FilterProfile>, McpError> {
+        if name.is_empty() {
+            return Ok(None);
+        }
+        match self.config.filter_profiles.get(name) {
+            Some(profile) => Ok(Some(profile)),
+            None if self.config.filter_profiles.is_empty() => Err(invalid_params(format!(
+                "Unknown filter_profile: {}. The server has no filter profiles; start it with --filter-profiles.",
+                name
+            ))),
+            None => Err(invalid_params(format!(
+                "Unknown filter_profile: {}. Available profiles: {}.",
+                name,
+                self.config.filter_profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))),
+        }
+    }
+
+    /// Checks that `pattern` parses cleanly and matches the example code (or something in the
+    /// project folder) of a `refine_pattern` call.
+    async fn try_pattern(&self, pattern: &str, params: &RefinePatternParams) -> Result<RefineAttempt, McpError> {
+        let mut args = vec![
+            "--pattern".to_string(),
+            pattern.to_string(),
+            "--lang".to_string(),
+            params.language.clone(),
+        ];
+        let mut dump_args = args.clone();
+        dump_args.push("--debug-query=pattern".to_string());
+        let dump = run_ast_grep("run", &dump_args, None, &self.config)
+            .await
+            .map_err(tool_error)?
+            .stderr;
+        let mut attempt = RefineAttempt { pattern: pattern.to_string(), problem: None, matches: 0, dump };
+        if dump_has_errors(&attempt.dump) {
+            attempt.problem = Some(format!(
+                "does not parse cleanly as {}; ast-grep's dump has ERROR or MISSING nodes",
+                params.language
+            ));
+            return Ok(attempt);
+        }
+
+        args.push("--json".to_string());
+        let result = if params.code.is_empty() {
+            args.push(params.project_folder.clone());
+            run_ast_grep("run", &args, None, &self.config).await
+        } else {
+            args.push("--stdin".to_string());
+            run_ast_grep("run", &args, Some(&params.code), &self.config).await
+        };
+        match result {
+            Ok(result) => {
+                attempt.matches = parse_matches(&result.stdout).len();
+                if attempt.matches == 0 {
+                    attempt.problem = Some(if params.code.is_empty() {
+                        format!("matches nothing in {}", params.project_folder)
+                    } else {
+                        "does not match the example code".to_string()
+                    });
+                }
+            }
+            Err(e) => attempt.problem = Some(format!("ast-grep rejected it: {}", e.to_string().trim())),
+        }
+        Ok(attempt)
+    }
+
+    /// This session's private directory for temp files; see [`SessionTempDir`].
+    pub fn temp_dir(&self) -> std::io::Result<Arc<SessionTempDir>> {
+        let mut temp_dir = self.temp_dir.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(dir) = temp_dir.as_ref() {
+            return Ok(dir.clone());
+        }
+        let dir = Arc::new(SessionTempDir::new()?);
+        *temp_dir = Some(dir.clone());
+        Ok(dir)
+    }
+
+    /// Frees what the session holds once its client is gone: watches, running background scans
+    /// (whose checkpoints stay, so they can be resumed), remembered matches and temp files.
+    ///
+    /// Tool calls still in flight are cancelled by the MCP service itself when the connection
+    /// ends, which kills their ast-grep processes and releases their concurrency permits.
+    pub fn end_session(&self) {
+        if !self.sessions.close(&self.session_id) {
+            return;
+        }
+        tracing::info!(session_id = %self.session_id, "session ended, releasing its resources");
+        logging::unregister_client(&self.session_id);
+        self.watches.clear();
+        self.rule_sessions.clear();
+        self.jobs.abort_all();
+        self.matches.clear();
+        self.temp_dir.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    /// Starts watch `watch_id` of `yaml` on `project_folder` from a full scan, returning the
+    /// number of matches and, given the `saved` matches of an earlier watch, how the scan differs
+    /// from them.
+    async fn start_watch(
+        &self,
+        peer: Peer<RoleServer>,
+        watch_id: &str,
+        yaml: &str,
+        project_folder: &str,
+        include_vendored: bool,
+        saved: Option<Vec<Value>>,
+    ) -> Result<(usize, MatchChanges), McpError> {
+        let folder = Path::new(project_folder);
+        if !folder.is_absolute() || !folder.is_dir() {
+            return Err(invalid_params(format!(
+                "project_folder must be an absolute path to a directory: {}",
+                project_folder
+            )));
+        }
+        let quota = self.sessions.start_watch(&self.session_id).map_err(invalid_params)?;
+
+        let mut query_args = vec!["--inline-rules".to_string(), yaml.to_string()];
+        query_args.extend(exclude_glob_args(include_vendored));
+        query_args.push("--json".to_string());
+        let mut args = query_args.clone();
+        args.push(project_folder.to_string());
+        let result = run_ast_grep("scan", &args, None, &self.config).await.map_err(tool_error)?;
+        let mut baseline = parse_matches(&result.stdout);
+        make_paths_absolute(&mut baseline, project_folder);
+        add_fingerprints(&mut baseline);
+
+        let (events_tx, events) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = events_tx.send(event);
+        })
+        .map_err(tool_error)?;
+        notify::Watcher::watch(&mut watcher, folder, notify::RecursiveMode::Recursive).map_err(tool_error)?;
+
+        let baseline_count = baseline.len();
+        let (matches, changes) = match saved {
+            Some(saved) => {
+                let mut matches = WatchedMatches::new(saved);
+                let changes = matches.update(&[project_folder.to_string()], baseline);
+                (matches, changes)
+            }
+            None => (WatchedMatches::new(baseline), MatchChanges::default()),
+        };
+        let matches = Arc::new(Mutex::new(matches));
+        let info = WatchInfo {
+            watch_id: watch_id.to_string(),
+            rule_id: first_rule_document(yaml)
+                .ok()
+                .and_then(|rule| rule.get("id").and_then(|id| id.as_str()).map(str::to_string))
+                .unwrap_or_default(),
+            yaml: yaml.to_string(),
+            project_folder: project_folder.to_string(),
+            include_vendored,
+            matches: baseline_count,
+            added: 0,
+            removed: 0,
+            notifications: 0,
+        };
+        let watch = RuleWatch {
+            id: watch_id.to_string(),
+            folder: project_folder.to_string(),
+            languages: rule_languages(yaml),
+            query_args,
+            changes: ChangeFilter::new(folder, include_vendored),
+            matches: matches.clone(),
+            rescan_interval: self.config.rescan_interval,
+            config: self.config.clone(),
+            watches: self.watches.clone(),
+            peer,
+        };
+        let task = tokio::spawn(
+            async move {
+                // The watcher stops when dropped, so it lives as long as the task
+                let _watcher = watcher;
+                watch.run(events).await;
+            }
+            .in_current_span(),
+        );
+        self.watches.insert(info, matches, task.abort_handle(), Some(quota));
+        Ok((baseline_count, changes))
+    }
+
+    /// Runs the scan of job `job_id` in the background, storing its outcome in the job.
+    fn start_scan_job(
+        &self,
+        context: &RequestContext<RoleServer>,
+        job_id: &str,
+        params: SubmitScanParams,
+    ) -> Result<(), McpError> {
+        let (command, args) = scan_query(&params)?;
+        let server = self.clone();
+        let id = job_id.to_string();
+        // Progress notifications would outlive this call; partial results go to the job instead
+        let progress = Progress::for_job(context, self.jobs.clone(), id.clone());
+        let handle = tokio::spawn(
+            async move {
+                let result = server
+                    .run_find(command, args, &params.project_folder, &params.options, Vec::new(), progress)
+                    .await
+                    .map_err(|e| e.message.to_string());
+                server.jobs.finish(&id, result);
+            }
+            .in_current_span(),
+        );
+        self.jobs.set_abort_handle(job_id, handle.abort_handle());
+        Ok(())
+    }
+
+    /// Runs a project-wide search and applies the shared find options to its results.
+    ///
+    /// `args` holds the command-specific query arguments; filtering flags, `--json` and the
+    /// project folder are appended here. `notes` are added to the response's own notes, e.g. on
+    /// how the query was built.
+    async fn run_find(
+        &self,
+        command: &str,
+        args: Vec<String>,
+        project_folder: &str,
+        options: &FindOptions,
+        notes: Vec<String>,
+        progress: Progress,
     ) -> Result<CallToolResult, McpError> {
-        if params.output_format != "text" && params.output_format != "json" {
-             return Err(McpError {
-                 code: ErrorCode(-32602), // Invalid params
-                 message: format!("Invalid output_format: {}. Must be 'text' or 'json'.", params.output_format).into(),
-                 data: None,
-             });
+        validate_output_format(&options.output_format)?;
+        for (name, value, values) in [
+            ("fallback", &options.fallback, FALLBACKS),
+            ("text_source", &options.text_source, TEXT_SOURCES),
+            ("line_endings", &options.line_endings, LINE_ENDINGS),
+        ] {
+            if !value.is_empty() && !values.contains(&value.as_str()) {
+                return Err(invalid_params(format!("Invalid {}: {}. Must be {}.", name, value, one_of(values))));
+            }
+        }
+        let Some(dialect) = Dialect::parse(&options.dialect) else {
+            return Err(invalid_params(format!(
+                "Invalid dialect: {}. Must be {}.",
+                options.dialect,
+                one_of(DIALECTS)
+            )));
+        };
+        let aggregate = metavariable_name(&options.aggregate);
+        let valid_aggregate = !aggregate.is_empty() && aggregate.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !options.aggregate.is_empty() && !valid_aggregate {
+            return Err(invalid_params(format!(
+                "Invalid aggregate: {}. Must be a metavariable such as '$NAME'.",
+                options.aggregate
+            )));
+        }
+
+        let filter_profile = self.filter_profile(&options.filter_profile)?;
+
+        let query_args = args.clone();
+        let mut filter_args = exclude_glob_args(options.include_vendored);
+        filter_args.extend(no_ignore_args(options.no_ignore));
+        filter_args.extend(filter_profile.map(FilterProfile::glob_args).unwrap_or_default());
+        let scan_args = |query_args: &[String]| {
+            let mut args = query_args.to_vec();
+            args.extend(filter_args.iter().cloned());
+            args.extend(inspect_args(options.profile));
+            args.push("--json".to_string());
+            args
+        };
+
+        let mut folders = vec![project_folder.to_string()];
+        folders.extend(options.additional_folders.iter().cloned());
+        let mut report = ScanReport::default();
+        let started = Instant::now();
+        // TypeScript queries take a scan per grammar; see `Dialect`
+        let queries = dialect_queries(&args, dialect).map_err(invalid_params)?;
+        let exact_args: Vec<Vec<String>> = queries.iter().map(|query| scan_args(query)).collect();
+        let mut matches = self.scan_dialects(command, &exact_args, &folders, options, &progress, &mut report).await?;
+        if matches.is_empty() && options.fallback != "none" {
+            // Relational rules without `stopBy` only look one level away, the most common reason
+            // a rule finds nothing, so that is retried unless asked not to
+            let retries = |query_args: &[String]| {
+                let mut retries: Vec<Relaxation> = stop_by_retry(query_args).into_iter().collect();
+                if options.fallback == "relaxed" {
+                    retries.extend(strictness_relaxations(query_args));
+                }
+                retries
+            };
+            // Each query relaxes the same way, as they only differ in their language
+            let mut retries: Vec<std::vec::IntoIter<Relaxation>> =
+                queries.iter().map(|query| retries(query).into_iter()).collect();
+            while let Some(relaxations) = retries.iter_mut().map(Iterator::next).collect::<Option<Vec<_>>>() {
+                let mut retry = ScanReport::default();
+                let relaxed_args: Vec<Vec<String>> = relaxations.iter().map(|r| scan_args(&r.args)).collect();
+                let relaxed =
+                    self.scan_dialects(command, &relaxed_args, &folders, options, &progress, &mut retry).await?;
+                if !relaxed.is_empty() {
+                    retry.relaxed = relaxations.into_iter().next().map(|relaxation| relaxation.description);
+                    matches = relaxed;
+                    report = retry;
+                    break;
+                }
+            }
+        }
+        if options.report_not_searched || matches.is_empty() {
+            report.not_searched = self.not_searched(&folders, options).await;
+        }
+        report.notes.extend(notes);
+        let scanned = Instant::now();
+
+        let project_folder = Path::new(project_folder);
+        for folder in &folders {
+            report.notes.extend(ignore_note(Path::new(folder), options.no_ignore));
+        }
+        if let Some(filter_profile) = filter_profile {
+            filter_profile.retain_matches(&mut matches);
+        }
+        if !options.include_minified {
+            let (kept, skipped) = drop_generated_files(matches, project_folder);
+            matches = kept;
+            report.skipped = skipped;
+        }
+        normalize_match_ranges(&mut matches, project_folder);
+        sort_by_position(&mut matches);
+        let undecodable = fix_match_encodings(&mut matches, project_folder);
+        if !undecodable.is_empty() {
+            report.notes.push(format!(
+                "Could not decode {}; match text may contain replacement characters.",
+                undecodable.join(", ")
+            ));
+        }
+        if options.text_source == "file" {
+            let changed = reread_match_text(&mut matches, project_folder);
+            if !changed.is_empty() {
+                report.notes.push(format!(
+                    "{} changed since the scan; their matches keep ast-grep's text (textSource: cli).",
+                    changed.join(", ")
+                ));
+            }
+        }
+        if options.line_endings == "lf" {
+            normalize_line_endings(&mut matches, project_folder);
+        }
+
+        if options.diagnose && matches.is_empty() {
+            report.diagnosis = self.diagnose_zero_matches(command, &query_args, &filter_args, &folders).await;
+        }
+        if matches.is_empty() {
+            let listed = folders.clone();
+            let include_vendored = options.include_vendored;
+            let files = tokio::task::spawn_blocking(move || {
+                listed.iter().flat_map(|folder| list_files(Path::new(folder), include_vendored)).collect::<Vec<_>>()
+            })
+            .await
+            .unwrap_or_default();
+            let scope = SearchScope {
+                files: &files,
+                filter_profile: filter_profile.map(|profile| (options.filter_profile.as_str(), profile)),
+                include_vendored: options.include_vendored,
+                no_ignore: options.no_ignore,
+                retried_strictness: options.fallback == "relaxed",
+                retried_stop_by: options.fallback != "none",
+                not_searched: &report.not_searched,
+            };
+            report.no_matches = Some(no_match_report(command, &query_args, &scope, &report.diagnosis));
+        }
+
+        if !aggregate.is_empty() {
+            let aggregate = aggregate_metavariable(&matches, aggregate);
+            if let Some(profile) = report.profile.as_mut() {
+                profile.scan_ms = millis(scanned - started);
+                profile.postprocess_ms = millis(scanned.elapsed());
+            }
+            return Ok(render_aggregate(aggregate, options.max_results, &options.output_format, &report));
+        }
+
+        if options.max_matches_per_file > 0 {
+            let (kept, capped) = cap_matches_per_file(matches, options.max_matches_per_file);
+            matches = kept;
+            report.capped = capped;
+        }
+        add_fingerprints(&mut matches);
+        if options.collapse_duplicates {
+            let total = matches.len();
+            matches = collapse_duplicates(matches);
+            if matches.len() < total {
+                report.notes.push(format!(
+                    "Collapsed {} matches into {} entries with distinct text.",
+                    total,
+                    matches.len()
+                ));
+            }
+        }
+        if options.output_format == "headers" {
+            self.matches.remember_within(&mut matches, self.sessions.cache_budget(&self.session_id));
+            self.sessions.set_cache_bytes(&self.session_id, self.matches.bytes());
+        }
+        if let Some(profile) = report.profile.as_mut() {
+            profile.scan_ms = millis(scanned - started);
+            profile.postprocess_ms = millis(scanned.elapsed());
+        }
+
+        Ok(render_matches(matches, options.max_results, &options.output_format, &report, project_folder))
+    }
+
+    /// Runs `scan_folders` with each of the argument lists `dialect_queries` made for a query and
+    /// merges the matches.
+    async fn scan_dialects(
+        &self,
+        command: &str,
+        queries: &[Vec<String>],
+        folders: &[String],
+        options: &FindOptions,
+        progress: &Progress,
+        report: &mut ScanReport,
+    ) -> Result<Vec<Value>, McpError> {
+        let mut matches = Vec::new();
+        for args in queries {
+            matches.extend(self.scan_folders(command, args, folders, options, progress, report).await?);
+        }
+        Ok(matches)
+    }
+
+    /// Runs the scans for all folders, at most `max_concurrency` at a time, and merges the matches.
+    ///
+    /// Folders with more files than the shard threshold are split into shards, each scanned by
+    /// its own ast-grep invocation with a progress notification as it completes. A failed shard
+    /// does not fail the call: its matches are missing and the report says how to rescan it.
+    /// With several folders, relative match paths are made absolute so that matches from
+    /// different folders cannot be confused.
+    async fn scan_folders(
+        &self,
+        command: &str,
+        args: &[String],
+        folders: &[String],
+        options: &FindOptions,
+        progress: &Progress,
+        report: &mut ScanReport,
+    ) -> Result<Vec<Value>, McpError> {
+        let _slot = self.sessions.start_scan(&self.session_id).map_err(tool_error)?;
+        let planned = plan_scan_jobs(folders, self.config.shard_threshold, options, report).await?;
+        let total = planned.len();
+        let plan = plan_hash(planned.iter().map(|job| job.paths.as_slice()));
+        // A resumed background scan skips the parts its checkpoint already has
+        let (completed, previous) = progress.completed_parts(args, &plan).map_err(tool_error)?;
+        let jobs: Vec<ScanJob> = planned
+            .into_iter()
+            .filter(|job| !completed.contains(&(job.folder, job.shard)))
+            .collect();
+        if jobs.len() < total {
+            report.notes.push(format!(
+                "Resumed from a checkpoint: {} of {} parts were already scanned.",
+                total - jobs.len(),
+                total
+            ));
+        }
+
+        // Only max_results matches are shown, so scans stop once they have found that many, unless
+        // all matches are needed: to aggregate, collapse or cap them per file, for profiling, or for
+        // the checkpoint of a background scan. Matches run_find drops afterwards (outside the filter
+        // profile, or in generated files) do not count, so they cannot stop the scan early.
+        let kept = Arc::new(KeptMatches::new(
+            self.filter_profile(&options.filter_profile)?.cloned(),
+            !options.include_minified,
+        ));
+        let limit = (options.max_results > 0
+            && options.aggregate.is_empty()
+            && !options.collapse_duplicates
+            && options.max_matches_per_file == 0
+            && !options.profile
+            && progress.job.is_none())
+        .then_some(options.max_results as usize);
+
+        let permits = Arc::new(Semaphore::new(self.config.max_concurrency));
+        let mut scans = JoinSet::new();
+        for (index, job) in jobs.iter().enumerate() {
+            let permits = permits.clone();
+            let config = self.config.clone();
+            let command = command.to_string();
+            let mut args = args.to_vec();
+            args.extend(job.paths.iter().cloned());
+            let kept = kept.clone();
+            let folder = PathBuf::from(&folders[job.folder]);
+            scans.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let counts = |m: &Value| kept.keeps(m, &folder);
+                let result = match limit {
+                    Some(limit) => run_ast_grep_limited(&command, &args, &config, limit, &counts).await,
+                    None => run_ast_grep(&command, &args, None, &config)
+                        .await
+                        .map(|result| LimitedResult { result, stopped: false }),
+                };
+                (index, result)
+            });
+        }
+
+        // Dropping the set on error or cancellation aborts the remaining scans
+        let mut outputs = vec![Vec::new(); folders.len()];
+        let mut failed_shards = Vec::new();
+        let mut done = total - jobs.len();
+        let mut found = previous.len();
+        let mut shown = 0;
+        let mut profile = options.profile.then(|| ScanProfile {
+            invocations: jobs.len(),
+            ..Default::default()
+        });
+        while let Some(joined) = scans.join_next().await {
+            let (index, result) = joined.map_err(tool_error)?;
+            let job = &jobs[index];
+            done += 1;
+            match (result, job.shard) {
+                (Ok(LimitedResult { result, stopped }), _) => {
+                    report.stopped_early |= stopped;
+                    if let Some(profile) = profile.as_mut() {
+                        add_inspect_counters(&result.stderr, &mut profile.counters);
+                    }
+                    let mut matches = parse_matches(&result.stdout);
+                    if folders.len() > 1 {
+                        make_paths_absolute(&mut matches, &folders[job.folder]);
+                    }
+                    found += matches.len();
+                    shown += matches.iter().filter(|m| kept.keeps(m, Path::new(&folders[job.folder]))).count();
+                    progress.publish(args, &plan, (job.folder, job.shard), (done, total), &matches);
+                    outputs[job.folder].extend(matches);
+                }
+                (Err(e), Some(shard)) => failed_shards.push((shard, job.paths.join(", "), e.to_string())),
+                (Err(e), None) => return Err(tool_error(e)),
+            }
+            if total > 1 {
+                let message = format!("Scanned {} of {} parts, {} matches so far", done, total, found);
+                progress.report(done, total, message).await;
+            }
+            if limit.is_some_and(|limit| shown >= limit) {
+                // Dropping the set aborts the scans of the other parts
+                report.stopped_early |= done < total;
+                break;
+            }
+        }
+
+        report.profile = profile;
+        if !failed_shards.is_empty() {
+            failed_shards.sort();
+            for (shard, paths, error) in &failed_shards {
+                report.notes.push(format!("Shard {} ({}) failed: {}", shard, paths, error));
+            }
+            let numbers: Vec<String> = failed_shards.iter().map(|(shard, _, _)| shard.to_string()).collect();
+            report.notes.push(format!(
+                "Results are partial; pass shards=[{}] to rescan only the failed shards.",
+                numbers.join(", ")
+            ));
+        }
+
+        Ok(previous.into_iter().chain(outputs.into_iter().flatten()).collect())
+    }
+
+    /// Asks the user to approve the rewrite of `file`, as a whole or fix by fix, and leaves the
+    /// rejected fixes out of it; rejections are added to `rejected`, as the file's path or
+    /// `path:line`. A cancelled approval leaves `file` as it was.
+    async fn approve_planned_file(
+        &self,
+        peer: &Peer<RoleServer>,
+        scope: ApprovalScope,
+        file: &mut PlannedFile,
+        rejected: &mut Vec<String>,
+    ) -> Result<Approval, ServiceError> {
+        let ask = |message: String| async move {
+            let result = peer.create_elicitation(approval_request(message)).await?;
+            Ok::<Approval, ServiceError>(approval_of(&result))
+        };
+        if scope == ApprovalScope::File {
+            let diff = unified_diff(&file.path, &file.source, &file.edits, CONTEXT_LINES);
+            let approval = ask(file_approval_message(&file.path, file.fixes.len(), &diff)).await?;
+            if approval == Approval::Rejected {
+                rejected.push(file.path.clone());
+                file.edits.clear();
+                file.fixes.clear();
+            }
+            return Ok(approval);
+        }
+
+        let mut approved = Vec::with_capacity(file.fixes.len());
+        for (i, fix) in file.fixes.iter().enumerate() {
+            match ask(match_approval_message(&file.path, i + 1, file.fixes.len(), fix)).await? {
+                Approval::Approved => approved.push(true),
+                Approval::Rejected => {
+                    rejected.push(format!("{}:{}", file.path, fix.line + 1));
+                    approved.push(false);
+                }
+                Approval::Cancelled => return Ok(Approval::Cancelled),
+            }
+        }
+        let (edits, fixes) = approved_edits(&file.edits, std::mem::take(&mut file.fixes), &approved);
+        file.edits = edits;
+        file.fixes = fixes;
+        Ok(Approval::Approved)
+    }
+
+    /// Matches of the inline rule `yaml` in `code`, with ranges in `code`'s own terms. A rule that
+    /// finds nothing is retried with `stopBy: end` on its relational rules that lack it; the
+    /// description of that retry is returned when it ran.
+    async fn test_rule_on_code(&self, yaml: String, code: &str) -> Result<(Vec<Value>, Option<String>), McpError> {
+        let scan = |query_args: Vec<String>| async move {
+            let args = [query_args, vec!["--json".to_string(), "--stdin".to_string()]].concat();
+            let result = run_ast_grep("scan", &args, Some(code), &self.config)
+                .await
+                .map_err(tool_error)?;
+            Ok::<Vec<Value>, McpError>(serde_json::from_str(&result.stdout).unwrap_or_else(|_| vec![]))
+        };
+        let query_args = vec!["--inline-rules".to_string(), yaml];
+        let mut matches = scan(query_args.clone()).await?;
+
+        let retry = if matches.is_empty() { stop_by_retry(&query_args) } else { None };
+        if let Some(retry) = &retry {
+            matches = scan(retry.args.clone()).await?;
+        }
+        normalize_source_ranges(&mut matches, code);
+        Ok((matches, retry.map(|retry| retry.description)))
+    }
+
+    /// Runs relaxed variants of a query that found nothing, in order, until one finds matches.
+    async fn diagnose_zero_matches(
+        &self,
+        command: &str,
+        query_args: &[String],
+        filter_args: &[String],
+        folders: &[String],
+    ) -> Vec<RelaxationAttempt> {
+        let mut attempts = Vec::new();
+        for relaxation in relaxations(query_args) {
+            let mut matches = 0;
+            let mut error = None;
+            for folder in folders {
+                let mut args = relaxation.args.clone();
+                args.extend(filter_args.iter().cloned());
+                args.push("--json".to_string());
+                args.push(folder.clone());
+                match run_ast_grep(command, &args, None, &self.config).await {
+                    Ok(result) => matches += parse_matches(&result.stdout).len(),
+                    Err(e) => error = Some(e.to_string()),
+                }
+            }
+            let relaxation = match error {
+                Some(e) => format!("{} (failed: {})", relaxation.description, e),
+                None => relaxation.description,
+            };
+            attempts.push(RelaxationAttempt { relaxation, matches });
+            if matches > 0 {
+                break;
+            }
+        }
+        attempts
+    }
+
+    /// Adds `language` to the active sgconfig.yaml once `sample` parses cleanly with it, and
+    /// returns a summary for the caller.
+    async fn register_language(
+        &self,
+        name: &str,
+        language: &CustomLanguage,
+        sample: &str,
+        replace: bool,
+    ) -> Result<String, McpError> {
+        let Some(config_path) = self.config.config_path.clone() else {
+            return Err(invalid_params(
+                "No sgconfig.yaml is active; start the server with --config <path> or AST_GREP_CONFIG.",
+            ));
+        };
+        let config_dir = config_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        language.validate(&config_dir).map_err(invalid_params)?;
+
+        let current = std::fs::read_to_string(&config_path).map_err(tool_error)?;
+        let updated = add_custom_language(&current, name, language, replace).map_err(invalid_params)?;
+
+        // Validate against a copy next to the real file, so relative library paths resolve the same way
+        let candidate = config_dir.join(format!(".sgconfig-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&candidate, updated).map_err(tool_error)?;
+        let mut candidate_config = self.config.clone();
+        candidate_config.config_path = Some(candidate.clone());
+        let args = [
+            "--pattern".to_string(),
+            sample.to_string(),
+            "--lang".to_string(),
+            name.to_string(),
+            "--debug-query=cst".to_string(),
+        ];
+        let validation = match run_ast_grep("run", &args, None, &candidate_config).await {
+            Ok(result) if dump_has_errors(&result.stderr) => Err(format!(
+                "The sample did not parse cleanly with this parser:\n{}",
+                result.stderr.trim()
+            )),
+            Ok(result) => Ok(result.stderr),
+            Err(e) => Err(format!("Could not load the parser: {}", e)),
+        };
+        let dump = match validation {
+            Ok(dump) => dump,
+            Err(message) => {
+                let _ = std::fs::remove_file(&candidate);
+                return Err(tool_error(message));
+            }
+        };
+        if let Err(e) = std::fs::rename(&candidate, &config_path) {
+            let _ = std::fs::remove_file(&candidate);
+            return Err(tool_error(e));
+        }
+
+        Ok(format!(
+            "Registered custom language '{}' ({}) in {}.\n\nParsed sample:\n{}",
+            name,
+            language.extensions.join(", "),
+            config_path.display(),
+            dump.trim()
+        ))
+    }
+
+    /// Saves the manifest of `backup` and remembers it as this session's latest change, for
+    /// undo_rewrite.
+    fn record_backup(&self, backup_dir: &Path, backup: &Backup) -> Result<(), McpError> {
+        backup.save(backup_dir).map_err(tool_error)?;
+        self.rewrites.lock().unwrap_or_else(|e| e.into_inner()).push(backup.id.clone());
+        Ok(())
+    }
+
+    /// Directories file-reading tools may access: the configured roots, or else the roots
+    /// the client declares. Empty when neither is available.
+    async fn allowed_roots(&self, peer: &Peer<RoleServer>) -> Vec<PathBuf> {
+        if !self.config.allowed_roots.is_empty() {
+            return self.config.allowed_roots.clone();
+        }
+
+        let client_has_roots = peer
+            .peer_info()
+            .is_some_and(|info| info.capabilities.roots.is_some());
+        if !client_has_roots {
+            return Vec::new();
+        }
+        match peer.list_roots().await {
+            Ok(result) => roots_from_uris(result.roots.iter().map(|root| root.uri.as_str())),
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to list client roots");
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// One ast-grep invocation of a find call: a whole folder, or one shard of it.
+struct ScanJob {
+    /// Index into the call's folder list.
+    folder: usize,
+    /// 1-based shard number, numbered across all folders; `None` for unsharded folders.
+    shard: Option<usize>,
+    paths: Vec<String>,
+}
+
+/// Decides which invocations a find call needs, sharding folders above `shard_threshold`.
+///
+/// When `options.shards` is set only those shards are kept, to resume a partial scan.
+async fn plan_scan_jobs(
+    folders: &[String],
+    shard_threshold: Option<usize>,
+    options: &FindOptions,
+    report: &mut ScanReport,
+) -> Result<Vec<ScanJob>, McpError> {
+    let include_vendored = options.include_vendored;
+    let mut jobs = Vec::new();
+    let mut next_shard = 1;
+    for (folder_index, folder) in folders.iter().enumerate() {
+        let shards = match shard_threshold {
+            Some(threshold) => {
+                let root = PathBuf::from(folder);
+                tokio::task::spawn_blocking(move || {
+                    (count_files(&root, threshold, include_vendored) > threshold)
+                        .then(|| plan_shards(&root, SHARD_TARGET_FILES.min(threshold), include_vendored))
+                })
+                .await
+                .map_err(tool_error)?
+            }
+            None => None,
+        };
+
+        match shards {
+            Some(shards) => {
+                report.notes.push(format!("Scanned {} in {} shards.", folder, shards.len()));
+                for paths in shards {
+                    let shard = next_shard;
+                    next_shard += 1;
+                    if options.shards.is_empty() || options.shards.contains(&shard) {
+                        jobs.push(ScanJob {
+                            folder: folder_index,
+                            shard: Some(shard),
+                            paths: paths.iter().map(|p| p.to_string_lossy().into_owned()).collect(),
+                        });
+                    }
+                }
+            }
+            None => jobs.push(ScanJob {
+                folder: folder_index,
+                shard: None,
+                paths: vec![folder.clone()],
+            }),
+        }
+    }
+    Ok(jobs)
+}
+
+
+/// A rule watched by `watch_rule`, re-evaluated on the files its folder's change events name.
+struct RuleWatch {
+    id: String,
+    folder: String,
+    /// Languages of the rule, to skip changed files it cannot match.
+    languages: Vec<String>,
+    /// Scan arguments without the paths to scan.
+    query_args: Vec<String>,
+    /// Leaves out changed paths a scan of the folder skips.
+    changes: ChangeFilter,
+    /// Shared with the registry, which snapshots them for `export_session`.
+    matches: Arc<Mutex<WatchedMatches>>,
+    /// How often the whole folder is rescanned regardless of file events.
+    rescan_interval: Option<Duration>,
+    config: Config,
+    watches: Arc<WatchRegistry>,
+    peer: Peer<RoleServer>,
+}
+
+impl RuleWatch {
+    /// Handles file events until the watcher goes away or the session ends, then drops the watch
+    /// from the registry.
+    async fn run(mut self, mut events: tokio::sync::mpsc::UnboundedReceiver<notify::Result<notify::Event>>) {
+        let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+        let mut scheduled_rescan = self
+            .rescan_interval
+            .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = session_check.tick() => {
+                    if self.peer.is_transport_closed() {
+                        break;
+                    }
+                    continue;
+                }
+                _ = async {
+                    match scheduled_rescan.as_mut() {
+                        Some(interval) => interval.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    // Catches changes the file watcher missed, e.g. on network file systems
+                    if !self.evaluate(Vec::new(), RescanPlan::Folder).await {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
+            // Collect the whole burst (a branch switch touches many files at once) into one batch
+            let mut changed = std::collections::BTreeSet::new();
+            self.collect(event, &mut changed);
+            let batch_deadline = tokio::time::Instant::now() + WATCH_MAX_BATCH_DELAY;
+            let mut watcher_gone = false;
+            loop {
+                let quiet = (tokio::time::Instant::now() + WATCH_DEBOUNCE).min(batch_deadline);
+                match tokio::time::timeout_at(quiet, events.recv()).await {
+                    Ok(Some(event)) => self.collect(event, &mut changed),
+                    Ok(None) => {
+                        watcher_gone = true;
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+            if watcher_gone {
+                break;
+            }
+            let paths: Vec<String> = changed.into_iter().collect();
+            let plan = plan_rescan(&paths, &self.languages);
+            if !paths.is_empty() && !self.evaluate(paths, plan).await {
+                break;
+            }
+        }
+        self.watches.forget(&self.id);
+    }
+
+    /// Re-evaluates the rule on changed `paths` as planned and notifies the client about changed
+    /// matches; `false` once the client can no longer be notified.
+    async fn evaluate(&mut self, paths: Vec<String>, plan: RescanPlan) -> bool {
+        let (scanned, groups) = match plan {
+            RescanPlan::Nothing => (paths, Vec::new()),
+            RescanPlan::Paths(groups) => (paths, groups),
+            RescanPlan::Folder => (vec![self.folder.clone()], vec![vec![self.folder.clone()]]),
+        };
+        let mut matches = Vec::new();
+        for group in groups {
+            let mut args = self.query_args.clone();
+            args.extend(group);
+            match run_ast_grep("scan", &args, None, &self.config).await {
+                Ok(result) => matches.extend(parse_matches(&result.stdout)),
+                Err(e) => {
+                    tracing::warn!(watch_id = %self.id, error = %e, "watch rescan failed");
+                    return true;
+                }
+            }
         }
+        make_paths_absolute(&mut matches, &self.folder);
+        add_fingerprints(&mut matches);
 
-        let mut args = vec!["--pattern".to_string(), params.pattern];
-        if !params.language.is_empty() {
-            args.push("--lang".to_string());
-            args.push(params.language);
+        let (changes, total) = {
+            let mut current = self.matches.lock().unwrap_or_else(|e| e.into_inner());
+            (current.update(&scanned, matches), current.len())
+        };
+        if changes.is_empty() {
+            return true;
         }
-        args.push("--json".to_string());
-        args.push(params.project_folder);
+        self.watches.record(&self.id, &changes, total);
+        let notification = LoggingMessageNotificationParam {
+            level: LoggingLevel::Notice,
+            logger: Some(WATCH_LOGGER.to_string()),
+            data: serde_json::json!({
+                "watch_id": self.id,
+                "added": changes.added.iter().map(match_summary).collect::<Vec<_>>(),
+                "removed": changes.removed.iter().map(match_summary).collect::<Vec<_>>(),
+                "total_matches": total,
+            }),
+        };
+        if let Err(e) = self.peer.notify_logging_message(notification).await {
+            tracing::debug!(watch_id = %self.id, error = %e, "stopping watch, client is gone");
+            return false;
+        }
+        true
+    }
 
-        let result = run_ast_grep(
-            "run",
-            &args,
-            None,
-            self.config.config_path.as_ref(),
-        )
-        .await
-        .map_err(|e| McpError {
-            code: ErrorCode(0),
-            message: e.to_string().into(),
-            data: None,
-        })?;
-
-        let stdout = result.stdout.trim();
-        let matches: Vec<Value> = if stdout.is_empty() {
-            vec![]
-        } else {
-            serde_json::from_str(stdout).unwrap_or_else(|_| vec![])
+    /// Adds the paths of a content-changing event, leaving out those a scan of the folder skips:
+    /// hidden, ignored and vendored ones.
+    fn collect(&mut self, event: notify::Result<notify::Event>, changed: &mut std::collections::BTreeSet<String>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!(watch_id = %self.id, error = %e, "file watch error");
+                return;
+            }
         };
+        if matches!(event.kind, notify::EventKind::Access(_)) {
+            return;
+        }
+        for path in event.paths {
+            if self.changes.is_scanned(&path) {
+                changed.insert(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+}
 
-        let total_matches = matches.len();
-        let matches = if params.max_results > 0 && total_matches > params.max_results as usize {
-            matches[..params.max_results as usize].to_vec()
-        } else {
-            matches
+/// Tells the client when the sgconfig file, its rule directories or its custom language libraries
+/// change, since tools then behave differently (other languages, other rules), until the session
+/// ends.
+async fn watch_config(config_path: PathBuf, peer: Peer<RoleServer>) {
+    let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+    'watcher: loop {
+        let watched = config_watch_paths(&config_path);
+        let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |event| {
+            let _ = events_tx.send(event);
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::warn!(error = %e, "cannot watch the sgconfig file");
+                return;
+            }
         };
+        for path in &watched {
+            // Editors often replace a file instead of writing it, so watch the file's directory
+            let (target, mode) = if path.is_dir() {
+                (path.as_path(), notify::RecursiveMode::Recursive)
+            } else {
+                (path.parent().unwrap_or(path), notify::RecursiveMode::NonRecursive)
+            };
+            if let Err(e) = notify::Watcher::watch(&mut watcher, target, mode) {
+                tracing::warn!(path = %target.display(), error = %e, "cannot watch sgconfig path");
+            }
+        }
 
-        if params.output_format == "text" {
-            if matches.is_empty() {
-                return Ok(CallToolResult::success(vec![Content::text("No matches found")]));
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = session_check.tick() => {
+                    if peer.is_transport_closed() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+            let Some(event) = event else {
+                return;
+            };
+            let mut changed = std::collections::BTreeSet::new();
+            let mut collect = |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if matches!(event.kind, notify::EventKind::Access(_)) {
+                    return;
+                }
+                for path in event.paths {
+                    if watched.iter().any(|watched| path.starts_with(watched)) {
+                        changed.insert(path.to_string_lossy().into_owned());
+                    }
+                }
+            };
+            collect(event);
+            while let Ok(Some(event)) = tokio::time::timeout(WATCH_DEBOUNCE, events.recv()).await {
+                collect(event);
             }
-            let text_output = format_matches_as_text(&matches);
-            let mut header = format!("Found {} matches", matches.len());
-            if params.max_results > 0 && total_matches > params.max_results as usize {
-                header = format!("Found {} matches (showing first {} of {})", total_matches, params.max_results, total_matches);
+            if changed.is_empty() {
+                continue;
+            }
+
+            tracing::info!(changed = changed.len(), "sgconfig or rules changed");
+            let notification = LoggingMessageNotificationParam {
+                level: LoggingLevel::Notice,
+                logger: Some(CONFIG_LOGGER.to_string()),
+                data: serde_json::json!({
+                    "message": "The sgconfig file or its rules changed; earlier results may be outdated.",
+                    "config": config_path.to_string_lossy(),
+                    "changed": changed,
+                }),
+            };
+            let notified = peer.notify_tool_list_changed().await.is_ok()
+                && peer.notify_logging_message(notification).await.is_ok();
+            if !notified {
+                return;
+            }
+            // The config may now name other rule directories or libraries
+            if config_watch_paths(&config_path) != watched {
+                drop(watcher);
+                continue 'watcher;
             }
-            Ok(CallToolResult::success(vec![Content::text(format!("{}:\n\n{}", header, text_output))]))
-        } else {
-             let json_str = serde_json::to_string_pretty(&matches).unwrap_or_default();
-             Ok(CallToolResult::success(vec![Content::text(json_str)]))
         }
     }
+}
 
-    #[tool(description = "
-Find code using ast-grep's YAML rule in a project folder.
-YAML rule is more powerful than simple pattern and can perform complex search like find AST inside/having another AST.
-It is a more advanced search tool than the simple `find_code`.
+/// Joins relative match paths onto `folder`.
+fn make_paths_absolute(matches: &mut [Value], folder: &str) {
+    for m in matches.iter_mut() {
+        if let Some(file) = m.get("file").and_then(|v| v.as_str()) {
+            let absolute = Path::new(folder).join(file).to_string_lossy().into_owned();
+            m["file"] = Value::String(absolute);
+        }
+    }
+}
 
-Tip: When using relational rules (inside/has), add `stopBy: end` to ensure complete traversal.
+/// Sends MCP progress notifications when the client asked for them with a progress token.
+///
+/// Scans running as background jobs instead publish each part's matches to their job, so they
+/// can be fetched before the whole scan is done.
+struct Progress {
+    peer: Peer<RoleServer>,
+    token: Option<ProgressToken>,
+    job: Option<(Arc<JobManager>, String)>,
+}
 
-Internally calls: ast-grep scan --inline-rules <yaml> [--json] <project_folder>
+impl Progress {
+    fn new(context: &RequestContext<RoleServer>) -> Self {
+        Self {
+            peer: context.peer.clone(),
+            token: context.meta.get_progress_token(),
+            job: None,
+        }
+    }
 
-Output formats:
-- text (default): Compact text format with file:line-range headers and complete match text
-  Example:
-    Found 2 matches:
+    fn for_job(context: &RequestContext<RoleServer>, jobs: Arc<JobManager>, job_id: String) -> Self {
+        Self {
+            peer: context.peer.clone(),
+            token: None,
+            job: Some((jobs, job_id)),
+        }
+    }
 
-    src/models.py:45-52
-    class UserModel:
-        def __init__(self):
-            self.id = None
-            self.name = None
+    /// Records a finished scan part of the scan planned as `plan` and its matches for the job, if
+    /// any.
+    fn publish(
+        &self,
+        args: &[String],
+        plan: &str,
+        part: (usize, Option<usize>),
+        parts: (usize, usize),
+        matches: &[Value],
+    ) {
+        if let Some((jobs, id)) = &self.job {
+            jobs.add_partial(id, args, plan, part, parts, matches);
+        }
+    }
 
-    src/views.py:12
-    class SimpleView: pass
+    /// Parts the job already scanned with `args` before it was resumed, and their matches.
+    fn completed_parts(&self, args: &[String], plan: &str) -> Result<CompletedParts, String> {
+        match &self.job {
+            Some((jobs, id)) => jobs.completed_parts(id, args, plan),
+            None => Ok((Vec::new(), Vec::new())),
+        }
+    }
 
-- json: Full match objects with metadata including ranges, meta-variables, etc.
+    async fn report(&self, done: usize, total: usize, message: String) {
+        let Some(token) = &self.token else {
+            return;
+        };
+        let notification = ProgressNotificationParam {
+            progress_token: token.clone(),
+            progress: done as f64,
+            total: Some(total as f64),
+            message: Some(message),
+        };
+        if let Err(e) = self.peer.notify_progress(notification).await {
+            tracing::debug!(error = %e, "failed to send progress notification");
+        }
+    }
+}
 
-The max_results parameter limits the number of complete matches returned (not individual lines).
-When limited, the header shows \"Found X matches (showing first Y of Z)\".
+/// Longest `git ls-files` may take to list the ignored entries of a project folder.
+const GIT_TIMEOUT: Duration = Duration::from_secs(10);
 
-Example usage:
-  find_code_by_rule(yaml=\"id: x\\nlanguage: python\\nrule: {pattern: 'class $NAME'}\", max_results=20)
-  find_code_by_rule(yaml=\"...\", output_format=\"json\")  # For full metadata
-")]
-    async fn find_code_by_rule(
-        &self,
-        Parameters(params): Parameters<FindCodeByRuleParams>,
-    ) -> Result<CallToolResult, McpError> {
-         if params.output_format != "text" && params.output_format != "json" {
-             return Err(McpError {
-                 code: ErrorCode(-32602), // Invalid params
-                 message: format!("Invalid output_format: {}. Must be 'text' or 'json'.", params.output_format).into(),
-                 data: None,
-             });
+/// `HEAD` of the git repository around `folder` and whether `folder` has uncommitted changes
+/// (untracked files left out), or `None`s outside a repository.
+async fn git_state(folder: &str) -> (Option<String>, Option<bool>) {
+    let git = |args: &[&str]| {
+        let mut command = vec!["git".to_string(), "-C".to_string(), folder.to_string()];
+        command.extend(args.iter().map(|arg| arg.to_string()));
+        command
+    };
+    let (head_args, status_args) =
+        (git(&["rev-parse", "HEAD"]), git(&["status", "--porcelain", "--untracked-files=no", "--", "."]));
+    let (head, status) = tokio::join!(
+        run_command(&head_args, None, Some(GIT_TIMEOUT)),
+        run_command(&status_args, None, Some(GIT_TIMEOUT))
+    );
+    let commit = head.ok().map(|result| result.stdout.trim().to_string()).filter(|commit| !commit.is_empty());
+    let dirty = commit.as_ref().and(status.ok()).map(|result| is_dirty(&result.stdout));
+    (commit, dirty)
+}
+
+/// How long the results of a finished background scan are kept when nobody fetches them.
+pub const JOB_RESULT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Scan parts a checkpoint has as completed and their matches.
+pub type CompletedParts = (Vec<(usize, Option<usize>)>, Vec<Value>);
+
+/// State of a background scan as reported by `scan_status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub job_id: String,
+    /// `running`, `done`, `failed`, or `interrupted` for a scan of an earlier server process
+    /// that left a checkpoint.
+    pub state: &'static str,
+    /// The pattern or rule being searched.
+    pub query: String,
+    pub elapsed_ms: f64,
+    /// Scan parts (shards or folders) finished so far, out of `parts_total`.
+    pub parts_done: usize,
+    pub parts_total: usize,
+    /// Matches found by the finished parts, available through `fetch_partial`.
+    pub partial_matches: usize,
+    /// Whether `resume_scan` can continue the scan from its checkpoint.
+    pub resumable: bool,
+    /// Seconds until the results of a finished job expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in_s: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub enum JobOutcome {
+    Running(JobStatus),
+    Finished(Result<CallToolResult, String>),
+}
+
+struct Job {
+    query: String,
+    submitted: Instant,
+    finished: Option<(Instant, Result<CallToolResult, String>)>,
+    abort: Option<tokio::task::AbortHandle>,
+    parts: (usize, usize),
+    /// Completed parts and their matches, which are also the partial results.
+    checkpoint: Checkpoint,
+}
+
+/// Matches of a background scan found so far, as returned by `fetch_partial`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialResults {
+    #[serde(flatten)]
+    pub status: JobStatus,
+    /// Pass as `since` to the next `fetch_partial` call to get only newer matches.
+    pub next_since: usize,
+    /// Raw ast-grep matches, in the order the scan parts finished.
+    pub matches: Vec<Value>,
+}
+
+/// Background scans started by `submit_scan`, kept until their results are fetched or expire.
+///
+/// Expired jobs are dropped lazily on every access; a job still running when it is dropped
+/// (which only happens when the manager itself is dropped) is aborted. With a checkpoint
+/// directory, every finished part is saved to disk until the scan succeeds, so a failed or
+/// interrupted scan can be resumed, also by a later server process.
+pub struct JobManager {
+    ttl: Duration,
+    checkpoint_dir: Option<PathBuf>,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl Default for JobManager {
+    fn default() -> Self {
+        Self::new(JOB_RESULT_TTL)
+    }
+}
+
+impl Drop for JobManager {
+    fn drop(&mut self) {
+        let jobs = self.jobs.get_mut().unwrap_or_else(|e| e.into_inner());
+        for job in jobs.values() {
+            if let Some(abort) = &job.abort {
+                abort.abort();
+            }
         }
+    }
+}
 
-        let args = vec!["--inline-rules".to_string(), params.yaml, "--json".to_string(), params.project_folder];
+impl JobManager {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            checkpoint_dir: None,
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
 
-        let result = run_ast_grep(
-            "scan",
-            &args,
-            None,
-            self.config.config_path.as_ref(),
-        )
-        .await
-        .map_err(|e| McpError {
-            code: ErrorCode(0),
-            message: e.to_string().into(),
-            data: None,
-        })?;
-
-        let stdout = result.stdout.trim();
-        let matches: Vec<Value> = if stdout.is_empty() {
-            vec![]
-        } else {
-            serde_json::from_str(stdout).unwrap_or_else(|_| vec![])
+    /// Saves checkpoints of running scans into `dir`.
+    pub fn with_checkpoint_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.checkpoint_dir = dir;
+        self
+    }
+
+    /// Aborts running scans and forgets all jobs. Checkpoints stay on disk.
+    pub fn abort_all(&self) {
+        for (_, job) in self.jobs.lock().unwrap_or_else(|e| e.into_inner()).drain() {
+            if let Some(abort) = job.abort {
+                abort.abort();
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Job>> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let ttl = self.ttl;
+        jobs.retain(|_, job| job.finished.as_ref().is_none_or(|(at, _)| at.elapsed() < ttl));
+        jobs
+    }
+
+    fn insert(&self, query: &str, checkpoint: Checkpoint) {
+        let job = Job {
+            query: query.to_string(),
+            submitted: Instant::now(),
+            finished: None,
+            abort: None,
+            parts: (checkpoint.completed.len(), 0),
+            checkpoint,
         };
+        self.lock().insert(job.checkpoint.job_id.clone(), job);
+    }
 
-        let total_matches = matches.len();
-        let matches = if params.max_results > 0 && total_matches > params.max_results as usize {
-            matches[..params.max_results as usize].to_vec()
-        } else {
-            matches
+    /// Registers a running job for `query` and returns its id. `request` holds the arguments
+    /// to restart the scan with when it is resumed.
+    pub fn submit(&self, query: &str, request: Value) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let checkpoint = Checkpoint {
+            job_id: id.clone(),
+            request,
+            ..Default::default()
+        };
+        self.insert(query, checkpoint);
+        id
+    }
+
+    /// Registers the job of `checkpoint` as running again; `false` if it is still running.
+    pub fn resume(&self, query: &str, checkpoint: Checkpoint) -> bool {
+        if self.is_running(&checkpoint.job_id) {
+            return false;
+        }
+        self.insert(query, checkpoint);
+        true
+    }
+
+    fn is_running(&self, id: &str) -> bool {
+        self.lock().get(id).is_some_and(|job| job.finished.is_none())
+    }
+
+    /// The checkpoint of job `id` if it is not running: from memory for a job of this
+    /// process, otherwise from the checkpoint directory.
+    pub fn checkpoint(&self, id: &str) -> Result<Checkpoint, String> {
+        if let Some(job) = self.lock().get(id) {
+            if job.finished.is_none() {
+                return Err(format!("Job '{}' is still running", id));
+            }
+            return Ok(job.checkpoint.clone());
+        }
+        let Some(dir) = &self.checkpoint_dir else {
+            return Err(format!("Unknown or expired job id '{}'", id));
+        };
+        load_checkpoint(dir, id).map_err(|e| format!("No checkpoint for job '{}': {}", id, e))
+    }
+
+    fn set_abort_handle(&self, id: &str, abort: tokio::task::AbortHandle) {
+        if let Some(job) = self.lock().get_mut(id) {
+            if job.finished.is_none() {
+                job.abort = Some(abort);
+            }
+        }
+    }
+
+    /// Records that part `part` (folder index, shard) of job `id`, scanned with `args` as part of
+    /// the scan planned as `plan` (see [`plan_hash`]), has finished with `matches`, as the `done`th
+    /// of `total` parts, and adds it to the saved checkpoint.
+    ///
+    /// Parts scanned with different arguments (e.g. a relaxed retry) or of another plan replace
+    /// the earlier ones.
+    pub fn add_partial(
+        &self,
+        id: &str,
+        args: &[String],
+        plan: &str,
+        part: (usize, Option<usize>),
+        (done, total): (usize, usize),
+        matches: &[Value],
+    ) {
+        let restarted = {
+            let mut jobs = self.lock();
+            let Some(job) = jobs.get_mut(id) else {
+                return;
+            };
+            let restarted = job.checkpoint.args != args || job.checkpoint.plan != plan;
+            if restarted {
+                job.checkpoint.args = args.to_vec();
+                job.checkpoint.plan = plan.to_string();
+                job.checkpoint.completed.clear();
+                job.checkpoint.matches.clear();
+            }
+            job.parts = (done, total);
+            job.checkpoint.completed.push(part);
+            job.checkpoint.matches.extend_from_slice(matches);
+            restarted.then(|| job.checkpoint.clone())
+        };
+        let Some(dir) = &self.checkpoint_dir else {
+            return;
+        };
+        let saved = match restarted {
+            Some(checkpoint) => save_checkpoint(dir, &checkpoint),
+            None => append_part(dir, id, part, matches),
+        };
+        if let Err(e) = saved {
+            tracing::warn!(job_id = %id, error = %e, "failed to save scan checkpoint");
+        }
+    }
+
+    /// Parts of job `id` already scanned with `args` and their matches, to skip on resume. A
+    /// checkpoint of another `plan` cannot be resumed: the project changed since, so its part
+    /// numbers stand for other files.
+    pub fn completed_parts(&self, id: &str, args: &[String], plan: &str) -> Result<CompletedParts, String> {
+        match self.lock().get(id) {
+            Some(job) if job.checkpoint.args == args && !job.checkpoint.completed.is_empty() => {
+                if job.checkpoint.plan != plan {
+                    return Err(format!(
+                        "Cannot resume job '{}': files of the project were added or removed since it was \
+                         checkpointed, so its parts no longer match; submit a new scan.",
+                        id
+                    ));
+                }
+                Ok((job.checkpoint.completed.clone(), job.checkpoint.matches.clone()))
+            }
+            _ => Ok((Vec::new(), Vec::new())),
+        }
+    }
+
+    /// The partial matches of job `id` from index `since` on.
+    pub fn partial(&self, id: &str, since: usize) -> Option<PartialResults> {
+        let jobs = self.lock();
+        let job = jobs.get(id)?;
+        Some(PartialResults {
+            status: self.status_of(id, job),
+            next_since: job.checkpoint.matches.len(),
+            matches: job.checkpoint.matches.get(since..).unwrap_or_default().to_vec(),
+        })
+    }
+
+    /// Stores the outcome of job `id`, starting its expiry clock. The checkpoint of a
+    /// successful scan is deleted.
+    pub fn finish(&self, id: &str, result: Result<CallToolResult, String>) {
+        if let (Some(dir), true) = (&self.checkpoint_dir, result.is_ok()) {
+            remove_checkpoint(dir, id);
+        }
+        if let Some(job) = self.lock().get_mut(id) {
+            job.finished = Some((Instant::now(), result));
+            job.abort = None;
+        }
+    }
+
+    fn status_of(&self, id: &str, job: &Job) -> JobStatus {
+        let (state, elapsed, expires_in_s, error) = match &job.finished {
+            None => ("running", job.submitted.elapsed(), None, None),
+            Some((at, result)) => (
+                if result.is_ok() { "done" } else { "failed" },
+                *at - job.submitted,
+                Some(self.ttl.saturating_sub(at.elapsed()).as_secs()),
+                result.as_ref().err().cloned(),
+            ),
         };
+        JobStatus {
+            job_id: id.to_string(),
+            state,
+            query: job.query.clone(),
+            elapsed_ms: millis(elapsed),
+            parts_done: job.parts.0,
+            parts_total: job.parts.1,
+            partial_matches: job.checkpoint.matches.len(),
+            resumable: state == "failed",
+            expires_in_s,
+            error,
+        }
+    }
+
+    /// Status of job `id`, or of a scan of an earlier server process that left a checkpoint.
+    pub fn status(&self, id: &str) -> Option<JobStatus> {
+        if let Some(job) = self.lock().get(id) {
+            return Some(self.status_of(id, job));
+        }
+        let checkpoint = load_checkpoint(self.checkpoint_dir.as_ref()?, id).ok()?;
+        Some(JobStatus {
+            job_id: id.to_string(),
+            state: "interrupted",
+            query: scan_query_text(&checkpoint.request),
+            elapsed_ms: 0.0,
+            parts_done: checkpoint.completed.len(),
+            parts_total: 0,
+            partial_matches: checkpoint.matches.len(),
+            resumable: true,
+            expires_in_s: None,
+            error: None,
+        })
+    }
+
+    /// The outcome of job `id`, removing the job once it has finished.
+    pub fn take(&self, id: &str) -> Option<JobOutcome> {
+        let mut jobs = self.lock();
+        let job = jobs.get(id)?;
+        if job.finished.is_none() {
+            return Some(JobOutcome::Running(self.status_of(id, job)));
+        }
+        let (_, result) = jobs.remove(id)?.finished?;
+        Some(JobOutcome::Finished(result))
+    }
+}
+
+/// The ast-grep command and query arguments of a background scan.
+fn scan_query(params: &SubmitScanParams) -> Result<(&'static str, Vec<String>), McpError> {
+    match (params.pattern.is_empty(), params.yaml.is_empty()) {
+        (false, true) => {
+            let mut args = vec!["--pattern".to_string(), params.pattern.clone()];
+            if !params.language.is_empty() {
+                args.push("--lang".to_string());
+                args.push(params.language.clone());
+            }
+            Ok(("run", args))
+        }
+        (true, false) => Ok(("scan", vec!["--inline-rules".to_string(), params.yaml.clone()])),
+        _ => Err(invalid_params("Pass exactly one of pattern or yaml")),
+    }
+}
+
+/// The pattern or rule of saved `submit_scan` arguments.
+fn scan_query_text(request: &Value) -> String {
+    ["pattern", "yaml"]
+        .iter()
+        .filter_map(|key| request.get(key).and_then(Value::as_str))
+        .find(|query| !query.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Milliseconds rounded to microsecond precision.
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+fn tool_error(e: impl std::fmt::Display) -> McpError {
+    McpError {
+        code: ErrorCode(0),
+        message: e.to_string().into(),
+        data: None,
+    }
+}
+
+fn invalid_params(e: impl std::fmt::Display) -> McpError {
+    McpError {
+        code: ErrorCode(-32602), // Invalid params
+        message: e.to_string().into(),
+        data: None,
+    }
+}
+
+fn validate_output_format(output_format: &str) -> Result<(), McpError> {
+    if !OUTPUT_FORMATS.contains(&output_format) {
+        return Err(invalid_params(format!(
+            "Invalid output_format: {}. Must be {}.",
+            output_format,
+            one_of(OUTPUT_FORMATS)
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects a YAML rule that does not follow the rule schema, listing where, instead of letting
+/// ast-grep fail on it.
+fn unknown_rule_session(id: &str) -> McpError {
+    invalid_params(format!("Unknown rule session id '{}'; it was finalized, dropped or never created.", id))
+}
+
+fn check_rule_schema(yaml: &str) -> Result<(), McpError> {
+    let Err(violations) = validate_rule_yaml(yaml) else {
+        return Ok(());
+    };
+    let list: Vec<String> = violations.iter().map(|violation| format!("- {}", violation)).collect();
+    Err(McpError {
+        code: ErrorCode(-32602), // Invalid params
+        message: format!(
+            "The rule does not follow the ast-grep rule schema ({}):\n{}",
+            RULE_SCHEMA_URI,
+            list.join("\n")
+        )
+        .into(),
+        data: Some(serde_json::json!({ "violations": violations })),
+    })
+}
+
+fn parse_expando_char(value: Option<&str>) -> Result<Option<char>, McpError> {
+    match value.filter(|c| !c.is_empty()) {
+        None => Ok(None),
+        Some(c) if c.chars().count() == 1 => Ok(c.chars().next()),
+        Some(c) => Err(invalid_params(format!("expando_char must be a single character, got '{}'", c))),
+    }
+}
+
+/// Parses ast-grep's `--json` output; empty or malformed output yields no matches.
+/// Parses ast-grep's `--json` output, or the one match per line of `--json=stream`.
+fn parse_matches(stdout: &str) -> Vec<Value> {
+    let stdout = stdout.trim();
+    if stdout.is_empty() {
+        vec![]
+    } else if stdout.starts_with('{') {
+        stdout.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()
+    } else {
+        serde_json::from_str(stdout).unwrap_or_else(|_| vec![])
+    }
+}
+
+/// Side information about how a search was scoped, reported next to the matches.
+#[derive(Debug, Default, Serialize)]
+struct ScanReport {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<SkippedFile>,
+    /// Files with more matches than `max_matches_per_file`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    capped: Vec<CappedFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<ScanProfile>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnosis: Vec<RelaxationAttempt>,
+    /// The relaxation the matches were found with, when the exact query found nothing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relaxed: Option<String>,
+    /// Whether scanning stopped once `max_results` matches were found.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stopped_early: bool,
+    /// What the walk of the project folders left out, and why.
+    #[serde(skip_serializing_if = "NotSearched::is_empty")]
+    not_searched: NotSearched,
+    /// The query, scope and suggestions of a search that found nothing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_matches: Option<NoMatchReport>,
+}
 
-        if params.output_format == "text" {
-            if matches.is_empty() {
-                return Ok(CallToolResult::success(vec![Content::text("No matches found")]));
+impl ScanReport {
+    fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+            && self.skipped.is_empty()
+            && self.capped.is_empty()
+            && self.profile.is_none()
+            && self.diagnosis.is_empty()
+            && self.relaxed.is_none()
+            && !self.stopped_early
+            && self.not_searched.is_empty()
+            && self.no_matches.is_none()
+    }
+
+    fn to_text(&self) -> String {
+        let mut sections = Vec::new();
+        if !self.skipped.is_empty() {
+            let lines: Vec<String> = self
+                .skipped
+                .iter()
+                .map(|s| format!("  {} ({})", s.file, s.reason))
+                .collect();
+            sections.push(format!("Skipped files:\n{}", lines.join("\n")));
+        }
+        if !self.capped.is_empty() {
+            let lines: Vec<String> = self
+                .capped
+                .iter()
+                .map(|c| format!("  {} ({} of {} matches shown)", c.file, c.shown, c.matches))
+                .collect();
+            sections.push(format!("Files capped at max_matches_per_file:\n{}", lines.join("\n")));
+        }
+        if let Some(profile) = &self.profile {
+            sections.push(profile.to_text());
+        }
+        if let Some(no_matches) = &self.no_matches {
+            sections.push(no_matches.to_text());
+        }
+        if !self.diagnosis.is_empty() {
+            sections.push(diagnosis_text(&self.diagnosis));
+        }
+        if let Some(relaxation) = &self.relaxed {
+            sections.push(format!(
+                "Note: No exact matches; these were found with {} and may be looser than the query.",
+                relaxation
+            ));
+        }
+        if self.stopped_early {
+            sections.push(
+                "Note: The search stopped once max_results matches were found, not counting those in generated \
+                 files or outside the filter profile; raise max_results (0 for no limit) to see more."
+                    .to_string(),
+            );
+        }
+        for note in &self.notes {
+            sections.push(format!("Note: {}", note));
+        }
+        if !self.not_searched.is_empty() {
+            sections.push(self.not_searched.to_text());
+        }
+        sections.join("\n\n")
+    }
+}
+
+/// Applies `max_results` and renders matches in the requested output format.
+///
+/// The `report` (skipped files, notes on how the search was scoped) is appended to text
+/// output and returned as a separate JSON content block for JSON and diagnostics output, so
+/// the match array stays parseable on its own. Each returned match is followed by a resource link
+/// to its file region, except in headers output, which exists to keep responses small.
+fn render_matches(
+    matches: Vec<Value>,
+    max_results: i32,
+    output_format: &str,
+    report: &ScanReport,
+    project_folder: &Path,
+) -> CallToolResult {
+    let total_matches = matches.len();
+    let truncated = max_results > 0 && total_matches > max_results as usize;
+    let mut matches = matches;
+    if truncated {
+        tracing::info!(total = total_matches, shown = max_results, "results truncated to max_results");
+        matches.truncate(max_results as usize);
+    }
+
+    let links: Vec<Content> = if output_format == "headers" {
+        Vec::new()
+    } else {
+        matches
+            .iter()
+            .filter_map(|m| match_resource_link(m, project_folder))
+            .map(Content::resource_link)
+            .collect()
+    };
+
+    let mut content = if output_format == "text" || output_format == "headers" {
+        let mut text = if matches.is_empty() {
+            "No matches found".to_string()
+        } else {
+            let kind = if report.relaxed.is_some() { "relaxed matches" } else { "matches" };
+            let mut text = format!("Found {} {}", matches.len(), kind);
+            if report.stopped_early {
+                text.push_str(" (stopped at max_results, there may be more)");
+            } else if truncated {
+                text = format!("Found {} {} (showing first {} of {})", total_matches, kind, max_results, total_matches);
             }
-            let text_output = format_matches_as_text(&matches);
-            let mut header = format!("Found {} matches", matches.len());
-            if params.max_results > 0 && total_matches > params.max_results as usize {
-                header = format!("Found {} matches (showing first {} of {})", total_matches, params.max_results, total_matches);
+            text.push_str(":\n\n");
+            if output_format == "headers" {
+                text.push_str(&format_match_headers(&matches));
+            } else {
+                let written = write_matches_as_text(&mut text, &matches, MAX_TEXT_BYTES);
+                if written < matches.len() {
+                    tracing::info!(shown = written, total = matches.len(), "text output stopped at its size limit");
+                    text.push_str(&format!(
+                        "\n\nText output stopped after {} of {} matches at its {} MB limit; use max_results, \
+                         output_format='headers' or export_matches to see the rest.",
+                        written,
+                        matches.len(),
+                        MAX_TEXT_BYTES / (1024 * 1024)
+                    ));
+                }
             }
-            Ok(CallToolResult::success(vec![Content::text(format!("{}:\n\n{}", header, text_output))]))
+            text
+        };
+        if !report.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(&report.to_text());
+        }
+        vec![Content::text(text)]
+    } else {
+        let json_str = if output_format == "diagnostics" {
+            serde_json::to_string_pretty(&matches_to_diagnostics(&matches, project_folder)).unwrap_or_default()
         } else {
-             let json_str = serde_json::to_string_pretty(&matches).unwrap_or_default();
-             Ok(CallToolResult::success(vec![Content::text(json_str)]))
+            serde_json::to_string_pretty(&matches).unwrap_or_default()
+        };
+        let mut content = vec![Content::text(json_str)];
+        if !report.is_empty() {
+            content.push(Content::text(serde_json::to_string_pretty(report).unwrap_or_default()));
+        }
+        content
+    };
+    content.extend(links);
+    CallToolResult::success(content)
+}
+
+fn render_aggregate(
+    mut aggregate: MetavariableAggregate,
+    max_results: i32,
+    output_format: &str,
+    report: &ScanReport,
+) -> CallToolResult {
+    let truncated = max_results > 0 && aggregate.values.len() > max_results as usize;
+    if truncated {
+        aggregate.values.truncate(max_results as usize);
+    }
+
+    if output_format == "json" {
+        let mut content = vec![Content::text(serde_json::to_string_pretty(&aggregate).unwrap_or_default())];
+        if !report.is_empty() {
+            content.push(Content::text(serde_json::to_string_pretty(report).unwrap_or_default()));
+        }
+        return CallToolResult::success(content);
+    }
+
+    let mut text = if aggregate.values.is_empty() {
+        format!("No values of {} found ({} matches)", aggregate.metavariable, aggregate.matches)
+    } else {
+        let mut header = format!(
+            "Found {} distinct values of {} in {} matches",
+            aggregate.distinct, aggregate.metavariable, aggregate.matches
+        );
+        if truncated {
+            header.push_str(&format!(" (showing top {})", max_results));
         }
+        format!("{}:\n\n{}", header, format_aggregate_as_text(&aggregate))
+    };
+    if aggregate.unbound > 0 {
+        text.push_str(&format!(
+            "\n\n{} matches did not bind {}.",
+            aggregate.unbound, aggregate.metavariable
+        ));
+    }
+    if !report.is_empty() {
+        text = format!("{}\n\n{}", text, report.to_text());
     }
+    CallToolResult::success(vec![Content::text(text)])
 }
 
-#[tool_handler]
+/// Key under which the per-call request id is reported in response `_meta` and error `data`.
+pub const REQUEST_ID_KEY: &str = "request_id";
+
 impl ServerHandler for AstGrepServer {
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        // Every tool call gets its own id so a failure reported by a user can be matched
+        // to the exact log lines of that invocation.
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let tool_name = request.name.clone();
+        let span = tracing::info_span!(
+            "tool_call",
+            session_id = %self.session_id,
+            request_id = %request_id,
+            tool = %tool_name
+        );
+
+        async move {
+            tracing::info!("tool call started");
+            // Tokens from an OAuth issuer carry scopes; write-capable tools need the write scope
+            let grant = context
+                .extensions
+                .get::<axum::http::request::Parts>()
+                .and_then(|parts| parts.extensions.get::<Grant>());
+            // Quotas count against the client the token identifies, over all of its sessions
+            if let Some(client) = grant.and_then(|grant| grant.client.as_deref()) {
+                self.sessions.set_identity(&self.session_id, client);
+            }
+            if let (Some(grant), Some(oauth)) = (grant, &self.config.auth.oauth) {
+                if !self.may_call(grant, &tool_name) {
+                    tracing::warn!(subject = ?grant.subject, "tool call lacks the write scope");
+                    return Err(McpError::invalid_request(
+                        format!("Tool '{}' changes files and needs the '{}' scope", tool_name, oauth.write_scope),
+                        Some(serde_json::json!({ REQUEST_ID_KEY: request_id, "required_scope": oauth.write_scope })),
+                    ));
+                }
+            }
+            for deprecation in call_deprecations(DEPRECATIONS, &tool_name, request.arguments.as_ref()) {
+                tracing::warn!(
+                    parameter = deprecation.parameter,
+                    since = deprecation.since,
+                    replacement = deprecation.replacement,
+                    "deprecated tool or parameter used"
+                );
+            }
+            let ct = context.ct.clone();
+            let tcc = ToolCallContext::new(self, request, context);
+            // Dropping the tool future on cancellation kills any ast-grep process it started.
+            let result = tokio::select! {
+                result = self.tool_router.call(tcc) => result,
+                _ = ct.cancelled() => Err(McpError::internal_error("Request cancelled", None)),
+            };
+            let is_error = result.as_ref().map_or(true, |r| r.is_error.unwrap_or(false));
+            stats::global().record_tool_call(&tool_name, is_error);
+
+            match result {
+                Ok(mut result) => {
+                    tracing::info!(is_error = result.is_error.unwrap_or(false), "tool call finished");
+                    result
+                        .meta
+                        .get_or_insert_with(Meta::new)
+                        .insert(REQUEST_ID_KEY.to_string(), Value::String(request_id));
+                    Ok(result)
+                }
+                Err(mut error) => {
+                    // Spans below info are filtered at the default level, so repeat the id here.
+                    tracing::warn!(request_id = %request_id, error = %error.message, "tool call failed");
+                    match error.data {
+                        Some(Value::Object(ref mut data)) => {
+                            data.insert(REQUEST_ID_KEY.to_string(), Value::String(request_id));
+                        }
+                        None => {
+                            error.data = Some(serde_json::json!({ REQUEST_ID_KEY: request_id }));
+                        }
+                        Some(_) => {}
+                    }
+                    Err(error)
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: self.available_tools().await,
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        Ok(ListResourcesResult {
+            resources: resources::list_resources(),
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        match resources::read_resource(&request.uri) {
+            Some(contents) => Ok(ReadResourceResult { contents: vec![contents] }),
+            None => Err(McpError::resource_not_found(
+                format!("Unknown resource '{}'", request.uri),
+                Some(serde_json::json!({ "uri": request.uri })),
+            )),
+        }
+    }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        logging::set_client_level(&self.session_id, request.level);
+        Ok(())
+    }
+
+    fn get_tool(&self, name: &str) -> Option<Tool> {
+        self.tool_router.get(name).cloned()
+    }
+
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        tracing::info!(session_id = %self.session_id, "client initialized");
+        if let Some(client) = context.peer.peer_info() {
+            self.sessions.set_client(&self.session_id, &client.client_info.name);
+        }
+        logging::register_client(&self.session_id, context.peer.clone());
+        if let Some(config_path) = self.config.config_path.clone() {
+            let span = tracing::info_span!("sgconfig_watch", session_id = %self.session_id);
+            tokio::spawn(watch_config(config_path, context.peer.clone()).instrument(span));
+        }
+
+        let server = self.clone();
+        let peer = context.peer;
+        let span = tracing::info_span!("session", session_id = %self.session_id);
+        tokio::spawn(
+            async move {
+                let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+                let mut installed = ast_grep_version().await.is_some();
+                while !peer.is_transport_closed() {
+                    session_check.tick().await;
+                    // Installing or removing ast-grep changes which tools are listed
+                    let now_installed = ast_grep_version().await.is_some();
+                    if now_installed != installed {
+                        installed = now_installed;
+                        tracing::info!(installed, "ast-grep availability changed, tool list updated");
+                        let _ = peer.notify_tool_list_changed().await;
+                    }
+                }
+                server.end_session();
+            }
+            .instrument(span),
+        );
+    }
+
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             server_info: Implementation {
@@ -355,7 +5745,12 @@ impl ServerHandler for AstGrepServer {
                 version: "0.1.0".into(),
                 ..Default::default()
             },
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_tool_list_changed()
+                .enable_logging()
+                .enable_resources()
+                .build(),
             ..Default::default()
         }
     }
@@ -0,0 +1,259 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How often a session checks whether its client is gone.
+pub const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Limits on what one client may use at once, so that one misbehaving client of a shared server
+/// cannot starve the others. A client is the subject of its access token (see
+/// [`Grant::client`]), over all of its sessions, or else a single session: opening more
+/// sessions does not get a client more.
+///
+/// [`Grant::client`]: crate::auth::Grant::client
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionQuotas {
+    /// Scans running at once, including background scans; `None` for no limit.
+    pub max_scans: Option<usize>,
+    /// Size of the matches remembered for `get_match_details`, as JSON; `None` for no limit
+    /// beyond the store's entry count.
+    pub max_cache_bytes: Option<usize>,
+    pub max_watches: usize,
+}
+
+impl Default for SessionQuotas {
+    fn default() -> Self {
+        Self {
+            max_scans: None,
+            max_cache_bytes: None,
+            max_watches: crate::watch::MAX_WATCHES,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QuotaError {
+    #[error("This client already runs {0} scans, the most allowed at once; wait for one to finish")]
+    Scans(usize),
+
+    #[error("This client already watches {0} rules, the most allowed at once; stop one with unwatch")]
+    Watches(usize),
+}
+
+/// What is known about an open session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    /// Name the client gave in its `initialize` request, once it sent one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub age_secs: u64,
+    /// Scans running now.
+    pub scans: usize,
+}
+
+struct Session {
+    client: Option<String>,
+    /// Who the session's quotas count against, once an authenticated request said.
+    identity: Option<String>,
+    opened: Instant,
+    scans: usize,
+    watches: usize,
+    /// Size of the matches the session remembers.
+    cache_bytes: usize,
+}
+
+/// A running scan or active watch counted against its client's quota until dropped.
+pub struct QuotaSlot {
+    sessions: Arc<SessionRegistry>,
+    session_id: String,
+    watch: bool,
+}
+
+impl Drop for QuotaSlot {
+    fn drop(&mut self) {
+        if let Some(session) = self.sessions.lock().get_mut(&self.session_id) {
+            let count = if self.watch { &mut session.watches } else { &mut session.scans };
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+/// Closes session `id` when dropped. The server of a session shares one among its clones, so a
+/// session is closed once its server is gone, also when its client disconnected before sending
+/// `notifications/initialized` and so never got the task that ends the session.
+pub struct SessionGuard {
+    sessions: Arc<SessionRegistry>,
+    id: String,
+}
+
+impl SessionGuard {
+    pub fn new(sessions: Arc<SessionRegistry>, id: &str) -> Self {
+        Self {
+            sessions,
+            id: id.to_string(),
+        }
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.close(&self.id);
+    }
+}
+
+/// The open sessions of this server process: one per HTTP client, or the single stdio client.
+///
+/// Each session's server registers itself when created and is closed once its client
+/// disconnects, at which point the server has freed what the session held. The registry also
+/// holds the per-client quotas and counts what each session uses against them.
+#[derive(Default)]
+pub struct SessionRegistry {
+    quotas: SessionQuotas,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl SessionRegistry {
+    pub fn new(quotas: SessionQuotas) -> Self {
+        Self {
+            quotas,
+            sessions: Mutex::default(),
+        }
+    }
+
+    pub fn quotas(&self) -> &SessionQuotas {
+        &self.quotas
+    }
+
+    /// Counts a scan of session `id` until the returned slot is dropped, unless its client
+    /// already runs as many scans as its quota allows.
+    pub fn start_scan(self: &Arc<Self>, id: &str) -> Result<QuotaSlot, QuotaError> {
+        self.take_slot(id, false, self.quotas.max_scans).map_err(QuotaError::Scans)
+    }
+
+    /// Counts a watch of session `id` until the returned slot is dropped, unless its client
+    /// already has as many watches as its quota allows.
+    pub fn start_watch(self: &Arc<Self>, id: &str) -> Result<QuotaSlot, QuotaError> {
+        self.take_slot(id, true, Some(self.quotas.max_watches)).map_err(QuotaError::Watches)
+    }
+
+    /// A slot of session `id` for a scan or a watch; the count of its client when that has `max`.
+    fn take_slot(self: &Arc<Self>, id: &str, watch: bool, max: Option<usize>) -> Result<QuotaSlot, usize> {
+        let mut sessions = self.lock();
+        let count = |session: &Session| if watch { session.watches } else { session.scans };
+        let used: usize = client_sessions(&sessions, id).map(|(_, session)| count(session)).sum();
+        if max.is_some_and(|max| used >= max) {
+            return Err(used);
+        }
+        if let Some(session) = sessions.get_mut(id) {
+            if watch {
+                session.watches += 1;
+            } else {
+                session.scans += 1;
+            }
+        }
+        Ok(QuotaSlot {
+            sessions: self.clone(),
+            session_id: id.to_string(),
+            watch,
+        })
+    }
+
+    /// How many bytes of matches session `id` may remember: the cache quota less what the other
+    /// sessions of its client remember; `None` for no limit.
+    pub fn cache_budget(&self, id: &str) -> Option<usize> {
+        let max = self.quotas.max_cache_bytes?;
+        let sessions = self.lock();
+        let others: usize = client_sessions(&sessions, id)
+            .filter(|(other, _)| *other != id)
+            .map(|(_, session)| session.cache_bytes)
+            .sum();
+        Some(max.saturating_sub(others))
+    }
+
+    /// Records that session `id` now remembers `bytes` of matches.
+    pub fn set_cache_bytes(&self, id: &str, bytes: usize) {
+        if let Some(session) = self.lock().get_mut(id) {
+            session.cache_bytes = bytes;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a new session and returns its id.
+    pub fn open(&self) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let session = Session {
+            client: None,
+            identity: None,
+            opened: Instant::now(),
+            scans: 0,
+            watches: 0,
+            cache_bytes: 0,
+        };
+        self.lock().insert(id.clone(), session);
+        id
+    }
+
+    /// Records the client name of session `id`.
+    pub fn set_client(&self, id: &str, client: &str) {
+        if let Some(session) = self.lock().get_mut(id) {
+            session.client = Some(client.to_string());
+        }
+    }
+
+    /// Records who session `id` belongs to, as its authenticated requests say: from then on its
+    /// usage counts against the quotas of `identity`, together with the client's other sessions.
+    pub fn set_identity(&self, id: &str, identity: &str) {
+        if let Some(session) = self.lock().get_mut(id) {
+            session.identity = Some(identity.to_string());
+        }
+    }
+
+    /// Removes session `id`; `false` if it was already closed.
+    pub fn close(&self, id: &str) -> bool {
+        self.lock().remove(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Open sessions, oldest first.
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let sessions = self.lock();
+        let mut list: Vec<(Instant, SessionInfo)> = sessions
+            .iter()
+            .map(|(id, session)| {
+                let info = SessionInfo {
+                    session_id: id.clone(),
+                    client: session.client.clone(),
+                    age_secs: session.opened.elapsed().as_secs(),
+                    scans: session.scans,
+                };
+                (session.opened, info)
+            })
+            .collect();
+        list.sort_by_key(|(opened, _)| *opened);
+        list.into_iter().map(|(_, info)| info).collect()
+    }
+}
+
+/// Session `id` and the other open sessions of the same client, by id; just `id` while it has no
+/// identity.
+fn client_sessions<'a>(
+    sessions: &'a HashMap<String, Session>,
+    id: &'a str,
+) -> impl Iterator<Item = (&'a String, &'a Session)> {
+    let identity = sessions.get(id).and_then(|session| session.identity.as_deref());
+    sessions
+        .iter()
+        .filter(move |(other, session)| *other == id || (identity.is_some() && session.identity.as_deref() == identity))
+}
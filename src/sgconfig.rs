@@ -0,0 +1,110 @@
+use serde_yaml::{Mapping, Value};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SgConfigError {
+    #[error("Invalid sgconfig YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("sgconfig root must be a mapping")]
+    NotAMapping,
+
+    #[error("Custom language '{0}' is already registered; pass replace=true to overwrite it")]
+    AlreadyRegistered(String),
+
+    #[error("Library '{0}' does not exist")]
+    MissingLibrary(PathBuf),
+
+    #[error("At least one file extension is required")]
+    NoExtensions,
+}
+
+/// A `customLanguages` entry of sgconfig.yaml.
+#[derive(Debug, Clone)]
+pub struct CustomLanguage {
+    pub library_path: String,
+    pub extensions: Vec<String>,
+    pub expando_char: Option<char>,
+}
+
+impl CustomLanguage {
+    /// Checks the entry before it is written; relative library paths resolve against the
+    /// directory holding the sgconfig file, as ast-grep resolves them.
+    pub fn validate(&self, config_dir: &Path) -> Result<(), SgConfigError> {
+        if self.extensions.iter().all(|e| e.trim().is_empty()) {
+            return Err(SgConfigError::NoExtensions);
+        }
+        let library = config_dir.join(&self.library_path);
+        if !library.is_file() {
+            return Err(SgConfigError::MissingLibrary(library));
+        }
+        Ok(())
+    }
+
+    fn to_yaml(&self) -> Value {
+        let mut entry = Mapping::new();
+        entry.insert("libraryPath".into(), self.library_path.clone().into());
+        let extensions = self
+            .extensions
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.'))
+            .filter(|e| !e.is_empty())
+            .map(|e| Value::from(e.to_string()))
+            .collect();
+        entry.insert("extensions".into(), Value::Sequence(extensions));
+        if let Some(expando) = self.expando_char {
+            entry.insert("expandoChar".into(), expando.to_string().into());
+        }
+        Value::Mapping(entry)
+    }
+}
+
+/// Returns `config_text` with `language` added under `customLanguages.<name>`.
+///
+/// Other settings are kept, but comments and formatting are not, since the file is
+/// re-serialized.
+pub fn add_custom_language(
+    config_text: &str,
+    name: &str,
+    language: &CustomLanguage,
+    replace: bool,
+) -> Result<String, SgConfigError> {
+    let mut config: Value = if config_text.trim().is_empty() {
+        Value::Mapping(Mapping::new())
+    } else {
+        serde_yaml::from_str(config_text)?
+    };
+    let root = config.as_mapping_mut().ok_or(SgConfigError::NotAMapping)?;
+
+    let languages = root
+        .entry("customLanguages".into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if languages.is_null() {
+        *languages = Value::Mapping(Mapping::new());
+    }
+    let languages = languages.as_mapping_mut().ok_or(SgConfigError::NotAMapping)?;
+    if languages.contains_key(name) && !replace {
+        return Err(SgConfigError::AlreadyRegistered(name.to_string()));
+    }
+    languages.insert(name.into(), language.to_yaml());
+
+    Ok(serde_yaml::to_string(&config)?)
+}
+
+/// File extensions of the `customLanguages` of `config_text`, without the dot; empty when the
+/// config cannot be parsed.
+pub fn custom_language_extensions(config_text: &str) -> Vec<String> {
+    let Ok(config) = serde_yaml::from_str::<Value>(config_text) else {
+        return Vec::new();
+    };
+    let Some(languages) = config.get("customLanguages").and_then(Value::as_mapping) else {
+        return Vec::new();
+    };
+    languages
+        .values()
+        .filter_map(|language| language.get("extensions")?.as_sequence())
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(|extension| extension.trim_start_matches('.').to_string())
+        .collect()
+}
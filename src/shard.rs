@@ -0,0 +1,95 @@
+use crate::filter::DEFAULT_EXCLUDES;
+use std::path::{Path, PathBuf};
+
+/// Number of files each shard of a sharded scan aims for, unless the shard threshold is lower.
+pub const SHARD_TARGET_FILES: usize = 10_000;
+
+/// A walk of `root` that visits what ast-grep does: hidden entries and those of ignore files
+/// (.gitignore, .ignore, .sgignore, git excludes and parent directories) are skipped, and so are
+/// [`DEFAULT_EXCLUDES`] unless vendored code was requested. Symbolic links are not followed, so a
+/// link cannot lead the walk out of `root` or around a cycle.
+pub fn walker(root: &Path, include_vendored: bool) -> ignore::WalkBuilder {
+    let mut walker = ignore::WalkBuilder::new(root);
+    walker.follow_links(false).add_custom_ignore_filename(".sgignore").sort_by_file_name(|a, b| a.cmp(b));
+    if !include_vendored {
+        walker.filter_entry(|entry| {
+            let vendored = DEFAULT_EXCLUDES.iter().any(|dir| entry.file_name() == *dir);
+            !(vendored && entry.file_type().is_some_and(|kind| kind.is_dir()))
+        });
+    }
+    walker
+}
+
+/// The regular files of a walk of `root` (see [`walker`]); symbolic links are left out.
+fn walked_files(root: &Path, include_vendored: bool) -> impl Iterator<Item = PathBuf> {
+    walker(root, include_vendored)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(ignore::DirEntry::into_path)
+}
+
+/// Counts the files under `root`, stopping as soon as the count exceeds `limit`.
+pub fn count_files(root: &Path, limit: usize, include_vendored: bool) -> usize {
+    walked_files(root, include_vendored).take(limit.saturating_add(1)).count()
+}
+
+/// Lists the files under `root` that the walk visits, in path order.
+pub fn list_files(root: &Path, include_vendored: bool) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walked_files(root, include_vendored).collect();
+    files.sort();
+    files
+}
+
+/// Splits `root` into shards of roughly `target` files each, as lists of paths to pass to a
+/// single ast-grep invocation.
+///
+/// Directories too big for one shard are split into their entries, recursively; the rest are
+/// packed in path order, so the same tree always yields the same shards.
+pub fn plan_shards(root: &Path, target: usize, include_vendored: bool) -> Vec<Vec<PathBuf>> {
+    let files = list_files(root, include_vendored);
+    let mut units = Vec::new();
+    collect_units(root, &files, target, &mut units);
+
+    let mut shards: Vec<Vec<PathBuf>> = Vec::new();
+    let mut current = Vec::new();
+    let mut current_files = 0;
+    for (path, files) in units {
+        if !current.is_empty() && current_files + files > target {
+            shards.push(std::mem::take(&mut current));
+            current_files = 0;
+        }
+        current.push(path);
+        current_files += files;
+    }
+    if !current.is_empty() {
+        shards.push(current);
+    }
+    shards
+}
+
+/// Adds the entries of `dir` as units of `files` (those under `dir`, in path order, so the files
+/// of each entry are adjacent), splitting entries with more than `target` files.
+fn collect_units(dir: &Path, files: &[PathBuf], target: usize, units: &mut Vec<(PathBuf, usize)>) {
+    let mut rest = files;
+    while let Some(first) = rest.first() {
+        let Some(name) = first.strip_prefix(dir).ok().and_then(|relative| relative.components().next()) else {
+            rest = &rest[1..];
+            continue;
+        };
+        let entry = dir.join(name);
+        if *first == entry {
+            units.push((entry, 1));
+            rest = &rest[1..];
+            continue;
+        }
+        let count = rest.iter().take_while(|path| path.starts_with(&entry)).count();
+        let (inside, after) = rest.split_at(count);
+        if count > target {
+            collect_units(&entry, inside, target, units);
+        } else {
+            units.push((entry, count));
+        }
+        rest = after;
+    }
+}
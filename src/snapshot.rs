@@ -0,0 +1,60 @@
+use crate::rulesession::RuleSession;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+use std::path::Path;
+
+/// Format version of session snapshots; snapshots of other versions are not imported.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The state of a session that `export_session` saves and `import_session` restores, so a long
+/// investigation survives a restart of the client (which ends a stdio server with it).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub version: u32,
+    /// When the snapshot was taken, UTC.
+    pub exported_at: String,
+    #[serde(default)]
+    pub rule_sessions: Vec<RuleSessionSnapshot>,
+    #[serde(default)]
+    pub watches: Vec<WatchSnapshot>,
+}
+
+/// A rule session with its id, so ids noted before the restart still work afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleSessionSnapshot {
+    pub rule_session_id: String,
+    #[serde(flatten)]
+    pub session: RuleSession,
+}
+
+/// A watch and its matches when the snapshot was taken, the baseline later changes are told
+/// against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchSnapshot {
+    pub watch_id: String,
+    pub yaml: String,
+    pub project_folder: String,
+    #[serde(default)]
+    pub include_vendored: bool,
+    #[serde(default)]
+    pub matches: Vec<Value>,
+}
+
+/// Writes `snapshot` to `path`, replacing an older file only once the new one is complete.
+pub fn save_snapshot(path: &Path, snapshot: &SessionSnapshot) -> io::Result<()> {
+    let partial = path.with_extension("json.tmp");
+    std::fs::write(&partial, serde_json::to_vec_pretty(snapshot)?)?;
+    std::fs::rename(&partial, path)
+}
+
+pub fn load_snapshot(path: &Path) -> io::Result<SessionSnapshot> {
+    let snapshot: SessionSnapshot = serde_json::from_slice(&std::fs::read(path)?)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("snapshot version {} is not supported (expected {})", snapshot.version, SNAPSHOT_VERSION),
+        ));
+    }
+    Ok(snapshot)
+}
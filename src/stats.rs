@@ -0,0 +1,125 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Process-wide runtime statistics, shared by every session and subprocess.
+pub struct ServerStats {
+    started_at: Instant,
+    tools: Mutex<BTreeMap<String, ToolStats>>,
+    subprocess_runs: AtomicU64,
+    subprocess_micros: AtomicU64,
+    active_subprocesses: AtomicUsize,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ToolStats {
+    pub calls: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub uptime_secs: u64,
+    pub tools: BTreeMap<String, ToolStats>,
+    pub total_calls: u64,
+    pub total_errors: u64,
+    pub subprocess_runs: u64,
+    pub avg_subprocess_ms: Option<f64>,
+    pub active_subprocesses: usize,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub cache_hit_rate: Option<f64>,
+}
+
+/// Tracks one running subprocess; records its duration when dropped.
+pub struct SubprocessGuard<'a> {
+    stats: &'a ServerStats,
+    started_at: Instant,
+}
+
+impl Drop for SubprocessGuard<'_> {
+    fn drop(&mut self) {
+        let micros = self.started_at.elapsed().as_micros() as u64;
+        self.stats.subprocess_micros.fetch_add(micros, Ordering::Relaxed);
+        self.stats.subprocess_runs.fetch_add(1, Ordering::Relaxed);
+        self.stats.active_subprocesses.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            tools: Mutex::new(BTreeMap::new()),
+            subprocess_runs: AtomicU64::new(0),
+            subprocess_micros: AtomicU64::new(0),
+            active_subprocesses: AtomicUsize::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_tool_call(&self, tool: &str, is_error: bool) {
+        let mut tools = self.tools.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = tools.entry(tool.to_string()).or_default();
+        entry.calls += 1;
+        if is_error {
+            entry.errors += 1;
+        }
+    }
+
+    pub fn subprocess_started(&self) -> SubprocessGuard<'_> {
+        self.active_subprocesses.fetch_add(1, Ordering::Relaxed);
+        SubprocessGuard {
+            stats: self,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        if hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let tools = self.tools.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let subprocess_runs = self.subprocess_runs.load(Ordering::Relaxed);
+        let subprocess_micros = self.subprocess_micros.load(Ordering::Relaxed);
+        let cache_hits = self.cache_hits.load(Ordering::Relaxed);
+        let cache_misses = self.cache_misses.load(Ordering::Relaxed);
+        let cache_lookups = cache_hits + cache_misses;
+
+        StatsSnapshot {
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            total_calls: tools.values().map(|t| t.calls).sum(),
+            total_errors: tools.values().map(|t| t.errors).sum(),
+            tools,
+            subprocess_runs,
+            avg_subprocess_ms: (subprocess_runs > 0)
+                .then(|| subprocess_micros as f64 / subprocess_runs as f64 / 1000.0),
+            active_subprocesses: self.active_subprocesses.load(Ordering::Relaxed),
+            cache_hits,
+            cache_misses,
+            cache_hit_rate: (cache_lookups > 0).then(|| cache_hits as f64 / cache_lookups as f64),
+        }
+    }
+}
+
+/// Returns the statistics shared by the whole server process.
+pub fn global() -> &'static ServerStats {
+    static STATS: OnceLock<ServerStats> = OnceLock::new();
+    STATS.get_or_init(ServerStats::new)
+}
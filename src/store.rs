@@ -0,0 +1,121 @@
+use crate::fingerprint::Fnv1a;
+use crate::stats;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// How many matches are remembered for `get_match_details` before the oldest are evicted.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Remembers matches returned in `headers` mode so their details can be fetched by id later.
+pub struct MatchStore {
+    capacity: usize,
+    /// Limit on the size of the remembered matches as JSON; `None` for no limit.
+    max_bytes: Option<usize>,
+    inner: Mutex<StoreInner>,
+}
+
+#[derive(Default)]
+struct StoreInner {
+    /// Matches with their size as JSON.
+    matches: HashMap<String, (Value, usize)>,
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl Default for MatchStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl MatchStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            max_bytes: None,
+            inner: Mutex::new(StoreInner::default()),
+        }
+    }
+
+    /// Also evicts the oldest entries while the remembered matches take more than `max_bytes`
+    /// as JSON.
+    pub fn with_max_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Size of the remembered matches as JSON.
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).bytes
+    }
+
+    /// Gives every match an `id` field and stores it, evicting the oldest entries when full.
+    ///
+    /// Ids are derived from the file, range and text, so the same match found again by a
+    /// repeated search keeps its id.
+    pub fn remember(&self, matches: &mut [Value]) {
+        self.remember_within(matches, self.max_bytes);
+    }
+
+    /// Like [`remember`](Self::remember), evicting the oldest entries while the remembered matches
+    /// take more than `max_bytes` (rather than the store's own limit) as JSON.
+    pub fn remember_within(&self, matches: &mut [Value], max_bytes: Option<usize>) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        for m in matches.iter_mut() {
+            let id = match_id(m);
+            if let Some(obj) = m.as_object_mut() {
+                obj.insert("id".to_string(), Value::String(id.clone()));
+            }
+            let size = serde_json::to_string(m).map_or(0, |json| json.len());
+            inner.bytes += size;
+            match inner.matches.insert(id.clone(), (m.clone(), size)) {
+                Some((_, replaced)) => inner.bytes -= replaced,
+                None => inner.order.push_back(id),
+            }
+        }
+        let mut evicted = 0;
+        while inner.order.len() > self.capacity || max_bytes.is_some_and(|max| inner.bytes > max) {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+            if let Some((_, size)) = inner.matches.remove(&oldest) {
+                inner.bytes -= size;
+                evicted += 1;
+            }
+        }
+        if evicted > 0 {
+            tracing::info!(evicted, "oldest remembered matches evicted; get_match_details no longer finds them");
+        }
+    }
+
+    /// Looks up a remembered match; lookups are counted in the server's cache statistics.
+    pub fn get(&self, id: &str) -> Option<Value> {
+        let found = self
+            .inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .matches
+            .get(id)
+            .map(|(m, _)| m.clone());
+        stats::global().record_cache_lookup(found.is_some());
+        found
+    }
+
+    /// Forgets all remembered matches.
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap_or_else(|e| e.into_inner()) = StoreInner::default();
+    }
+}
+
+/// Short id for a match: FNV-1a over its file, byte range and text.
+fn match_id(m: &Value) -> String {
+    let mut hash = Fnv1a::new();
+    hash.write(m.get("file").and_then(|v| v.as_str()).unwrap_or("").as_bytes());
+    for pointer in ["/range/byteOffset/start", "/range/byteOffset/end"] {
+        let offset = m.pointer(pointer).and_then(|v| v.as_u64()).unwrap_or(0);
+        hash.write(&offset.to_le_bytes());
+    }
+    hash.write(m.get("text").and_then(|v| v.as_str()).unwrap_or("").as_bytes());
+    format!("m{:012x}", hash.finish() & 0xffff_ffff_ffff)
+}
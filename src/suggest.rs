@@ -0,0 +1,269 @@
+use crate::ranges::byte_offset_of;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A node of an ast-grep `--debug-query=cst` dump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpNode {
+    pub kind: String,
+    pub field: Option<String>,
+    /// Zero-based line and character column.
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub children: Vec<DumpNode>,
+}
+
+/// Parses a syntax tree dump into its root node.
+///
+/// Dump lines look like `call_expression (0,0)-(0,14)` or `function: identifier (0,0)-(0,3)`,
+/// indented by two spaces per level. Lines without a range, such as the `Debug CST:` header,
+/// are skipped.
+pub fn parse_dump(dump: &str) -> Option<DumpNode> {
+    let mut stack: Vec<(usize, DumpNode)> = Vec::new();
+    let mut root = None;
+    for line in dump.lines() {
+        let content = line.trim_start();
+        let depth = (line.len() - content.len()) / 2;
+        let Some(node) = parse_dump_line(content) else {
+            continue;
+        };
+        while stack.last().is_some_and(|(d, _)| *d >= depth) {
+            let (_, done) = stack.pop().expect("stack is not empty");
+            attach(&mut stack, &mut root, done);
+        }
+        stack.push((depth, node));
+    }
+    while let Some((_, done)) = stack.pop() {
+        attach(&mut stack, &mut root, done);
+    }
+    root
+}
+
+fn attach(stack: &mut [(usize, DumpNode)], root: &mut Option<DumpNode>, node: DumpNode) {
+    match stack.last_mut() {
+        Some((_, parent)) => parent.children.push(node),
+        None => {
+            root.get_or_insert(node);
+        }
+    }
+}
+
+fn parse_dump_line(content: &str) -> Option<DumpNode> {
+    let (rest, range) = content.trim_end().rsplit_once(' ')?;
+    let (start, end) = range.split_once('-')?;
+    let (field, kind) = match rest.split_once(": ") {
+        Some((field, kind)) if !field.is_empty() && field.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+            (Some(field.to_string()), kind)
+        }
+        _ => (None, rest),
+    };
+    Some(DumpNode {
+        kind: kind.to_string(),
+        field,
+        start: parse_point(start)?,
+        end: parse_point(end)?,
+        children: Vec::new(),
+    })
+}
+
+fn parse_point(point: &str) -> Option<(usize, usize)> {
+    let (line, column) = point.strip_prefix('(')?.strip_suffix(')')?.split_once(',')?;
+    Some((line.trim().parse().ok()?, column.trim().parse().ok()?))
+}
+
+fn is_literal(kind: &str) -> bool {
+    kind.contains("string")
+        || kind.contains("number")
+        || kind.contains("integer")
+        || kind.contains("float")
+        || kind.ends_with("_literal")
+        || ["true", "false", "none", "null", "nil"].contains(&kind)
+}
+
+fn is_identifier(kind: &str) -> bool {
+    kind.ends_with("identifier") || ["name", "constant", "word"].contains(&kind)
+}
+
+/// Lists and blocks whose contents the most general level replaces with a multi metavariable.
+fn is_sequence(kind: &str) -> bool {
+    ["argument", "parameter", "block", "body", "declaration_list", "compound_statement"]
+        .iter()
+        .any(|part| kind.contains(part))
+}
+
+/// How far a suggested pattern generalizes the snippet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Generalization {
+    Literals,
+    Identifiers,
+    Structure,
+}
+
+impl Generalization {
+    pub const ALL: [Generalization; 3] = [Self::Literals, Self::Identifiers, Self::Structure];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Literals => "literals",
+            Self::Identifiers => "identifiers",
+            Self::Structure => "structure",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::Literals => "Literal values (strings, numbers, booleans) replaced by metavariables",
+            Self::Identifiers => "Identifiers and literals replaced; repeated names share a metavariable",
+            Self::Structure => "Also matches any argument/parameter lists and block bodies",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternSuggestion {
+    pub level: &'static str,
+    pub description: &'static str,
+    pub pattern: String,
+    /// Metavariable to the snippet text it replaced.
+    pub metavariables: BTreeMap<String, String>,
+    /// Whether ast-grep parsed the pattern without errors; `None` when not checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid: Option<bool>,
+}
+
+struct Generalizer<'a> {
+    code: &'a str,
+    level: Generalization,
+    replacements: Vec<(usize, usize, String)>,
+    names: BTreeMap<String, String>,
+    multi_names: BTreeMap<String, usize>,
+}
+
+impl Generalizer<'_> {
+    fn span(&self, node: &DumpNode) -> Option<(usize, usize)> {
+        let start = byte_offset_of(self.code, node.start)?;
+        let end = byte_offset_of(self.code, node.end)?;
+        (start < end).then_some((start, end))
+    }
+
+    /// Metavariable for `text`; the same text always gets the same name.
+    fn single(&mut self, text: &str) -> String {
+        if let Some((name, _)) = self.names.iter().find(|(_, t)| t.as_str() == text) {
+            return name.clone();
+        }
+        let singles = self.names.keys().filter(|n| !n.starts_with("$$$")).count();
+        let name = format!("${}", metavariable_name(singles));
+        self.names.insert(name.clone(), text.to_string());
+        name
+    }
+
+    fn multi(&mut self, base: &str, text: &str) -> String {
+        let count = self.multi_names.entry(base.to_string()).or_default();
+        *count += 1;
+        let name = match *count {
+            1 => format!("$$${}", base),
+            n => format!("$$${}{}", base, n),
+        };
+        self.names.insert(name.clone(), text.to_string());
+        name
+    }
+
+    fn visit(&mut self, node: &DumpNode) {
+        let Some((start, end)) = self.span(node) else {
+            return;
+        };
+        let code = self.code;
+        let text = &code[start..end];
+
+        if is_literal(&node.kind) {
+            let name = self.single(text);
+            self.replacements.push((start, end, name));
+            return;
+        }
+        if node.children.is_empty() && self.level >= Generalization::Identifiers && is_identifier(&node.kind) {
+            let name = self.single(text);
+            self.replacements.push((start, end, name));
+            return;
+        }
+        if self.level >= Generalization::Structure && is_sequence(&node.kind) {
+            let base = if node.kind.contains("argument") || node.kind.contains("parameter") {
+                "ARGS"
+            } else {
+                "BODY"
+            };
+            let delimited = match (node.children.first(), node.children.last()) {
+                (Some(open), Some(close)) if node.children.len() >= 2 => {
+                    ["(", "{", "["].contains(&open.kind.as_str()) && [")", "}", "]"].contains(&close.kind.as_str())
+                }
+                _ => false,
+            };
+            if delimited {
+                let inner = (
+                    self.span(&node.children[0]).map(|(_, e)| e),
+                    self.span(&node.children[node.children.len() - 1]).map(|(s, _)| s),
+                );
+                if let (Some(inner_start), Some(inner_end)) = inner {
+                    if inner_start < inner_end && !code[inner_start..inner_end].trim().is_empty() {
+                        let name = self.multi(base, code[inner_start..inner_end].trim());
+                        self.replacements.push((inner_start, inner_end, name));
+                    }
+                    return;
+                }
+            } else if base == "BODY" {
+                let name = self.multi(base, text);
+                self.replacements.push((start, end, name));
+                return;
+            }
+        }
+        for child in &node.children {
+            self.visit(child);
+        }
+    }
+}
+
+/// `A`..`Z`, then `A1`..`Z1`, and so on.
+fn metavariable_name(index: usize) -> String {
+    let letter = (b'A' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => letter.to_string(),
+        round => format!("{}{}", letter, round),
+    }
+}
+
+/// Patterns generalizing `code` at each [`Generalization`] level, from the most specific, given
+/// its parsed syntax tree. Levels that would repeat the previous pattern are left out.
+pub fn suggest_patterns(code: &str, root: &DumpNode) -> Vec<PatternSuggestion> {
+    let mut suggestions: Vec<PatternSuggestion> = Vec::new();
+    for level in Generalization::ALL {
+        let mut generalizer = Generalizer {
+            code,
+            level,
+            replacements: Vec::new(),
+            names: BTreeMap::new(),
+            multi_names: BTreeMap::new(),
+        };
+        generalizer.visit(root);
+        if generalizer.replacements.is_empty() {
+            continue;
+        }
+
+        let mut pattern = code.to_string();
+        let mut replacements = generalizer.replacements;
+        replacements.sort_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+        for (start, end, name) in replacements {
+            pattern.replace_range(start..end, &name);
+        }
+        let pattern = pattern.trim().to_string();
+        if suggestions.last().is_some_and(|previous| previous.pattern == pattern) {
+            continue;
+        }
+        suggestions.push(PatternSuggestion {
+            level: level.label(),
+            description: level.description(),
+            pattern,
+            metavariables: generalizer.names,
+            valid: None,
+        });
+    }
+    suggestions
+}
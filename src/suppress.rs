@@ -0,0 +1,94 @@
+use crate::header::{format_header, CommentStyle};
+
+/// The comment directive ast-grep honours on the line above a finding (or on its line).
+pub const IGNORE_DIRECTIVE: &str = "ast-grep-ignore";
+
+/// A suppression comment added to a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suppression {
+    /// The file's new content.
+    pub source: String,
+    /// Lines added or changed above the finding, as written.
+    pub lines: Vec<String>,
+    /// 1-based line of the finding after the insertion.
+    pub line: usize,
+}
+
+/// The rule ids of an `ast-grep-ignore` comment in `line`: `Some(vec![])` for a bare directive,
+/// which suppresses every rule, and `None` when the line is not such a comment.
+fn directive_rule_ids(line: &str, style: CommentStyle) -> Option<Vec<String>> {
+    let mut text = line.trim();
+    if let Some(prefix) = style.line.filter(|prefix| text.starts_with(prefix)) {
+        text = &text[prefix.len()..];
+    } else if let Some((open, close)) = style.block.filter(|(open, _)| text.starts_with(open)) {
+        text = text[open.len()..].trim_end().trim_end_matches(close);
+    } else {
+        return None;
+    }
+    let rest = text.trim().strip_prefix(IGNORE_DIRECTIVE)?;
+    let ids = match rest.trim().strip_prefix(':') {
+        Some(ids) => ids.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect(),
+        None if rest.trim().is_empty() => Vec::new(),
+        None => return None,
+    };
+    Some(ids)
+}
+
+fn directive(rule_ids: &[String], style: CommentStyle) -> String {
+    let text = format!("{}: {}", IGNORE_DIRECTIVE, rule_ids.join(", "));
+    match (style.line, style.block) {
+        (Some(prefix), _) => format!("{} {}", prefix, text),
+        (_, Some((open, close))) => format!("{} {} {}", open, text, close),
+        (None, None) => text,
+    }
+}
+
+/// Suppresses `rule_id` for the finding on 1-based `line` of `source`: an `ast-grep-ignore`
+/// comment naming the rule is put on the line above, indented like the finding, with the
+/// justification in a comment above it. An `ast-grep-ignore` comment already there gets the rule
+/// id added instead. CRLF line endings are kept.
+pub fn insert_suppression(
+    source: &str,
+    line: usize,
+    rule_id: &str,
+    justification: &str,
+    style: CommentStyle,
+) -> Result<Suppression, String> {
+    if rule_id.is_empty() || rule_id.contains(|c: char| c == ',' || c.is_whitespace()) {
+        return Err(format!("Invalid rule_id '{}': it must be a single rule id.", rule_id));
+    }
+    if justification.trim().is_empty() {
+        return Err("justification must not be empty".to_string());
+    }
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<&str> = source.split_inclusive('\n').collect();
+    if line == 0 || line > lines.len() {
+        return Err(format!("line {} is not in the file ({} lines).", line, lines.len()));
+    }
+
+    let target = lines[line - 1];
+    let indent = &target[..target.len() - target.trim_start().len()];
+    let existing = (line > 1).then(|| directive_rule_ids(lines[line - 2], style)).flatten();
+    if let Some(ids) = &existing {
+        if ids.is_empty() || ids.iter().any(|id| id == rule_id) {
+            return Err(format!("The finding on line {} is already suppressed for {}.", line, rule_id));
+        }
+    }
+
+    let mut added: Vec<String> =
+        format_header(justification, style).lines().map(|l| format!("{}{}", indent, l)).collect();
+    let mut rule_ids = existing.clone().unwrap_or_default();
+    rule_ids.push(rule_id.to_string());
+    added.push(format!("{}{}", indent, directive(&rule_ids, style)));
+
+    // The justification goes above an existing directive, which is rewritten in place
+    let at = if existing.is_some() { line - 2 } else { line - 1 };
+    let replaced = if existing.is_some() { 1 } else { 0 };
+    let new_lines: Vec<String> = added.iter().map(|l| format!("{}{}", l, newline)).collect();
+    lines.splice(at..at + replaced, new_lines.iter().map(String::as_str));
+    Ok(Suppression {
+        source: lines.concat(),
+        line: line + added.len() - replaced,
+        lines: added,
+    })
+}
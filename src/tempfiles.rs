@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Prefix of the directories [`SessionTempDir`] creates in the system temp directory.
+pub const TEMP_DIR_PREFIX: &str = "ast-grep-mcp-";
+
+/// Session directories not yet removed, so a shutdown that skips destructors can still remove them.
+fn live_dirs() -> &'static Mutex<HashSet<PathBuf>> {
+    static LIVE: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+    LIVE.get_or_init(Default::default)
+}
+
+/// A private directory for the temp files of one session (inline rule files, spilled input,
+/// reports), readable only by the current user and removed with everything in it when dropped.
+///
+/// Features that need temp files create them here instead of in the shared temp directory, so
+/// they cannot collide with or be read by other users, and nothing outlives the session.
+#[derive(Debug)]
+pub struct SessionTempDir {
+    path: PathBuf,
+}
+
+impl SessionTempDir {
+    /// Creates a new session directory in the system temp directory.
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(&std::env::temp_dir())
+    }
+
+    pub fn new_in(parent: &Path) -> io::Result<Self> {
+        let path = parent.join(format!("{}{}-{}", TEMP_DIR_PREFIX, std::process::id(), uuid::Uuid::new_v4().simple()));
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&path)?;
+        live_dirs().lock().unwrap_or_else(|e| e.into_inner()).insert(path.clone());
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to a new file with a unique name ending in `suffix` (e.g. `.yml`),
+    /// readable and writable only by the current user.
+    pub fn create_file(&self, suffix: &str, contents: &[u8]) -> io::Result<PathBuf> {
+        let path = self.path.join(format!("{}{}", uuid::Uuid::new_v4().simple(), suffix));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path)?;
+        file.write_all(contents)?;
+        Ok(path)
+    }
+}
+
+impl Drop for SessionTempDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            tracing::warn!(path = %self.path.display(), error = %e, "cannot remove session temp directory");
+        }
+        live_dirs().lock().unwrap_or_else(|e| e.into_inner()).remove(&self.path);
+    }
+}
+
+/// Removes every session directory still alive, for shutdown paths that exit the process
+/// without running destructors.
+pub fn remove_all_session_dirs() {
+    for path in live_dirs().lock().unwrap_or_else(|e| e.into_inner()).drain() {
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::language::language_alias;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const DEFAULT_TAGS: &[&str] = &["TODO", "FIXME", "HACK", "XXX"];
+
+/// Languages searched when no language is given.
+pub const TODO_LANGUAGES: &[&str] = &[
+    "bash", "c", "cpp", "csharp", "css", "go", "java", "javascript", "lua", "php", "python", "ruby", "rust", "tsx",
+    "typescript", "yaml",
+];
+
+/// Comment node kinds of `language`; most grammars call them `comment`.
+pub fn comment_kinds(language: &str) -> &'static [&'static str] {
+    match language_alias(language).as_str() {
+        "rust" | "java" => &["line_comment", "block_comment"],
+        _ => &["comment"],
+    }
+}
+
+/// Inline rules (one document per language) matching comments that mention one of `tags`.
+pub fn todo_rules(languages: &[&str], tags: &[String]) -> String {
+    let regex = format!("\\b({})\\b", tags.join("|"));
+    languages
+        .iter()
+        .map(|language| {
+            let kinds: Vec<String> = comment_kinds(language)
+                .iter()
+                .map(|kind| format!("    - kind: {}", kind))
+                .collect();
+            format!(
+                "id: todo-{}\nlanguage: {}\nrule:\n  regex: '{}'\n  any:\n{}\n",
+                language,
+                language,
+                regex,
+                kinds.join("\n")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("---\n")
+}
+
+/// A TODO-style note found in a comment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TodoItem {
+    pub file: String,
+    /// 1-based line of the tag.
+    pub line: usize,
+    pub tag: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// A `YYYY-MM-DD` date mentioned with the tag.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    pub text: String,
+}
+
+/// Extracts the tagged notes from comment matches, one per tagged line, in file and line order.
+///
+/// The owner comes from `TAG(owner)` or an `@owner` mention; a date in parentheses is not
+/// taken for an owner.
+pub fn extract_todos(matches: &[Value], tags: &[String]) -> Vec<TodoItem> {
+    let mut items = Vec::new();
+    for m in matches {
+        let file = m.get("file").and_then(Value::as_str).unwrap_or_default();
+        let text = m.get("text").and_then(Value::as_str).unwrap_or_default();
+        let first_line = m.pointer("/range/start/line").and_then(Value::as_u64).unwrap_or(0) as usize;
+        for (offset, line) in text.lines().enumerate() {
+            if let Some(mut item) = parse_todo_line(line, tags) {
+                item.file = file.to_string();
+                item.line = first_line + offset + 1;
+                items.push(item);
+            }
+        }
+    }
+    items.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+    items
+}
+
+fn parse_todo_line(line: &str, tags: &[String]) -> Option<TodoItem> {
+    let (position, tag) = tags
+        .iter()
+        .filter_map(|tag| find_word(line, tag).map(|position| (position, tag)))
+        .min()?;
+    let mut rest = line[position + tag.len()..].trim_start();
+
+    let mut owner = None;
+    let mut date = find_date(line);
+    if let Some(inner) = rest.strip_prefix('(').and_then(|r| r.split_once(')')) {
+        let (inside, after) = inner;
+        for part in inside.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if is_date(part) {
+                date = Some(part.to_string());
+            } else if owner.is_none() {
+                owner = Some(part.trim_start_matches('@').to_string());
+            }
+        }
+        rest = after;
+    }
+    if owner.is_none() {
+        owner = rest
+            .split_whitespace()
+            .find_map(|word| word.strip_prefix('@'))
+            .map(|name| name.trim_end_matches(|c: char| !c.is_alphanumeric()).to_string())
+            .filter(|name| !name.is_empty());
+    }
+
+    let text = rest
+        .trim_start_matches([':', '-', ' '])
+        .trim_end()
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim_end()
+        .to_string();
+    Some(TodoItem {
+        file: String::new(),
+        line: 0,
+        tag: tag.clone(),
+        owner,
+        date,
+        text,
+    })
+}
+
+/// Byte position of `word` in `line` as a whole word.
+fn find_word(line: &str, word: &str) -> Option<usize> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    line.match_indices(word).map(|(i, _)| i).find(|&i| {
+        let before = line[..i].chars().next_back();
+        let after = line[i + word.len()..].chars().next();
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+fn is_date(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+fn find_date(line: &str) -> Option<String> {
+    line.char_indices()
+        .filter_map(|(i, _)| line.get(i..i + 10))
+        .find(|candidate| is_date(candidate))
+        .map(str::to_string)
+}
@@ -0,0 +1,285 @@
+use crate::approval::APPROVAL_SCOPES;
+use crate::callgraph::CALL_GRAPH_FORMATS;
+use crate::command::run_command;
+use crate::cooccur::{COOCCURRENCE_MODES, COOCCURRENCE_SCOPES};
+use crate::dialect::DIALECTS;
+use crate::encoding::TEXT_SOURCES;
+use crate::eol::LINE_ENDINGS;
+use crate::format::OUTPUT_FORMATS;
+use crate::dump::DUMP_FORMATS;
+use crate::relax::FALLBACKS;
+use crate::todo::TODO_LANGUAGES;
+use rmcp::model::{JsonObject, Meta, Tool};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tools that change files on disk: left out in read-only mode, and needing the write scope
+/// with OAuth tokens.
+pub const WRITE_TOOLS: &[&str] = &[
+    "apply_rewrite",
+    "export_matches",
+    "export_session",
+    "init_sgconfig",
+    "install_grammar",
+    "license_header",
+    "register_custom_language",
+    "suppress_finding",
+    "undo_rewrite",
+];
+
+/// Tools that run the ast-grep CLI, which are not listed while it is not installed.
+pub const AST_GREP_TOOLS: &[&str] = &[
+    "apply_rewrite",
+    "benchmark_rule",
+    "call_graph",
+    "code_metrics",
+    "explain_query",
+    "export_matches",
+    "find_code",
+    "find_code_at_rev",
+    "find_code_by_rule",
+    "find_cooccurrence",
+    "find_missing",
+    "find_todos",
+    "install_grammar",
+    "lint_rule",
+    "node_kind_stats",
+    "outline_file",
+    "pattern_playground",
+    "pattern_trend",
+    "preview_match_rewrite",
+    "preview_rewrite",
+    "refine_pattern",
+    "register_custom_language",
+    "resume_scan",
+    "rewrite_code",
+    "rule_effectiveness",
+    "submit_scan",
+    "suggest_pattern",
+    "test_match_code_rule",
+    "test_rule_session",
+    "watch_rule",
+];
+
+/// How long the result of looking for ast-grep is reused before looking again, so sessions
+/// notice an install or uninstall without each of them running `ast-grep --version` all the time.
+pub const AST_GREP_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest `ast-grep --version` may take.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Version line of the installed ast-grep (e.g. `ast-grep 0.38.1`), `None` when it cannot be run.
+/// The answer is shared by all sessions and refreshed after [`AST_GREP_CHECK_INTERVAL`].
+pub async fn ast_grep_version() -> Option<String> {
+    static CHECKED: Mutex<Option<(Instant, Option<String>)>> = Mutex::new(None);
+    if let Some((checked, version)) = CHECKED.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if checked.elapsed() < AST_GREP_CHECK_INTERVAL {
+            return version.clone();
+        }
+    }
+    let args = ["ast-grep".to_string(), "--version".to_string()];
+    let version = run_command(&args, None, Some(VERSION_TIMEOUT))
+        .await
+        .ok()
+        .map(|result| result.stdout.trim().to_string());
+    *CHECKED.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), version.clone()));
+    version
+}
+
+/// Why tools that write files are not offered in read-only mode.
+pub const READ_ONLY_REASON: &str = "the server runs in read-only mode";
+
+/// Why `tool` is not offered, if it is not: `None` when it is available.
+pub fn unavailable_reason(tool: &str, read_only: bool, ast_grep_installed: bool) -> Option<&'static str> {
+    if read_only && WRITE_TOOLS.contains(&tool) {
+        Some(READ_ONLY_REASON)
+    } else if !ast_grep_installed && AST_GREP_TOOLS.contains(&tool) {
+        Some("ast-grep is not installed or not on PATH")
+    } else {
+        None
+    }
+}
+
+/// A tool, or a parameter of a tool, that is going away. Deprecated tools and parameters keep
+/// working, so existing prompts do not break overnight, but say what replaces them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deprecation {
+    pub tool: &'static str,
+    /// The deprecated parameter; `None` when the whole tool is deprecated.
+    pub parameter: Option<&'static str>,
+    /// Server version that deprecated it.
+    pub since: &'static str,
+    /// What to use instead.
+    pub replacement: &'static str,
+}
+
+/// Deprecated tools and parameters of this server; `since` is the released version that
+/// deprecated them.
+pub const DEPRECATIONS: &[Deprecation] = &[];
+
+/// Key of a tool's `_meta` describing its deprecation (`since`, `replacement`), or that of its
+/// parameters (`parameters`, by name).
+pub const DEPRECATION_META_KEY: &str = "deprecated";
+
+impl Deprecation {
+    fn hint(&self) -> String {
+        format!("Deprecated since {}: use `{}` instead.", self.since, self.replacement)
+    }
+
+    fn meta(&self) -> Value {
+        json!({ "since": self.since, "replacement": self.replacement })
+    }
+}
+
+/// Marks `tool` as described by `deprecation`: the hint goes in front of the description of the
+/// tool (or of the parameter, which also gets `"deprecated": true` in the input schema), and the
+/// details into the tool's `_meta`. With `hide`, the parameter is removed from the schema instead;
+/// hiding whole tools is up to the caller.
+pub fn deprecate(tool: &mut Tool, deprecation: &Deprecation, hide: bool) {
+    let meta = tool.meta.get_or_insert_with(Meta::new);
+    let Some(parameter) = deprecation.parameter else {
+        let description = tool.description.as_deref().unwrap_or_default().trim_start();
+        tool.description = Some(format!("{}\n{}", deprecation.hint(), description).into());
+        meta.insert(DEPRECATION_META_KEY.to_string(), deprecation.meta());
+        return;
+    };
+
+    let schema = std::sync::Arc::make_mut(&mut tool.input_schema);
+    if hide {
+        if let Some(Value::Object(properties)) = schema.get_mut("properties") {
+            properties.remove(parameter);
+        }
+        if let Some(Value::Array(required)) = schema.get_mut("required") {
+            required.retain(|name| name != parameter);
+        }
+        return;
+    }
+    if let Some(Value::Object(property)) = schema.get_mut("properties").and_then(|p| p.get_mut(parameter)) {
+        let description = property.get("description").and_then(Value::as_str).unwrap_or_default();
+        let description = format!("{} {}", deprecation.hint(), description).trim_end().to_string();
+        property.insert("description".to_string(), Value::String(description));
+        property.insert("deprecated".to_string(), Value::Bool(true));
+    }
+    let entry = meta.entry(DEPRECATION_META_KEY.to_string()).or_insert_with(|| json!({ "parameters": {} }));
+    if let Some(Value::Object(parameters)) = entry.get_mut("parameters") {
+        parameters.insert(parameter.to_string(), deprecation.meta());
+    }
+}
+
+/// Deprecations a call of `tool` with `arguments` runs into, to warn the client about.
+pub fn call_deprecations<'a>(
+    deprecations: &'a [Deprecation],
+    tool: &str,
+    arguments: Option<&JsonObject>,
+) -> Vec<&'a Deprecation> {
+    deprecations
+        .iter()
+        .filter(|deprecation| deprecation.tool == tool)
+        .filter(|deprecation| match deprecation.parameter {
+            Some(parameter) => arguments.is_some_and(|arguments| arguments.contains_key(parameter)),
+            None => true,
+        })
+        .collect()
+}
+
+/// What a string parameter takes, shown in tool input schemas so clients can validate and complete
+/// calls before sending them.
+#[derive(Debug, Clone, Copy)]
+pub enum ParameterValues {
+    /// The only values accepted, as an `enum`; a default outside them (usually "") is added.
+    OneOf(&'static [&'static str]),
+    /// Typical values, as `examples`, for parameters that also accept others.
+    Examples(&'static [&'static str]),
+    /// The languages of the active sgconfig, built-in and custom, as `examples`: aliases such as
+    /// `ts`, and languages registered after the tools were listed, are accepted too.
+    Languages,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParameterHint {
+    /// The tool with the parameter; `None` for every tool with a parameter of that name.
+    pub tool: Option<&'static str>,
+    pub parameter: &'static str,
+    pub values: ParameterValues,
+}
+
+/// Values of string parameters, from the lists the tools check arguments against. Entries for a
+/// tool come before those for every tool, as the first matching entry is used.
+pub const PARAMETER_HINTS: &[ParameterHint] = &[
+    ParameterHint {
+        tool: Some("dump_syntax_tree"),
+        parameter: "format",
+        values: ParameterValues::OneOf(DUMP_FORMATS),
+    },
+    ParameterHint { tool: Some("call_graph"), parameter: "format", values: ParameterValues::OneOf(CALL_GRAPH_FORMATS) },
+    ParameterHint {
+        tool: Some("apply_rewrite"),
+        parameter: "approve",
+        values: ParameterValues::OneOf(APPROVAL_SCOPES),
+    },
+    ParameterHint {
+        tool: Some("find_cooccurrence"),
+        parameter: "mode",
+        values: ParameterValues::OneOf(COOCCURRENCE_MODES),
+    },
+    ParameterHint {
+        tool: Some("find_cooccurrence"),
+        parameter: "scope",
+        values: ParameterValues::OneOf(COOCCURRENCE_SCOPES),
+    },
+    ParameterHint {
+        tool: Some("find_todos"),
+        parameter: "language",
+        values: ParameterValues::Examples(TODO_LANGUAGES),
+    },
+    ParameterHint { tool: None, parameter: "output_format", values: ParameterValues::OneOf(OUTPUT_FORMATS) },
+    ParameterHint { tool: None, parameter: "fallback", values: ParameterValues::OneOf(FALLBACKS) },
+    ParameterHint { tool: None, parameter: "text_source", values: ParameterValues::OneOf(TEXT_SOURCES) },
+    ParameterHint { tool: None, parameter: "line_endings", values: ParameterValues::OneOf(LINE_ENDINGS) },
+    ParameterHint { tool: None, parameter: "dialect", values: ParameterValues::OneOf(DIALECTS) },
+    ParameterHint { tool: None, parameter: "language", values: ParameterValues::Languages },
+];
+
+/// Adds the values of [`PARAMETER_HINTS`] to the properties of `tool`'s input schema: `enum` for
+/// closed sets, `examples` otherwise. `languages` are the supported languages.
+pub fn add_parameter_values(tool: &mut Tool, languages: &[String]) {
+    let schema = std::sync::Arc::make_mut(&mut tool.input_schema);
+    let Some(Value::Object(properties)) = schema.get_mut("properties") else {
+        return;
+    };
+    for (name, property) in properties.iter_mut() {
+        let hint = PARAMETER_HINTS
+            .iter()
+            .filter(|hint| hint.parameter == name)
+            .find(|hint| hint.tool.is_none_or(|only| only == tool.name));
+        let (Some(hint), Some(property)) = (hint, property.as_object_mut()) else {
+            continue;
+        };
+        match hint.values {
+            ParameterValues::OneOf(values) => {
+                let mut accepted: Vec<Value> = values.iter().map(|&value| Value::from(value)).collect();
+                if let Some(default) = property.get("default").filter(|default| !accepted.contains(default)) {
+                    accepted.insert(0, default.clone());
+                }
+                property.insert("enum".to_string(), Value::Array(accepted));
+            }
+            ParameterValues::Examples(values) => {
+                property.insert("examples".to_string(), json!(values));
+            }
+            ParameterValues::Languages => {
+                property.insert("examples".to_string(), json!(languages));
+            }
+        }
+    }
+}
+
+/// `'a', 'b' or 'c'`, for error messages listing the accepted values.
+pub fn one_of(values: &[&str]) -> String {
+    let quoted: Vec<String> = values.iter().map(|value| format!("'{}'", value)).collect();
+    match quoted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
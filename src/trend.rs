@@ -0,0 +1,159 @@
+use crate::command::{run_command_bytes, CommandError};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// `git log` format of the commits `pattern_trend` samples: hash, committer date and subject.
+pub const COMMIT_FORMAT: &str = "--format=%H%x1f%cI%x1f%s";
+
+/// Most revisions one `pattern_trend` call scans, since each one is a checkout and a full scan.
+pub const MAX_REVISIONS: usize = 20;
+
+/// A detached worktree `pattern_trend` checks a revision out into. It is removed again when
+/// dropped, so a call that is cancelled between adding and removing it does not leave it
+/// registered in the repository's `.git/worktrees` with its directory behind.
+pub struct Worktree {
+    repository: String,
+    pub path: PathBuf,
+    removed: bool,
+}
+
+impl Worktree {
+    /// Adds a worktree of the repository of `repository` (a directory inside it) at `path`, with
+    /// `commit` checked out.
+    pub async fn add(
+        repository: &str,
+        path: PathBuf,
+        commit: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Self, CommandError> {
+        let worktree = Worktree { repository: repository.to_string(), path, removed: false };
+        let path = worktree.path.display().to_string();
+        run_command_bytes(&worktree.git(&["worktree", "add", "--detach", &path, commit]), None, timeout).await?;
+        Ok(worktree)
+    }
+
+    /// Removes the worktree with `git worktree remove --force`, or else by deleting its directory
+    /// and `git worktree prune`.
+    pub async fn remove(mut self, timeout: Option<Duration>) {
+        self.removed = true;
+        let path = self.path.display().to_string();
+        let removed = run_command_bytes(&self.git(&["worktree", "remove", "--force", &path]), None, timeout).await;
+        if let Err(e) = removed {
+            tracing::warn!(worktree = %path, error = %e, "cannot remove pattern_trend worktree; pruning it");
+            let _ = std::fs::remove_dir_all(&self.path);
+            let _ = run_command_bytes(&self.git(&["worktree", "prune"]), None, timeout).await;
+        }
+    }
+
+    fn git(&self, args: &[&str]) -> Vec<String> {
+        let mut command = vec!["git".to_string(), "-C".to_string(), self.repository.clone()];
+        command.extend(args.iter().map(|arg| arg.to_string()));
+        command
+    }
+}
+
+impl Drop for Worktree {
+    /// Removes a worktree [`Worktree::remove`] did not, the same way. Git runs on a thread of its
+    /// own, as this may drop on the async runtime.
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        let path = self.path.clone();
+        let commands = [
+            self.git(&["worktree", "remove", "--force", &path.display().to_string()]),
+            self.git(&["worktree", "prune"]),
+        ];
+        std::thread::spawn(move || {
+            let run = |args: &[String]| {
+                let output = std::process::Command::new(&args[0]).args(&args[1..]).output();
+                output.is_ok_and(|output| output.status.success())
+            };
+            if !run(&commands[0]) {
+                let _ = std::fs::remove_dir_all(&path);
+                if !run(&commands[1]) {
+                    tracing::warn!(worktree = %path.display(), "cannot remove pattern_trend worktree");
+                }
+            }
+        });
+    }
+}
+
+/// A commit as `git log` printed it with [`COMMIT_FORMAT`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Commit {
+    pub commit: String,
+    pub date: String,
+    pub subject: String,
+}
+
+/// Parses `git log` output written with [`COMMIT_FORMAT`], newest first as git prints it.
+pub fn parse_commits(stdout: &str) -> Vec<Commit> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\u{1f}');
+            let commit = fields.next().filter(|hash| !hash.is_empty())?.to_string();
+            let date = fields.next()?.to_string();
+            let subject = fields.next().unwrap_or_default().to_string();
+            Some(Commit { commit, date, subject })
+        })
+        .collect()
+}
+
+/// Every `step`th of `commits` (newest first) starting from the newest, at most `count` of them,
+/// oldest first.
+pub fn sample_commits(commits: Vec<Commit>, count: usize, step: usize) -> Vec<Commit> {
+    let mut sampled: Vec<Commit> = commits.into_iter().step_by(step.max(1)).take(count).collect();
+    sampled.reverse();
+    sampled
+}
+
+/// Match counts of a pattern at one revision.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrendPoint {
+    /// The revision as asked for, or the commit hash for sampled history.
+    pub revision: String,
+    #[serde(flatten)]
+    pub commit: Commit,
+    pub matches: usize,
+    /// Files with at least one match.
+    pub files: usize,
+}
+
+impl TrendPoint {
+    pub fn new(revision: String, commit: Commit, matches: &[Value]) -> Self {
+        let files: BTreeSet<&str> = matches.iter().filter_map(|m| m.get("file").and_then(Value::as_str)).collect();
+        Self { revision, commit, matches: matches.len(), files: files.len() }
+    }
+}
+
+/// One line on how the match count changed from the oldest to the newest of `points`.
+pub fn trend_summary(points: &[TrendPoint]) -> String {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return "No revisions were scanned.".to_string();
+    };
+    let short = |point: &TrendPoint| point.commit.commit.chars().take(10).collect::<String>();
+    let change = last.matches as i64 - first.matches as i64;
+    let direction = match change {
+        0 => "unchanged",
+        c if c < 0 => "shrinking",
+        _ => "growing",
+    };
+    format!(
+        "Usage is {}: {} matches in {} files at {} ({}), {} matches in {} files at {} ({}), {:+}.",
+        direction,
+        first.matches,
+        first.files,
+        short(first),
+        first.commit.date,
+        last.matches,
+        last.files,
+        short(last),
+        last.commit.date,
+        change
+    )
+}
@@ -0,0 +1,410 @@
+use crate::filter::DEFAULT_EXCLUDES;
+use crate::session::QuotaSlot;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+
+/// How many rules can be watched at once.
+pub const MAX_WATCHES: usize = 32;
+
+/// Logger name of the notifications a watch sends.
+pub const WATCH_LOGGER: &str = "watch_rule";
+
+/// Logger name of the notifications sent when the sgconfig file or its rules change.
+pub const CONFIG_LOGGER: &str = "sgconfig";
+
+/// How long a watch waits for file events to settle before re-evaluating the changed files.
+pub const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Longest a batch of changes waits for events to settle, so a steady stream of changes is still
+/// evaluated now and then.
+pub const WATCH_MAX_BATCH_DELAY: Duration = Duration::from_secs(5);
+
+/// Changed files passed to one ast-grep run.
+pub const WATCH_RESCAN_CHUNK: usize = 200;
+
+/// Above this many changed files (e.g. after a branch switch) one scan of the whole folder is
+/// cheaper than scanning the files in chunks.
+pub const WATCH_FULL_RESCAN_THRESHOLD: usize = 1000;
+
+/// How to re-evaluate a batch of changed paths.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RescanPlan {
+    /// None of the changed paths that still exist can match the rule.
+    Nothing,
+    /// Scan these groups of paths, one ast-grep run per group.
+    Paths(Vec<Vec<String>>),
+    /// Scan the whole watched folder.
+    Folder,
+}
+
+/// Languages of all documents of an inline rule; empty when one of them has no language.
+pub fn rule_languages(yaml: &str) -> Vec<String> {
+    let mut languages = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(yaml) {
+        let Ok(rule) = serde_yaml::Value::deserialize(document) else {
+            return Vec::new();
+        };
+        match rule.get("language").and_then(|v| v.as_str()) {
+            Some(language) => languages.push(language.to_string()),
+            None => return Vec::new(),
+        }
+    }
+    languages
+}
+
+/// Paths whose changes can change what ast-grep does under the sgconfig file at `config_path`:
+/// the file itself and its existing `ruleDirs`, `utilDirs` and `customLanguages` libraries.
+pub fn config_watch_paths(config_path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![config_path.to_path_buf()];
+    let Some(config) = std::fs::read_to_string(config_path)
+        .ok()
+        .and_then(|content| serde_yaml::from_str::<serde_yaml::Value>(&content).ok())
+    else {
+        return paths;
+    };
+    let config_dir = config_path.parent().unwrap_or(Path::new("."));
+    let mut referenced: Vec<&str> = Vec::new();
+    for key in ["ruleDirs", "utilDirs"] {
+        if let Some(dirs) = config.get(key).and_then(|v| v.as_sequence()) {
+            referenced.extend(dirs.iter().filter_map(|dir| dir.as_str()));
+        }
+    }
+    if let Some(languages) = config.get("customLanguages").and_then(|v| v.as_mapping()) {
+        referenced.extend(languages.values().filter_map(|language| language.get("libraryPath")?.as_str()));
+    }
+    paths.extend(referenced.into_iter().map(|path| config_dir.join(path)).filter(|path| path.exists()));
+    paths
+}
+
+/// Ignore files a directory can have, as the walk of [`crate::shard::walker`] reads them; later
+/// ones take precedence.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore", ".sgignore"];
+
+/// Tells which changed paths a scan of a watched folder visits, so changes to ignored files
+/// (build output, dependencies, the .git directory) do not make their way into a rescan, where
+/// ast-grep would scan them because they are named explicitly.
+///
+/// Like the walk, it skips hidden entries, [`DEFAULT_EXCLUDES`] unless vendored code was
+/// requested, and what the ignore files of the folder and its subdirectories (and the folder's
+/// `.git/info/exclude`) list. The ignore files of a directory are read once, and again after they
+/// change.
+pub struct ChangeFilter {
+    root: PathBuf,
+    include_vendored: bool,
+    ignores: HashMap<PathBuf, Gitignore>,
+}
+
+impl ChangeFilter {
+    pub fn new(root: &Path, include_vendored: bool) -> Self {
+        Self { root: root.to_path_buf(), include_vendored, ignores: HashMap::new() }
+    }
+
+    /// Whether a scan of the folder visits `path`. Paths outside the folder are kept.
+    pub fn is_scanned(&mut self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if IGNORE_FILES.contains(&name) {
+            if let Some(dir) = path.parent() {
+                self.ignores.remove(dir);
+            }
+        }
+        for component in relative.components() {
+            let component = component.as_os_str().to_string_lossy();
+            if component.starts_with('.') {
+                return false;
+            }
+            if !self.include_vendored && DEFAULT_EXCLUDES.contains(&component.as_ref()) {
+                return false;
+            }
+        }
+
+        // The deepest directory with a rule for the path decides
+        let is_dir = path.is_dir();
+        let mut dir = self.root.clone();
+        let mut ignored = false;
+        for component in relative.parent().into_iter().flat_map(Path::components) {
+            ignored = self.ignored_in(&dir, path, is_dir).unwrap_or(ignored);
+            dir.push(component);
+        }
+        !self.ignored_in(&dir, path, is_dir).unwrap_or(ignored)
+    }
+
+    /// Whether the ignore files of `dir` ignore `path`; `None` when they say nothing about it.
+    fn ignored_in(&mut self, dir: &Path, path: &Path, is_dir: bool) -> Option<bool> {
+        let root = &self.root;
+        let ignore = self.ignores.entry(dir.to_path_buf()).or_insert_with(|| {
+            let mut builder = GitignoreBuilder::new(dir);
+            if dir == root {
+                builder.add(dir.join(".git/info/exclude"));
+            }
+            for name in IGNORE_FILES {
+                builder.add(dir.join(name));
+            }
+            builder.build().unwrap_or_else(|_| Gitignore::empty())
+        });
+        let matched = ignore.matched_path_or_any_parents(path, is_dir);
+        (!matched.is_none()).then(|| matched.is_ignore())
+    }
+}
+
+/// Plans the rescan of changed `paths`, leaving out deleted paths and files none of `languages`
+/// (all files when empty) can be in.
+pub fn plan_rescan(paths: &[String], languages: &[String]) -> RescanPlan {
+    let existing: Vec<String> = paths
+        .iter()
+        .filter(|path| {
+            let path = Path::new(path);
+            path.is_dir()
+                || (path.is_file()
+                    && (languages.is_empty()
+                        || languages.iter().any(|language| crate::benchmark::is_language_file(path, language))))
+        })
+        .cloned()
+        .collect();
+    if existing.is_empty() {
+        RescanPlan::Nothing
+    } else if existing.len() > WATCH_FULL_RESCAN_THRESHOLD {
+        RescanPlan::Folder
+    } else {
+        RescanPlan::Paths(existing.chunks(WATCH_RESCAN_CHUNK).map(<[String]>::to_vec).collect())
+    }
+}
+
+/// Current matches of a watched rule, per file, to tell which matches a change added or removed.
+///
+/// Matches are compared by their `fingerprint`, so matches that only moved do not count as
+/// changed.
+#[derive(Debug, Default)]
+pub struct WatchedMatches {
+    by_file: HashMap<String, Vec<Value>>,
+}
+
+/// Matches that appeared or disappeared since the previous scan.
+#[derive(Debug, Default, Serialize)]
+pub struct MatchChanges {
+    pub added: Vec<Value>,
+    pub removed: Vec<Value>,
+}
+
+impl MatchChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn file_of(m: &Value) -> String {
+    m.get("file").and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+fn fingerprint_of(m: &Value) -> &str {
+    m.get("fingerprint").and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Whether `file` is `path` or inside it.
+fn is_within(file: &str, path: &str) -> bool {
+    Path::new(file).starts_with(path)
+}
+
+impl WatchedMatches {
+    pub fn new(matches: Vec<Value>) -> Self {
+        let mut watched = Self::default();
+        for m in matches {
+            watched.by_file.entry(file_of(&m)).or_default().push(m);
+        }
+        watched
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_file.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All current matches, by file.
+    pub fn matches(&self) -> Vec<Value> {
+        let mut files: Vec<_> = self.by_file.iter().collect();
+        files.sort_by(|a, b| a.0.cmp(b.0));
+        files.into_iter().flat_map(|(_, matches)| matches.iter().cloned()).collect()
+    }
+
+    /// Replaces the matches of every file in or under `paths` (which were just rescanned, or
+    /// deleted) with `matches`, returning what changed.
+    pub fn update(&mut self, paths: &[String], matches: Vec<Value>) -> MatchChanges {
+        let mut previous: Vec<Value> = Vec::new();
+        self.by_file.retain(|file, file_matches| {
+            let replaced = paths.iter().any(|path| is_within(file, path));
+            if replaced {
+                previous.append(file_matches);
+            }
+            !replaced
+        });
+
+        let mut changes = MatchChanges::default();
+        let mut unmatched: HashMap<&str, usize> = HashMap::new();
+        for m in &previous {
+            *unmatched.entry(fingerprint_of(m)).or_default() += 1;
+        }
+        for m in &matches {
+            match unmatched.get_mut(fingerprint_of(m)) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => changes.added.push(m.clone()),
+            }
+        }
+        for m in &previous {
+            if let Some(count) = unmatched.get_mut(fingerprint_of(m)).filter(|count| **count > 0) {
+                *count -= 1;
+                changes.removed.push(m.clone());
+            }
+        }
+
+        for m in matches {
+            self.by_file.entry(file_of(&m)).or_default().push(m);
+        }
+        changes
+    }
+}
+
+/// A compact form of a match for notifications: file, 1-based line, text and fingerprint.
+pub fn match_summary(m: &Value) -> Value {
+    let line = m.pointer("/range/start/line").and_then(Value::as_u64).unwrap_or(0) + 1;
+    serde_json::json!({
+        "file": file_of(m),
+        "line": line,
+        "text": m.get("text").and_then(Value::as_str).unwrap_or_default(),
+        "fingerprint": fingerprint_of(m),
+    })
+}
+
+/// What `list_watches` reports about a watch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchInfo {
+    pub watch_id: String,
+    /// `id` of the watched rule (of the first one when the YAML has several).
+    pub rule_id: String,
+    pub yaml: String,
+    pub project_folder: String,
+    pub include_vendored: bool,
+    /// Current matches of the rule in the folder.
+    pub matches: usize,
+    /// Matches that appeared and disappeared since the watch started.
+    pub added: usize,
+    pub removed: usize,
+    pub notifications: usize,
+}
+
+/// An active watch: what `list_watches` reports, the matches its task keeps current, and the task.
+struct Watch {
+    info: WatchInfo,
+    matches: Arc<Mutex<WatchedMatches>>,
+    task: AbortHandle,
+    /// Counts the watch against its client's quota while it is registered.
+    _quota: Option<QuotaSlot>,
+}
+
+/// Active watches with their background tasks, which are aborted when a watch is removed or the
+/// registry is dropped.
+#[derive(Default)]
+pub struct WatchRegistry {
+    watches: Mutex<HashMap<String, Watch>>,
+}
+
+impl WatchRegistry {
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Watch>> {
+        self.watches.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a watch whose task keeps `matches` current, stopping a watch with the same id. The
+    /// `quota` slot is released when the watch is removed.
+    pub fn insert(
+        &self,
+        info: WatchInfo,
+        matches: Arc<Mutex<WatchedMatches>>,
+        task: AbortHandle,
+        quota: Option<QuotaSlot>,
+    ) {
+        let watch = Watch { info: info.clone(), matches, task, _quota: quota };
+        if let Some(replaced) = self.lock().insert(info.watch_id, watch) {
+            replaced.task.abort();
+        }
+    }
+
+    /// Counts the changes a watch has notified about.
+    pub fn record(&self, id: &str, changes: &MatchChanges, matches: usize) {
+        if let Some(Watch { info, .. }) = self.lock().get_mut(id) {
+            info.matches = matches;
+            info.added += changes.added.len();
+            info.removed += changes.removed.len();
+            info.notifications += 1;
+        }
+    }
+
+    /// Active watches, by folder.
+    pub fn list(&self) -> Vec<WatchInfo> {
+        let mut watches: Vec<WatchInfo> = self.lock().values().map(|watch| watch.info.clone()).collect();
+        watches.sort_by(|a, b| a.project_folder.cmp(&b.project_folder).then_with(|| a.watch_id.cmp(&b.watch_id)));
+        watches
+    }
+
+    /// Active watches, as `list` orders them, with their current matches.
+    pub fn snapshot(&self) -> Vec<(WatchInfo, Vec<Value>)> {
+        let watches: Vec<(WatchInfo, Arc<Mutex<WatchedMatches>>)> =
+            self.lock().values().map(|watch| (watch.info.clone(), watch.matches.clone())).collect();
+        let mut watches: Vec<(WatchInfo, Vec<Value>)> = watches
+            .into_iter()
+            .map(|(info, matches)| (info, matches.lock().unwrap_or_else(|e| e.into_inner()).matches()))
+            .collect();
+        watches.sort_by(|(a, _), (b, _)| {
+            a.project_folder.cmp(&b.project_folder).then_with(|| a.watch_id.cmp(&b.watch_id))
+        });
+        watches
+    }
+
+    /// Stops watch `id`; `false` if there is no such watch.
+    pub fn remove(&self, id: &str) -> bool {
+        match self.lock().remove(id) {
+            Some(watch) => {
+                watch.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops all watches.
+    pub fn clear(&self) {
+        for (_, watch) in self.lock().drain() {
+            watch.task.abort();
+        }
+    }
+
+    /// Forgets watch `id` without aborting it, for a watch task that ends by itself.
+    pub fn forget(&self, id: &str) {
+        self.lock().remove(id);
+    }
+}
+
+impl Drop for WatchRegistry {
+    fn drop(&mut self) {
+        for watch in self.watches.get_mut().unwrap_or_else(|e| e.into_inner()).values() {
+            watch.task.abort();
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::auth::Authenticator;
+use crate::config::Config;
+use crate::http::{protect, serve_router, Endpoint};
+use crate::server::AstGrepServer;
+use crate::session::SessionRegistry;
+use crate::tls::server_config;
+use anyhow::Result;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Request, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::{future, Sink, SinkExt, Stream, StreamExt};
+use rmcp::model::GetExtensions;
+use rmcp::service::{RxJsonRpcMessage, TxJsonRpcMessage};
+use rmcp::{RoleServer, ServiceExt};
+use std::sync::Arc;
+
+/// Path of the WebSocket endpoint.
+pub const WS_PATH: &str = "/ws";
+
+/// What every connection needs to start its own server.
+struct WsState {
+    config: Config,
+    sessions: Arc<SessionRegistry>,
+}
+
+/// Serves MCP over WebSocket on `ws://<listen>:<port>/ws` (`wss://` with TLS), one JSON-RPC
+/// message per text frame, with a separate server, and so separate state, per connection. The
+/// session ends when the socket closes.
+pub async fn serve(config: Config) -> Result<()> {
+    let sessions = Arc::new(SessionRegistry::new(config.quotas.clone()));
+    let endpoint = Endpoint {
+        authenticator: Arc::new(Authenticator::new(config.auth.clone())),
+        path: WS_PATH,
+        secure: config.tls.is_some(),
+    };
+    let tls = config.tls.as_ref().map(|files| server_config(&files.cert, &files.key)).transpose()?;
+    let listener = tokio::net::TcpListener::bind((config.listen, config.port)).await?;
+    let address = listener.local_addr()?;
+    let state = Arc::new(WsState { config: config.clone(), sessions });
+    let router = axum::Router::new().route(WS_PATH, axum::routing::get(upgrade).with_state(state));
+    let router = protect(router, endpoint, &config)?.layer(axum::middleware::from_fn(token_from_query));
+
+    let scheme = if tls.is_some() { "wss" } else { "ws" };
+    eprintln!("Server listening on {}://{}{}", scheme, address, WS_PATH);
+    serve_router(listener, router, tls).await
+}
+
+/// Browsers cannot set headers on a WebSocket handshake, so a bearer token may come as the
+/// `access_token` query parameter instead (RFC 6750, section 2.3); it is moved into the
+/// `Authorization` header the authentication checks.
+async fn token_from_query(mut request: Request, next: Next) -> Response {
+    if !request.headers().contains_key(header::AUTHORIZATION) {
+        let token = request.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(name, _)| name == "access_token")
+                .map(|(_, token)| token.into_owned())
+        });
+        if let Some(value) = token.and_then(|token| HeaderValue::from_str(&format!("Bearer {}", token)).ok()) {
+            request.headers_mut().insert(header::AUTHORIZATION, value);
+        }
+    }
+    next.run(request).await
+}
+
+/// Accepts the WebSocket handshake and runs a server for the connection. Browsers do not apply
+/// CORS to WebSockets, so it is [`protect`]'s `Origin` check that keeps other pages out.
+async fn upgrade(State(state): State<Arc<WsState>>, upgrade: WebSocketUpgrade, parts: Parts) -> Response {
+    upgrade.on_upgrade(move |socket| async move {
+        let server = AstGrepServer::with_sessions(state.config.clone(), state.sessions.clone());
+        let (sink, stream) = transport(socket, parts);
+        match server.clone().serve((sink, stream)).await {
+            Ok(service) => {
+                if let Err(e) = service.waiting().await {
+                    tracing::warn!(error = %e, "WebSocket session failed");
+                }
+            }
+            Err(e) => tracing::warn!(error = %e, "WebSocket session could not be initialized"),
+        }
+        server.end_session();
+    })
+}
+
+/// The socket as a sink of outgoing and a stream of incoming MCP messages. Each incoming request
+/// and notification carries the handshake's request parts, as over HTTP, so tool calls see the
+/// [`Grant`] of the connection. The stream ends when the client closes the socket.
+///
+/// [`Grant`]: crate::auth::Grant
+fn transport(
+    socket: WebSocket,
+    parts: Parts,
+) -> (
+    impl Sink<TxJsonRpcMessage<RoleServer>, Error = axum::Error> + Send + Unpin + 'static,
+    impl Stream<Item = RxJsonRpcMessage<RoleServer>> + Send + Unpin + 'static,
+) {
+    let (sink, stream) = socket.split();
+    let sink = sink.with(|message: TxJsonRpcMessage<RoleServer>| {
+        future::ready(serde_json::to_string(&message).map(|text| Message::Text(text.into())).map_err(axum::Error::new))
+    });
+    let stream = stream
+        .take_while(|message| future::ready(message.is_ok()))
+        .filter_map(move |message| {
+            let message = match message {
+                Ok(Message::Text(text)) => decode(&text, &parts),
+                _ => None,
+            };
+            future::ready(message)
+        });
+    (sink, stream)
+}
+
+/// An incoming text frame as an MCP message, with `parts` attached to requests and notifications.
+fn decode(text: &str, parts: &Parts) -> Option<RxJsonRpcMessage<RoleServer>> {
+    let mut message: RxJsonRpcMessage<RoleServer> = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::warn!(error = %e, "ignored a WebSocket frame that is not an MCP message");
+            return None;
+        }
+    };
+    match &mut message {
+        RxJsonRpcMessage::<RoleServer>::Request(request) => {
+            request.request.extensions_mut().insert(parts.clone());
+        }
+        RxJsonRpcMessage::<RoleServer>::Notification(notification) => {
+            notification.notification.extensions_mut().insert(parts.clone());
+        }
+        _ => {}
+    }
+    Some(message)
+}
@@ -20,6 +20,10 @@ async fn test_find_code_integration() {
     // Since we refactored to a library, we can call run_ast_grep directly.
 
     use ast_grep_mcp::command::run_ast_grep;
+    use ast_grep_mcp::config::{Cli, Config};
+    use clap::Parser;
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
 
     let fixture_path = PathBuf::from("tests/fixtures/example.py");
     let absolute_path = std::fs::canonicalize(&fixture_path).expect("Failed to get absolute path");
@@ -37,7 +41,7 @@ async fn test_find_code_integration() {
             project_folder,
         ],
         None,
-        None
+        &config,
     ).await;
 
     assert!(result.is_ok(), "ast-grep command failed");
@@ -45,3 +49,758 @@ async fn test_find_code_integration() {
     // Verify JSON output
     assert!(output.stdout.contains("example_function") || output.stdout.contains("hello") || output.stdout.contains("add"));
 }
+
+#[tokio::test]
+async fn test_find_code_keeps_utf16_files() {
+    if !ast_grep_available() {
+        eprintln!("ast-grep not found, skipping integration test");
+        return;
+    }
+    use ast_grep_mcp::call::call_tool;
+    use ast_grep_mcp::config::{CallArgs, Cli, Config};
+    use clap::Parser;
+
+    let dir = tempfile::tempdir().unwrap();
+    let utf16: Vec<u8> = [0xFF, 0xFE]
+        .into_iter()
+        .chain("def hello():\n    return 1\n".encode_utf16().flat_map(u16::to_le_bytes))
+        .collect();
+    std::fs::write(dir.path().join("wide.py"), &utf16).unwrap();
+    std::fs::write(dir.path().join("narrow.py"), "def world():\n    return 2\n").unwrap();
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    let folder = dir.path().to_string_lossy().into_owned();
+    let args = CallArgs {
+        tool: "find_code".to_string(),
+        args: ["--project-folder", &folder, "--pattern", "def $NAME(): $$$BODY", "--language", "python"]
+            .into_iter()
+            .chain(["--output-format", "json"])
+            .map(str::to_string)
+            .collect(),
+    };
+    let result = call_tool(config, &args).await.unwrap();
+    let report = result.content.iter().filter_map(|content| content.as_text()).find_map(|text| {
+        serde_json::from_str::<serde_json::Value>(&text.text).ok().filter(serde_json::Value::is_object)
+    });
+    // The UTF-16 file's NUL bytes do not make it look binary
+    let skipped = report.and_then(|report| report.get("skipped").cloned()).unwrap_or_default();
+    assert!(!skipped.to_string().contains("wide.py"), "{}", skipped);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_license_header_insert_can_be_undone() {
+    use ast_grep_mcp::call::call_tool;
+    use ast_grep_mcp::config::{CallArgs, Cli, Config};
+    use clap::Parser;
+
+    let dir = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+    std::fs::create_dir_all(project.join("node_modules")).unwrap();
+    std::fs::write(project.join("main.py"), "print(1)\n").unwrap();
+    std::fs::write(project.join("node_modules/dep.py"), "print(2)\n").unwrap();
+    std::fs::write(outside.path().join("linked.py"), "print(3)\n").unwrap();
+    std::os::unix::fs::symlink(outside.path().join("linked.py"), project.join("linked.py")).unwrap();
+
+    let folder = project.to_string_lossy().into_owned();
+    let backups = dir.path().join("backups").to_string_lossy().into_owned();
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--allowed-root", &folder, "--backup-dir", &backups]);
+    let config = Config::from_args_with_env(&cli, |_| None).unwrap();
+    let args = CallArgs {
+        tool: "license_header".to_string(),
+        args: ["--project-folder", &folder, "--language", "python", "--header", "MIT", "--insert"]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    };
+    let result = call_tool(config.clone(), &args).await.unwrap();
+    let text = &result.content[0].as_text().unwrap().text;
+    let report: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(report["inserted"], serde_json::json!(["main.py"]), "{}", text);
+    assert_eq!(std::fs::read_to_string(project.join("main.py")).unwrap(), "# MIT\n\nprint(1)\n");
+    // Neither vendored code nor a linked file outside the folder is touched
+    assert_eq!(std::fs::read_to_string(project.join("node_modules/dep.py")).unwrap(), "print(2)\n");
+    assert_eq!(std::fs::read_to_string(outside.path().join("linked.py")).unwrap(), "print(3)\n");
+
+    let backup_id = report["backup_id"].as_str().unwrap().to_string();
+    let args = CallArgs {
+        tool: "undo_rewrite".to_string(),
+        args: vec!["--backup-id".to_string(), backup_id],
+    };
+    call_tool(config, &args).await.unwrap();
+    assert_eq!(std::fs::read_to_string(project.join("main.py")).unwrap(), "print(1)\n");
+}
+
+#[tokio::test]
+async fn test_suppress_finding_can_be_undone() {
+    use ast_grep_mcp::call::call_tool;
+    use ast_grep_mcp::config::{CallArgs, Cli, Config};
+    use clap::Parser;
+
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+    std::fs::create_dir_all(&project).unwrap();
+    let file = project.join("main.py");
+    std::fs::write(&file, "x = 1\nprint(x)\n").unwrap();
+
+    let folder = project.to_string_lossy().into_owned();
+    let path = file.to_string_lossy().into_owned();
+    let backups = dir.path().join("backups").to_string_lossy().into_owned();
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--allowed-root", &folder, "--backup-dir", &backups]);
+    let config = Config::from_args_with_env(&cli, |_| None).unwrap();
+    let args = CallArgs {
+        tool: "suppress_finding".to_string(),
+        args: ["--path", &path, "--line", "2", "--rule-id", "no-print", "--justification", "debug output"]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    };
+    let result = call_tool(config.clone(), &args).await.unwrap();
+    let text = &result.content[0].as_text().unwrap().text;
+    let report: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(report["written"], true, "{}", text);
+    assert!(std::fs::read_to_string(&file).unwrap().contains("ast-grep-ignore: no-print"));
+
+    let backup_id = report["backup_id"].as_str().unwrap().to_string();
+    let args = CallArgs {
+        tool: "undo_rewrite".to_string(),
+        args: vec!["--backup-id".to_string(), backup_id],
+    };
+    call_tool(config, &args).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "x = 1\nprint(x)\n");
+}
+
+#[tokio::test]
+async fn test_apply_rewrite_applies_a_subset() {
+    if !ast_grep_available() {
+        eprintln!("ast-grep not found, skipping integration test");
+        return;
+    }
+    use ast_grep_mcp::call::call_tool;
+    use ast_grep_mcp::config::{CallArgs, Cli, Config};
+    use clap::Parser;
+
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+    std::fs::create_dir_all(&project).unwrap();
+    std::fs::write(project.join("a.py"), "print(x)\nprint(y)\n").unwrap();
+    std::fs::write(project.join("b.py"), "print(z)\n").unwrap();
+
+    let folder = project.to_string_lossy().into_owned();
+    let backups = dir.path().join("backups").to_string_lossy().into_owned();
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--allowed-root", &folder, "--backup-dir", &backups]);
+    let config = Config::from_args_with_env(&cli, |_| None).unwrap();
+    let yaml = "id: log\nlanguage: python\nrule:\n  pattern: print($A)\nfix: log($A)\n";
+    let call = |tool: &str, extra: &[&str]| CallArgs {
+        tool: tool.to_string(),
+        args: ["--project-folder", &folder, "--yaml", yaml].iter().chain(extra).map(|arg| arg.to_string()).collect(),
+    };
+
+    let preview = call_tool(config.clone(), &call("preview_rewrite", &[])).await.unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&preview.content[1].as_text().unwrap().text).unwrap();
+    let a = summary["files"].as_array().unwrap().iter().find(|file| file["file"] == "a.py").unwrap();
+    let second = a["fixes"][1]["fingerprint"].as_str().unwrap().to_string();
+
+    // Only the previewed fix with that fingerprint is applied
+    let fingerprints = serde_json::json!([second, "0000000000000000"]).to_string();
+    let result = call_tool(config.clone(), &call("apply_rewrite", &["--fingerprints", &fingerprints])).await.unwrap();
+    let report: serde_json::Value = serde_json::from_str(&result.content[0].as_text().unwrap().text).unwrap();
+    assert_eq!(report["fixes"], 1, "{}", report);
+    assert_eq!(report["skipped"].as_array().unwrap().len(), 1, "the unknown fingerprint is reported");
+    assert_eq!(std::fs::read_to_string(project.join("a.py")).unwrap(), "print(x)\nlog(y)\n");
+    assert_eq!(std::fs::read_to_string(project.join("b.py")).unwrap(), "print(z)\n");
+
+    // Only the listed file is rewritten
+    call_tool(config, &call("apply_rewrite", &["--files", r#"["b.py"]"#])).await.unwrap();
+    assert_eq!(std::fs::read_to_string(project.join("a.py")).unwrap(), "print(x)\nlog(y)\n");
+    assert_eq!(std::fs::read_to_string(project.join("b.py")).unwrap(), "log(z)\n");
+}
+
+#[tokio::test]
+async fn test_tool_errors_carry_request_id() {
+    use ast_grep_mcp::call::call_tool;
+    use ast_grep_mcp::config::{CallArgs, Cli, Config};
+    use clap::Parser;
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    let args = CallArgs {
+        tool: "read_file_range".to_string(),
+        args: ["--path", "/tmp/x.py", "--start-line", "0", "--end-line", "1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    };
+
+    // read_file_range does not need ast-grep, so it is listed, and this runs, without it installed.
+    let err = call_tool(config, &args).await.unwrap_err().to_string();
+    assert!(err.contains("Invalid line range"));
+    assert!(err.contains("request_id"));
+}
+
+/// Writes an executable shell script, since `run_command` refuses `sh -c`.
+#[cfg(unix)]
+fn write_script(dir: &std::path::Path, body: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("script.sh");
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_hostile_arguments_are_not_interpreted() {
+    use ast_grep_mcp::command::{run_command, CommandError};
+
+    let dir = tempfile::tempdir().unwrap();
+    let echo_args = write_script(dir.path(), "for arg in \"$@\"; do printf '%s\\n' \"$arg\"; done");
+    let pwned = dir.path().join("pwned");
+    let hostile = [
+        format!("$X; rm -rf {}", dir.path().display()),
+        format!("`touch {}`", pwned.display()),
+        format!("$(touch {})", pwned.display()),
+        format!("a && touch {} | cat", pwned.display()),
+        "%PATH% $HOME ${IFS}".to_string(),
+        "id: x\nrule:\n  pattern: '\"; exit 1'".to_string(),
+    ];
+    let mut args = vec![echo_args.to_string_lossy().into_owned()];
+    args.extend(hostile.iter().cloned());
+
+    let output = run_command(&args, None, None).await.unwrap();
+    let expected: String = hostile.iter().map(|arg| format!("{}\n", arg)).collect();
+    assert_eq!(output.stdout, expected);
+    assert!(!pwned.exists() && echo_args.exists());
+
+    for shell in [&["sh", "-c", "echo hi"][..], &["/bin/bash", "-ec", "echo hi"], &["cmd.exe", "/C", "echo hi"]] {
+        let args: Vec<String> = shell.iter().map(|s| s.to_string()).collect();
+        assert!(matches!(run_command(&args, None, None).await, Err(CommandError::ShellInvocation { .. })));
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_timeout_kills_process_group() {
+    use ast_grep_mcp::command::{run_command, CommandError};
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join("grandchild.pid");
+    let script = write_script(dir.path(), &format!("sleep 30 & echo $! > {}; wait", pid_file.display()));
+    let args = vec![script.to_string_lossy().into_owned()];
+
+    let result = run_command(&args, None, Some(Duration::from_millis(500))).await;
+    assert!(matches!(result, Err(CommandError::Timeout { .. })));
+
+    // The background `sleep` is a grandchild; it must die along with the shell. Once killed
+    // it may linger as a zombie until its new parent reaps it, which counts as dead.
+    let pid = std::fs::read_to_string(&pid_file).unwrap().trim().to_string();
+    let mut alive = true;
+    for _ in 0..50 {
+        alive = Command::new("ps")
+            .args(["-o", "stat=", "-p", &pid])
+            .output()
+            .map(|out| {
+                let stat = String::from_utf8_lossy(&out.stdout);
+                !stat.trim().is_empty() && !stat.trim_start().starts_with('Z')
+            })
+            .unwrap_or(false);
+        if !alive {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(!alive, "grandchild process {} survived the timeout", pid);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_limited_command_stops_after_max_lines() {
+    use ast_grep_mcp::command::{run_command_limited, running_children};
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join("child.pid");
+    let body = format!("echo $$ > {}; echo a; echo; echo b; echo c; sleep 30", pid_file.display());
+    let script = write_script(dir.path(), &body);
+    let args = vec![script.to_string_lossy().into_owned()];
+
+    // Without stopping, the 10s timeout would fail the command
+    let limited = run_command_limited(&args, None, Some(Duration::from_secs(10)), Some(2)).await.unwrap();
+    assert!(limited.stopped);
+    assert_eq!(limited.result.stdout, "a\n\nb\n");
+    let pid: u32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+    assert!(!running_children().contains(&pid));
+
+    let script = write_script(dir.path(), "echo a; echo b");
+    let args = vec![script.to_string_lossy().into_owned()];
+    let complete = run_command_limited(&args, None, None, Some(5)).await.unwrap();
+    assert!(!complete.stopped);
+    assert_eq!(complete.result.stdout, "a\nb\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_limited_scan_counts_only_kept_matches() {
+    use ast_grep_mcp::command::run_command_counted;
+    use ast_grep_mcp::filter::KeptMatches;
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("bundle.py"), format!("x = '{}'\n", "a".repeat(20_000))).unwrap();
+    std::fs::write(dir.path().join("main.py"), "print(1)\nprint(2)\n").unwrap();
+    // Matches in a generated file come first, as they would from a walk that reaches it first
+    let body = [
+        r#"echo '{"file": "bundle.py", "text": "x"}'"#,
+        r#"echo '{"file": "bundle.py", "text": "x"}'"#,
+        r#"echo '{"file": "main.py", "text": "print(1)"}'"#,
+        r#"echo '{"file": "main.py", "text": "print(2)"}'"#,
+        "sleep 30",
+    ];
+    let script = write_script(dir.path(), &body.join("; "));
+    let args = vec![script.to_string_lossy().into_owned()];
+
+    let kept = KeptMatches::new(None, true);
+    let counts = |line: &[u8]| {
+        let m: serde_json::Value = serde_json::from_slice(line).unwrap();
+        kept.keeps(&m, dir.path())
+    };
+    let limited = run_command_counted(&args, None, Some(Duration::from_secs(10)), Some(2), &counts).await.unwrap();
+    assert!(limited.stopped);
+    assert_eq!(limited.result.stdout.lines().count(), 4, "{}", limited.result.stdout);
+
+    // With generated files kept, the first two matches are enough
+    let kept = KeptMatches::new(None, false);
+    let counts = |line: &[u8]| {
+        let m: serde_json::Value = serde_json::from_slice(line).unwrap();
+        kept.keeps(&m, dir.path())
+    };
+    let limited = run_command_counted(&args, None, Some(Duration::from_secs(10)), Some(2), &counts).await.unwrap();
+    assert_eq!(limited.result.stdout.lines().count(), 2);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_dropped_command_is_killed_and_reaped() {
+    use ast_grep_mcp::command::{run_command, running_children};
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join("child.pid");
+    let script = write_script(dir.path(), &format!("echo $$ > {}; exec sleep 30", pid_file.display()));
+    let args = vec![script.to_string_lossy().into_owned()];
+    let task = tokio::spawn(async move { run_command(&args, None, None).await });
+
+    let mut pid = None;
+    for _ in 0..50 {
+        if let Some(found) = std::fs::read_to_string(&pid_file).ok().and_then(|s| s.trim().parse::<u32>().ok()) {
+            pid = Some(found);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let pid = pid.expect("the child never started");
+    assert!(running_children().contains(&pid));
+
+    // Like a client disconnecting mid-request: the future running the command is dropped
+    task.abort();
+    assert!(task.await.is_err_and(|e| e.is_cancelled()));
+    assert!(!running_children().contains(&pid));
+
+    // Reaped, not left as a zombie
+    let mut gone = false;
+    for _ in 0..50 {
+        let stat = Command::new("ps").args(["-o", "stat=", "-p", &pid.to_string()]).output().unwrap();
+        if stat.stdout.is_empty() {
+            gone = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(gone, "child process {} was not reaped", pid);
+}
+
+#[tokio::test]
+async fn test_tracing_events_reach_the_client() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::logging::ClientLogLayer;
+    use ast_grep_mcp::server::AstGrepServer;
+    use clap::Parser;
+    use rmcp::model::{CallToolRequestParams, LoggingLevel, LoggingMessageNotificationParam, SetLevelRequestParams};
+    use rmcp::service::NotificationContext;
+    use rmcp::{ClientHandler, RoleClient, ServiceExt};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    struct LogCollector(mpsc::UnboundedSender<LoggingMessageNotificationParam>);
+
+    impl ClientHandler for LogCollector {
+        async fn on_logging_message(
+            &self,
+            params: LoggingMessageNotificationParam,
+            _context: NotificationContext<RoleClient>,
+        ) {
+            let _ = self.0.send(params);
+        }
+    }
+
+    let _ = tracing_subscriber::registry().with(ClientLogLayer).try_init();
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let service = AstGrepServer::new(config).serve(server_io).await.unwrap();
+        let _ = service.waiting().await;
+    });
+    let (sender, mut messages) = mpsc::unbounded_channel();
+    let client = LogCollector(sender).serve(client_io).await.unwrap();
+
+    // Fails validation before ast-grep is spawned, logging a warning inside the tool call
+    let failing_call = || CallToolRequestParams {
+        meta: None,
+        name: "find_code".into(),
+        arguments: serde_json::json!({"pattern": "x", "project_folder": "/tmp", "output_format": "xml"})
+            .as_object()
+            .cloned(),
+        task: None,
+    };
+    let drain = |messages: &mut mpsc::UnboundedReceiver<LoggingMessageNotificationParam>| {
+        std::iter::from_fn(|| messages.try_recv().ok()).collect::<Vec<_>>()
+    };
+
+    // The server starts forwarding once it has handled the client's `initialized` notification
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        assert!(client.call_tool(failing_call()).await.is_err());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        received = drain(&mut messages);
+        if !received.is_empty() {
+            break;
+        }
+    }
+    let failure = received
+        .iter()
+        .find(|m| m.level == LoggingLevel::Warning)
+        .expect("the tool call failure is forwarded");
+    assert_eq!(failure.data["tool"], "find_code");
+    assert!(failure.data["request_id"].is_string());
+    assert!(failure.data.get("session_id").is_none());
+    assert!(received.iter().any(|m| m.level == LoggingLevel::Info), "info events are sent by default");
+
+    client.set_level(SetLevelRequestParams { meta: None, level: LoggingLevel::Warning }).await.unwrap();
+    assert!(client.call_tool(failing_call()).await.is_err());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let received = drain(&mut messages);
+    assert!(!received.is_empty());
+    assert!(received.iter().all(|m| m.level == LoggingLevel::Warning), "below the client's level: {:?}", received);
+}
+
+#[tokio::test]
+async fn test_refine_pattern_uses_sampling() {
+    if !ast_grep_available() {
+        eprintln!("ast-grep not found, skipping integration test");
+        return;
+    }
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::server::AstGrepServer;
+    use clap::Parser;
+    use rmcp::model::{
+        CallToolRequestParams, ClientCapabilities, ClientInfo, CreateMessageRequestParams, CreateMessageResult,
+        SamplingMessage, SamplingMessageContent,
+    };
+    use rmcp::service::RequestContext;
+    use rmcp::{ClientHandler, ErrorData, RoleClient, ServiceExt};
+    use std::sync::{Arc, Mutex};
+
+    /// Answers every sampling request with the same pattern and keeps the prompts.
+    #[derive(Clone, Default)]
+    struct Sampler(Arc<Mutex<Vec<String>>>);
+
+    impl ClientHandler for Sampler {
+        async fn create_message(
+            &self,
+            params: CreateMessageRequestParams,
+            _context: RequestContext<RoleClient>,
+        ) -> Result<CreateMessageResult, ErrorData> {
+            for message in params.messages {
+                if let Some(SamplingMessageContent::Text(text)) = message.content.first() {
+                    self.0.lock().unwrap().push(text.text.clone());
+                }
+            }
+            Ok(CreateMessageResult {
+                model: "test".to_string(),
+                stop_reason: Some(CreateMessageResult::STOP_REASON_END_TURN.to_string()),
+                message: SamplingMessage::assistant_text("```python\nprint($A)\n```"),
+            })
+        }
+
+        fn get_info(&self) -> ClientInfo {
+            ClientInfo {
+                capabilities: ClientCapabilities::builder().enable_sampling().build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let service = AstGrepServer::new(config).serve(server_io).await.unwrap();
+        let _ = service.waiting().await;
+    });
+    let sampler = Sampler::default();
+    let client = sampler.clone().serve(client_io).await.unwrap();
+
+    let result = client
+        .call_tool(CallToolRequestParams {
+            meta: None,
+            name: "refine_pattern".into(),
+            arguments: serde_json::json!({"pattern": "print($A, $B)", "language": "python", "code": "print(x)\n"})
+                .as_object()
+                .cloned(),
+            task: None,
+        })
+        .await
+        .unwrap();
+    let text = &result.content[0].as_text().unwrap().text;
+    let response: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(response["problem"], "does not match the example code");
+    assert_eq!(response["refined"], "print($A)");
+    assert_eq!(response["matches"], 1);
+    assert_eq!(response["attempts"].as_array().unwrap().len(), 1);
+
+    let prompts = sampler.0.lock().unwrap();
+    assert_eq!(prompts.len(), 1);
+    assert!(prompts[0].contains("print($A, $B)") && prompts[0].contains("print(x)"));
+}
+
+#[tokio::test]
+async fn test_apply_rewrite_asks_for_approval() {
+    if !ast_grep_available() {
+        eprintln!("ast-grep not found, skipping integration test");
+        return;
+    }
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::server::AstGrepServer;
+    use clap::Parser;
+    use rmcp::model::{
+        CallToolRequestParams, ClientCapabilities, ClientInfo, CreateElicitationRequestParams,
+        CreateElicitationResult, ElicitationAction,
+    };
+    use rmcp::service::RequestContext;
+    use rmcp::{ClientHandler, ErrorData, RoleClient, ServiceExt};
+    use std::sync::{Arc, Mutex};
+
+    /// Declines the fixes of `print(2)`, approves the others, and keeps the messages.
+    #[derive(Clone, Default)]
+    struct Approver(Arc<Mutex<Vec<String>>>);
+
+    impl ClientHandler for Approver {
+        async fn create_elicitation(
+            &self,
+            params: CreateElicitationRequestParams,
+            _context: RequestContext<RoleClient>,
+        ) -> Result<CreateElicitationResult, ErrorData> {
+            let CreateElicitationRequestParams::FormElicitationParams { message, .. } = params else {
+                return Ok(CreateElicitationResult { action: ElicitationAction::Cancel, content: None });
+            };
+            let action =
+                if message.contains("print(2)") { ElicitationAction::Decline } else { ElicitationAction::Accept };
+            self.0.lock().unwrap().push(message);
+            Ok(CreateElicitationResult { action, content: Some(serde_json::json!({"apply": true})) })
+        }
+
+        fn get_info(&self) -> ClientInfo {
+            ClientInfo {
+                capabilities: ClientCapabilities::builder().enable_elicitation().build(),
+                ..Default::default()
+            }
+        }
+    }
+
+    let project = tempfile::tempdir().unwrap();
+    let backups = tempfile::tempdir().unwrap();
+    std::fs::write(project.path().join("m.py"), "print(1)\nprint(2)\n").unwrap();
+    let root = project.path().to_str().unwrap();
+    let args = ["ast-grep-mcp-server", "--allowed-root", root, "--backup-dir", backups.path().to_str().unwrap()];
+    let config = Config::from_args_with_env(&Cli::parse_from(args), |_| None).unwrap();
+    let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let service = AstGrepServer::new(config).serve(server_io).await.unwrap();
+        let _ = service.waiting().await;
+    });
+    let approver = Approver::default();
+    let client = approver.clone().serve(client_io).await.unwrap();
+
+    let yaml = "id: log\nlanguage: python\nrule:\n  pattern: print($A)\nfix: log($A)\n";
+    let arguments = serde_json::json!({"project_folder": root, "yaml": yaml, "interactive": true, "approve": "match"});
+    let result = client
+        .call_tool(CallToolRequestParams {
+            meta: None,
+            name: "apply_rewrite".into(),
+            arguments: arguments.as_object().cloned(),
+            task: None,
+        })
+        .await
+        .unwrap();
+    let text = &result.content[0].as_text().unwrap().text;
+    let response: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(response["fixes"], 1);
+    assert_eq!(response["rejected"], serde_json::json!(["m.py:2"]));
+    assert_eq!(response["cancelled"], false);
+    let rewritten = std::fs::read_to_string(project.path().join("m.py")).unwrap();
+    assert_eq!(rewritten, "log(1)\nprint(2)\n");
+    assert_eq!(approver.0.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_client_find_code() {
+    if !ast_grep_available() {
+        eprintln!("ast-grep not found, skipping integration test");
+        return;
+    }
+    use ast_grep_mcp::client::{AstGrepClient, ClientError, FindCodeRequest};
+
+    let client = AstGrepClient::default();
+    let project_folder = std::fs::canonicalize("tests/fixtures").unwrap();
+    let mut request = FindCodeRequest::new(&project_folder, "def $NAME($$$PARAMS): $$$BODY");
+    request.language = Some("python".to_string());
+    request.options.max_results = Some(1);
+
+    let found = client.find_code(request).await.unwrap();
+    assert!(found.total >= 1);
+    assert_eq!(found.matches.len(), 1);
+    let first = &found.matches[0];
+    assert!(first.file.ends_with("example.py"));
+    assert!(first.metavariables.contains_key("NAME"));
+    assert!(first.fingerprint.is_some());
+
+    let relative = client.find_code(FindCodeRequest::new("tests/fixtures", "def $NAME")).await;
+    assert!(matches!(relative, Err(ClientError::InvalidRequest(_))));
+}
+
+#[tokio::test]
+async fn test_client_rewrite_applies_with_backup() {
+    if !ast_grep_available() {
+        eprintln!("ast-grep not found, skipping integration test");
+        return;
+    }
+    use ast_grep_mcp::backup::{load_backup, restore_backup};
+    use ast_grep_mcp::client::{AstGrepClient, ClientError, RewriteRequest, SearchOptions};
+    use ast_grep_mcp::config::{Cli, Config};
+    use clap::Parser;
+
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().canonicalize().unwrap().join("project");
+    std::fs::create_dir_all(&project).unwrap();
+    std::fs::write(project.join("a.py"), "print(x)\n").unwrap();
+    let request = RewriteRequest {
+        project_folder: project.clone(),
+        pattern: "print($A)".to_string(),
+        rewrite: "log($A)".to_string(),
+        language: "python".to_string(),
+        apply: true,
+        options: SearchOptions::default(),
+    };
+
+    // Without allowed roots nothing is written
+    let backups = dir.path().join("backups");
+    let backup_dir = backups.to_string_lossy().into_owned();
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--backup-dir", &backup_dir]);
+    let client = AstGrepClient::new(Config::from_args_with_env(&cli, |_| None).unwrap());
+    assert!(matches!(client.rewrite(request.clone()).await, Err(ClientError::InvalidRequest(_))));
+    assert_eq!(std::fs::read_to_string(project.join("a.py")).unwrap(), "print(x)\n");
+
+    let folder = project.to_string_lossy().into_owned();
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--allowed-root", &folder, "--backup-dir", &backup_dir]);
+    let client = AstGrepClient::new(Config::from_args_with_env(&cli, |_| None).unwrap());
+    let rewritten = client.rewrite(request).await.unwrap();
+    assert_eq!(rewritten.files, vec!["a.py"]);
+    assert_eq!(std::fs::read_to_string(project.join("a.py")).unwrap(), "log(x)\n");
+
+    let backup = load_backup(&backups, rewritten.backup_id.as_deref().unwrap()).unwrap();
+    assert_eq!(restore_backup(&backups, &backup, false).restored.len(), 1);
+    assert_eq!(std::fs::read_to_string(project.join("a.py")).unwrap(), "print(x)\n");
+}
+
+#[tokio::test]
+async fn test_tls_listener_serves_https() {
+    use ast_grep_mcp::tls::{server_config, TlsListener};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    let fixtures = Path::new("tests/fixtures/tls");
+    // The CA's certificate does not go with the server's key
+    assert!(server_config(&fixtures.join("ca.pem"), &fixtures.join("key.pem")).is_err());
+    assert!(server_config(&fixtures.join("missing.pem"), &fixtures.join("key.pem")).is_err());
+    let config = server_config(&fixtures.join("cert.pem"), &fixtures.join("key.pem")).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let router = axum::Router::new().route("/", axum::routing::get(|| async { "hello over tls" }));
+    tokio::spawn(async move { axum::serve(TlsListener::new(listener, config), router).await });
+
+    // A client that never starts its handshake does not hold up the next one
+    let _stalled = tokio::net::TcpStream::connect(address).await.unwrap();
+    let response = tokio::task::spawn_blocking(move || {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(fixtures.join("ca.pem")).unwrap() {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let conn = rustls::ClientConnection::new(Arc::new(client), "localhost".try_into().unwrap()).unwrap();
+        let mut stream = rustls::StreamOwned::new(conn, std::net::TcpStream::connect(address).unwrap());
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+    .await
+    .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("hello over tls"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_trend_worktree_is_removed_when_dropped() {
+    use ast_grep_mcp::trend::Worktree;
+
+    let dir = tempfile::tempdir().unwrap();
+    let repository = dir.path().join("repo");
+    std::fs::create_dir_all(&repository).unwrap();
+    std::fs::write(repository.join("a.py"), "print(1)\n").unwrap();
+    let git = |args: &[&str]| {
+        let output = Command::new("git").arg("-C").arg(&repository).args(args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["-c", "user.name=t", "-c", "user.email=t@example.com", "commit", "-qm", "first"]);
+    let repo = repository.to_string_lossy().into_owned();
+
+    let removed = Worktree::add(&repo, dir.path().join("removed"), "HEAD", None).await.unwrap();
+    assert!(removed.path.join("a.py").exists());
+    removed.remove(None).await;
+    assert_eq!(git(&["worktree", "list"]).lines().count(), 1);
+
+    // A call cancelled while it has a worktree drops it without removing it
+    let dropped = dir.path().join("dropped");
+    drop(Worktree::add(&repo, dropped.clone(), "HEAD", None).await.unwrap());
+    for _ in 0..100 {
+        if !dropped.exists() && git(&["worktree", "list"]).lines().count() == 1 {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("the worktree was left behind: {}", git(&["worktree", "list"]));
+}
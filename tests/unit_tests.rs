@@ -62,6 +62,24 @@ fn test_format_matches_as_text_multiple_matches() {
     assert_eq!(result, "test.py:1\nmatch1\n\ntest.py:11\nmatch2");
 }
 
+#[test]
+fn test_write_matches_as_text_size_cap() {
+    use ast_grep_mcp::format::write_matches_as_text;
+
+    let matches: Vec<_> = (0..10)
+        .map(|i| json!({"file": "a.py", "range": {"start": {"line": i}, "end": {"line": i}}, "text": "x = 1"}))
+        .collect();
+    let mut text = String::from("Found 10 matches:\n\n");
+    // Each block is "a.py:N\nx = 1" (12 bytes) plus a blank line between blocks
+    let written = write_matches_as_text(&mut text, &matches, 20 + 12 + 14 + 5);
+    assert_eq!(written, 2);
+    assert_eq!(text, "Found 10 matches:\n\na.py:1\nx = 1\n\na.py:2\nx = 1");
+
+    let mut text = String::new();
+    assert_eq!(write_matches_as_text(&mut text, &matches, 1), 1, "the first block is always written");
+    assert_eq!(text, "a.py:1\nx = 1");
+}
+
 #[test]
 fn test_get_supported_languages_default() {
     let langs = get_supported_languages(None);
@@ -86,3 +104,3083 @@ fn test_get_supported_languages_with_config() {
     assert!(langs.contains(&"my-lang".to_string()));
     assert!(langs.contains(&"python".to_string()));
 }
+
+#[test]
+fn test_supported_languages_cache_follows_config_changes() {
+    use std::time::{Duration, SystemTime};
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("sgconfig.yaml");
+    let write = |content: &str, modified: SystemTime| {
+        std::fs::write(&config_path, content).unwrap();
+        std::fs::File::options().write(true).open(&config_path).unwrap().set_modified(modified).unwrap();
+    };
+
+    let modified = SystemTime::now() - Duration::from_secs(60);
+    write("customLanguages:\n  lang-a: {}\n", modified);
+    assert!(get_supported_languages(Some(&config_path)).contains(&"lang-a".to_string()));
+
+    // Same modification time and size: the cached list is reused without reading the file
+    write("customLanguages:\n  lang-b: {}\n", modified);
+    assert!(get_supported_languages(Some(&config_path)).contains(&"lang-a".to_string()));
+
+    write("customLanguages:\n  lang-b: {}\n", modified + Duration::from_secs(1));
+    let langs = get_supported_languages(Some(&config_path));
+    assert!(langs.contains(&"lang-b".to_string()) && !langs.contains(&"lang-a".to_string()));
+}
+
+#[test]
+fn test_parse_tool_args_coerces_by_schema_type() {
+    use ast_grep_mcp::call::parse_tool_args;
+
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "pattern": { "type": "string" },
+            "project_folder": { "type": "string" },
+            "max_results": { "type": "integer" }
+        }
+    });
+    let args: Vec<String> = ["--pattern", "42", "--project-folder=/tmp/project", "--max-results", "5"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    let parsed = parse_tool_args(&args, schema.as_object().unwrap()).unwrap();
+    assert_eq!(parsed["pattern"], json!("42"));
+    assert_eq!(parsed["project_folder"], json!("/tmp/project"));
+    assert_eq!(parsed["max_results"], json!(5));
+}
+
+#[test]
+fn test_parse_tool_args_rejects_positional_values() {
+    use ast_grep_mcp::call::parse_tool_args;
+
+    let schema = json!({ "type": "object", "properties": {} });
+    let args = vec!["def $NAME".to_string()];
+    assert!(parse_tool_args(&args, schema.as_object().unwrap()).is_err());
+}
+
+#[test]
+fn test_completions_cover_subcommands() {
+    use ast_grep_mcp::config::Cli;
+    use clap::CommandFactory;
+
+    Cli::command().debug_assert();
+
+    let mut cmd = Cli::command();
+    let mut buf = Vec::new();
+    clap_complete::generate(clap_complete::Shell::Bash, &mut cmd, "ast-grep-mcp-server", &mut buf);
+    let script = String::from_utf8(buf).unwrap();
+    assert!(script.contains("call"));
+    assert!(script.contains("completions"));
+    assert!(script.contains("--config"));
+}
+
+#[test]
+fn test_config_transport_and_port_from_env() {
+    use ast_grep_mcp::config::{Cli, Config, TransportType};
+    use clap::Parser;
+
+    let cli = Cli::parse_from(["ast-grep-mcp-server"]);
+    let config = Config::from_args_with_env(&cli, |name| match name {
+        "AST_GREP_MCP_TRANSPORT" => Some("SSE".to_string()),
+        "AST_GREP_MCP_PORT" => Some("4000".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(config.transport, TransportType::Sse);
+    assert_eq!(config.port, 4000);
+
+    let defaults = Config::from_args_with_env(&cli, |_| None).unwrap();
+    assert_eq!(defaults.transport, TransportType::Stdio);
+    assert_eq!(defaults.port, 3101);
+    assert!(defaults.allowed_roots.is_empty());
+
+    assert_eq!(defaults.listen, std::net::IpAddr::from([127, 0, 0, 1]));
+
+    let ws = Config::from_args_with_env(&cli, |name| match name {
+        "AST_GREP_MCP_TRANSPORT" => Some("ws".to_string()),
+        "AST_GREP_MCP_LISTEN" => Some("0.0.0.0".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(ws.transport, TransportType::Ws);
+    assert_eq!(ws.listen, std::net::IpAddr::from([0, 0, 0, 0]));
+    let listen = Cli::parse_from(["ast-grep-mcp-server", "--transport", "ws", "--listen", "::1"]);
+    let config = Config::from_args_with_env(&listen, |name| (name == "AST_GREP_MCP_LISTEN").then(|| "bad".to_string()));
+    assert_eq!(config.unwrap().listen, std::net::IpAddr::from(std::net::Ipv6Addr::LOCALHOST));
+    let bad = Config::from_args_with_env(&cli, |name| (name == "AST_GREP_MCP_LISTEN").then(|| "bad".to_string()));
+    assert!(bad.is_err());
+    assert!(defaults.tls.is_none());
+
+    let tls = Cli::parse_from(["ast-grep-mcp-server", "--tls-cert", "cert.pem", "--tls-key", "key.pem"]);
+    let tls = Config::from_args_with_env(&tls, |_| None).unwrap().tls.unwrap();
+    assert_eq!((tls.cert.to_str(), tls.key.to_str()), (Some("cert.pem"), Some("key.pem")));
+    assert!(Cli::try_parse_from(["ast-grep-mcp-server", "--tls-cert", "cert.pem"]).is_err());
+
+    let joined = std::env::join_paths(["/a", "/b"]).unwrap().into_string().unwrap();
+    let roots = Config::from_args_with_env(&cli, |name| (name == "AST_GREP_MCP_ALLOWED_ROOTS").then(|| joined.clone()))
+        .unwrap();
+    assert_eq!(roots.allowed_roots, vec![std::path::PathBuf::from("/a"), std::path::PathBuf::from("/b")]);
+}
+
+#[test]
+fn test_config_cli_flags_override_env() {
+    use ast_grep_mcp::config::{Cli, Config, TransportType};
+    use clap::Parser;
+
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--transport", "stdio", "--port", "5000"]);
+    let config = Config::from_args_with_env(&cli, |name| match name {
+        "AST_GREP_MCP_TRANSPORT" => Some("sse".to_string()),
+        "AST_GREP_MCP_PORT" => Some("not-a-port".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(config.transport, TransportType::Stdio);
+    assert_eq!(config.port, 5000);
+
+    let bare = Cli::parse_from(["ast-grep-mcp-server"]);
+    let err = Config::from_args_with_env(&bare, |name| (name == "AST_GREP_MCP_PORT").then(|| "abc".to_string()));
+    assert!(err.is_err());
+}
+
+#[test]
+fn test_config_scan_limits() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use clap::Parser;
+
+    let defaults = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    assert_eq!(defaults.max_concurrency, 4);
+    assert_eq!(defaults.shard_threshold, Some(50_000));
+
+    let unsharded = Cli::parse_from(["ast-grep-mcp-server", "--shard-threshold", "0"]);
+    assert_eq!(Config::from_args_with_env(&unsharded, |_| None).unwrap().shard_threshold, None);
+
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--max-concurrency", "8"]);
+    assert_eq!(Config::from_args_with_env(&cli, |_| None).unwrap().max_concurrency, 8);
+    assert!(Cli::try_parse_from(["ast-grep-mcp-server", "--max-concurrency", "0"]).is_err());
+
+    assert_eq!(defaults.rescan_interval, None);
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--rescan-interval", "600"]);
+    let config = Config::from_args_with_env(&cli, |_| None).unwrap();
+    assert_eq!(config.rescan_interval, Some(std::time::Duration::from_secs(600)));
+}
+
+#[test]
+fn test_server_stats_snapshot() {
+    use ast_grep_mcp::stats::ServerStats;
+
+    let stats = ServerStats::new();
+    stats.record_tool_call("find_code", false);
+    stats.record_tool_call("find_code", true);
+    stats.record_tool_call("dump_syntax_tree", false);
+    stats.record_cache_lookup(true);
+    stats.record_cache_lookup(false);
+
+    {
+        let _running = stats.subprocess_started();
+        assert_eq!(stats.snapshot().active_subprocesses, 1);
+    }
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.total_calls, 3);
+    assert_eq!(snapshot.total_errors, 1);
+    assert_eq!(snapshot.tools["find_code"].calls, 2);
+    assert_eq!(snapshot.tools["find_code"].errors, 1);
+    assert_eq!(snapshot.active_subprocesses, 0);
+    assert_eq!(snapshot.subprocess_runs, 1);
+    assert!(snapshot.avg_subprocess_ms.is_some());
+    assert_eq!(snapshot.cache_hit_rate, Some(0.5));
+}
+
+#[test]
+fn test_exclude_glob_args() {
+    use ast_grep_mcp::filter::{exclude_glob_args, DEFAULT_EXCLUDES};
+
+    let args = exclude_glob_args(false);
+    assert_eq!(args.len(), DEFAULT_EXCLUDES.len() * 2);
+    assert_eq!(&args[..2], &["--globs".to_string(), "!node_modules".to_string()]);
+    assert!(args.contains(&"!.venv".to_string()));
+
+    assert!(exclude_glob_args(true).is_empty());
+}
+
+#[test]
+fn test_ignore_note_reports_sgignore() {
+    use ast_grep_mcp::filter::{ignore_note, no_ignore_args};
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(ignore_note(dir.path(), false).is_none());
+
+    std::fs::write(dir.path().join(".sgignore"), "generated/\n").unwrap();
+    let note = ignore_note(dir.path(), false).unwrap();
+    assert!(note.contains(".sgignore"));
+    assert!(note.contains("no_ignore=true"));
+
+    // Bypassing ignore files makes the note irrelevant
+    assert!(ignore_note(dir.path(), true).is_none());
+    assert!(no_ignore_args(true).contains(&"--no-ignore".to_string()));
+    assert!(no_ignore_args(false).is_empty());
+}
+
+#[test]
+fn test_classify_generated_files() {
+    use ast_grep_mcp::filter::{classify_generated, SkipReason};
+
+    let dir = tempfile::tempdir().unwrap();
+    let normal = dir.path().join("app.js");
+    std::fs::write(&normal, "function add(a, b) {\n  return a + b;\n}\n").unwrap();
+    let minified_name = dir.path().join("app.min.js");
+    std::fs::write(&minified_name, "function add(a,b){return a+b}").unwrap();
+    let long_lines = dir.path().join("bundle.js");
+    std::fs::write(&long_lines, "var a=1;".repeat(2000)).unwrap();
+    let binary = dir.path().join("data.js");
+    std::fs::write(&binary, [0u8, 1, 2, 3, 0, 5]).unwrap();
+
+    assert_eq!(classify_generated(&normal), None);
+    assert_eq!(classify_generated(&minified_name), Some(SkipReason::MinifiedSuffix));
+    assert_eq!(classify_generated(&long_lines), Some(SkipReason::LongLines));
+    assert_eq!(classify_generated(&binary), Some(SkipReason::Binary));
+}
+
+#[test]
+fn test_drop_generated_files_lists_each_file_once() {
+    use ast_grep_mcp::filter::drop_generated_files;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.js"), "foo();\n").unwrap();
+    let matches = vec![
+        json!({ "file": "main.js", "text": "foo()" }),
+        json!({ "file": "vendor.min.js", "text": "foo()" }),
+        json!({ "file": "vendor.min.js", "text": "foo()" }),
+    ];
+
+    let (kept, skipped) = drop_generated_files(matches, dir.path());
+    assert_eq!(kept.len(), 1);
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].file, "vendor.min.js");
+}
+
+#[test]
+fn test_decode_detects_encodings() {
+    use ast_grep_mcp::encoding::{decode, SourceEncoding};
+
+    assert_eq!(decode(b"caf\xc3\xa9").unwrap(), ("café".to_string(), SourceEncoding::Utf8));
+    assert_eq!(decode(b"\xef\xbb\xbfx").unwrap(), ("x".to_string(), SourceEncoding::Utf8));
+    assert_eq!(decode(b"caf\xe9").unwrap(), ("café".to_string(), SourceEncoding::Latin1));
+    assert_eq!(decode(b"\xff\xfeh\x00i\x00").unwrap(), ("hi".to_string(), SourceEncoding::Utf16Le));
+    assert_eq!(decode(b"\xfe\xff\x00h\x00i").unwrap(), ("hi".to_string(), SourceEncoding::Utf16Be));
+    // Odd-length UTF-16 cannot be decoded
+    assert!(decode(b"\xff\xfeh\x00i").is_none());
+}
+
+#[test]
+fn test_fix_match_encodings_transcodes_latin1() {
+    use ast_grep_mcp::encoding::fix_match_encodings;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("latin.py"), b"name = \"caf\xe9\"\n").unwrap();
+    std::fs::write(dir.path().join("broken.py"), b"\xff\xfeodd").unwrap();
+
+    let mut matches = vec![
+        json!({
+            "file": "latin.py",
+            "text": "\"caf\u{FFFD}\"",
+            "range": { "start": { "line": 0, "column": 7 }, "end": { "line": 0, "column": 13 } }
+        }),
+        json!({
+            "file": "broken.py",
+            "text": "\u{FFFD}",
+            "range": { "start": { "line": 0, "column": 0 }, "end": { "line": 0, "column": 1 } }
+        }),
+    ];
+
+    let undecodable = fix_match_encodings(&mut matches, dir.path());
+    assert_eq!(matches[0]["text"], json!("\"café\""));
+    assert_eq!(matches[0]["encoding"], json!("latin-1"));
+    assert_eq!(undecodable, vec!["broken.py".to_string()]);
+}
+
+#[test]
+fn test_utf16_files_are_not_dropped_as_binary() {
+    use ast_grep_mcp::encoding::fix_match_encodings;
+    use ast_grep_mcp::filter::{classify_generated, drop_generated_files};
+
+    let dir = tempfile::tempdir().unwrap();
+    let utf16: Vec<u8> = [0xFF, 0xFE]
+        .into_iter()
+        .chain("def hello():\n    return 'h\u{e9}'\n".encode_utf16().flat_map(u16::to_le_bytes))
+        .collect();
+    std::fs::write(dir.path().join("wide.py"), &utf16).unwrap();
+    assert_eq!(classify_generated(&dir.path().join("wide.py")), None);
+
+    // As in run_find: generated files are dropped before match text is transcoded
+    let matches = vec![json!({
+        "file": "wide.py",
+        "text": "\u{FFFD}",
+        "range": { "start": { "line": 1, "column": 11 }, "end": { "line": 1, "column": 15 } }
+    })];
+    let (mut kept, skipped) = drop_generated_files(matches, dir.path());
+    assert!(skipped.is_empty());
+    assert!(fix_match_encodings(&mut kept, dir.path()).is_empty());
+    assert_eq!(kept[0]["text"], json!("'h\u{e9}'"));
+    assert_eq!(kept[0]["encoding"], json!("utf-16le"));
+}
+
+#[test]
+fn test_normalize_match_ranges_fills_missing_fields() {
+    use ast_grep_mcp::ranges::{normalize_match_ranges, normalize_source_ranges};
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.py"), "x = 1\ndef café():\n    pass\n").unwrap();
+
+    // Only start positions: byte offsets come from the file, the end from the text
+    let mut matches = vec![json!({
+        "file": "a.py",
+        "text": "def café():\n    pass",
+        "range": { "start": { "line": 1, "column": 0 } }
+    })];
+    normalize_match_ranges(&mut matches, dir.path());
+    assert_eq!(matches[0]["range"]["byteOffset"], json!({ "start": 6, "end": 27 }));
+    assert_eq!(matches[0]["range"]["end"], json!({ "line": 2, "column": 8 }));
+
+    // Only byte offsets: positions are recovered from the source
+    let mut matches = vec![json!({ "text": "café", "range": { "byteOffset": { "start": 10, "end": 15 } } })];
+    normalize_source_ranges(&mut matches, "x = 1\ndef café():\n");
+    assert_eq!(matches[0]["range"]["start"], json!({ "line": 1, "column": 4 }));
+    assert_eq!(matches[0]["range"]["end"], json!({ "line": 1, "column": 8 }));
+}
+
+#[test]
+fn test_match_resource_link_points_at_line_range() {
+    use ast_grep_mcp::format::match_resource_link;
+
+    let m = json!({
+        "file": "src/my file.py",
+        "range": { "start": { "line": 9, "column": 0 }, "end": { "line": 24, "column": 3 } }
+    });
+    let link = match_resource_link(&m, std::path::Path::new("/work/project")).unwrap();
+    assert_eq!(link.uri, "file:///work/project/src/my%20file.py#L10-L25");
+    assert_eq!(link.name, "src/my file.py:10-25");
+
+    let single = json!({ "file": "a.py", "range": { "start": { "line": 0 }, "end": { "line": 0 } } });
+    let link = match_resource_link(&single, std::path::Path::new("/work")).unwrap();
+    assert_eq!(link.uri, "file:///work/a.py#L1");
+}
+
+#[test]
+fn test_resolve_within_roots_rejects_escapes() {
+    use ast_grep_mcp::roots::{resolve_within_roots, roots_from_uris, RootError};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("project");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("a.py"), "x = 1\n").unwrap();
+    std::fs::write(dir.path().join("secret.txt"), "hidden\n").unwrap();
+    let roots = vec![root.clone()];
+
+    assert!(resolve_within_roots(&root.join("a.py"), &roots).is_ok());
+    assert!(matches!(
+        resolve_within_roots(&root.join("../secret.txt"), &roots),
+        Err(RootError::OutsideRoots(_))
+    ));
+    assert!(matches!(resolve_within_roots(std::path::Path::new("a.py"), &roots), Err(RootError::NotAbsolute(_))));
+    assert!(matches!(resolve_within_roots(&root.join("a.py"), &[]), Err(RootError::NoRoots)));
+
+    let from_client = roots_from_uris(["file:///work/repo", "https://example.com/x"]);
+    assert_eq!(from_client, vec![std::path::PathBuf::from("/work/repo")]);
+}
+
+#[test]
+fn test_match_store_assigns_stable_ids_and_evicts() {
+    use ast_grep_mcp::format::format_match_headers;
+    use ast_grep_mcp::store::MatchStore;
+
+    let make = |file: &str| {
+        json!({
+            "file": file,
+            "text": "fn main() {\n}",
+            "ruleId": "no-main",
+            "range": { "start": { "line": 2, "column": 0 }, "end": { "line": 3, "column": 1 } }
+        })
+    };
+    let store = MatchStore::new(2);
+    let mut first = vec![make("a.rs"), make("b.rs"), make("c.rs")];
+    store.remember(&mut first);
+    let mut again = vec![make("c.rs")];
+    store.remember(&mut again);
+
+    let id = |m: &serde_json::Value| m["id"].as_str().unwrap().to_string();
+    assert_eq!(id(&first[2]), id(&again[0]));
+    assert_ne!(id(&first[0]), id(&first[1]));
+    // Capacity 2: the oldest match was evicted
+    assert!(store.get(&id(&first[0])).is_none());
+    assert_eq!(store.get(&id(&first[2])).unwrap()["file"], json!("c.rs"));
+
+    let headers = format_match_headers(&first[1..2]);
+    assert_eq!(headers, format!("{} b.rs:3-4 [no-main] fn main() {{", id(&first[1])));
+}
+
+#[test]
+fn test_fingerprint_ignores_position_and_whitespace() {
+    use ast_grep_mcp::fingerprint::{add_fingerprints, fingerprint};
+
+    let original = json!({
+        "ruleId": "no-eval", "file": "a.js", "text": "eval(a, b)",
+        "range": { "start": { "line": 3, "column": 0 } }
+    });
+    let shifted = json!({
+        "ruleId": "no-eval", "file": "a.js", "text": "eval(a,\n     b)",
+        "range": { "start": { "line": 40, "column": 4 } }
+    });
+    let other_rule = json!({ "ruleId": "no-exec", "file": "a.js", "text": "eval(a, b)" });
+
+    assert_eq!(fingerprint(&original), fingerprint(&shifted));
+    assert_ne!(fingerprint(&original), fingerprint(&other_rule));
+    // The hash is fixed across runs, so baselines stay valid after an upgrade
+    assert_eq!(fingerprint(&original).len(), 16);
+
+    let mut matches = vec![original.clone()];
+    add_fingerprints(&mut matches);
+    assert_eq!(matches[0]["fingerprint"], json!(fingerprint(&original)));
+}
+
+#[test]
+fn test_sort_by_position_orders_by_file_line_column() {
+    use ast_grep_mcp::ranges::sort_by_position;
+
+    let at = |file: &str, line: u64, column: u64| {
+        json!({ "file": file, "range": { "start": { "line": line, "column": column } } })
+    };
+    let mut matches = vec![at("b.py", 1, 0), at("a.py", 10, 0), at("a.py", 2, 8), at("a.py", 2, 4)];
+    sort_by_position(&mut matches);
+    assert_eq!(matches, vec![at("a.py", 2, 4), at("a.py", 2, 8), at("a.py", 10, 0), at("b.py", 1, 0)]);
+}
+
+#[test]
+fn test_collapse_duplicates_groups_identical_text() {
+    use ast_grep_mcp::format::collapse_duplicates;
+
+    let at = |file: &str, line: u64, text: &str| {
+        json!({ "file": file, "text": text, "range": { "start": { "line": line }, "end": { "line": line } } })
+    };
+    let collapsed = collapse_duplicates(vec![at("a.py", 0, "log()"), at("b.py", 4, "log() "), at("c.py", 1, "other()")]);
+
+    assert_eq!(collapsed.len(), 2);
+    assert_eq!(collapsed[0]["count"], json!(2));
+    assert_eq!(collapsed[0]["locations"][1]["file"], json!("b.py"));
+    assert!(collapsed[1].get("locations").is_none());
+    assert_eq!(
+        format_matches_as_text(&collapsed),
+        "a.py:1 (2 occurrences)\nlog()\nAlso at: b.py:5\n\nc.py:2\nother()"
+    );
+}
+
+#[test]
+fn test_plan_shards_splits_large_directories() {
+    use ast_grep_mcp::shard::{count_files, plan_shards};
+
+    let dir = tempfile::tempdir().unwrap();
+    for sub in ["lib", "src/a", "src/b", "node_modules/x", ".git"] {
+        std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        for i in 0..3 {
+            std::fs::write(dir.path().join(sub).join(format!("f{}.py", i)), "x\n").unwrap();
+        }
+    }
+    std::fs::write(dir.path().join("setup.py"), "x\n").unwrap();
+    // Ignored like ast-grep ignores it, as the folder is a git repository
+    std::fs::write(dir.path().join(".gitignore"), "generated/\n").unwrap();
+    std::fs::create_dir_all(dir.path().join("generated")).unwrap();
+    std::fs::write(dir.path().join("generated").join("out.py"), "x\n").unwrap();
+    // A link cycle is not followed
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("src").join("a").join("loop")).unwrap();
+
+    assert_eq!(count_files(dir.path(), 100, false), 10);
+    assert_eq!(count_files(dir.path(), 100, true), 13);
+    assert_eq!(count_files(dir.path(), 4, false), 5);
+
+    // src (6 files) is too big for a shard of 4, so it is split into src/a and src/b
+    let shards = plan_shards(dir.path(), 4, false);
+    let names: Vec<Vec<String>> = shards
+        .iter()
+        .map(|shard| {
+            shard
+                .iter()
+                .map(|p| p.strip_prefix(dir.path()).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect()
+        })
+        .collect();
+    assert_eq!(names, vec![vec!["lib", "setup.py"], vec!["src/a"], vec!["src/b"]]);
+}
+
+#[test]
+fn test_benchmark_profile_candidates() {
+    use ast_grep_mcp::benchmark::{profile_candidates, rule_language};
+
+    assert_eq!(rule_language("id: x\nlanguage: Python\nrule: {pattern: x}").unwrap(), "Python");
+    assert_eq!(rule_language("id: a\nlanguage: rust\n---\nid: b\nlanguage: go\n").unwrap(), "rust");
+    assert!(rule_language("id: [unclosed").is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, bytes: usize| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, "x".repeat(bytes)).unwrap();
+        path
+    };
+    let files = vec![write("small.py", 10), write("big.py", 1000), write("types.pyi", 100), write("main.rs", 5000)];
+
+    let (picked, total) = profile_candidates(files.clone(), "python", 2);
+    assert_eq!(total, 3);
+    assert_eq!(picked, vec![files[1].clone(), files[2].clone()]);
+
+    // Unknown languages (e.g. custom ones from sgconfig.yaml) consider every file
+    let (picked, total) = profile_candidates(files.clone(), "mylang", 1);
+    assert_eq!((picked, total), (vec![files[3].clone()], 4));
+}
+
+#[test]
+fn test_add_inspect_counters_sums_summary_lines() {
+    use ast_grep_mcp::inspect::add_inspect_counters;
+    use std::collections::BTreeMap;
+
+    let mut counters = BTreeMap::new();
+    let stderr = "sg: summary|file: scannedFileCount=12,skippedFileCount=3\n\
+                  sg: entity|file|a.py: skipReason=gitignore\n\
+                  sg: summary|rule: effectiveRuleCount=1,skippedRuleCount=0\n\
+                  warning: unrelated";
+    add_inspect_counters(stderr, &mut counters);
+    add_inspect_counters("sg: summary|file: scannedFileCount=8,skippedFileCount=0", &mut counters);
+
+    assert_eq!(counters["file.scannedFileCount"], 20);
+    assert_eq!(counters["file.skippedFileCount"], 3);
+    assert_eq!(counters["rule.effectiveRuleCount"], 1);
+    assert_eq!(counters.len(), 4);
+}
+
+#[test]
+fn test_pattern_check_from_debug_dump() {
+    use ast_grep_mcp::explain::{rule_pattern, PatternCheck};
+
+    let dump = "Debug Pattern:\ncall_expression (0,0)-(0,15)\n  member_expression (0,0)-(0,11)\n";
+    let check = PatternCheck::from_dump("console.log($A)", dump, Some("call_expression"));
+    assert_eq!(check.root_kind.as_deref(), Some("call_expression"));
+    assert_eq!(check.matches_expected, Some(true));
+    assert!(!check.has_errors);
+
+    let broken = PatternCheck::from_dump("def (", "Debug Pattern:\nERROR (0,0)-(0,5)\n", Some("function_definition"));
+    assert_eq!(broken.matches_expected, Some(false));
+    assert!(broken.has_errors);
+
+    assert_eq!(rule_pattern("id: x\nlanguage: js\nrule:\n  pattern: foo($A)\n").as_deref(), Some("foo($A)"));
+    assert_eq!(rule_pattern("id: x\nlanguage: js\nrule:\n  kind: call_expression\n"), None);
+}
+
+#[test]
+fn test_add_custom_language_to_sgconfig() {
+    use ast_grep_mcp::sgconfig::{add_custom_language, CustomLanguage, SgConfigError};
+
+    let zig = CustomLanguage {
+        library_path: "parsers/zig.so".to_string(),
+        extensions: vec![".zig".to_string(), "zon".to_string()],
+        expando_char: Some('_'),
+    };
+    let updated = add_custom_language("ruleDirs: [rules]\n", "zig", &zig, false).unwrap();
+    let config: serde_yaml::Value = serde_yaml::from_str(&updated).unwrap();
+    assert_eq!(config["ruleDirs"][0], serde_yaml::Value::from("rules"));
+    assert_eq!(config["customLanguages"]["zig"]["libraryPath"], serde_yaml::Value::from("parsers/zig.so"));
+    assert_eq!(config["customLanguages"]["zig"]["extensions"][0], serde_yaml::Value::from("zig"));
+    assert_eq!(config["customLanguages"]["zig"]["expandoChar"], serde_yaml::Value::from("_"));
+
+    assert!(matches!(
+        add_custom_language(&updated, "zig", &zig, false),
+        Err(SgConfigError::AlreadyRegistered(_))
+    ));
+    assert!(add_custom_language(&updated, "zig", &zig, true).is_ok());
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(matches!(zig.validate(dir.path()), Err(SgConfigError::MissingLibrary(_))));
+}
+
+#[test]
+fn test_grammar_lookup_and_dir() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::grammar::{default_grammar_dir, known_grammar};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    let zig = known_grammar("Zig").unwrap();
+    assert_eq!(zig.extensions, ["zig"]);
+    assert!(known_grammar("cobol").is_none());
+
+    if !cfg!(windows) {
+        let env = |name: &str| (name == "HOME").then(|| "/home/u".to_string());
+        assert_eq!(
+            default_grammar_dir(env),
+            Some(PathBuf::from("/home/u/.local/share/ast-grep-mcp/grammars"))
+        );
+        assert_eq!(default_grammar_dir(|_| None), None);
+    }
+
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--grammar-dir", "/opt/grammars"]);
+    let env = |name: &str| (name == "AST_GREP_MCP_GRAMMAR_DIR").then(|| "/env/grammars".to_string());
+    let config = Config::from_args_with_env(&cli, env).unwrap();
+    assert_eq!(config.grammar_dir, Some(PathBuf::from("/opt/grammars")));
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), env).unwrap();
+    assert_eq!(config.grammar_dir, Some(PathBuf::from("/env/grammars")));
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--checkpoint-dir", "/opt/checkpoints"]);
+    assert_eq!(Config::from_args_with_env(&cli, env).unwrap().checkpoint_dir, Some(PathBuf::from("/opt/checkpoints")));
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--backup-dir", "/opt/backups"]);
+    assert_eq!(Config::from_args_with_env(&cli, env).unwrap().backup_dir, Some(PathBuf::from("/opt/backups")));
+}
+
+#[test]
+fn test_grammar_build_command() {
+    use ast_grep_mcp::grammar::build_command;
+
+    let dir = std::env::temp_dir().join(format!("grammar-build-{}", std::process::id()));
+    let src = dir.join("src");
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::write(src.join("parser.c"), "").unwrap();
+    let output = dir.join("foo.so");
+
+    let command = build_command(&dir, &output, "cc", "c++");
+    assert_eq!(command[0], "cc");
+    assert!(command.contains(&src.join("parser.c").display().to_string()));
+    assert!(!command.iter().any(|arg| arg.contains("scanner")));
+    assert_eq!(command[command.len() - 2..], ["-o".to_string(), output.display().to_string()]);
+
+    std::fs::write(src.join("scanner.cc"), "").unwrap();
+    let command = build_command(&dir, &output, "cc", "c++");
+    assert_eq!(command[0], "c++");
+    assert!(command.contains(&src.join("scanner.cc").display().to_string()));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_grammar_clone_is_confined() {
+    use ast_grep_mcp::grammar::{clone_command, grammar_source_dir};
+
+    let dir = tempfile::tempdir().unwrap();
+    let checkout = dir.path().join("sources").join("toml");
+    std::fs::create_dir_all(checkout.join("grammars").join("toml")).unwrap();
+
+    let command = clone_command("https://github.com/x/tree-sitter-toml", &checkout).unwrap();
+    assert_eq!(command[..5], ["git", "clone", "--depth", "1", "--"]);
+    assert!(clone_command("--upload-pack=touch /tmp/pwned", &checkout).is_err());
+
+    assert_eq!(grammar_source_dir(&checkout, None).unwrap(), checkout);
+    let inside = grammar_source_dir(&checkout, Some("grammars/toml")).unwrap();
+    assert!(inside.ends_with("grammars/toml"));
+    assert!(grammar_source_dir(&checkout, Some("../..")).is_err());
+    assert!(grammar_source_dir(&checkout, Some("missing")).is_err());
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(dir.path(), checkout.join("out")).unwrap();
+        assert!(grammar_source_dir(&checkout, Some("out")).is_err());
+    }
+}
+
+#[test]
+fn test_lint_rule_diagnostics() {
+    use ast_grep_mcp::lint::{lint_rule, Severity};
+
+    let yaml = "id: no-eval
+language: javascript
+severty: warning
+rule:
+  all:
+    - pattern: eval($X)
+    - inside:
+        kind: function_declaration
+  message: |
+    not: a key
+utils:
+  unused-one:
+    kind: identifier
+  used:
+    has:
+      kind: string
+      stopBy: end
+constraints:
+  X:
+    matches: used
+";
+    let (diagnostics, patterns) = lint_rule(yaml);
+    let found: Vec<(usize, usize, &str)> = diagnostics.iter().map(|d| (d.line, d.column, d.code)).collect();
+    assert_eq!(
+        found,
+        [
+            (3, 1, "unknown-key"),
+            (7, 7, "relational-without-stop-by"),
+            (9, 3, "unknown-key"),
+            (12, 3, "unused-util"),
+        ]
+    );
+    assert_eq!(diagnostics[0].suggestion, "Did you mean `severity`?");
+    assert_eq!(diagnostics[1].severity, Severity::Warning);
+    assert_eq!(patterns.len(), 1);
+    assert_eq!((patterns[0].pattern.as_str(), patterns[0].line, patterns[0].column), ("eval($X)", 6, 7));
+
+    let (diagnostics, _) = lint_rule("id: x\nrule:\n  kind: [\n");
+    assert_eq!(diagnostics[0].code, "yaml-syntax");
+    let (diagnostics, _) = lint_rule("id: x\nrule:\n  kind: call\n");
+    assert_eq!(diagnostics[0].message, "Missing required key `language`");
+}
+
+#[test]
+fn test_relaxations() {
+    use ast_grep_mcp::relax::relaxations;
+
+    let pattern = relaxations(&["--pattern".to_string(), "eval($X)".to_string()]);
+    assert_eq!(pattern.len(), 2);
+    assert_eq!(pattern[0].args[2..], ["--strictness".to_string(), "relaxed".to_string()]);
+
+    let yaml = "id: x
+language: python
+rule:
+  all:
+    - pattern: eval($X)
+    - inside:
+        kind: function_definition
+";
+    let rule = relaxations(&["--inline-rules".to_string(), yaml.to_string()]);
+    let descriptions: Vec<&str> = rule.iter().map(|r| r.description.as_str()).collect();
+    assert_eq!(
+        descriptions,
+        [
+            "`stopBy: end` on rule.all[1].inside",
+            "pattern strictness `relaxed`",
+            "pattern strictness `signature`",
+            "without the `inside` at rule.all[1]",
+        ]
+    );
+    let parsed = |i: usize| serde_yaml::from_str::<serde_yaml::Value>(&rule[i].args[1]).unwrap();
+    assert_eq!(parsed(0)["rule"]["all"][1]["inside"]["stopBy"], "end");
+    assert_eq!(parsed(1)["rule"]["all"][0]["pattern"]["strictness"], "relaxed");
+    assert_eq!(parsed(1)["rule"]["all"][0]["pattern"]["context"], "eval($X)");
+    assert_eq!(parsed(3)["rule"]["all"].as_sequence().unwrap().len(), 1);
+
+    let only_inside = "id: x\nlanguage: python\nrule:\n  inside:\n    kind: call\n    stopBy: end\n";
+    assert!(relaxations(&["--inline-rules".to_string(), only_inside.to_string()]).is_empty());
+}
+
+#[test]
+fn test_stop_by_retry() {
+    use ast_grep_mcp::relax::stop_by_retry;
+
+    let yaml = "id: x\nlanguage: python\nrule:\n  pattern: eval($X)\n  inside: { kind: function_definition }\n  not: { has: { kind: string, stopBy: neighbor } }\n---\nid: y\nlanguage: python\nrule: { kind: call }\n";
+    let retry = stop_by_retry(&["--inline-rules".to_string(), yaml.to_string()]).unwrap();
+    assert_eq!(retry.description, "`stopBy: end` on rule.inside");
+    let rule: serde_yaml::Value = serde_yaml::from_str(&retry.args[1]).unwrap();
+    assert_eq!(rule["rule"]["inside"]["stopBy"], "end");
+    // An explicit stopBy is left alone
+    assert_eq!(rule["rule"]["not"]["has"]["stopBy"], "neighbor");
+
+    let no_relations = "id: x\nlanguage: python\nrule: { pattern: eval($X) }\n";
+    assert!(stop_by_retry(&["--inline-rules".to_string(), no_relations.to_string()]).is_none());
+    assert!(stop_by_retry(&["--pattern".to_string(), "eval($X)".to_string()]).is_none());
+}
+
+#[test]
+fn test_strictness_relaxations() {
+    use ast_grep_mcp::relax::strictness_relaxations;
+
+    let yaml = "id: x\nlanguage: python\nrule:\n  pattern:\n    context: 'class A: $$$'\n    selector: class_definition\n";
+    let relaxed = strictness_relaxations(&["--inline-rules".to_string(), yaml.to_string()]);
+    assert_eq!(relaxed.len(), 2);
+    assert_eq!(relaxed[1].description, "pattern strictness `signature`");
+    let rule: serde_yaml::Value = serde_yaml::from_str(&relaxed[1].args[1]).unwrap();
+    assert_eq!(rule["rule"]["pattern"]["strictness"], "signature");
+    assert_eq!(rule["rule"]["pattern"]["selector"], "class_definition");
+
+    let no_patterns = "id: x\nlanguage: python\nrule:\n  kind: class_definition\n";
+    assert!(strictness_relaxations(&["--inline-rules".to_string(), no_patterns.to_string()]).is_empty());
+}
+
+#[test]
+fn test_no_match_report() {
+    use ast_grep_mcp::filter::{NotSearched, NotSearchedGroup, NotSearchedReason};
+    use ast_grep_mcp::profiles::FilterProfile;
+    use ast_grep_mcp::relax::{no_match_report, RelaxationAttempt, SearchScope};
+    use std::path::PathBuf;
+
+    let files: Vec<PathBuf> = ["src/a.py", "src/b.py", "README.md"].iter().map(PathBuf::from).collect();
+    let mut not_searched = NotSearched::default();
+    not_searched.0.insert(
+        NotSearchedReason::Vendored,
+        NotSearchedGroup { count: 1, examples: vec!["node_modules/".to_string()] },
+    );
+    let scope = SearchScope {
+        files: &files,
+        filter_profile: None,
+        include_vendored: false,
+        no_ignore: false,
+        retried_strictness: false,
+        retried_stop_by: true,
+        not_searched: &not_searched,
+    };
+    let query = ["--pattern".to_string(), "console.log($A)".to_string(), "--lang".to_string(), "js".to_string()];
+    let report = no_match_report("run", &query, &scope, &[]);
+    assert_eq!(report.query, "ast-grep run --pattern 'console.log($A)' --lang js");
+    assert_eq!(report.files_scanned, 0);
+    assert_eq!(report.filters.len(), 2);
+    let kinds: Vec<&str> = report.suggestions.iter().map(|s| s.kind).collect();
+    assert_eq!(kinds, ["relax_strictness", "diagnose", "check_language", "include_vendored"]);
+    assert!(report.suggestions[2].message.contains("2 python files"));
+
+    let profile = FilterProfile { languages: vec!["python".to_string()], ..Default::default() };
+    let scope = SearchScope { filter_profile: Some(("app", &profile)), include_vendored: true, ..scope };
+    let query = ["--pattern".to_string(), "eval($X)".to_string()];
+    let diagnosis = [RelaxationAttempt { relaxation: "pattern strictness `relaxed`".to_string(), matches: 3 }];
+    let report = no_match_report("run", &query, &scope, &diagnosis);
+    assert_eq!(report.files_scanned, 2);
+    assert_eq!(
+        report.filters,
+        ["filter_profile 'app': languages python", "files excluded by .gitignore, .ignore and .sgignore skipped"]
+    );
+    let kinds: Vec<&str> = report.suggestions.iter().map(|s| s.kind).collect();
+    assert_eq!(kinds, ["relax_strictness", "remove_glob"]);
+}
+
+#[test]
+fn test_suggest_patterns_from_dump() {
+    use ast_grep_mcp::suggest::{parse_dump, suggest_patterns};
+
+    let dump = "Debug CST:
+module (0,0)-(0,19)
+  expression_statement (0,0)-(0,19)
+    call (0,0)-(0,19)
+      function: attribute (0,0)-(0,8)
+        object: identifier (0,0)-(0,3)
+        . (0,3)-(0,4)
+        attribute: identifier (0,4)-(0,8)
+      arguments: argument_list (0,8)-(0,19)
+        ( (0,8)-(0,9)
+        string (0,9)-(0,14)
+          string_content (0,10)-(0,13)
+        , (0,14)-(0,15)
+        identifier (0,16)-(0,18)
+        ) (0,18)-(0,19)
+";
+    let root = parse_dump(dump).unwrap();
+    assert_eq!(root.kind, "module");
+    let call = &root.children[0].children[0];
+    assert_eq!(call.children[0].field.as_deref(), Some("function"));
+    assert_eq!(call.children[1].children[0].kind, "(");
+
+    let code = "log.info(\"abc\", xs)";
+    let patterns: Vec<(&str, String)> =
+        suggest_patterns(code, &root).into_iter().map(|s| (s.level, s.pattern)).collect();
+    assert_eq!(
+        patterns,
+        [
+            ("literals", "log.info($A, xs)".to_string()),
+            ("identifiers", "$A.$B($C, $D)".to_string()),
+            ("structure", "$A.$B($$$ARGS)".to_string()),
+        ]
+    );
+
+    let repeated = parse_dump("x (0,0)-(0,5)\n  identifier (0,0)-(0,1)\n  + (0,2)-(0,3)\n  identifier (0,4)-(0,5)\n").unwrap();
+    let suggestions = suggest_patterns("a + a", &repeated);
+    assert_eq!(suggestions[0].pattern, "$A + $A");
+}
+
+#[cfg(feature = "parsers")]
+#[test]
+fn test_embedded_dump_tree() {
+    use ast_grep_mcp::dump::DumpFormat;
+    use ast_grep_mcp::explain::dump_root_kind;
+    use ast_grep_mcp::parsers::dump_tree;
+    use ast_grep_mcp::suggest::parse_dump;
+
+    // `$` is not an identifier character in Python, so metavariables are parsed as expando names
+    let pattern = dump_tree("print($A, $$$REST)", "py", DumpFormat::Pattern).unwrap();
+    assert!(pattern.starts_with("Debug Pattern:\ncall (0,0)-(0,18)\n"), "{}", pattern);
+    assert!(pattern.contains("    $A (0,6)-(0,8)\n") && pattern.contains("    $$$REST (0,10)-(0,17)\n"), "{}", pattern);
+
+    let cst = dump_tree("x = \"é\"", "python", DumpFormat::Cst).unwrap();
+    let root = parse_dump(&cst).unwrap();
+    assert_eq!(root.kind, "module");
+    let assignment = &root.children[0].children[0];
+    assert_eq!(assignment.children[1].kind, "=");
+    assert_eq!((assignment.children[2].field.as_deref(), assignment.children[2].end), (Some("right"), (0, 7)));
+
+    // Named nodes only, and broken code shows up as ERROR
+    let ast = dump_tree("let x = 1;", "TypeScript", DumpFormat::Ast).unwrap();
+    assert!(!ast.contains(" = ") && ast.contains("value: number"), "{}", ast);
+    let broken = dump_tree("def (", "python", DumpFormat::Pattern).unwrap();
+    assert_eq!(dump_root_kind(&broken).as_deref(), Some("ERROR"));
+
+    assert!(dump_tree("x", "kotlin", DumpFormat::Cst).is_none());
+    assert_eq!(DumpFormat::parse("sexp"), None);
+}
+
+#[test]
+fn test_kind_frequencies() {
+    use ast_grep_mcp::benchmark::language_for_path;
+    use ast_grep_mcp::kinds::{dump_prefix, kind_frequencies, MAX_DUMP_SOURCE_BYTES};
+    use ast_grep_mcp::suggest::parse_dump;
+    use std::path::Path;
+
+    let dump = "Debug AST:
+module (0,0)-(2,0)
+  expression_statement (0,0)-(0,5)
+    assignment (0,0)-(0,5)
+      left: identifier (0,0)-(0,1)
+      = (0,2)-(0,3)
+      right: identifier (0,4)-(0,5)
+  expression_statement (1,0)-(1,1)
+    identifier (1,0)-(1,1)
+";
+    let kinds = kind_frequencies(&parse_dump(dump).unwrap(), 2);
+    let counts: Vec<(&str, usize)> = kinds.iter().map(|k| (k.kind.as_str(), k.count)).collect();
+    assert_eq!(counts, [("identifier", 3), ("expression_statement", 2), ("assignment", 1), ("module", 1)]);
+    assert_eq!(kinds[0].examples, ["1:1-1:2", "1:5-1:6"]);
+
+    assert_eq!(language_for_path(Path::new("src/lib.rs")), Some("rust"));
+    assert_eq!(language_for_path(Path::new("a.tsx")), Some("tsx"));
+    assert_eq!(language_for_path(Path::new("notes.txt")), None);
+
+    let long = "x = 1\n".repeat(MAX_DUMP_SOURCE_BYTES / 6 + 10);
+    let prefix = dump_prefix(&long);
+    assert!(prefix.len() <= MAX_DUMP_SOURCE_BYTES && prefix.ends_with('\n'));
+    assert_eq!(dump_prefix("short"), "short");
+}
+
+#[test]
+fn test_file_metrics() {
+    use ast_grep_mcp::metrics::{file_metrics, metrics_rules};
+
+    let rules = metrics_rules("python").unwrap();
+    assert_eq!(rules.split("---\n").count(), 3);
+    assert!(rules.contains("id: metrics-nesting\nlanguage: python\n"));
+    assert!(rules.contains("    - kind: function_definition"));
+    assert!(metrics_rules("cobol").is_none());
+
+    let m = |file: &str, rule: &str, start: (u64, u64), end: (u64, u64)| {
+        json!({
+            "file": file,
+            "ruleId": rule,
+            "range": {"start": {"line": start.0, "column": start.1}, "end": {"line": end.0, "column": end.1}}
+        })
+    };
+    let matches = vec![
+        m("a.py", "metrics-function", (0, 0), (9, 0)),
+        m("a.py", "metrics-function", (10, 0), (11, 0)),
+        m("a.py", "metrics-class", (0, 0), (11, 0)),
+        m("a.py", "metrics-nesting", (1, 4), (8, 0)),
+        m("a.py", "metrics-nesting", (2, 8), (5, 0)),
+        m("a.py", "metrics-nesting", (6, 8), (7, 0)),
+        m("b.py", "metrics-nesting", (0, 0), (1, 0)),
+        m("b.py", "metrics-nesting", (2, 0), (3, 0)),
+    ];
+    let metrics = file_metrics(&matches);
+    assert_eq!(metrics.len(), 2);
+    assert_eq!(metrics[0].file, "a.py");
+    assert_eq!(
+        (metrics[0].functions, metrics[0].classes, metrics[0].max_nesting_depth),
+        (2, 1, 2)
+    );
+    assert_eq!((metrics[0].avg_function_lines, metrics[0].longest_function_lines), (6.0, 10));
+    assert_eq!((metrics[1].functions, metrics[1].max_nesting_depth), (0, 1));
+}
+
+#[test]
+fn test_extract_todos() {
+    use ast_grep_mcp::todo::{extract_todos, todo_rules};
+
+    let tags: Vec<String> = ["TODO", "FIXME"].iter().map(|t| t.to_string()).collect();
+    let rules = todo_rules(&["rust", "python"], &tags);
+    assert!(rules.contains("id: todo-rust\nlanguage: rust\nrule:\n  regex: '\\b(TODO|FIXME)\\b'\n"));
+    assert!(rules.contains("    - kind: line_comment\n    - kind: block_comment\n---\n"));
+
+    let matches = vec![
+        json!({
+            "file": "b.rs",
+            "text": "/* FIXME(@alice, 2024-03-01): handle overflow\n * and TODO: later */",
+            "range": {"start": {"line": 9, "column": 0}}
+        }),
+        json!({
+            "file": "a.py",
+            "text": "# TODOS are not notes; TODO - ask @bob about this",
+            "range": {"start": {"line": 0, "column": 4}}
+        }),
+    ];
+    let items = extract_todos(&matches, &tags);
+    assert_eq!(items.len(), 3);
+    assert_eq!((items[0].file.as_str(), items[0].line, items[0].tag.as_str()), ("a.py", 1, "TODO"));
+    assert_eq!(items[0].owner.as_deref(), Some("bob"));
+    assert_eq!(items[0].text, "ask @bob about this");
+    assert_eq!((items[1].line, items[1].owner.as_deref(), items[1].date.as_deref()), (10, Some("alice"), Some("2024-03-01")));
+    assert_eq!(items[1].text, "handle overflow");
+    assert_eq!((items[2].line, items[2].tag.as_str(), items[2].text.as_str()), (11, "TODO", "later"));
+}
+
+#[test]
+fn test_license_header() {
+    use ast_grep_mcp::header::{comment_style, format_header, has_header, insert_header, prologue_len};
+
+    let python = comment_style("python").unwrap();
+    let rust = comment_style("rust").unwrap();
+    let header = "Copyright ACME\n\nSPDX-License-Identifier: MIT";
+    assert_eq!(format_header(header, python), "# Copyright ACME\n#\n# SPDX-License-Identifier: MIT\n");
+    assert_eq!(format_header("MIT", comment_style("css").unwrap()), "/*\n  MIT\n*/\n");
+
+    let script = "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\nimport os\n";
+    assert_eq!(prologue_len(script), script.find("import").unwrap());
+    let inserted = insert_header(script, header, python);
+    assert!(inserted.starts_with("#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\n# Copyright ACME\n"));
+    assert!(inserted.ends_with("MIT\n\nimport os\n"));
+    assert!(has_header(&inserted, header, python));
+    assert!(!has_header(script, header, python));
+
+    let block = "/*\n * Copyright ACME\n *\n * SPDX-License-Identifier: MIT\n */\nfn main() {}\n";
+    assert!(has_header(block, header, rust));
+    let doc = "//! Copyright ACME\n//! SPDX-License-Identifier: MIT\n";
+    assert!(has_header(doc, header, rust));
+    let later = "fn main() {}\n// Copyright ACME SPDX-License-Identifier: MIT\n";
+    assert!(!has_header(later, header, rust));
+
+    let crlf = insert_header("fn main() {}\r\n", "MIT", rust);
+    assert_eq!(crlf, "// MIT\r\n\r\nfn main() {}\r\n");
+}
+
+#[test]
+fn test_language_alias_and_match_position() {
+    use ast_grep_mcp::header::comment_style;
+    use ast_grep_mcp::language::language_alias;
+    use ast_grep_mcp::metrics::metric_kinds;
+    use ast_grep_mcp::ranges::match_position;
+
+    assert_eq!(language_alias("Py"), "python");
+    assert_eq!(language_alias("golang"), "go");
+    assert_eq!(language_alias("jsx"), "javascript");
+    assert_eq!(language_alias("tsx"), "tsx");
+    assert_eq!(language_alias("Zig"), "zig");
+    // Every table takes the aliases
+    assert!(metric_kinds("rs").is_some() && comment_style("C#").is_some());
+
+    let m = json!({"range": {"start": {"line": 2, "column": 4}, "end": {"line": 5}}});
+    assert_eq!(match_position(&m, "start"), (2, 4));
+    assert_eq!(match_position(&m, "end"), (5, 0));
+}
+
+#[test]
+fn test_call_graph() {
+    use ast_grep_mcp::callgraph::{build_call_graph, call_graph_rules, callee_name, CALL_RULE_ID, DEF_RULE_ID};
+
+    let rules = call_graph_rules("python").unwrap();
+    assert!(rules.contains("id: callgraph-def") && rules.contains("pattern: $CALLEE"));
+    assert!(call_graph_rules("haskell").is_none());
+    assert_eq!(callee_name("self.helper"), "helper");
+    assert_eq!(callee_name("Vec::<u8>::new"), "new");
+    assert_eq!(callee_name("std::mem::take"), "take");
+    assert_eq!(callee_name("obj->run"), "run");
+
+    let def = |name: &str, start: u64, end: u64| {
+        json!({"ruleId": DEF_RULE_ID, "file": "a.py", "range": {"start": {"line": start, "column": 0}, "end": {"line": end, "column": 0}},
+            "metaVariables": {"single": {"NAME": {"text": name}}}})
+    };
+    let call = |callee: &str, line: u64| {
+        json!({"ruleId": CALL_RULE_ID, "file": "a.py", "range": {"start": {"line": line, "column": 4}, "end": {"line": line, "column": 12}},
+            "metaVariables": {"single": {"CALLEE": {"text": callee}}}})
+    };
+    let matches = vec![
+        def("main", 0, 10),
+        def("inner", 2, 4),
+        def("helper", 12, 14),
+        call("helper", 3),
+        call("self.helper", 6),
+        call("print", 7),
+        call("main", 20),
+    ];
+    let graph = build_call_graph(&matches, false);
+    assert_eq!(graph.functions["helper"], vec!["a.py:13"]);
+    assert_eq!(graph.edges["inner"].iter().collect::<Vec<_>>(), vec!["helper"]);
+    assert_eq!(graph.edges["main"].iter().collect::<Vec<_>>(), vec!["helper"]);
+    assert_eq!(graph.edges["<top-level>"].iter().collect::<Vec<_>>(), vec!["main"]);
+    assert_eq!(graph.edge_count(), 3);
+    assert!(graph.to_dot().contains("  \"main\" -> \"helper\";"));
+    assert!(build_call_graph(&matches, true).edges["main"].contains("print"));
+}
+
+#[test]
+fn test_outline() {
+    use ast_grep_mcp::outline::{build_outline, outline_rules};
+
+    let rules = outline_rules("rust").unwrap();
+    assert!(rules.contains("id: outline-impl") && rules.contains("field: type"));
+    assert!(!outline_rules("javascript").unwrap().contains("interface_declaration"));
+    assert!(outline_rules("cobol").is_none());
+
+    let item = |label: &str, name: &str, start: u64, end: u64| {
+        json!({"ruleId": format!("outline-{}", label), "range": {"start": {"line": start, "column": 0}, "end": {"line": end, "column": 1}},
+            "metaVariables": {"single": {"NAME": {"text": name}}}})
+    };
+    let matches = vec![
+        item("function", "helper", 12, 13),
+        item("function", "run", 2, 8),
+        item("class", "Job", 0, 10),
+        item("function", "step", 4, 6),
+    ];
+    let outline = build_outline(&matches);
+    assert_eq!(outline.len(), 2);
+    assert_eq!((outline[0].kind.as_str(), outline[0].name.as_str(), outline[0].start_line, outline[0].end_line), ("class", "Job", 1, 11));
+    let run = &outline[0].children[0];
+    assert_eq!((run.kind.as_str(), run.name.as_str()), ("method", "run"));
+    assert_eq!((run.children[0].kind.as_str(), run.children[0].name.as_str()), ("function", "step"));
+    assert_eq!((outline[1].kind.as_str(), outline[1].children.len()), ("function", 0));
+}
+
+#[test]
+fn test_aggregate_metavariable() {
+    use ast_grep_mcp::aggregate::{aggregate_metavariable, format_aggregate_as_text};
+
+    let import = |file: &str, module: &str| json!({"file": file, "metaVariables": {"single": {"MODULE": {"text": module}}, "multi": {}}});
+    let matches = vec![
+        import("a.py", "os"),
+        import("b.py", "os"),
+        import("b.py", "sys"),
+        import("c.py", "os"),
+        json!({"file": "d.py", "metaVariables": {"single": {}, "multi": {"ARGS": [{"text": "1"}, {"text": ","}, {"text": "x"}]}}}),
+    ];
+    let aggregate = aggregate_metavariable(&matches, "$MODULE");
+    assert_eq!((aggregate.metavariable.as_str(), aggregate.matches, aggregate.unbound, aggregate.distinct), ("$MODULE", 5, 1, 2));
+    assert_eq!((aggregate.values[0].value.as_str(), aggregate.values[0].count, aggregate.values[0].files), ("os", 3, 3));
+    assert_eq!((aggregate.values[1].value.as_str(), aggregate.values[1].count), ("sys", 1));
+    assert_eq!(format_aggregate_as_text(&aggregate).lines().next(), Some("     3  3 files   os"));
+
+    let args = aggregate_metavariable(&matches, "$$$ARGS");
+    assert_eq!((args.values.len(), args.values[0].value.as_str()), (1, "1, x"));
+}
+
+#[test]
+fn test_export_matches_to_sqlite() {
+    use ast_grep_mcp::export::export_matches;
+
+    let matches = vec![
+        json!({"file": "a.py", "ruleId": "r", "text": "import os", "fingerprint": "abc",
+            "range": {"start": {"line": 0, "column": 0}, "end": {"line": 0, "column": 9}, "byteOffset": {"start": 0, "end": 9}},
+            "metaVariables": {"single": {"M": {"text": "os"}}, "multi": {"ARGS": [{"text": "a"}, {"text": ","}, {"text": "b"}]}, "transformed": {}}}),
+        json!({"file": "b.py", "text": "import sys", "range": {"start": {"line": 3, "column": 0}, "end": {"line": 3, "column": 10}}}),
+    ];
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    let first = export_matches(&mut conn, "/p", "import $M", &matches).unwrap();
+    let second = export_matches(&mut conn, "/p", "import $M", &matches[1..]).unwrap();
+    assert_eq!((first, second), (1, 2));
+
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM matches WHERE scan_id = 1", [], |row| row.get(0)).unwrap();
+    assert_eq!(count, 2);
+    let (file, line, byte): (String, i64, Option<i64>) = conn
+        .query_row("SELECT file, start_line, start_byte FROM matches WHERE scan_id = 2", [], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap();
+    assert_eq!((file.as_str(), line, byte), ("b.py", 3, None));
+    let vars: Vec<(String, String, String)> = conn
+        .prepare("SELECT name, kind, text FROM metavariables ORDER BY name")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(vars, vec![
+        ("ARGS".to_string(), "multi".to_string(), "a, b".to_string()),
+        ("M".to_string(), "single".to_string(), "os".to_string()),
+    ]);
+}
+
+#[test]
+fn test_job_manager() {
+    use ast_grep_mcp::server::{JobManager, JobOutcome};
+    use rmcp::model::{CallToolResult, Content};
+    use std::time::Duration;
+
+    let jobs = JobManager::new(Duration::from_secs(60));
+    let id = jobs.submit("class $NAME", json!({"pattern": "class $NAME"}));
+    let status = jobs.status(&id).unwrap();
+    assert_eq!((status.state, status.query.as_str(), status.expires_in_s), ("running", "class $NAME", None));
+    assert!(matches!(jobs.take(&id), Some(JobOutcome::Running(_))));
+
+    let args = vec!["--pattern".to_string(), "class $NAME".to_string()];
+    jobs.add_partial(&id, &args, "plan", (0, Some(1)), (1, 3), &[json!({"file": "a.py"}), json!({"file": "b.py"})]);
+    jobs.add_partial(&id, &args, "plan", (0, Some(2)), (2, 3), &[json!({"file": "c.py"})]);
+    let partial = jobs.partial(&id, 0).unwrap();
+    assert_eq!((partial.status.parts_done, partial.status.parts_total, partial.status.partial_matches), (2, 3, 3));
+    assert_eq!((partial.next_since, partial.matches.len()), (3, 3));
+    let newer = jobs.partial(&id, 2).unwrap();
+    assert_eq!(newer.matches, vec![json!({"file": "c.py"})]);
+    assert!(jobs.partial(&id, 10).unwrap().matches.is_empty());
+
+    jobs.finish(&id, Ok(CallToolResult::success(vec![Content::text("Found 1 matches")])));
+    let status = jobs.status(&id).unwrap();
+    assert_eq!(status.state, "done");
+    assert!(status.expires_in_s.is_some_and(|s| s <= 60));
+    assert!(matches!(jobs.take(&id), Some(JobOutcome::Finished(Ok(_)))));
+    assert!(jobs.take(&id).is_none());
+
+    let failed = jobs.submit("x", json!({}));
+    jobs.finish(&failed, Err("boom".to_string()));
+    assert_eq!(jobs.status(&failed).unwrap().error.as_deref(), Some("boom"));
+
+    let expiring = JobManager::new(Duration::ZERO);
+    let id = expiring.submit("x", json!({}));
+    assert!(expiring.status(&id).is_some());
+    expiring.finish(&id, Err("boom".to_string()));
+    assert!(expiring.status(&id).is_none());
+}
+
+#[test]
+fn test_scan_checkpoints() {
+    use ast_grep_mcp::checkpoint::{checkpoint_path, load_checkpoint, plan_hash};
+    use ast_grep_mcp::server::JobManager;
+    use rmcp::model::{CallToolResult, Content};
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(checkpoint_path(dir.path(), "../escape").is_none());
+
+    let jobs = JobManager::new(Duration::from_secs(60)).with_checkpoint_dir(Some(dir.path().to_path_buf()));
+    let request = json!({"project_folder": "/p", "pattern": "class $NAME"});
+    let id = jobs.submit("class $NAME", request.clone());
+    assert!(jobs.checkpoint(&id).is_err(), "running jobs cannot be resumed");
+    let args = vec!["--pattern".to_string()];
+    let plan = plan_hash([["src".to_string()].as_slice(), &["tests".to_string()]]);
+    jobs.add_partial(&id, &args, &plan, (0, Some(1)), (1, 2), &[json!({"file": "a.py"})]);
+    let saved = load_checkpoint(dir.path(), &id).unwrap();
+    assert_eq!((saved.request, saved.completed, saved.matches.len()), (request, vec![(0, Some(1))], 1));
+
+    // Parts scanned with other arguments, e.g. a relaxed retry, replace the earlier ones
+    let relaxed = vec!["--pattern".to_string(), "--strictness".to_string()];
+    jobs.add_partial(&id, &relaxed, &plan, (0, Some(2)), (1, 2), &[]);
+    let saved = load_checkpoint(dir.path(), &id).unwrap();
+    assert_eq!((saved.args, saved.completed, saved.matches.len()), (relaxed.clone(), vec![(0, Some(2))], 0));
+
+    // Later parts are appended; a part cut short by a crash is left out
+    jobs.add_partial(&id, &relaxed, &plan, (0, Some(1)), (2, 2), &[json!({"file": "b.py"})]);
+    let path = checkpoint_path(dir.path(), &id).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    std::io::Write::write_all(&mut file, b"{\"parts\":[[0,3]],\"matc").unwrap();
+    let saved = load_checkpoint(dir.path(), &id).unwrap();
+    assert_eq!((saved.completed, saved.matches.len()), (vec![(0, Some(2)), (0, Some(1))], 1));
+
+    // A later server process sees the scan as interrupted and can resume it
+    let restarted = JobManager::new(Duration::from_secs(60)).with_checkpoint_dir(Some(dir.path().to_path_buf()));
+    let status = restarted.status(&id).unwrap();
+    assert_eq!((status.state, status.query.as_str(), status.parts_done, status.resumable), ("interrupted", "class $NAME", 2, true));
+    let checkpoint = restarted.checkpoint(&id).unwrap();
+    assert!(restarted.resume("class $NAME", checkpoint.clone()));
+    assert!(!restarted.resume("class $NAME", checkpoint));
+    assert_eq!(restarted.status(&id).unwrap().parts_done, 2);
+    assert_eq!(restarted.completed_parts(&id, &relaxed, &plan).unwrap().0.len(), 2);
+    // A project planned into other parts since cannot be resumed
+    let replanned = plan_hash([["src".to_string(), "tests".to_string()].as_slice()]);
+    assert!(restarted.completed_parts(&id, &relaxed, &replanned).unwrap_err().contains("submit a new scan"));
+
+    restarted.finish(&id, Ok(CallToolResult::success(vec![Content::text("done")])));
+    assert!(load_checkpoint(dir.path(), &id).is_err(), "successful scans delete their checkpoint");
+}
+
+#[test]
+fn test_watched_matches() {
+    use ast_grep_mcp::watch::{match_summary, WatchedMatches};
+
+    let found = |file: &str, line: u64, fingerprint: &str| {
+        json!({"file": file, "text": "eval(x)", "range": {"start": {"line": line}}, "fingerprint": fingerprint})
+    };
+    let mut watched = WatchedMatches::new(vec![found("/p/a.js", 1, "f1"), found("/p/b.js", 4, "f2")]);
+    assert_eq!(watched.len(), 2);
+
+    // Moving a match is not a change
+    let changes = watched.update(&["/p/a.js".to_string()], vec![found("/p/a.js", 9, "f1")]);
+    assert!(changes.is_empty());
+
+    let changes = watched.update(
+        &["/p/a.js".to_string()],
+        vec![found("/p/a.js", 9, "f1"), found("/p/a.js", 12, "f1"), found("/p/a.js", 20, "f3")],
+    );
+    assert_eq!((changes.added.len(), changes.removed.len()), (2, 0));
+    assert_eq!(match_summary(&changes.added[1])["line"], 21);
+
+    // Deleting a directory removes the matches of the files inside it
+    let changes = watched.update(&["/p".to_string()], vec![]);
+    assert_eq!((changes.added.len(), changes.removed.len()), (0, 4));
+    assert!(watched.is_empty());
+}
+
+#[tokio::test]
+async fn test_watch_registry() {
+    use ast_grep_mcp::watch::{MatchChanges, WatchInfo, WatchRegistry, WatchedMatches};
+    use std::sync::{Arc, Mutex};
+
+    let registry = WatchRegistry::default();
+    let info = |id: &str, folder: &str| WatchInfo {
+        watch_id: id.to_string(),
+        rule_id: "no-eval".to_string(),
+        yaml: "id: no-eval".to_string(),
+        project_folder: folder.to_string(),
+        include_vendored: false,
+        matches: 1,
+        added: 0,
+        removed: 0,
+        notifications: 0,
+    };
+    let matches = Arc::new(Mutex::new(WatchedMatches::new(vec![json!({"file": "/b/x.py", "fingerprint": "f"})])));
+    let task = tokio::spawn(std::future::pending::<()>());
+    registry.insert(info("w2", "/b"), matches.clone(), task.abort_handle(), None);
+    registry.insert(info("w1", "/a"), Arc::default(), tokio::spawn(async {}).abort_handle(), None);
+    let changes = MatchChanges { added: vec![json!({}), json!({})], removed: vec![] };
+    registry.record("w2", &changes, 3);
+
+    let listed = registry.list();
+    assert_eq!(listed.iter().map(|w| w.watch_id.as_str()).collect::<Vec<_>>(), ["w1", "w2"]);
+    assert_eq!((listed[1].matches, listed[1].added, listed[1].notifications), (3, 2, 1));
+
+    // Snapshots see the matches the watch task keeps current
+    matches.lock().unwrap().update(&["/b".to_string()], vec![json!({"file": "/b/y.py", "fingerprint": "g"})]);
+    let snapshot = registry.snapshot();
+    assert_eq!(snapshot[1].1, [json!({"file": "/b/y.py", "fingerprint": "g"})]);
+    assert!(snapshot[0].1.is_empty());
+
+    assert!(registry.remove("w2"));
+    assert!(!registry.remove("w2"));
+    assert!(task.await.unwrap_err().is_cancelled());
+}
+
+#[test]
+fn test_plan_watch_rescan() {
+    use ast_grep_mcp::watch::{plan_rescan, rule_languages, RescanPlan, WATCH_FULL_RESCAN_THRESHOLD, WATCH_RESCAN_CHUNK};
+
+    let languages = rule_languages(
+        "id: a\nlanguage: python\nrule: {pattern: eval($X)}\n---\nid: b\nlanguage: JavaScript\nrule: {pattern: eval($X)}\n",
+    );
+    assert_eq!(languages, ["python", "JavaScript"]);
+    assert!(rule_languages("id: a\nrule: {pattern: x}").is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = |name: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, "eval(x)").unwrap();
+        path.to_string_lossy().into_owned()
+    };
+    let deleted = dir.path().join("gone.py").to_string_lossy().into_owned();
+    let paths = vec![file("a.py"), file("b.js"), file("notes.md"), deleted.clone()];
+    assert_eq!(plan_rescan(&paths, &languages), RescanPlan::Paths(vec![paths[..2].to_vec()]));
+    assert_eq!(plan_rescan(&[deleted, paths[2].clone()], &languages), RescanPlan::Nothing);
+
+    // A branch switch touching many files is scanned in chunks, or as a whole folder
+    let many: Vec<String> = (0..WATCH_RESCAN_CHUNK + 1).map(|i| file(&format!("m{}.py", i))).collect();
+    match plan_rescan(&many, &languages) {
+        RescanPlan::Paths(groups) => assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), [WATCH_RESCAN_CHUNK, 1]),
+        plan => panic!("unexpected plan {:?}", plan),
+    }
+    let many: Vec<String> = (0..=WATCH_FULL_RESCAN_THRESHOLD).map(|i| file(&format!("m{}.py", i))).collect();
+    assert_eq!(plan_rescan(&many, &[]), RescanPlan::Folder);
+}
+
+#[test]
+fn test_watch_change_filter() {
+    use ast_grep_mcp::watch::ChangeFilter;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src/gen")).unwrap();
+    std::fs::write(root.join(".gitignore"), "*.log\nout/\n").unwrap();
+    std::fs::write(root.join("src/.gitignore"), "gen/\n!keep.log\n").unwrap();
+
+    let mut changes = ChangeFilter::new(root, false);
+    assert!(changes.is_scanned(&root.join("src/main.py")));
+    assert!(changes.is_scanned(&root.join("src/keep.log")), "a nested ignore file overrides the root's");
+    for ignored in ["debug.log", "out/a.py", "src/gen/a.py", "target/a.rs", "node_modules/x/a.js", ".git/index"] {
+        assert!(!changes.is_scanned(&root.join(ignored)), "{}", ignored);
+    }
+    assert!(ChangeFilter::new(root, true).is_scanned(&root.join("node_modules/x/a.js")));
+
+    // Edited ignore files are read again
+    std::fs::write(root.join(".gitignore"), "out/\n").unwrap();
+    assert!(!changes.is_scanned(&root.join(".gitignore")));
+    assert!(changes.is_scanned(&root.join("debug.log")));
+}
+
+#[test]
+fn test_config_watch_paths() {
+    use ast_grep_mcp::watch::config_watch_paths;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("sgconfig.yml");
+    assert_eq!(config_watch_paths(&config), vec![config.clone()]);
+
+    std::fs::create_dir(dir.path().join("rules")).unwrap();
+    std::fs::write(dir.path().join("mojo.so"), "").unwrap();
+    std::fs::write(
+        &config,
+        "ruleDirs: [rules, missing]\nutilDirs: []\ncustomLanguages:\n  mojo:\n    libraryPath: mojo.so\n    extensions: [mojo]\n",
+    )
+    .unwrap();
+    assert_eq!(config_watch_paths(&config), [config.clone(), dir.path().join("rules"), dir.path().join("mojo.so")]);
+}
+
+#[test]
+fn test_session_temp_dir() {
+    use ast_grep_mcp::tempfiles::{remove_all_session_dirs, SessionTempDir, TEMP_DIR_PREFIX};
+
+    let parent = tempfile::tempdir().unwrap();
+    let dir = SessionTempDir::new_in(parent.path()).unwrap();
+    assert!(dir.path().file_name().unwrap().to_string_lossy().starts_with(TEMP_DIR_PREFIX));
+    let file = dir.create_file(".yml", b"id: rule").unwrap();
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "id: rule");
+    assert!(file.to_string_lossy().ends_with(".yml"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(dir.path()).unwrap().permissions().mode() & 0o777, 0o700);
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    let path = dir.path().to_path_buf();
+    drop(dir);
+    assert!(!path.exists());
+
+    // Shutdown removes directories whose owners never got dropped
+    let leaked = std::mem::ManuallyDrop::new(SessionTempDir::new_in(parent.path()).unwrap());
+    remove_all_session_dirs();
+    assert!(!leaked.path().exists());
+}
+
+#[test]
+fn test_is_shell_invocation() {
+    use ast_grep_mcp::command::is_shell_invocation;
+
+    let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert!(is_shell_invocation("sh", &args(&["-c", "ast-grep run"])));
+    assert!(is_shell_invocation("/usr/bin/bash", &args(&["-e", "-c", "ls"])));
+    assert!(is_shell_invocation("CMD.EXE", &args(&["/c", "ast-grep"])));
+    assert!(is_shell_invocation("pwsh", &args(&["-NoProfile", "-Command", "ast-grep"])));
+    assert!(!is_shell_invocation("bash", &args(&["build.sh", "-c"])));
+    assert!(!is_shell_invocation("ast-grep", &args(&["run", "-c", "sgconfig.yml"])));
+    assert!(!is_shell_invocation("git", &args(&["clone", "--depth", "1"])));
+}
+
+#[tokio::test]
+async fn test_end_session_releases_resources() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::server::AstGrepServer;
+    use ast_grep_mcp::session::SessionRegistry;
+    use clap::Parser;
+    use std::sync::Arc;
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    let sessions = Arc::new(SessionRegistry::default());
+    let first = AstGrepServer::with_sessions(config.clone(), sessions.clone());
+    let second = AstGrepServer::with_sessions(config.clone(), sessions.clone());
+    assert_eq!(sessions.len(), 2);
+    // A session whose client never initialized closes with its server
+    let uninitialized = AstGrepServer::with_sessions(config, sessions.clone());
+    let clone = uninitialized.clone();
+    drop(uninitialized);
+    assert_eq!(sessions.len(), 3);
+    drop(clone);
+    assert_eq!(sessions.len(), 2);
+
+    let temp_dir = first.temp_dir().unwrap().path().to_path_buf();
+    assert!(temp_dir.is_dir());
+    first.end_session();
+    assert!(!temp_dir.exists());
+    assert_eq!(sessions.len(), 1);
+
+    // Ending a session twice is harmless
+    first.end_session();
+    assert_eq!(sessions.list().len(), 1);
+    second.end_session();
+    assert!(sessions.is_empty());
+}
+
+#[test]
+fn test_session_quotas() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::session::{SessionQuotas, SessionRegistry};
+    use ast_grep_mcp::store::MatchStore;
+    use clap::Parser;
+    use std::sync::Arc;
+
+    let cli = Cli::parse_from(["ast-grep-mcp-server", "--max-client-scans", "1", "--max-client-watches", "2"]);
+    let quotas = Config::from_args_with_env(&cli, |_| None).unwrap().quotas;
+    assert_eq!(quotas, SessionQuotas { max_scans: Some(1), max_cache_bytes: None, max_watches: 2 });
+
+    let sessions = Arc::new(SessionRegistry::new(SessionQuotas { max_cache_bytes: Some(100), ..quotas }));
+    let (greedy, other) = (sessions.open(), sessions.open());
+    let slot = sessions.start_scan(&greedy).unwrap();
+    assert!(sessions.start_scan(&greedy).is_err(), "a client cannot exceed its scan quota");
+    let _other_slot = sessions.start_scan(&other).unwrap();
+    assert_eq!(sessions.list().iter().map(|s| s.scans).collect::<Vec<_>>(), [1, 1]);
+    drop(slot);
+    assert!(sessions.start_scan(&greedy).is_ok());
+
+    // A client that opens more sessions shares its quotas among them
+    let (first, second, stranger) = (sessions.open(), sessions.open(), sessions.open());
+    sessions.set_identity(&first, "sub:alice");
+    sessions.set_identity(&second, "sub:alice");
+    sessions.set_identity(&stranger, "sub:bob");
+    let _scan = sessions.start_scan(&first).unwrap();
+    assert!(sessions.start_scan(&second).is_err());
+    assert!(sessions.start_scan(&stranger).is_ok());
+    let _watches = (sessions.start_watch(&first).unwrap(), sessions.start_watch(&second).unwrap());
+    assert!(sessions.start_watch(&first).is_err());
+    assert!(sessions.start_watch(&stranger).is_ok());
+    sessions.set_cache_bytes(&first, 70);
+    assert_eq!(sessions.cache_budget(&second), Some(30));
+    assert_eq!(sessions.cache_budget(&stranger), Some(100));
+    sessions.close(&first);
+    assert_eq!(sessions.cache_budget(&second), Some(100));
+
+    let store = MatchStore::new(100).with_max_bytes(Some(250));
+    let found = |at: u64| {
+        json!({"file": "a.py", "text": "eval(user_input)", "range": {"byteOffset": {"start": at, "end": at + 16}}})
+    };
+    let mut matches: Vec<_> = (0..5).map(found).collect();
+    store.remember(&mut matches);
+    assert!(store.bytes() <= 250);
+    assert!(store.get(matches[4]["id"].as_str().unwrap()).is_some());
+    assert!(store.get(matches[0]["id"].as_str().unwrap()).is_none(), "the oldest matches are evicted first");
+}
+
+#[test]
+fn test_cors_origins() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::http::cors_layer;
+    use clap::Parser;
+
+    let parse = |origins: &[&str]| {
+        let mut args = vec!["ast-grep-mcp-server"];
+        for origin in origins {
+            args.extend(["--cors-origin", origin]);
+        }
+        Config::from_args_with_env(&Cli::parse_from(args), |_| None)
+    };
+    assert!(parse(&[]).unwrap().cors_origins.is_empty());
+    let config = parse(&["https://app.example.com", "http://localhost:5173"]).unwrap();
+    assert_eq!(config.cors_origins, ["https://app.example.com", "http://localhost:5173"]);
+    assert!(parse(&["*"]).is_ok());
+    for invalid in ["app.example.com", "https://app.example.com/", "https://app.example.com/mcp", "file:///tmp"] {
+        assert!(parse(&[invalid]).is_err(), "{} should be rejected", invalid);
+    }
+
+    assert!(cors_layer(&[]).unwrap().is_none(), "no CORS headers unless origins are configured");
+    assert!(cors_layer(&config.cors_origins).unwrap().is_some());
+}
+
+#[test]
+fn test_jwt_verification_and_scopes() {
+    use ast_grep_mcp::auth::{verify_jwt, Jwk, Jwks, OAuthConfig};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+    let point = key_pair.public_key().as_ref();
+    let jwks = Jwks {
+        keys: vec![Jwk {
+            kty: "EC".to_string(),
+            kid: Some("key-1".to_string()),
+            crv: Some("P-256".to_string()),
+            n: None,
+            e: None,
+            x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+            y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+        }],
+    };
+    let sign = |claims: serde_json::Value| {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "ES256", "kid": "key-1", "typ": "JWT"}).to_string());
+        let message = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let signature = key_pair.sign(&rng, message.as_bytes()).unwrap();
+        format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    };
+    let oauth = OAuthConfig {
+        issuer: "https://auth.example.com".to_string(),
+        audience: Some("ast-grep-mcp".to_string()),
+        jwks_uri: None,
+        write_scope: "ast-grep:write".to_string(),
+    };
+    let now = 1_700_000_000;
+
+    let reader = sign(json!({
+        "iss": "https://auth.example.com", "aud": "ast-grep-mcp", "sub": "alice", "exp": now + 300, "scope": "openid",
+    }));
+    let grant = verify_jwt(&reader, &jwks, &oauth, now).unwrap();
+    assert_eq!((grant.subject.as_deref(), grant.client.as_deref()), (Some("alice"), Some("sub:alice")));
+    assert!(!grant.may_write(&oauth.write_scope));
+
+    let writer = sign(json!({
+        "iss": "https://auth.example.com", "aud": ["other", "ast-grep-mcp"], "exp": now + 300,
+        "scp": ["ast-grep:write"],
+    }));
+    assert!(verify_jwt(&writer, &jwks, &oauth, now).unwrap().may_write(&oauth.write_scope));
+
+    let wrong_audience = sign(json!({"iss": "https://auth.example.com", "aud": "other", "exp": now + 300}));
+    assert!(verify_jwt(&wrong_audience, &jwks, &oauth, now).is_err());
+    let wrong_issuer = sign(json!({"iss": "https://evil.example.com", "aud": "ast-grep-mcp", "exp": now + 300}));
+    assert!(verify_jwt(&wrong_issuer, &jwks, &oauth, now).is_err());
+    let expired = sign(json!({"iss": "https://auth.example.com", "aud": "ast-grep-mcp", "exp": now - 120}));
+    assert!(verify_jwt(&expired, &jwks, &oauth, now).is_err());
+
+    // a token whose claims were changed after signing
+    let (signed, signature) = reader.rsplit_once('.').unwrap();
+    let (header, _) = signed.split_once('.').unwrap();
+    let forged_claims = json!({
+        "iss": "https://auth.example.com", "aud": "ast-grep-mcp", "exp": now + 300, "scope": "ast-grep:write",
+    });
+    let forged = format!("{}.{}.{}", header, URL_SAFE_NO_PAD.encode(forged_claims.to_string()), signature);
+    assert!(verify_jwt(&forged, &jwks, &oauth, now).is_err());
+    let unsigned = format!("{}.{}.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#), URL_SAFE_NO_PAD.encode(forged_claims.to_string()));
+    assert!(verify_jwt(&unsigned, &jwks, &oauth, now).is_err());
+}
+
+#[tokio::test]
+async fn test_static_bearer_tokens() {
+    use ast_grep_mcp::auth::{AuthConfig, AuthError, Authenticator};
+    use ast_grep_mcp::config::{Cli, Config};
+    use clap::Parser;
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |name| {
+        (name == "AST_GREP_MCP_AUTH_TOKEN").then(|| "s3cret".to_string())
+    })
+    .unwrap();
+    assert_eq!(config.auth.tokens, ["s3cret"]);
+    assert!(config.auth.oauth.is_none());
+    let config = Config::from_args_with_env(
+        &Cli::parse_from(["ast-grep-mcp-server", "--oauth-issuer", "https://auth.example.com"]),
+        |_| None,
+    )
+    .unwrap();
+    assert_eq!(config.auth.oauth.unwrap().write_scope, "ast-grep:write");
+    assert!(Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server", "--oauth-issuer", "auth"]), |_| None).is_err());
+
+    let tokens = vec!["s3cret".to_string(), "other".to_string()];
+    let authenticator = Authenticator::new(AuthConfig { tokens, oauth: None });
+    let grant = authenticator.authenticate(Some("Bearer s3cret")).await.unwrap();
+    assert!(grant.may_write("ast-grep:write"), "static tokens may call every tool");
+    let other = authenticator.authenticate(Some("Bearer other")).await.unwrap();
+    assert_eq!((grant.client.as_deref(), other.client.as_deref()), (Some("token:0"), Some("token:1")));
+    assert!(matches!(authenticator.authenticate(None).await, Err(AuthError::Missing)));
+    assert!(matches!(authenticator.authenticate(Some("Bearer wrong")).await, Err(AuthError::Invalid(_))));
+    assert!(authenticator.authenticate(Some("Basic s3cret")).await.is_err());
+}
+
+#[test]
+fn test_tool_availability() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::server::AstGrepServer;
+    use ast_grep_mcp::toolset::{unavailable_reason, AST_GREP_TOOLS, WRITE_TOOLS};
+    use clap::Parser;
+    use rmcp::ServerHandler;
+
+    let parse = |args: &[&str]| {
+        let mut argv = vec!["ast-grep-mcp-server"];
+        argv.extend(args);
+        Config::from_args_with_env(&Cli::parse_from(argv), |_| None).unwrap()
+    };
+    let server = AstGrepServer::new(parse(&[]));
+    for tool in WRITE_TOOLS.iter().chain(AST_GREP_TOOLS) {
+        assert!(server.get_tool(tool).is_some(), "{} is not a tool", tool);
+    }
+    assert!(server.get_tool("doctor").is_some());
+
+    let read_only = AstGrepServer::new(parse(&["--read-only"]));
+    for tool in WRITE_TOOLS {
+        assert!(read_only.get_tool(tool).is_none(), "{} is routed in read-only mode", tool);
+    }
+    assert!(read_only.get_tool("find_code").is_some());
+
+    assert!(unavailable_reason("license_header", true, true).is_some());
+    assert!(unavailable_reason("license_header", false, false).is_none(), "does not run ast-grep");
+    assert!(unavailable_reason("find_code", false, false).is_some());
+    assert!(unavailable_reason("find_code", true, true).is_none());
+    for tool in ["doctor", "read_file_range", "get_server_stats", "scan_status"] {
+        assert!(unavailable_reason(tool, true, false).is_none(), "{} should always be offered", tool);
+    }
+}
+
+#[test]
+fn test_tool_deprecations() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::server::AstGrepServer;
+    use ast_grep_mcp::toolset::{call_deprecations, deprecate, Deprecation};
+    use clap::Parser;
+    use rmcp::ServerHandler;
+
+    let parse = |args: &[&str]| {
+        let mut argv = vec!["ast-grep-mcp-server"];
+        argv.extend(args);
+        Config::from_args_with_env(&Cli::parse_from(argv), |_| None).unwrap()
+    };
+    let server = AstGrepServer::new(parse(&[]));
+    assert!(server.get_tool("unwatch").unwrap().meta.is_none());
+    let hidden = AstGrepServer::new(parse(&["--hide-deprecated"]));
+    assert!(hidden.get_tool("unwatch").is_some());
+
+    let retired = Deprecation { tool: "unwatch", parameter: None, since: "0.2.0", replacement: "stop_watch" };
+    let mut tool = server.get_tool("unwatch").unwrap();
+    deprecate(&mut tool, &retired, false);
+    assert!(tool.description.as_deref().unwrap().starts_with("Deprecated since 0.2.0: use `stop_watch` instead."));
+    assert_eq!(tool.meta.as_ref().unwrap().get("deprecated").unwrap()["replacement"], "stop_watch");
+
+    let renamed = Deprecation { tool: "find_code", parameter: Some("max_results"), since: "0.2.0", replacement: "limit" };
+    let mut tool = server.get_tool("find_code").unwrap();
+    deprecate(&mut tool, &renamed, false);
+    let property = &tool.input_schema["properties"]["max_results"];
+    assert_eq!(property["deprecated"], true);
+    assert!(property["description"].as_str().unwrap().starts_with("Deprecated since 0.2.0: use `limit` instead. "));
+    assert_eq!(tool.meta.unwrap().get("deprecated").unwrap()["parameters"]["max_results"]["replacement"], "limit");
+    let mut tool = server.get_tool("find_code").unwrap();
+    deprecate(&mut tool, &renamed, true);
+    assert!(tool.input_schema["properties"].get("max_results").is_none());
+    assert!(tool.input_schema["properties"].get("pattern").is_some());
+
+    let deprecations = [renamed];
+    let with = json!({"pattern": "x", "max_results": 3});
+    assert_eq!(call_deprecations(&deprecations, "find_code", with.as_object()).len(), 1);
+    assert!(call_deprecations(&deprecations, "find_code", json!({"pattern": "x"}).as_object()).is_empty());
+    assert!(call_deprecations(&deprecations, "find_code_by_rule", with.as_object()).is_empty());
+}
+
+#[cfg(feature = "plugins")]
+#[tokio::test]
+async fn test_tool_plugins() {
+    use ast_grep_mcp::auth::{Grant, OAuthConfig};
+    use ast_grep_mcp::call::{call_tool, render_result};
+    use ast_grep_mcp::config::{CallArgs, Cli, Config};
+    use ast_grep_mcp::plugin::{PluginRoute, ToolPlugin};
+    use ast_grep_mcp::server::AstGrepServer;
+    use clap::Parser;
+    use rmcp::handler::server::router::tool::ToolRoute;
+    use rmcp::handler::server::tool::ToolCallContext;
+    use rmcp::model::{CallToolResult, Content, Tool};
+    use rmcp::ServerHandler;
+    use std::sync::Arc;
+
+    struct Catalog;
+    impl ToolPlugin for Catalog {
+        fn name(&self) -> &str {
+            "catalog"
+        }
+
+        fn routes(&self, config: &Config) -> Vec<PluginRoute> {
+            let schema = Arc::new(json!({"type": "object", "properties": {}}).as_object().unwrap().clone());
+            let mut routes = vec![PluginRoute::new(ToolRoute::new_dyn(
+                Tool::new("list_catalog_rules", "Lists the catalog's rules", schema.clone()),
+                |context: ToolCallContext<'_, AstGrepServer>| {
+                    let read_only = context.service.config().read_only;
+                    Box::pin(async move {
+                        let text = format!("no-eval (read_only: {})", read_only);
+                        Ok(CallToolResult::success(vec![Content::text(text)]))
+                    })
+                },
+            ))];
+            routes.push(PluginRoute::writing(ToolRoute::new_dyn(
+                Tool::new("install_catalog_rules", "Copies the catalog's rules into a project", schema.clone()),
+                |_| Box::pin(async { Ok(CallToolResult::success(vec![Content::text("installed")])) }),
+            )));
+            if !config.read_only {
+                let shadow = ToolRoute::new_dyn(Tool::new("find_code", "Shadows a built-in", schema), |_| {
+                    Box::pin(async { Ok(CallToolResult::success(vec![Content::text("shadowed")])) })
+                });
+                routes.push(PluginRoute::new(shadow));
+            }
+            routes
+        }
+    }
+
+    let mut config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    config.plugins.add(Catalog);
+    assert_eq!(format!("{:?}", config.plugins), r#"["catalog"]"#);
+
+    let server = AstGrepServer::new(config.clone());
+    assert_eq!(server.get_tool("list_catalog_rules").unwrap().description.as_deref(), Some("Lists the catalog's rules"));
+    assert!(server.get_tool("find_code").unwrap().description.as_deref().unwrap().contains("ast-grep"));
+
+    assert!(server.get_tool("install_catalog_rules").is_some());
+
+    let args = CallArgs { tool: "list_catalog_rules".to_string(), args: Vec::new() };
+    let result = call_tool(config.clone(), &args).await.unwrap();
+    assert_eq!(render_result(&result), "no-eval (read_only: false)");
+
+    // With OAuth, a plugin tool that writes files needs the write scope, like the built-in ones
+    let mut oauth = config.clone();
+    oauth.auth.oauth = Some(OAuthConfig {
+        issuer: "https://auth.example.com".to_string(),
+        audience: None,
+        jwks_uri: None,
+        write_scope: "ast-grep:write".to_string(),
+    });
+    let server = AstGrepServer::new(oauth);
+    let reader = Grant { subject: None, client: None, scopes: Some(vec!["openid".to_string()]) };
+    assert!(server.may_call(&reader, "list_catalog_rules"));
+    assert!(!server.may_call(&reader, "install_catalog_rules"));
+    assert!(!server.may_call(&reader, "license_header"));
+    let writer = Grant { scopes: Some(vec!["ast-grep:write".to_string()]), ..reader };
+    assert!(server.may_call(&writer, "install_catalog_rules"));
+
+    // A plugin tool that writes files is left out in read-only mode, like the built-in ones
+    config.read_only = true;
+    let server = AstGrepServer::new(config.clone());
+    assert!(server.get_tool("list_catalog_rules").is_some());
+    assert!(server.get_tool("install_catalog_rules").is_none());
+    let doctor = CallArgs { tool: "doctor".to_string(), args: Vec::new() };
+    let report = render_result(&call_tool(config, &doctor).await.unwrap());
+    assert!(report.contains("install_catalog_rules"), "{}", report);
+}
+
+#[test]
+fn test_rule_resources() {
+    use ast_grep_mcp::resources::{list_resources, read_resource, RULE_CHEATSHEET_URI, RULE_SCHEMA, RULE_SCHEMA_URI};
+    use rmcp::model::ResourceContents;
+
+    let uris: Vec<String> = list_resources().into_iter().map(|resource| resource.raw.uri).collect();
+    assert_eq!(uris, [RULE_SCHEMA_URI, RULE_CHEATSHEET_URI]);
+    assert!(read_resource("astgrep://schema/unknown").is_none());
+
+    let schema: serde_json::Value = serde_json::from_str(RULE_SCHEMA).unwrap();
+    assert_eq!(schema["required"], json!(["id", "language", "rule"]));
+    for key in ["pattern", "kind", "regex", "inside", "has", "all", "any", "not", "matches"] {
+        assert!(schema["definitions"]["Rule"]["properties"].get(key).is_some(), "{} missing", key);
+    }
+    assert!(schema["definitions"]["Relation"]["properties"].get("stopBy").is_some());
+
+    let Some(ResourceContents::TextResourceContents { text, mime_type, .. }) = read_resource(RULE_CHEATSHEET_URI) else {
+        panic!("the cheat sheet is text");
+    };
+    assert_eq!(mime_type.as_deref(), Some("text/markdown"));
+    assert!(text.contains("stopBy: end"));
+}
+
+#[test]
+fn test_rule_schema_validation() {
+    use ast_grep_mcp::schema::validate_rule_yaml;
+
+    let valid = "\
+id: no-eval
+language: python
+rule:
+  pattern: eval($A)
+  inside: { kind: function_definition, stopBy: end }
+  not: { has: { pattern: { context: 'x = 1', selector: expression_statement } } }
+constraints: { A: { kind: string } }
+transform: { B: { convert: { source: $A, toCase: camelCase } }, C: 'substring($A, startChar=1)' }
+fix: { template: '', expandEnd: { regex: ',' } }
+severity: warning
+---
+id: second
+language: python
+rule: { any: [{ kind: call }, { nthChild: { position: 1, reverse: true } }] }
+";
+    assert_eq!(validate_rule_yaml(valid), Ok(()));
+
+    let violations = validate_rule_yaml("id: x\nlanguage: python\nrule:\n  all:\n    - kind: call\n    - patern: foo($A)\n")
+        .unwrap_err();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].to_string(), "unknown key 'patern' at rule.all[1]; did you mean 'pattern'?");
+
+    let violations = validate_rule_yaml("language: python\nrule: { inside: { kind: call, stopBy: ends } }\nseverity: 1")
+        .unwrap_err();
+    let messages: Vec<String> = violations.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        messages,
+        [
+            "missing required key 'id' at the top level",
+            "'ends' is not one of: neighbor, end at rule.inside.stopBy",
+            "1 is not one of: hint, info, warning, error, off at severity",
+        ]
+    );
+
+    // `stopBy` belongs to relational rules only
+    let violations = validate_rule_yaml("id: x\nlanguage: python\nrule: { kind: call, stopBy: end }").unwrap_err();
+    assert_eq!(violations[0].path, "rule");
+    assert_eq!(violations[0].message, "unknown key 'stopBy'");
+
+    let violations = validate_rule_yaml("id: a\nlanguage: python\nrule: { kind: call }\n---\nid: b\nrule: []").unwrap_err();
+    let messages: Vec<String> = violations.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        messages,
+        ["document 2: missing required key 'language' at the top level", "document 2: expected an object, found an array at rule"]
+    );
+    assert!(validate_rule_yaml("id: [unclosed").is_err());
+}
+
+#[test]
+fn test_matches_to_diagnostics() {
+    use ast_grep_mcp::diagnostics::matches_to_diagnostics;
+
+    let matches = vec![
+        json!({
+            "file": "src/b.ts",
+            "text": "eval(x)",
+            "lines": "const s = \"😀\"; eval(x)",
+            "range": { "start": { "line": 3, "column": 15 }, "end": { "line": 3, "column": 22 } },
+            "ruleId": "no-eval",
+            "severity": "error",
+            "message": "Do not use eval",
+            "note": "eval runs arbitrary code",
+            "labels": [
+                { "text": "eval(x)", "style": "primary", "range": { "start": { "line": 3, "column": 15 }, "end": { "line": 3, "column": 22 } } },
+                { "text": "x", "style": "secondary", "message": "tainted input",
+                  "range": { "start": { "line": 3, "column": 20 }, "end": { "line": 3, "column": 21 } } },
+            ],
+        }),
+        json!({
+            "file": "src/a.ts",
+            "text": "foo()",
+            "range": { "start": { "line": 0, "column": 0 }, "end": { "line": 0, "column": 5 } },
+        }),
+        json!({ "file": "src/a.ts", "text": "no range" }),
+    ];
+    let published = matches_to_diagnostics(&matches, std::path::Path::new("/project"));
+
+    assert_eq!(published.len(), 2);
+    assert_eq!(published[0]["uri"], "file:///project/src/a.ts");
+    assert_eq!(
+        published[0]["diagnostics"],
+        json!([{
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 5 } },
+            "severity": 3,
+            "source": "ast-grep",
+            "message": "Matched pattern",
+        }])
+    );
+
+    let diagnostic = &published[1]["diagnostics"][0];
+    assert_eq!(published[1]["uri"], "file:///project/src/b.ts");
+    // The emoji before the match is two UTF-16 code units
+    assert_eq!(diagnostic["range"]["start"], json!({ "line": 3, "character": 16 }));
+    assert_eq!(diagnostic["range"]["end"], json!({ "line": 3, "character": 23 }));
+    assert_eq!(diagnostic["severity"], 1);
+    assert_eq!(diagnostic["code"], "no-eval");
+    assert_eq!(diagnostic["message"], "Do not use eval");
+    assert_eq!(diagnostic["data"], json!({ "note": "eval runs arbitrary code" }));
+    assert_eq!(
+        diagnostic["relatedInformation"],
+        json!([{
+            "location": {
+                "uri": "file:///project/src/b.ts",
+                "range": { "start": { "line": 3, "character": 21 }, "end": { "line": 3, "character": 22 } },
+            },
+            "message": "tainted input",
+        }])
+    );
+}
+
+#[test]
+fn test_refine_prompt_and_reply() {
+    use ast_grep_mcp::refine::{extract_pattern, refine_prompt, RefineAttempt};
+
+    assert_eq!(extract_pattern("```python\nprint($A)\n```\nThis matches any call."), Some("print($A)".to_string()));
+    assert_eq!(extract_pattern("  `foo($$$ARGS)`  "), Some("foo($$$ARGS)".to_string()));
+    assert_eq!(extract_pattern("```\n\n```"), None);
+
+    let attempts = vec![
+        RefineAttempt {
+            pattern: "if ($A) {".to_string(),
+            problem: Some("does not parse cleanly as javascript".to_string()),
+            matches: 0,
+            dump: "Debug Pattern:\nERROR (0,0)-(0,9)\n".to_string(),
+        },
+        RefineAttempt {
+            pattern: "if ($A) {}".to_string(),
+            problem: Some("does not match the example code".to_string()),
+            matches: 0,
+            dump: "Debug Pattern:\nif_statement (0,0)-(0,10)\n".to_string(),
+        },
+    ];
+    let prompt = refine_prompt("javascript", &attempts, Some(("if (x) { y() }", "program (0,0)-(0,14)")));
+    assert!(prompt.starts_with("This ast-grep pattern for javascript does not work."));
+    assert!(prompt.contains("Pattern:\n```\nif ($A) {\n```\nProblem: does not parse cleanly as javascript"));
+    assert!(prompt.contains("Attempt 1:\n```\nif ($A) {}\n```"));
+    // Only the last attempt's dump is included
+    assert!(prompt.contains("if_statement (0,0)-(0,10)") && !prompt.contains("ERROR (0,0)"));
+    assert!(prompt.contains("It should match this code:\n```\nif (x) { y() }\n```"));
+}
+
+#[test]
+fn test_pattern_playground_divergence() {
+    use ast_grep_mcp::playground::{enclosing_node, first_divergence, pattern_root, render_dump};
+    use ast_grep_mcp::suggest::parse_dump;
+
+    let code = "print(x, y)\n";
+    let code_root = parse_dump(
+        "Debug CST:
+module (0,0)-(1,0)
+  expression_statement (0,0)-(0,11)
+    call (0,0)-(0,11)
+      function: identifier (0,0)-(0,5)
+      arguments: argument_list (0,5)-(0,11)
+        ( (0,5)-(0,6)
+        identifier (0,6)-(0,7)
+        , (0,7)-(0,8)
+        identifier (0,9)-(0,10)
+        ) (0,10)-(0,11)
+",
+    )
+    .unwrap();
+    let call_pattern = |pattern: &str, argument: &str| {
+        let end = pattern.len();
+        parse_dump(&format!(
+            "module (0,0)-(0,{end})
+  expression_statement (0,0)-(0,{end})
+    call (0,0)-(0,{end})
+      function: identifier (0,0)-(0,{open})
+      arguments: argument_list (0,{open})-(0,{end})
+        ( (0,{open})-(0,{arg})
+        identifier (0,{arg})-(0,{close})
+        ) (0,{close})-(0,{end})
+",
+            open = pattern.find('(').unwrap(),
+            arg = pattern.find('(').unwrap() + 1,
+            close = pattern.find('(').unwrap() + 1 + argument.len(),
+        ))
+        .unwrap()
+    };
+
+    let pattern = call_pattern("print($A)", "$A");
+    assert_eq!(pattern_root(&pattern).kind, "call");
+    let divergence = first_divergence("print($A)", &pattern, code, &code_root).unwrap();
+    assert_eq!(divergence.reason, "expected a `)` node, found `identifier`");
+    let code_node = divergence.code_node.unwrap();
+    assert_eq!((code_node.text.as_str(), code_node.range.as_str()), ("y", "1:10-1:11"));
+    assert_eq!(divergence.candidate.unwrap().text, "print(x, y)");
+
+    let divergence = first_divergence("len($A)", &call_pattern("len($A)", "$A"), code, &code_root).unwrap();
+    assert_eq!(divergence.reason, "expected `len`, found `print`");
+    assert!(first_divergence("print($$$ARGS)", &call_pattern("print($$$ARGS)", "$$$ARGS"), code, &code_root).is_none());
+
+    let attribute = parse_dump("module (0,0)-(0,7)\n  expression_statement (0,0)-(0,7)\n    attribute (0,0)-(0,7)\n").unwrap();
+    let divergence = first_divergence("foo.bar", &attribute, code, &code_root).unwrap();
+    assert_eq!(divergence.reason, "the code has no `attribute` node, the kind of the pattern's root");
+
+    let region = enclosing_node(&code_root, (0, 6), (0, 7));
+    assert_eq!(render_dump(region), "identifier (0,6)-(0,7)\n");
+    let region = enclosing_node(&code_root, (0, 0), (0, 11));
+    assert!(render_dump(region).starts_with("call (0,0)-(0,11)\n  function: identifier (0,0)-(0,5)\n"));
+}
+
+#[test]
+fn test_client_match_from_json() {
+    use ast_grep_mcp::client::{Match, Position};
+
+    let m = Match::from_json(&json!({
+        "file": "src/app.py",
+        "text": "log(a, b)",
+        "range": {
+            "start": { "line": 2, "column": 4 },
+            "end": { "line": 2, "column": 13 },
+            "byteOffset": { "start": 30, "end": 39 },
+        },
+        "metaVariables": {
+            "single": { "F": { "text": "log" } },
+            "multi": { "ARGS": [{ "text": "a" }, { "text": "," }, { "text": "b" }] },
+        },
+        "ruleId": "no-log",
+        "severity": "warning",
+        "replacement": "logger.debug(a, b)",
+    }))
+    .unwrap();
+    assert_eq!(m.file, "src/app.py");
+    assert_eq!(m.range.start, Position { line: 2, column: 4 });
+    assert_eq!((m.range.byte_start, m.range.byte_end), (30, 39));
+    assert_eq!(m.metavariables["F"], "log");
+    assert_eq!(m.multi_metavariables["ARGS"], ["a", ",", "b"]);
+    assert_eq!(m.rule_id.as_deref(), Some("no-log"));
+    assert_eq!(m.replacement.as_deref(), Some("logger.debug(a, b)"));
+    assert_eq!(m.message, None);
+
+    assert!(Match::from_json(&json!({ "text": "no file" })).is_none());
+}
+
+#[test]
+fn test_find_missing_report() {
+    use ast_grep_mcp::missing::{candidate_files, missing_report};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for file in ["a_test.py", "b_test.py", "pkg/c_test.py", "notes.md", "node_modules/d.py", ".hidden/e.py"] {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "def test(): pass\n").unwrap();
+    }
+    std::fs::write(root.join("generated.py"), format!("x = '{}'\n", "a".repeat(20_000))).unwrap();
+    // Ignored files are left out like ast-grep leaves them out
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    std::fs::write(root.join(".gitignore"), "out/\n").unwrap();
+    std::fs::write(root.join(".sgignore"), "skip_test.py\n").unwrap();
+    for file in ["out/f_test.py", "skip_test.py"] {
+        std::fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+        std::fs::write(root.join(file), "def test(): pass\n").unwrap();
+    }
+
+    let candidates = candidate_files(root, "python", false);
+    let names: Vec<_> = candidates.iter().map(|path| path.strip_prefix(root).unwrap().to_path_buf()).collect();
+    assert_eq!(names, ["a_test.py", "b_test.py", "pkg/c_test.py"].map(std::path::PathBuf::from));
+    assert_eq!(candidate_files(root, "python", true).len(), 4);
+
+    // Relative and absolute match paths both count
+    let matches = vec![
+        json!({"file": "a_test.py", "text": "assert_ok()"}),
+        json!({"file": root.join("a_test.py").to_string_lossy(), "text": "assert_ok()"}),
+        json!({"file": root.join("pkg/c_test.py").to_string_lossy(), "text": "assert_ok()"}),
+    ];
+    let report = missing_report("python", &candidates, &matches, root, 0);
+    assert_eq!((report.files_checked, report.files_with_matches), (3, 2));
+    assert_eq!(report.missing, ["b_test.py"]);
+    assert_eq!(report.total_missing, None);
+
+    let report = missing_report("python", &candidates, &[], root, 2);
+    assert_eq!(report.missing, ["a_test.py", "b_test.py"]);
+    assert_eq!(report.total_missing, Some(3));
+    assert_eq!(report.files_with_matches, 0);
+}
+
+
+#[test]
+fn test_find_cooccurrences() {
+    use ast_grep_mcp::cooccur::{
+        cooccurrence_rules, find_cooccurrences, CooccurrenceMode, A_RULE_ID, B_RULE_ID, FUNCTION_RULE_ID,
+    };
+
+    let rules = cooccurrence_rules("python", "db.save($X)", "with transaction(): $$$", true).unwrap();
+    let documents: Vec<serde_yaml::Value> =
+        serde_yaml::Deserializer::from_str(&rules).map(|d| serde::Deserialize::deserialize(d).unwrap()).collect();
+    assert_eq!(documents.len(), 3);
+    assert_eq!(documents[1]["rule"]["pattern"], "with transaction(): $$$");
+    assert_eq!(documents[2]["rule"]["any"][0]["kind"], "function_definition");
+    assert!(cooccurrence_rules("nix", "a", "b", true).is_none());
+    assert!(cooccurrence_rules("nix", "a", "b", false).is_some());
+
+    let m = |rule: &str, file: &str, lines: (u64, u64), bytes: (u64, u64), text: &str| {
+        json!({
+            "ruleId": rule, "file": file, "text": text,
+            "range": {
+                "start": {"line": lines.0, "column": 0}, "end": {"line": lines.1, "column": 0},
+                "byteOffset": {"start": bytes.0, "end": bytes.1},
+            },
+        })
+    };
+    let matches = vec![
+        // save() guards its write; the write sits in a callback of the transaction
+        m(FUNCTION_RULE_ID, "a.py", (0, 5), (0, 100), "def save():\n    ..."),
+        m(FUNCTION_RULE_ID, "a.py", (2, 3), (40, 80), "def callback():"),
+        m(B_RULE_ID, "a.py", (1, 4), (20, 90), "with transaction():"),
+        m(A_RULE_ID, "a.py", (3, 3), (60, 70), "db.save(x)"),
+        // update() does not
+        m(FUNCTION_RULE_ID, "a.py", (7, 9), (120, 200), "def update():"),
+        m(A_RULE_ID, "a.py", (8, 8), (140, 150), "db.save(y)"),
+        m(A_RULE_ID, "a.py", (11, 11), (220, 230), "db.save(z)"),
+        m(A_RULE_ID, "b.py", (0, 0), (0, 10), "db.save(w)"),
+    ];
+
+    let report = find_cooccurrences(&matches, CooccurrenceMode::AWithoutB, true);
+    assert_eq!(report.results.len(), 1);
+    let result = &report.results[0];
+    assert_eq!((result.file.as_str(), result.a_lines.as_slice()), ("a.py", &[9][..]));
+    let function = result.function.as_ref().unwrap();
+    assert_eq!((function.lines.as_str(), function.signature.as_str()), ("8-10", "def update():"));
+    assert_eq!(report.a_outside_functions, 2);
+
+    let both = find_cooccurrences(&matches, CooccurrenceMode::Both, true);
+    assert_eq!(both.results.len(), 1);
+    assert_eq!((both.results[0].a_lines.as_slice(), both.results[0].b_lines.as_slice()), (&[4][..], &[2][..]));
+
+    let files = find_cooccurrences(&matches, CooccurrenceMode::AWithoutB, false);
+    assert_eq!(files.results.iter().map(|r| r.file.as_str()).collect::<Vec<_>>(), ["b.py"]);
+    assert!(files.results[0].function.is_none());
+}
+
+#[test]
+fn test_multi_language_rules() {
+    use ast_grep_mcp::multi::multi_language_rules;
+    use std::collections::BTreeMap;
+
+    let patterns = BTreeMap::from([
+        ("python".to_string(), "print($$$)".to_string()),
+        ("javascript".to_string(), "console.log($$$ARGS)".to_string()),
+    ]);
+    let rules = multi_language_rules(&patterns, &BTreeMap::new()).unwrap();
+    let documents: Vec<serde_yaml::Value> =
+        serde_yaml::Deserializer::from_str(&rules).map(|d| serde::Deserialize::deserialize(d).unwrap()).collect();
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[0]["id"], "find-code-javascript");
+    assert_eq!(documents[0]["rule"]["pattern"], "console.log($$$ARGS)");
+    assert_eq!(documents[1]["language"], "python");
+
+    let missing = BTreeMap::from([("go".to_string(), " ".to_string())]);
+    assert!(multi_language_rules(&missing, &BTreeMap::new()).unwrap_err().contains("'go'"));
+}
+
+#[test]
+fn test_find_code_constraints() {
+    use ast_grep_mcp::multi::{constrained_rule, multi_language_rules};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    let constraints = BTreeMap::from([
+        ("$NAME".to_string(), json!("^Test")),
+        ("ARGS".to_string(), json!({"kind": "identifier"})),
+    ]);
+    let rule: serde_yaml::Value =
+        serde_yaml::from_str(&constrained_rule("go", "func $NAME($$$ARGS) {}", &constraints).unwrap()).unwrap();
+    assert_eq!(rule["id"], "find-code");
+    assert_eq!(rule["constraints"]["NAME"]["regex"], "^Test");
+    assert_eq!(rule["constraints"]["ARGS"]["kind"], "identifier");
+
+    let unused = BTreeMap::from([("$NAMES".to_string(), json!("x"))]);
+    assert!(constrained_rule("go", "f($NAME)", &unused).unwrap_err().contains("$NAMES"));
+    let unsupported = BTreeMap::from([("$NAME".to_string(), json!({"inside": {"kind": "block"}}))]);
+    assert!(constrained_rule("go", "f($NAME)", &unsupported).unwrap_err().contains("'inside'"));
+
+    let patterns = BTreeMap::from([
+        ("python".to_string(), "def $NAME(): $$$".to_string()),
+        ("javascript".to_string(), "console.log($$$)".to_string()),
+    ]);
+    let name = BTreeMap::from([("$NAME".to_string(), json!("^test_"))]);
+    let rules = multi_language_rules(&patterns, &name).unwrap();
+    let documents: Vec<serde_yaml::Value> =
+        serde_yaml::Deserializer::from_str(&rules).map(|d| serde::Deserialize::deserialize(d).unwrap()).collect();
+    assert!(documents[0].get("constraints").is_none());
+    assert_eq!(documents[1]["constraints"]["NAME"]["regex"], "^test_");
+}
+
+#[test]
+fn test_detect_languages() {
+    use ast_grep_mcp::multi::{detect_languages, folder_languages, ranked_languages};
+    use std::path::PathBuf;
+
+    let files: Vec<PathBuf> =
+        ["src/a.py", "src/b.py", "web/app.ts", "web/main.js", "web/util.js", "package.json", "ci.yml", "README.md"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+    // Most files first, ties by name; data formats and unknown extensions left out
+    assert_eq!(detect_languages(&files), vec![("javascript", 2), ("python", 2), ("typescript", 1)]);
+    assert!(detect_languages(&[PathBuf::from("notes.txt")]).is_empty());
+
+    // A folder is walked like ast-grep walks it: vendored and ignored files do not count
+    let dir = tempfile::tempdir().unwrap();
+    for file in ["a.py", "b.py", "c.rs", "node_modules/d.js", "node_modules/e.js", "gen/f.go", "g.json"] {
+        let path = dir.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "x\n").unwrap();
+    }
+    std::fs::write(dir.path().join(".ignore"), "gen/\n").unwrap();
+    let counts = folder_languages(dir.path(), false);
+    assert_eq!(ranked_languages(counts), vec![("python", 2), ("rust", 1)]);
+    assert_eq!(folder_languages(dir.path(), true).get("javascript"), Some(&2));
+}
+
+#[test]
+fn test_filter_profiles() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::profiles::parse_filter_profiles;
+    use clap::Parser;
+
+    let profiles = parse_filter_profiles(
+        "tests-only:\n  globs: ['**/tests/**']\n  excludes: ['**/fixtures/**']\n\
+         frontend:\n  languages: [typescript, css]\n",
+    )
+    .unwrap();
+    assert_eq!(profiles.keys().collect::<Vec<_>>(), ["frontend", "tests-only"]);
+    assert_eq!(profiles["tests-only"].glob_args(), ["--globs", "**/tests/**", "--globs", "!**/fixtures/**"]);
+    assert!(profiles["frontend"].glob_args().is_empty());
+
+    let mut matches = vec![json!({"file": "src/app.ts"}), json!({"file": "src/app.py"}), json!({"file": "a.css"})];
+    assert_eq!(profiles["frontend"].retain_matches(&mut matches), 1);
+    assert_eq!(matches, vec![json!({"file": "src/app.ts"}), json!({"file": "a.css"})]);
+    assert!(parse_filter_profiles("tests:\n  paths: [x]\n").is_err());
+    assert!(parse_filter_profiles("").unwrap().is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("profiles.yaml");
+    std::fs::write(&path, "backend:\n  languages: [python]\n").unwrap();
+    let env_path = path.display().to_string();
+    let cli = Cli::parse_from(["ast-grep-mcp-server"]);
+    let config =
+        Config::from_args_with_env(&cli, |name| (name == "AST_GREP_MCP_FILTER_PROFILES").then(|| env_path.clone()))
+            .unwrap();
+    assert_eq!(config.filter_profiles["backend"].languages, ["python"]);
+    let missing = Cli::parse_from(["ast-grep-mcp-server", "--filter-profiles", "/nonexistent/profiles.yaml"]);
+    assert!(Config::from_args_with_env(&missing, |_| None).is_err());
+}
+
+#[test]
+fn test_insert_suppression() {
+    use ast_grep_mcp::header::comment_style;
+    use ast_grep_mcp::suppress::insert_suppression;
+
+    let js = comment_style("javascript").unwrap();
+    let source = "function f() {\n  console.log(x);\n}\n";
+    let suppression = insert_suppression(source, 2, "no-console", "Debug output is wanted here", js).unwrap();
+    assert_eq!(
+        suppression.source,
+        "function f() {\n  // Debug output is wanted here\n  // ast-grep-ignore: no-console\n  console.log(x);\n}\n"
+    );
+    assert_eq!(suppression.line, 4);
+
+    // A directive already above the finding gets the rule id added
+    let twice = insert_suppression(&suppression.source, 4, "no-debug", "Same", js).unwrap();
+    assert!(twice.source.contains("  // Same\n  // ast-grep-ignore: no-console, no-debug\n  console.log"));
+    assert_eq!(twice.line, 5);
+    assert!(insert_suppression(&twice.source, 5, "no-console", "Again", js).unwrap_err().contains("already"));
+
+    let python = comment_style("python").unwrap();
+    let crlf = insert_suppression("x = 1\r\nprint(x)\r\n", 2, "no-print", "CLI output", python).unwrap();
+    assert_eq!(crlf.source, "x = 1\r\n# CLI output\r\n# ast-grep-ignore: no-print\r\nprint(x)\r\n");
+    let css = comment_style("css").unwrap();
+    let block = insert_suppression("a { color: red; }\n", 1, "no-red", "Brand color", css).unwrap();
+    assert_eq!(block.lines.last().unwrap(), "/* ast-grep-ignore: no-red */");
+
+    assert!(insert_suppression(source, 9, "no-console", "Why", js).is_err());
+    assert!(insert_suppression(source, 2, "a, b", "Why", js).is_err());
+    assert!(insert_suppression(source, 2, "no-console", " ", js).is_err());
+}
+
+#[test]
+fn test_rule_effectiveness_report() {
+    use ast_grep_mcp::effectiveness::{effectiveness_report, last_matched, parse_rule_sources, rule_files};
+    use ast_grep_mcp::export::export_matches;
+    use std::collections::HashMap;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("style")).unwrap();
+    std::fs::write(
+        dir.path().join("style/print.yml"),
+        "id: no-print\nlanguage: python\nrule: {pattern: print($$$)}\n---\n\
+         id: no-eval\nlanguage: python\nrule: {pattern: eval($X)}\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("exec.yaml"), "id: no-exec\nlanguage: python\nrule: {pattern: exec($X)}\n").unwrap();
+    std::fs::write(dir.path().join("README.md"), "# rules\n").unwrap();
+    let mut rules = Vec::new();
+    for path in rule_files(dir.path()) {
+        let relative = path.strip_prefix(dir.path()).unwrap().to_string_lossy().into_owned();
+        rules.extend(parse_rule_sources(&std::fs::read_to_string(&path).unwrap(), &relative).unwrap());
+    }
+    assert_eq!(rules.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(), ["no-exec", "no-print", "no-eval"]);
+    assert_eq!(rules[1].file, "style/print.yml");
+
+    let matches = vec![
+        json!({"ruleId": "no-print", "file": "a.py"}),
+        json!({"ruleId": "no-print", "file": "a.py"}),
+        json!({"ruleId": "no-print", "file": "b.py"}),
+    ];
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    export_matches(&mut conn, "/proj", "rules", &[json!({"ruleId": "no-exec", "file": "c.py", "text": "exec(x)"})])
+        .unwrap();
+    export_matches(&mut conn, "/other", "rules", &[json!({"ruleId": "no-eval", "file": "c.py", "text": "eval(x)"})])
+        .unwrap();
+    let history = last_matched(&conn, "/proj").unwrap();
+    assert_eq!(history.keys().collect::<Vec<_>>(), ["no-exec"]);
+
+    let report = effectiveness_report(&rules, &matches, &history);
+    assert_eq!((report.rules_total, report.rules_matching), (3, 1));
+    assert_eq!(report.never_matched, ["no-eval"]);
+    let ids: Vec<&str> = report.rules.iter().map(|r| r.id.as_str()).collect();
+    assert_eq!(ids, ["no-eval", "no-exec", "no-print"]);
+    assert!(report.rules[1].last_matched.is_some());
+    assert_eq!((report.rules[2].matches, report.rules[2].files), (3, 2));
+
+    let without_history = effectiveness_report(&rules, &matches, &HashMap::new());
+    assert_eq!(without_history.never_matched, ["no-eval", "no-exec"]);
+}
+
+#[test]
+fn test_not_searched_summary() {
+    use ast_grep_mcp::filter::{not_searched, NotSearched, NotSearchedReason};
+    use ast_grep_mcp::sgconfig::custom_language_extensions;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for sub in ["src", "node_modules/pkg", ".cache", "out"] {
+        std::fs::create_dir_all(root.join(sub)).unwrap();
+    }
+    for file in ["src/a.py", "src/b.mojo", "README.md", ".env", "node_modules/pkg/i.js", "out/gen.py", "debug.log"] {
+        std::fs::write(root.join(file), "x\n").unwrap();
+    }
+    std::fs::write(root.join(".ignore"), "out/\ndebug.log\n").unwrap();
+
+    let summary = not_searched(root, false, false, &[]);
+    let examples = |reason| summary.0[&reason].examples.clone();
+    assert_eq!(examples(NotSearchedReason::Hidden), [".cache/", ".env", ".ignore"]);
+    assert_eq!(examples(NotSearchedReason::Vendored), ["node_modules/"]);
+    assert_eq!(examples(NotSearchedReason::Ignored), ["debug.log", "out/"]);
+    assert_eq!(examples(NotSearchedReason::UnsupportedLanguage), ["README.md", "src/b.mojo"]);
+    let text = summary.to_text();
+    assert!(text.starts_with("Not searched: 3 hidden (.cache/, .env, .ignore), 1 vendored (node_modules/)"));
+
+    let config = "customLanguages:\n  mojo:\n    libraryPath: mojo.so\n    extensions: [mojo, .🔥]\n";
+    let extensions = custom_language_extensions(config);
+    assert_eq!(extensions, ["mojo", "🔥"]);
+    let with_custom = not_searched(root, true, true, &extensions);
+    assert_eq!(with_custom.0[&NotSearchedReason::UnsupportedLanguage].examples, ["README.md", "debug.log"]);
+    assert!(!with_custom.0.contains_key(&NotSearchedReason::Vendored));
+    assert!(!with_custom.0.contains_key(&NotSearchedReason::Ignored));
+
+    let mut merged = NotSearched::default();
+    merged.merge(summary.clone(), std::path::Path::new("/other"));
+    assert_eq!(merged.0[&NotSearchedReason::Vendored].examples, ["/other/node_modules/"]);
+    assert_eq!(merged.0[&NotSearchedReason::Hidden].count, 3);
+}
+
+#[test]
+fn test_starter_sgconfig() {
+    use ast_grep_mcp::init::{starter_config, survey_project};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for sub in ["src", "lint/rules/security", "lint/rules/utils", "lint/tests", "node_modules/pkg"] {
+        std::fs::create_dir_all(root.join(sub)).unwrap();
+    }
+    let rule = "id: no-eval\nlanguage: python\nrule: {pattern: eval($X)}\n";
+    std::fs::write(root.join("lint/rules/security/no-eval.yml"), rule).unwrap();
+    std::fs::write(root.join("lint/rules/utils/helpers.yml"), rule).unwrap();
+    std::fs::write(root.join("lint/tests/no-eval-test.yml"), "id: no-eval\nvalid: [x]\ninvalid: [eval(x)]\n").unwrap();
+    for file in ["src/a.py", "src/b.py", "src/c.ts", "node_modules/pkg/i.js"] {
+        std::fs::write(root.join(file), "x\n").unwrap();
+    }
+    std::fs::write(root.join("docker-compose.yml"), "services: {}\n").unwrap();
+
+    let survey = survey_project(root);
+    assert_eq!(survey.rule_dirs, ["lint/rules/security"]);
+    assert_eq!(survey.util_dirs, ["lint/rules/utils"]);
+    assert_eq!(survey.test_dirs, ["lint/tests"]);
+    let languages: Vec<(&str, usize)> = survey.languages.iter().map(|l| (l.language.as_str(), l.files)).collect();
+    assert_eq!(languages, [("python", 2), ("typescript", 1), ("yaml", 1)]);
+
+    let (config, dirs) = starter_config(&survey);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&config).unwrap();
+    assert_eq!(parsed["ruleDirs"][0], "lint/rules/security");
+    assert_eq!(parsed["testConfigs"][0]["testDir"], "lint/tests");
+    assert!(config.contains("# Source files per language: python 2, typescript 1, yaml 1\n"));
+    assert_eq!(dirs.len(), 3);
+
+    let (empty, dirs) = starter_config(&Default::default());
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&empty).unwrap();
+    assert_eq!(parsed["utilDirs"][0], "utils");
+    assert_eq!(dirs, ["rules", "rule-tests", "utils"]);
+}
+
+#[test]
+fn test_inline_rule_with_utils() {
+    use ast_grep_mcp::inline::with_utils;
+    use std::collections::BTreeMap;
+
+    let yaml = "id: literal-arg\nlanguage: python\nrule:\n  kind: call\n  has: {matches: is-literal, stopBy: end}\n";
+    assert_eq!(with_utils(yaml, &BTreeMap::new()).unwrap(), yaml);
+
+    let utils = BTreeMap::from([
+        ("is-literal".to_string(), json!({"any": [{"kind": "string"}, {"kind": "integer"}]})),
+        ("is-name".to_string(), json!("kind: identifier")),
+    ]);
+    let combined = with_utils(&format!("{}---\n{}", yaml, yaml.replace("literal-arg", "second")), &utils).unwrap();
+    let documents: Vec<serde_yaml::Value> =
+        serde_yaml::Deserializer::from_str(&combined).map(|d| serde::Deserialize::deserialize(d).unwrap()).collect();
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[1]["id"], "second");
+    assert_eq!(documents[1]["utils"]["is-literal"]["any"][1]["kind"], "integer");
+    assert_eq!(documents[0]["utils"]["is-name"]["kind"], "identifier");
+
+    let local = "id: x\nlanguage: python\nutils:\n  is-name: {kind: name}\nrule: {matches: is-name}\n";
+    assert!(with_utils(local, &utils).unwrap_err().contains("'is-name'"));
+    let not_a_rule = BTreeMap::from([("bad".to_string(), json!(["kind"]))]);
+    assert!(with_utils(yaml, &not_a_rule).unwrap_err().contains("utils.bad"));
+}
+
+#[test]
+fn test_match_rewrite() {
+    use ast_grep_mcp::rewrite::match_rewrite;
+    use serde_json::json;
+
+    let m = json!({
+        "text": "log(a, b)",
+        "lines": "    log(a, b)",
+        "replacement": "logger.log(ctx, a, b, )",
+        "range": {"byteOffset": {"start": 4, "end": 13}, "start": {"line": 2, "column": 4}},
+        "metaVariables": {
+            "single": {"F": {"text": "log"}},
+            "multi": {"ARGS": [
+                {"text": "a", "range": {"byteOffset": {"start": 8, "end": 9}, "start": {"line": 2, "column": 8}}},
+                {"text": ",", "range": {"byteOffset": {"start": 9, "end": 10}, "start": {"line": 2, "column": 9}}},
+                {"text": "b", "range": {"byteOffset": {"start": 11, "end": 12}, "start": {"line": 2, "column": 11}}}
+            ]},
+            "transformed": {"CAMEL": "Log"}
+        }
+    });
+    let preview = match_rewrite(&m, "logger.$F(ctx, $$$ARGS, $EXTRA)").unwrap();
+    assert_eq!(preview.replacement, "logger.log(ctx, a, b, )");
+    assert_eq!(preview.lines_after.as_deref(), Some("    logger.log(ctx, a, b, )"));
+    assert_eq!(preview.metavariables["ARGS"], "a, b");
+    assert_eq!(preview.unbound, ["EXTRA"]);
+
+    let preview = match_rewrite(&m, "$CAMEL($$$)").unwrap();
+    assert_eq!(preview.metavariables["CAMEL"], "Log");
+    assert!(preview.unbound.is_empty());
+
+    // Without ast-grep's replacement there is nothing to preview
+    let mut unrewritten = m.clone();
+    unrewritten.as_object_mut().unwrap().remove("replacement");
+    assert!(match_rewrite(&unrewritten, "$F").is_none());
+}
+
+#[test]
+fn test_cap_matches_per_file() {
+    use ast_grep_mcp::format::{cap_matches_per_file, CappedFile};
+
+    let matches: Vec<serde_json::Value> = ["gen.js", "gen.js", "gen.js", "gen.js", "a.js", "b.js", "b.js"]
+        .iter()
+        .enumerate()
+        .map(|(line, file)| json!({"file": file, "range": {"start": {"line": line}}}))
+        .collect();
+    let (kept, capped) = cap_matches_per_file(matches, 2);
+    let files: Vec<&str> = kept.iter().map(|m| m["file"].as_str().unwrap()).collect();
+    assert_eq!(files, ["gen.js", "gen.js", "a.js", "b.js", "b.js"]);
+    assert_eq!(capped, [CappedFile { file: "gen.js".to_string(), matches: 4, shown: 2 }]);
+}
+
+#[test]
+fn test_unified_diff() {
+    use ast_grep_mcp::diff::{unified_diff, Edit};
+
+    let old = "a\nb\nc\nd\ne\nf\ng\n";
+    let edit = |start: usize, end: usize, replacement: &str| Edit { start, end, replacement: replacement.to_string() };
+    // Changes four lines apart get a hunk each with one line of context
+    let diff = unified_diff("x.txt", old, &[edit(0, 1, "A"), edit(10, 12, "")], 1);
+    assert_eq!(diff, "--- a/x.txt\n+++ b/x.txt\n@@ -1,2 +1,2 @@\n-a\n+A\n b\n@@ -5,3 +5,2 @@\n e\n-f\n g\n");
+    // Closer changes share a hunk
+    let diff = unified_diff("x.txt", old, &[edit(2, 3, "B1\nB2"), edit(6, 7, "D")], 1);
+    assert_eq!(diff, "--- a/x.txt\n+++ b/x.txt\n@@ -1,5 +1,6 @@\n a\n-b\n+B1\n+B2\n c\n-d\n+D\n e\n");
+    assert_eq!(
+        unified_diff("x.txt", "a", &[edit(0, 1, "b")], 3),
+        "--- a/x.txt\n+++ b/x.txt\n@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n"
+    );
+    assert!(unified_diff("x.txt", old, &[edit(0, 1, "a")], 3).is_empty());
+}
+
+#[test]
+fn test_apply_fixes() {
+    use ast_grep_mcp::diff::Edit;
+    use ast_grep_mcp::inline::{has_fix, with_language};
+    use ast_grep_mcp::rewrite::{apply_fixes, fix_fingerprint};
+
+    let yaml = "id: x\nrule:\n  pattern: var $A = $B\nfix: let $A = $B\n---\nid: y\nlanguage: ts\nrule: { kind: x }\n";
+    let with = with_language(yaml, "typescript").unwrap();
+    let rule: serde_yaml::Value = serde_yaml::from_str(with.split("---\n").next().unwrap()).unwrap();
+    assert_eq!(rule["language"], "typescript");
+    assert!(with_language(yaml, "python").unwrap_err().contains("The rule is for ts"));
+    assert!(has_fix(yaml));
+    assert!(!has_fix("id: y\nlanguage: ts\nrule: { kind: x }\n"));
+
+    let code = "var a = f(var b = 2);\nvar c = 3;\n";
+    let fix = |start: usize, end: usize, replacement: &str| {
+        json!({"ruleId": "x", "replacement": replacement, "replacementOffsets": {"start": start, "end": end},
+               "range": {"start": {"line": 0, "column": start}}})
+    };
+    let matches = vec![
+        fix(22, 32, "let c = 3;"),
+        fix(0, 21, "let a = f(var b = 2);"),
+        // Inside the first fix, so left for another run
+        fix(10, 19, "let b = 2"),
+        json!({"ruleId": "y", "range": {"byteOffset": {"start": 0, "end": 3}}}),
+    ];
+    let (fixed, applied, overlapping) = apply_fixes(code, &matches);
+    assert_eq!(fixed, "let a = f(var b = 2);\nlet c = 3;\n");
+    assert_eq!(applied.len(), 2);
+    assert_eq!((applied[1].original.as_str(), applied[1].replacement.as_str()), ("var c = 3;", "let c = 3;"));
+    assert_eq!(overlapping, 1);
+
+    // Equal fixes of a file are told apart by position
+    let edit = |start: usize| Edit { start, end: start + 8, replacement: "log(x)".to_string() };
+    assert_eq!(fix_fingerprint("a.py", &edit(0), "print(x)"), fix_fingerprint("a.py", &edit(0), "print(x)"));
+    assert_ne!(fix_fingerprint("a.py", &edit(0), "print(x)"), fix_fingerprint("a.py", &edit(9), "print(x)"));
+    assert_ne!(fix_fingerprint("a.py", &edit(0), "print(x)"), fix_fingerprint("b.py", &edit(0), "print(x)"));
+}
+
+#[test]
+fn test_rewrite_transform_section() {
+    use ast_grep_mcp::rewrite::{transform_rule, transform_section};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    let transforms = BTreeMap::from([
+        ("$CAMEL".to_string(), json!({"source": "$NAME", "to_case": "camel_case", "separated_by": ["Underscore"]})),
+        ("BARE".to_string(), json!({"source": "$NAME", "replace": "^get_", "by": ""})),
+        ("INNER".to_string(), json!({"source": "$STR", "start_char": 1, "end_char": -1})),
+        ("RAW".to_string(), json!({"convert": {"source": "$NAME", "toCase": "upperCase"}})),
+    ]);
+    let section = transform_section(&transforms).unwrap();
+    assert_eq!(
+        section["CAMEL"],
+        json!({"convert": {"source": "$NAME", "toCase": "camelCase", "separatedBy": ["underscore"]}})
+    );
+    assert_eq!(section["BARE"], json!({"replace": {"source": "$NAME", "replace": "^get_", "by": ""}}));
+    assert_eq!(section["INNER"], json!({"substring": {"source": "$STR", "startChar": 1, "endChar": -1}}));
+    assert_eq!(section["RAW"], transforms["RAW"]);
+
+    let rule: serde_yaml::Value =
+        serde_yaml::from_str(&transform_rule("$NAME($$$)", "python", &transforms, "$CAMEL($$$)").unwrap()).unwrap();
+    assert_eq!(rule["transform"]["CAMEL"]["convert"]["toCase"], "camelCase");
+    assert_eq!(rule["fix"], "$CAMEL($$$)");
+
+    let bad_case = BTreeMap::from([("X".to_string(), json!({"source": "$NAME", "to_case": "title"}))]);
+    assert!(transform_section(&bad_case).unwrap_err().contains("to_case"));
+    let no_by = BTreeMap::from([("X".to_string(), json!({"source": "$NAME", "replace": "a"}))]);
+    assert!(transform_section(&no_by).unwrap_err().contains("by"));
+    let mixed = BTreeMap::from([("X".to_string(), json!({"source": "$NAME", "to_case": "camelCase", "by": "a"}))]);
+    assert!(transform_section(&mixed).unwrap_err().contains("'by'"));
+}
+
+#[test]
+fn test_pattern_trend_points() {
+    use ast_grep_mcp::trend::{parse_commits, sample_commits, trend_summary, TrendPoint};
+    use serde_json::json;
+
+    let log: String =
+        (1..=7).rev().map(|i| format!("hash{}\u{1f}2024-0{}-01T00:00:00Z\u{1f}commit {}\n", i, i, i)).collect();
+    let commits = parse_commits(&log);
+    assert_eq!(commits.len(), 7);
+    assert_eq!(commits[0].subject, "commit 7");
+
+    let sampled = sample_commits(commits, 3, 3);
+    let hashes: Vec<&str> = sampled.iter().map(|c| c.commit.as_str()).collect();
+    assert_eq!(hashes, ["hash1", "hash4", "hash7"]);
+
+    let matches = [json!({"file": "a.py"}), json!({"file": "a.py"}), json!({"file": "b.py"})];
+    let points: Vec<TrendPoint> = sampled
+        .into_iter()
+        .zip([3, 2, 0])
+        .map(|(commit, count)| TrendPoint::new(commit.commit.clone(), commit, &matches[..count]))
+        .collect();
+    assert_eq!((points[0].matches, points[0].files), (3, 2));
+    let summary = trend_summary(&points);
+    assert!(summary.starts_with("Usage is shrinking: 3 matches in 2 files at hash1"), "{}", summary);
+    assert!(summary.ends_with("-3."));
+}
+
+#[test]
+fn test_scan_manifest() {
+    use ast_grep_mcp::export::export_matches;
+    use ast_grep_mcp::manifest::{is_dirty, query_hash, record_manifest, utc_timestamp, ScanManifest};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    assert_eq!(utc_timestamp(UNIX_EPOCH + Duration::from_secs(951_827_696)), "2000-02-29T12:34:56Z");
+    assert!(is_dirty(" M src/lib.rs\n"));
+    assert!(!is_dirty("\n"));
+    assert_eq!(query_hash("import $M", "python"), query_hash("import $M", "python"));
+    assert_ne!(query_hash("import $M", "python"), query_hash("import $M", "go"));
+
+    let manifest = ScanManifest {
+        git_commit: Some("abc123".to_string()),
+        git_dirty: Some(false),
+        files_scanned: Some(12),
+        query_hash: query_hash("import $M", "python"),
+        ast_grep_version: None,
+        started_at: "2024-05-01T12:00:00Z".to_string(),
+        finished_at: "2024-05-01T12:00:01Z".to_string(),
+    };
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    let scan_id = export_matches(&mut conn, "/p", "import $M", &[]).unwrap();
+    record_manifest(&conn, scan_id, &manifest).unwrap();
+    let (commit, dirty, files): (String, bool, i64) = conn
+        .query_row("SELECT git_commit, git_dirty, files_scanned FROM manifests WHERE scan_id = ?1", [scan_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap();
+    assert_eq!((commit.as_str(), dirty, files), ("abc123", false, 12));
+}
+
+#[test]
+fn test_reread_match_text() {
+    use ast_grep_mcp::encoding::reread_match_text;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.py"), "def f():\n    x = call(1,  2)\n    return x\n").unwrap();
+    std::fs::write(dir.path().join("b.py"), "y = call(3)\n").unwrap();
+    let mut matches = vec![
+        json!({"file": "a.py", "text": "x = call(1,  2)", "range": {"byteOffset": {"start": 13, "end": 28}}}),
+        json!({"file": "a.py", "text": "call(1,  2)", "range": {"byteOffset": {"start": 17, "end": 28}}}),
+        json!({"file": "b.py", "text": "call(4)", "range": {"byteOffset": {"start": 4, "end": 11}}}),
+    ];
+    let changed = reread_match_text(&mut matches, dir.path());
+    assert_eq!(changed, ["b.py"]);
+    assert_eq!(matches[0]["text"], "    x = call(1,  2)");
+    assert_eq!(matches[0]["textSource"], "file");
+    // Code before the match on its line is not indentation
+    assert_eq!(matches[1]["text"], "call(1,  2)");
+    assert_eq!((matches[2]["text"].as_str(), matches[2]["textSource"].as_str()), (Some("call(4)"), Some("cli")));
+}
+
+#[test]
+fn test_normalize_line_endings() {
+    use ast_grep_mcp::eol::{normalize_line_endings, LineEnding};
+
+    assert_eq!(LineEnding::detect("a\r\nb\r\n"), LineEnding::Crlf);
+    assert_eq!(LineEnding::detect("a\rb\nc"), LineEnding::Mixed);
+    assert_eq!(LineEnding::detect("a"), LineEnding::None);
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.py"), "x = 1\r\ny = call(\r\n  2)\r\n").unwrap();
+    std::fs::write(dir.path().join("b.py"), "a\rcall(1)\n").unwrap();
+    std::fs::write(dir.path().join("c.py"), "call(3)\n").unwrap();
+    let range = |start: usize, end: usize, (l1, c1): (usize, usize), (l2, c2): (usize, usize)| {
+        json!({"byteOffset": {"start": start, "end": end}, "start": {"line": l1, "column": c1},
+               "end": {"line": l2, "column": c2}})
+    };
+    let mut matches = vec![
+        json!({"file": "a.py", "text": "call(\r\n  2)", "lines": "y = call(\r\n  2)",
+               "range": range(11, 22, (1, 4), (2, 4)),
+               "metaVariables": {"single": {"A": {"text": "2", "range": range(20, 21, (2, 2), (2, 3))}}, "multi": {}}}),
+        json!({"file": "b.py", "text": "call(1)", "range": range(2, 9, (0, 2), (0, 9))}),
+        json!({"file": "c.py", "text": "call(3)", "range": range(0, 7, (0, 0), (0, 7))}),
+    ];
+    let untouched = matches[2].clone();
+    normalize_line_endings(&mut matches, dir.path());
+
+    assert_eq!(matches[0]["eol"], "crlf");
+    assert_eq!(matches[0]["text"], "call(\n  2)");
+    assert_eq!(matches[0]["lines"], "y = call(\n  2)");
+    assert_eq!(matches[0]["range"], range(10, 20, (1, 4), (2, 4)));
+    assert_eq!(matches[0]["metaVariables"]["single"]["A"]["range"], range(18, 19, (2, 2), (2, 3)));
+    // A lone carriage return starts a line, as it does in editors
+    assert_eq!(matches[1]["eol"], "mixed");
+    assert_eq!(matches[1]["range"], range(2, 9, (1, 0), (1, 7)));
+    assert_eq!(matches[2]["eol"], "lf");
+    matches[2].as_object_mut().unwrap().remove("eol");
+    assert_eq!(matches[2], untouched);
+}
+
+#[test]
+fn test_origin_and_host_allowed() {
+    use ast_grep_mcp::http::{host_allowed, origin_allowed};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    // Clients other than browsers send no Origin
+    assert!(origin_allowed(None, &[]));
+    // A page whose origin matches the Host header may be a DNS rebinding attack
+    assert!(!origin_allowed(Some("http://127.0.0.1:3101"), &[]));
+    assert!(!origin_allowed(Some("https://evil.example"), &[]));
+
+    let allowed = vec!["https://app.example.com".to_string()];
+    assert!(origin_allowed(Some("https://app.example.com"), &allowed));
+    assert!(!origin_allowed(Some("https://app.example.com:8443"), &allowed));
+    assert!(origin_allowed(Some("https://anything.example"), &["*".to_string()]));
+
+    let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    for host in ["127.0.0.1:3101", "localhost:3101", "LOCALHOST", "[::1]:3101"] {
+        assert!(host_allowed(Some(host), loopback, &[]), "{}", host);
+    }
+    assert!(!host_allowed(Some("evil.example:3101"), loopback, &[]));
+    assert!(!host_allowed(Some("10.0.0.5:3101"), loopback, &[]));
+    assert!(!host_allowed(None, loopback, &[]));
+    let listen = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5));
+    assert!(host_allowed(Some("10.0.0.5:3101"), listen, &[]));
+    assert!(host_allowed(Some("10.0.0.6"), IpAddr::V4(Ipv4Addr::UNSPECIFIED), &[]));
+    assert!(host_allowed(Some("mcp.example.com"), listen, &["mcp.example.com".to_string()]));
+}
+
+#[test]
+fn test_rule_sessions() {
+    use ast_grep_mcp::rulesession::{match_changes, set_rule_fields, RuleSessions, MAX_RULE_SESSIONS};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    let yaml = "id: x\nlanguage: python\nrule:\n  pattern: print($A)\n\
+                ---\nid: y\nlanguage: python\nrule:\n  kind: call\n";
+    let fields = BTreeMap::from([
+        ("rule.pattern".to_string(), json!("log($A)")),
+        ("constraints.A.regex".to_string(), json!("^x")),
+        ("language".to_string(), serde_json::Value::Null),
+    ]);
+    let changed = set_rule_fields(yaml, &fields).unwrap();
+    let documents: Vec<serde_yaml::Value> =
+        serde_yaml::Deserializer::from_str(&changed).map(|d| serde::Deserialize::deserialize(d).unwrap()).collect();
+    assert_eq!(documents[0]["rule"]["pattern"], "log($A)");
+    assert_eq!(documents[0]["constraints"]["A"]["regex"], "^x");
+    assert!(documents[0].get("language").is_none());
+    assert_eq!(documents[1]["id"], "y");
+    let into_scalar = BTreeMap::from([("id.name".to_string(), json!("z"))]);
+    assert!(set_rule_fields(yaml, &into_scalar).unwrap_err().contains("id.name"));
+    assert!(set_rule_fields(yaml, &BTreeMap::from([("rule.".to_string(), json!(1))])).is_err());
+
+    let at =
+        |start: u64, text: &str| json!({"text": text, "range": {"byteOffset": {"start": start, "end": start + 3}}});
+    let (added, removed) = match_changes(&[at(0, "f()"), at(10, "g()")], &[at(10, "g()"), at(20, "h()")]);
+    assert_eq!((added, removed), (vec!["h()".to_string()], vec!["f()".to_string()]));
+
+    let sessions = RuleSessions::default();
+    let (id, dropped) = sessions.create(yaml.to_string(), BTreeMap::new(), "print(1)".to_string());
+    assert!(dropped.is_none());
+    assert!(sessions.record(&id, yaml, "print(1)", &[at(0, "f()")]).is_none());
+    assert_eq!(sessions.record(&id, yaml, "print(1)", &[]).unwrap().len(), 1);
+    // A failed change leaves the session as it was; a new sample invalidates the last matches
+    assert!(sessions.update(&id, |session| { session.code.clear(); Err("no".to_string()) }).unwrap().is_err());
+    assert_eq!(sessions.get(&id).unwrap().code, "print(1)");
+    let updated = sessions.update(&id, |session| {
+        session.code.push_str("\nprint(2)");
+        session.last_matches = None;
+        Ok(())
+    });
+    assert_eq!(updated.unwrap().unwrap().tests, 2);
+    assert!(sessions.record(&id, yaml, "print(1)", &[]).is_none());
+    assert!(sessions.update("missing", |_| Ok(())).is_none());
+
+    for _ in 1..MAX_RULE_SESSIONS {
+        sessions.create(yaml.to_string(), BTreeMap::new(), String::new());
+    }
+    sessions.get(&id);
+    let (_, dropped) = sessions.create(yaml.to_string(), BTreeMap::new(), String::new());
+    assert!(dropped.is_some_and(|dropped| dropped != id));
+    assert_eq!(sessions.len(), MAX_RULE_SESSIONS);
+    assert!(sessions.remove(&id).is_some());
+}
+
+#[test]
+fn test_session_snapshot_round_trip() {
+    use ast_grep_mcp::rulesession::RuleSessions;
+    use ast_grep_mcp::snapshot::{
+        load_snapshot, save_snapshot, RuleSessionSnapshot, SessionSnapshot, WatchSnapshot, SNAPSHOT_VERSION,
+    };
+    use std::collections::BTreeMap;
+
+    let sessions = RuleSessions::default();
+    let yaml = "id: a\nlanguage: python\nrule: {pattern: print($X)}\n";
+    let utils = BTreeMap::from([("u".to_string(), json!({"kind": "call"}))]);
+    let (id, _) = sessions.create(yaml.to_string(), utils, "print(1)".to_string());
+    sessions.record(&id, yaml, "print(1)", &[json!({"text": "print(1)"})]);
+
+    let snapshot = SessionSnapshot {
+        version: SNAPSHOT_VERSION,
+        exported_at: "2026-01-01T00:00:00Z".to_string(),
+        rule_sessions: sessions
+            .list()
+            .into_iter()
+            .map(|(rule_session_id, session)| RuleSessionSnapshot { rule_session_id, session })
+            .collect(),
+        watches: vec![WatchSnapshot {
+            watch_id: "w1".to_string(),
+            yaml: yaml.to_string(),
+            project_folder: "/p".to_string(),
+            include_vendored: true,
+            matches: vec![json!({"file": "/p/a.py", "fingerprint": "f"})],
+        }],
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("session.json");
+    save_snapshot(&path, &snapshot).unwrap();
+    let loaded = load_snapshot(&path).unwrap();
+    assert_eq!(loaded.watches[0].matches, snapshot.watches[0].matches);
+    assert!(loaded.watches[0].include_vendored);
+
+    // Restored rule sessions keep their id, rule, code and last matches
+    let restored = RuleSessions::default();
+    for saved in loaded.rule_sessions {
+        assert!(restored.restore(saved.rule_session_id, saved.session).is_none());
+    }
+    let session = restored.get(&id).unwrap();
+    assert_eq!((session.yaml.as_str(), session.code.as_str(), session.tests), (yaml, "print(1)", 1));
+    assert_eq!(session.utils["u"], json!({"kind": "call"}));
+    assert_eq!(session.last_matches, Some(vec![json!({"text": "print(1)"})]));
+
+    std::fs::write(&path, r#"{"version": 99, "exported_at": ""}"#).unwrap();
+    assert!(load_snapshot(&path).unwrap_err().to_string().contains("version 99"));
+}
+
+#[test]
+fn test_parse_git_blobs() {
+    use ast_grep_mcp::gitrev::{is_searchable, parse_cat_file_batch, parse_ls_tree, TreeFile};
+
+    let listing = [
+        "100644 blob aaa\tsrc/a.js",
+        "120000 blob bbb\tlink.js",
+        "160000 commit ccc\tvendor/sub",
+        "100755 blob ddd\tsrc/my file.py",
+        "100644 blob eee\t../outside.js",
+        "100644 blob fff\tsrc/../../outside.js",
+        "",
+    ]
+    .join("\0");
+    assert_eq!(
+        parse_ls_tree(listing.as_bytes()),
+        vec![
+            TreeFile { object: "aaa".to_string(), path: "src/a.js".to_string() },
+            TreeFile { object: "ddd".to_string(), path: "src/my file.py".to_string() },
+        ]
+    );
+
+    assert!(is_searchable("src/a.js", ""));
+    assert!(is_searchable("src/a.js", "javascript"));
+    assert!(!is_searchable("src/a.js", "python"));
+    assert!(!is_searchable("README.md", ""));
+    assert!(!is_searchable("web/node_modules/x/index.js", ""));
+
+    let batch = b"aaa blob 3\nf()\nbbb missing\nddd blob 0\n\n";
+    assert_eq!(parse_cat_file_batch(batch), vec![Some(b"f()".to_vec()), None, Some(Vec::new())]);
+    // A truncated object ends the output
+    assert_eq!(parse_cat_file_batch(b"aaa blob 10\nf()\n"), Vec::<Option<Vec<u8>>>::new());
+}
+
+#[test]
+fn test_rewrite_backups() {
+    use ast_grep_mcp::backup::{backup_path, load_backup, remove_backup, restore_backup, write_atomically, Backup};
+
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+    std::fs::create_dir_all(project.join("a")).unwrap();
+    let file = project.join("a").join("m.js");
+    std::fs::write(&file, "var a = 1;\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    let backups = dir.path().join("backups");
+    let mut backup = Backup {
+        id: "b1".to_string(),
+        project_folder: project.clone(),
+        created_at: "2024-01-01T00:00:00Z".to_string(),
+        yaml: "id: x".to_string(),
+        files: Vec::new(),
+    };
+    backup.save_original(&backups, &file, b"var a = 1;\n", b"let a = 1;\n").unwrap();
+    write_atomically(&file, b"let a = 1;\n").unwrap();
+    backup.save(&backups).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "let a = 1;\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+    // No temporary file is left next to the rewritten one
+    assert_eq!(std::fs::read_dir(project.join("a")).unwrap().count(), 1);
+
+    let loaded = load_backup(&backups, "b1").unwrap();
+    assert_eq!(loaded, backup);
+    assert_eq!(loaded.files[0].backup, "0001-m.js.bak");
+    let original = std::fs::read_to_string(backups.join("b1").join("0001-m.js.bak")).unwrap();
+    assert_eq!(original, "var a = 1;\n");
+    assert!(backup_path(&backups, "../escape").is_none());
+    assert!(load_backup(&backups, "missing").is_err());
+
+    // A file edited after the rewrite is only restored with force
+    std::fs::write(&file, "let a = 2;\n").unwrap();
+    let restored = restore_backup(&backups, &loaded, false);
+    assert!(restored.restored.is_empty());
+    assert!(restored.skipped[0].contains("changed since it was rewritten"));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "let a = 2;\n");
+    let restored = restore_backup(&backups, &loaded, true);
+    assert_eq!(restored.restored, vec![file.clone()]);
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "var a = 1;\n");
+    remove_backup(&backups, "b1");
+    assert!(!backups.join("b1").exists());
+}
+
+#[test]
+fn test_rewrite_approval() {
+    use ast_grep_mcp::approval::{
+        approval_of, approval_request, approved_edits, match_approval_message, Approval, ApprovalScope,
+    };
+    use ast_grep_mcp::diff::{apply_edits, Edit};
+    use ast_grep_mcp::rewrite::planned_fixes;
+    use rmcp::model::{CreateElicitationResult, ElicitationAction};
+
+    let code = "a(1); a(2); a(3);";
+    let matches: Vec<serde_json::Value> = [0, 6, 12]
+        .iter()
+        .map(|&start| json!({"range": {"byteOffset": {"start": start, "end": start + 4}}, "replacement": "b()"}))
+        .collect();
+    let (edits, fixes, _) = planned_fixes(code, &matches);
+    let message = match_approval_message("m.js", 2, 3, &fixes[1]);
+    assert!(message.starts_with("Apply fix 2 of 3 to m.js (line 1)?"));
+    assert!(message.contains("--- original\na(2)\n+++ replacement\nb()"));
+    let (edits, fixes): (Vec<Edit>, _) = approved_edits(&edits, fixes, &[true, false, true]);
+    assert_eq!(apply_edits(code, &edits), "b(); a(2); b();");
+    assert_eq!(fixes.len(), 2);
+
+    let request = serde_json::to_value(approval_request("Apply?".to_string())).unwrap();
+    assert_eq!(request["mode"], "form");
+    assert_eq!(request["requestedSchema"]["properties"]["apply"]["type"], "boolean");
+    let answer = |action, content| CreateElicitationResult { action, content };
+    assert_eq!(approval_of(&answer(ElicitationAction::Accept, Some(json!({"apply": true})))), Approval::Approved);
+    assert_eq!(approval_of(&answer(ElicitationAction::Accept, None)), Approval::Approved);
+    assert_eq!(approval_of(&answer(ElicitationAction::Accept, Some(json!({"apply": false})))), Approval::Rejected);
+    assert_eq!(approval_of(&answer(ElicitationAction::Decline, None)), Approval::Rejected);
+    assert_eq!(approval_of(&answer(ElicitationAction::Cancel, None)), Approval::Cancelled);
+    assert_eq!(ApprovalScope::parse(""), Some(ApprovalScope::File));
+    assert_eq!(ApprovalScope::parse("hunk"), None);
+}
+
+#[test]
+fn test_dialect_queries() {
+    use ast_grep_mcp::dialect::{dialect_queries, Dialect};
+    use serde::Deserialize;
+
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let langs = |queries: Vec<Vec<String>>| queries.into_iter().map(|query| query[3].clone()).collect::<Vec<_>>();
+    let pattern = args(&["--pattern", "f($A)", "--lang", "ts"]);
+    assert_eq!(langs(dialect_queries(&pattern, Dialect::Auto).unwrap()), ["typescript", "tsx"]);
+    assert_eq!(langs(dialect_queries(&pattern, Dialect::Jsx).unwrap()), ["tsx"]);
+    let tsx = args(&["--pattern", "f($A)", "--lang", "tsx"]);
+    assert_eq!(langs(dialect_queries(&tsx, Dialect::Auto).unwrap()), ["tsx", "typescript"]);
+    assert_eq!(langs(dialect_queries(&tsx, Dialect::Plain).unwrap()), ["typescript"]);
+    let javascript = args(&["--pattern", "f($A)", "--lang", "javascript"]);
+    assert_eq!(dialect_queries(&javascript, Dialect::Jsx).unwrap(), vec![javascript]);
+    assert_eq!(Dialect::parse(""), Some(Dialect::Auto));
+    assert_eq!(Dialect::parse("tsx"), None);
+
+    let languages = |query: &[String]| {
+        serde_yaml::Deserializer::from_str(&query[1])
+            .map(|document| serde_yaml::Value::deserialize(document).unwrap()["language"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let rule = |id: &str, language: &str| format!("id: {}\nlanguage: {}\nrule: {{pattern: f()}}\n", id, language);
+    let rules = args(&["--inline-rules", &format!("{}---\n{}", rule("a", "TypeScript"), rule("b", "python"))]);
+    let queries = dialect_queries(&rules, Dialect::Auto).unwrap();
+    // The python rule is only scanned once
+    let scanned: Vec<Vec<String>> = queries.iter().map(|query| languages(query)).collect();
+    assert_eq!(scanned, [vec!["typescript", "python"], vec!["tsx"]]);
+
+    let both = args(&["--inline-rules", &format!("{}---\n{}", rule("a", "typescript"), rule("b", "tsx"))]);
+    assert_eq!(dialect_queries(&both, Dialect::Auto).unwrap(), vec![both.clone()]);
+    let queries = dialect_queries(&both, Dialect::Jsx).unwrap();
+    assert_eq!(queries.iter().map(|query| languages(query)).collect::<Vec<_>>(), [vec!["tsx"]]);
+}
+
+#[test]
+fn test_parameter_values() {
+    use ast_grep_mcp::config::{Cli, Config};
+    use ast_grep_mcp::server::AstGrepServer;
+    use ast_grep_mcp::toolset::one_of;
+    use clap::Parser;
+    use rmcp::ServerHandler;
+
+    let config = Config::from_args_with_env(&Cli::parse_from(["ast-grep-mcp-server"]), |_| None).unwrap();
+    let server = AstGrepServer::new(config);
+    let find_code = server.get_tool("find_code").unwrap();
+    let properties = &find_code.input_schema["properties"];
+    assert_eq!(properties["output_format"]["enum"], json!(["text", "json", "headers", "diagnostics"]));
+    // The empty default is accepted too
+    assert_eq!(properties["fallback"]["enum"], json!(["", "none", "relaxed"]));
+    let languages = properties["language"]["examples"].as_array().unwrap();
+    assert!(languages.contains(&json!("typescript")) && languages.contains(&json!("tsx")));
+    assert!(properties["language"].get("enum").is_none());
+    assert!(properties["pattern"].get("enum").is_none());
+
+    let dump = server.get_tool("dump_syntax_tree").unwrap();
+    assert_eq!(dump.input_schema["properties"]["format"]["enum"], json!(["pattern", "ast", "cst"]));
+    let call_graph = server.get_tool("call_graph").unwrap();
+    assert_eq!(call_graph.input_schema["properties"]["format"]["enum"], json!(["", "json", "dot"]));
+
+    assert_eq!(one_of(&["a", "b", "c"]), "'a', 'b' or 'c'");
+    assert_eq!(one_of(&["a"]), "'a'");
+}